@@ -0,0 +1,66 @@
+//! A ball bouncing across a checkered floor, rendered as a sequence of frames.
+//! The ball hangs off a node of the scene graph, whose transform is the only
+//! thing that changes from one frame to the next.
+//!
+//! Run with `cargo run --example animation`, which saves the frames next to
+//! the output file, numbered from `animation_00.png`.
+
+mod common;
+
+use common::Options;
+use rustbeam::lights::Sun;
+use rustbeam::materials::Material;
+use rustbeam::math::UnitQuaternion;
+use rustbeam::scene::Scene;
+use rustbeam::surfaces::{Plane, Sphere, Transform};
+use rustbeam::textures::{Checker, CheckerMapping, Constant};
+use std::error::Error;
+use std::f64::consts::PI;
+use std::path::Path;
+
+/// The number of frames of the animation.
+const FRAMES: usize = 8;
+
+/// The scene at `time`, from 0 at the first frame to 1 after the last.
+fn scene_at(time: f64) -> Scene {
+    let mut scene = Scene::new();
+
+    let height = (PI * 2.0 * time).sin().abs();
+    let ball = scene.add_node(
+        None,
+        Transform::new(
+            (1.0, 1.0, 1.0),
+            UnitQuaternion::id(),
+            (4.0 * time - 2.0, 5.0, height - 0.5),
+        ),
+    );
+    scene.add_surface_to_node(
+        ball,
+        Sphere::new((0.0, 0.0, 0.0), 0.5),
+        Material::new(Constant::new((0.8, 0.2, 0.2))),
+    );
+    scene.add_textured_surface(
+        Plane::new((0.0, 0.0, 1.0), -1.0),
+        Checker::from_colors((0.9, 0.9, 0.9), (0.3, 0.3, 0.3), 0.5, CheckerMapping::Solid),
+    );
+    scene.add_light(Sun::noon());
+
+    scene
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut options = Options::from_args("test-data/test-data-out/animation.png")?;
+
+    let output = Path::new(&options.output).to_path_buf();
+    let stem = output
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("frame");
+    for frame in 0..FRAMES {
+        let filename = output.with_file_name(format!("{}_{:02}.png", stem, frame));
+        options.output = filename.to_string_lossy().into_owned();
+        common::render_to_png(scene_at(frame as f64 / FRAMES as f64), &options)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,40 @@
+//! A glass sphere and a curved mirror focusing sunlight onto the floor. The
+//! caustics are rendered from a photon map, on top of direct lighting.
+//!
+//! Run with `cargo run --example caustics`.
+
+mod common;
+
+use common::Options;
+use rustbeam::lights::Sun;
+use rustbeam::materials::Material;
+use rustbeam::scene::Scene;
+use rustbeam::surfaces::{Plane, Sphere};
+use rustbeam::textures::{Checker, CheckerMapping, Constant};
+use std::error::Error;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let options = Options::from_args("test-data/test-data-out/caustics.png")?;
+
+    let mut scene = Scene::new();
+
+    scene.add_surface_with_material(
+        Sphere::new((-0.8, 4.0, 0.0), 0.7),
+        Material::new(Constant::new((1.0, 1.0, 1.0))).with_transmission(1.0),
+    );
+    scene.add_surface_with_material(
+        Sphere::new((1.0, 4.5, -0.2), 0.5),
+        Material::new(Constant::new((0.95, 0.85, 0.6)))
+            .with_metallic(1.0)
+            .with_roughness(0.0),
+    );
+    scene.add_textured_surface(
+        Plane::new((0.0, 0.0, 1.0), -0.7),
+        Checker::from_colors((0.9, 0.9, 0.9), (0.5, 0.5, 0.5), 0.6, CheckerMapping::Solid),
+    );
+
+    scene.add_light(Sun::new((1.0, 1.0, 1.0), (-0.4, -0.3, -0.6)));
+    scene.build_photon_map(1_000_000);
+
+    common::render_to_png(scene, &options)
+}
@@ -0,0 +1,31 @@
+//! A sphere resting on the classic checkered ground plane, lit by the noon sun.
+//!
+//! Run with `cargo run --example checker_floor`.
+
+mod common;
+
+use common::Options;
+use rustbeam::lights::Sun;
+use rustbeam::scene::Scene;
+use rustbeam::surfaces::{Plane, Sphere};
+use rustbeam::textures::{Checker, CheckerMapping, Constant};
+use std::error::Error;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let options = Options::from_args("test-data/test-data-out/checker_floor.png")?;
+
+    let mut scene = Scene::new();
+
+    scene.add_textured_surface(
+        Sphere::new((0.0, 4.0, 0.0), 1.0),
+        Constant::new((0.8, 0.3, 0.2)),
+    );
+    scene.add_textured_surface(
+        Plane::new((0.0, 0.0, 1.0), -1.0),
+        Checker::from_colors((0.9, 0.9, 0.9), (0.1, 0.1, 0.1), 0.6, CheckerMapping::Solid),
+    );
+
+    scene.add_light(Sun::noon());
+
+    common::render_to_png(scene, &options)
+}
@@ -0,0 +1,100 @@
+//! Helpers shared by the examples.
+
+// Not every example uses every helper.
+#![allow(dead_code)]
+
+use rustbeam::image::Image;
+use rustbeam::scene::{Integrator, Scene};
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+/// Command line options accepted by every example.
+pub struct Options {
+    pub width: usize,
+    pub height: usize,
+    /// The png file that the rendered image is saved to.
+    pub output: String,
+    /// Whether to save the metadata of the render next to the image.
+    pub metadata: bool,
+    /// The number of samples of each pixel, if not the one of the example.
+    pub spp: Option<u32>,
+}
+
+impl Options {
+    /// Parse the command line arguments. `--width <pixels>`, `--height
+    /// <pixels>`, `--output <filename>`, `--metadata` and `--spp <samples>`
+    /// are accepted. `default_output` is used if no output file is given.
+    pub fn from_args(default_output: &str) -> Result<Self, Box<dyn Error>> {
+        let mut options = Self {
+            width: 1280,
+            height: 720,
+            output: default_output.to_string(),
+            metadata: false,
+            spp: None,
+        };
+
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("Missing value for {}", arg));
+            match arg.as_str() {
+                "--width" => options.width = value()?.parse()?,
+                "--height" => options.height = value()?.parse()?,
+                "--output" => options.output = value()?,
+                "--metadata" => options.metadata = true,
+                "--spp" => options.spp = Some(value()?.parse()?),
+                _ => return Err(format!("Unknown argument {}", arg).into()),
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+/// Set the number of samples of each pixel of `scene` to `spp`: the paths
+/// traced through each pixel when path tracing, and otherwise the rays traced
+/// through points spread over each pixel.
+pub fn set_samples(scene: &mut Scene, spp: u32) {
+    match scene.render_settings(0, 0).integrator {
+        Integrator::PathTracing { max_depth, .. } => {
+            scene.set_integrator(Integrator::PathTracing {
+                max_depth,
+                samples_per_pixel: spp,
+            })
+        }
+        _ => {
+            let mut sampler = scene.sampler().clone();
+            sampler.pixel_samples = spp;
+            scene.set_sampler(sampler);
+        }
+    }
+}
+
+/// Render `scene` with the number of samples given by the options, if any,
+/// and save the result as a png file, and the metadata of the render as a
+/// JSON file, if asked for.
+pub fn render_to_png(mut scene: Scene, options: &Options) -> Result<(), Box<dyn Error>> {
+    if let Some(spp) = options.spp {
+        set_samples(&mut scene, spp);
+    }
+    let mut image = Image::new(options.width, options.height);
+
+    let start = Instant::now();
+    let metadata = options
+        .metadata
+        .then(|| scene.metadata(options.width, options.height, Duration::ZERO));
+    let render = scene.spawn_render_threads(options.width, options.height);
+    image.update(render.iter());
+    render.join()?;
+    let render_time = start.elapsed();
+
+    image.clamp();
+    image.save_png(&options.output)?;
+    println!("Saved {}", options.output);
+
+    if let Some(mut metadata) = metadata {
+        metadata.timings.render = render_time.as_secs_f64();
+        metadata.save_sidecar(&options.output)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,68 @@
+//! The Cornell box: a white room with a red wall on the left and a green one
+//! on the right, lit by a square light in the ceiling, with a diffuse and a
+//! mirror sphere on the floor. Path tracing brings out the color bleeding
+//! from the walls and the soft shadows under the spheres.
+//!
+//! Run with `cargo run --example cornell_box -- --spp 64`.
+
+mod common;
+
+use common::Options;
+use rustbeam::lights::AreaLight;
+use rustbeam::materials::Material;
+use rustbeam::math::UnitQuaternion;
+use rustbeam::scene::{Integrator, Scene};
+use rustbeam::surfaces::{Rect, Sphere};
+use rustbeam::textures::Constant;
+use std::error::Error;
+use std::f64::consts::{FRAC_PI_2, PI};
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let options = Options::from_args("test-data/test-data-out/cornell_box.png")?;
+
+    let mut scene = Scene::new();
+    let diffuse = |color| Material::new(Constant::new(color));
+    let white = (0.73, 0.73, 0.73);
+
+    // The room spans -1 to 1 along x and z, and 1 to 3 along y, and is open
+    // towards the camera. Rectangles face up along z before they are turned.
+    let wall = |axis: (f64, f64, f64), angle: f64, center: (f64, f64, f64)| {
+        Rect::new(2.0, 2.0).with_transform(UnitQuaternion::from_axis_angle(axis, angle), center)
+    };
+    let x_axis = (1.0, 0.0, 0.0);
+    let y_axis = (0.0, 1.0, 0.0);
+    scene.add_surface_with_material(wall(x_axis, 0.0, (0.0, 2.0, -1.0)), diffuse(white));
+    scene.add_surface_with_material(wall(x_axis, PI, (0.0, 2.0, 1.0)), diffuse(white));
+    scene.add_surface_with_material(wall(x_axis, FRAC_PI_2, (0.0, 3.0, 0.0)), diffuse(white));
+    scene.add_surface_with_material(
+        wall(y_axis, FRAC_PI_2, (-1.0, 2.0, 0.0)),
+        diffuse((0.63, 0.07, 0.05)),
+    );
+    scene.add_surface_with_material(
+        wall(y_axis, -FRAC_PI_2, (1.0, 2.0, 0.0)),
+        diffuse((0.14, 0.45, 0.09)),
+    );
+
+    scene.add_surface_with_material(Sphere::new((-0.45, 2.3, -0.6), 0.4), diffuse(white));
+    scene.add_surface_with_material(
+        Sphere::new((0.45, 1.8, -0.65), 0.35),
+        diffuse((0.95, 0.95, 0.95))
+            .with_metallic(1.0)
+            .with_roughness(0.0),
+    );
+
+    // Just below the ceiling, shining down.
+    scene.add_light(AreaLight::new(0.5, 0.5, (12.0, 12.0, 12.0)).with_transform(
+        UnitQuaternion::from_axis_angle(x_axis, PI),
+        (0.0, 2.0, 0.99),
+    ));
+
+    scene.set_camera((0.0, -2.1, 0.0), UnitQuaternion::id());
+    scene.set_horizontal_fov(60.0);
+    scene.set_integrator(Integrator::PathTracing {
+        max_depth: 4,
+        samples_per_pixel: 64,
+    });
+
+    common::render_to_png(scene, &options)
+}
@@ -0,0 +1,51 @@
+//! A row of spheres going into the distance, seen through a camera lens
+//! focused on the middle one, so that the nearer and farther spheres are
+//! blurred.
+//!
+//! Run with `cargo run --example depth_of_field -- --spp 4`.
+
+mod common;
+
+use common::Options;
+use rustbeam::lights::Sun;
+use rustbeam::math::Vector3;
+use rustbeam::scene::{Camera, Scene};
+use rustbeam::surfaces::{Plane, Sphere};
+use rustbeam::textures::{Checker, CheckerMapping, Constant};
+use std::error::Error;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let options = Options::from_args("test-data/test-data-out/depth_of_field.png")?;
+
+    let mut scene = Scene::new();
+
+    let colors = [
+        (0.8, 0.2, 0.2),
+        (0.9, 0.6, 0.1),
+        (0.3, 0.7, 0.2),
+        (0.2, 0.5, 0.8),
+        (0.5, 0.3, 0.8),
+    ];
+    for (index, &color) in colors.iter().enumerate() {
+        let distance = 2.0 + 1.5 * index as f64;
+        scene.add_textured_surface(
+            Sphere::new((0.6 * index as f64 - 1.2, distance, -0.5), 0.5),
+            Constant::new(color),
+        );
+    }
+    scene.add_textured_surface(
+        Plane::new((0.0, 0.0, 1.0), -1.0),
+        Checker::from_colors((0.9, 0.9, 0.9), (0.3, 0.3, 0.3), 0.5, CheckerMapping::Solid),
+    );
+    scene.add_light(Sun::noon());
+
+    let target = Vector3::from((0.0, 5.0, -0.5));
+    let camera = Camera::look_at((0.0, 0.0, 0.0), target, (0.0, 0.0, 1.0));
+    scene.set_camera(camera.position, camera.orientation);
+    // Focused on the middle sphere, with a wide aperture for a shallow depth
+    // of field.
+    scene.set_depth_of_field(0.15, (target - camera.position).norm());
+    scene.set_lens_samples(32);
+
+    common::render_to_png(scene, &options)
+}
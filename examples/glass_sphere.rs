@@ -0,0 +1,43 @@
+//! A glass sphere on a checkered floor, refracting the checkers upside down
+//! and casting a shadow lit by the light it focuses.
+//!
+//! Run with `cargo run --example glass_sphere -- --spp 16`.
+
+mod common;
+
+use common::Options;
+use rustbeam::lights::Sun;
+use rustbeam::materials::Material;
+use rustbeam::scene::{Camera, SamplerSettings, Scene};
+use rustbeam::surfaces::{Plane, Sphere};
+use rustbeam::textures::{Checker, CheckerMapping, Constant};
+use std::error::Error;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let options = Options::from_args("test-data/test-data-out/glass_sphere.png")?;
+
+    let mut scene = Scene::new();
+
+    scene.add_surface_with_material(
+        Sphere::new((0.0, 4.0, 0.0), 1.0),
+        Material::new(Constant::new((1.0, 1.0, 1.0)))
+            .with_transmission(1.0)
+            .with_ior(1.5),
+    );
+    scene.add_textured_surface(
+        Plane::new((0.0, 0.0, 1.0), -1.0),
+        Checker::from_colors((0.9, 0.9, 0.9), (0.1, 0.1, 0.1), 0.5, CheckerMapping::Solid),
+    );
+    scene.add_light(Sun::new((1.0, 1.0, 1.0), (-0.3, 0.5, -1.0)));
+
+    let camera = Camera::look_at((0.0, -0.5, 1.0), (0.0, 4.0, -0.3), (0.0, 0.0, 1.0));
+    scene.set_camera(camera.position, camera.orientation);
+    // Several rays through each pixel smooth the edges of the checkers seen
+    // through the glass.
+    scene.set_sampler(SamplerSettings {
+        pixel_samples: 4,
+        ..SamplerSettings::default()
+    });
+
+    common::render_to_png(scene, &options)
+}
@@ -0,0 +1,55 @@
+//! A contact sheet of spheres with varying roughness and metallicity, lit by a
+//! small sphere light, for comparing the looks of materials.
+//!
+//! Run with `cargo run --example material_sweep`. The width and height give
+//! the approximate size of the whole contact sheet.
+
+mod common;
+
+use common::Options;
+use rustbeam::lights::{SphereLight, Sun};
+use rustbeam::materials::Material;
+use rustbeam::scene::{Integrator, Scene};
+use rustbeam::surfaces::{Plane, Sphere};
+use rustbeam::sweep::{Parameter, Sweep};
+use rustbeam::textures::{Checker, CheckerMapping, Constant};
+use std::error::Error;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let options = Options::from_args("test-data/test-data-out/material_sweep.png")?;
+
+    let roughness = Parameter::new("roughness", vec![0.1, 0.3, 0.6, 0.9]);
+    let metallic = Parameter::linear("metallic", 0.0, 1.0, 3);
+    let (columns, rows) = (roughness.values.len(), metallic.values.len());
+    let sweep = Sweep::new(roughness)
+        .with_rows(metallic)
+        .with_cell_size(options.width / columns, options.height / rows);
+
+    let sheet = sweep.render(|values| {
+        let (roughness, metallic) = (values[0], values[1]);
+        let mut scene = Scene::new();
+        scene.set_integrator(Integrator::PathTracing {
+            max_depth: 3,
+            samples_per_pixel: 16,
+        });
+
+        scene.add_surface_with_material(
+            Sphere::new((0.0, 3.0, 0.0), 0.8),
+            Material::new(Constant::new((0.95, 0.65, 0.3)))
+                .with_roughness(roughness)
+                .with_metallic(metallic),
+        );
+        scene.add_textured_surface(
+            Plane::new((0.0, 0.0, 1.0), -0.8),
+            Checker::from_colors((0.7, 0.7, 0.7), (0.3, 0.3, 0.3), 0.5, CheckerMapping::Solid),
+        );
+
+        scene.add_light(SphereLight::new((-1.5, 2.0, 1.5), 0.2, (60.0, 60.0, 60.0)));
+        scene.add_light(Sun::new((0.2, 0.2, 0.25), (0.0, 1.0, -1.0)));
+        scene
+    });
+
+    sheet.save_png(&options.output)?;
+    println!("Saved {}", options.output);
+    Ok(())
+}
@@ -0,0 +1,63 @@
+//! A mesh imported from an OBJ file, shown three times: as it is, scaled and
+//! turned by a transform, and with its surface displaced by a texture.
+//!
+//! Run with `cargo run --example mesh_import`.
+
+mod common;
+
+use common::Options;
+use rustbeam::lights::Sun;
+use rustbeam::materials::Material;
+use rustbeam::math::UnitQuaternion;
+use rustbeam::obj;
+use rustbeam::scene::Scene;
+use rustbeam::surfaces::{Plane, Transform, Transformed};
+use rustbeam::textures::{Checker, CheckerMapping, Constant};
+use std::error::Error;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let options = Options::from_args("test-data/test-data-out/mesh_import.png")?;
+
+    let mut scene = Scene::new();
+    let load_mesh = || obj::load_obj("test-data/icosahedron.obj");
+    let material = |color| Material::new(Constant::new(color));
+
+    let displaced = load_mesh()?.displace(
+        &Checker::from_colors((1.0, 1.0, 1.0), (0.0, 0.0, 0.0), 0.2, CheckerMapping::Solid),
+        0.05,
+        3,
+    );
+    scene.add_surface_with_material(
+        Transformed::new(
+            displaced,
+            Transform::new((0.8, 0.8, 0.8), UnitQuaternion::id(), (2.3, 5.0, -0.24)),
+        ),
+        material((0.3, 0.6, 0.9)),
+    );
+    scene.add_surface_with_material(
+        Transformed::new(
+            load_mesh()?,
+            Transform::new(
+                (1.2, 1.2, 0.8),
+                UnitQuaternion::from_axis_angle((0.0, 0.0, 1.0), 0.6),
+                (0.0, 5.0, -0.24),
+            ),
+        ),
+        material((0.9, 0.6, 0.2)),
+    );
+    scene.add_surface_with_material(
+        Transformed::new(
+            load_mesh()?,
+            Transform::new((0.8, 0.8, 0.8), UnitQuaternion::id(), (-2.3, 5.0, -0.24)),
+        ),
+        material((0.8, 0.2, 0.2)),
+    );
+    scene.add_textured_surface(
+        Plane::new((0.0, 0.0, 1.0), -1.0),
+        Checker::from_colors((0.9, 0.9, 0.9), (0.5, 0.5, 0.5), 0.5, CheckerMapping::Solid),
+    );
+
+    scene.add_light(Sun::new((1.0, 1.0, 1.0), (-0.5, 0.6, -1.0)));
+
+    common::render_to_png(scene, &options)
+}
@@ -0,0 +1,51 @@
+//! Spheres with the procedural marble, wood and turbulence textures.
+//!
+//! Run with `cargo run --example noise_textures`.
+
+mod common;
+
+use common::Options;
+use rustbeam::lights::Sun;
+use rustbeam::scene::Scene;
+use rustbeam::surfaces::{Plane, Sphere};
+use rustbeam::textures::{Constant, Marble, Turbulence, Wood};
+use std::error::Error;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let options = Options::from_args("test-data/test-data-out/noise_textures.png")?;
+
+    let mut scene = Scene::new();
+
+    scene.add_textured_surface(
+        Sphere::new((-1.3, 5.0, 0.0), 0.6),
+        Marble::new(
+            Constant::new((0.9, 0.9, 0.85)),
+            Constant::new((0.2, 0.2, 0.25)),
+            0.5,
+            6,
+        ),
+    );
+    scene.add_textured_surface(
+        Sphere::new((0.0, 5.0, 0.0), 0.6),
+        Wood::new(
+            Constant::new((0.8, 0.55, 0.3)),
+            Constant::new((0.45, 0.25, 0.1)),
+            0.1,
+            4,
+        ),
+    );
+    scene.add_textured_surface(
+        Sphere::new((1.3, 5.0, 0.0), 0.6),
+        Turbulence::new(
+            Constant::new((0.1, 0.2, 0.6)),
+            Constant::new((0.9, 0.9, 1.0)),
+            0.4,
+            5,
+        ),
+    );
+    scene.add_surface(Plane::new((0.0, 0.0, 1.0), -0.6));
+
+    scene.add_light(Sun::new((1.0, 1.0, 1.0), (1.0, 1.0, -1.0)));
+
+    common::render_to_png(scene, &options)
+}
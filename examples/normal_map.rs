@@ -0,0 +1,52 @@
+//! A sphere and a floor whose shading normals are perturbed by procedural
+//! normal maps, lit by a low sun to bring out the relief.
+//!
+//! Run with `cargo run --example normal_map`.
+
+mod common;
+
+use common::Options;
+use rustbeam::lights::Sun;
+use rustbeam::materials::Material;
+use rustbeam::math::{noise, Vector3};
+use rustbeam::scene::Scene;
+use rustbeam::surfaces::{Plane, Sphere};
+use rustbeam::textures::{Constant, Texture};
+use std::error::Error;
+
+/// A normal map of bumps made from gradient noise.
+struct Bumps {
+    scale: f64,
+}
+
+impl Texture for Bumps {
+    fn color(&self, point: Vector3, _uv: (f64, f64)) -> Vector3 {
+        let p = point * (1.0 / self.scale);
+        let offset = 0.5 * Vector3::ones();
+        // Tilt the normal in the tangent plane by two decorrelated noise values.
+        Vector3 {
+            x: 0.5 + 0.4 * noise::perlin(p),
+            y: 0.5 + 0.4 * noise::perlin(p + 10.0 * offset),
+            z: 1.0,
+        }
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let options = Options::from_args("test-data/test-data-out/normal_map.png")?;
+
+    let mut scene = Scene::new();
+
+    scene.add_surface_with_material(
+        Sphere::new((0.0, 4.0, 0.0), 1.0),
+        Material::new(Constant::new((0.7, 0.7, 0.7))).with_normal_map(Bumps { scale: 0.1 }),
+    );
+    scene.add_surface_with_material(
+        Plane::new((0.0, 0.0, 1.0), -1.0),
+        Material::new(Constant::new((0.5, 0.6, 0.5))).with_normal_map(Bumps { scale: 0.3 }),
+    );
+
+    scene.add_light(Sun::golden_hour());
+
+    common::render_to_png(scene, &options)
+}
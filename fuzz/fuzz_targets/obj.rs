@@ -0,0 +1,9 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+// Run with `cargo fuzz run obj`. Parsing must never panic, whatever the input.
+fuzz_target!(|data: &[u8]| {
+    if let Ok(source) = std::str::from_utf8(data) {
+        let _ = rustbeam::obj::parse_obj(source);
+    }
+});
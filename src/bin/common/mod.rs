@@ -0,0 +1,373 @@
+//! Helpers shared by the rustbeam binaries.
+
+// Not every binary uses every helper.
+#![allow(dead_code)]
+
+use rustbeam::denoise::{self, Features};
+use rustbeam::flare::{FlareSource, LensFlare};
+use rustbeam::image::Image;
+use rustbeam::lights::Sun;
+use rustbeam::metadata::RenderMetadata;
+use rustbeam::notify::Notifier;
+use rustbeam::plugins::Registry;
+use rustbeam::recording::Command;
+use rustbeam::scene::{
+    CostMap, CropWindow, DebugView, LightGroupImages, PixelCost, Scene, ThreadSettings,
+};
+use rustbeam::scene_file::{self, SceneFormat};
+use rustbeam::sensor::Sensor;
+use rustbeam::surfaces::{Plane, Sphere};
+use rustbeam::textures::{Checker, CheckerMapping};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+/// The size of rendered images.
+pub const WIDTH: usize = 1280;
+pub const HEIGHT: usize = 720;
+
+/// Options that may be given anywhere on the command line.
+pub struct Flags {
+    /// Save the metadata of the render as a JSON file next to the image.
+    pub metadata: bool,
+    /// The settings of the scene to turn on.
+    pub settings: SceneSettings,
+    /// How to tell that the render is done, given as `--notify` for a desktop
+    /// notification, and `--webhook <url>` for posting the metadata to an
+    /// `http` URL, or to an `https` URL through `curl`.
+    pub notifiers: Vec<Notifier>,
+}
+
+/// Settings of the scene that may be turned on by flags.
+pub struct SceneSettings {
+    /// Keep the system from going to sleep while rendering.
+    pub keep_awake: bool,
+    /// Render at a lower priority than other programs.
+    pub low_priority: bool,
+    /// Offset the random numbers of each pixel by a blue-noise mask, so that
+    /// the noise of renders with few samples is fine grain instead of clumps.
+    pub blue_noise: bool,
+    /// The number of threads to render with, given as `--threads <count>`.
+    pub threads: Option<usize>,
+    /// The part of the image to render, given as
+    /// `--crop <x>,<y>,<width>,<height>`.
+    pub crop: Option<CropWindow>,
+    /// A view of the geometry of the scene to render instead of its colors,
+    /// given as `--debug <normals|depth:<distance>|uv|ids>`.
+    pub debug_view: Option<DebugView>,
+    /// What to measure the cost of each pixel in, for a heatmap saved next to
+    /// the render, given as `--heatmap <time|rays>`.
+    pub heatmap: Option<PixelCost>,
+}
+
+impl Flags {
+    /// Find the flags in `args`, and remove them. `usage` is the error if
+    /// they are wrong.
+    pub fn take(args: &mut Vec<String>, usage: &str) -> Result<Self, Box<dyn Error>> {
+        let mut take = |name: &str| {
+            let given = args.iter().any(|arg| arg == name);
+            args.retain(|arg| arg != name);
+            given
+        };
+        let mut flags = Self {
+            metadata: take("--metadata"),
+            settings: SceneSettings {
+                keep_awake: take("--keep-awake"),
+                low_priority: take("--low-priority"),
+                blue_noise: take("--blue-noise"),
+                threads: None,
+                crop: None,
+                debug_view: None,
+                heatmap: None,
+            },
+            notifiers: Vec::new(),
+        };
+        if take("--notify") {
+            flags.notifiers.push(Notifier::Desktop);
+        }
+        while let Some(index) = args.iter().position(|arg| arg == "--webhook") {
+            if index + 1 == args.len() {
+                return Err(usage.into());
+            }
+            flags
+                .notifiers
+                .push(Notifier::Webhook(args.remove(index + 1)));
+            args.remove(index);
+        }
+        flags.settings.threads = take_option(args, "--threads", usage)?
+            .map(|threads| threads.parse::<usize>())
+            .transpose()
+            .map_err(|_| usage)?;
+        flags.settings.crop = take_option(args, "--crop", usage)?
+            .map(|crop| parse_crop(&crop).ok_or(usage))
+            .transpose()?;
+        flags.settings.debug_view = take_option(args, "--debug", usage)?
+            .map(|view| parse_debug_view(&view).ok_or(usage))
+            .transpose()?;
+        flags.settings.heatmap = take_option(args, "--heatmap", usage)?
+            .map(|cost| match cost.as_str() {
+                "time" => Ok(PixelCost::Time),
+                "rays" => Ok(PixelCost::Rays),
+                _ => Err(usage),
+            })
+            .transpose()?;
+        Ok(flags)
+    }
+
+    /// Is the metadata of the render needed, for saving or notifying?
+    pub fn wants_metadata(&self) -> bool {
+        self.metadata || !self.notifiers.is_empty()
+    }
+
+    /// Tell that the render described by `metadata` is done, after
+    /// `render_time`, as asked for. Failures are only warned about, since the
+    /// render itself went fine.
+    pub fn notify(&self, metadata: Option<&mut RenderMetadata>, render_time: Duration) {
+        if let Some(metadata) = metadata {
+            metadata.timings.render = render_time.as_secs_f64();
+            for notifier in &self.notifiers {
+                if let Err(error) = notifier.notify(metadata) {
+                    eprintln!("Warning: Could not notify that the render is done: {error}");
+                }
+            }
+        }
+    }
+
+    /// Turn on the settings of `scene` that are given as flags. Settings that
+    /// are on in a scene file stay on.
+    pub fn apply(&self, scene: &mut Scene) {
+        if self.settings.keep_awake {
+            scene.set_keep_awake(true);
+        }
+        if self.settings.low_priority {
+            scene.set_low_priority(true);
+        }
+        if self.settings.blue_noise {
+            // The mask of the scene file, if any, is kept.
+            let mut sampler = scene.sampler().clone();
+            sampler.blue_noise = true;
+            scene.set_sampler(sampler);
+        }
+        if let Some(num_threads) = self.settings.threads {
+            scene.set_threads(ThreadSettings {
+                num_threads: Some(num_threads),
+            });
+        }
+        if self.settings.crop.is_some() {
+            scene.set_crop_window(self.settings.crop);
+        }
+        if let Some(debug_view) = self.settings.debug_view {
+            scene.set_render_algorithm(debug_view);
+        }
+        if self.settings.heatmap.is_some() {
+            scene.set_cost_map(self.settings.heatmap);
+        }
+    }
+}
+
+/// Parse a crop window given as `<x>,<y>,<width>,<height>`.
+fn parse_crop(crop: &str) -> Option<CropWindow> {
+    let values = crop
+        .split(',')
+        .map(|value| value.trim().parse().ok())
+        .collect::<Option<Vec<usize>>>()?;
+    match values[..] {
+        [x, y, width, height] => Some(CropWindow {
+            x,
+            y,
+            width,
+            height,
+        }),
+        _ => None,
+    }
+}
+
+/// Parse a debug view given as `normals`, `depth:<distance>`, `uv` or `ids`.
+fn parse_debug_view(view: &str) -> Option<DebugView> {
+    match view {
+        "normals" => Some(DebugView::Normals),
+        "uv" => Some(DebugView::Uv),
+        "ids" => Some(DebugView::SurfaceIds),
+        _ => {
+            let max_distance = view.strip_prefix("depth:")?.parse().ok()?;
+            Some(DebugView::Depth { max_distance })
+        }
+    }
+}
+
+/// Find the option `name` and the value following it in `args`, and remove
+/// them. `usage` is the error if the value is missing.
+pub fn take_option(
+    args: &mut Vec<String>,
+    name: &str,
+    usage: &str,
+) -> Result<Option<String>, Box<dyn Error>> {
+    match args.iter().position(|arg| arg == name) {
+        Some(index) if index + 1 < args.len() => {
+            let value = args.remove(index + 1);
+            args.remove(index);
+            Ok(Some(value))
+        }
+        Some(_) => Err(usage.into()),
+        None => Ok(None),
+    }
+}
+
+/// Find the plugin libraries, given as `--plugin <path>`, and the scene file
+/// in `args`, which are the arguments after the name of the program. The
+/// plugins are loaded into the returned registry. `usage` is the error if
+/// `args` are wrong.
+pub fn plugins_and_scene<'a>(
+    args: &'a [String],
+    usage: &str,
+) -> Result<(Registry, Option<&'a String>), Box<dyn Error>> {
+    let mut registry = Registry::new();
+    let mut scene_filename = None;
+    let mut remaining_args = args.iter();
+    while let Some(arg) = remaining_args.next() {
+        if arg == "--plugin" {
+            let path = remaining_args.next().ok_or(usage)?;
+            // The user asked for the plugin to be loaded, and trusts it.
+            unsafe { registry.load_library(path)? };
+        } else {
+            scene_filename = Some(arg);
+        }
+    }
+    Ok((registry, scene_filename))
+}
+
+/// Render the images that guide denoising the render of `scene` at size
+/// `width` x `height`, if it is to be denoised. Warns and returns `None` if
+/// rustbeam was built without denoising.
+pub fn denoise_features(scene: &Scene, width: usize, height: usize) -> Option<Features> {
+    if !scene.denoise() {
+        return None;
+    }
+    if !denoise::AVAILABLE {
+        eprintln!("Warning: Not denoising, since rustbeam was built without the denoise feature");
+        return None;
+    }
+    Some(Features::render(scene, width, height))
+}
+
+/// Find the lens flares to add to renders of `scene` at size `width` x
+/// `height`, and where, if it asks for them.
+pub fn lens_flare(
+    scene: &Scene,
+    width: usize,
+    height: usize,
+) -> Option<(LensFlare, Vec<FlareSource>)> {
+    let lens_flare = scene.lens_flare()?.clone();
+    Some((lens_flare, scene.flare_sources(width, height)))
+}
+
+/// Save a raw image of the unclamped render `image`, as captured by `sensor`
+/// if there is one, next to the render saved as `filename`, with `.raw.png`
+/// in place of its extension.
+pub fn save_raw(
+    sensor: Option<&Sensor>,
+    image: &Image,
+    filename: &str,
+) -> Result<(), Box<dyn Error>> {
+    if let Some(sensor) = sensor {
+        let raw_filename = Path::new(filename).with_extension("raw.png");
+        let raw_filename = raw_filename.to_string_lossy();
+        sensor.capture(image).save_png(&raw_filename)?;
+        println!("Saved {raw_filename}");
+    }
+    Ok(())
+}
+
+/// Save the images of the light groups in `light_groups`, if any, as PFM files
+/// named after the render saved as `filename` and their groups.
+pub fn save_light_groups(
+    light_groups: Option<&LightGroupImages>,
+    filename: &str,
+) -> Result<(), Box<dyn Error>> {
+    for (name, image) in light_groups.map(LightGroupImages::take).unwrap_or_default() {
+        let group_filename = Path::new(filename).with_extension(format!("{name}.pfm"));
+        let group_filename = group_filename.to_string_lossy();
+        image.save_pfm(&group_filename)?;
+        println!("Saved {group_filename}");
+    }
+    Ok(())
+}
+
+/// Save the heatmap of the costs of the pixels in `cost_map`, if any, as a PNG
+/// file next to the render `filename`.
+pub fn save_heatmap(cost_map: Option<&CostMap>, filename: &str) -> Result<(), Box<dyn Error>> {
+    if let Some(cost_map) = cost_map {
+        let heatmap_filename = Path::new(filename).with_extension("heatmap.png");
+        let heatmap_filename = heatmap_filename.to_string_lossy();
+        cost_map.heatmap().save_png(&heatmap_filename)?;
+        println!("Saved {heatmap_filename}");
+    }
+    Ok(())
+}
+
+/// Save the unclamped render `image` as the PNG file `filename`, along with a
+/// raw image as captured by `sensor`, the images of `light_groups` and the
+/// heatmap of `cost_map`, if any, next to it. The image is clamped in the
+/// process.
+pub fn save_render(
+    image: &mut Image,
+    sensor: Option<&Sensor>,
+    light_groups: Option<&LightGroupImages>,
+    cost_map: Option<&CostMap>,
+    filename: &str,
+) -> Result<(), Box<dyn Error>> {
+    save_raw(sensor, image, filename)?;
+    save_light_groups(light_groups, filename)?;
+    save_heatmap(cost_map, filename)?;
+    image.clamp();
+    image.save_png(filename)?;
+    println!("Saved {filename}");
+    Ok(())
+}
+
+/// Load the scene file `filename`, with surfaces, textures and lights from the
+/// plugins in `registry`.
+pub fn load_scene(filename: &str, registry: &Registry) -> Result<Scene, Box<dyn Error>> {
+    let (scene, warnings) = scene_file::load_scene_with_plugins(filename, registry)?;
+    for warning in warnings {
+        eprintln!("Warning: {warning}");
+    }
+    Ok(scene)
+}
+
+/// Make the command that starts a recording with the scene file `filename`.
+/// The demo scene, used when there is no scene file, can't be recorded.
+pub fn record_scene(filename: Option<&String>) -> Result<Command, Box<dyn Error>> {
+    let filename = filename.ok_or("Only scene files can be recorded, not the demo scene")?;
+    let (description, _) = scene_file::parse_scene_description_as(
+        &fs::read_to_string(filename)?,
+        SceneFormat::from_filename(filename),
+    )?;
+    let directory = Path::new(filename)
+        .parent()
+        .unwrap_or_else(|| Path::new(""));
+    Ok(Command::SetScene {
+        description: Box::new(description),
+        directory: directory.to_string_lossy().into_owned(),
+    })
+}
+
+/// Make a scene with two spheres on a checkered floor, lit by a red, a green
+/// and a blue light.
+pub fn demo_scene() -> Scene {
+    let mut scene = Scene::new();
+
+    scene.add_surface(Sphere::new((-1.0, 5.0, 0.0), 1.5));
+    scene.add_surface(Sphere::new((1.0, 5.0, 0.0), 1.0));
+    scene.add_textured_surface(
+        Plane::new((0.0, 0.0, 1.0), -2.0),
+        Checker::from_colors((0.8, 0.8, 0.8), (0.2, 0.2, 0.2), 0.8, CheckerMapping::Solid),
+    );
+
+    scene.add_light(Sun::new((1.0, 0.0, 0.0), (1.0, 1.0, -1.0)));
+    scene.add_light(Sun::new((0.0, 1.0, 0.0), (-1.0, 1.0, -1.0)));
+    scene.add_light(Sun::new((0.0, 0.0, 1.0), (0.0, 1.0, 1.0)));
+
+    scene
+}
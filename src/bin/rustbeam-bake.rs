@@ -0,0 +1,63 @@
+#![warn(clippy::all, clippy::pedantic)]
+
+//! Bake the surfaces of a scene file into a mesh, for use in other programs.
+
+use rustbeam::obj;
+use rustbeam::scene_file;
+use rustbeam::stl;
+use std::env;
+use std::error::Error;
+
+/// How to bake a scene file, shown when the command line is wrong.
+const USAGE: &str =
+    "Usage: rustbeam-bake <scene.ron> <output.obj|output.stl|output.gltf> [tolerance]";
+
+/// Tessellate the surfaces of the scene in the scene file `filename` within
+/// `tolerance` meters, and save them as `output`, which is an OBJ file, an
+/// STL file in millimeters if its name ends with `.stl`, or a glTF file with
+/// the transforms, materials and camera if it ends with `.gltf`.
+fn export(filename: &str, output: &str, tolerance: f64) -> Result<(), Box<dyn Error>> {
+    let (scene, warnings) = scene_file::load_scene(filename)?;
+    for warning in warnings {
+        eprintln!("Warning: {warning}");
+    }
+
+    if output.to_lowercase().ends_with(".gltf") {
+        // Scenes are rendered at 16:9.
+        let (gltf, skipped) = scene.to_gltf(tolerance, 16.0 / 9.0)?;
+        if skipped > 0 {
+            eprintln!("Warning: Left out {skipped} surfaces that can't be tessellated");
+        }
+        gltf.save(output)?;
+        println!("Saved {output}");
+        return Ok(());
+    }
+
+    let (mesh, skipped) = scene.tessellate(tolerance);
+    if skipped > 0 {
+        eprintln!("Warning: Left out {skipped} surfaces that can't be tessellated");
+    }
+    if output.to_lowercase().ends_with(".stl") {
+        stl::save_stl(&mesh, output, 1000.0)?;
+    } else {
+        obj::save_obj(&mesh, output)?;
+    }
+    println!("Saved {} triangles as {output}", mesh.triangles().len());
+    Ok(())
+}
+
+/// # Errors
+///
+/// Returns `Err` if the command line is wrong, or if baking fails.
+pub fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = env::args().collect();
+    let tolerance = match args.get(3) {
+        Some(tolerance) => tolerance.parse()?,
+        None => 1e-3,
+    };
+    export(
+        args.get(1).ok_or(USAGE)?,
+        args.get(2).ok_or(USAGE)?,
+        tolerance,
+    )
+}
@@ -0,0 +1,347 @@
+#![warn(clippy::all, clippy::pedantic)]
+
+//! Render a scene file without a window, and work with scene files and
+//! renders.
+
+mod common;
+
+use common::{
+    demo_scene, denoise_features, lens_flare, load_scene, plugins_and_scene, record_scene,
+    save_render, take_option, Flags, HEIGHT, WIDTH,
+};
+use rustbeam::denoise;
+use rustbeam::image::Image;
+use rustbeam::pbrt;
+use rustbeam::plugins::Registry;
+use rustbeam::recording::{Command, Recording};
+use rustbeam::report::{self, Render};
+use rustbeam::scene::Scene;
+use rustbeam::scene_file;
+#[cfg(feature = "scripting")]
+use rustbeam::scripting::Animation;
+use rustbeam::stats;
+use rustbeam::verify;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// How to render a scene file, shown when the command line is wrong.
+const USAGE: &str = "Usage: rustbeam-render [--metadata] [--keep-awake] [--low-priority] \
+                     [--blue-noise] [--threads <count>] [--crop <x>,<y>,<width>,<height>] \
+                     [--debug <normals|depth:<distance>|uv|ids>] [--heatmap <time|rays>] \
+                     [--notify] [--webhook <url>] [--plugin <library>]... [--output <image.png>] \
+                     [--record <recording.ron>] [scene.ron]";
+
+/// Where renders are saved if no output is given.
+const DEFAULT_OUTPUT: &str = "test-data/test-data-out/test.png";
+
+/// Render `scene` at size `width` x `height`, denoise it and add lens flares
+/// if it asks for them, and save it as the PNG file `filename`. The render
+/// statistics are printed if rustbeam is built to count rays.
+fn render(scene: Scene, width: usize, height: usize, filename: &str) -> Result<(), Box<dyn Error>> {
+    let features = denoise_features(&scene, width, height);
+    let sensor = scene.sensor().cloned();
+    let lens_flare = lens_flare(&scene, width, height);
+    let light_groups = scene.light_group_images();
+    let cost_map = scene.cost_map();
+
+    let mut image = Image::new(width, height);
+    let handle = scene.spawn_render_threads(width, height);
+    image.update(handle.iter());
+    let render_stats = handle.join()?;
+    if stats::AVAILABLE {
+        println!("{render_stats}");
+    }
+    if let Some(features) = &features {
+        denoise::denoise(&mut image, features)?;
+    }
+    if let Some((lens_flare, sources)) = &lens_flare {
+        lens_flare.apply(&mut image, sources);
+    }
+    save_render(
+        &mut image,
+        sensor.as_ref(),
+        light_groups.as_ref(),
+        cost_map.as_ref(),
+        filename,
+    )
+}
+
+/// Rewrite the scene file `filename` in the current version of the scene file
+/// format. The original file is kept with `.bak` appended to its name.
+fn upgrade(filename: &str) -> Result<(), Box<dyn Error>> {
+    let source = fs::read_to_string(filename)?;
+    let (upgraded, warnings) = scene_file::upgrade_scene(&source)?;
+    for warning in warnings {
+        eprintln!("Warning: {warning}");
+    }
+
+    fs::write(format!("{filename}.bak"), &source)?;
+    fs::write(filename, upgraded)?;
+    println!(
+        "Upgraded {} to version {} of the scene file format.",
+        filename,
+        scene_file::CURRENT_VERSION
+    );
+    Ok(())
+}
+
+/// Print how much memory the scene in the scene file `filename` uses.
+fn memory(filename: &str) -> Result<(), Box<dyn Error>> {
+    let (scene, warnings) = scene_file::load_scene(filename)?;
+    for warning in warnings {
+        eprintln!("Warning: {warning}");
+    }
+
+    println!("{}", scene.memory_report());
+    Ok(())
+}
+
+/// Render the PBRT scene file `filename` at the resolution of its film, and
+/// save it as the PNG file `output`.
+fn render_pbrt(filename: &str, output: &str) -> Result<(), Box<dyn Error>> {
+    let (imported, warnings) = pbrt::load_pbrt(filename)?;
+    for warning in warnings {
+        eprintln!("Warning: {warning}");
+    }
+
+    render(imported.scene, imported.width, imported.height, output)?;
+    println!("Saved {output}");
+    Ok(())
+}
+
+/// Save an HTML report comparing the renders `before` and `after` as
+/// `output`.
+fn compare(before: &str, after: &str, output: &str) -> Result<(), Box<dyn Error>> {
+    let before = Render::load(Path::new(before))?;
+    let after = Render::load(Path::new(after))?;
+    fs::write(output, report::comparison_report(&before, &after)?)?;
+    println!("Saved {output}");
+    Ok(())
+}
+
+/// Replay the recording `filename`, saving each of its renders. Plugins in
+/// the recorded scenes are made by `registry`.
+fn replay(filename: &str, registry: &Registry) -> Result<(), Box<dyn Error>> {
+    Recording::load(filename)?.replay(registry, render)
+}
+
+/// Render the canonical scenes and compare them with the reference images in
+/// `reference_dir`, printing a report. Returns `Err` if any render differs
+/// noticeably from its reference.
+fn verify(reference_dir: &str) -> Result<(), Box<dyn Error>> {
+    let verifications = verify::canonical_scenes()
+        .iter()
+        .map(|scene| verify::verify(scene, Path::new(reference_dir)))
+        .collect::<Result<Vec<_>, _>>()?;
+    print!("{}", verify::report(&verifications));
+
+    let failed = verifications
+        .iter()
+        .filter(|verification| !verification.passed())
+        .count();
+    if failed > 0 {
+        return Err(format!(
+            "{failed} of {} renders differ noticeably",
+            verifications.len()
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Run `rustbeam-render animate` with the command line `args`.
+#[cfg(feature = "scripting")]
+fn animate_command(args: &[String], flags: &Flags) -> Result<(), Box<dyn Error>> {
+    let usage = "Usage: rustbeam-render animate <scene.ron> <script.rhai> <frames> [--metadata] \
+                 [--keep-awake] [--low-priority] [--blue-noise] [--threads <count>] \
+                 [--crop <x>,<y>,<width>,<height>] [--debug <normals|depth:<distance>|uv|ids>] \
+                 [--heatmap <time|rays>] [--notify] [--webhook <url>] [--motion-vectors] \
+                 [--width <pixels>] [--height <pixels>] [--plugin <library>]...";
+    let mut args = args.to_vec();
+    let width = take_option(&mut args, "--width", usage)?.map_or(Ok(WIDTH), |w| w.parse())?;
+    let height = take_option(&mut args, "--height", usage)?.map_or(Ok(HEIGHT), |h| h.parse())?;
+    if width == 0 || height == 0 {
+        return Err(usage.into());
+    }
+    let motion_vectors = args.iter().any(|arg| arg == "--motion-vectors");
+    args.retain(|arg| arg != "--motion-vectors");
+
+    let mut registry = Registry::new();
+    while let Some(path) = take_option(&mut args, "--plugin", usage)? {
+        // The user asked for the plugin to be loaded, and trusts it.
+        unsafe { registry.load_library(&path)? };
+    }
+    let scene_filename = args.get(2).ok_or(usage)?;
+    let script_filename = args.get(3).ok_or(usage)?;
+    let frames = args.get(4).ok_or(usage)?.parse()?;
+    animate(
+        scene_filename,
+        script_filename,
+        frames,
+        motion_vectors,
+        (width, height),
+        &registry,
+        flags,
+    )
+}
+
+/// Render `frames` frames of the animation given by the scene file
+/// `scene_filename` and the Rhai script `script_filename` at size `width` x
+/// `height`, saving them as numbered PNG files in the current directory.
+/// Plugins in the scene are made by `registry`. If `flags.metadata` is true,
+/// the metadata of each frame is saved next to it. If `motion_vectors` is
+/// true, the motion vectors of each frame are saved next to it as a PFM file.
+/// Notifications are sent when all frames are done, with the metadata of the
+/// last one.
+#[cfg(feature = "scripting")]
+fn animate(
+    scene_filename: &str,
+    script_filename: &str,
+    frames: u32,
+    motion_vectors: bool,
+    (width, height): (usize, usize),
+    registry: &Registry,
+    flags: &Flags,
+) -> Result<(), Box<dyn Error>> {
+    let (animation, warnings) = Animation::load(scene_filename, script_filename)?;
+    for warning in warnings {
+        eprintln!("Warning: {warning}");
+    }
+
+    let animation_start = Instant::now();
+    let mut last_metadata = None;
+    for frame in 0..frames {
+        let mut scene = if motion_vectors {
+            animation.scene_with_motion_at(frame, registry)?
+        } else {
+            animation.scene_at(frame, registry)?
+        };
+        flags.apply(&mut scene);
+        let frame_metadata = flags
+            .wants_metadata()
+            .then(|| scene.metadata(width, height, Duration::ZERO));
+
+        let filename = format!("frame_{frame:04}.png");
+        if motion_vectors {
+            let filename = format!("frame_{frame:04}_motion.pfm");
+            scene
+                .render_motion_vectors(width, height)
+                .save_pfm(&filename)?;
+            println!("Saved {filename}");
+        }
+
+        let start = Instant::now();
+        render(scene, width, height, &filename)?;
+
+        if let Some(mut frame_metadata) = frame_metadata {
+            frame_metadata.timings.render = start.elapsed().as_secs_f64();
+            if flags.metadata {
+                frame_metadata.save_sidecar(&filename)?;
+            }
+            last_metadata = Some(frame_metadata);
+        }
+    }
+    flags.notify(last_metadata.as_mut(), animation_start.elapsed());
+    Ok(())
+}
+
+/// Run the command given as the first of `args`, other than rendering a scene
+/// or an animation. Returns whether there was such a command.
+fn run_command(args: &[String]) -> Result<bool, Box<dyn Error>> {
+    match args.get(1).map(String::as_str) {
+        Some("upgrade") => upgrade(
+            args.get(2)
+                .ok_or("Usage: rustbeam-render upgrade <scene.ron>")?,
+        )?,
+        Some("memory") => memory(
+            args.get(2)
+                .ok_or("Usage: rustbeam-render memory <scene.ron>")?,
+        )?,
+        Some("compare") => {
+            let usage = "Usage: rustbeam-render compare <before.png> <after.png> [report.html]";
+            compare(
+                args.get(2).ok_or(usage)?,
+                args.get(3).ok_or(usage)?,
+                args.get(4).map_or("comparison.html", String::as_str),
+            )?;
+        }
+        Some("replay") => {
+            let usage = "Usage: rustbeam-render replay <recording.ron> [--plugin <library>]...";
+            let (registry, filename) = plugins_and_scene(&args[2..], usage)?;
+            replay(filename.ok_or(usage)?, &registry)?;
+        }
+        Some("pbrt") => render_pbrt(
+            args.get(2)
+                .ok_or("Usage: rustbeam-render pbrt <scene.pbrt> [output.png]")?,
+            args.get(3).map_or(DEFAULT_OUTPUT, String::as_str),
+        )?,
+        Some("verify") => verify(args.get(2).map_or(verify::REFERENCE_DIR, String::as_str))?,
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+/// # Errors
+///
+/// Returns `Err` if any function call in the main function returns an `Err`.
+pub fn main() -> Result<(), Box<dyn Error>> {
+    // `--metadata` may be given anywhere, to save the metadata of the render
+    // as a JSON file next to the image, and so may the options for long
+    // renders: `--keep-awake`, `--low-priority`, `--blue-noise`, `--threads`,
+    // `--crop`, `--debug`, `--heatmap`, `--notify` and `--webhook`.
+    let mut args: Vec<String> = env::args().collect();
+    let flags = Flags::take(&mut args, USAGE)?;
+
+    if run_command(&args)? {
+        return Ok(());
+    }
+    #[cfg(feature = "scripting")]
+    if args.get(1).map(String::as_str) == Some("animate") {
+        return animate_command(&args, &flags);
+    }
+
+    let output =
+        take_option(&mut args, "--output", USAGE)?.unwrap_or_else(|| DEFAULT_OUTPUT.to_string());
+    let recording_filename = take_option(&mut args, "--record", USAGE)?;
+
+    // Render the scene file given on the command line, or else a demo scene.
+    let (registry, scene_filename) = plugins_and_scene(&args[1..], USAGE)?;
+
+    // The recording is saved before rendering, so that it is there even if
+    // the render fails.
+    if let Some(recording_filename) = recording_filename {
+        let mut recording = Recording::new();
+        recording.record(record_scene(scene_filename)?);
+        recording.record(Command::Render {
+            width: WIDTH,
+            height: HEIGHT,
+            output: output.clone(),
+        });
+        recording.save(&recording_filename)?;
+        println!("Saved {recording_filename}");
+    }
+
+    let mut scene = match scene_filename {
+        Some(filename) => load_scene(filename, &registry)?,
+        None => demo_scene(),
+    };
+    flags.apply(&mut scene);
+    let mut render_metadata = flags
+        .wants_metadata()
+        .then(|| scene.metadata(WIDTH, HEIGHT, Duration::ZERO));
+
+    let start = Instant::now();
+    render(scene, WIDTH, HEIGHT, &output)?;
+    let render_time = start.elapsed();
+
+    flags.notify(render_metadata.as_mut(), render_time);
+    if let Some(mut render_metadata) = render_metadata.filter(|_| flags.metadata) {
+        render_metadata.timings.render = render_time.as_secs_f64();
+        render_metadata.save_sidecar(&output)?;
+    }
+
+    Ok(())
+}
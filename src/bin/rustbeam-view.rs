@@ -0,0 +1,257 @@
+#![warn(clippy::all, clippy::pedantic)]
+
+//! Render a scene file in a window, showing the pixels as they are done.
+
+mod common;
+
+use common::{
+    demo_scene, denoise_features, lens_flare, load_scene, plugins_and_scene, record_scene,
+    save_render, take_option, Flags, HEIGHT, WIDTH,
+};
+use rustbeam::denoise;
+use rustbeam::image::{Image, Pixel};
+use rustbeam::plugins::Registry;
+use rustbeam::recording::{Command, Recording};
+use rustbeam::scene::{RenderHandle, Scene};
+use sdl2::{
+    event::Event,
+    keyboard::Keycode,
+    pixels::{Color, PixelFormatEnum},
+};
+use std::convert::TryFrom;
+use std::env;
+use std::error::Error;
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::time::{Duration, Instant};
+
+/// How to render a scene file, shown when the command line is wrong.
+const USAGE: &str = "Usage: rustbeam-view [--metadata] [--keep-awake] [--low-priority] \
+                     [--blue-noise] [--threads <count>] [--crop <x>,<y>,<width>,<height>] \
+                     [--debug <normals|depth:<distance>|uv|ids>] [--heatmap <time|rays>] \
+                     [--notify] [--webhook <url>] [--plugin <library>]... [--record <recording.ron>] \
+                     [--replay <recording.ron>] [--passes <count>] [scene.ron]";
+
+/// Where the render is saved when the window is closed.
+const OUTPUT: &str = "test-data/test-data-out/test.png";
+
+/// Find the number of passes to render in, given as `--passes <count>`, and
+/// remove it from `args`. Rendering in passes of one sample per pixel refines
+/// the image over time, instead of finishing one pixel at a time.
+fn take_passes(args: &mut Vec<String>) -> Result<Option<u32>, Box<dyn Error>> {
+    Ok(take_option(args, "--passes", USAGE)?
+        .map(|passes| passes.parse::<u32>())
+        .transpose()
+        .map_err(|_| USAGE)?
+        .filter(|&passes| passes > 0))
+}
+
+/// Start rendering `scene` in `passes` passes, if given, or else one pixel at
+/// a time. Later passes spend their rays on the pixels that are still noisy,
+/// like soft shadows and glass, rather than on those that have settled.
+fn spawn_render(mut scene: Scene, passes: Option<u32>) -> RenderHandle {
+    match passes {
+        Some(passes) => {
+            let mut sampler = scene.sampler().clone();
+            sampler.adaptive_passes = true;
+            scene.set_sampler(sampler);
+            scene.spawn_progressive_render(WIDTH, HEIGHT, Some(passes))
+        }
+        None => scene.spawn_render_threads(WIDTH, HEIGHT),
+    }
+}
+
+/// Take the runs of pixels sent by the render threads so far from `receiver`,
+/// and find whether the threads are done. The render is complete when they are,
+/// which may be before every pixel has a sample from every pass, when clean
+/// pixels are left out of later passes.
+fn receive_pixels(
+    receiver: &Receiver<(usize, usize, Vec<Pixel>)>,
+) -> (Vec<(usize, usize, Vec<Pixel>)>, bool) {
+    let mut pixels: Vec<_> = receiver.try_iter().collect();
+    let finished = match receiver.try_recv() {
+        Ok(run) => {
+            pixels.push(run);
+            false
+        }
+        Err(error) => error == TryRecvError::Disconnected,
+    };
+    (pixels, finished)
+}
+
+/// The scene to render: the scene as it is at the end of the recording
+/// `replay_filename` if given, or else the scene file `scene_filename`, with
+/// the plugins in `registry`, or else a demo scene.
+fn view_scene(
+    replay_filename: Option<&str>,
+    scene_filename: Option<&String>,
+    registry: &Registry,
+) -> Result<Scene, Box<dyn Error>> {
+    Ok(match (replay_filename, scene_filename) {
+        (Some(replay_filename), _) => Recording::load(replay_filename)?.scene(registry)?,
+        (None, Some(filename)) => load_scene(filename, registry)?,
+        (None, None) => demo_scene(),
+    })
+}
+
+/// Save a recording of rendering the scene file `scene_filename` in the
+/// window as `recording_filename`.
+fn save_recording(
+    recording_filename: &str,
+    scene_filename: Option<&String>,
+) -> Result<(), Box<dyn Error>> {
+    let mut recording = Recording::new();
+    recording.record(record_scene(scene_filename)?);
+    recording.record(Command::Render {
+        width: WIDTH,
+        height: HEIGHT,
+        output: OUTPUT.to_string(),
+    });
+    recording.save(recording_filename)?;
+    println!("Saved {recording_filename}");
+    Ok(())
+}
+
+/// # Errors
+///
+/// Returns `Err` if any function call in the main function returns an `Err`.
+pub fn main() -> Result<(), Box<dyn Error>> {
+    // `--metadata` may be given anywhere, to save the metadata of the render
+    // as a JSON file next to the image, and so may the options for long
+    // renders: `--keep-awake`, `--low-priority`, `--blue-noise`, `--threads`,
+    // `--crop`, `--debug`, `--heatmap`, `--notify` and `--webhook`.
+    let mut args: Vec<String> = env::args().collect();
+    let mut flags = Flags::take(&mut args, USAGE)?;
+    let recording_filename = take_option(&mut args, "--record", USAGE)?;
+    let replay_filename = take_option(&mut args, "--replay", USAGE)?;
+    let passes = take_passes(&mut args)?;
+    // The previews of the first passes have a single sample per pixel, whose
+    // noise looks much better as blue noise.
+    flags.settings.blue_noise |= passes.is_some();
+    let (registry, scene_filename) = plugins_and_scene(&args[1..], USAGE)?;
+    if let Some(recording_filename) = recording_filename {
+        save_recording(&recording_filename, scene_filename)?;
+    }
+
+    // Initialize SDL and make a window that can be drawn into.
+    let sdl_context = sdl2::init()?;
+    let video_subsystem = sdl_context.video()?;
+
+    let (width, height) = (WIDTH, HEIGHT);
+    let (window_width, window_height) = (u32::try_from(width)?, u32::try_from(height)?);
+
+    let window = video_subsystem
+        .window("rustbeam", window_width, window_height)
+        .position_centered()
+        .build()?;
+
+    let mut canvas = window.into_canvas().present_vsync().build()?;
+    canvas.set_draw_color(Color::RGB(0, 0, 0));
+    canvas.clear();
+    canvas.present();
+
+    // Make a texture that is to be copied into the canvas every frame.
+    let texture_creator = canvas.texture_creator();
+    let mut texture = texture_creator.create_texture_streaming(
+        PixelFormatEnum::ABGR8888,
+        window_width,
+        window_height,
+    )?;
+
+    let mut scene = view_scene(replay_filename.as_deref(), scene_filename, &registry)?;
+    flags.apply(&mut scene);
+    let mut render_metadata = flags
+        .wants_metadata()
+        .then(|| scene.metadata(width, height, Duration::ZERO));
+
+    // The rendered pixels are written to this image, which is denoised and
+    // gets lens flares when it is complete if the scene asks for them.
+    let mut image = Image::new(width, height);
+    let mut features = denoise_features(&scene, width, height);
+    let sensor = scene.sensor().cloned();
+    let mut lens_flare = lens_flare(&scene, width, height);
+    // Light groups and cost maps aren't rendered in passes.
+    let light_groups = scene.light_group_images().filter(|_| passes.is_none());
+    let cost_map = scene.cost_map().filter(|_| passes.is_none());
+
+    // Rendering of the scene is done in separate threads. When each pixel, or
+    // each sample of a pixel in a pass, is complete, it is sent through a
+    // channel to the main thread and written into the image.
+    let start = Instant::now();
+    let render = spawn_render(scene, passes);
+    // Passes spread their samples by noise, so only the pixels of a render
+    // that isn't in passes can be counted to find when it is complete.
+    let total_pixels = width * height;
+    let mut received_pixels = 0;
+    let mut render_time = None;
+
+    let mut event_pump = sdl_context.event_pump()?;
+
+    // SDL event loop.
+    'render_loop: loop {
+        for event in event_pump.poll_iter() {
+            match event {
+                // Exit the event loop if the user closes the window or presses
+                // the escape key.
+                Event::Quit { .. }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => break 'render_loop,
+                _ => {}
+            }
+        }
+
+        let (pixels, finished) = receive_pixels(render.receiver());
+        if !pixels.is_empty() || (finished && render_time.is_none()) {
+            // If there are any pixels that have been rendered and that have
+            // been sent through the channel, write them to the image, and then
+            // update the texture that is drawn on the screen.
+            received_pixels += pixels.iter().map(|(_, _, run)| run.len()).sum::<usize>();
+            if passes.is_some() {
+                image.accumulate(pixels.into_iter());
+            } else {
+                image.update(pixels.into_iter());
+            }
+            let complete = finished || (passes.is_none() && received_pixels == total_pixels);
+            if let Some(features) = features.take_if(|_| complete) {
+                denoise::denoise(&mut image, &features)?;
+            }
+            if let Some((lens_flare, sources)) = lens_flare.take_if(|_| complete) {
+                lens_flare.apply(&mut image, &sources);
+            }
+            let srgba_vec = image.get_srgba_vector();
+            texture.update(None, srgba_vec.as_slice(), 4 * width)?;
+
+            canvas.copy(&texture, None, None)?;
+
+            if complete && render_time.is_none() {
+                let elapsed = start.elapsed();
+                render_time = Some(elapsed);
+                flags.notify(render_metadata.as_mut(), elapsed);
+            }
+        }
+
+        canvas.present();
+    }
+
+    // Stop rendering if the window was closed before the render was done.
+    render.cancel();
+    render.join()?;
+    save_render(
+        &mut image,
+        sensor.as_ref(),
+        light_groups.as_ref(),
+        cost_map.as_ref(),
+        OUTPUT,
+    )?;
+
+    // If the window was closed before the render was done, the render time is
+    // up to now.
+    if let Some(mut render_metadata) = render_metadata.filter(|_| flags.metadata) {
+        let render_time = render_time.unwrap_or_else(|| start.elapsed());
+        render_metadata.timings.render = render_time.as_secs_f64();
+        render_metadata.save_sidecar(OUTPUT)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,197 @@
+//! Module for denoising renders with Intel Open Image Denoise (OIDN).
+//!
+//! OIDN removes the noise of sampling from a render, guided by images of the
+//! albedo and the normals of the surfaces seen in each pixel, which keep the
+//! edges and textures sharp. The library is linked only when rustbeam is
+//! built with the `denoise` feature, and then needs to be installed.
+//! Otherwise, denoising returns an error.
+
+use crate::image::Image;
+use crate::scene::Scene;
+use std::error::Error;
+
+/// Whether rustbeam was built with the `denoise` feature, so that renders can
+/// be denoised.
+pub const AVAILABLE: bool = cfg!(feature = "denoise");
+
+/// The images that guide denoising, rendered before the scene itself.
+pub struct Features {
+    /// The albedo, as given by `Scene::render_albedo`.
+    pub albedo: Image,
+    /// The shading normals, as given by `Scene::render_normals`.
+    pub normals: Image,
+}
+
+impl Features {
+    /// Render the features of `scene` at size `width` x `height`.
+    pub fn render(scene: &Scene, width: usize, height: usize) -> Self {
+        Self {
+            albedo: scene.render_albedo(width, height),
+            normals: scene.render_normals(width, height),
+        }
+    }
+}
+
+/// Denoise the HDR render `image`, guided by `features` of the same size. The
+/// alpha channel is kept. `image` mustn't be clamped yet, since the denoiser
+/// works best with the full range of the render.
+pub fn denoise(image: &mut Image, features: &Features) -> Result<(), Box<dyn Error>> {
+    let size = image.get_size();
+    if features.albedo.get_size() != size || features.normals.get_size() != size {
+        return Err("The images guiding the denoiser don't match the render".into());
+    }
+
+    let output = filter(
+        size,
+        &image.rgb_f32(),
+        &features.albedo.rgb_f32(),
+        &features.normals.rgb_f32(),
+    )?;
+    image.set_rgb_f32(&output);
+    Ok(())
+}
+
+/// Run the ray tracing filter of OIDN on `color`, guided by `albedo` and
+/// `normal`, all of size `width` x `height` with 3 floats per pixel.
+#[cfg(feature = "denoise")]
+fn filter(
+    (width, height): (usize, usize),
+    color: &[f32],
+    albedo: &[f32],
+    normal: &[f32],
+) -> Result<Vec<f32>, Box<dyn Error>> {
+    use std::ffi::CStr;
+    use std::os::raw::{c_char, c_void};
+
+    /// Point to a NUL-terminated name.
+    fn c_str(name: &[u8]) -> *const c_char {
+        name.as_ptr().cast()
+    }
+
+    let mut output = vec![0.0_f32; color.len()];
+    // The shared images are only read by the filter, except `output`.
+    let images: [(&[u8], *mut c_void); 4] = [
+        (b"color\0", color.as_ptr() as *mut _),
+        (b"albedo\0", albedo.as_ptr() as *mut _),
+        (b"normal\0", normal.as_ptr() as *mut _),
+        (b"output\0", output.as_mut_ptr().cast()),
+    ];
+
+    unsafe {
+        let device = oidn::oidnNewDevice(oidn::DEVICE_TYPE_DEFAULT);
+        if device.is_null() {
+            return Err("Could not make an Open Image Denoise device".into());
+        }
+        oidn::oidnCommitDevice(device);
+
+        let filter = oidn::oidnNewFilter(device, c_str(b"RT\0"));
+        if !filter.is_null() {
+            for (name, data) in images {
+                oidn::oidnSetSharedFilterImage(
+                    filter,
+                    c_str(name),
+                    data,
+                    oidn::FORMAT_FLOAT3,
+                    width,
+                    height,
+                    0,
+                    0,
+                    0,
+                );
+            }
+            oidn::oidnSetFilterBool(filter, c_str(b"hdr\0"), true);
+            oidn::oidnCommitFilter(filter);
+            oidn::oidnExecuteFilter(filter);
+            oidn::oidnReleaseFilter(filter);
+        }
+
+        let mut message = std::ptr::null();
+        let error = oidn::oidnGetDeviceError(device, &mut message);
+        let result = if error != oidn::ERROR_NONE || filter.is_null() {
+            let message = if message.is_null() {
+                "unknown error".into()
+            } else {
+                CStr::from_ptr(message).to_string_lossy()
+            };
+            Err(format!("Could not denoise the render: {}", message).into())
+        } else {
+            Ok(output)
+        };
+        oidn::oidnReleaseDevice(device);
+        result
+    }
+}
+
+#[cfg(not(feature = "denoise"))]
+fn filter(
+    _size: (usize, usize),
+    _color: &[f32],
+    _albedo: &[f32],
+    _normal: &[f32],
+) -> Result<Vec<f32>, Box<dyn Error>> {
+    Err("Could not denoise the render: rustbeam was built without the denoise feature".into())
+}
+
+/// The parts of the C API of OIDN 2 that are used.
+#[cfg(feature = "denoise")]
+mod oidn {
+    use std::os::raw::{c_char, c_int, c_void};
+
+    pub type Device = *mut c_void;
+    pub type Filter = *mut c_void;
+
+    pub const DEVICE_TYPE_DEFAULT: c_int = 0;
+    pub const FORMAT_FLOAT3: c_int = 3;
+    pub const ERROR_NONE: c_int = 0;
+
+    #[link(name = "OpenImageDenoise")]
+    extern "C" {
+        pub fn oidnNewDevice(device_type: c_int) -> Device;
+        pub fn oidnCommitDevice(device: Device);
+        pub fn oidnGetDeviceError(device: Device, message: *mut *const c_char) -> c_int;
+        pub fn oidnReleaseDevice(device: Device);
+
+        pub fn oidnNewFilter(device: Device, filter_type: *const c_char) -> Filter;
+        /// Strides of 0 mean that the pixels and rows are packed.
+        pub fn oidnSetSharedFilterImage(
+            filter: Filter,
+            name: *const c_char,
+            data: *mut c_void,
+            format: c_int,
+            width: usize,
+            height: usize,
+            byte_offset: usize,
+            pixel_byte_stride: usize,
+            row_byte_stride: usize,
+        );
+        pub fn oidnSetFilterBool(filter: Filter, name: *const c_char, value: bool);
+        pub fn oidnCommitFilter(filter: Filter);
+        pub fn oidnExecuteFilter(filter: Filter);
+        pub fn oidnReleaseFilter(filter: Filter);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn features_must_match_the_render() {
+        let features = Features {
+            albedo: Image::new(4, 3),
+            normals: Image::new(4, 3),
+        };
+        let mut image = Image::new(3, 4);
+        assert!(denoise(&mut image, &features).is_err());
+
+        let mut image = Image::new(4, 3);
+        image.set_pixel(1, 2, (0.5, 2.0, 0.25));
+        let rgb = image.rgb_f32();
+        assert_eq!(&rgb[3 * 9..3 * 10], [0.5, 2.0, 0.25]);
+        image.set_rgb_f32(&rgb);
+        assert_eq!(image.rgb_f32(), rgb);
+        if !AVAILABLE {
+            assert!(denoise(&mut image, &features).is_err());
+        }
+    }
+}
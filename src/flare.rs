@@ -0,0 +1,167 @@
+//! Module adding lens flares from bright lights to renders.
+//!
+//! In a real camera, a bright light in view scatters inside the lens. The
+//! edges of the aperture spread it into a starburst of streaks around the
+//! light, and reflections between the glass surfaces of the lens add ghosts:
+//! faint, tinted discs on the line from the light through the middle of the
+//! image. Both are added to a finished render here, at the places where
+//! `Scene::flare_sources` finds visible lights.
+
+use crate::image::Image;
+use crate::math::Vector3;
+use std::f64::consts::PI;
+
+/// A light seen by the camera, which a lens flare is added for.
+#[derive(Clone, Copy, Debug)]
+pub struct FlareSource {
+    /// The position of the light in the image, in pixels from the top left.
+    pub x: f64,
+    pub y: f64,
+    /// The color that a white, diffuse surface facing the light at the camera
+    /// would have in the image.
+    pub color: Vector3,
+}
+
+/// The look of lens flares.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LensFlare {
+    /// The luminance of the color of a source below which it doesn't flare.
+    pub threshold: f64,
+    /// The brightness of the flare in the middle of the starburst, as a
+    /// factor on the color of the source.
+    pub intensity: f64,
+    /// The number of streaks of the starburst, which is the number of blades
+    /// of the aperture, doubled if it is odd. No starburst if 0.
+    pub streaks: u32,
+    /// The length over which the streaks fade to about a third, as a fraction
+    /// of the width of the image.
+    pub streak_length: f64,
+    /// The number of ghosts.
+    pub ghosts: u32,
+}
+
+impl Default for LensFlare {
+    fn default() -> Self {
+        Self {
+            threshold: 1.0,
+            intensity: 0.5,
+            streaks: 6,
+            streak_length: 0.1,
+            ghosts: 4,
+        }
+    }
+}
+
+/// The width of the streaks, in pixels, as a standard deviation.
+const STREAK_WIDTH: f64 = 1.0;
+/// The brightness of the ghosts relative to the middle of the starburst.
+const GHOST_BRIGHTNESS: f64 = 0.02;
+/// The tints of the ghosts, from the coatings of the lens, used in turn.
+const GHOST_TINTS: [(f64, f64, f64); 3] = [(1.0, 0.7, 0.4), (0.4, 0.8, 1.0), (0.7, 1.0, 0.5)];
+
+impl LensFlare {
+    /// Add the flares of `sources` to the unclamped render `image`.
+    pub fn apply(&self, image: &mut Image, sources: &[FlareSource]) {
+        let (width, height) = image.get_size();
+        let mut rgb = image.rgb_f32();
+        for source in sources {
+            if luminance(source.color) < self.threshold {
+                continue;
+            }
+            let color = self.intensity * source.color;
+            for y in 0..height {
+                for x in 0..width {
+                    let (dx, dy) = (x as f64 - source.x, y as f64 - source.y);
+                    let flare = (self.starburst(dx, dy, width)
+                        + self.ghosts(source, x as f64, y as f64, width, height))
+                    .elementwise_mul(color);
+                    let offset = 3 * (y * width + x);
+                    rgb[offset] += flare.x as f32;
+                    rgb[offset + 1] += flare.y as f32;
+                    rgb[offset + 2] += flare.z as f32;
+                }
+            }
+        }
+        image.set_rgb_f32(&rgb);
+    }
+
+    /// Find the brightness of the starburst at (`dx`, `dy`) pixels from its
+    /// middle, in an image `width` pixels wide.
+    fn starburst(&self, dx: f64, dy: f64, width: usize) -> Vector3 {
+        if self.streaks == 0 {
+            return Vector3::zero();
+        }
+        let distance = dx.hypot(dy);
+        let length = (self.streak_length * width as f64).max(1.0);
+        let fade = (-distance / length).exp();
+        // A small glow in the middle, and streaks at equal angles, of which
+        // the nearest one counts.
+        let glow = (-distance / (0.1 * length)).exp();
+        let spacing = PI / f64::from(self.streaks.div_ceil(2));
+        let angle = dy.atan2(dx).rem_euclid(spacing);
+        let across = distance * angle.min(spacing - angle).sin();
+        let streak = (-0.5 * (across / STREAK_WIDTH).powi(2)).exp();
+        (glow + streak * fade).min(1.0) * Vector3::ones()
+    }
+
+    /// Find the brightness of the ghosts of `source` at pixel (`x`, `y`) of an
+    /// image of size `width` x `height`.
+    fn ghosts(&self, source: &FlareSource, x: f64, y: f64, width: usize, height: usize) -> Vector3 {
+        let center = (0.5 * (width - 1) as f64, 0.5 * (height - 1) as f64);
+        let mut brightness = Vector3::zero();
+        for ghost in 0..self.ghosts {
+            // The ghosts are spread along the line from the source through the
+            // middle of the image, mostly on the far side of the middle.
+            let along = 0.6 - 1.6 * f64::from(ghost + 1) / f64::from(self.ghosts);
+            let ghost_x = center.0 + along * (source.x - center.0);
+            let ghost_y = center.1 + along * (source.y - center.1);
+            let radius = width as f64 * (0.02 + 0.01 * f64::from((ghost * 3) % 5));
+            let distance = (x - ghost_x).hypot(y - ghost_y);
+            // A disc with a soft edge.
+            let edge = ((radius - distance) / (0.2 * radius)).clamp(0.0, 1.0);
+            let tint = Vector3::from(GHOST_TINTS[ghost as usize % GHOST_TINTS.len()]);
+            brightness += (GHOST_BRIGHTNESS * edge) * tint;
+        }
+        brightness
+    }
+}
+
+/// Find the luminance of a color in linear RGB.
+fn luminance(rgb: Vector3) -> f64 {
+    0.2126 * rgb.x + 0.7152 * rgb.y + 0.0722 * rgb.z
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flares_follow_bright_sources() {
+        let (width, height) = (64, 36);
+        let flare = LensFlare::default();
+        let source = FlareSource {
+            x: 48.0,
+            y: 9.0,
+            color: Vector3::from((4.0, 4.0, 4.0)),
+        };
+
+        let mut image = Image::new(width, height);
+        let dim = FlareSource {
+            color: Vector3::from((0.5, 0.5, 0.5)),
+            ..source
+        };
+        flare.apply(&mut image, &[dim]);
+        assert!(image.rgb_f32().iter().all(|&value| value == 0.0));
+
+        flare.apply(&mut image, &[source]);
+        let rgb = image.rgb_f32();
+        let value = |x: usize, y: usize| rgb[3 * (y * width + x) + 1];
+        // Bright in the middle, along the horizontal streak, but not between
+        // the streaks, and a ghost on the far side of the middle.
+        assert!(value(48, 9) >= 1.9);
+        assert!(value(56, 9) > 0.5);
+        assert!(value(56, 14) < 0.1);
+        assert!(value(15, 26) > 0.02);
+        assert!(value(2, 34) < 1e-6);
+    }
+}
@@ -0,0 +1,364 @@
+//! Module for saving scenes as glTF 2.0 files, for opening in other tools.
+//!
+//! Each surface becomes a node with a mesh, keeping its transform, and the
+//! camera becomes a perspective or orthographic camera. Materials are
+//! approximated by the metallic-roughness model of glTF, with the albedo
+//! averaged over the vertices of the first mesh using the material, since
+//! textures aren't exported. Lights aren't exported either.
+//!
+//! The scene is Z-up while glTF is Y-up, so all nodes are children of a root
+//! node that rotates Z-up to Y-up. The binary data is embedded in the JSON
+//! file, base64-encoded, so that the scene is a single file.
+
+use crate::materials::Material;
+use crate::math::Vector3;
+use crate::surfaces::{Mesh, Transform};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+/// The target of buffer views with vertex attributes.
+const ARRAY_BUFFER: u32 = 34962;
+/// The target of buffer views with triangle indices.
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+/// The component types of accessors.
+const FLOAT: u32 = 5126;
+const UNSIGNED_INT: u32 = 5125;
+
+/// A glTF document under construction.
+#[derive(Default)]
+pub struct Gltf {
+    buffer: Vec<u8>,
+    buffer_views: Vec<Value>,
+    accessors: Vec<Value>,
+    meshes: Vec<Value>,
+    materials: Vec<Value>,
+    /// The index of each material added so far, by its address.
+    material_indices: HashMap<*const Material, usize>,
+    cameras: Vec<Value>,
+    /// The nodes under the root node.
+    nodes: Vec<Value>,
+    uses_transmission: bool,
+}
+
+impl Gltf {
+    /// Make an empty document.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `mesh` with `material`, placed in the scene by `transform`.
+    /// Materials are shared between meshes that use the same `Material`, and
+    /// `material_name` is only used the first time a material is added.
+    pub fn add_mesh(
+        &mut self,
+        mesh: &Mesh,
+        transform: &Transform,
+        material: &Material,
+        material_name: Option<&str>,
+    ) -> Result<(), Box<dyn Error>> {
+        let material = self.add_material(mesh, material, material_name);
+
+        let position_min = [0, 1, 2].map(|axis| {
+            mesh.positions()
+                .iter()
+                .map(|position| position.component(axis))
+                .fold(f64::INFINITY, f64::min)
+        });
+        let position_max = [0, 1, 2].map(|axis| {
+            mesh.positions()
+                .iter()
+                .map(|position| position.component(axis))
+                .fold(f64::NEG_INFINITY, f64::max)
+        });
+        let vectors = |vectors: &[Vector3]| -> Vec<f32> {
+            vectors
+                .iter()
+                .flat_map(|v| [v.x as f32, v.y as f32, v.z as f32])
+                .collect()
+        };
+        let positions = self.add_floats(&vectors(mesh.positions()), "VEC3");
+        self.accessors[positions]["min"] = json!(position_min);
+        self.accessors[positions]["max"] = json!(position_max);
+        let normals = self.add_floats(&vectors(mesh.normals()), "VEC3");
+        // The texture coordinates of glTF start at the top of the image.
+        let uvs: Vec<f32> = mesh
+            .uvs()
+            .iter()
+            .flat_map(|&(u, v)| [u as f32, (1.0 - v) as f32])
+            .collect();
+        let uvs = self.add_floats(&uvs, "VEC2");
+
+        let indices = mesh
+            .triangles()
+            .iter()
+            .flatten()
+            .map(|&index| u32::try_from(index))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| "Too many vertices for a glTF mesh")?;
+        let bytes: Vec<u8> = indices
+            .iter()
+            .flat_map(|index| index.to_le_bytes())
+            .collect();
+        let view = self.add_buffer_view(&bytes, ELEMENT_ARRAY_BUFFER);
+        let indices = self.add_accessor(json!({
+            "bufferView": view,
+            "componentType": UNSIGNED_INT,
+            "count": indices.len(),
+            "type": "SCALAR",
+        }));
+
+        self.meshes.push(json!({
+            "primitives": [{
+                "attributes": {"POSITION": positions, "NORMAL": normals, "TEXCOORD_0": uvs},
+                "indices": indices,
+                "material": material,
+            }],
+        }));
+        self.nodes.push(json!({
+            "mesh": self.meshes.len() - 1,
+            "matrix": column_major(transform.matrix()),
+        }));
+        Ok(())
+    }
+
+    /// Add a perspective camera placed in the scene by `matrix`, looking
+    /// along its negative z-axis with up along its y-axis, as glTF cameras
+    /// do. `vertical_fov` is in radians.
+    pub fn add_camera(&mut self, matrix: [[f64; 4]; 4], vertical_fov: f64, aspect_ratio: f64) {
+        self.cameras.push(json!({
+            "type": "perspective",
+            "perspective": {"yfov": vertical_fov, "aspectRatio": aspect_ratio, "znear": 1e-3},
+        }));
+        self.nodes.push(json!({
+            "camera": self.cameras.len() - 1,
+            "matrix": column_major(matrix),
+        }));
+    }
+
+    /// Add an orthographic camera placed in the scene by `matrix`, as for
+    /// `add_camera`, seeing a part of the scene `width` x `height` large.
+    pub fn add_orthographic_camera(&mut self, matrix: [[f64; 4]; 4], width: f64, height: f64) {
+        self.cameras.push(json!({
+            "type": "orthographic",
+            "orthographic": {"xmag": 0.5 * width, "ymag": 0.5 * height, "znear": 0.0, "zfar": 1e6},
+        }));
+        self.nodes.push(json!({
+            "camera": self.cameras.len() - 1,
+            "matrix": column_major(matrix),
+        }));
+    }
+
+    /// Save the document as the glTF file `filename`.
+    pub fn save(&self, filename: &str) -> Result<(), Box<dyn Error>> {
+        let mut writer = BufWriter::new(File::create(filename)?);
+        self.write(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Write the document as glTF JSON to `writer`.
+    pub fn write(&self, writer: &mut impl Write) -> Result<(), Box<dyn Error>> {
+        serde_json::to_writer_pretty(&mut *writer, &self.to_json())?;
+        writeln!(writer)?;
+        Ok(())
+    }
+
+    /// Make the JSON of the document.
+    fn to_json(&self) -> Value {
+        // Rotates the Z-up scene to Y-up: z becomes y, and y becomes -z.
+        let root = json!({
+            "name": "rustbeam",
+            "matrix": [1, 0, 0, 0, 0, 0, -1, 0, 0, 1, 0, 0, 0, 0, 0, 1],
+            "children": (1..=self.nodes.len()).collect::<Vec<_>>(),
+        });
+        let mut nodes = vec![root];
+        nodes.extend(self.nodes.iter().cloned());
+
+        let mut document = json!({
+            "asset": {"version": "2.0", "generator": "rustbeam"},
+            "scene": 0,
+            "scenes": [{"nodes": [0]}],
+            "nodes": nodes,
+        });
+        let arrays = [
+            ("meshes", &self.meshes),
+            ("materials", &self.materials),
+            ("cameras", &self.cameras),
+            ("accessors", &self.accessors),
+            ("bufferViews", &self.buffer_views),
+        ];
+        for (name, array) in arrays {
+            if !array.is_empty() {
+                document[name] = json!(array);
+            }
+        }
+        if !self.buffer.is_empty() {
+            document["buffers"] = json!([{
+                "byteLength": self.buffer.len(),
+                "uri": format!("data:application/octet-stream;base64,{}", base64(&self.buffer)),
+            }]);
+        }
+        if self.uses_transmission {
+            document["extensionsUsed"] = json!(["KHR_materials_ior", "KHR_materials_transmission"]);
+        }
+        document
+    }
+
+    /// Add `material` if it hasn't been added yet, with its albedo averaged
+    /// over the vertices of `mesh`. Returns its index.
+    fn add_material(&mut self, mesh: &Mesh, material: &Material, name: Option<&str>) -> usize {
+        if let Some(&index) = self.material_indices.get(&(material as *const _)) {
+            return index;
+        }
+
+        let albedo = mesh
+            .positions()
+            .iter()
+            .zip(mesh.uvs())
+            .fold(Vector3::zero(), |sum, (&position, &uv)| {
+                sum + material.albedo(position, uv)
+            })
+            * (1.0 / mesh.positions().len().max(1) as f64);
+        let mut value = json!({
+            "pbrMetallicRoughness": {
+                "baseColorFactor": [albedo.x, albedo.y, albedo.z, 1.0],
+                "metallicFactor": material.metallic(),
+                "roughnessFactor": material.roughness(),
+            },
+        });
+        if let Some(name) = name {
+            value["name"] = json!(name);
+        }
+        if material.is_transparent() {
+            value["extensions"] = json!({
+                "KHR_materials_transmission": {"transmissionFactor": material.transmission()},
+                "KHR_materials_ior": {"ior": material.ior()},
+            });
+            self.uses_transmission = true;
+        }
+
+        self.materials.push(value);
+        let index = self.materials.len() - 1;
+        self.material_indices.insert(material, index);
+        index
+    }
+
+    /// Add an accessor for `floats`, grouped into elements of `kind`, such as
+    /// "VEC3". Returns its index.
+    fn add_floats(&mut self, floats: &[f32], kind: &str) -> usize {
+        let components = if kind == "VEC2" { 2 } else { 3 };
+        let bytes: Vec<u8> = floats
+            .iter()
+            .flat_map(|float| float.to_le_bytes())
+            .collect();
+        let view = self.add_buffer_view(&bytes, ARRAY_BUFFER);
+        self.add_accessor(json!({
+            "bufferView": view,
+            "componentType": FLOAT,
+            "count": floats.len() / components,
+            "type": kind,
+        }))
+    }
+
+    /// Append `bytes` to the buffer, in a view for `target`. Returns the index
+    /// of the view.
+    fn add_buffer_view(&mut self, bytes: &[u8], target: u32) -> usize {
+        self.buffer_views.push(json!({
+            "buffer": 0,
+            "byteOffset": self.buffer.len(),
+            "byteLength": bytes.len(),
+            "target": target,
+        }));
+        // All components are 4 bytes, so views stay aligned.
+        self.buffer.extend_from_slice(bytes);
+        self.buffer_views.len() - 1
+    }
+
+    fn add_accessor(&mut self, accessor: Value) -> usize {
+        self.accessors.push(accessor);
+        self.accessors.len() - 1
+    }
+}
+
+/// List the elements of `matrix` column by column, as glTF does.
+fn column_major(matrix: [[f64; 4]; 4]) -> Vec<f64> {
+    (0..16).map(|i| matrix[i % 4][i / 4]).collect()
+}
+
+/// Encode `bytes` as base64, with padding.
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0_u32, |group, (i, &byte)| {
+            group | u32::from(byte) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(char::from(ALPHABET[(group >> (18 - 6 * i) & 63) as usize]));
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::UnitQuaternion;
+    use crate::surfaces::{Sphere, Surface};
+
+    #[test]
+    fn base64_is_padded() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+    }
+
+    #[test]
+    fn meshes_share_materials_and_keep_transforms() {
+        let mesh = Sphere::new((0.0, 0.0, 0.0), 1.0).tessellate(0.1).unwrap();
+        let glass = Material::default().with_transmission(1.0);
+        let transform = Transform::new(
+            (2.0, 2.0, 2.0),
+            UnitQuaternion::id(),
+            Vector3::from((1.0, 2.0, 3.0)),
+        );
+
+        let mut gltf = Gltf::new();
+        gltf.add_mesh(&mesh, &transform, &glass, Some("glass"))
+            .unwrap();
+        gltf.add_mesh(&mesh, &Transform::identity(), &glass, None)
+            .unwrap();
+        gltf.add_camera(Transform::identity().matrix(), 1.0, 1.5);
+        let mut bytes = Vec::new();
+        gltf.write(&mut bytes).unwrap();
+        let document: Value = serde_json::from_slice(&bytes).unwrap();
+
+        assert_eq!(document["nodes"].as_array().unwrap().len(), 4);
+        assert_eq!(document["nodes"][0]["children"], json!([1, 2, 3]));
+        assert_eq!(
+            document["nodes"][1]["matrix"],
+            json!([2.0, 0.0, 0.0, 0.0, 0.0, 2.0, 0.0, 0.0, 0.0, 0.0, 2.0, 0.0, 1.0, 2.0, 3.0, 1.0])
+        );
+        assert_eq!(document["nodes"][3]["camera"], 0);
+        assert_eq!(document["materials"].as_array().unwrap().len(), 1);
+        assert_eq!(document["materials"][0]["name"], "glass");
+        assert_eq!(document["meshes"][1]["primitives"][0]["material"], 0);
+
+        let indices = &document["accessors"][3];
+        assert_eq!(indices["count"], 3 * mesh.triangles().len());
+        let uri = document["buffers"][0]["uri"].as_str().unwrap();
+        let length = document["buffers"][0]["byteLength"].as_u64().unwrap();
+        let encoded = uri.split(',').nth(1).unwrap();
+        assert_eq!(encoded.len() as u64, length.div_ceil(3) * 4);
+    }
+}
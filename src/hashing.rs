@@ -0,0 +1,83 @@
+//! Module for hashing the content of scenes.
+//!
+//! The hash of a scene changes whenever anything that affects how it renders
+//! changes, and stays the same across runs, platforms and versions of Rust, so
+//! that data computed from a scene and saved can be checked against it later.
+
+use crate::math::Vector3;
+use serde::Serialize;
+
+/// Computes a 64-bit FNV-1a hash of content fed to it piece by piece. Content
+/// that can't be hashed, such as surfaces made by plugins, is recorded with
+/// `write_unknown`, after which there is no hash.
+pub struct ContentHasher {
+    hash: u64,
+    known: bool,
+}
+
+impl Default for ContentHasher {
+    fn default() -> Self {
+        Self {
+            hash: 0xcbf2_9ce4_8422_2325,
+            known: true,
+        }
+    }
+}
+
+impl ContentHasher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.hash = (self.hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.write_bytes(&value.to_le_bytes());
+    }
+
+    /// Write a number. Positive and negative zero hash the same.
+    pub fn write_f64(&mut self, value: f64) {
+        let value = if value == 0.0 { 0.0 } else { value };
+        self.write_u64(value.to_bits());
+    }
+
+    pub fn write_vector(&mut self, vector: Vector3) {
+        self.write_f64(vector.x);
+        self.write_f64(vector.y);
+        self.write_f64(vector.z);
+    }
+
+    /// Write a string, prefixed by its length, so that consecutive strings
+    /// can't run together. Used for naming the kind of content that follows.
+    pub fn write_str(&mut self, string: &str) {
+        self.write_u64(string.len() as u64);
+        self.write_bytes(string.as_bytes());
+    }
+
+    /// Write a value that can be serialized, such as the settings of a scene.
+    pub fn write_serialized(&mut self, value: &impl Serialize) {
+        match ron::to_string(value) {
+            Ok(string) => self.write_str(&string),
+            Err(_) => self.write_unknown(),
+        }
+    }
+
+    /// Record that some of the content can't be hashed.
+    pub fn write_unknown(&mut self) {
+        self.known = false;
+    }
+
+    /// Find the hash of the content written, or `None` if some of it couldn't
+    /// be hashed.
+    pub fn finish(&self) -> Option<u64> {
+        if self.known {
+            Some(self.hash)
+        } else {
+            None
+        }
+    }
+}
@@ -1,9 +1,16 @@
 //! Module for working with images and pixels.
 
+mod font;
+mod hdr;
+
+pub use hdr::{load_hdr, parse_hdr};
+
 use crate::math::Vector3;
+use serde::Serialize;
+use std::convert::TryFrom;
 use std::error::Error;
 use std::fs::File;
-use std::io::BufWriter;
+use std::io::{BufWriter, Write};
 
 /// A pixel containing RGBA data in floating point format. Values range from 0
 /// to 1, where 0 means black, and 1 means max color. For the alpha channel, 0
@@ -24,6 +31,38 @@ impl Pixel {
     fn new(r: f64, g: f64, b: f64, a: f64) -> Self {
         Self { r, g, b, a }
     }
+
+    /// Make a pixel of color `rgb`, which is not premultiplied by `alpha`.
+    pub fn with_alpha(rgb: Vector3, alpha: f64) -> Self {
+        Self::new(rgb.x, rgb.y, rgb.z, alpha)
+    }
+
+    /// The color of the pixel.
+    pub(crate) fn rgb(self) -> Vector3 {
+        Vector3::from((self.r, self.g, self.b))
+    }
+
+    /// Scale the color of the pixel by `factor`, keeping its alpha.
+    pub fn scaled(self, factor: f64) -> Self {
+        Self::new(factor * self.r, factor * self.g, factor * self.b, self.a)
+    }
+
+    /// Move the pixel the fraction `weight` of the way to `other`.
+    pub(crate) fn blend(self, other: Self, weight: f64) -> Self {
+        let mix = |a: f64, b: f64| a + weight * (b - a);
+        Self::new(
+            mix(self.r, other.r),
+            mix(self.g, other.g),
+            mix(self.b, other.b),
+            mix(self.a, other.a),
+        )
+    }
+
+    /// Make a pixel of the red channel of `red`, the green channel of `green`
+    /// and the blue channel of `blue`, with their average alpha.
+    pub fn from_channels(red: Self, green: Self, blue: Self) -> Self {
+        Self::new(red.r, green.g, blue.b, (red.a + green.a + blue.a) / 3.0)
+    }
 }
 
 impl Default for Pixel {
@@ -45,11 +84,151 @@ impl From<Vector3> for Pixel {
     }
 }
 
+/// The color space that authored colors and color data are given in. Rendering
+/// is always done in linear RGB, so other color spaces are converted on input.
+#[derive(Clone, Copy, Default, PartialEq, Serialize)]
+pub enum ColorSpace {
+    /// Linear RGB, with the primaries of SRGB.
+    #[default]
+    Linear,
+    /// Gamma-encoded SRGB, as used by most image files and color pickers.
+    Srgb,
+}
+
+impl ColorSpace {
+    /// Convert an RGB color given in this color space to linear RGB.
+    pub fn to_linear(self, rgb: Vector3) -> Vector3 {
+        match self {
+            ColorSpace::Linear => rgb,
+            ColorSpace::Srgb => Vector3 {
+                x: srgb_to_linear(rgb.x),
+                y: srgb_to_linear(rgb.y),
+                z: srgb_to_linear(rgb.z),
+            },
+        }
+    }
+
+    /// Convert a linear RGB color to this color space, as the inverse of
+    /// `to_linear`.
+    pub fn from_linear(self, rgb: Vector3) -> Vector3 {
+        match self {
+            ColorSpace::Linear => rgb,
+            ColorSpace::Srgb => Vector3 {
+                x: linear_to_srgb(rgb.x),
+                y: linear_to_srgb(rgb.y),
+                z: linear_to_srgb(rgb.z),
+            },
+        }
+    }
+
+    /// Parse a hex color of the form "#rrggbb" or "rrggbb", interpreted in this
+    /// color space, and convert it to linear RGB. Returns `None` if the string
+    /// isn't a valid hex color.
+    pub fn from_hex(self, hex: &str) -> Option<Vector3> {
+        let digits = hex.strip_prefix('#').unwrap_or(hex);
+        if digits.len() != 6 || !digits.is_ascii() {
+            return None;
+        }
+
+        let channel = |range| {
+            u8::from_str_radix(&digits[range], 16)
+                .ok()
+                .map(|value| f64::from(value) / 255.0)
+        };
+        let rgb = Vector3 {
+            x: channel(0..2)?,
+            y: channel(2..4)?,
+            z: channel(4..6)?,
+        };
+
+        Some(self.to_linear(rgb))
+    }
+}
+
+/// Convert a color channel from SRGB to linear color space. This is the inverse
+/// of the SRGB transfer function.
+pub(crate) fn srgb_to_linear(color: f64) -> f64 {
+    if color <= 0.040_45 {
+        color / 12.92
+    } else {
+        ((color + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Convert a color channel from linear color space to SRGB, as the SRGB
+/// transfer function, unclamped.
+fn linear_to_srgb(color: f64) -> f64 {
+    if color < 0.003_130_8 {
+        12.92 * color
+    } else {
+        1.055 * color.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Find the color of light emitted by a black body at `temperature` kelvin, in
+/// linear RGB. The color is scaled to have a luminance of 1.
+///
+/// Planck's law is integrated over the visible spectrum, using the analytic
+/// fit of the CIE 1931 color matching functions by Wyman, Sloan and Shirley.
+pub fn blackbody_color(temperature: f64) -> Vector3 {
+    // Physical constants in SI units.
+    const PLANCK: f64 = 6.626_070_15e-34;
+    const SPEED_OF_LIGHT: f64 = 2.997_924_58e8;
+    const BOLTZMANN: f64 = 1.380_649e-23;
+
+    // Piecewise Gaussian with different widths on each side of the center.
+    let gaussian = |wavelength: f64, center: f64, left_width: f64, right_width: f64| {
+        let width = if wavelength < center {
+            left_width
+        } else {
+            right_width
+        };
+        (-0.5 * ((wavelength - center) / width).powi(2)).exp()
+    };
+
+    let mut xyz = (0.0, 0.0, 0.0);
+    for step in 0..=80 {
+        let wavelength = 380.0 + 5.0 * f64::from(step);
+
+        let meters = wavelength * 1e-9;
+        let radiance = 2.0 * PLANCK * SPEED_OF_LIGHT.powi(2)
+            / (meters.powi(5)
+                * ((PLANCK * SPEED_OF_LIGHT / (meters * BOLTZMANN * temperature)).exp() - 1.0));
+
+        xyz.0 += radiance
+            * (1.056 * gaussian(wavelength, 599.8, 37.9, 31.0)
+                + 0.362 * gaussian(wavelength, 442.0, 16.0, 26.7)
+                - 0.065 * gaussian(wavelength, 501.1, 20.4, 26.2));
+        xyz.1 += radiance
+            * (0.821 * gaussian(wavelength, 568.8, 46.9, 40.5)
+                + 0.286 * gaussian(wavelength, 530.9, 16.3, 31.1));
+        xyz.2 += radiance
+            * (1.217 * gaussian(wavelength, 437.0, 11.8, 36.0)
+                + 0.681 * gaussian(wavelength, 459.0, 26.0, 13.8));
+    }
+
+    xyz_to_linear(xyz.0 / xyz.1, 1.0, xyz.2 / xyz.1)
+}
+
+/// Convert a color from CIE XYZ to linear RGB with SRGB primaries.
+pub(crate) fn xyz_to_linear(x: f64, y: f64, z: f64) -> Vector3 {
+    Vector3 {
+        x: 3.240_454_2 * x - 1.537_138_5 * y - 0.498_531_4 * z,
+        y: -0.969_266_0 * x + 1.876_010_8 * y + 0.041_556_0 * z,
+        z: 0.055_643_4 * x - 0.204_025_9 * y + 1.057_225_2 * z,
+    }
+}
+
+/// The largest scale of the text in the labels of `Image::grid`.
+const MAX_LABEL_SCALE: usize = 2;
+
 /// An image containing `Pixel`s. Internally, it also contains SRGBA data.
 pub struct Image {
     width: usize,
     height: usize,
     pixels: Vec<Pixel>,
+    /// The number of samples averaged in each pixel by `accumulate`.
+    samples: Vec<u32>,
     srgba_data: Vec<u8>,
 }
 
@@ -72,6 +251,7 @@ impl Image {
             width,
             height,
             pixels,
+            samples: vec![0; num_pixels],
             srgba_data,
         }
     }
@@ -82,31 +262,234 @@ impl Image {
     }
 
     /// Set pixel at coordinate (`x`, `y`). The function updates both the vector
-    /// of `Pixel`s and the vector of SRGBA data.
+    /// of `Pixel`s and the vector of SRGBA data. The pixel no longer counts any
+    /// samples averaged in it by `accumulate`.
     pub fn set_pixel<T: Into<Pixel>>(&mut self, x: usize, y: usize, pixel: T) {
         assert!(x < self.width);
         assert!(y < self.height);
 
         let offset = self.width * y + x;
 
-        let pixel = pixel.into();
-        self.pixels[offset] = pixel;
+        self.pixels[offset] = pixel.into();
+        self.samples[offset] = 0;
+        self.update_srgba_pixel(offset);
+    }
 
-        self.srgba_data[offset * 4] = Image::linear_to_srgb(pixel.r);
-        self.srgba_data[offset * 4 + 1] = Image::linear_to_srgb(pixel.g);
-        self.srgba_data[offset * 4 + 2] = Image::linear_to_srgb(pixel.b);
-        self.srgba_data[offset * 4 + 3] = (pixel.a * 255.0).round() as u8;
+    /// Average samples of pixels into the image, such as those sent by
+    /// `Scene::spawn_progressive_render`, given in runs along rows as for
+    /// `update`. Each pixel is the average of the samples given for it. A
+    /// pixel set by `set_pixel` or `update` is replaced by its first sample.
+    pub fn accumulate(&mut self, samples: impl Iterator<Item = (usize, usize, Vec<Pixel>)>) {
+        for (x, y, run) in samples {
+            assert!(x + run.len() <= self.width);
+            assert!(y < self.height);
+
+            for (offset, pixel) in (self.width * y + x..).zip(run) {
+                self.samples[offset] += 1;
+                let weight = 1.0 / f64::from(self.samples[offset]);
+                self.pixels[offset] = self.pixels[offset].blend(pixel, weight);
+                self.update_srgba_pixel(offset);
+            }
+        }
+    }
+
+    /// The number of samples averaged in the pixel at (`x`, `y`) by
+    /// `accumulate`.
+    pub fn sample_count(&self, x: usize, y: usize) -> u32 {
+        self.samples[self.width * y + x]
     }
 
     /// Update pixels of the image. `pixels` is an iterator that yields tuples
-    /// containing the x- and y-coordinates, and the `Pixel` that is to be
-    /// written into the image.
-    pub fn update(&mut self, pixels: impl Iterator<Item = (usize, usize, Pixel)>) {
-        for (x, y, pixel) in pixels {
-            self.set_pixel(x, y, pixel);
+    /// containing the x- and y-coordinates of a pixel, and the `Pixel`s that
+    /// are to be written into the image from there to the right.
+    pub fn update(&mut self, pixels: impl Iterator<Item = (usize, usize, Vec<Pixel>)>) {
+        for (x, y, run) in pixels {
+            for (index, pixel) in run.into_iter().enumerate() {
+                self.set_pixel(x + index, y, pixel);
+            }
         }
     }
 
+    /// Copy `image` into this image, with its top left corner at (`x`, `y`).
+    /// Parts of `image` outside this image are skipped.
+    pub fn draw_image(&mut self, image: &Image, x: usize, y: usize) {
+        for source_y in 0..image.height.min(self.height.saturating_sub(y)) {
+            for source_x in 0..image.width.min(self.width.saturating_sub(x)) {
+                let pixel = image.pixels[image.width * source_y + source_x];
+                self.set_pixel(x + source_x, y + source_y, pixel);
+            }
+        }
+    }
+
+    /// Draw `text` on a single line, with the top left corner at (`x`, `y`),
+    /// using a built-in bitmap font where each character is 6 x 7 pixels,
+    /// including spacing, times `scale`. Parts of the text outside the image
+    /// are skipped.
+    pub fn draw_text<T: Into<Pixel>>(
+        &mut self,
+        text: &str,
+        x: usize,
+        y: usize,
+        scale: usize,
+        color: T,
+    ) {
+        let color = color.into();
+        let advance = (font::GLYPH_WIDTH + 1) * scale;
+
+        for (index, c) in text.chars().enumerate() {
+            let glyph = font::glyph(c);
+            let glyph_x = x + index * advance;
+
+            for (row, bits) in glyph.iter().enumerate() {
+                for column in 0..font::GLYPH_WIDTH {
+                    if bits & (1 << (font::GLYPH_WIDTH - 1 - column)) == 0 {
+                        continue;
+                    }
+                    for dy in 0..scale {
+                        for dx in 0..scale {
+                            let (pixel_x, pixel_y) =
+                                (glyph_x + column * scale + dx, y + row * scale + dy);
+                            if pixel_x < self.width && pixel_y < self.height {
+                                self.set_pixel(pixel_x, pixel_y, color);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Assemble `images` into a contact sheet, in rows of `columns` images
+    /// from left to right, with `gap` pixels between and around them. Every
+    /// image gets a cell as large as the largest image. `labels` are drawn in
+    /// white above the images with the same index, and may have several
+    /// lines, separated by newlines. The text is as large as fits the cells,
+    /// and lines that are too long anyway are cut. Images without a label may
+    /// be left out of `labels`.
+    pub fn grid(images: &[Image], columns: usize, gap: usize, labels: &[String]) -> Self {
+        let columns = columns.max(1);
+        let rows = images.len().div_ceil(columns);
+        let cell_width = images.iter().map(|image| image.width).max().unwrap_or(0);
+        let cell_height = images.iter().map(|image| image.height).max().unwrap_or(0);
+
+        let lines = |label: &String| label.lines().map(|line| line.chars().count()).collect();
+        let line_lengths: Vec<Vec<usize>> = labels.iter().map(lines).collect();
+        let max_lines = line_lengths.iter().map(Vec::len).max().unwrap_or(0);
+        let max_length = line_lengths.iter().flatten().copied().max().unwrap_or(0);
+
+        let character_width = font::GLYPH_WIDTH + 1;
+        let scale = (1..=MAX_LABEL_SCALE)
+            .rev()
+            .find(|scale| max_length * character_width * scale <= cell_width)
+            .unwrap_or(1);
+        let max_characters = cell_width / character_width;
+        let line_height = (font::GLYPH_HEIGHT + 1) * scale;
+        let label_height = if max_lines == 0 {
+            0
+        } else {
+            max_lines * line_height + gap
+        };
+
+        let stride_x = cell_width + gap;
+        let stride_y = label_height + cell_height + gap;
+        let mut sheet = Image::new(columns * stride_x + gap, rows * stride_y + gap);
+
+        for (index, image) in images.iter().enumerate() {
+            let x = gap + (index % columns) * stride_x;
+            let y = gap + (index / columns) * stride_y;
+
+            if let Some(label) = labels.get(index) {
+                for (line_index, line) in label.lines().enumerate() {
+                    let line: String = line.chars().take(max_characters).collect();
+                    sheet.draw_text(
+                        &line,
+                        x,
+                        y + line_index * line_height,
+                        scale,
+                        Vector3::ones(),
+                    );
+                }
+            }
+            sheet.draw_image(image, x, y + label_height);
+        }
+
+        sheet
+    }
+
+    /// Combine several independently rendered images of the same scene into a
+    /// single image. Each image is given together with the number of samples
+    /// per pixel it was rendered with. Each pixel of the result is the average
+    /// of the pixels of the images, weighted by the number of samples counted
+    /// in them, as by `accumulate`, or by the given number of samples of their
+    /// image where none are counted, as in images filled by `update`. The
+    /// result counts the summed samples.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `images` is empty, if the images differ in size, or if a
+    /// pixel has no samples in any of the images.
+    pub fn merge_weighted(images: &[(Image, usize)]) -> Self {
+        assert!(!images.is_empty());
+
+        let (width, height) = images[0].0.get_size();
+        for (image, _) in images.iter() {
+            assert_eq!(image.get_size(), (width, height));
+        }
+
+        let mut merged = Image::new(width, height);
+
+        for offset in 0..merged.pixels.len() {
+            let mut rgba = (0.0, 0.0, 0.0, 0.0);
+            let mut total_weight = 0.0;
+            let mut total_samples: u32 = 0;
+
+            for (image, samples) in images.iter() {
+                let samples = match image.samples[offset] {
+                    0 => u32::try_from(*samples).unwrap_or(u32::MAX),
+                    counted => counted,
+                };
+                let pixel = image.pixels[offset];
+                let weight = f64::from(samples);
+                rgba.0 += weight * pixel.r;
+                rgba.1 += weight * pixel.g;
+                rgba.2 += weight * pixel.b;
+                rgba.3 += weight * pixel.a;
+                total_weight += weight;
+                total_samples = total_samples.saturating_add(samples);
+            }
+            assert!(total_samples > 0);
+
+            let recip_total = 1.0 / total_weight;
+            merged.pixels[offset] = Pixel::new(
+                rgba.0 * recip_total,
+                rgba.1 * recip_total,
+                rgba.2 * recip_total,
+                rgba.3 * recip_total,
+            );
+            merged.samples[offset] = total_samples;
+        }
+
+        merged.update_srgba_data();
+        merged
+    }
+
+    /// Recompute the SRGBA data from the vector of `Pixel`s.
+    fn update_srgba_data(&mut self) {
+        for offset in 0..self.pixels.len() {
+            self.update_srgba_pixel(offset);
+        }
+    }
+
+    /// Recompute the SRGBA data of the pixel at `offset`.
+    fn update_srgba_pixel(&mut self, offset: usize) {
+        let pixel = self.pixels[offset];
+
+        self.srgba_data[offset * 4] = Image::linear_to_srgb(pixel.r);
+        self.srgba_data[offset * 4 + 1] = Image::linear_to_srgb(pixel.g);
+        self.srgba_data[offset * 4 + 2] = Image::linear_to_srgb(pixel.b);
+        self.srgba_data[offset * 4 + 3] = (pixel.a * 255.0).round() as u8;
+    }
+
     /// Get the SRGBA data vector. These data are gamma corrected.
     pub fn get_srgba_vector(&self) -> &Vec<u8> {
         &self.srgba_data
@@ -131,6 +514,47 @@ impl Image {
         Ok(())
     }
 
+    /// Save the color channels of the image as a PFM file, a simple format of
+    /// 32-bit floats that keeps values outside 0 to 1, such as the motion
+    /// vectors from `Scene::render_motion_vectors`.
+    pub fn save_pfm(&self, filename: &str) -> Result<(), Box<dyn Error>> {
+        let mut writer = BufWriter::new(File::create(filename)?);
+        // A negative scale means little-endian. The rows go from the bottom.
+        write!(writer, "PF\n{} {}\n-1.0\n", self.width, self.height)?;
+        for row in self.pixels.chunks(self.width.max(1)).rev() {
+            for pixel in row {
+                for channel in [pixel.r, pixel.g, pixel.b] {
+                    writer.write_all(&(channel as f32).to_le_bytes())?;
+                }
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Get the color channels of the image as 32-bit floats, pixel by pixel
+    /// from the top left, as image libraries commonly take them.
+    pub(crate) fn rgb_f32(&self) -> Vec<f32> {
+        self.pixels
+            .iter()
+            .flat_map(|pixel| [pixel.r as f32, pixel.g as f32, pixel.b as f32])
+            .collect()
+    }
+
+    /// Set the color channels of the image from 32-bit floats, laid out as by
+    /// `rgb_f32`. The alpha channel is kept.
+    pub(crate) fn set_rgb_f32(&mut self, rgb: &[f32]) {
+        assert_eq!(rgb.len(), 3 * self.pixels.len());
+
+        for (offset, rgb) in rgb.chunks(3).enumerate() {
+            let pixel = &mut self.pixels[offset];
+            pixel.r = f64::from(rgb[0]);
+            pixel.g = f64::from(rgb[1]);
+            pixel.b = f64::from(rgb[2]);
+            self.update_srgba_pixel(offset);
+        }
+    }
+
     /// Find the minimum and maximum color values in the image, looking through
     /// R, G, and B channels.
     pub fn min_max(&self) -> (f64, f64) {
@@ -184,12 +608,89 @@ impl Image {
     /// Convert color from linear color space to SRGB. `color` must be between 0
     /// and 1.
     fn linear_to_srgb(color: f64) -> u8 {
-        let srgb = if color < 0.003_130_8 {
-            12.92 * color
-        } else {
-            1.055 * color.powf(1.0 / 2.4) - 0.055
-        };
+        (linear_to_srgb(color) * 255.0).round() as u8
+    }
+}
 
-        (srgb * 255.0).round() as u8
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grid_places_images_in_rows_below_labels() {
+        let mut red = Image::new(4, 3);
+        red.set_pixel(0, 0, (1.0, 0.0, 0.0));
+        let mut blue = Image::new(2, 2);
+        blue.set_pixel(0, 0, (0.0, 0.0, 1.0));
+        let images = [red, blue, Image::new(4, 3)];
+
+        let without_labels = Image::grid(&images, 2, 1, &[]);
+        assert_eq!(without_labels.get_size(), (2 * 5 + 1, 2 * 4 + 1));
+        assert_eq!(without_labels.pixels[without_labels.width + 1].r, 1.0);
+        assert_eq!(without_labels.pixels[without_labels.width + 6].b, 1.0);
+
+        // A label of two lines takes two lines of 8 pixels and a gap.
+        let labels = ["A\nB".to_string()];
+        let with_labels = Image::grid(&images, 2, 1, &labels);
+        assert_eq!(with_labels.get_size(), (2 * 5 + 1, 2 * (17 + 4) + 1));
+        assert_eq!(with_labels.pixels[18 * with_labels.width + 1].r, 1.0);
+    }
+
+    #[test]
+    fn merge_weighted_averages_by_sample_counts() {
+        // Two passes accumulated from one and three samples per pixel.
+        let mut one = Image::new(2, 1);
+        one.accumulate(vec![(0, 0, vec![Pixel::from((1.0, 0.0, 0.0)); 2])].into_iter());
+        let mut three = Image::new(2, 1);
+        for _ in 0..3 {
+            three.accumulate(vec![(0, 0, vec![Pixel::from((0.0, 0.0, 1.0))])].into_iter());
+        }
+
+        // The second pixel of `three` has no counted samples, so it is weighted
+        // by the given count.
+        let merged = Image::merge_weighted(&[(one, 1), (three, 7)]);
+        assert_eq!(merged.pixels[0].r, 0.25);
+        assert_eq!(merged.pixels[0].b, 0.75);
+        assert_eq!(merged.sample_count(0, 0), 4);
+        assert_eq!(merged.pixels[1].r, 0.125);
+        assert_eq!(merged.pixels[1].b, 0.0);
+        assert_eq!(merged.sample_count(1, 0), 8);
+    }
+
+    #[test]
+    fn merge_weighted_weights_updated_images_by_their_samples_per_pixel() {
+        // Renders collected by `update` count no samples of their own.
+        let mut few = Image::new(1, 1);
+        few.update(vec![(0, 0, vec![Pixel::from((1.0, 0.0, 0.0))])].into_iter());
+        let mut many = Image::new(1, 1);
+        many.update(vec![(0, 0, vec![Pixel::from((0.0, 0.0, 1.0))])].into_iter());
+        assert_eq!(many.sample_count(0, 0), 0);
+
+        let merged = Image::merge_weighted(&[(few, 16), (many, 48)]);
+        assert_eq!(merged.pixels[0].r, 0.25);
+        assert_eq!(merged.pixels[0].b, 0.75);
+        assert_eq!(merged.sample_count(0, 0), 64);
+
+        // Huge sample counts saturate instead of overflowing.
+        let merged =
+            Image::merge_weighted(&[(Image::new(1, 1), usize::MAX), (Image::new(1, 1), 1)]);
+        assert_eq!(merged.sample_count(0, 0), u32::MAX);
+    }
+
+    #[test]
+    fn blackbody_colors_warm_up_as_they_cool() {
+        // Around 6500 K, black bodies look close to the white point of sRGB.
+        let white = blackbody_color(6_500.0);
+        let mean = (white.x + white.y + white.z) / 3.0;
+        for channel in [white.x, white.y, white.z] {
+            assert!((channel / mean - 1.0).abs() < 0.1, "{:?}", white);
+        }
+
+        let red_to_blue = |temperature| {
+            let color = blackbody_color(temperature);
+            color.x / color.z
+        };
+        assert!(red_to_blue(2_700.0) > red_to_blue(4_000.0));
+        assert!(red_to_blue(4_000.0) > red_to_blue(10_000.0));
     }
 }
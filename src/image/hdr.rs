@@ -0,0 +1,188 @@
+//! Module for reading Radiance HDR images, the usual format of environment
+//! maps.
+//!
+//! Each pixel is stored as RGBE: an 8-bit mantissa for each color channel and
+//! a shared exponent. Scanlines are either stored flat, or run-length encoded
+//! one channel at a time. The old run-length encoding, which repeats whole
+//! pixels, is rare and not supported.
+
+use crate::math::Vector3;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+/// The largest width or height of an image that is read.
+const MAX_SIZE: usize = 1 << 15;
+
+/// Read the Radiance HDR image `path`. Returns its width, its height, and the
+/// linear RGB radiance of its pixels, row by row from the top.
+pub fn load_hdr(path: &Path) -> Result<(usize, usize, Vec<Vector3>), Box<dyn Error>> {
+    parse_hdr(&fs::read(path)?).map_err(|error| format!("{}: {}", path.display(), error).into())
+}
+
+/// Parse the contents of a Radiance HDR file, as with `load_hdr`.
+pub fn parse_hdr(bytes: &[u8]) -> Result<(usize, usize, Vec<Vector3>), Box<dyn Error>> {
+    let mut lines = Lines { bytes, position: 0 };
+
+    let signature = lines.next().ok_or("Empty file")?;
+    if signature != "#?RADIANCE" && signature != "#?RGBE" {
+        return Err("Not a Radiance HDR file".into());
+    }
+    // The header ends with an empty line.
+    loop {
+        let line = lines.next().ok_or("Unexpected end of header")?;
+        if line.is_empty() {
+            break;
+        }
+        if let Some(format) = line.strip_prefix("FORMAT=") {
+            if format != "32-bit_rle_rgbe" {
+                return Err(format!("Unsupported pixel format {}", format).into());
+            }
+        }
+    }
+
+    // Only the standard orientation, with rows from the top and pixels from
+    // the left, is supported.
+    let resolution = lines.next().ok_or("Missing resolution")?;
+    let (height, width) = match resolution.split_whitespace().collect::<Vec<_>>()[..] {
+        ["-Y", height, "+X", width] => (height.parse::<usize>()?, width.parse::<usize>()?),
+        _ => return Err(format!("Unsupported resolution {}", resolution).into()),
+    };
+    if width == 0 || height == 0 || width > MAX_SIZE || height > MAX_SIZE {
+        return Err(format!("Invalid image size {} x {}", width, height).into());
+    }
+
+    let mut data = &bytes[lines.position..];
+    let mut pixels = Vec::with_capacity(width * height);
+    let mut scanline = vec![[0; 4]; width];
+    for _ in 0..height {
+        data = read_scanline(data, &mut scanline)?;
+        pixels.extend(scanline.iter().map(|&rgbe| rgbe_to_linear(rgbe)));
+    }
+
+    Ok((width, height, pixels))
+}
+
+/// The lines of the header of an HDR file.
+struct Lines<'a> {
+    bytes: &'a [u8],
+    /// The position after the last line read.
+    position: usize,
+}
+
+impl<'a> Iterator for Lines<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        let rest = &self.bytes[self.position..];
+        let length = rest.iter().position(|&byte| byte == b'\n')?;
+        self.position += length + 1;
+        std::str::from_utf8(&rest[..length]).ok()
+    }
+}
+
+/// Read one scanline of RGBE pixels from the start of `data` into `scanline`.
+/// Returns the data after the scanline.
+fn read_scanline<'a>(data: &'a [u8], scanline: &mut [[u8; 4]]) -> Result<&'a [u8], Box<dyn Error>> {
+    let width = scanline.len();
+    let truncated = "Unexpected end of pixel data";
+
+    // Run-length encoded scanlines start with 2, 2 and the width, which can't
+    // be a normalized pixel.
+    let encoded = (8..0x8000).contains(&width)
+        && data.len() >= 4
+        && data[0] == 2
+        && data[1] == 2
+        && usize::from(data[2]) << 8 | usize::from(data[3]) == width;
+    if !encoded {
+        let bytes = data.get(..4 * width).ok_or(truncated)?;
+        for (pixel, rgbe) in scanline.iter_mut().zip(bytes.chunks(4)) {
+            if rgbe[..3] == [1, 1, 1] {
+                return Err("Old-style run-length encoding is not supported".into());
+            }
+            pixel.copy_from_slice(rgbe);
+        }
+        return Ok(&data[4 * width..]);
+    }
+
+    let mut data = &data[4..];
+    for channel in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let (&count, rest) = data.split_first().ok_or(truncated)?;
+            if count > 128 {
+                // A run of the same value.
+                let count = usize::from(count - 128);
+                let (&value, rest) = rest.split_first().ok_or(truncated)?;
+                if x + count > width {
+                    return Err("Run past the end of a scanline".into());
+                }
+                for pixel in &mut scanline[x..x + count] {
+                    pixel[channel] = value;
+                }
+                x += count;
+                data = rest;
+            } else {
+                // Values given one by one.
+                let count = usize::from(count);
+                if count == 0 || x + count > width {
+                    return Err("Invalid run in a scanline".into());
+                }
+                let values = rest.get(..count).ok_or(truncated)?;
+                for (pixel, &value) in scanline[x..x + count].iter_mut().zip(values) {
+                    pixel[channel] = value;
+                }
+                x += count;
+                data = &rest[count..];
+            }
+        }
+    }
+    Ok(data)
+}
+
+/// Convert an RGBE pixel to linear RGB.
+fn rgbe_to_linear(rgbe: [u8; 4]) -> Vector3 {
+    if rgbe[3] == 0 {
+        return Vector3::zero();
+    }
+    // The mantissas are fractions of 256.
+    let scale = 2.0_f64.powi(i32::from(rgbe[3]) - (128 + 8));
+    Vector3::from((
+        f64::from(rgbe[0]) * scale,
+        f64::from(rgbe[1]) * scale,
+        f64::from(rgbe[2]) * scale,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flat_and_encoded_scanlines_are_read() {
+        let mut file = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 2 +X 8\n".to_vec();
+        // A flat scanline of 1 in red, with mantissa 128 and exponent 129.
+        for _ in 0..8 {
+            file.extend_from_slice(&[128, 0, 0, 129]);
+        }
+        // An encoded scanline of 0.5 in green in the first two pixels, and
+        // 2 in blue in the rest.
+        file.extend_from_slice(&[2, 2, 0, 8]);
+        file.extend_from_slice(&[128 + 8, 0]);
+        file.extend_from_slice(&[2, 128, 128, 128 + 6, 0]);
+        file.extend_from_slice(&[2, 0, 0, 128 + 6, 128]);
+        file.extend_from_slice(&[2, 128, 128, 128 + 6, 130]);
+
+        let (width, height, pixels) = parse_hdr(&file).unwrap();
+        assert_eq!((width, height), (8, 2));
+        let rgb = |pixel: Vector3| (pixel.x, pixel.y, pixel.z);
+        assert!(pixels[..8]
+            .iter()
+            .all(|&pixel| rgb(pixel) == (1.0, 0.0, 0.0)));
+        assert_eq!(rgb(pixels[9]), (0.0, 0.5, 0.0));
+        assert_eq!(rgb(pixels[15]), (0.0, 0.0, 2.0));
+
+        assert!(parse_hdr(&file[..file.len() - 1]).is_err());
+        assert!(parse_hdr(b"#?RADIANCE\n\n-Y 2 +X 8\n").is_err());
+    }
+}
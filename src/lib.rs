@@ -1,17 +1,60 @@
+pub mod denoise;
+pub mod flare;
+pub mod gltf;
+pub mod hashing;
 pub mod image;
 pub mod lights;
+pub mod materials;
 pub mod math;
+pub mod memory;
+pub mod metadata;
+pub mod notify;
+pub mod obj;
+pub mod pbrt;
+pub mod plugins;
+pub mod power;
+pub mod recording;
+pub mod report;
 pub mod scene;
+pub mod scene_file;
+#[cfg(feature = "scripting")]
+pub mod scripting;
+pub mod sensor;
+pub mod stats;
+pub mod stl;
 pub mod surfaces;
+pub mod sweep;
+pub mod textures;
+pub mod verify;
 
 #[cfg(test)]
 mod tests {
-    use crate::image::Image;
-    use crate::lights::Sun;
-    use crate::scene::Scene;
-    use crate::surfaces::{Plane, Sphere};
+    use crate::flare::LensFlare;
+    use crate::image::{ColorSpace, Image, Pixel};
+    use crate::lights::{AreaLight, PointLight, SphereLight, Sun};
+    use crate::materials::Material;
+    use crate::math::blue_noise::BlueNoiseMask;
+    use crate::math::sampling::SamplePattern;
+    use crate::math::{Ray, UnitQuaternion, Vector3};
+    use crate::scene::{
+        AmbientOcclusion, Camera, CropWindow, DebugView, Exposure, FisheyeMapping, HaltConditions,
+        Integrator, LightId, PhysicalCamera, PixelCost, PixelLimits, Projection, RayBias,
+        RenderSchedule, RenderSettings, SamplerSettings, Scene, SurfaceId, ThreadSettings,
+    };
+    use crate::stats;
+    use crate::surfaces::{Intersection, Mesh, Plane, Rect, Sphere, Surface, Transform};
+    use crate::textures::Constant;
+    use crate::verify;
+    use std::collections::BTreeMap;
     use std::error::Error;
-    use std::fs::File;
+    use std::f64::consts::PI;
+    use std::f64::INFINITY;
+    use std::fs::{self, File};
+    use std::path::Path;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::{mpsc, Arc};
+    use std::thread;
+    use std::time::Duration;
 
     /// Read a png file into a vector of SRGB data.
     fn read_png(filename: &str) -> Result<Vec<u8>, Box<dyn Error>> {
@@ -41,72 +84,1694 @@ mod tests {
     }
 
     #[test]
-    fn render_sphere() {
-        let image_width = 1280;
-        let image_height = 720;
-        let mut image = Image::new(image_width, image_height);
-        let ref_filename = "test-data/test_render_sphere_ref.png";
+    fn hex_colors() {
+        let white = ColorSpace::Srgb.from_hex("#ffffff").unwrap();
+        assert_eq!((white.x, white.y, white.z), (1.0, 1.0, 1.0));
 
+        // Mid-gray in SRGB is much darker in linear RGB.
+        let gray = ColorSpace::Srgb.from_hex("808080").unwrap();
+        assert!((gray.x - 0.2158).abs() < 1e-4);
+        let gray = ColorSpace::Linear.from_hex("808080").unwrap();
+        assert!((gray.x - 128.0 / 255.0).abs() < 1e-12);
+
+        assert!(ColorSpace::Srgb.from_hex("#fff").is_none());
+        assert!(ColorSpace::Srgb.from_hex("#gggggg").is_none());
+    }
+
+    #[test]
+    fn lights_in_srgb_scenes_fall_off_with_distance_squared() {
+        let bsdf =
+            Material::default().bsdf(Vector3::zero(), (0.0, 0.0), Vector3::k(), Vector3::i());
+        let lit = |distance: f64, intensity: f64| {
+            let mut scene = Scene::new();
+            scene.set_color_space(ColorSpace::Srgb);
+            scene.add_light(PointLight::new(
+                (0.0, 0.0, distance),
+                (0.5, 0.5, 0.5),
+                intensity,
+            ));
+            scene.direct_light(Vector3::zero(), &bsdf, Vector3::k(), None)
+        };
+
+        // Only the color is converted, so the light is still proportional to
+        // the intensity, and falls off with the distance squared.
+        let near = lit(1.0, 3.0);
+        assert!((lit(2.0, 3.0) * 4.0 - near).norm() < 1e-9 * near.norm());
+        assert!((lit(1.0, 6.0) - near * 2.0).norm() < 1e-9 * near.norm());
+
+        // The light is that of a linear scene with the converted color.
+        let mut linear = Scene::new();
+        let color = ColorSpace::Srgb.to_linear(Vector3::from((0.5, 0.5, 0.5)));
+        linear.add_light(PointLight::new((0.0, 0.0, 1.0), color, 3.0));
+        let expected = linear.direct_light(Vector3::zero(), &bsdf, Vector3::k(), None);
+        assert!((near - expected).norm() < 1e-12);
+        let back = ColorSpace::Srgb.from_linear(color);
+        assert!((back - Vector3::from((0.5, 0.5, 0.5))).norm() < 1e-12);
+    }
+
+    #[test]
+    fn pixels_over_the_limit_are_flagged() {
+        let (width, height) = (64, 36);
+        let mut image = Image::new(width, height);
+
+        let mut scene = Scene::new();
+        scene.add_surface(Sphere::new((0.0, 2.0, 0.0), 0.5));
+        scene.add_light(Sun::new((1.0, 1.0, 1.0), (1.0, 1.0, -1.0)));
+        // Pixels on the sphere need a shadow ray in addition to the camera ray.
+        scene.set_pixel_limits(PixelLimits {
+            max_rays: Some(1),
+            max_time: None,
+        });
+
+        image.update(scene.spawn_render_threads(width, height).iter());
+
+        let srgba = image.get_srgba_vector();
+        let pixel = |x: usize, y: usize| &srgba[4 * (y * width + x)..4 * (y * width + x) + 3];
+        assert_eq!(pixel(width / 2, height / 2), [255, 0, 255]);
+        assert_eq!(pixel(0, 0), [0, 0, 0]);
+    }
+
+    #[test]
+    fn exposure_matches_physical_light_units() {
+        let (width, height) = (32, 18);
+        let render = |ev100: f64, exposure: Option<Exposure>| {
+            let mut scene = Scene::new();
+            scene.add_surface(Plane::new((0.0, 0.0, 1.0), -0.5));
+            // A white floor lit by the sun is as bright as the sun's color,
+            // which is the brightest luminance that isn't clipped at `ev100`.
+            let lux = PI * 1.2 * 2_f64.powf(ev100);
+            scene.add_light(Sun::new((1.0, 1.0, 1.0), (0.0, 0.0, -1.0)).with_illuminance(lux));
+            scene.set_exposure(exposure);
+
+            let mut image = Image::new(width, height);
+            image.update(scene.spawn_render_threads(width, height).iter());
+            image.get_srgba_vector()[4 * ((height - 1) * width + width / 2)]
+        };
+
+        assert!(render(0.0, Some(Exposure { ev100: 0.0 })) >= 254);
+        assert!(render(15.0, Some(Exposure { ev100: 15.0 })) >= 254);
+        // The sunny 16 rule gives an exposure of almost 15, darker than needed.
+        assert!(render(12.0, Some(Exposure::from_camera(16.0, 0.01, 100.0))) < 128);
+
+        // Without an exposure, the light saturates the image.
+        assert_eq!(render(12.0, None), 255);
+    }
+
+    #[test]
+    fn light_tree_matches_sampling_every_light() {
+        let (width, height) = (32, 18);
+        let render = |light_tree_samples: Option<u32>| {
+            let mut scene = Scene::new();
+            scene.add_surface(Plane::new((0.0, 0.0, 1.0), -0.5));
+            for index in 0..100 {
+                let position = (
+                    f64::from(index % 10) - 4.5,
+                    f64::from(index / 10) + 2.0,
+                    0.5,
+                );
+                if index % 10 == 0 {
+                    scene.add_light(SphereLight::new(position, 0.2, (0.5, 0.5, 0.5)));
+                } else {
+                    scene.add_light(PointLight::new(position, (1.0, 0.8, 0.6), 0.05));
+                }
+            }
+            scene.set_integrator(Integrator::PathTracing {
+                max_depth: 1,
+                samples_per_pixel: 16,
+            });
+            if let Some(samples) = light_tree_samples {
+                scene.build_light_tree(samples);
+            }
+
+            let mut image = Image::new(width, height);
+            image.update(scene.spawn_render_threads(width, height).iter());
+            image
+                .rgb_f32()
+                .iter()
+                .map(|&value| f64::from(value))
+                .sum::<f64>()
+        };
+
+        let all = render(None);
+        let picked = render(Some(4));
+        assert!(all > 0.0);
+        assert!((picked / all - 1.0).abs() < 0.05, "{} {}", picked, all);
+    }
+
+    #[test]
+    fn lens_flares_come_from_visible_lights() {
+        let (width, height) = (64, 36);
+        let mut scene = Scene::new();
+        scene.add_light(PointLight::new((0.0, 5.0, 0.0), (1.0, 1.0, 1.0), 100.0));
+        scene.add_light(SphereLight::new((2.0, 5.0, 0.0), 0.1, (1.0, 1.0, 1.0)));
+        // Hidden behind a sphere, and behind the camera.
+        scene.add_light(PointLight::new((-2.0, 6.0, 0.0), (1.0, 1.0, 1.0), 100.0));
+        scene.add_surface(Sphere::new((-1.0, 3.0, 0.0), 0.3));
+        scene.add_light(Sun::new((1.0, 1.0, 1.0), (0.0, 1.0, 0.0)));
+
+        let sources = scene.flare_sources(width, height);
+        assert_eq!(sources.len(), 2);
+        assert!((sources[0].x - 31.5).abs() < 1e-9 && (sources[0].y - 17.5).abs() < 1e-9);
+        assert!((sources[0].color.x - 100.0 / (25.0 * PI)).abs() < 1e-9);
+        assert!(sources[1].x > sources[0].x);
+
+        // The flare brightens the image around the light.
+        let mut image = Image::new(width, height);
+        LensFlare::default().apply(&mut image, &sources);
+        let rgb = image.rgb_f32();
+        assert!(rgb[3 * (17 * width + 31)] > 0.1);
+    }
+
+    #[test]
+    fn light_groups_add_up_to_the_render() {
+        let (width, height) = (32, 18);
+        let integrators = [
+            Integrator::DirectLighting,
+            Integrator::PathTracing {
+                max_depth: 3,
+                samples_per_pixel: 4,
+            },
+        ];
+        for integrator in integrators {
+            let mut scene = Scene::new();
+            scene.add_surface(Plane::new((0.0, 0.0, 1.0), -0.5));
+            let mirror = Material::default().with_metallic(1.0).with_roughness(0.0);
+            scene.add_surface_with_material(Sphere::new((0.5, 3.0, 0.0), 0.5), mirror);
+            // Removed before rendering, moving the lights after it down.
+            let removed = scene.add_light(PointLight::new((0.0, 0.0, 1.0), (1.0, 1.0, 1.0), 5.0));
+            let sun = scene.add_light(Sun::new((0.5, 0.5, 0.5), (1.0, 1.0, -1.0)));
+            let bulb = scene.add_light(SphereLight::new((-1.0, 2.0, 0.5), 0.2, (5.0, 2.0, 1.0)));
+            scene.add_light(PointLight::new((1.0, 1.0, 1.0), (0.2, 0.4, 1.0), 2.0));
+            scene.set_integrator(integrator);
+            let mut groups = BTreeMap::new();
+            groups.insert("warm".to_string(), vec![removed, sun, bulb]);
+            scene.set_light_groups(Some(groups));
+            scene.remove_light(removed);
+            let light_groups = scene.light_group_images().unwrap();
+
+            let mut image = Image::new(width, height);
+            image.update(scene.spawn_render_threads(width, height).iter());
+            let images = light_groups.take();
+            let names: Vec<&str> = images.iter().map(|(name, _)| name.as_str()).collect();
+            assert_eq!(names, ["warm", "light2"]);
+
+            let rgb = image.rgb_f32();
+            let (warm, cool) = (images[0].1.rgb_f32(), images[1].1.rgb_f32());
+            assert!(warm.iter().any(|&value| value > 0.0));
+            assert!(cool.iter().any(|&value| value > 0.0));
+            for ((value, warm), cool) in rgb.iter().zip(&warm).zip(&cool) {
+                assert!((warm + cool - value).abs() <= 1e-5 * value.max(1.0));
+            }
+        }
+    }
+
+    #[test]
+    fn coincident_surfaces_are_hit_by_priority() {
+        let (width, height) = (64, 36);
+        let render = |floor_priority: i32| {
+            let mut scene = Scene::new();
+            let floor = scene.add_surface(Plane::new((0.0, 0.0, 1.0), -0.5));
+            let decal =
+                Rect::new(20.0, 20.0).with_transform(UnitQuaternion::id(), (0.0, 0.0, -0.5));
+            let decal = scene.add_textured_surface(decal, Constant::new((1.0, 0.0, 0.0)));
+            scene.set_surface_priority(floor, floor_priority);
+            scene.set_surface_priority(decal, 1);
+            scene.add_light(Sun::new((1.0, 1.0, 1.0), (0.0, 0.0, -1.0)));
+
+            let mut image = Image::new(width, height);
+            image.update(scene.spawn_render_threads(width, height).iter());
+            let srgba = image.get_srgba_vector();
+            // The green channel of the pixel at the bottom center, on the decal.
+            srgba[4 * ((height - 1) * width + width / 2) + 1]
+        };
+
+        assert_eq!(render(0), 0);
+        assert!(render(2) > 0);
+    }
+
+    #[test]
+    fn ray_bias_follows_the_scale_of_the_scene() {
+        let (width, height) = (64, 36);
+        // A sphere with a shadow on the floor, far from the origin of the
+        // scene.
+        let offset = Vector3::from((1e8, 1e8, 1e8));
+        let render = |bias: RayBias| {
+            let mut scene = Scene::new();
+            scene.add_surface(Plane::new((0.0, 0.0, 1.0), offset.z - 0.5));
+            scene.add_surface(Sphere::new(offset + Vector3::from((0.0, 2.0, 0.0)), 0.5));
+            scene.add_light(Sun::new((1.0, 1.0, 1.0), (0.0, 0.0, -1.0)));
+            scene.set_camera(offset, UnitQuaternion::id());
+            scene.set_ray_bias(bias);
+
+            let mut image = Image::new(width, height);
+            image.update(scene.spawn_render_threads(width, height).iter());
+            image.get_srgba_vector().to_vec()
+        };
+        let dark = |srgba: &[u8]| srgba.chunks(4).filter(|pixel| pixel[0] == 0).count();
+
+        // A bias that doesn't grow with the coordinates gives shadow acne on
+        // the lit side of the sphere, but the default doesn't, and keeps the
+        // shadow on the floor.
+        let fixed = render(RayBias {
+            relative: 0.0,
+            ..RayBias::default()
+        });
+        let scaled = render(RayBias::default());
+        assert!(dark(&fixed) > dark(&scaled) + 10);
+        assert_eq!(scaled[4 * ((height - 1) * width + 2)], 255);
+        assert_eq!(scaled[4 * ((height - 3) * width + width / 2)], 0);
+    }
+
+    #[test]
+    fn closed_meshes_shadow_themselves_up_to_their_edges() {
+        // A cube from -1 to 1 along each axis.
+        let corners = (0..8)
+            .map(|i| {
+                let coordinate = |bit| if i & bit == 0 { -1.0 } else { 1.0 };
+                Vector3::from((coordinate(1), coordinate(2), coordinate(4)))
+            })
+            .collect();
+        let triangles = vec![
+            [0, 2, 1],
+            [1, 2, 3],
+            [4, 5, 6],
+            [5, 7, 6],
+            [0, 1, 4],
+            [1, 5, 4],
+            [2, 6, 3],
+            [3, 6, 7],
+            [0, 4, 2],
+            [2, 4, 6],
+            [1, 3, 5],
+            [3, 7, 5],
+        ];
         let mut scene = Scene::new();
+        scene.add_surface(Mesh::new(corners, triangles));
+        let light = Vector3::from((1.0, 0.0, 1.0)).normalize();
+
+        // Light from above and to the right, reaching the bottom near its
+        // right edge, would leave the cube through its right side closer than
+        // the ray bias.
+        for gap in [1e-2, 1e-4, 1e-6, 1e-8] {
+            let bottom = Vector3::from((1.0 - gap, 0.3, -1.0));
+            let transmittance = scene.transmittance(bottom, light, INFINITY, false);
+            assert_eq!(transmittance.norm2(), 0.0, "{gap}");
+        }
+        // The top and the right side are lit, also across the diagonals of
+        // their triangles.
+        for point in [
+            (0.3, 0.3, 1.0),
+            (-0.7, 0.1, 1.0),
+            (1.0, 0.2, 0.2),
+            (1.0, 0.5, -1.0),
+        ] {
+            let transmittance = scene.transmittance(Vector3::from(point), light, INFINITY, false);
+            assert_eq!(transmittance.norm2(), 3.0, "{point:?}");
+        }
+    }
+
+    #[test]
+    fn shaders_match_the_materials_they_imitate() {
+        let (width, height) = (64, 36);
+        let render = |diffuse: Material, mirror: Material| {
+            let mut scene = Scene::new();
+            scene.add_surface_with_material(Plane::new((0.0, 0.0, 1.0), -0.5), diffuse);
+            scene.add_surface_with_material(Sphere::new((0.0, 2.0, 0.0), 0.5), mirror);
+            scene.add_light(Sun::new((1.0, 0.9, 0.8), (1.0, 1.0, -1.0)));
+            scene.add_light(PointLight::new((-1.0, 1.0, 1.0), (0.2, 0.3, 1.0), 2.0));
+
+            let mut image = Image::new(width, height);
+            image.update(scene.spawn_render_threads(width, height).iter());
+            image.get_srgba_vector().to_vec()
+        };
+        let albedo = (0.8, 0.5, 0.2);
+        let mirror = || {
+            Material::new(Constant::new((1.0, 1.0, 1.0)))
+                .with_metallic(1.0)
+                .with_roughness(0.0)
+        };
+
+        let materials = render(Material::new(Constant::new(albedo)), mirror());
+        let shaders = render(
+            Material::new(Constant::new(albedo)).with_shader(|context| {
+                let normal = context.normal;
+                let light = context
+                    .lights()
+                    .iter()
+                    .fold(Vector3::zero(), |sum, illumination| {
+                        sum + normal.dot(illumination.direction).max(0.0) * illumination.color
+                    });
+                context.albedo.elementwise_mul(light)
+            }),
+            mirror().with_shader(|context| {
+                let (normal, outgoing) = (context.normal, context.outgoing);
+                context.trace(2.0 * normal.dot(outgoing) * normal - outgoing)
+            }),
+        );
+        assert!(materials.iter().any(|&value| value > 0));
+        for (&a, &b) in materials.iter().zip(&shaders) {
+            assert!((i32::from(a) - i32::from(b)).abs() <= 1, "{} {}", a, b);
+        }
+    }
+
+    #[test]
+    fn render_algorithms_replace_the_integrator() {
+        let (width, height) = (64, 36);
+        let scene = || {
+            let mut scene = Scene::new();
+            scene.add_surface(Plane::new((0.0, 0.0, 1.0), -0.5));
+            scene.add_surface(Sphere::new((0.0, 2.0, 0.0), 0.5));
+            scene.add_light(SphereLight::new((1.0, 1.0, 2.0), 0.5, (2.0, 2.0, 2.0)));
+            scene
+        };
+        let render = |scene: Scene| {
+            let mut image = Image::new(width, height);
+            image.update(scene.spawn_render_threads(width, height).iter());
+            image.get_srgba_vector().to_vec()
+        };
+        let path_tracing = Integrator::PathTracing {
+            max_depth: 3,
+            samples_per_pixel: 4,
+        };
+
+        // The integrators render the same as render algorithms.
+        let mut integrator = scene();
+        integrator.set_integrator(path_tracing);
+        let mut algorithm = scene();
+        algorithm.set_render_algorithm(path_tracing);
+        assert!(algorithm.content_hash().is_none());
+        assert_eq!(render(integrator), render(algorithm));
+
+        // Ambient occlusion darkens the floor under the sphere, but not the
+        // floor in the open or the sky.
+        let mut occlusion = scene();
+        occlusion.set_render_algorithm(AmbientOcclusion {
+            samples: 64,
+            distance: 1.0,
+        });
+        let srgba = render(occlusion);
+        let red = |x: usize, y: usize| srgba[4 * (y * width + x)];
+        assert_eq!(red(0, 0), 255);
+        assert!(red(2, height - 1) > 240);
+        assert!(red(width / 2, height - 3) < 200);
+    }
+
+    #[test]
+    fn debug_views_show_geometry() {
+        let (width, height) = (32, 18);
+        let render = |view: DebugView| {
+            let mut scene = Scene::new();
+            scene.add_surface(Sphere::new((0.0, 0.0, 0.0), 1.0));
+            scene.add_surface(Sphere::new((3.0, 0.0, 0.0), 1.0));
+            let camera = Camera::look_at((0.0, -10.0, 0.0), (0.0, 0.0, 0.0), (0.0, 0.0, 1.0));
+            scene.set_camera(camera.position, camera.orientation);
+            let settings = RenderSettings {
+                debug_view: Some(view),
+                ..scene.render_settings(width, height)
+            };
+            let mut pixels = vec![Vector3::zero(); width * height];
+            for (x, y, pixel) in scene.spawn_render(&settings).pixels() {
+                pixels[y * width + x] = pixel.rgb();
+            }
+            move |x: usize, y: usize| pixels[y * width + x]
+        };
+        let near = |a: Vector3, b: Vector3| (a - b).norm() < 0.2;
+        let gray = |value: f64| Vector3::from((value, value, value));
+
+        // The middle of the image sees about the front of the first sphere,
+        // facing the camera 9 away, and the corners see nothing.
+        let normals = render(DebugView::Normals);
+        assert!(near(normals(16, 9), Vector3::from((0.5, 0.0, 0.5))));
+        assert!(near(normals(0, 0), gray(0.0)));
+        let depth = render(DebugView::Depth { max_distance: 18.0 });
+        assert!(near(depth(16, 9), gray(0.5)));
+        assert!(near(depth(0, 0), gray(1.0)));
+        let uv = render(DebugView::Uv);
+        assert!([uv(16, 9).x, uv(16, 9).y]
+            .iter()
+            .all(|c| (0.0..1.0).contains(c)));
+
+        // Each surface has a color of its own.
+        let ids = render(DebugView::SurfaceIds);
+        assert!(near(ids(16, 9), ids(15, 8)));
+        assert!(!near(ids(16, 9), ids(23, 9)));
+    }
+
+    #[test]
+    fn camera_can_be_placed_and_zoomed() {
+        let (width, height) = (64, 36);
+        // A sphere to the left of the camera, which is turned towards it.
+        let render = |screen_width: f64| {
+            let mut scene = Scene::new();
+            scene.add_surface(Sphere::new((-2.0, 0.0, 0.0), 0.5));
+            scene.add_light(Sun::new((1.0, 1.0, 1.0), (-1.0, 0.0, 0.0)));
+            scene.set_camera(
+                (0.0, 0.0, 0.0),
+                UnitQuaternion::from_axis_angle((0.0, 0.0, 1.0), 0.5 * PI),
+            );
+            scene.set_camera_screen(screen_width, 0.5);
+            assert!((scene.camera().direction() - Vector3::from((-1.0, 0.0, 0.0))).norm() < 1e-12);
+
+            let mut image = Image::new(width, height);
+            image.update(scene.spawn_render_threads(width, height).iter());
+            let srgba = image.get_srgba_vector();
+            (0..width * height)
+                .filter(|&offset| srgba[4 * offset] > 0)
+                .count()
+        };
+
+        // A narrower screen zooms in, so the sphere covers more pixels.
+        let wide = render(0.64);
+        let narrow = render(0.32);
+        assert!(wide > 0);
+        assert!(narrow > 3 * wide);
+    }
+
+    #[test]
+    fn cameras_look_at_their_targets() {
+        let (width, height) = (63, 35);
+        let target = Vector3::from((3.0, -2.0, 1.0));
+        let camera = Camera::look_at((0.0, 0.0, 0.0), target, (0.0, 0.0, 1.0));
+        assert!((camera.direction() - target.normalize()).norm() < 1e-12);
+        assert!(camera.up().z > 0.0 && camera.right().z.abs() < 1e-12);
+
+        let mut scene = Scene::new();
+        scene.add_surface(Sphere::new(target, 0.2));
+        scene.add_light(Sun::new((1.0, 1.0, 1.0), target));
+        scene.set_camera(camera.position, camera.orientation);
+
+        let mut image = Image::new(width, height);
+        image.update(scene.spawn_render_threads(width, height).iter());
+        let srgba = image.get_srgba_vector();
+        let red = |x: usize, y: usize| srgba[4 * (y * width + x)];
+        assert!(red(width / 2, height / 2) > 200);
+        assert_eq!(red(0, 0), 0);
+
+        // Fields of view are kept as screens, and can be found from them.
+        let wide = camera.with_horizontal_fov(90.0);
+        assert!((wide.screen_width - 2.0 * wide.distance_to_screen).abs() < 1e-12);
+        let tall = camera.with_vertical_fov(60.0, 16.0 / 9.0);
+        assert!((tall.vertical_fov(16.0 / 9.0) - 60.0).abs() < 1e-9);
+        assert!(tall.horizontal_fov() > 60.0);
+
+        // Looking straight up still gives a camera.
+        let up = Camera::look_at((0.0, 0.0, 0.0), (0.0, 0.0, 1.0), (0.0, 0.0, 1.0));
+        assert!((up.direction() - Vector3::k()).norm() < 1e-12);
+    }
+
+    #[test]
+    fn cameras_survive_serialization() {
+        let camera = Camera::look_at((1.0, -3.0, 2.0), (0.0, 0.0, 0.0), (0.0, 0.0, 1.0))
+            .with_fisheye(FisheyeMapping::Equisolid, 180.0)
+            .with_depth_of_field(0.05, 3.0)
+            .with_distortion(0.1, -0.02);
+        let source = serde_json::to_string(&camera).unwrap();
+        let found: Camera = serde_json::from_str(&source).unwrap();
+        assert!((found.position - camera.position).norm() < 1e-12);
+        assert!((found.direction() - camera.direction()).norm() < 1e-12);
+        assert_eq!(found.projection, camera.projection);
+        assert_eq!(found.aperture_radius, 0.05);
+        assert_eq!(found.distortion, (0.1, -0.02));
+
+        // Settings that are left out are those of the default camera.
+        let found: Camera = ron::from_str("(screen_width: 1.0)").unwrap();
+        assert_eq!(found.screen_width, 1.0);
+        assert_eq!(
+            found.distance_to_screen,
+            Camera::default().distance_to_screen
+        );
+        assert!((found.direction() - Vector3::j()).norm() < 1e-12);
+    }
+
+    #[test]
+    fn cameras_are_placed_by_view_matrices() {
+        let camera = Camera::look_at((2.0, -3.0, 1.5), (0.0, 1.0, 0.5), (0.0, 0.0, 1.0));
+        let found = Camera::from_matrix(camera.matrix());
+        assert!((found.position - camera.position).norm() < 1e-12);
+        assert!((found.direction() - camera.direction()).norm() < 1e-12);
+        assert!((found.up() - camera.up()).norm() < 1e-12);
+
+        // The metadata of renders places the camera the same way.
+        let mut scene = Scene::new();
+        scene.set_camera(camera.position, camera.orientation);
+        let metadata = scene.metadata(1, 1, Duration::ZERO);
+        assert_eq!(metadata.camera.matrix, scene.camera().matrix());
+
+        // A camera from Blender at the origin, with no rotation, looks down
+        // along its negative z-axis, scaled by 2.
+        let matrix = [
+            [2.0, 0.0, 0.0, 1.0],
+            [0.0, 2.0, 0.0, 2.0],
+            [0.0, 0.0, 2.0, 3.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ];
+        let camera = Camera::from_matrix(matrix);
+        assert!((camera.position - Vector3::from((1.0, 2.0, 3.0))).norm() < 1e-12);
+        assert!((camera.direction() + Vector3::k()).norm() < 1e-12);
+        assert!((camera.up() - Vector3::j()).norm() < 1e-12);
+        assert!((camera.right() - Vector3::i()).norm() < 1e-12);
+    }
+
+    #[test]
+    fn orthographic_cameras_keep_sizes_at_any_distance() {
+        let (width, height) = (64, 36);
+        // Two equal spheres, the one to the right twice as far away.
+        let render = |camera: Camera| {
+            let mut scene = Scene::new();
+            scene.add_surface(Sphere::new((-1.0, 4.0, 0.0), 0.5));
+            scene.add_surface(Sphere::new((1.0, 8.0, 0.0), 0.5));
+            scene.add_light(Sun::new((1.0, 1.0, 1.0), (0.0, 1.0, 0.0)));
+            scene.set_camera(camera.position, camera.orientation);
+            scene.set_camera_projection(camera.projection);
+
+            let mut image = Image::new(width, height);
+            image.update(scene.spawn_render_threads(width, height).iter());
+            let srgba = image.get_srgba_vector();
+            let lit = |xs: std::ops::Range<usize>| {
+                (0..height)
+                    .flat_map(|y| xs.clone().map(move |x| y * width + x))
+                    .filter(|&offset| srgba[4 * offset] > 0)
+                    .count()
+            };
+            (lit(0..width / 2), lit(width / 2..width))
+        };
+
+        let (near, far) = render(Camera::default());
+        assert!(far > 0 && near > 3 * far);
+
+        let camera = Camera::default().with_orthographic(4.0);
+        assert_eq!(
+            camera.projection,
+            Projection::Orthographic { view_width: 4.0 }
+        );
+        let (near, far) = render(camera);
+        assert!(near > 0 && near == far);
+        // The spheres are a quarter of the view across.
+        let expected = PI * (width as f64 / 8.0).powi(2);
+        assert!((near as f64 - expected).abs() < 0.2 * expected);
+    }
+
+    #[test]
+    fn fisheye_cameras_see_to_the_sides() {
+        let (width, height) = (64, 36);
+        let render = |mapping: FisheyeMapping, fov: f64| {
+            let mut scene = Scene::new();
+            // Straight ahead, and to the right, at right angles.
+            scene.add_surface(Sphere::new((0.0, 5.0, 0.0), 1.0));
+            scene.add_surface(Sphere::new((5.0, 0.0, 0.0), 1.0));
+            scene.add_light(PointLight::new((0.0, 0.0, 0.0), (1.0, 1.0, 1.0), 100.0));
+            scene.set_camera_projection(Camera::default().with_fisheye(mapping, fov).projection);
+
+            let mut image = Image::new(width, height);
+            image.update(scene.spawn_render_threads(width, height).iter());
+            image.get_srgba_vector().to_vec()
+        };
+        let offset = |x: usize, y: usize| 4 * (y * width + x);
+
+        // Half of 180 degrees is at the sides of the image.
+        for mapping in [FisheyeMapping::Equidistant, FisheyeMapping::Equisolid] {
+            let srgba = render(mapping, 180.0);
+            assert!(srgba[offset(width / 2, height / 2)] > 0);
+            assert!(srgba[offset(width - 1, height / 2)] > 0);
+            assert_eq!(srgba[offset(0, height / 2)], 0);
+        }
+
+        // Equidistant cameras show no more than 180 degrees from the middle,
+        // leaving the corners empty.
+        let srgba = render(FisheyeMapping::Equidistant, 360.0);
+        assert_eq!(srgba[offset(width / 2, height / 2) + 3], 255);
+        assert_eq!(srgba[offset(0, 0) + 3], 0);
+
+        let camera = Camera::default().with_fisheye(FisheyeMapping::Equidistant, 180.0);
+        assert_eq!(camera.horizontal_fov(), 180.0);
+        assert!((camera.vertical_fov(2.0) - 90.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn lenses_blur_what_is_out_of_focus() {
+        let (width, height) = (64, 36);
+        let lit_pixels = |lens: Option<(f64, f64)>| {
+            let mut scene = Scene::new();
+            scene.add_surface(Sphere::new((0.0, 4.0, 0.0), 0.5));
+            scene.add_light(Sun::new((1.0, 1.0, 1.0), (0.0, 1.0, 0.0)));
+            if let Some((aperture_radius, focus_distance)) = lens {
+                scene.set_depth_of_field(aperture_radius, focus_distance);
+                scene.set_lens_samples(32);
+            }
+
+            let mut image = Image::new(width, height);
+            image.update(scene.spawn_render_threads(width, height).iter());
+            let srgba = image.get_srgba_vector();
+            (0..width * height)
+                .filter(|&offset| srgba[4 * offset] > 0)
+                .count()
+        };
+
+        let pinhole = lit_pixels(None);
+        let in_focus = lit_pixels(Some((0.2, 3.5)));
+        let out_of_focus = lit_pixels(Some((0.2, 1.0)));
+        assert!(pinhole > 0);
+        assert!((in_focus as f64 - pinhole as f64).abs() < 0.2 * pinhole as f64);
+        assert!(out_of_focus as f64 > 1.5 * pinhole as f64);
+    }
+
+    #[test]
+    fn lens_shift_and_tilt_move_the_image_and_the_focus() {
+        let (width, height) = (64, 36);
+        // The lit pixels, and the average row they are on.
+        let render = |set_up: &dyn Fn(&mut Scene)| {
+            let mut scene = Scene::new();
+            // One sphere low and near, and one high and far.
+            scene.add_surface(Sphere::new((-1.2, 2.5, -1.5), 0.3));
+            scene.add_surface(Sphere::new((1.2, 5.5, 1.5), 0.3));
+            scene.add_light(PointLight::new((0.0, 0.0, 0.0), (1.0, 1.0, 1.0), 100.0));
+            set_up(&mut scene);
+
+            let mut image = Image::new(width, height);
+            image.update(scene.spawn_render_threads(width, height).iter());
+            let srgba = image.get_srgba_vector();
+            let lit: Vec<_> = (0..width * height)
+                .filter(|&offset| srgba[4 * offset] > 0)
+                .collect();
+            let row = lit
+                .iter()
+                .map(|offset| (offset / width) as f64)
+                .sum::<f64>();
+            (lit.len(), row / lit.len() as f64)
+        };
+
+        // Shifting up moves the image down, by the fraction of its width.
+        let (pinhole, row) = render(&|_| {});
+        let (_, shifted_row) = render(&|scene| scene.set_lens_shift(0.0, 0.125));
+        assert!((shifted_row - row - 8.0).abs() < 0.5);
+
+        // The spheres are both out of focus, unless the plane in focus is
+        // tilted through them.
+        let (flat, _) = render(&|scene| {
+            scene.set_depth_of_field(0.2, 4.0);
+            scene.set_lens_samples(32);
+        });
+        let (tilted, _) = render(&|scene| {
+            scene.set_depth_of_field(0.2, 4.0);
+            scene.set_lens_samples(32);
+            scene.set_lens_tilt(45.0);
+        });
+        assert!(flat as f64 > 1.3 * pinhole as f64);
+        assert!((tilted as f64 - pinhole as f64).abs() < 0.2 * pinhole as f64);
+    }
+
+    #[test]
+    fn lens_distortion_and_chromatic_aberration_bend_the_image() {
+        let (width, height) = (64, 36);
+        // The pixels lit in each channel, and the average column they are in.
+        let render = |set_up: &dyn Fn(&mut Scene)| {
+            let mut scene = Scene::new();
+            scene.add_surface(Sphere::new((1.5, 4.0, 0.0), 0.5));
+            scene.add_light(PointLight::new((0.0, 0.0, 0.0), (1.0, 1.0, 1.0), 100.0));
+            set_up(&mut scene);
+
+            let mut image = Image::new(width, height);
+            image.update(scene.spawn_render_threads(width, height).iter());
+            let srgba = image.get_srgba_vector();
+            [0, 1, 2].map(|channel| {
+                let lit: Vec<_> = (0..width * height)
+                    .filter(|&offset| srgba[4 * offset + channel] > 0)
+                    .collect();
+                let column = lit
+                    .iter()
+                    .map(|offset| (offset % width) as f64)
+                    .sum::<f64>();
+                (lit.len(), column / lit.len() as f64)
+            })
+        };
+
+        // Barrel distortion pulls the sphere to the middle, and shrinks it.
+        let [(area, column), ..] = render(&|_| {});
+        let [(barrel_area, barrel_column), ..] =
+            render(&|scene| scene.set_lens_distortion(0.3, 0.0));
+        assert!(barrel_column < column - 1.0);
+        assert!(barrel_area < area);
+        assert!(barrel_column > 0.5 * width as f64);
+
+        // The sphere is larger in red and smaller in blue.
+        let [(red, _), (green, _), (blue, _)] =
+            render(&|scene| scene.set_chromatic_aberration(0.05));
+        assert!(red > green && green > blue);
+        assert_eq!(green, area);
+    }
+
+    #[test]
+    fn physical_cameras_set_the_view_lens_and_exposure() {
+        // A full frame camera with a 50 mm lens, wide open at f/2, four
+        // stops brighter than at f/8.
+        let physical = PhysicalCamera {
+            f_number: 2.0,
+            focus_distance: 3.0,
+            ..PhysicalCamera::default()
+        };
+        let mut scene = Scene::new();
+        scene.set_physical_camera(&physical);
+
+        let camera = scene.camera();
+        assert!((camera.horizontal_fov() - 39.6).abs() < 0.05);
+        assert!((camera.horizontal_fov() - physical.horizontal_fov()).abs() < 1e-9);
+        assert!((camera.aperture_radius - 0.0125).abs() < 1e-12);
+        assert_eq!(camera.focus_distance, 3.0);
+        let ev100 = physical.exposure().ev100;
+        assert!((ev100 - (PhysicalCamera::default().exposure().ev100 - 4.0)).abs() < 1e-9);
+        assert_eq!(
+            scene.metadata(1, 1, Duration::ZERO).camera.exposure,
+            Some(ev100)
+        );
+    }
+
+    #[test]
+    fn incremental_renders_update_what_changed() {
+        let (width, height) = (64, 36);
+        let scene = |x: f64, albedo: (f64, f64, f64)| {
+            let mut scene = Scene::new();
+            scene.add_surface(Sphere::new((-3.0, 0.0, 0.0), 1.0));
+            scene.add_surface_with_material(
+                Sphere::new((x, 0.0, 0.0), 1.0),
+                Material::new(Constant::new(albedo)),
+            );
+            scene.add_light(Sun::new((1.0, 1.0, 1.0), (0.0, 1.0, 0.0)));
+            let camera = Camera::look_at((0.0, -10.0, 0.0), (0.0, 0.0, 0.0), (0.0, 0.0, 1.0));
+            scene.set_camera(camera.position, camera.orientation);
+            scene
+        };
+        let render = |scene: Scene| {
+            let mut image = Image::new(width, height);
+            image.update(scene.spawn_render_threads(width, height).iter());
+            image
+        };
+        let white = (1.0, 1.0, 1.0);
+        let (first, second) = {
+            let ids = scene(3.0, white).surface_ids();
+            (ids[0], ids[1])
+        };
+
+        assert!(scene(3.0, white).diff(&scene(3.0, white)).is_empty());
+        let changes = scene(3.5, white).diff(&scene(3.0, white));
+        assert_eq!(changes.surfaces, vec![second]);
+        assert!(changes.materials.is_empty() && changes.lights.is_empty() && !changes.settings);
+        let changes = scene(3.0, (1.0, 0.0, 0.0)).diff(&scene(3.0, white));
+        assert!(changes.surfaces.is_empty());
+        assert_eq!(changes.materials, vec![second]);
 
+        // Removing a surface doesn't make the ones after it look changed.
+        let mut removed = scene(3.0, white);
+        removed.remove_surface(first);
+        let changes = removed.diff(&scene(3.0, white));
+        assert_eq!(changes.surfaces, vec![first]);
+        assert!(changes.materials.is_empty());
+
+        // Only the pixels around the moved sphere are rendered, and they are
+        // all that differ from the earlier image.
+        let mut image = render(scene(3.0, white));
+        let changes = scene(3.5, white).diff(&scene(3.0, white));
+        let region = scene(3.5, white)
+            .changed_region(&changes, width, height)
+            .unwrap();
+        let pixels: Vec<_> = scene(3.5, white)
+            .spawn_incremental_render(&changes, width, height)
+            .pixels()
+            .collect();
+        assert!(!pixels.is_empty() && pixels.len() < width * height / 2);
+        assert!(pixels.len() <= region.iter().map(|(xs, ys)| xs.len() * ys.len()).sum());
+        assert!(pixels.iter().all(|(x, y, _)| region
+            .iter()
+            .any(|(xs, ys)| xs.contains(x) && ys.contains(y))));
+        image.update(pixels.into_iter().map(|(x, y, pixel)| (x, y, vec![pixel])));
+        assert_eq!(
+            image.get_srgba_vector(),
+            render(scene(3.5, white)).get_srgba_vector()
+        );
+
+        // Changed lights are seen everywhere.
+        let mut lit = scene(3.0, white);
+        let lamp = lit.add_light(PointLight::new((0.0, -5.0, 0.0), (1.0, 1.0, 1.0), 10.0));
+        let changes = lit.diff(&scene(3.0, white));
+        assert_eq!(changes.lights, vec![lamp]);
+        let pixels = lit
+            .spawn_incremental_render(&changes, width, height)
+            .pixels()
+            .count();
+        assert_eq!(pixels, width * height);
+    }
+
+    #[test]
+    fn shadow_catchers_keep_only_shadows() {
+        let (width, height) = (64, 36);
+        let mut image = Image::new(width, height);
+
+        let mut scene = Scene::new();
         scene.add_surface(Sphere::new((0.0, 2.0, 0.0), 0.5));
+        let catcher = Material::new(Constant::new((0.5, 0.5, 0.5))).with_shadow_catcher(true);
+        scene.add_surface_with_material(Plane::new((0.0, 0.0, 1.0), -0.5), catcher);
+        scene.add_light(Sun::new((1.0, 1.0, 1.0), (0.0, 0.0, -1.0)));
+
+        image.update(scene.spawn_render_threads(width, height).iter());
 
-        scene.add_light(Sun::new((1.0, 0.0, 0.0), (1.0, 1.0, -1.0)));
-        scene.add_light(Sun::new((0.0, 1.0, 0.0), (-1.0, 1.0, -1.0)));
-        scene.add_light(Sun::new((0.0, 0.0, 1.0), (0.0, 1.0, 1.0)));
+        let srgba = image.get_srgba_vector();
+        let pixel = |x: usize, y: usize| &srgba[4 * (y * width + x)..4 * (y * width + x) + 4];
+        // The sphere is opaque, and its shadow is black and opaque.
+        assert_eq!(pixel(width / 2, height / 2)[3], 255);
+        assert_eq!(pixel(width / 2, height - 3), [0, 0, 0, 255]);
+        // The lit floor and the sky are transparent.
+        assert_eq!(pixel(2, height - 1)[3], 0);
+        assert_eq!(pixel(0, 0)[3], 0);
+    }
 
-        let receiver = scene.spawn_render_threads(image_width, image_height);
-        image.update(receiver.iter());
+    #[test]
+    fn holdouts_are_cut_out() {
+        let (width, height) = (64, 36);
+        let mut image = Image::new(width, height);
 
-        let image_data = image.get_srgba_vector();
-        let ref_image_data = read_png(ref_filename).unwrap();
-        assert_eq!(*image_data, ref_image_data);
+        let mut scene = Scene::new();
+        scene.add_surface(Plane::new((0.0, 0.0, 1.0), -0.5));
+        let sphere = scene.add_surface(Sphere::new((0.0, 2.0, 0.0), 0.5));
+        scene.set_surface_holdout(sphere, true);
+        scene.add_light(Sun::new((1.0, 1.0, 1.0), (0.0, 0.0, -1.0)));
+
+        image.update(scene.spawn_render_threads(width, height).iter());
+
+        let srgba = image.get_srgba_vector();
+        let pixel = |x: usize, y: usize| &srgba[4 * (y * width + x)..4 * (y * width + x) + 4];
+        // The floor behind the sphere is hidden, but the shadow of the sphere
+        // is not.
+        assert_eq!(pixel(width / 2, height / 2), [0, 0, 0, 0]);
+        assert_eq!(pixel(width / 2, height - 3), [0, 0, 0, 255]);
+        assert_eq!(pixel(2, height - 1)[3], 255);
+        assert!(pixel(2, height - 1)[0] > 0);
+    }
+
+    #[test]
+    fn surfaces_and_lights_are_changed_through_handles() {
+        let (width, height) = (32, 18);
+        let mut scene = Scene::new();
+        let floor = scene.add_surface(Plane::new((0.0, 0.0, 1.0), -0.5));
+        let sphere = scene.add_surface(Sphere::new((0.0, 2.0, 0.0), 0.5));
+        let sun = scene.add_light(Sun::new((1.0, 1.0, 1.0), (0.0, 0.0, -1.0)));
+        let lamp = scene.add_light(PointLight::new((0.0, 0.0, 3.0), (1.0, 1.0, 1.0), 1.0));
+
+        // Removing a surface or light moves those after it down one index,
+        // and its handle no longer finds anything.
+        assert!(scene.remove_surface(floor).is_some());
+        assert!(scene.remove_surface(floor).is_none());
+        assert_eq!(scene.surface_index(sphere), Some(0));
+        assert!(scene.remove_light(sun).is_some());
+        assert_eq!(scene.light_index(lamp), Some(0));
+        assert!(scene.get_mut_light(sun).is_none());
+
+        // Replace the sphere with one that fills the view.
+        *scene.get_mut_surface(sphere).unwrap() = Box::new(Sphere::new((0.0, 2.0, 0.0), 1.5));
+        *scene.get_mut_light(lamp).unwrap() = Box::new(Sun::new((1.0, 1.0, 1.0), (0.0, 1.0, 0.0)));
+
+        let mut image = Image::new(width, height);
+        image.update(scene.spawn_render_threads(width, height).iter());
+        let srgba = image.get_srgba_vector();
+        let pixel = |x: usize, y: usize| srgba[4 * (y * width + x)];
+        assert!(pixel(width / 2, height / 2) > 200);
+        assert!(pixel(width / 2, 1) > 0);
     }
 
     #[test]
-    fn render_plane() {
-        let image_width = 1280;
-        let image_height = 720;
-        let mut image = Image::new(image_width, image_height);
-        let ref_filename = "test-data/test_render_plane_ref.png";
+    fn motion_vectors_follow_surfaces_and_camera() {
+        let (width, height) = (64, 36);
+        let filename = "test-data/test-data-out/test_motion_vectors.pfm";
+        let motion_vectors = |scene: &Scene| {
+            scene
+                .render_motion_vectors(width, height)
+                .save_pfm(filename)
+                .unwrap();
+            let data = fs::read(filename).unwrap();
+            let header = format!("PF\n{} {}\n-1.0\n", width, height);
+            assert!(data.starts_with(header.as_bytes()));
+            let floats: Vec<f32> = data[header.len()..]
+                .chunks(4)
+                .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+                .collect();
+            // The rows of the file go from the bottom.
+            move |x: usize, y: usize| {
+                let index = 3 * ((height - 1 - y) * width + x);
+                (f64::from(floats[index]), f64::from(floats[index + 1]))
+            }
+        };
 
+        // The sphere moved 0.1 m to the right. With 100 pixels per meter at
+        // the screen, 0.5 m from the camera, it moved about 0.1 * 0.5 / 3.5 *
+        // 100 pixels at its front, 3.5 m away.
         let mut scene = Scene::new();
+        let sphere = scene.add_surface(Sphere::new((0.0, 4.0, 0.0), 0.5));
+        let placement =
+            |x: f64| Transform::new((0.5, 0.5, 0.5), UnitQuaternion::id(), (x, 4.0, 0.0));
+        scene.set_surface_motion(sphere, placement(-0.1), placement(0.0));
+        let motion = motion_vectors(&scene);
+        let (sphere_x, y) = motion(width / 2, height / 2);
+        assert!((sphere_x - 0.1 * 0.5 / 3.5 * 100.0).abs() < 0.01);
+        assert!(y.abs() < 1e-3);
+        let (x, y) = motion(0, 0);
+        assert!(x.abs() < 1e-3 && y.abs() < 1e-3);
+
+        // Turning the camera to the left moves the background to the right.
+        scene.set_previous_camera(
+            (0.0, 0.0, 0.0),
+            UnitQuaternion::from_axis_angle((0.0, 0.0, 1.0), -0.01),
+        );
+        let motion = motion_vectors(&scene);
+        assert!(motion(0, 0).0 > 0.0);
+        assert!(motion(width / 2, height / 2).0 > sphere_x);
+    }
+
+    #[test]
+    fn albedo_and_normals_guide_denoising() {
+        let (width, height) = (64, 36);
+        let mut scene = Scene::new();
+        let red = Material::new(Constant::new((0.8, 0.1, 0.1)));
+        scene.add_surface_with_material(Sphere::new((0.0, 2.0, 0.0), 0.5), red);
+        let mirror = Material::default().with_metallic(1.0).with_roughness(0.0);
+        scene.add_surface_with_material(Plane::new((0.0, 0.0, 1.0), -0.5), mirror);
+
+        let albedo = scene.render_albedo(width, height).rgb_f32();
+        let normals = scene.render_normals(width, height).rgb_f32();
+        let pixel = |image: &[f32], x: usize, y: usize| {
+            let index = 3 * (y * width + x);
+            [image[index], image[index + 1], image[index + 2]]
+        };
+        assert_eq!(pixel(&albedo, width / 2, height / 2), [0.8, 0.1, 0.1]);
+        assert_eq!(pixel(&albedo, 0, height - 1), [1.0, 1.0, 1.0]);
+        assert_eq!(pixel(&albedo, 0, 0), [0.0, 0.0, 0.0]);
+        // The middle of the sphere faces the camera, and the floor faces up.
+        let [x, y, z] = pixel(&normals, width / 2, height / 2);
+        assert!(x.abs() < 0.1 && y < -0.99 && z.abs() < 0.1);
+        assert_eq!(pixel(&normals, 0, height - 1), [0.0, 0.0, 1.0]);
+        assert_eq!(pixel(&normals, 0, 0), [0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn blue_noise_blurs_away_more_easily() {
+        let (width, height) = (64, 36);
+        let blurred_noise = |blue_noise: bool| {
+            let mut scene = Scene::new();
+            scene.add_surface(Plane::new((0.0, 0.0, 1.0), -0.5));
+            let light = AreaLight::new(4.0, 4.0, (1.0, 1.0, 1.0))
+                .with_transform(
+                    UnitQuaternion::from_axis_angle((1.0, 0.0, 0.0), PI),
+                    (0.0, 4.0, 1.5),
+                )
+                .with_samples(1);
+            scene.add_light(light);
+            scene.set_integrator(Integrator::PathTracing {
+                max_depth: 1,
+                samples_per_pixel: 1,
+            });
+            scene.set_sampler(SamplerSettings {
+                blue_noise,
+                blue_noise_mask: Some(Arc::new(BlueNoiseMask::generate(16, 0))),
+                ..SamplerSettings::default()
+            });
+
+            let mut image = Image::new(width, height);
+            image.update(scene.spawn_render_threads(width, height).iter());
+            let srgba = image.get_srgba_vector();
+            // The differences between neighboring 4 x 4 blocks of the floor.
+            let block = |x: usize, y: usize| {
+                (0..16)
+                    .map(|i| f64::from(srgba[4 * ((y + i / 4) * width + x + i % 4)]))
+                    .sum::<f64>()
+            };
+            (height / 2 + 2..height - 4)
+                .step_by(4)
+                .flat_map(|y| (0..width - 8).step_by(4).map(move |x| (x, y)))
+                .map(|(x, y)| (block(x, y) - block(x + 4, y)).abs())
+                .sum::<f64>()
+        };
+
+        assert!(blurred_noise(true) < 0.9 * blurred_noise(false));
+    }
+
+    #[test]
+    fn pixel_samples_smooth_edges() {
+        let (width, height) = (48, 27);
+        let render = |pixel_samples| {
+            let mut scene = Scene::new();
+            scene.add_surface_with_material(
+                Sphere::new((0.0, 4.0, 0.0), 1.0),
+                Material::default().with_shader(|_| Vector3::ones()),
+            );
+            scene.set_sampler(SamplerSettings {
+                pixel_samples,
+                ..SamplerSettings::default()
+            });
+            let mut image = Image::new(width, height);
+            image.update(scene.spawn_render_threads(width, height).iter());
+            image.get_srgba_vector().to_vec()
+        };
+        // The number of pixels that are neither black nor white.
+        let partly_covered = |srgba: &[u8]| {
+            srgba
+                .chunks(4)
+                .filter(|pixel| (1..255).contains(&pixel[0]))
+                .count()
+        };
+
+        let aliased = render(1);
+        let smooth = render(16);
+        assert_eq!(partly_covered(&aliased), 0);
+        assert!(partly_covered(&smooth) > 20);
+        // The sphere covers about as much of the image either way.
+        let coverage = |srgba: &[u8]| {
+            srgba
+                .chunks(4)
+                .map(|pixel| f64::from(pixel[0]))
+                .sum::<f64>()
+        };
+        let ratio = coverage(&smooth) / coverage(&aliased);
+        assert!((ratio - 1.0).abs() < 0.05);
+    }
+
+    #[test]
+    fn progressive_renders_refine_toward_the_full_render() {
+        let (width, height) = (32, 18);
+        let scene = || {
+            let mut scene = Scene::new();
+            scene.add_surface(Plane::new((0.0, 0.0, 1.0), -0.5));
+            scene.add_surface(Sphere::new((0.0, 3.0, 0.0), 0.5));
+            scene.add_light(SphereLight::new((1.0, 2.0, 2.0), 0.5, (4.0, 4.0, 4.0)));
+            scene.set_integrator(Integrator::PathTracing {
+                max_depth: 2,
+                samples_per_pixel: 64,
+            });
+            scene
+        };
+
+        let mut full = Image::new(width, height);
+        full.update(scene().spawn_render_threads(width, height).iter());
+        let mut progressive = Image::new(width, height);
+        let passes = scene().spawn_progressive_render(width, height, Some(64));
+        progressive.accumulate(passes.iter());
+        assert_eq!(progressive.sample_count(0, 0), 64);
+        assert_eq!(progressive.sample_count(width - 1, height - 1), 64);
+
+        let difference = |a: &Image, b: &Image| {
+            let (a, b) = (a.get_srgba_vector(), b.get_srgba_vector());
+            let sum: i32 = a
+                .iter()
+                .zip(b)
+                .map(|(&a, &b)| (i32::from(a) - i32::from(b)).abs())
+                .sum();
+            f64::from(sum) / a.len() as f64
+        };
+        let mut first_pass = Image::new(width, height);
+        first_pass.accumulate(
+            scene()
+                .spawn_progressive_render(width, height, Some(1))
+                .iter(),
+        );
+        assert!(difference(&progressive, &full) < 3.0);
+        assert!(difference(&progressive, &full) < difference(&first_pass, &full));
+    }
+
+    #[test]
+    fn sample_patterns_converge_faster_than_random() {
+        let (width, height) = (32, 18);
+        let render = |samples_per_pixel: u32, pattern: SamplePattern| {
+            let mut scene = Scene::new();
+            scene.add_surface(Plane::new((0.0, 0.0, 1.0), -0.5));
+            scene.add_surface(Sphere::new((0.0, 4.0, 0.3), 0.4));
+            let light = AreaLight::new(2.0, 2.0, (1.0, 1.0, 1.0))
+                .with_transform(
+                    UnitQuaternion::from_axis_angle((1.0, 0.0, 0.0), PI),
+                    (0.0, 4.0, 2.0),
+                )
+                .with_samples(1);
+            scene.add_light(light);
+            scene.set_integrator(Integrator::PathTracing {
+                max_depth: 1,
+                samples_per_pixel,
+            });
+            scene.set_sampler(SamplerSettings {
+                pattern,
+                ..SamplerSettings::default()
+            });
+            let mut image = Image::new(width, height);
+            image.update(scene.spawn_render_threads(width, height).iter());
+            image.get_srgba_vector().to_vec()
+        };
+        let reference = render(1024, SamplePattern::Stratified);
+        let error = |srgba: Vec<u8>| {
+            srgba
+                .iter()
+                .zip(&reference)
+                .map(|(&a, &b)| (f64::from(a) - f64::from(b)).powi(2))
+                .sum::<f64>()
+        };
+
+        let random = error(render(16, SamplePattern::Random));
+        for pattern in [
+            SamplePattern::Stratified,
+            SamplePattern::Halton,
+            SamplePattern::Sobol,
+        ] {
+            assert!(error(render(16, pattern)) < 0.7 * random);
+        }
+    }
 
+    #[test]
+    fn progressive_renders_sample_noisy_pixels_more() {
+        let (width, height) = (32, 18);
+        let halt = HaltConditions {
+            noise_threshold: Some(0.001),
+            max_time: None,
+        };
+        let mut scene = Scene::new();
         scene.add_surface(Plane::new((0.0, 0.0, 1.0), -0.5));
+        let light = AreaLight::new(4.0, 4.0, (1.0, 1.0, 1.0)).with_transform(
+            UnitQuaternion::from_axis_angle((1.0, 0.0, 0.0), PI),
+            (0.0, 4.0, 1.5),
+        );
+        scene.add_light(light);
+        scene.set_integrator(Integrator::PathTracing {
+            max_depth: 1,
+            samples_per_pixel: 1,
+        });
+        scene.set_halt_conditions(halt);
+        let mut image = Image::new(width, height);
+        image.accumulate(
+            scene
+                .spawn_progressive_render(width, height, Some(64))
+                .iter(),
+        );
+        // The black sky is clean after the fewest samples, give or take the
+        // passes that other threads had started, while the noise of the floor
+        // never gets below the threshold.
+        let few = HaltConditions::MIN_SAMPLES..2 * HaltConditions::MIN_SAMPLES;
+        assert!(few.contains(&image.sample_count(width / 2, 0)));
+        assert_eq!(image.sample_count(width / 2, height - 1), 64);
 
-        scene.add_light(Sun::new((1.0, 0.0, 0.0), (1.0, 1.0, -1.0)));
-        scene.add_light(Sun::new((0.0, 1.0, 0.0), (-1.0, 1.0, -1.0)));
-        scene.add_light(Sun::new((0.0, 0.0, 1.0), (0.0, 1.0, 1.0)));
+        // Without a limit on the passes, rendering stops when all is clean.
+        let mut empty = Scene::new();
+        empty.set_halt_conditions(halt);
+        let mut image = Image::new(width, height);
+        image.accumulate(empty.spawn_progressive_render(width, height, None).iter());
+        assert!(few.contains(&image.sample_count(0, 0)));
+    }
 
-        let receiver = scene.spawn_render_threads(image_width, image_height);
-        image.update(receiver.iter());
+    #[test]
+    fn adaptive_passes_spend_their_rays_on_noisy_pixels() {
+        let (width, height) = (32, 18);
+        let passes = 64;
+        let mut scene = Scene::new();
+        scene.add_surface(Plane::new((0.0, 0.0, 1.0), -0.5));
+        let light = AreaLight::new(4.0, 4.0, (1.0, 1.0, 1.0)).with_transform(
+            UnitQuaternion::from_axis_angle((1.0, 0.0, 0.0), PI),
+            (0.0, 4.0, 1.5),
+        );
+        scene.add_light(light);
+        scene.set_integrator(Integrator::PathTracing {
+            max_depth: 1,
+            samples_per_pixel: 1,
+        });
+        scene.set_sampler(SamplerSettings {
+            adaptive_passes: true,
+            ..SamplerSettings::default()
+        });
+        let mut image = Image::new(width, height);
+        image.accumulate(
+            scene
+                .spawn_progressive_render(width, height, Some(passes))
+                .iter(),
+        );
 
-        let image_data = image.get_srgba_vector();
-        let ref_image_data = read_png(ref_filename).unwrap();
-        assert_eq!(*image_data, ref_image_data);
+        // The black sky has no noise, so it gets no more rays once its noise
+        // is known, give or take the passes that other threads had started,
+        // and the noisy floor gets them instead.
+        let few = HaltConditions::MIN_SAMPLES..2 * HaltConditions::MIN_SAMPLES;
+        assert!(few.contains(&image.sample_count(width / 2, 0)));
+        let floor = image.sample_count(width / 2, height - 1);
+        assert!(floor > passes && floor <= passes * SamplerSettings::MAX_PASS_RAYS);
+
+        // The passes still trace about one ray per pixel.
+        let total: u32 = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| image.sample_count(x, y))
+            .sum();
+        let expected = f64::from(passes) * (width * height) as f64;
+        assert!((f64::from(total) / expected - 1.0).abs() < 0.25);
     }
 
     #[test]
-    fn render_sphere_and_plane() {
-        let image_width = 1280;
-        let image_height = 720;
-        let mut image = Image::new(image_width, image_height);
-        let ref_filename = "test-data/test_render_sphere_and_plane_ref.png";
+    fn threads_share_the_tiles_of_an_image() {
+        let (width, height) = (37, 21);
+        let mut scene = Scene::new();
+        scene.add_surface(Sphere::new((0.0, 2.0, 0.0), 0.5));
+        let scene = Arc::new(scene);
+        let next_tile = Arc::new(AtomicUsize::new(0));
+        let (sender, receiver) = mpsc::channel();
+        let threads: Vec<_> = (0..3)
+            .map(|_| {
+                let (scene, next_tile, sender) = (scene.clone(), next_tile.clone(), sender.clone());
+                thread::spawn(move || {
+                    scene.render_tiles(width, height, 8, &next_tile, sender);
+                })
+            })
+            .collect();
+        drop(sender);
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        // Every pixel is rendered once, including those of the smaller tiles
+        // at the edges.
+        let mut rendered = vec![0; width * height];
+        for (x, y, run) in receiver.iter() {
+            for pixel_x in x..x + run.len() {
+                rendered[y * width + pixel_x] += 1;
+            }
+        }
+        assert!(rendered.iter().all(|&count| count == 1));
+    }
 
+    #[test]
+    fn rendering_halts_when_clean_or_out_of_time() {
+        let (width, height) = (32, 18);
+        let render = |samples_per_pixel: u32, halt: HaltConditions| {
+            let mut scene = Scene::new();
+            scene.add_surface(Plane::new((0.0, 0.0, 1.0), -0.5));
+            let light = AreaLight::new(4.0, 4.0, (1.0, 1.0, 1.0)).with_transform(
+                UnitQuaternion::from_axis_angle((1.0, 0.0, 0.0), PI),
+                (0.0, 4.0, 1.5),
+            );
+            scene.add_light(light);
+            scene.set_integrator(Integrator::PathTracing {
+                max_depth: 1,
+                samples_per_pixel,
+            });
+            scene.set_halt_conditions(halt);
+            // Taking all the samples of a pixel on the floor traces more rays
+            // than the limit.
+            scene.set_pixel_limits(PixelLimits {
+                max_rays: Some(10_000),
+                max_time: None,
+            });
+
+            let mut image = Image::new(width, height);
+            image.update(scene.spawn_render_threads(width, height).iter());
+            image.get_srgba_vector().clone()
+        };
+
+        let full = render(1000, HaltConditions::default());
+        let floor = 4 * ((height - 1) * width + width / 2);
+        assert_eq!(full[floor..floor + 3], [255, 0, 255]);
+        let clean = render(
+            1000,
+            HaltConditions {
+                noise_threshold: Some(0.02),
+                max_time: None,
+            },
+        );
+        assert_ne!(clean[floor..floor + 3], [255, 0, 255]);
+
+        // Out of time from the start, every pixel gets a single sample.
+        let out_of_time = HaltConditions {
+            noise_threshold: None,
+            max_time: Some(Duration::ZERO),
+        };
+        assert!(render(1000, out_of_time) == render(1, HaltConditions::default()));
+    }
+
+    #[test]
+    fn schedules_render_the_same_image() {
+        let (width, height) = (61, 37);
+        let render = |schedule: Option<RenderSchedule>| {
+            let mut scene = Scene::new();
+            scene.add_surface(Sphere::new((0.0, 2.0, 0.0), 0.5));
+            scene.add_surface(Plane::new((0.0, 0.0, 1.0), -0.5));
+            scene.add_light(Sun::new((1.0, 1.0, 1.0), (1.0, 1.0, -1.0)));
+            if schedule.is_none() {
+                let calibrated = scene.calibrate(width, height);
+                assert!(calibrated.num_threads >= 1);
+                assert!((8..=128).contains(&calibrated.tile_size));
+            }
+            scene.set_render_schedule(schedule);
+
+            let mut image = Image::new(width, height);
+            image.update(scene.spawn_render_threads(width, height).iter());
+            image.get_srgba_vector().clone()
+        };
+
+        let calibrated = render(None);
+        let schedule = RenderSchedule {
+            num_threads: 3,
+            tile_size: 5,
+        };
+        assert!(calibrated == render(Some(schedule)));
+    }
+
+    #[test]
+    fn cancelled_renders_stop() {
+        let (width, height) = (40, 30);
+        let mut scene = Scene::new();
+        scene.add_surface(Sphere::new((0.0, 2.0, 0.0), 0.5));
+        scene.add_surface(Plane::new((0.0, 0.0, 1.0), -0.5));
+        scene.add_light(Sun::new((1.0, 1.0, 1.0), (1.0, 1.0, -1.0)));
+        // Far too many samples to finish.
+        scene.set_integrator(Integrator::PathTracing {
+            max_depth: 4,
+            samples_per_pixel: 1_000_000,
+        });
+        scene.set_render_schedule(Some(RenderSchedule {
+            num_threads: 2,
+            tile_size: 8,
+        }));
+
+        let render = scene.spawn_render_threads(width, height);
+        render.cancel();
+        assert!(render.is_cancelled());
+        // The channel is closed when the threads stop.
+        assert!(render.pixels().count() < width * height);
+        render.join().unwrap();
+
+        // Threads whose pixels nobody waits for any more just stop.
         let mut scene = Scene::new();
+        scene.add_surface(Sphere::new((0.0, 2.0, 0.0), 0.5));
+        let (sender, receiver) = mpsc::channel();
+        drop(receiver);
+        let next_tile = AtomicUsize::new(0);
+        scene.render_tiles(width, height, 8, &next_tile, sender);
+        assert_eq!(next_tile.into_inner(), 1);
+    }
 
+    #[test]
+    fn renders_are_counted() {
+        let (width, height) = (20, 15);
+        let mut scene = Scene::new();
         scene.add_surface(Sphere::new((0.0, 2.0, 0.0), 0.5));
         scene.add_surface(Plane::new((0.0, 0.0, 1.0), -0.5));
+        scene.add_light(Sun::new((1.0, 1.0, 1.0), (1.0, 1.0, -1.0)));
+        scene.set_render_schedule(Some(RenderSchedule {
+            num_threads: 2,
+            tile_size: 8,
+        }));
 
-        scene.add_light(Sun::new((1.0, 0.0, 0.0), (1.0, 1.0, -1.0)));
-        scene.add_light(Sun::new((0.0, 1.0, 0.0), (-1.0, 1.0, -1.0)));
-        scene.add_light(Sun::new((0.0, 0.0, 1.0), (0.0, 1.0, 1.0)));
+        let render = scene.spawn_render_threads(width, height);
+        assert_eq!(render.pixels().count(), width * height);
+        let render_stats = render.join().unwrap();
+        assert_eq!(render_stats.thread_times.len(), 2);
+        if stats::AVAILABLE {
+            assert!(render_stats.primary_rays >= (width * height) as u64);
+            assert!(render_stats.shadow_rays > 0);
+        } else {
+            assert_eq!(render_stats.primary_rays + render_stats.shadow_rays, 0);
+        }
+    }
 
-        let receiver = scene.spawn_render_threads(image_width, image_height);
-        image.update(receiver.iter());
+    #[test]
+    fn irradiance_caches_are_rendered_anew() {
+        let (width, height) = (32, 18);
+        let scene = |wall: (f64, f64, f64)| {
+            let mut scene = Scene::new();
+            scene.add_surface(Plane::new((0.0, 0.0, 1.0), -0.5));
+            scene.set_material("wall", Material::new(Constant::new(wall)));
+            scene
+                .add_surface_with_named_material(Plane::new((0.0, -1.0, 0.0), -3.0), "wall")
+                .unwrap();
+            scene.add_light(Sun::new((1.0, 1.0, 1.0), (0.5, 1.0, -1.0)));
+            scene.set_integrator(Integrator::IrradianceCaching {
+                max_depth: 2,
+                samples: 16,
+                max_error: 0.3,
+            });
+            // One thread adds the records in the same order every time.
+            scene.set_threads(ThreadSettings {
+                num_threads: Some(1),
+            });
+            scene
+        };
+        let render = |scene: Scene| {
+            let mut image = Image::new(width, height);
+            image.update(scene.spawn_render_threads(width, height).iter());
+            image.get_srgba_vector().to_vec()
+        };
 
-        let image_data = image.get_srgba_vector();
-        let ref_image_data = read_png(ref_filename).unwrap();
-        assert_eq!(*image_data, ref_image_data);
+        // Records of the red wall, cached by calibrating, don't tint the
+        // floor once the wall is green.
+        let mut repainted = scene((0.9, 0.1, 0.1));
+        repainted.calibrate(width, height);
+        repainted.set_material("wall", Material::new(Constant::new((0.1, 0.9, 0.1))));
+        // The mean red of the floor at the bottom of the image.
+        let floor_red = |srgba: Vec<u8>| {
+            let rows = &srgba[4 * width * (height - 3)..];
+            rows.iter()
+                .step_by(4)
+                .map(|&red| f64::from(red))
+                .sum::<f64>()
+                / (3 * width) as f64
+        };
+        let expected = floor_red(render(scene((0.1, 0.9, 0.1))));
+        assert!((floor_red(render(repainted)) - expected).abs() < 5.0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn irradiance_caching_needs_samples() {
+        Scene::new().set_integrator(Integrator::IrradianceCaching {
+            max_depth: 2,
+            samples: 0,
+            max_error: 0.3,
+        });
+    }
+
+    #[test]
+    fn mirrors_reflect_sphere_lights() {
+        let (width, height) = (16, 12);
+        let mut scene = Scene::new();
+        let mirror = Material::default().with_metallic(1.0).with_roughness(0.0);
+        scene.add_surface_with_material(Plane::new((0.0, 0.0, 1.0), -0.5), mirror);
+        scene.add_light(SphereLight::new((0.0, 3.0, 0.5), 0.5, (4.0, 4.0, 4.0)));
+        scene.set_integrator(Integrator::PathTracing {
+            max_depth: 2,
+            samples_per_pixel: 4,
+        });
+
+        let mut pixels = vec![Vector3::zero(); width * height];
+        for (x, y, pixel) in scene.spawn_render_threads(width, height).pixels() {
+            pixels[y * width + x] = pixel.rgb();
+        }
+        assert!(pixels
+            .iter()
+            .all(|rgb| rgb.x.is_finite() && rgb.y.is_finite() && rgb.z.is_finite()));
+        // The light is seen both directly and in the mirror, below the
+        // horizon.
+        let (top, bottom) = pixels.split_at(width * height / 2);
+        let brightest = |pixels: &[Vector3]| pixels.iter().map(|rgb| rgb.x).fold(0.0, f64::max);
+        assert!(brightest(top) > 0.5);
+        assert!(brightest(bottom) > 0.5);
+    }
+
+    #[test]
+    fn cost_maps_show_costly_pixels() {
+        let (width, height) = (20, 10);
+        let mut scene = Scene::new();
+        // Rays that hit the mirror are reflected, and the light is checked
+        // for shadows, while those that miss it end there.
+        let mirror = Material::default().with_metallic(1.0).with_roughness(0.0);
+        scene.add_surface_with_material(Sphere::new((0.0, 2.0, 0.0), 0.5), mirror);
+        scene.add_light(Sun::new((1.0, 1.0, 1.0), (1.0, 1.0, -1.0)));
+        scene.set_cost_map(Some(PixelCost::Rays));
+        let cost_map = scene.cost_map().unwrap();
+        assert_eq!(cost_map.cost(), PixelCost::Rays);
+
+        let render = scene.spawn_render_threads(width, height);
+        assert_eq!(render.pixels().count(), width * height);
+        render.join().unwrap();
+        let heatmap = cost_map.heatmap();
+        assert_eq!(heatmap.get_size(), (width, height));
+        let brightness = |x: usize, y: usize| {
+            let offset = 4 * (y * width + x);
+            let srgba = &heatmap.get_srgba_vector()[offset..offset + 3];
+            srgba.iter().map(|&channel| u32::from(channel)).sum::<u32>()
+        };
+        assert!(brightness(width / 2, height / 2) > brightness(0, 0));
+    }
+
+    #[test]
+    fn render_settings_set_up_renders() {
+        let scene = || {
+            let mut scene = Scene::new();
+            scene.add_surface(Sphere::new((0.0, 2.0, 0.0), 0.5));
+            scene.add_surface(Plane::new((0.0, 0.0, 1.0), -0.5));
+            scene.add_light(Sun::new((1.0, 1.0, 1.0), (1.0, 1.0, -1.0)));
+            scene
+        };
+        let settings = RenderSettings {
+            integrator: Integrator::PathTracing {
+                max_depth: 2,
+                samples_per_pixel: 2,
+            },
+            threads: ThreadSettings {
+                num_threads: Some(2),
+            },
+            tile_size: Some(5),
+            ..scene().render_settings(24, 16)
+        };
+        let render = |settings: &RenderSettings| {
+            let mut image = Image::new(settings.width, settings.height);
+            image.update(scene().spawn_render(settings).iter());
+            image.get_srgba_vector().clone()
+        };
+
+        // The same as with a setter for each.
+        let mut set_up = scene();
+        set_up.set_integrator(settings.integrator);
+        set_up.set_render_schedule(Some(RenderSchedule {
+            num_threads: 2,
+            tile_size: 5,
+        }));
+        assert!(set_up.render_settings(24, 16).tile_size == Some(5));
+        let mut image = Image::new(24, 16);
+        image.update(set_up.spawn_render_threads(24, 16).iter());
+        assert!(render(&settings) == *image.get_srgba_vector());
+
+        // Seeds change the noise, and the same seed gives the same image.
+        let seeded = RenderSettings {
+            seed: 7,
+            ..settings
+        };
+        assert!(render(&seeded) == render(&seeded));
+        assert!(render(&seeded) != render(&settings));
+    }
+
+    #[test]
+    fn crop_windows_render_part_of_the_image() {
+        let (width, height) = (40, 30);
+        let scene = |crop: Option<CropWindow>| {
+            let mut scene = Scene::new();
+            scene.add_surface(Sphere::new((0.0, 2.0, 0.0), 0.5));
+            scene.add_surface(Plane::new((0.0, 0.0, 1.0), -0.5));
+            scene.add_light(Sun::new((1.0, 1.0, 1.0), (1.0, 1.0, -1.0)));
+            scene.set_crop_window(crop);
+            scene
+        };
+        let full: Vec<_> = scene(None)
+            .spawn_render_threads(width, height)
+            .pixels()
+            .collect();
+
+        // The window sticks out of the image, which clips it.
+        let crop = CropWindow {
+            x: 13,
+            y: 20,
+            width: 11,
+            height: 20,
+        };
+        let inside = |&(x, y, _): &(usize, usize, Pixel)| (13..24).contains(&x) && y >= 20;
+        let cropped: Vec<_> = scene(Some(crop))
+            .spawn_render_threads(width, height)
+            .pixels()
+            .collect();
+        assert_eq!(cropped.len(), 11 * 10);
+        assert!(cropped.iter().all(inside));
+        let mut image = Image::new(width, height);
+        let runs = |pixels: Vec<_>| pixels.into_iter().map(|(x, y, pixel)| (x, y, vec![pixel]));
+        image.update(runs(cropped));
+        let mut expected = Image::new(width, height);
+        expected.update(runs(full.into_iter().filter(inside).collect()));
+        assert!(image.get_srgba_vector() == expected.get_srgba_vector());
+
+        let progressive = scene(Some(crop)).spawn_progressive_render(width, height, Some(2));
+        let samples: Vec<_> = progressive.pixels().collect();
+        assert_eq!(samples.len(), 2 * 11 * 10);
+        assert!(samples.iter().all(inside));
+    }
+
+    #[test]
+    fn thread_settings_set_the_number_of_threads() {
+        let (width, height) = (40, 30);
+        let render = |num_threads: Option<usize>| {
+            let mut scene = Scene::new();
+            scene.add_surface(Sphere::new((0.0, 2.0, 0.0), 0.5));
+            scene.add_light(Sun::new((1.0, 1.0, 1.0), (1.0, 1.0, -1.0)));
+            scene.set_threads(ThreadSettings { num_threads });
+            let calibrated = scene.calibrate(width, height).num_threads;
+
+            let mut image = Image::new(width, height);
+            image.update(scene.spawn_render_threads(width, height).iter());
+            (calibrated, image.get_srgba_vector().clone())
+        };
+
+        let (_, image) = render(None);
+        for (num_threads, expected) in [(0, 1), (1, 1), (3, 3)] {
+            assert!(render(Some(num_threads)) == (expected, image.clone()));
+        }
+        assert!(ThreadSettings::default().max_threads() >= 1);
+    }
+
+    #[test]
+    fn canonical_scenes_match_references() {
+        for scene in verify::canonical_scenes() {
+            let verification = verify::verify(&scene, Path::new(verify::REFERENCE_DIR)).unwrap();
+            assert!(verification.passed(), "{}", verify::report(&[verification]));
+        }
+    }
+
+    #[test]
+    fn content_hash_follows_the_content_of_the_scene() {
+        type Change = dyn Fn(&mut Scene, SurfaceId, LightId);
+        let scene = |change: &Change| {
+            let mut scene = Scene::new();
+            scene.set_material("red", Material::new(Constant::new((0.8, 0.1, 0.1))));
+            scene.add_surface(Plane::new((0.0, 0.0, 1.0), -0.5));
+            let sphere = scene
+                .add_surface_with_named_material(Sphere::new((0.0, 2.0, 0.0), 0.5), "red")
+                .unwrap();
+            let light = scene.add_light(SphereLight::new((1.0, 1.0, 2.0), 0.5, (2.0, 2.0, 2.0)));
+            change(&mut scene, sphere, light);
+            scene
+        };
+        let hash = scene(&|_, _, _| {}).content_hash().unwrap();
+        assert_eq!(scene(&|_, _, _| {}).content_hash(), Some(hash));
+
+        let changes: [&Change; 5] = [
+            &|scene, sphere, _| {
+                *scene.get_mut_surface(sphere).unwrap() =
+                    Box::new(Sphere::new((0.0, 3.0, 0.0), 0.5));
+            },
+            &|scene, _, _| {
+                scene.set_material("red", Material::new(Constant::new((0.9, 0.1, 0.1))));
+            },
+            &|scene, _, light| {
+                *scene.get_mut_light(light).unwrap() =
+                    Box::new(SphereLight::new((1.0, 1.0, 2.0), 0.4, (2.0, 2.0, 2.0)));
+            },
+            &|scene, _, _| {
+                let orientation = UnitQuaternion::from_axis_angle((0.0, 0.0, 1.0), 0.1);
+                scene.set_camera((0.0, -1.0, 0.0), orientation);
+            },
+            &|scene, _, _| {
+                scene.set_integrator(Integrator::PathTracing {
+                    max_depth: 3,
+                    samples_per_pixel: 4,
+                });
+            },
+        ];
+        for change in changes {
+            assert_ne!(scene(change).content_hash(), Some(hash));
+        }
+
+        // Surfaces made by plugins can't be hashed.
+        struct Ball(Sphere);
+
+        impl Surface for Ball {
+            fn closest_intersection(&self, ray: &Ray) -> Option<Intersection> {
+                self.0.closest_intersection(ray)
+            }
+        }
+
+        let plugin = scene(&|scene, _, _| {
+            scene.add_surface(Ball(Sphere::new((0.0, 3.0, 0.0), 0.5)));
+        });
+        assert_eq!(plugin.content_hash(), None);
     }
 }
@@ -1,20 +1,994 @@
 //! Module containing different light sources.
 
-use crate::math::Vector3;
+mod environment;
+mod sky;
 
-/// A light source emitting parallel light rays from a specified direction.
+pub use environment::EnvironmentMap;
+pub use sky::PhysicalSky;
+
+use crate::hashing::ContentHasher;
+use crate::image::{blackbody_color, ColorSpace};
+use crate::math::{sampling, Ray, UnitQuaternion, Vector3};
+use crate::scene_file::{LightDescription, TransformDescription};
+use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
+use std::f64::INFINITY;
+
+/// The angular radius of the real sun, seen from the earth, in radians.
+pub const SUN_ANGULAR_RADIUS: f64 = 0.004_654;
+
+/// The light arriving at a point from a light source, along one sampled
+/// direction.
+pub struct Illumination {
+    /// Unit vector pointing from the point towards the light source.
+    pub direction: Vector3,
+    /// The distance from the point to the light source. Infinite for light
+    /// sources that are infinitely far away.
+    pub distance: f64,
+    /// The color of the arriving light, in linear RGB, divided by the
+    /// probability of sampling the direction. A white, diffuse surface facing
+    /// the light reflects this color.
+    pub color: Vector3,
+    /// The probability density, over solid angle, of sampling the direction.
+    /// Infinite for lights that shine from a single point or direction.
+    pub pdf: f64,
+}
+
+/// Where a ray hits a light source with an extent.
+pub struct LightHit {
+    /// The length along the ray to the light.
+    pub distance: f64,
+    /// The radiance emitted towards the origin of the ray, in linear RGB.
+    pub radiance: Vector3,
+    /// The probability density, over solid angle, that `Light::illuminate`
+    /// samples the direction of the ray from its origin.
+    pub pdf: f64,
+}
+
+/// A photon emitted by a light source, for photon mapping.
+pub struct Emission {
+    pub ray: Ray,
+    /// The power of the photon, in linear RGB, divided by the probability of
+    /// sampling it. A white, diffuse surface hit by photons with a total power
+    /// of P per unit area reflects the color P / π.
+    pub power: Vector3,
+}
+
+/// The region that a light with a position shines from, and how much light it
+/// sends out, for deciding how important it is to a point.
+#[derive(Clone, Copy, Debug)]
+pub struct LightBounds {
+    /// The corner of a box around the light with the lowest coordinates.
+    pub min: Vector3,
+    /// The corner of the box with the highest coordinates.
+    pub max: Vector3,
+    /// The luminance of the total power that the light sends out, which only
+    /// needs to be right relative to that of other lights.
+    pub power: f64,
+}
+
+/// How bright a light is, in physical units. With physical units, the colors
+/// of the scene are luminances in cd/m², which are scaled to pixel values by
+/// the exposure of the camera, as set with `Scene::set_exposure`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Brightness {
+    /// The luminous power, in lumens, as given on the packaging of lamps.
+    Lumens(f64),
+    /// The luminous intensity, in candela, which is the same in all directions
+    /// for point and sphere lights, and straight out from the front for area
+    /// lights.
+    Candela(f64),
+    /// The electrical power, in watts, of a lamp turning it into light with
+    /// `efficacy` lumens per watt, such as `INCANDESCENT_EFFICACY`.
+    Watts { watts: f64, efficacy: f64 },
+}
+
+/// The luminous efficacy of incandescent light bulbs, in lumens per watt.
+pub const INCANDESCENT_EFFICACY: f64 = 15.0;
+
+impl Brightness {
+    /// Find the luminous intensity, in candela, of a light that sends
+    /// `lumens_per_candela` times its intensity in lumens, e.g. 4π for a light
+    /// shining equally in all directions.
+    fn candela(self, lumens_per_candela: f64) -> f64 {
+        match self {
+            Brightness::Lumens(lumens) => lumens / lumens_per_candela,
+            Brightness::Candela(candela) => candela,
+            Brightness::Watts { watts, efficacy } => watts * efficacy / lumens_per_candela,
+        }
+    }
+}
+
+/// Scale `color` to have the luminance `luminance`, keeping its hue and
+/// saturation. Black stays black.
+fn with_luminance(color: Vector3, luminance: f64) -> Vector3 {
+    let color_luminance = color_luminance(color);
+    if color_luminance > 0.0 {
+        color * (luminance / color_luminance)
+    } else {
+        Vector3::zero()
+    }
+}
+
+/// Find the luminance of a color in linear RGB.
+fn color_luminance(color: Vector3) -> f64 {
+    0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z
+}
+
+/// A `Light` illuminates the surfaces of a scene.
+pub trait Light {
+    /// Sample the light arriving at `point`, ignoring shadows. `u` is a pair
+    /// of uniformly distributed numbers in [0, 1), which lights with an extent
+    /// use to choose the part of the light that the light comes from.
+    fn illuminate(&self, point: Vector3, u: (f64, f64)) -> Illumination;
+
+    /// Find where `ray` hits the light. Lights without an extent can't be hit,
+    /// which is the default.
+    fn hit(&self, _ray: &Ray) -> Option<LightHit> {
+        None
+    }
+
+    /// Emit a photon towards the sphere with `center` and `radius`, which
+    /// encloses the surfaces that photons are traced for. `u` is a pair of
+    /// uniformly distributed numbers in [0, 1). Lights that don't support
+    /// photon mapping return `None`, which is the default.
+    fn emit(&self, _center: Vector3, _radius: f64, _u: (f64, f64)) -> Option<Emission> {
+        None
+    }
+
+    /// The number of times the light is sampled at each point, with a shadow
+    /// ray for each. Large lights need many for smooth soft shadows. The
+    /// default is 1.
+    fn samples(&self) -> u32 {
+        1
+    }
+
+    /// Find the box that the light shines from and its power, for picking
+    /// among many lights. Lights without a position, like the sun, return
+    /// `None`, which is the default, and are sampled at every point.
+    fn bounds(&self) -> Option<LightBounds> {
+        None
+    }
+
+    /// Find how the light appears from `point`, for lens flares: towards its
+    /// middle, how far away, and with the color that a white, diffuse surface
+    /// facing it there reflects. Lights that don't shine from one place, like
+    /// environment maps, return `None`, which is the default.
+    fn glare(&self, _point: Vector3) -> Option<Illumination> {
+        None
+    }
+
+    /// Write the kind, placement and color of the light to `hasher`. The
+    /// default writes that the light can't be hashed.
+    fn hash_content(&self, hasher: &mut ContentHasher) {
+        hasher.write_unknown();
+    }
+
+    /// Describe the light as in scene files, for saving scenes built in code.
+    /// The default returns `None`, for lights that scene files can't
+    /// describe, such as those made by plugins.
+    fn describe(&self) -> Option<LightDescription> {
+        None
+    }
+
+    /// Convert the color the light was given in `color_space` to linear RGB.
+    /// Scenes call this once, when the light is added. Only the color is
+    /// converted, not the light arriving at points, which would bend how the
+    /// light falls off. The default leaves the light as it is, for lights
+    /// given in linear RGB, such as environments.
+    fn convert_color(&mut self, _color_space: ColorSpace) {}
+}
+
+impl<T: Light + ?Sized> Light for Box<T> {
+    fn illuminate(&self, point: Vector3, u: (f64, f64)) -> Illumination {
+        self.as_ref().illuminate(point, u)
+    }
+
+    fn hit(&self, ray: &Ray) -> Option<LightHit> {
+        self.as_ref().hit(ray)
+    }
+
+    fn emit(&self, center: Vector3, radius: f64, u: (f64, f64)) -> Option<Emission> {
+        self.as_ref().emit(center, radius, u)
+    }
+
+    fn samples(&self) -> u32 {
+        self.as_ref().samples()
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        self.as_ref().bounds()
+    }
+
+    fn glare(&self, point: Vector3) -> Option<Illumination> {
+        self.as_ref().glare(point)
+    }
+
+    fn hash_content(&self, hasher: &mut ContentHasher) {
+        self.as_ref().hash_content(hasher);
+    }
+
+    fn describe(&self) -> Option<LightDescription> {
+        self.as_ref().describe()
+    }
+
+    fn convert_color(&mut self, color_space: ColorSpace) {
+        self.as_mut().convert_color(color_space);
+    }
+}
+
+/// A light source emitting parallel light rays from a specified direction, or
+/// from a small disc in the sky if it is given an angular radius.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Sun {
     /// The color of the light rays, in linear RGB.
     pub color: Vector3,
     /// The direction the rays point in. Must be a unit vector.
     pub direction: Vector3,
+    /// The angular radius of the disc of the sun, in radians.
+    angular_radius: f64,
+    samples: u32,
 }
 
 impl Sun {
+    /// Make a sun without an angular radius, which gives hard shadows.
     pub fn new<T: Into<Vector3>, U: Into<Vector3>>(color: T, direction: U) -> Self {
         Self {
             color: color.into(),
             direction: direction.into().normalize(),
+            angular_radius: 0.0,
+            samples: 16,
+        }
+    }
+
+    /// Give the sun a disc with an angular radius of `angular_radius`
+    /// radians, so that its shadows get soft edges, which grow with the
+    /// distance from the surface casting them. The real sun has an angular
+    /// radius of `SUN_ANGULAR_RADIUS`.
+    pub fn with_angular_radius(mut self, angular_radius: f64) -> Self {
+        self.angular_radius = angular_radius.max(0.0);
+        self
+    }
+
+    /// Set the illuminance of the sun on a surface facing it to `lux`, keeping
+    /// its color. Direct sunlight is about 100 000 lux.
+    pub fn with_illuminance(mut self, lux: f64) -> Self {
+        // A white, diffuse surface facing the sun reflects its color.
+        self.color = with_luminance(self.color, lux / PI);
+        self
+    }
+
+    /// Sample a sun with an angular radius `samples` times at each point.
+    /// More samples give smoother edges of shadows, at the cost of more
+    /// shadow rays. The default is 16.
+    pub fn with_samples(mut self, samples: u32) -> Self {
+        self.samples = samples.max(1);
+        self
+    }
+
+    /// Make a sun with the color of a black body at `temperature` kelvin,
+    /// scaled by `intensity`.
+    pub fn from_temperature<T: Into<Vector3>>(
+        temperature: f64,
+        intensity: f64,
+        direction: T,
+    ) -> Self {
+        Self::new(intensity * blackbody_color(temperature), direction)
+    }
+
+    /// Bright, neutral sunlight from high in the sky.
+    pub fn noon() -> Self {
+        Self::from_temperature(5_800.0, 1.0, (0.2, 0.3, -1.0))
+    }
+
+    /// Dim, warm sunlight from just above the horizon.
+    pub fn golden_hour() -> Self {
+        Self::from_temperature(3_000.0, 0.6, (0.3, 1.0, -0.15))
+    }
+}
+
+impl Light for Sun {
+    fn illuminate(&self, _point: Vector3, u: (f64, f64)) -> Illumination {
+        // The disc is sampled uniformly for the shadow rays, but it is too
+        // small for other rays to hit it in practice, so it isn't lit by them,
+        // and the light still counts as coming from a single direction.
+        let direction = if self.angular_radius > 0.0 {
+            let cone = sampling::uniform_cone(u, self.angular_radius.cos());
+            sampling::orient_along(cone, -self.direction)
+        } else {
+            -self.direction
+        };
+        Illumination {
+            direction,
+            distance: INFINITY,
+            color: self.color,
+            pdf: INFINITY,
+        }
+    }
+
+    fn glare(&self, _point: Vector3) -> Option<Illumination> {
+        Some(Illumination {
+            direction: -self.direction,
+            distance: INFINITY,
+            color: self.color,
+            pdf: INFINITY,
+        })
+    }
+
+    fn emit(&self, center: Vector3, radius: f64, u: (f64, f64)) -> Option<Emission> {
+        // Parallel rays through a disk facing the sun, covering the sphere. They
+        // start far away, so that surfaces shadowing the sphere are hit first.
+        let (x, y) = sampling::uniform_disk(u);
+        let offset = sampling::orient_along(Vector3::from((x, y, 0.0)), self.direction);
+        let origin = center + radius * offset - 1e3 * radius * self.direction;
+
+        // A white, diffuse surface facing the sun reflects its color, so the
+        // irradiance is π times the color.
+        let area = PI * radius * radius;
+        Some(Emission {
+            ray: Ray::new(origin, self.direction),
+            power: (PI * area) * self.color,
+        })
+    }
+
+    /// A sun without an angular radius is sampled once, since all samples
+    /// would be the same.
+    fn samples(&self) -> u32 {
+        if self.angular_radius > 0.0 {
+            self.samples
+        } else {
+            1
+        }
+    }
+
+    fn hash_content(&self, hasher: &mut ContentHasher) {
+        hasher.write_str("Sun");
+        hasher.write_vector(self.color);
+        hasher.write_vector(self.direction);
+        hasher.write_f64(self.angular_radius);
+        hasher.write_u64(u64::from(self.samples()));
+    }
+
+    fn describe(&self) -> Option<LightDescription> {
+        Some(LightDescription::Sun {
+            color: self.color.into(),
+            direction: self.direction.into(),
+            illuminance: None,
+            angular_radius: self.angular_radius.to_degrees(),
+            samples: self.samples,
+        })
+    }
+
+    fn convert_color(&mut self, color_space: ColorSpace) {
+        self.color = color_space.to_linear(self.color);
+    }
+}
+
+/// A light source emitting light equally in all directions from a single
+/// point. The light falls off with the square of the distance, and gives
+/// hard shadows.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PointLight {
+    pub position: Vector3,
+    /// The color of the light, in linear RGB.
+    pub color: Vector3,
+    /// The radiant intensity of the light, as a factor on `color`. A white,
+    /// diffuse surface facing the light at a distance of r meters reflects
+    /// the color times intensity / (π r²).
+    pub intensity: f64,
+}
+
+impl PointLight {
+    pub fn new<T: Into<Vector3>, U: Into<Vector3>>(position: T, color: U, intensity: f64) -> Self {
+        Self {
+            position: position.into(),
+            color: color.into(),
+            intensity,
         }
     }
+
+    /// Make a point light with the color of a black body at `temperature`
+    /// kelvin.
+    pub fn from_temperature<T: Into<Vector3>>(
+        position: T,
+        temperature: f64,
+        intensity: f64,
+    ) -> Self {
+        Self::new(position, blackbody_color(temperature), intensity)
+    }
+
+    /// Set the brightness of the light, keeping its color.
+    pub fn with_brightness(mut self, brightness: Brightness) -> Self {
+        self.color = with_luminance(self.color, 1.0);
+        self.intensity = brightness.candela(4.0 * PI);
+        self
+    }
+
+    /// A warm, 60 watt incandescent light bulb, bright enough to light a
+    /// small room on its own.
+    pub fn incandescent_60w<T: Into<Vector3>>(position: T) -> Self {
+        Self::from_temperature(position, 2_700.0, 1.0).with_brightness(Brightness::Watts {
+            watts: 60.0,
+            efficacy: INCANDESCENT_EFFICACY,
+        })
+    }
+}
+
+impl Light for PointLight {
+    fn illuminate(&self, point: Vector3, _u: (f64, f64)) -> Illumination {
+        let to_light = self.position - point;
+        let distance2 = to_light.norm2();
+        if distance2 == 0.0 {
+            return Illumination {
+                direction: Vector3::k(),
+                distance: 0.0,
+                color: Vector3::zero(),
+                pdf: INFINITY,
+            };
+        }
+
+        Illumination {
+            direction: to_light.normalize(),
+            distance: distance2.sqrt(),
+            color: (self.intensity / (PI * distance2)) * self.color,
+            pdf: INFINITY,
+        }
+    }
+
+    fn emit(&self, center: Vector3, radius: f64, u: (f64, f64)) -> Option<Emission> {
+        // Emit photons uniformly in the cone of directions towards the
+        // sphere, or in all directions if the light is inside it.
+        let to_center = center - self.position;
+        let distance2 = to_center.norm2();
+        let (cos_max, axis) = if distance2 > radius * radius {
+            (
+                (1.0 - radius * radius / distance2).sqrt(),
+                to_center.normalize(),
+            )
+        } else {
+            (-1.0, Vector3::k())
+        };
+        let direction = sampling::orient_along(sampling::uniform_cone(u, cos_max), axis);
+        Some(Emission {
+            ray: Ray::new(self.position, direction),
+            power: (self.intensity / sampling::uniform_cone_pdf(cos_max)) * self.color,
+        })
+    }
+
+    fn glare(&self, point: Vector3) -> Option<Illumination> {
+        Some(self.illuminate(point, (0.0, 0.0))).filter(|glare| glare.distance > 0.0)
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        Some(LightBounds {
+            min: self.position,
+            max: self.position,
+            power: 4.0 * PI * self.intensity * color_luminance(self.color),
+        })
+    }
+
+    fn hash_content(&self, hasher: &mut ContentHasher) {
+        hasher.write_str("PointLight");
+        hasher.write_vector(self.position);
+        hasher.write_vector(self.color);
+        hasher.write_f64(self.intensity);
+    }
+
+    fn describe(&self) -> Option<LightDescription> {
+        Some(LightDescription::PointLight {
+            position: self.position.into(),
+            color: self.color.into(),
+            intensity: self.intensity,
+            brightness: None,
+        })
+    }
+
+    fn convert_color(&mut self, color_space: ColorSpace) {
+        self.color = color_space.to_linear(self.color);
+    }
+}
+
+/// A glowing sphere, emitting the same radiance in all directions from its
+/// surface. Small, bright sphere lights give the highlights and soft shadows
+/// of light bulbs.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SphereLight {
+    pub center: Vector3,
+    pub radius: f64,
+    /// The emitted radiance, in linear RGB.
+    pub radiance: Vector3,
+}
+
+impl SphereLight {
+    pub fn new<T: Into<Vector3>, U: Into<Vector3>>(center: T, radius: f64, radiance: U) -> Self {
+        Self {
+            center: center.into(),
+            radius,
+            radiance: radiance.into(),
+        }
+    }
+
+    /// Set the brightness of the light, keeping its color.
+    pub fn with_brightness(mut self, brightness: Brightness) -> Self {
+        // The sphere is seen as a disc of area π r² from every direction.
+        let area = PI * self.radius * self.radius;
+        self.radiance = with_luminance(self.radiance, brightness.candela(4.0 * PI) / area);
+        self
+    }
+
+    /// Find the cosine of the half-angle of the cone of directions from `point`
+    /// that hit the sphere, or `None` if the point is inside the sphere.
+    fn cos_max(&self, point: Vector3) -> Option<f64> {
+        let distance2 = (self.center - point).norm2();
+        let radius2 = self.radius * self.radius;
+        if distance2 <= radius2 {
+            None
+        } else {
+            Some((1.0 - radius2 / distance2).sqrt())
+        }
+    }
+}
+
+impl Light for SphereLight {
+    fn illuminate(&self, point: Vector3, u: (f64, f64)) -> Illumination {
+        let to_center = self.center - point;
+        let cos_max = match self.cos_max(point) {
+            Some(cos_max) => cos_max,
+            None => {
+                return Illumination {
+                    direction: Vector3::k(),
+                    distance: 0.0,
+                    color: Vector3::zero(),
+                    pdf: INFINITY,
+                }
+            }
+        };
+
+        // Sample the cone of directions that hit the sphere uniformly.
+        let direction =
+            sampling::orient_along(sampling::uniform_cone(u, cos_max), to_center.normalize());
+        let pdf = sampling::uniform_cone_pdf(cos_max);
+        let distance = match self.hit(&Ray::new(point, direction)) {
+            Some(hit) => hit.distance,
+            // Only possible due to rounding errors, at the edge of the cone.
+            None => to_center.dot(direction),
+        };
+
+        Illumination {
+            direction,
+            distance,
+            color: self.radiance * (1.0 / (PI * pdf)),
+            pdf,
+        }
+    }
+
+    fn hit(&self, ray: &Ray) -> Option<LightHit> {
+        let to_center = self.center - ray.origin;
+        let projection = to_center.dot(ray.direction);
+        let distance2 = to_center.norm2() - projection * projection;
+        let radius2 = self.radius * self.radius;
+        if distance2 > radius2 {
+            return None;
+        }
+
+        let distance = projection - (radius2 - distance2).sqrt();
+        if distance <= 0.0 {
+            return None;
+        }
+
+        Some(LightHit {
+            distance,
+            radiance: self.radiance,
+            pdf: self
+                .cos_max(ray.origin)
+                .map_or(0.0, sampling::uniform_cone_pdf),
+        })
+    }
+
+    fn emit(&self, center: Vector3, radius: f64, u: (f64, f64)) -> Option<Emission> {
+        // Treat the light as a point at its center, and emit photons uniformly
+        // in the cone of directions towards the sphere, or in all directions if
+        // the light is inside it.
+        let to_center = center - self.center;
+        let distance2 = to_center.norm2();
+        let cos_max = if distance2 > radius * radius {
+            (1.0 - radius * radius / distance2).sqrt()
+        } else {
+            -1.0
+        };
+        let direction = sampling::orient_along(
+            sampling::uniform_cone(u, cos_max),
+            if distance2 > 0.0 {
+                to_center.normalize()
+            } else {
+                Vector3::k()
+            },
+        );
+
+        // A sphere has the same projected area in every direction.
+        let intensity = (PI * self.radius * self.radius) * self.radiance;
+        Some(Emission {
+            ray: Ray::new(self.center + self.radius * direction, direction),
+            power: intensity * (1.0 / sampling::uniform_cone_pdf(cos_max)),
+        })
+    }
+
+    fn glare(&self, point: Vector3) -> Option<Illumination> {
+        let cos_max = self.cos_max(point)?;
+        let to_center = self.center - point;
+        Some(Illumination {
+            direction: to_center.normalize(),
+            distance: to_center.norm() - self.radius,
+            color: self.radiance * (1.0 / (PI * sampling::uniform_cone_pdf(cos_max))),
+            pdf: INFINITY,
+        })
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        let radius = self.radius * Vector3::ones();
+        let intensity = PI * self.radius * self.radius * color_luminance(self.radiance);
+        Some(LightBounds {
+            min: self.center - radius,
+            max: self.center + radius,
+            power: 4.0 * PI * intensity,
+        })
+    }
+
+    fn hash_content(&self, hasher: &mut ContentHasher) {
+        hasher.write_str("SphereLight");
+        hasher.write_vector(self.center);
+        hasher.write_f64(self.radius);
+        hasher.write_vector(self.radiance);
+    }
+
+    fn describe(&self) -> Option<LightDescription> {
+        Some(LightDescription::SphereLight {
+            center: self.center.into(),
+            radius: self.radius,
+            radiance: self.radiance.into(),
+            brightness: None,
+        })
+    }
+
+    fn convert_color(&mut self, color_space: ColorSpace) {
+        self.radiance = color_space.to_linear(self.radiance);
+    }
+}
+
+/// A glowing rectangle, emitting the same radiance in all directions from its
+/// front. Large area lights give soft shadows, with penumbrae that are found
+/// by sampling the light several times at each point.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct AreaLight {
+    /// The corner that the edges start from.
+    corner: Vector3,
+    /// The sides of the rectangle. The front faces along their cross product.
+    edges: (Vector3, Vector3),
+    /// The emitted radiance, in linear RGB.
+    radiance: Vector3,
+    samples: u32,
+}
+
+impl AreaLight {
+    /// Make a rectangle light of size `width` x `height`, centered at the
+    /// origin of the scene, with its width along the x-axis and its height
+    /// along the y-axis, shining up along the z-axis. Use `with_transform` to
+    /// turn it around and place it elsewhere. It is sampled 16 times at each
+    /// point.
+    pub fn new<T: Into<Vector3>>(width: f64, height: f64, radiance: T) -> Self {
+        Self {
+            corner: Vector3::from((-0.5 * width, -0.5 * height, 0.0)),
+            edges: (width * Vector3::i(), height * Vector3::j()),
+            radiance: radiance.into(),
+            samples: 16,
+        }
+    }
+
+    /// Rotate the light by `rotation` around the origin of the scene, and then
+    /// move it by `translation`.
+    pub fn with_transform<T: Into<Vector3>>(
+        mut self,
+        rotation: UnitQuaternion,
+        translation: T,
+    ) -> Self {
+        self.corner = self.corner.rotate(rotation) + translation.into();
+        self.edges = (self.edges.0.rotate(rotation), self.edges.1.rotate(rotation));
+        self
+    }
+
+    /// Sample the light `samples` times at each point. More samples give
+    /// smoother penumbrae, at the cost of more shadow rays.
+    pub fn with_samples(mut self, samples: u32) -> Self {
+        self.samples = samples.max(1);
+        self
+    }
+
+    /// Set the brightness of the light, keeping its color.
+    pub fn with_brightness(mut self, brightness: Brightness) -> Self {
+        // A diffuse emitter sends π times its intensity straight out.
+        self.radiance = with_luminance(self.radiance, brightness.candela(PI) / self.area());
+        self
+    }
+
+    fn area(&self) -> f64 {
+        self.edges.0.cross(self.edges.1).norm()
+    }
+
+    fn normal(&self) -> Vector3 {
+        self.edges.0.cross(self.edges.1).normalize()
+    }
+
+    /// Find the probability density, over solid angle, of sampling the
+    /// direction from a point to a point on the light `distance` away, where
+    /// the cosine between the direction and the normal of the light is
+    /// `cos_light`.
+    fn pdf(&self, distance: f64, cos_light: f64) -> f64 {
+        distance * distance / (self.area() * cos_light)
+    }
+}
+
+impl Light for AreaLight {
+    fn illuminate(&self, point: Vector3, u: (f64, f64)) -> Illumination {
+        // Shifted by half, so that (0, 0) is the middle of the light.
+        let (s, t) = ((u.0 + 0.5).fract(), (u.1 + 0.5).fract());
+        let to_light = self.corner + s * self.edges.0 + t * self.edges.1 - point;
+        let distance = to_light.norm();
+        let direction = to_light * (1.0 / distance);
+        let cos_light = -direction.dot(self.normal());
+        if distance == 0.0 || cos_light <= 0.0 {
+            // The point is behind the light.
+            return Illumination {
+                direction: Vector3::k(),
+                distance: 0.0,
+                color: Vector3::zero(),
+                pdf: INFINITY,
+            };
+        }
+
+        let pdf = self.pdf(distance, cos_light);
+        Illumination {
+            direction,
+            distance,
+            color: self.radiance * (1.0 / (PI * pdf)),
+            pdf,
+        }
+    }
+
+    fn hit(&self, ray: &Ray) -> Option<LightHit> {
+        let normal = self.normal();
+        let cos_light = -ray.direction.dot(normal);
+        if cos_light <= 0.0 {
+            return None;
+        }
+
+        let distance = (self.corner - ray.origin).dot(normal) / ray.direction.dot(normal);
+        if distance <= 0.0 {
+            return None;
+        }
+        let offset = ray.origin + distance * ray.direction - self.corner;
+        let s = offset.dot(self.edges.0) / self.edges.0.norm2();
+        let t = offset.dot(self.edges.1) / self.edges.1.norm2();
+        if !((0.0..=1.0).contains(&s) && (0.0..=1.0).contains(&t)) {
+            return None;
+        }
+
+        Some(LightHit {
+            distance,
+            radiance: self.radiance,
+            pdf: self.pdf(distance, cos_light),
+        })
+    }
+
+    fn emit(&self, _center: Vector3, _radius: f64, u: (f64, f64)) -> Option<Emission> {
+        // A uniformly chosen point on the light, and a cosine weighted
+        // direction from it, where the cosine cancels against the density.
+        // The low digits of `u` are as uniform as the whole, and nearly
+        // independent of it, so they choose the direction.
+        let origin = self.corner + u.0 * self.edges.0 + u.1 * self.edges.1;
+        let v = ((u.0 * 1_048_576.0).fract(), (u.1 * 1_048_576.0).fract());
+        let direction = sampling::orient_along(sampling::cosine_hemisphere(v), self.normal());
+        Some(Emission {
+            ray: Ray::new(origin, direction),
+            power: (PI * self.area()) * self.radiance,
+        })
+    }
+
+    fn samples(&self) -> u32 {
+        self.samples
+    }
+
+    fn glare(&self, point: Vector3) -> Option<Illumination> {
+        // Seen from the front only, as a small light.
+        let to_center = self.corner + 0.5 * (self.edges.0 + self.edges.1) - point;
+        let distance = to_center.norm();
+        let direction = to_center.normalize();
+        let cos_light = -direction.dot(self.normal());
+        if distance == 0.0 || cos_light <= 0.0 {
+            return None;
+        }
+        Some(Illumination {
+            direction,
+            distance,
+            color: self.radiance * (1.0 / (PI * self.pdf(distance, cos_light))),
+            pdf: INFINITY,
+        })
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        let far_corner = self.corner + self.edges.0 + self.edges.1;
+        let corners = [
+            self.corner + self.edges.0,
+            self.corner + self.edges.1,
+            far_corner,
+        ];
+        let (min, max) = corners
+            .iter()
+            .fold((self.corner, self.corner), |(min, max), &corner| {
+                (min.min(corner), max.max(corner))
+            });
+        Some(LightBounds {
+            min,
+            max,
+            power: PI * self.area() * color_luminance(self.radiance),
+        })
+    }
+
+    fn hash_content(&self, hasher: &mut ContentHasher) {
+        hasher.write_str("AreaLight");
+        hasher.write_vector(self.corner);
+        hasher.write_vector(self.edges.0);
+        hasher.write_vector(self.edges.1);
+        hasher.write_vector(self.radiance);
+        hasher.write_u64(u64::from(self.samples));
+    }
+
+    fn describe(&self) -> Option<LightDescription> {
+        // The light made by `new` has its width along the x-axis and its
+        // height along the y-axis, which the rotation turns into the edges.
+        let (width, height) = (self.edges.0.norm(), self.edges.1.norm());
+        let rotation = UnitQuaternion::from_basis(
+            self.edges.0 * (1.0 / width),
+            self.edges.1 * (1.0 / height),
+            self.normal(),
+        );
+        let corner = Vector3::from((-0.5 * width, -0.5 * height, 0.0));
+        Some(LightDescription::AreaLight {
+            width,
+            height,
+            radiance: self.radiance.into(),
+            transform: Some(TransformDescription::new(
+                rotation,
+                self.corner - corner.rotate(rotation),
+            )),
+            samples: self.samples,
+            brightness: None,
+        })
+    }
+
+    fn convert_color(&mut self, color_space: ColorSpace) {
+        self.radiance = color_space.to_linear(self.radiance);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn point_light_falls_off_with_distance_squared() {
+        let light = PointLight::new((0.0, 0.0, 2.0), (1.0, 0.5, 0.25), 3.0);
+
+        let near = light.illuminate(Vector3::zero(), (0.5, 0.5));
+        assert!((near.direction - Vector3::k()).norm() < 1e-12);
+        assert!((near.distance - 2.0).abs() < 1e-12);
+        assert!((near.color.x - 3.0 / (4.0 * PI)).abs() < 1e-12);
+        assert!((near.color.y - 0.5 * near.color.x).abs() < 1e-12);
+
+        let far = light.illuminate(Vector3::from((0.0, 0.0, -2.0)), (0.5, 0.5));
+        assert!((far.distance - 4.0).abs() < 1e-12);
+        assert!((4.0 * far.color.x - near.color.x).abs() < 1e-12);
+    }
+
+    #[test]
+    fn physical_units_give_the_expected_illuminance() {
+        // 4π lumens from a point is 1 candela, giving 1/4 lux at 2 meters.
+        let bulb = PointLight::new((0.0, 0.0, 2.0), (1.0, 0.5, 0.25), 3.0)
+            .with_brightness(Brightness::Lumens(4.0 * PI));
+        let sample = bulb.illuminate(Vector3::zero(), (0.5, 0.5));
+        assert!((color_luminance(sample.color) - 0.25 / PI).abs() < 1e-12);
+        assert!((sample.color.y - 0.5 * sample.color.x).abs() < 1e-12);
+
+        let watts = Brightness::Watts {
+            watts: 60.0,
+            efficacy: INCANDESCENT_EFFICACY,
+        };
+        assert!((watts.candela(1.0) - 900.0).abs() < 1e-9);
+        assert_eq!(Brightness::Candela(7.0).candela(4.0 * PI), 7.0);
+
+        // A sphere far away looks like a point with the same intensity.
+        let sphere = SphereLight::new((0.0, 0.0, 100.0), 0.5, (1.0, 1.0, 1.0))
+            .with_brightness(Brightness::Candela(1e4));
+        let sample = sphere.illuminate(Vector3::zero(), (0.5, 0.5));
+        assert!((color_luminance(sample.color) - 1.0 / PI).abs() < 1e-3 / PI);
+
+        let sun = Sun::new((1.0, 0.9, 0.8), (0.0, 0.0, -1.0)).with_illuminance(1e5);
+        let sample = sun.illuminate(Vector3::zero(), (0.5, 0.5));
+        assert!((color_luminance(sample.color) - 1e5 / PI).abs() < 1e-6);
+    }
+
+    #[test]
+    fn sun_samples_its_disc() {
+        let hard = Sun::new((1.0, 1.0, 1.0), (0.0, 1.0, -1.0)).with_samples(8);
+        assert_eq!(hard.samples(), 1);
+        let sample = hard.illuminate(Vector3::zero(), (0.3, 0.7));
+        assert!((sample.direction + hard.direction).norm() < 1e-12);
+
+        let radius = 10.0 * SUN_ANGULAR_RADIUS;
+        let soft = Sun::new((1.0, 1.0, 1.0), (0.0, 1.0, -1.0)).with_angular_radius(radius);
+        assert_eq!(soft.samples(), 16);
+        let angles: Vec<f64> = (0..16)
+            .map(|index| {
+                let sample = soft.illuminate(Vector3::zero(), sampling::lattice_point(index, 16));
+                assert_eq!(sample.color.x, 1.0);
+                (-sample.direction.dot(soft.direction)).min(1.0).acos()
+            })
+            .collect();
+        assert!(angles.iter().all(|&angle| angle <= radius * (1.0 + 1e-6)));
+        assert!(angles.iter().any(|&angle| angle > 0.5 * radius));
+    }
+
+    #[test]
+    fn area_light_samples_match_hits() {
+        // A 2 x 1 light 3 meters up, facing down.
+        let rotation = UnitQuaternion::from_axis_angle((1.0, 0.0, 0.0), PI);
+        let light =
+            AreaLight::new(2.0, 1.0, (1.0, 1.0, 1.0)).with_transform(rotation, (0.0, 0.0, 3.0));
+
+        let middle = light.illuminate(Vector3::zero(), (0.0, 0.0));
+        assert!((middle.direction - Vector3::k()).norm() < 1e-12);
+        assert!((middle.distance - 3.0).abs() < 1e-12);
+        assert!((middle.pdf - 9.0 / 2.0).abs() < 1e-12);
+
+        let sample = light.illuminate(Vector3::zero(), (0.8, 0.3));
+        let hit = light
+            .hit(&Ray::new(Vector3::zero(), sample.direction))
+            .unwrap();
+        assert!((hit.distance - sample.distance).abs() < 1e-9);
+        assert!((hit.pdf - sample.pdf).abs() < 1e-9);
+
+        // The back of the light is dark.
+        let above = Vector3::from((0.0, 0.0, 4.0));
+        assert_eq!(light.illuminate(above, (0.0, 0.0)).color.x, 0.0);
+        assert!(light.hit(&Ray::new(above, -Vector3::k())).is_none());
+    }
+
+    #[test]
+    fn lights_survive_serialization() {
+        fn round_trip<L: Light + Serialize + for<'de> Deserialize<'de>>(light: &L) {
+            let source = ron::to_string(light).unwrap();
+            let found: L = ron::from_str(&source).unwrap();
+            let mut hashers = (ContentHasher::new(), ContentHasher::new());
+            light.hash_content(&mut hashers.0);
+            found.hash_content(&mut hashers.1);
+            assert_eq!(hashers.0.finish(), hashers.1.finish());
+        }
+
+        let rotation = UnitQuaternion::from_axis_angle((1.0, 0.0, 0.0), PI);
+        round_trip(&Sun::golden_hour().with_angular_radius(SUN_ANGULAR_RADIUS));
+        round_trip(&PointLight::incandescent_60w((0.0, 0.0, 2.0)));
+        round_trip(&SphereLight::new((1.0, 2.0, 3.0), 0.1, (5.0, 4.0, 3.0)));
+        round_trip(
+            &AreaLight::new(2.0, 1.0, (1.0, 1.0, 1.0))
+                .with_transform(rotation, (0.0, 0.0, 3.0))
+                .with_samples(4),
+        );
+    }
+
+    #[test]
+    fn golden_hour_is_warmer_and_dimmer_than_noon() {
+        let (noon, golden_hour) = (Sun::noon().color, Sun::golden_hour().color);
+        assert!(golden_hour.x / golden_hour.z > noon.x / noon.z);
+        let brightness = |color: Vector3| 0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z;
+        assert!(brightness(golden_hour) < brightness(noon));
+    }
 }
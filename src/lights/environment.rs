@@ -0,0 +1,349 @@
+//! Module containing environment maps: light arriving from every direction,
+//! infinitely far away, given by an equirectangular HDR image.
+//!
+//! The map is importance sampled, so that bright parts, like the sun in an
+//! outdoor map or the softboxes in a studio map, are sampled more often.
+//! Directions are chosen by first choosing a row of the image, with a
+//! probability proportional to its total weight, and then a texel in the row.
+//! The weight of a texel is its luminance times the solid angle it covers,
+//! which shrinks towards the poles.
+
+use super::{Illumination, Light, LightHit};
+use crate::hashing::ContentHasher;
+use crate::image;
+use crate::math::{Ray, UnitQuaternion, Vector3};
+use std::error::Error;
+use std::f64::consts::PI;
+use std::f64::INFINITY;
+use std::path::Path;
+
+/// Light arriving from all directions, given by an image in the
+/// equirectangular projection, where the columns are longitudes and the rows
+/// latitudes. The top of the image is up along the z-axis, and the middle of
+/// the image is along the y-axis, in front of the default camera. Seen from
+/// inside, the image isn't mirrored.
+pub struct EnvironmentMap {
+    width: usize,
+    height: usize,
+    /// The radiance of the texels, in linear RGB, row by row from the top.
+    texels: Vec<[f32; 3]>,
+    /// The cumulative distribution of choosing each row, from 0 to 1.
+    row_cdf: Vec<f64>,
+    /// The cumulative distribution of choosing each texel within its row, for
+    /// each row, from 0 to 1.
+    column_cdfs: Vec<f32>,
+    intensity: f64,
+    rotation: UnitQuaternion,
+    samples: u32,
+}
+
+impl EnvironmentMap {
+    /// Make an environment map from an image of size `width` x `height`, given
+    /// by the radiance of its texels in linear RGB, row by row from the top.
+    /// It is sampled 16 times at each point.
+    pub fn new(width: usize, height: usize, texels: &[Vector3]) -> Result<Self, Box<dyn Error>> {
+        if width == 0 || height == 0 || texels.len() != width * height {
+            return Err("Invalid environment map size".into());
+        }
+        if !texels.iter().all(|texel| texel.is_finite()) {
+            return Err("Environment map with infinite or NaN radiance".into());
+        }
+
+        let texels: Vec<[f32; 3]> = texels
+            .iter()
+            .map(|texel| [texel.x as f32, texel.y as f32, texel.z as f32])
+            .collect();
+
+        let mut row_cdf = Vec::with_capacity(height + 1);
+        let mut column_cdfs = Vec::with_capacity(height * (width + 1));
+        row_cdf.push(0.0);
+        for (row, row_texels) in texels.chunks(width).enumerate() {
+            let sin_theta = (PI * (row as f64 + 0.5) / height as f64).sin();
+            let weights: Vec<f64> = row_texels
+                .iter()
+                .map(|&[r, g, b]| {
+                    let rgb = Vector3::from((f64::from(r), f64::from(g), f64::from(b)));
+                    sin_theta * luminance(rgb).max(0.0)
+                })
+                .collect();
+            let row_weight: f64 = weights.iter().sum();
+
+            // Rows without light are never chosen, and are given a uniform
+            // distribution all the same.
+            let mut sum = 0.0;
+            column_cdfs.push(0.0);
+            for (column, weight) in weights.iter().enumerate() {
+                sum += weight;
+                column_cdfs.push(if row_weight > 0.0 {
+                    (sum / row_weight) as f32
+                } else {
+                    (column + 1) as f32 / width as f32
+                });
+            }
+            row_cdf.push(row_cdf[row] + row_weight);
+        }
+        let total_weight = row_cdf[height];
+        for value in row_cdf.iter_mut() {
+            *value = if total_weight > 0.0 {
+                *value / total_weight
+            } else {
+                0.0
+            };
+        }
+
+        Ok(Self {
+            width,
+            height,
+            texels,
+            row_cdf,
+            column_cdfs,
+            intensity: 1.0,
+            rotation: UnitQuaternion::id(),
+            samples: 16,
+        })
+    }
+
+    /// Make an environment map of size `width` x `height` with the radiance
+    /// `radiance(direction)` in the middle of each texel.
+    pub(super) fn from_radiance(
+        width: usize,
+        height: usize,
+        radiance: impl Fn(Vector3) -> Vector3,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut texels = Vec::with_capacity(width * height);
+        for row in 0..height {
+            for column in 0..width {
+                let u = (column as f64 + 0.5) / width as f64;
+                let v = (row as f64 + 0.5) / height as f64;
+                texels.push(radiance(image_to_direction((u, v))));
+            }
+        }
+        Self::new(width, height, &texels)
+    }
+
+    /// Load an environment map from the Radiance HDR image `path`.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let (width, height, texels) = image::load_hdr(path)?;
+        Self::new(width, height, &texels)
+    }
+
+    /// Scale the radiance of the map by `intensity`.
+    pub fn with_intensity(mut self, intensity: f64) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    /// Turn the map by `rotation`, e.g. around the z-axis to move the sun.
+    pub fn with_rotation(mut self, rotation: UnitQuaternion) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    /// Sample the map `samples` times at each point. More samples give
+    /// smoother shadows, at the cost of more shadow rays.
+    pub fn with_samples(mut self, samples: u32) -> Self {
+        self.samples = samples.max(1);
+        self
+    }
+
+    /// Find the point in the image, with coordinates from 0 to 1, seen in the
+    /// unit `direction`.
+    fn direction_to_image(&self, direction: Vector3) -> (f64, f64) {
+        let direction = direction.rotate(self.rotation.invert());
+        let phi = direction.y.atan2(direction.x);
+        let theta = direction.z.clamp(-1.0, 1.0).acos();
+        ((0.75 - phi / (2.0 * PI)).rem_euclid(1.0), theta / PI)
+    }
+
+    /// Find the direction seen at a point in the image, with coordinates from
+    /// 0 to 1.
+    fn image_to_direction(&self, point: (f64, f64)) -> Vector3 {
+        image_to_direction(point).rotate(self.rotation)
+    }
+
+    /// Find the row and column of the texel at a point in the image.
+    fn texel_at(&self, (u, v): (f64, f64)) -> (usize, usize) {
+        let row = ((v * self.height as f64) as usize).min(self.height - 1);
+        let column = ((u * self.width as f64) as usize).min(self.width - 1);
+        (row, column)
+    }
+
+    /// Find the radiance arriving from the unit `direction`. It is constant
+    /// over each texel, like the probability density of sampling it, which
+    /// keeps bright, small texels from giving noise around them.
+    fn radiance(&self, direction: Vector3) -> Vector3 {
+        let (row, column) = self.texel_at(self.direction_to_image(direction));
+        let [r, g, b] = self.texels[row * self.width + column];
+        self.intensity * Vector3::from((f64::from(r), f64::from(g), f64::from(b)))
+    }
+
+    /// Find the probability density, over solid angle, of sampling the unit
+    /// `direction`.
+    fn pdf(&self, direction: Vector3) -> f64 {
+        let (u, v) = self.direction_to_image(direction);
+        let sin_theta = (PI * v).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+
+        let (row, column) = self.texel_at((u, v));
+        let row_probability = self.row_cdf[row + 1] - self.row_cdf[row];
+        let column_cdf = &self.column_cdfs[row * (self.width + 1)..(row + 1) * (self.width + 1)];
+        let column_probability = f64::from(column_cdf[column + 1] - column_cdf[column]);
+
+        // The density over the image, divided by the area on the unit sphere
+        // per area in the image.
+        let image_pdf = row_probability * column_probability * (self.width * self.height) as f64;
+        image_pdf / (2.0 * PI * PI * sin_theta)
+    }
+
+    /// Choose a point in the image from the uniformly distributed `u`, with a
+    /// probability density proportional to the weights of the texels.
+    fn sample_image(&self, u: (f64, f64)) -> (f64, f64) {
+        let (row, v) = sample_cdf(&self.row_cdf, u.0);
+        let column_cdf = &self.column_cdfs[row * (self.width + 1)..(row + 1) * (self.width + 1)];
+        let (_, u) = sample_cdf(column_cdf, u.1);
+        (u, v)
+    }
+}
+
+impl Light for EnvironmentMap {
+    fn illuminate(&self, _point: Vector3, u: (f64, f64)) -> Illumination {
+        let direction = self.image_to_direction(self.sample_image(u));
+        let pdf = self.pdf(direction);
+        if pdf <= 0.0 {
+            // A black map, or a sample at a pole.
+            return Illumination {
+                direction: Vector3::k(),
+                distance: INFINITY,
+                color: Vector3::zero(),
+                pdf: INFINITY,
+            };
+        }
+
+        Illumination {
+            direction,
+            distance: INFINITY,
+            color: self.radiance(direction) * (1.0 / (PI * pdf)),
+            pdf,
+        }
+    }
+
+    fn hit(&self, ray: &Ray) -> Option<LightHit> {
+        Some(LightHit {
+            distance: INFINITY,
+            radiance: self.radiance(ray.direction),
+            pdf: self.pdf(ray.direction),
+        })
+    }
+
+    fn samples(&self) -> u32 {
+        self.samples
+    }
+
+    fn hash_content(&self, hasher: &mut ContentHasher) {
+        hasher.write_str("EnvironmentMap");
+        hasher.write_u64(self.width as u64);
+        hasher.write_u64(self.height as u64);
+        for &[r, g, b] in &self.texels {
+            hasher.write_f64(f64::from(r));
+            hasher.write_f64(f64::from(g));
+            hasher.write_f64(f64::from(b));
+        }
+        hasher.write_f64(self.intensity);
+        hasher.write_vector(Vector3::i().rotate(self.rotation));
+        hasher.write_vector(Vector3::j().rotate(self.rotation));
+        hasher.write_u64(u64::from(self.samples));
+    }
+}
+
+/// Find the direction seen at a point in an unturned map, with coordinates
+/// from 0 to 1.
+fn image_to_direction((u, v): (f64, f64)) -> Vector3 {
+    let phi = 2.0 * PI * (0.75 - u);
+    let theta = PI * v;
+    let (sin_theta, cos_theta) = theta.sin_cos();
+    Vector3::from((sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta))
+}
+
+/// Choose an interval of the cumulative distribution `cdf` with the uniformly
+/// distributed `u`, and find where in the interval `u` falls. Returns the
+/// index of the interval, and the position as a fraction of the whole range,
+/// from 0 to 1.
+fn sample_cdf<T: Copy + Into<f64>>(cdf: &[T], u: f64) -> (usize, f64) {
+    let intervals = cdf.len() - 1;
+    // The last interval with its start at or before `u`, skipping empty ones.
+    let index = (cdf.partition_point(|&value| value.into() <= u).max(1) - 1).min(intervals - 1);
+    let (start, end) = (cdf[index].into(), cdf[index + 1].into());
+    let width = end - start;
+    let offset = if width > 0.0 {
+        ((u - start) / width).clamp(0.0, 1.0)
+    } else {
+        0.5
+    };
+    (index, (index as f64 + offset) / intervals as f64)
+}
+
+/// Find the luminance of a color in linear RGB.
+fn luminance(rgb: Vector3) -> f64 {
+    0.2126 * rgb.x + 0.7152 * rgb.y + 0.0722 * rgb.z
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::sampling;
+
+    /// A dim gray map, with a bright spot in front of the default camera,
+    /// and turned a little.
+    fn spotted_map() -> EnvironmentMap {
+        let (width, height) = (64, 32);
+        let mut texels = vec![Vector3::from((0.1, 0.1, 0.1)); width * height];
+        texels[12 * width + 32] = Vector3::from((1000.0, 900.0, 800.0));
+        EnvironmentMap::new(width, height, &texels)
+            .unwrap()
+            .with_rotation(UnitQuaternion::from_axis_angle((0.0, 0.0, 1.0), 0.3))
+    }
+
+    #[test]
+    fn samples_match_hits() {
+        let map = spotted_map();
+        let point = Vector3::from((1.0, 2.0, 3.0));
+        for index in 0..64 {
+            let sample = map.illuminate(point, sampling::lattice_point(index, 64));
+            let hit = map.hit(&Ray::new(point, sample.direction)).unwrap();
+            assert!(sample.distance.is_infinite() && hit.distance.is_infinite());
+            assert!((sample.pdf - hit.pdf).abs() <= 1e-9 * hit.pdf);
+            assert!((sample.color * (PI * sample.pdf) - hit.radiance).norm() < 1e-9);
+        }
+
+        // The bright spot is sampled far more often than its size, and it is
+        // in front, slightly above the horizon.
+        let bright = (0..64)
+            .map(|index| map.illuminate(point, sampling::lattice_point(index, 64)))
+            .filter(|sample| sample.color.x > 1.0)
+            .count();
+        assert!(bright > 32);
+        let spot = map.image_to_direction((32.5 / 64.0, 12.5 / 32.0));
+        assert!(spot.rotate(map.rotation.invert()).y > 0.9 && spot.z > 0.0);
+    }
+
+    #[test]
+    fn uniform_map_gives_its_radiance_as_irradiance() {
+        // A white, diffuse surface lit by a uniform environment reflects its
+        // radiance, whichever way it faces.
+        let map = EnvironmentMap::new(16, 8, &[Vector3::from((0.5, 0.5, 0.5)); 16 * 8]).unwrap();
+        for normal in [Vector3::k(), Vector3::from((1.0, 1.0, 0.0)).normalize()] {
+            let count = 20_000;
+            let reflected: f64 = (0..count)
+                .map(|index| {
+                    let sample =
+                        map.illuminate(Vector3::zero(), sampling::lattice_point(index, count));
+                    normal.dot(sample.direction).max(0.0) * sample.color.x
+                })
+                .sum::<f64>()
+                / f64::from(count);
+            assert!((reflected - 0.5).abs() < 0.01, "{}", reflected);
+        }
+    }
+}
@@ -0,0 +1,358 @@
+//! Module containing a procedural sky: daylight from a clear or hazy sky, with
+//! the sun, given by the position of the sun and the turbidity of the air.
+//!
+//! The sky follows the analytic model of Preetham, Shirley and Smits, "A
+//! Practical Analytic Model for Daylight" (1999). The luminance and
+//! chromaticity of each direction are the values at the zenith, scaled by the
+//! Perez function of the angle from the zenith and the angle from the sun. The
+//! sun is a disc with the luminance of the sun outside the atmosphere,
+//! attenuated by Rayleigh scattering by the air and Mie scattering by haze,
+//! which makes it redder towards the horizon.
+//!
+//! The sky is tabulated into an `EnvironmentMap`, which is importance sampled,
+//! while the sun, far smaller than a texel, is sampled separately. Below the
+//! horizon, the sky is black, so outdoor scenes need a ground.
+
+use super::{EnvironmentMap, Illumination, Light, LightHit, SUN_ANGULAR_RADIUS};
+use crate::hashing::ContentHasher;
+use crate::image::{blackbody_color, xyz_to_linear};
+use crate::math::{sampling, Ray, Vector3};
+use std::f64::consts::{FRAC_PI_2, PI};
+use std::f64::INFINITY;
+
+/// The radiance of a luminance of 1 kcd/m², so that a white surface facing
+/// the sun high in a clear sky reflects about 1, like it does for `Sun::noon`.
+const LUMINANCE_SCALE: f64 = 1.0 / 30.0;
+/// The illuminance from the sun outside the atmosphere, in klx.
+const SOLAR_ILLUMINANCE: f64 = 127.5;
+/// The wavelengths, in micrometers, that the red, green and blue light of the
+/// sun is attenuated at.
+const WAVELENGTHS: [f64; 3] = [0.61, 0.55, 0.465];
+/// The size of the table of the sky.
+const SKY_WIDTH: usize = 256;
+const SKY_HEIGHT: usize = 128;
+/// The range of turbidity that the model is fitted for.
+const MIN_TURBIDITY: f64 = 1.7;
+const MAX_TURBIDITY: f64 = 10.0;
+
+/// The coefficients of the Perez function for the luminance and the x and y
+/// chromaticity, as linear functions of turbidity: the slopes and the
+/// intercepts of A to E.
+const PEREZ: [[[f64; 2]; 5]; 3] = [
+    [
+        [0.1787, -1.4630],
+        [-0.3554, 0.4275],
+        [-0.0227, 5.3251],
+        [0.1206, -2.5771],
+        [-0.0670, 0.3703],
+    ],
+    [
+        [-0.0193, -0.2592],
+        [-0.0665, 0.0008],
+        [-0.0004, 0.2125],
+        [-0.0641, -0.8989],
+        [-0.0033, 0.0452],
+    ],
+    [
+        [-0.0167, -0.2608],
+        [-0.0950, 0.0092],
+        [-0.0079, 0.2102],
+        [-0.0441, -1.6537],
+        [-0.0109, 0.0529],
+    ],
+];
+/// The x and y chromaticity at the zenith, as polynomials in the square of the
+/// turbidity, the turbidity and 1 (the rows), and the cube, the square, the
+/// first power and 1 of the angle of the sun from the zenith (the columns).
+const ZENITH_CHROMATICITY: [[[f64; 4]; 3]; 2] = [
+    [
+        [0.00166, -0.00375, 0.00209, 0.0],
+        [-0.02903, 0.06377, -0.03202, 0.00394],
+        [0.11693, -0.21196, 0.06052, 0.25886],
+    ],
+    [
+        [0.00275, -0.00610, 0.00317, 0.0],
+        [-0.04214, 0.08970, -0.04153, 0.00516],
+        [0.15346, -0.26756, 0.06670, 0.26688],
+    ],
+];
+
+/// Daylight from a procedural sky and sun. It is sampled 16 times at each
+/// point, with each sample taken from the sun or the sky with equal
+/// probability, while the sun is up.
+pub struct PhysicalSky {
+    sun_elevation: f64,
+    sun_azimuth: f64,
+    turbidity: f64,
+    intensity: f64,
+    sky: EnvironmentMap,
+    /// The unit vector pointing towards the sun.
+    sun_direction: Vector3,
+    /// The radiance of the disc of the sun, in linear RGB.
+    sun_radiance: Vector3,
+    samples: u32,
+}
+
+impl PhysicalSky {
+    /// Make a sky with the sun `sun_elevation` radians above the horizon, up to
+    /// π/2 at the zenith, and turned `sun_azimuth` radians from the y-axis, in
+    /// front of the default camera, towards the x-axis, to its right.
+    /// `turbidity` is how hazy the air is, from about 2 for a clear sky to 10
+    /// for a hazy one, and is clamped to the range that the model is made for.
+    pub fn new(sun_elevation: f64, sun_azimuth: f64, turbidity: f64) -> Self {
+        let sun_elevation = sun_elevation.clamp(0.0, FRAC_PI_2);
+        let turbidity = turbidity.clamp(MIN_TURBIDITY, MAX_TURBIDITY);
+        let (sin_elevation, cos_elevation) = sun_elevation.sin_cos();
+        let (sin_azimuth, cos_azimuth) = sun_azimuth.sin_cos();
+        let sun_direction = Vector3::from((
+            cos_elevation * sin_azimuth,
+            cos_elevation * cos_azimuth,
+            sin_elevation,
+        ));
+
+        let sun_zenith = FRAC_PI_2 - sun_elevation;
+        let sky = EnvironmentMap::from_radiance(SKY_WIDTH, SKY_HEIGHT, |direction| {
+            sky_radiance(direction, sun_direction, sun_zenith, turbidity)
+        })
+        .expect("The sky has a valid size and finite radiance")
+        .with_samples(1);
+
+        Self {
+            sun_elevation,
+            sun_azimuth,
+            turbidity,
+            intensity: 1.0,
+            sky,
+            sun_direction,
+            sun_radiance: sun_radiance(sun_zenith, turbidity),
+            samples: 16,
+        }
+    }
+
+    /// Scale the radiance of the sky and the sun by `intensity`.
+    pub fn with_intensity(mut self, intensity: f64) -> Self {
+        self.intensity = intensity;
+        self.sky = self.sky.with_intensity(intensity);
+        self
+    }
+
+    /// Sample the sky `samples` times at each point. More samples give
+    /// smoother shadows, at the cost of more shadow rays.
+    pub fn with_samples(mut self, samples: u32) -> Self {
+        self.samples = samples.max(1);
+        self
+    }
+
+    /// The probability of sampling the sun rather than the sky.
+    fn sun_probability(&self) -> f64 {
+        if self.sun_radiance.norm2() > 0.0 {
+            0.5
+        } else {
+            0.0
+        }
+    }
+
+    /// Find the radiance of the sun seen in the unit `direction`, which is
+    /// zero outside its disc.
+    fn sun_radiance_towards(&self, direction: Vector3) -> Vector3 {
+        if direction.dot(self.sun_direction) >= SUN_ANGULAR_RADIUS.cos() {
+            self.intensity * self.sun_radiance
+        } else {
+            Vector3::zero()
+        }
+    }
+
+    /// Find the probability density, over solid angle, of sampling the unit
+    /// `direction` from the sun.
+    fn sun_pdf(&self, direction: Vector3) -> f64 {
+        if direction.dot(self.sun_direction) >= SUN_ANGULAR_RADIUS.cos() {
+            sampling::uniform_cone_pdf(SUN_ANGULAR_RADIUS.cos())
+        } else {
+            0.0
+        }
+    }
+}
+
+impl Light for PhysicalSky {
+    fn illuminate(&self, point: Vector3, u: (f64, f64)) -> Illumination {
+        // The first number chooses between the sun and the sky, and is then
+        // reused for sampling the one chosen.
+        let sun_probability = self.sun_probability();
+        let direction = if u.0 < sun_probability {
+            let u = (u.0 / sun_probability, u.1);
+            let cone = sampling::uniform_cone(u, SUN_ANGULAR_RADIUS.cos());
+            sampling::orient_along(cone, self.sun_direction)
+        } else {
+            let u = ((u.0 - sun_probability) / (1.0 - sun_probability), u.1);
+            self.sky.illuminate(point, u).direction
+        };
+
+        match self.hit(&Ray::new(point, direction)) {
+            Some(hit) if hit.pdf > 0.0 => Illumination {
+                direction,
+                distance: INFINITY,
+                color: hit.radiance * (1.0 / (PI * hit.pdf)),
+                pdf: hit.pdf,
+            },
+            // Only at the poles of the sky, where it has no area.
+            _ => Illumination {
+                direction: Vector3::k(),
+                distance: INFINITY,
+                color: Vector3::zero(),
+                pdf: INFINITY,
+            },
+        }
+    }
+
+    fn glare(&self, _point: Vector3) -> Option<Illumination> {
+        if self.sun_radiance.norm2() == 0.0 {
+            return None;
+        }
+        let pdf = sampling::uniform_cone_pdf(SUN_ANGULAR_RADIUS.cos());
+        Some(Illumination {
+            direction: self.sun_direction,
+            distance: INFINITY,
+            color: self.intensity * self.sun_radiance * (1.0 / (PI * pdf)),
+            pdf: INFINITY,
+        })
+    }
+
+    fn hit(&self, ray: &Ray) -> Option<LightHit> {
+        let sky = self.sky.hit(ray)?;
+        let sun_probability = self.sun_probability();
+        Some(LightHit {
+            distance: INFINITY,
+            radiance: sky.radiance + self.sun_radiance_towards(ray.direction),
+            pdf: sun_probability * self.sun_pdf(ray.direction) + (1.0 - sun_probability) * sky.pdf,
+        })
+    }
+
+    fn samples(&self) -> u32 {
+        self.samples
+    }
+
+    fn hash_content(&self, hasher: &mut ContentHasher) {
+        hasher.write_str("PhysicalSky");
+        hasher.write_f64(self.sun_elevation);
+        hasher.write_f64(self.sun_azimuth);
+        hasher.write_f64(self.turbidity);
+        hasher.write_f64(self.intensity);
+        hasher.write_u64(u64::from(self.samples));
+    }
+}
+
+/// Find the radiance of the sky, in linear RGB, seen in the unit `direction`,
+/// with the sun in the unit `sun_direction`, `sun_zenith` radians from the
+/// zenith, and the air of `turbidity`.
+fn sky_radiance(
+    direction: Vector3,
+    sun_direction: Vector3,
+    sun_zenith: f64,
+    turbidity: f64,
+) -> Vector3 {
+    if direction.z <= 0.0 {
+        return Vector3::zero();
+    }
+    let zenith = direction.z.min(1.0).acos();
+    let gamma = direction.dot(sun_direction).clamp(-1.0, 1.0).acos();
+
+    // The luminance at the zenith, in kcd/m².
+    let chi = (4.0 / 9.0 - turbidity / 120.0) * (PI - 2.0 * sun_zenith);
+    let zenith_luminance = (4.0453 * turbidity - 4.9710) * chi.tan() - 0.2155 * turbidity + 2.4192;
+    let zenith_chromaticity = ZENITH_CHROMATICITY.map(|matrix| {
+        let turbidity_powers = [turbidity * turbidity, turbidity, 1.0];
+        let angle_powers = [sun_zenith.powi(3), sun_zenith.powi(2), sun_zenith, 1.0];
+        (0..3)
+            .map(|row| {
+                (0..4)
+                    .map(|column| {
+                        turbidity_powers[row] * matrix[row][column] * angle_powers[column]
+                    })
+                    .sum::<f64>()
+            })
+            .sum::<f64>()
+    });
+
+    let [luminance, x, y] = [
+        (0, zenith_luminance),
+        (1, zenith_chromaticity[0]),
+        (2, zenith_chromaticity[1]),
+    ]
+    .map(|(index, zenith_value)| {
+        let [a, b, c, d, e] = PEREZ[index].map(|[slope, intercept]| slope * turbidity + intercept);
+        let perez = |zenith: f64, gamma: f64| {
+            (1.0 + a * (b / zenith.cos()).exp())
+                * (1.0 + c * (d * gamma).exp() + e * gamma.cos().powi(2))
+        };
+        zenith_value * perez(zenith, gamma) / perez(0.0, sun_zenith)
+    });
+
+    let luminance = luminance.max(0.0) * LUMINANCE_SCALE;
+    xyz_to_linear(x / y * luminance, luminance, (1.0 - x - y) / y * luminance).max(Vector3::zero())
+}
+
+/// Find the radiance of the disc of the sun, in linear RGB, `sun_zenith`
+/// radians from the zenith, seen through the air of `turbidity`.
+fn sun_radiance(sun_zenith: f64, turbidity: f64) -> Vector3 {
+    // The relative length of the path through the air, which is 1 at the
+    // zenith, from the fit by Kasten and Young.
+    let degrees = sun_zenith.to_degrees();
+    let air_mass = 1.0 / (sun_zenith.cos() + 0.50572 * (96.07995 - degrees).powf(-1.6364));
+
+    // Ångström's formula for haze, with the coefficient fitted to turbidity by
+    // Preetham et al.
+    let beta = 0.04608 * turbidity - 0.04586;
+    let [red, green, blue] = WAVELENGTHS.map(|wavelength: f64| {
+        let rayleigh = 0.008_735 * wavelength.powf(-4.08);
+        let mie = beta * wavelength.powf(-1.3);
+        (-(rayleigh + mie) * air_mass).exp()
+    });
+
+    let solid_angle = 2.0 * PI * (1.0 - SUN_ANGULAR_RADIUS.cos());
+    let luminance = SOLAR_ILLUMINANCE / solid_angle * LUMINANCE_SCALE;
+    (luminance * blackbody_color(5_800.0)).elementwise_mul(Vector3::from((red, green, blue)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_match_hits() {
+        let sky = PhysicalSky::new(0.5, 1.0, 3.0);
+        let point = Vector3::zero();
+        let count = 256;
+        let mut from_sun = 0;
+        for index in 0..count {
+            let sample = sky.illuminate(point, sampling::lattice_point(index, count));
+            let hit = sky.hit(&Ray::new(point, sample.direction)).unwrap();
+            assert!((sample.pdf - hit.pdf).abs() <= 1e-9 * hit.pdf);
+            assert!(
+                (sample.color * (PI * sample.pdf) - hit.radiance).norm()
+                    <= 1e-9 * hit.radiance.norm()
+            );
+            if sample.direction.dot(sky.sun_direction) >= SUN_ANGULAR_RADIUS.cos() {
+                from_sun += 1;
+            }
+        }
+        assert!(from_sun >= count / 2);
+    }
+
+    #[test]
+    fn sky_is_blue_and_sunset_is_red() {
+        let noon = PhysicalSky::new(1.4, 0.0, 2.5);
+        let zenith = noon.hit(&Ray::new(Vector3::zero(), Vector3::k())).unwrap();
+        assert!(zenith.radiance.z > zenith.radiance.x);
+        let below = noon.hit(&Ray::new(Vector3::zero(), -Vector3::k())).unwrap();
+        assert_eq!(below.radiance.norm(), 0.0);
+
+        // A white surface facing the sun at noon reflects about 1 from it.
+        let sun =
+            noon.sun_radiance * (1.0 / (PI * sampling::uniform_cone_pdf(SUN_ANGULAR_RADIUS.cos())));
+        assert!((0.6..1.2).contains(&sun.y), "{}", sun.y);
+
+        let sunset = PhysicalSky::new(0.02, 0.0, 2.5);
+        assert!(sunset.sun_radiance.x > 2.0 * sunset.sun_radiance.z);
+        assert!(sunset.sun_radiance.y < noon.sun_radiance.y);
+    }
+}
@@ -0,0 +1,801 @@
+//! Module containing materials, which determine how surfaces are shaded.
+
+use crate::hashing::ContentHasher;
+use crate::image::ColorSpace;
+use crate::math::sampling::{self, Rng};
+use crate::math::Vector3;
+use crate::scene::{Shader, ShadingContext};
+use crate::scene_file::MaterialDescription;
+use crate::textures::{Constant, Converted, Texture};
+use std::collections::BTreeMap;
+use std::f64::consts::PI;
+use std::f64::INFINITY;
+
+/// A `Material` describes the appearance of a surface.
+pub struct Material {
+    /// The color of the surface.
+    albedo: Box<dyn Texture + Send + Sync>,
+    /// An optional normal map in tangent space, perturbing the shading normal.
+    normal_map: Option<Box<dyn Texture + Send + Sync>>,
+    /// How rough the metallic part of the surface is, from 0 (a perfect
+    /// mirror) to 1.
+    roughness: f64,
+    /// How much rougher the surface is along the tangent than along the
+    /// bitangent, from -1 to 1. 0 is the same roughness in all directions.
+    anisotropy: f64,
+    /// How metallic the surface is, from 0 (diffuse) to 1 (glossy metal).
+    metallic: f64,
+    /// How transparent the surface is, from 0 (opaque) to 1 (clear, like
+    /// glass).
+    transmission: f64,
+    /// The index of refraction of the transparent part of the surface.
+    ior: f64,
+    /// Whether the surface only catches shadows and reflections, for
+    /// compositing.
+    shadow_catcher: bool,
+    /// A custom shader, used instead of the BSDF for shading.
+    shader: Option<Box<Shader>>,
+}
+
+impl Default for Material {
+    /// Make a plain, white material.
+    fn default() -> Self {
+        Self::new(Constant::new(Vector3::ones()))
+    }
+}
+
+impl Material {
+    /// Make a material colored by the `albedo` texture.
+    pub fn new(albedo: impl Texture + Send + Sync + 'static) -> Self {
+        Self {
+            albedo: Box::new(albedo),
+            normal_map: None,
+            roughness: 0.5,
+            anisotropy: 0.0,
+            metallic: 0.0,
+            transmission: 0.0,
+            ior: 1.5,
+            shadow_catcher: false,
+            shader: None,
+        }
+    }
+
+    /// Set the roughness of the material, from 0 (a perfect mirror) to 1. The
+    /// roughness only affects the metallic part of the material.
+    pub fn with_roughness(mut self, roughness: f64) -> Self {
+        self.roughness = roughness.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Set the anisotropy of the material, from -1 to 1. Positive values
+    /// stretch highlights along the tangent of the surface, and negative values
+    /// along the bitangent, like on brushed metal, which is brushed across the
+    /// highlights. 0, the default, gives round highlights.
+    pub fn with_anisotropy(mut self, anisotropy: f64) -> Self {
+        self.anisotropy = anisotropy.clamp(-1.0, 1.0);
+        self
+    }
+
+    /// Set how metallic the material is. A metallic material reflects glossily,
+    /// tinted by the albedo, while a non-metallic one reflects diffusely.
+    /// Values between 0 and 1 blend between the two.
+    pub fn with_metallic(mut self, metallic: f64) -> Self {
+        self.metallic = metallic.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Set how transparent the material is. The transparent part of the
+    /// material is a smooth dielectric, like glass or water, which reflects
+    /// and refracts light perfectly specularly, tinted by the albedo. Values
+    /// between 0 and 1 blend between it and the opaque material.
+    pub fn with_transmission(mut self, transmission: f64) -> Self {
+        self.transmission = transmission.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Set the index of refraction of the transparent part of the material.
+    /// The default is 1.5, which is typical for glass.
+    pub fn with_ior(mut self, ior: f64) -> Self {
+        self.ior = ior;
+        self
+    }
+
+    /// Make the material a shadow catcher, for compositing rendered objects
+    /// onto photographs. Seen by the camera, a shadow catcher is transparent,
+    /// except for the shadows and the mirror reflections it receives from
+    /// other surfaces. Otherwise, e.g. in reflections and when bouncing light,
+    /// it is the material it would be without the flag, which should match
+    /// the ground in the photograph.
+    pub fn with_shadow_catcher(mut self, shadow_catcher: bool) -> Self {
+        self.shadow_catcher = shadow_catcher;
+        self
+    }
+
+    /// Add a tangent-space normal map to the material. The red, green and blue
+    /// channels of the texture, mapped from [0, 1] to [-1, 1], are the
+    /// components of the normal along the tangent, the bitangent and the
+    /// geometric normal of the surface, respectively. A normal map is data, so
+    /// it is never color converted.
+    pub fn with_normal_map(mut self, normal_map: impl Texture + Send + Sync + 'static) -> Self {
+        self.normal_map = Some(Box::new(normal_map));
+        self
+    }
+
+    /// Shade the material with `shader` instead of its BSDF, for prototyping
+    /// shading models. The shader is given a `crate::scene::ShadingContext`
+    /// for the point hit, and returns the color leaving the point towards the
+    /// ray. The rest of the material is still used for shadows and photons.
+    /// Scenes with shaders can't be hashed.
+    pub fn with_shader(
+        mut self,
+        shader: impl Fn(&mut ShadingContext) -> Vector3 + Send + Sync + 'static,
+    ) -> Self {
+        self.shader = Some(Box::new(shader));
+        self
+    }
+
+    /// Interpret the colors of the albedo texture as being in `color_space`.
+    pub(crate) fn convert_albedo(mut self, color_space: ColorSpace) -> Self {
+        if color_space != ColorSpace::Linear {
+            self.albedo = Box::new(Converted::from_boxed(self.albedo, color_space));
+        }
+        self
+    }
+
+    /// Write the textures and parameters of the material to `hasher`.
+    pub fn hash_content(&self, hasher: &mut ContentHasher) {
+        self.albedo.hash_content(hasher);
+        match &self.normal_map {
+            None => hasher.write_str("None"),
+            Some(normal_map) => normal_map.hash_content(hasher),
+        }
+        hasher.write_f64(self.roughness);
+        hasher.write_f64(self.anisotropy);
+        hasher.write_f64(self.metallic);
+        hasher.write_f64(self.transmission);
+        hasher.write_f64(self.ior);
+        hasher.write_u64(u64::from(self.shadow_catcher));
+        if self.shader.is_some() {
+            hasher.write_unknown();
+        }
+    }
+
+    /// Describe the material as in scene files, or return `None` if it has a
+    /// shader or a texture that scene files can't describe.
+    pub fn describe(&self) -> Option<MaterialDescription> {
+        if self.shader.is_some() {
+            return None;
+        }
+        Some(MaterialDescription {
+            albedo: self.albedo.describe()?,
+            normal_map: match &self.normal_map {
+                None => None,
+                Some(normal_map) => Some(normal_map.describe()?),
+            },
+            roughness: self.roughness,
+            anisotropy: self.anisotropy,
+            metallic: self.metallic,
+            transmission: self.transmission,
+            ior: self.ior,
+            shadow_catcher: self.shadow_catcher,
+        })
+    }
+
+    /// The custom shader of the material, if it has one.
+    pub fn shader(&self) -> Option<&Shader> {
+        self.shader.as_deref()
+    }
+
+    pub fn is_shadow_catcher(&self) -> bool {
+        self.shadow_catcher
+    }
+
+    pub fn roughness(&self) -> f64 {
+        self.roughness
+    }
+
+    pub fn metallic(&self) -> f64 {
+        self.metallic
+    }
+
+    pub fn transmission(&self) -> f64 {
+        self.transmission
+    }
+
+    pub fn ior(&self) -> f64 {
+        self.ior
+    }
+
+    /// Do the textures of the material use the footprint given to
+    /// `filtered_bsdf`?
+    pub fn uses_footprint(&self) -> bool {
+        self.albedo.uses_footprint()
+            || self
+                .normal_map
+                .as_ref()
+                .is_some_and(|normal_map| normal_map.uses_footprint())
+    }
+
+    /// Does any light pass through the material?
+    pub fn is_transparent(&self) -> bool {
+        self.transmission > 0.0
+    }
+
+    /// Does the material reflect or refract any light perfectly specularly,
+    /// like glass and mirrors do?
+    pub fn is_specular(&self) -> bool {
+        self.transmission > 0.0 || (self.metallic > 0.0 && self.roughness == 0.0)
+    }
+
+    /// Find the albedo of the material at `point`, with texture coordinates
+    /// `uv`.
+    pub fn albedo(&self, point: Vector3, uv: (f64, f64)) -> Vector3 {
+        self.albedo.color(point, uv)
+    }
+
+    /// Find the normal used for shading at `point`. `normal` and `tangent` are
+    /// the unit normal and tangent of the surface geometry at the point. If
+    /// the material has no normal map, the geometric normal is returned.
+    pub fn shading_normal(
+        &self,
+        point: Vector3,
+        uv: (f64, f64),
+        normal: Vector3,
+        tangent: Vector3,
+    ) -> Vector3 {
+        self.filtered_shading_normal(point, uv, 0.0, normal, tangent)
+    }
+
+    /// Like `shading_normal`, but with the normal map filtered over
+    /// `footprint`, as in `Texture::filtered_color`.
+    fn filtered_shading_normal(
+        &self,
+        point: Vector3,
+        uv: (f64, f64),
+        footprint: f64,
+        normal: Vector3,
+        tangent: Vector3,
+    ) -> Vector3 {
+        match &self.normal_map {
+            None => normal,
+            Some(normal_map) => {
+                let bitangent = normal.cross(tangent);
+                let rgb = normal_map.filtered_color(point, uv, footprint);
+                let perturbed = (2.0 * rgb.x - 1.0) * tangent
+                    + (2.0 * rgb.y - 1.0) * bitangent
+                    + (2.0 * rgb.z - 1.0) * normal;
+                let perturbed = perturbed.normalize();
+
+                if perturbed.dot(normal) > 0.0 {
+                    perturbed
+                } else {
+                    // A broken normal map must not turn the surface inside out.
+                    normal
+                }
+            }
+        }
+    }
+
+    /// Find how the material reflects light at `point`. The arguments are the
+    /// same as for `shading_normal`.
+    pub fn bsdf(&self, point: Vector3, uv: (f64, f64), normal: Vector3, tangent: Vector3) -> Bsdf {
+        self.filtered_bsdf(point, uv, 0.0, normal, tangent)
+    }
+
+    /// Like `bsdf`, but with the textures filtered over `footprint`, as in
+    /// `Texture::filtered_color`.
+    pub fn filtered_bsdf(
+        &self,
+        point: Vector3,
+        uv: (f64, f64),
+        footprint: f64,
+        normal: Vector3,
+        tangent: Vector3,
+    ) -> Bsdf {
+        let shading_normal = self.filtered_shading_normal(point, uv, footprint, normal, tangent);
+        // Make the tangent perpendicular to the shading normal.
+        let tangent = tangent - tangent.dot(shading_normal) * shading_normal;
+        let tangent = if tangent.norm2() > 1e-12 {
+            tangent.normalize()
+        } else {
+            shading_normal.perpendicular()
+        };
+
+        // Squaring the roughness makes it perceptually more linear. A
+        // roughness of 0 is a perfect mirror, while very smooth microfacet
+        // surfaces are numerically unstable.
+        let (alpha_x, alpha_y) = if self.roughness == 0.0 {
+            (0.0, 0.0)
+        } else {
+            let alpha = self.roughness * self.roughness;
+            let aspect = (1.0 - 0.9 * self.anisotropy.abs()).sqrt();
+            let (along, across) = (alpha / aspect, alpha * aspect);
+            let (alpha_x, alpha_y) = if self.anisotropy >= 0.0 {
+                (along, across)
+            } else {
+                (across, along)
+            };
+            (alpha_x.max(1e-3), alpha_y.max(1e-3))
+        };
+
+        Bsdf {
+            albedo: self.albedo.filtered_color(point, uv, footprint),
+            normal: shading_normal,
+            tangent,
+            alpha_x,
+            alpha_y,
+            metallic: self.metallic,
+            transmission: self.transmission,
+            ior: self.ior,
+        }
+    }
+}
+
+/// Materials registered under names, so that many surfaces can share a
+/// material, and scene files and imported models can refer to materials by
+/// name.
+#[derive(Default)]
+pub struct MaterialLibrary {
+    materials: BTreeMap<String, Material>,
+}
+
+impl MaterialLibrary {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register `material` under `name`, replacing any material already
+    /// registered under it.
+    pub fn insert(&mut self, name: impl Into<String>, material: Material) {
+        self.materials.insert(name.into(), material);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Material> {
+        self.materials.get(name)
+    }
+
+    /// The names of the materials, in alphabetical order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.materials.keys().map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.materials.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.materials.is_empty()
+    }
+}
+
+impl IntoIterator for MaterialLibrary {
+    type Item = (String, Material);
+    type IntoIter = std::collections::btree_map::IntoIter<String, Material>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.materials.into_iter()
+    }
+}
+
+/// The reflectance of a material at a point on a surface. It is a blend of
+/// Lambertian diffuse reflection and GGX microfacet reflection, which may be
+/// anisotropic, with Fresnel reflectance given by the albedo, or mirror
+/// reflection if the roughness is 0. Transparent materials also have a smooth dielectric part, which reflects
+/// and refracts light perfectly specularly.
+///
+/// All directions point away from the surface. Perfectly specular directions
+/// are only found by `sample` and `specular_directions`, and not included in
+/// `reflectance` and `pdf`.
+pub struct Bsdf {
+    albedo: Vector3,
+    /// The shading normal.
+    normal: Vector3,
+    /// A unit tangent perpendicular to the shading normal.
+    tangent: Vector3,
+    /// The roughness of the microfacet distribution along the tangent and the
+    /// bitangent, or 0 for a mirror.
+    alpha_x: f64,
+    alpha_y: f64,
+    metallic: f64,
+    transmission: f64,
+    ior: f64,
+}
+
+/// A direction sampled from a `Bsdf`.
+pub struct BsdfSample {
+    pub direction: Vector3,
+    /// The reflectance times the cosine of the angle between the direction
+    /// and the normal, divided by the probability density of the direction.
+    pub weight: Vector3,
+    /// The probability density, over solid angle, of the direction. Infinite
+    /// for perfectly specular directions.
+    pub pdf: f64,
+}
+
+impl Bsdf {
+    /// The shading normal.
+    pub fn normal(&self) -> Vector3 {
+        self.normal
+    }
+
+    /// Does all light reflect or refract perfectly specularly, as from clear
+    /// glass and perfect mirrors? Such surfaces are not lit directly by the
+    /// lights.
+    pub fn is_specular(&self) -> bool {
+        self.transmission == 1.0 || (self.metallic == 1.0 && self.is_mirror())
+    }
+
+    /// The albedo of the diffuse part of the material.
+    pub fn diffuse_albedo(&self) -> Vector3 {
+        ((1.0 - self.transmission) * (1.0 - self.metallic)) * self.albedo
+    }
+
+    /// Find π times the BRDF for light arriving from `incoming` and leaving
+    /// towards `outgoing`. A white, diffuse surface has reflectance 1 in all
+    /// directions.
+    pub fn reflectance(&self, outgoing: Vector3, incoming: Vector3) -> Vector3 {
+        if self.metallic == 0.0 && self.transmission == 0.0 {
+            return self.albedo;
+        }
+
+        let diffuse = (1.0 - self.metallic) * self.albedo;
+        let reflectance = if self.is_mirror() {
+            diffuse
+        } else {
+            diffuse + self.metallic * self.specular(outgoing, incoming)
+        };
+        (1.0 - self.transmission) * reflectance
+    }
+
+    /// Find the probability density, over solid angle, that `sample` samples
+    /// `incoming`.
+    pub fn pdf(&self, outgoing: Vector3, incoming: Vector3) -> f64 {
+        let cos_incoming = self.normal.dot(incoming);
+        if cos_incoming <= 0.0 {
+            return 0.0;
+        }
+
+        let diffuse_pdf = cos_incoming / PI;
+        if self.metallic == 0.0 && self.transmission == 0.0 {
+            return diffuse_pdf;
+        }
+
+        let pdf = if self.is_mirror() {
+            (1.0 - self.metallic) * diffuse_pdf
+        } else {
+            (1.0 - self.metallic) * diffuse_pdf
+                + self.metallic * self.specular_pdf(outgoing, incoming)
+        };
+        (1.0 - self.transmission) * pdf
+    }
+
+    /// Sample a direction for light arriving at the surface, proportionally to
+    /// the cosine-weighted reflectance as far as possible. Returns `None` if the
+    /// sampled direction is below the surface.
+    pub fn sample(&self, outgoing: Vector3, rng: &mut Rng) -> Option<BsdfSample> {
+        if self.metallic == 0.0 && self.transmission == 0.0 {
+            // Cosine weighted sampling of the diffuse reflectance, where the
+            // cosine cancels against the probability density.
+            let direction =
+                sampling::orient_along(sampling::cosine_hemisphere(rng.next_pair()), self.normal);
+            return Some(BsdfSample {
+                direction,
+                weight: self.albedo,
+                pdf: self.normal.dot(direction).max(0.0) / PI,
+            });
+        }
+
+        // Choose a part of the material with the probability of its weight, so
+        // that the weight cancels for the perfectly specular parts.
+        if self.transmission == 1.0 || rng.next_f64() < self.transmission {
+            let (normal, eta) = self.dielectric_side(outgoing);
+            let cos_outgoing = normal.dot(outgoing);
+            return Some(if rng.next_f64() < fresnel_dielectric(cos_outgoing, eta) {
+                BsdfSample {
+                    direction: reflect(outgoing, normal),
+                    weight: Vector3::ones(),
+                    pdf: INFINITY,
+                }
+            } else {
+                BsdfSample {
+                    direction: refract(outgoing, normal, eta),
+                    weight: self.albedo,
+                    pdf: INFINITY,
+                }
+            });
+        }
+
+        let choose_specular = self.metallic == 1.0 || rng.next_f64() < self.metallic;
+        if choose_specular && self.is_mirror() {
+            let cos_outgoing = self.normal.dot(outgoing);
+            if cos_outgoing <= 0.0 {
+                return None;
+            }
+            return Some(BsdfSample {
+                direction: reflect(outgoing, self.normal),
+                weight: self.fresnel(cos_outgoing),
+                pdf: INFINITY,
+            });
+        }
+
+        let direction = if choose_specular {
+            let half_vector = self.to_world(sampling::ggx_normal(
+                rng.next_pair(),
+                self.alpha_x,
+                self.alpha_y,
+            ));
+            2.0 * outgoing.dot(half_vector) * half_vector - outgoing
+        } else {
+            sampling::orient_along(sampling::cosine_hemisphere(rng.next_pair()), self.normal)
+        };
+
+        let cos_incoming = self.normal.dot(direction);
+        let pdf = self.pdf(outgoing, direction);
+        if cos_incoming <= 0.0 || pdf <= 0.0 {
+            return None;
+        }
+
+        Some(BsdfSample {
+            direction,
+            weight: self.reflectance(outgoing, direction) * (cos_incoming / (PI * pdf)),
+            pdf,
+        })
+    }
+
+    /// Find the directions from which light is reflected or refracted
+    /// perfectly specularly towards `outgoing`, together with the fraction of
+    /// that light reaching `outgoing`. Empty if the material has no perfectly
+    /// specular parts.
+    pub fn specular_directions(&self, outgoing: Vector3) -> Vec<(Vector3, Vector3)> {
+        let mut directions = Vec::new();
+
+        if self.transmission > 0.0 {
+            let (normal, eta) = self.dielectric_side(outgoing);
+            let reflectance = fresnel_dielectric(normal.dot(outgoing), eta);
+            if reflectance > 0.0 {
+                directions.push((
+                    reflect(outgoing, normal),
+                    self.transmission * reflectance * Vector3::ones(),
+                ));
+            }
+            if reflectance < 1.0 {
+                directions.push((
+                    refract(outgoing, normal, eta),
+                    self.transmission * (1.0 - reflectance) * self.albedo,
+                ));
+            }
+        }
+
+        let mirror = (1.0 - self.transmission) * self.metallic;
+        let cos_outgoing = self.normal.dot(outgoing);
+        if self.is_mirror() && mirror > 0.0 && cos_outgoing > 0.0 {
+            directions.push((
+                reflect(outgoing, self.normal),
+                mirror * self.fresnel(cos_outgoing),
+            ));
+        }
+
+        directions
+    }
+
+    fn is_mirror(&self) -> bool {
+        self.alpha_x == 0.0
+    }
+
+    /// Convert `local` from the tangent space of the surface, where x is along
+    /// the tangent, y along the bitangent and z along the normal.
+    fn to_world(&self, local: Vector3) -> Vector3 {
+        let bitangent = self.normal.cross(self.tangent);
+        local.x * self.tangent + local.y * bitangent + local.z * self.normal
+    }
+
+    /// Convert `world` to the tangent space of the surface.
+    fn to_local(&self, world: Vector3) -> Vector3 {
+        let bitangent = self.normal.cross(self.tangent);
+        Vector3::from((
+            world.dot(self.tangent),
+            world.dot(bitangent),
+            world.dot(self.normal),
+        ))
+    }
+
+    /// Find the fraction of light passing straight through the surface along
+    /// `direction`, ignoring refraction. Used for shadows of transparent
+    /// surfaces, which are colored by the albedo.
+    pub fn shadow_transmittance(&self, direction: Vector3) -> Vector3 {
+        if self.transmission == 0.0 {
+            return Vector3::zero();
+        }
+
+        let (normal, eta) = self.dielectric_side(-direction);
+        let reflectance = fresnel_dielectric(normal.dot(-direction), eta);
+        (self.transmission * (1.0 - reflectance)) * self.albedo
+    }
+
+    /// Find the normal on the side of the dielectric part that `outgoing` is
+    /// on, and the ratio of the index of refraction on that side to the one on
+    /// the other side. The normal of a transparent surface points out of it.
+    fn dielectric_side(&self, outgoing: Vector3) -> (Vector3, f64) {
+        if self.normal.dot(outgoing) >= 0.0 {
+            (self.normal, 1.0 / self.ior)
+        } else {
+            (-self.normal, self.ior)
+        }
+    }
+
+    /// Find π times the GGX microfacet BRDF.
+    fn specular(&self, outgoing: Vector3, incoming: Vector3) -> Vector3 {
+        let cos_outgoing = self.normal.dot(outgoing);
+        let cos_incoming = self.normal.dot(incoming);
+        if cos_outgoing <= 0.0 || cos_incoming <= 0.0 {
+            return Vector3::zero();
+        }
+
+        let half_vector = (outgoing + incoming).normalize();
+        let fresnel = self.fresnel(outgoing.dot(half_vector));
+        let shadowing = self.smith_g1(outgoing) * self.smith_g1(incoming);
+
+        fresnel * (PI * self.ggx(half_vector) * shadowing / (4.0 * cos_outgoing * cos_incoming))
+    }
+
+    /// Find the probability density of sampling `incoming` from the GGX
+    /// distribution of normals.
+    fn specular_pdf(&self, outgoing: Vector3, incoming: Vector3) -> f64 {
+        let half_vector = (outgoing + incoming).normalize();
+        let cos_half = self.normal.dot(half_vector);
+        let outgoing_dot_half = outgoing.dot(half_vector);
+        if cos_half <= 0.0 || outgoing_dot_half <= 0.0 {
+            return 0.0;
+        }
+
+        self.ggx(half_vector) * cos_half / (4.0 * outgoing_dot_half)
+    }
+
+    /// The GGX distribution of microfacet normals, at the unit `half_vector`.
+    fn ggx(&self, half_vector: Vector3) -> f64 {
+        let h = self.to_local(half_vector);
+        let stretched = (h.x / self.alpha_x).powi(2) + (h.y / self.alpha_y).powi(2) + h.z * h.z;
+        1.0 / (PI * self.alpha_x * self.alpha_y * stretched * stretched)
+    }
+
+    /// The Smith masking function for the GGX distribution, for light leaving
+    /// or arriving from the unit `direction`.
+    fn smith_g1(&self, direction: Vector3) -> f64 {
+        let v = self.to_local(direction);
+        let stretched = (self.alpha_x * v.x).powi(2) + (self.alpha_y * v.y).powi(2);
+        2.0 * v.z / (v.z + (v.z * v.z + stretched).sqrt())
+    }
+
+    /// Schlick's approximation of the Fresnel reflectance, with the albedo as
+    /// the reflectance at normal incidence.
+    fn fresnel(&self, cos: f64) -> Vector3 {
+        let t = (1.0 - cos.clamp(0.0, 1.0)).powi(5);
+        (1.0 - t) * self.albedo + t * Vector3::ones()
+    }
+}
+
+/// Reflect `direction` in the plane with unit normal `normal`.
+fn reflect(direction: Vector3, normal: Vector3) -> Vector3 {
+    2.0 * direction.dot(normal) * normal - direction
+}
+
+/// Refract `direction`, which is on the side of the unit `normal`, through a
+/// surface where the ratio of the indices of refraction on that side and the
+/// other is `eta`. There must not be total internal reflection.
+fn refract(direction: Vector3, normal: Vector3, eta: f64) -> Vector3 {
+    let cos_outgoing = direction.dot(normal);
+    let sin2_refracted = eta * eta * (1.0 - cos_outgoing * cos_outgoing);
+    let cos_refracted = (1.0 - sin2_refracted).max(0.0).sqrt();
+    (eta * cos_outgoing - cos_refracted) * normal - eta * direction
+}
+
+/// The Fresnel reflectance of unpolarized light hitting a smooth dielectric at
+/// an angle with cosine `cos` to the normal, where `eta` is the ratio of the
+/// indices of refraction on the side of the light and the other side.
+fn fresnel_dielectric(cos: f64, eta: f64) -> f64 {
+    let sin2_refracted = eta * eta * (1.0 - cos * cos);
+    if sin2_refracted >= 1.0 {
+        // Total internal reflection.
+        return 1.0;
+    }
+
+    let cos_refracted = (1.0 - sin2_refracted).sqrt();
+    let perpendicular = (eta * cos - cos_refracted) / (eta * cos + cos_refracted);
+    let parallel = (cos - eta * cos_refracted) / (cos + eta * cos_refracted);
+    0.5 * (perpendicular * perpendicular + parallel * parallel)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_weights_match_reflectance_and_pdf() {
+        let mut rng = Rng::new(0);
+        let outgoing = Vector3::from((0.6, 0.0, 0.8));
+
+        for &(metallic, anisotropy) in [(0.0, 0.0), (0.5, 0.0), (1.0, 0.0), (1.0, -0.8)].iter() {
+            let bsdf = Material::new(Constant::new((0.9, 0.6, 0.3)))
+                .with_roughness(0.4)
+                .with_anisotropy(anisotropy)
+                .with_metallic(metallic)
+                .bsdf(Vector3::zero(), (0.0, 0.0), Vector3::k(), Vector3::i());
+
+            for _ in 0..100 {
+                if let Some(sample) = bsdf.sample(outgoing, &mut rng) {
+                    let expected = bsdf.reflectance(outgoing, sample.direction)
+                        * (sample.direction.z / (PI * sample.pdf));
+                    assert!((sample.weight - expected).norm() < 1e-9);
+                    assert!((bsdf.pdf(outgoing, sample.direction) - sample.pdf).abs() < 1e-9);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn glass_refracts_by_snells_law_and_conserves_energy() {
+        let bsdf = Material::default()
+            .with_transmission(1.0)
+            .with_ior(1.5)
+            .bsdf(Vector3::zero(), (0.0, 0.0), Vector3::k(), Vector3::i());
+        assert!(bsdf.is_specular());
+
+        for &outgoing in [
+            Vector3::from((0.6, 0.0, 0.8)),
+            Vector3::from((0.0, 0.28, -0.96)),
+            Vector3::from((0.0, 0.8, -0.6)),
+        ]
+        .iter()
+        {
+            let directions = bsdf.specular_directions(outgoing);
+            let total = directions
+                .iter()
+                .fold(Vector3::zero(), |total, (_, weight)| total + *weight);
+            assert!((total - Vector3::ones()).norm() < 1e-9);
+
+            for (direction, _) in directions {
+                assert!((direction.norm() - 1.0).abs() < 1e-9);
+                if direction.z * outgoing.z < 0.0 {
+                    let sin = |v: Vector3| (v.x * v.x + v.y * v.y).sqrt();
+                    let (outside, inside) = if outgoing.z > 0.0 {
+                        (outgoing, direction)
+                    } else {
+                        (direction, outgoing)
+                    };
+                    assert!((sin(outside) - 1.5 * sin(inside)).abs() < 1e-9);
+                }
+            }
+        }
+
+        // At normal incidence, shadows let through what is refracted.
+        let refracted = bsdf
+            .specular_directions(Vector3::k())
+            .into_iter()
+            .find(|(direction, _)| direction.z < 0.0)
+            .unwrap()
+            .1;
+        assert!((bsdf.shadow_transmittance(-Vector3::k()) - refracted).norm() < 1e-9);
+    }
+
+    #[test]
+    fn normal_maps_perturb_the_shading_normal() {
+        let normal = Vector3::from((0.0, 0.6, 0.8));
+        let tangent = Vector3::i();
+        let shading_normal = |rgb: (f64, f64, f64)| {
+            Material::default()
+                .with_normal_map(Constant::new(rgb))
+                .bsdf(Vector3::zero(), (0.0, 0.0), normal, tangent)
+                .normal()
+        };
+
+        // A flat normal map leaves the normal alone.
+        assert!((shading_normal((0.5, 0.5, 1.0)) - normal).norm() < 1e-9);
+
+        // Red above one half tilts the normal towards the tangent, without
+        // turning it towards the bitangent.
+        let tilted = shading_normal((0.75, 0.5, 1.0));
+        assert!((tilted.norm() - 1.0).abs() < 1e-9);
+        assert!((tilted - (0.5 * tangent + normal).normalize()).norm() < 1e-9);
+        assert!(tilted.dot(tangent) > 0.0 && tilted.dot(normal) > 0.0);
+    }
+}
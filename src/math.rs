@@ -1,8 +1,22 @@
 //! Module containing carious mathematical structs.
 
+pub mod blue_noise;
+pub mod kd_tree;
+pub mod low_discrepancy;
+pub mod noise;
+pub mod sampling;
+pub mod stratified;
+
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
 use std::ops::{Add, AddAssign, Mul, Neg, Sub};
 
-/// A closed interval in the set of real numbers.
+/// A closed interval in the set of real numbers. It is serialized as its
+/// endpoints.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(from = "(f64, f64)", into = "(f64, f64)")]
 pub struct Interval {
     endpoints: (f64, f64),
 }
@@ -38,24 +52,111 @@ impl Interval {
     }
 }
 
+impl From<(f64, f64)> for Interval {
+    fn from((first_endpoint, second_endpoint): (f64, f64)) -> Self {
+        Self::new(first_endpoint, second_endpoint)
+    }
+}
+
+impl From<Interval> for (f64, f64) {
+    fn from(interval: Interval) -> Self {
+        interval.endpoints
+    }
+}
+
 /// A ray that is cast from `origin` in the `direction` direction, which must
-/// be a unit vector.
+/// be a unit vector. It is serialized as the origin and the direction, which
+/// is normalized when deserialized.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(try_from = "(Vector3, Vector3)", into = "(Vector3, Vector3)")]
 pub struct Ray {
     pub origin: Vector3,
     pub direction: Vector3,
 }
 
 impl Ray {
+    /// Make a ray from `origin` along `direction`, which is normalized. The
+    /// origin and direction must be finite, and the direction non-zero. This
+    /// is checked in debug builds only; use `try_new` for rays that might be
+    /// invalid.
     pub fn new(origin: Vector3, direction: Vector3) -> Self {
-        Self {
+        match Self::try_new(origin, direction) {
+            Ok(ray) => ray,
+            Err(error) => {
+                if cfg!(debug_assertions) {
+                    panic!("{}", error);
+                }
+                Self {
+                    origin,
+                    direction: direction.normalize(),
+                }
+            }
+        }
+    }
+
+    /// Like `new`, but returns an error if the origin or direction is invalid.
+    pub fn try_new(origin: Vector3, direction: Vector3) -> Result<Self, RayError> {
+        if !origin.is_finite() {
+            return Err(RayError::NonFiniteOrigin);
+        }
+        if !direction.is_finite() {
+            return Err(RayError::NonFiniteDirection);
+        }
+        if direction.is_zero() {
+            return Err(RayError::ZeroDirection);
+        }
+
+        Ok(Self {
             origin,
             direction: direction.normalize(),
-        }
+        })
+    }
+
+    /// Are the origin and direction finite? Rays made with `new` in release
+    /// builds, or by setting the fields, may not be.
+    pub fn is_finite(&self) -> bool {
+        self.origin.is_finite() && self.direction.is_finite()
+    }
+}
+
+/// Why a ray could not be made.
+#[derive(Debug, PartialEq)]
+pub enum RayError {
+    NonFiniteOrigin,
+    NonFiniteDirection,
+    ZeroDirection,
+}
+
+impl TryFrom<(Vector3, Vector3)> for Ray {
+    type Error = RayError;
+
+    fn try_from((origin, direction): (Vector3, Vector3)) -> Result<Self, RayError> {
+        Self::try_new(origin, direction)
     }
 }
 
-/// Unit quaternions are used for representing rotations.
-#[derive(Clone, Copy)]
+impl From<Ray> for (Vector3, Vector3) {
+    fn from(ray: Ray) -> Self {
+        (ray.origin, ray.direction)
+    }
+}
+
+impl fmt::Display for RayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            RayError::NonFiniteOrigin => "Ray origin is infinite or NaN",
+            RayError::NonFiniteDirection => "Ray direction is infinite or NaN",
+            RayError::ZeroDirection => "Ray direction is zero",
+        })
+    }
+}
+
+impl Error for RayError {}
+
+/// Unit quaternions are used for representing rotations. They are serialized
+/// as the real part and the imaginary part, and normalized when deserialized.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(from = "(f64, Vector3)", into = "(f64, Vector3)")]
 pub struct UnitQuaternion {
     real: f64,
     imag: Vector3,
@@ -80,6 +181,57 @@ impl UnitQuaternion {
         }
     }
 
+    /// Make the unit quaternion of the rotation that turns the unit vectors
+    /// along the x-, y- and z-axes into `x`, `y` and `z`, which must be
+    /// orthonormal and right-handed.
+    pub fn from_basis(x: Vector3, y: Vector3, z: Vector3) -> Self {
+        // The largest of the four components is found first, and the others
+        // from it, which keeps the division well-conditioned.
+        let trace = x.x + y.y + z.z;
+        if trace > 0.0 {
+            let s = 2.0 * (trace + 1.0).sqrt();
+            Self::new(
+                0.25 * s,
+                ((y.z - z.y) / s, (z.x - x.z) / s, (x.y - y.x) / s),
+            )
+        } else if x.x > y.y && x.x > z.z {
+            let s = 2.0 * (1.0 + x.x - y.y - z.z).sqrt();
+            Self::new(
+                (y.z - z.y) / s,
+                (0.25 * s, (y.x + x.y) / s, (z.x + x.z) / s),
+            )
+        } else if y.y > z.z {
+            let s = 2.0 * (1.0 + y.y - x.x - z.z).sqrt();
+            Self::new(
+                (z.x - x.z) / s,
+                ((y.x + x.y) / s, 0.25 * s, (z.y + y.z) / s),
+            )
+        } else {
+            let s = 2.0 * (1.0 + z.z - x.x - y.y).sqrt();
+            Self::new(
+                (x.y - y.x) / s,
+                ((z.x + x.z) / s, (z.y + y.z) / s, 0.25 * s),
+            )
+        }
+    }
+
+    /// Find the unit axis and the angle, in radians from 0 to π, of the
+    /// rotation, as taken by `from_axis_angle`. The identity has the z-axis
+    /// and the angle 0.
+    pub fn axis_angle(self) -> (Vector3, f64) {
+        let (real, imag) = if self.real < 0.0 {
+            (-self.real, -self.imag)
+        } else {
+            (self.real, self.imag)
+        };
+        let sin = imag.norm();
+        if sin > 0.0 {
+            ((1.0 / sin) * imag, 2.0 * sin.atan2(real))
+        } else {
+            (Vector3::k(), 0.0)
+        }
+    }
+
     /// The identity quaternion.
     pub fn id() -> Self {
         Self::new(1.0, (0.0, 0.0, 0.0))
@@ -101,10 +253,60 @@ impl UnitQuaternion {
     }
 
     /// Compute the multiplicative inverse of the quaternion.
-    fn invert(mut self) -> Self {
+    pub fn invert(mut self) -> Self {
         self.imag = -self.imag;
         self
     }
+
+    /// Blend `rotations` by their weights, which should sum to 1, for
+    /// interpolating between rotations. Each quaternion is taken with the sign
+    /// that is closest to the one before it, so that the blend doesn't turn
+    /// the long way around. Returns the first rotation if the blend cancels
+    /// out, and the identity if there are no rotations.
+    pub fn blend(rotations: &[(Self, f64)]) -> Self {
+        let mut previous = match rotations.first() {
+            Some(&(rotation, _)) => rotation,
+            None => return Self::id(),
+        };
+        let (mut real, mut imag) = (0.0, Vector3::zero());
+        for &(rotation, weight) in rotations {
+            let sign = if previous.real * rotation.real + previous.imag.dot(rotation.imag) < 0.0 {
+                -1.0
+            } else {
+                1.0
+            };
+            previous = Self::new(sign * rotation.real, sign * rotation.imag);
+            real += weight * previous.real;
+            imag += weight * previous.imag;
+        }
+
+        let norm = (real * real + imag.norm2()).sqrt();
+        if norm > 1e-12 && norm.is_finite() {
+            Self::new(real / norm, (1.0 / norm) * imag)
+        } else {
+            rotations[0].0
+        }
+    }
+}
+
+impl From<(f64, Vector3)> for UnitQuaternion {
+    /// Make the unit quaternion with the direction of the quaternion with
+    /// the real part `real` and the imaginary part `imag`, or the identity if
+    /// that is zero.
+    fn from((real, imag): (f64, Vector3)) -> Self {
+        let norm = (real * real + imag.norm2()).sqrt();
+        if norm > 0.0 && norm.is_finite() {
+            Self::new(real / norm, (1.0 / norm) * imag)
+        } else {
+            Self::id()
+        }
+    }
+}
+
+impl From<UnitQuaternion> for (f64, Vector3) {
+    fn from(quaternion: UnitQuaternion) -> Self {
+        (quaternion.real, quaternion.imag)
+    }
 }
 
 impl Mul for UnitQuaternion {
@@ -119,7 +321,7 @@ impl Mul for UnitQuaternion {
 }
 
 /// A 3D vector
-#[derive(Clone, Copy, Default)]
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
 pub struct Vector3 {
     pub x: f64,
     pub y: f64,
@@ -170,13 +372,47 @@ impl Vector3 {
         )
     }
 
+    /// Multiply two vectors component by component.
+    pub fn elementwise_mul(self, other: Self) -> Self {
+        Self::new(self.x * other.x, self.y * other.y, self.z * other.z)
+    }
+
+    /// The smallest of each component of two vectors.
+    pub fn min(self, other: Self) -> Self {
+        Self::new(
+            self.x.min(other.x),
+            self.y.min(other.y),
+            self.z.min(other.z),
+        )
+    }
+
+    /// The largest of each component of two vectors.
+    pub fn max(self, other: Self) -> Self {
+        Self::new(
+            self.x.max(other.x),
+            self.y.max(other.y),
+            self.z.max(other.z),
+        )
+    }
+
+    /// Get a component of the vector, where `axis` 0, 1 and 2 are the x-, y-
+    /// and z-components, respectively.
+    pub fn component(self, axis: usize) -> f64 {
+        match axis {
+            0 => self.x,
+            1 => self.y,
+            2 => self.z,
+            _ => panic!("Invalid axis {}", axis),
+        }
+    }
+
     /// The square of the norm of the vector.
     pub fn norm2(self) -> f64 {
         self.dot(self)
     }
 
     /// Norm of the vector.
-    fn norm(self) -> f64 {
+    pub fn norm(self) -> f64 {
         self.norm2().sqrt()
     }
 
@@ -191,10 +427,27 @@ impl Vector3 {
         }
     }
 
+    /// Find a unit vector that is perpendicular to this vector, which must be a
+    /// unit vector.
+    pub fn perpendicular(self) -> Self {
+        // Cross with the cardinal axis that is least parallel to the vector.
+        let axis = if self.x.abs() < 0.9 {
+            Self::i()
+        } else {
+            Self::j()
+        };
+        self.cross(axis).normalize()
+    }
+
     fn is_zero(self) -> bool {
         self.x == 0.0 && self.y == 0.0 && self.z == 0.0
     }
 
+    /// Are all the components finite, i.e. neither infinite nor NaN?
+    pub fn is_finite(self) -> bool {
+        self.x.is_finite() && self.y.is_finite() && self.z.is_finite()
+    }
+
     /// Rotate the vector using a unit quaternion.
     pub fn rotate(self, rotation: UnitQuaternion) -> Self {
         let q = UnitQuaternion::new(0.0, self);
@@ -210,6 +463,12 @@ impl From<(f64, f64, f64)> for Vector3 {
     }
 }
 
+impl From<Vector3> for (f64, f64, f64) {
+    fn from(vector: Vector3) -> Self {
+        (vector.x, vector.y, vector.z)
+    }
+}
+
 impl Add for Vector3 {
     type Output = Self;
 
@@ -265,3 +524,135 @@ impl Sub for Vector3 {
         self + (-other)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use proptest::test_runner::RngSeed;
+    use std::f64::consts::PI;
+
+    const TOLERANCE: f64 = 1e-9;
+
+    fn vector(range: f64) -> impl Strategy<Value = Vector3> {
+        (-range..range, -range..range, -range..range).prop_map(Vector3::from)
+    }
+
+    #[test]
+    fn math_types_survive_serialization() {
+        let rotation = UnitQuaternion::from_axis_angle((1.0, 2.0, 3.0), 0.7);
+        let source = ron::to_string(&rotation).unwrap();
+        let found: UnitQuaternion = ron::from_str(&source).unwrap();
+        let v = Vector3::from((0.3, -1.0, 2.0));
+        assert!((v.rotate(found) - v.rotate(rotation)).norm() < TOLERANCE);
+
+        // Quaternions and ray directions are normalized when read.
+        let doubled: UnitQuaternion = ron::from_str("(2.0, (x: 0.0, y: 0.0, z: 0.0))").unwrap();
+        assert!((v.rotate(doubled) - v).norm() < TOLERANCE);
+        let ray: Ray =
+            ron::from_str("((x: 0.0, y: 0.0, z: 0.0), (x: 0.0, y: 3.0, z: 0.0))").unwrap();
+        assert!((ray.direction - Vector3::j()).norm() < TOLERANCE);
+        assert!(
+            ron::from_str::<Ray>("((x: 0.0, y: 0.0, z: 0.0), (x: 0.0, y: 0.0, z: 0.0))").is_err()
+        );
+
+        let interval: Interval = ron::from_str("(2.0, 1.0)").unwrap();
+        assert_eq!(interval.get_endpoints(), (1.0, 2.0));
+    }
+
+    proptest! {
+        // Use a fixed seed, so that every run tests the same cases.
+        #![proptest_config(ProptestConfig {
+            rng_seed: RngSeed::Fixed(0),
+            ..ProptestConfig::default()
+        })]
+
+        #[test]
+        fn normalized_vector_has_unit_length(v in vector(10.0)) {
+            prop_assume!(v.norm2() > 1e-6);
+            prop_assert!((v.normalize().norm() - 1.0).abs() < TOLERANCE);
+        }
+
+        #[test]
+        fn rotation_preserves_length_and_axis(
+            v in vector(10.0),
+            axis in vector(1.0),
+            angle in -10.0..10.0,
+        ) {
+            prop_assume!(axis.norm2() > 1e-6);
+            let rotation = UnitQuaternion::from_axis_angle(axis, angle);
+
+            prop_assert!((v.rotate(rotation).norm() - v.norm()).abs() < TOLERANCE);
+            prop_assert!((axis.rotate(rotation) - axis).norm() < TOLERANCE);
+        }
+
+        #[test]
+        fn rotation_is_found_from_the_rotated_basis(axis in vector(1.0), angle in -10.0..10.0) {
+            prop_assume!(axis.norm2() > 1e-6);
+            let rotation = UnitQuaternion::from_axis_angle(axis, angle);
+            let (x, y, z) = (
+                Vector3::i().rotate(rotation),
+                Vector3::j().rotate(rotation),
+                Vector3::k().rotate(rotation),
+            );
+            let found = UnitQuaternion::from_basis(x, y, z);
+
+            for (unit, rotated) in [(Vector3::i(), x), (Vector3::j(), y), (Vector3::k(), z)] {
+                prop_assert!((unit.rotate(found) - rotated).norm() < TOLERANCE);
+            }
+        }
+
+        #[test]
+        fn rotation_is_rebuilt_from_its_axis_and_angle(axis in vector(1.0), angle in -10.0..10.0) {
+            prop_assume!(axis.norm2() > 1e-6);
+            let rotation = UnitQuaternion::from_axis_angle(axis, angle);
+            let (found_axis, found_angle) = rotation.axis_angle();
+            let found = UnitQuaternion::from_axis_angle(found_axis, found_angle);
+
+            prop_assert!((0.0..=PI).contains(&found_angle));
+            for unit in [Vector3::i(), Vector3::j(), Vector3::k()] {
+                prop_assert!((unit.rotate(found) - unit.rotate(rotation)).norm() < TOLERANCE);
+            }
+        }
+
+        #[test]
+        fn concentric_disk_keeps_the_square_rings(u in 0.0..1.0, v in 0.0..1.0) {
+            let (x, y) = sampling::concentric_disk((u, v));
+            let ring = (2.0 * u - 1.0).abs().max((2.0 * v - 1.0).abs());
+            prop_assert!((x.hypot(y) - ring).abs() < TOLERANCE);
+        }
+
+        #[test]
+        fn interval_intersection_lies_in_both_intervals(
+            a in -10.0f64..10.0,
+            b in -10.0f64..10.0,
+            c in -10.0f64..10.0,
+            d in -10.0f64..10.0,
+        ) {
+            let (lower, upper) = (a.min(b).max(c.min(d)), a.max(b).min(c.max(d)));
+
+            match Interval::new(a, b).intersection(Interval::new(c, d)) {
+                None => prop_assert!(upper < lower),
+                Some(interval) => prop_assert_eq!(interval.get_endpoints(), (lower, upper)),
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_rays_are_rejected() {
+        let origin = Vector3::zero();
+        assert!(Ray::try_new(origin, Vector3::k()).is_ok());
+        assert_eq!(
+            Ray::try_new(origin, Vector3::zero()).err(),
+            Some(RayError::ZeroDirection)
+        );
+        assert_eq!(
+            Ray::try_new(origin, Vector3::from((f64::NAN, 0.0, 1.0))).err(),
+            Some(RayError::NonFiniteDirection)
+        );
+        assert_eq!(
+            Ray::try_new(Vector3::from((f64::INFINITY, 0.0, 0.0)), Vector3::k()).err(),
+            Some(RayError::NonFiniteOrigin)
+        );
+    }
+}
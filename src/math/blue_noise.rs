@@ -0,0 +1,276 @@
+//! Module containing blue-noise masks, used for spreading the error of
+//! sampling evenly over the pixels of an image.
+//!
+//! A blue-noise mask is a square, tileable image whose values are spread
+//! evenly over [0, 1), and where neighboring texels have values that are as
+//! different as possible. When the random numbers of each pixel are offset by
+//! the mask, the error of a render with few samples looks like fine, even
+//! grain instead of clumps and blotches, and it blurs away more easily.
+//!
+//! Masks are generated with the void-and-cluster method of Robert Ulichney,
+//! or loaded from grayscale PNG images, such as those published by Christoph
+//! Peters.
+
+use super::sampling::Rng;
+use crate::hashing::ContentHasher;
+use std::error::Error;
+use std::fs::File;
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+
+/// A square, tileable image with blue-noise values in [0, 1).
+pub struct BlueNoiseMask {
+    size: usize,
+    /// The values of the texels, row by row from the top.
+    values: Vec<f32>,
+}
+
+impl BlueNoiseMask {
+    /// The width and height of the mask given by `default_mask`.
+    pub const DEFAULT_SIZE: usize = 64;
+
+    /// Make a mask of size `size` x `size` from the values of its texels, row
+    /// by row from the top, which must be in [0, 1).
+    pub fn new(size: usize, values: Vec<f32>) -> Result<Self, Box<dyn Error>> {
+        if size == 0 || values.len() != size * size {
+            return Err("Invalid blue-noise mask size".into());
+        }
+        if !values.iter().all(|value| (0.0..1.0).contains(value)) {
+            return Err("Blue-noise mask values must be in [0, 1)".into());
+        }
+        Ok(Self { size, values })
+    }
+
+    /// Generate a mask of size `size` x `size` with the void-and-cluster
+    /// method, starting from a pattern chosen by `seed`. The time taken grows
+    /// with the fourth power of `size`, so masks larger than 128 x 128 are
+    /// better loaded from a file.
+    pub fn generate(size: usize, seed: u64) -> Self {
+        let count = size * size;
+        let mut pattern = VoidAndCluster::new(size);
+
+        // Start with a tenth of the texels set at random, and move texels from
+        // the tightest cluster to the largest void until it is the same texel.
+        let mut rng = Rng::new(seed);
+        let initial = (count / 10).max(1);
+        while pattern.ones < initial {
+            let index = (rng.next_u32() as usize) % count;
+            if !pattern.is_set[index] {
+                pattern.toggle(index);
+            }
+        }
+        loop {
+            let cluster = pattern.tightest_cluster();
+            pattern.toggle(cluster);
+            let void = pattern.largest_void();
+            pattern.toggle(void);
+            if void == cluster {
+                break;
+            }
+        }
+
+        // Rank the texels of the initial pattern by removing the tightest
+        // cluster one at a time, and then the rest by filling the largest void.
+        let mut ranks = vec![0; count];
+        let mut removing = pattern.clone();
+        while removing.ones > 0 {
+            let cluster = removing.tightest_cluster();
+            removing.toggle(cluster);
+            ranks[cluster] = removing.ones;
+        }
+        while pattern.ones < count {
+            let void = pattern.largest_void();
+            ranks[void] = pattern.ones;
+            pattern.toggle(void);
+        }
+
+        let values = ranks
+            .iter()
+            .map(|&rank| ((rank as f64 + 0.5) / count as f64) as f32)
+            .collect();
+        Self { size, values }
+    }
+
+    /// A mask of size `DEFAULT_SIZE` x `DEFAULT_SIZE`, generated the first
+    /// time it is asked for.
+    pub fn default_mask() -> Arc<Self> {
+        static MASK: OnceLock<Arc<BlueNoiseMask>> = OnceLock::new();
+        Arc::clone(MASK.get_or_init(|| Arc::new(Self::generate(Self::DEFAULT_SIZE, 0))))
+    }
+
+    /// Load a mask from the square PNG image `path`. Only the first channel is
+    /// used, so gray images work best.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        // Palettes and less than 8 bits per channel are expanded, and 16 bits
+        // per channel are reduced to 8, by default.
+        let decoder = png::Decoder::new(File::open(path)?);
+        let (info, mut reader) = decoder.read_info()?;
+        let mut buffer = vec![0; info.buffer_size()];
+        reader.next_frame(&mut buffer)?;
+
+        let channels = match info.color_type {
+            png::ColorType::Grayscale => 1,
+            png::ColorType::GrayscaleAlpha => 2,
+            png::ColorType::RGB => 3,
+            png::ColorType::RGBA => 4,
+            png::ColorType::Indexed => return Err("Unexpected palette in PNG image".into()),
+        };
+        if info.width != info.height {
+            return Err(format!("Blue-noise mask {} isn't square", path.display()).into());
+        }
+        let size = info.width as usize;
+
+        // Texels with the value 255 are put in the middle of the last of the
+        // 256 steps, below 1.
+        let values = buffer
+            .chunks(info.line_size)
+            .flat_map(|row| row[..channels * size].chunks(channels))
+            .map(|texel| (f32::from(texel[0]) + 0.5) / 256.0)
+            .collect();
+        Self::new(size, values)
+    }
+
+    /// The width and height of the mask.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Find the value at pixel (`x`, `y`), with the mask tiled over the image,
+    /// for the random number `dimension` of the pixel. The mask is shifted by
+    /// a different amount for each dimension, so that the numbers of a pixel
+    /// are unrelated to each other, while each of them is blue noise over the
+    /// image.
+    pub fn value(&self, x: usize, y: usize, dimension: u32) -> f64 {
+        // The shifts follow the R2 sequence of Martin Roberts, which spreads
+        // them evenly over the mask.
+        const ALPHA: (f64, f64) = (0.754_877_666_246_692_8, 0.569_840_290_998_053_3);
+        let dimension = f64::from(dimension);
+        let shift_x = ((dimension * ALPHA.0).fract() * self.size as f64) as usize;
+        let shift_y = ((dimension * ALPHA.1).fract() * self.size as f64) as usize;
+        let (x, y) = ((x + shift_x) % self.size, (y + shift_y) % self.size);
+        f64::from(self.values[y * self.size + x])
+    }
+
+    /// Write the size and values of the mask to `hasher`.
+    pub(crate) fn hash_content(&self, hasher: &mut ContentHasher) {
+        hasher.write_u64(self.size as u64);
+        for &value in &self.values {
+            hasher.write_f64(f64::from(value));
+        }
+    }
+}
+
+/// A binary pattern on a torus, together with how crowded each texel is by
+/// the set texels, for the void-and-cluster method.
+#[derive(Clone)]
+struct VoidAndCluster {
+    size: usize,
+    is_set: Vec<bool>,
+    ones: usize,
+    /// The sum of a Gaussian around each set texel, at each texel.
+    energy: Vec<f64>,
+    /// The Gaussian, by the offset between texels, wrapping around.
+    kernel: Vec<f64>,
+}
+
+impl VoidAndCluster {
+    /// The standard deviation of the Gaussian, in texels, as suggested by
+    /// Ulichney.
+    const SIGMA: f64 = 1.5;
+
+    /// Make an empty pattern of size `size` x `size`.
+    fn new(size: usize) -> Self {
+        let distance = |offset: usize| offset.min(size - offset) as f64;
+        let kernel: Vec<f64> = (0..size * size)
+            .map(|offset| {
+                let (dx, dy) = (distance(offset % size), distance(offset / size));
+                (-(dx * dx + dy * dy) / (2.0 * Self::SIGMA * Self::SIGMA)).exp()
+            })
+            .collect();
+        Self {
+            size,
+            is_set: vec![false; size * size],
+            ones: 0,
+            energy: vec![0.0; size * size],
+            kernel,
+        }
+    }
+
+    /// Set the texel at `index` if it isn't set, and clear it otherwise.
+    fn toggle(&mut self, index: usize) {
+        let sign = if self.is_set[index] { -1.0 } else { 1.0 };
+        self.is_set[index] = !self.is_set[index];
+        if self.is_set[index] {
+            self.ones += 1;
+        } else {
+            self.ones -= 1;
+        }
+
+        let size = self.size;
+        let (x, y) = (index % size, index / size);
+        for (other, energy) in self.energy.iter_mut().enumerate() {
+            let dx = (other % size + size - x) % size;
+            let dy = (other / size + size - y) % size;
+            *energy += sign * self.kernel[dy * size + dx];
+        }
+    }
+
+    /// Find the set texel with the most energy.
+    fn tightest_cluster(&self) -> usize {
+        self.extreme(true, |a, b| a > b)
+    }
+
+    /// Find the cleared texel with the least energy.
+    fn largest_void(&self) -> usize {
+        self.extreme(false, |a, b| a < b)
+    }
+
+    /// Find the texel with the best energy by `better` among those that are
+    /// set, or cleared, as given by `set`. Ties go to the first texel.
+    fn extreme(&self, set: bool, better: impl Fn(f64, f64) -> bool) -> usize {
+        let mut best: Option<usize> = None;
+        for (index, &energy) in self.energy.iter().enumerate() {
+            if self.is_set[index] == set
+                && best.is_none_or(|best| better(energy, self.energy[best]))
+            {
+                best = Some(index);
+            }
+        }
+        best.expect("No texel to choose in the blue-noise pattern")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_mask_is_stratified_and_spread_out() {
+        let size = 16;
+        let mask = BlueNoiseMask::generate(size, 0);
+
+        // Each value is used once.
+        let mut ranks: Vec<usize> = mask
+            .values
+            .iter()
+            .map(|&value| (f64::from(value) * (size * size) as f64) as usize)
+            .collect();
+        ranks.sort_unstable();
+        assert!(ranks.iter().copied().eq(0..size * size));
+
+        // Neighboring texels differ more than they would at random, where the
+        // mean absolute difference is 1/3.
+        let mean_difference = (0..size * size)
+            .map(|index| {
+                let (x, y) = (index % size, index / size);
+                (mask.value(x, y, 0) - mask.value(x + 1, y, 0)).abs()
+            })
+            .sum::<f64>()
+            / (size * size) as f64;
+        assert!(mean_difference > 0.4, "{}", mean_difference);
+
+        // The mask tiles, and is shifted for other dimensions.
+        assert_eq!(mask.value(3, 5, 0), mask.value(3 + size, 5 + 2 * size, 0));
+        assert_ne!(mask.value(3, 5, 0), mask.value(3, 5, 1));
+    }
+}
@@ -0,0 +1,227 @@
+//! Module containing a k-d tree, for quickly finding the points nearest to a
+//! given point.
+
+use crate::math::Vector3;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::mem;
+
+/// A balanced k-d tree over points in 3D space, each with an item attached.
+/// The tree is stored implicitly in one array: the root of every subtree is
+/// the middle element of its range, with the left subtree before it and the
+/// right subtree after it.
+pub struct KdTree<T> {
+    points: Vec<(Vector3, T)>,
+    /// The axis (0, 1 or 2 for x, y or z) that each node splits along.
+    axes: Vec<u8>,
+}
+
+impl<T> KdTree<T> {
+    /// Build a tree over `points`.
+    pub fn new(mut points: Vec<(Vector3, T)>) -> Self {
+        let mut axes = vec![0; points.len()];
+        build(&mut points, &mut axes);
+        Self { points, axes }
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    /// Find the memory used by the points and the items, in bytes, not
+    /// counting memory owned by the items.
+    pub fn memory_usage(&self) -> usize {
+        self.points.len() * (mem::size_of::<(Vector3, T)>() + mem::size_of::<u8>())
+    }
+
+    /// Find the at most `count` points nearest to `point`, that are closer to
+    /// it than `max_distance`. The points are returned with their items and
+    /// squared distances to `point`, nearest first.
+    pub fn nearest(&self, point: Vector3, count: usize, max_distance: f64) -> Vec<Neighbor<'_, T>> {
+        let mut heap = BinaryHeap::with_capacity(count + 1);
+        if count > 0 {
+            self.search(
+                0,
+                self.points.len(),
+                point,
+                count,
+                &mut (max_distance * max_distance),
+                &mut heap,
+            );
+        }
+
+        heap.into_sorted_vec()
+            .into_iter()
+            .map(|candidate| {
+                let (position, item) = &self.points[candidate.index];
+                Neighbor {
+                    position: *position,
+                    item,
+                    distance2: candidate.distance2,
+                }
+            })
+            .collect()
+    }
+
+    /// Search the subtree in the range `start..end` of the points, keeping the
+    /// nearest points found in `heap`. `max_distance2` shrinks to the squared
+    /// distance of the farthest point in the heap once the heap is full.
+    fn search(
+        &self,
+        start: usize,
+        end: usize,
+        point: Vector3,
+        count: usize,
+        max_distance2: &mut f64,
+        heap: &mut BinaryHeap<Candidate>,
+    ) {
+        if start >= end {
+            return;
+        }
+
+        let middle = (start + end) / 2;
+        let (position, _) = &self.points[middle];
+        let distance2 = (*position - point).norm2();
+        if distance2 < *max_distance2 {
+            heap.push(Candidate {
+                distance2,
+                index: middle,
+            });
+            if heap.len() > count {
+                heap.pop();
+            }
+            if heap.len() == count {
+                *max_distance2 = heap.peek().map_or(*max_distance2, |c| c.distance2);
+            }
+        }
+
+        // Search the side of the splitting plane that the point is on first,
+        // since it most likely holds the nearest points.
+        let axis = usize::from(self.axes[middle]);
+        let offset = point.component(axis) - position.component(axis);
+        let (near, far) = if offset < 0.0 {
+            ((start, middle), (middle + 1, end))
+        } else {
+            ((middle + 1, end), (start, middle))
+        };
+        self.search(near.0, near.1, point, count, max_distance2, heap);
+        if offset * offset < *max_distance2 {
+            self.search(far.0, far.1, point, count, max_distance2, heap);
+        }
+    }
+}
+
+/// A point found by `KdTree::nearest`.
+pub struct Neighbor<'a, T> {
+    pub position: Vector3,
+    pub item: &'a T,
+    /// The squared distance from the point searched around.
+    pub distance2: f64,
+}
+
+/// A point found while searching, ordered by distance.
+struct Candidate {
+    distance2: f64,
+    index: usize,
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.distance2.total_cmp(&other.distance2)
+    }
+}
+
+/// Arrange `points` as an implicit k-d tree, and store the splitting axis of
+/// each node in `axes`.
+fn build<T>(points: &mut [(Vector3, T)], axes: &mut [u8]) {
+    if points.len() <= 1 {
+        return;
+    }
+
+    // Split at the median along the axis where the points are most spread out.
+    let (lower, upper) = points.iter().fold(
+        (points[0].0, points[0].0),
+        |(lower, upper), (position, _)| (lower.min(*position), upper.max(*position)),
+    );
+    let extent = upper - lower;
+    let axis = if extent.x >= extent.y && extent.x >= extent.z {
+        0
+    } else if extent.y >= extent.z {
+        1
+    } else {
+        2
+    };
+
+    let middle = points.len() / 2;
+    points.select_nth_unstable_by(middle, |first, second| {
+        first.0.component(axis).total_cmp(&second.0.component(axis))
+    });
+    axes[middle] = axis as u8;
+
+    let (left_points, right_points) = points.split_at_mut(middle);
+    let (left_axes, right_axes) = axes.split_at_mut(middle);
+    build(left_points, left_axes);
+    build(&mut right_points[1..], &mut right_axes[1..]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use proptest::test_runner::RngSeed;
+
+    fn vector(range: f64) -> impl Strategy<Value = Vector3> {
+        (-range..range, -range..range, -range..range).prop_map(Vector3::from)
+    }
+
+    proptest! {
+        // Use a fixed seed, so that every run tests the same cases.
+        #![proptest_config(ProptestConfig {
+            rng_seed: RngSeed::Fixed(0),
+            ..ProptestConfig::default()
+        })]
+
+        #[test]
+        fn nearest_points_match_brute_force(
+            points in prop::collection::vec(vector(10.0), 0..200),
+            point in vector(12.0),
+            count in 0usize..20,
+            max_distance in 0.0..20.0,
+        ) {
+            let tree = KdTree::new(points.iter().cloned().zip(0usize..).collect());
+
+            let mut expected: Vec<f64> = points
+                .iter()
+                .map(|p| (*p - point).norm2())
+                .filter(|&distance2| distance2 < max_distance * max_distance)
+                .collect();
+            expected.sort_by(f64::total_cmp);
+            expected.truncate(count);
+
+            let found = tree.nearest(point, count, max_distance);
+            let distances: Vec<f64> = found.iter().map(|n| n.distance2).collect();
+            prop_assert_eq!(distances, expected);
+            for neighbor in found {
+                prop_assert_eq!((points[*neighbor.item] - neighbor.position).norm2(), 0.0);
+            }
+        }
+    }
+}
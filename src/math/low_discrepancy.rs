@@ -0,0 +1,139 @@
+//! Module containing low-discrepancy sequences, used for spreading the samples
+//! of a pixel more evenly than random numbers can, over every dimension and
+//! every pair of dimensions at once.
+//!
+//! The Halton sequence takes the radical inverse of the index of a sample in
+//! a prime base of its own for each dimension, shifted by a random amount
+//! that wraps around. The Sobol sequence is taken in pairs of dimensions,
+//! each the first two dimensions of the Sobol sequence with the order of the
+//! samples shuffled and the points scrambled by hashing, after Brent Burley's
+//! practical hash-based Owen scrambling. Both keep their good spread for any
+//! number of samples, and prefixes of them are well spread too, which suits
+//! halting sampling early.
+
+/// The bases of the dimensions of the Halton sequence. Dimensions past the
+/// last one start over, with other shifts.
+const PRIMES: [u32; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131,
+];
+
+/// Find sample `index` of dimension `dimension` of the Halton sequence,
+/// shifted by an amount given by `seed`.
+pub fn halton_value(index: u32, dimension: u32, seed: u32) -> f64 {
+    let base = PRIMES[dimension as usize % PRIMES.len()];
+    let shift = f64::from(hash(dimension ^ seed)) / 4_294_967_296.0;
+    (radical_inverse(index, base) + shift).fract()
+}
+
+/// Find sample `index` of the scrambled Sobol sequence in one dimension,
+/// scrambled by `seed`.
+pub fn sobol_value(index: u32, seed: u32) -> f64 {
+    let index = nested_uniform_scramble(index, hash(seed));
+    to_unit(nested_uniform_scramble(
+        index.reverse_bits(),
+        hash(seed ^ 1),
+    ))
+}
+
+/// Find sample `index` of the scrambled Sobol sequence in two dimensions,
+/// shuffled and scrambled by `seed`.
+pub fn sobol_point(index: u32, seed: u32) -> (f64, f64) {
+    let index = nested_uniform_scramble(index, hash(seed));
+    (
+        to_unit(nested_uniform_scramble(
+            index.reverse_bits(),
+            hash(seed ^ 1),
+        )),
+        to_unit(nested_uniform_scramble(sobol_second(index), hash(seed ^ 2))),
+    )
+}
+
+/// Mirror the digits of `index` in base `base` around the radix point.
+fn radical_inverse(index: u32, base: u32) -> f64 {
+    let inverse_base = 1.0 / f64::from(base);
+    let (mut index, mut result, mut digit_value) = (index, 0.0, inverse_base);
+    while index > 0 {
+        result += f64::from(index % base) * digit_value;
+        index /= base;
+        digit_value *= inverse_base;
+    }
+    // Rounding can give 1 for the largest indices.
+    result.min(1.0 - f64::EPSILON)
+}
+
+/// The second dimension of the Sobol sequence, as bits of a fraction.
+fn sobol_second(index: u32) -> u32 {
+    let (mut index, mut result, mut direction) = (index, 0, 1 << 31);
+    while index != 0 {
+        if index & 1 != 0 {
+            result ^= direction;
+        }
+        index >>= 1;
+        direction ^= direction >> 1;
+    }
+    result
+}
+
+/// Owen-scramble the bits of a fraction by `seed`: each bit is flipped or
+/// not by the bits above it, which keeps the spread of the sequence.
+fn nested_uniform_scramble(value: u32, seed: u32) -> u32 {
+    laine_karras_permutation(value.reverse_bits(), seed).reverse_bits()
+}
+
+/// A hash in which each bit depends only on the bits below it, by Samuli
+/// Laine and Tero Karras.
+fn laine_karras_permutation(value: u32, seed: u32) -> u32 {
+    let mut value = value.wrapping_add(seed);
+    value ^= value.wrapping_mul(0x6c50_b47c);
+    value ^= value.wrapping_mul(0xb82f_1e52);
+    value ^= value.wrapping_mul(0xc7af_e638);
+    value ^= value.wrapping_mul(0x8d22_f6e6);
+    value
+}
+
+fn hash(value: u32) -> u32 {
+    let mut value = value;
+    value ^= value >> 16;
+    value = value.wrapping_mul(0x7feb_352d);
+    value ^= value >> 15;
+    value = value.wrapping_mul(0x846c_a68b);
+    value ^= value >> 16;
+    value
+}
+
+fn to_unit(bits: u32) -> f64 {
+    f64::from(bits) / 4_294_967_296.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sequences_fill_elementary_intervals() {
+        // Each of the first 2^k points of a Sobol pair lies in its own
+        // interval of width 2^-k along both axes, however it is scrambled.
+        for seed in [0, 1, 12345] {
+            let count = 64;
+            let mut columns = vec![false; count];
+            let mut rows = vec![false; count];
+            for index in 0..count as u32 {
+                let (x, y) = sobol_point(index, seed);
+                assert!((0.0..1.0).contains(&x) && (0.0..1.0).contains(&y));
+                columns[(x * count as f64) as usize] = true;
+                rows[(y * count as f64) as usize] = true;
+            }
+            assert!(columns.iter().all(|&hit| hit) && rows.iter().all(|&hit| hit));
+        }
+
+        // The first 3^k points of the second Halton dimension lie in
+        // intervals of width 3^-k, before they are shifted.
+        let mut thirds: Vec<u32> = (0..27)
+            .map(|index| (radical_inverse(index, 3) * 27.0).round() as u32)
+            .collect();
+        thirds.sort_unstable();
+        assert!(thirds.into_iter().eq(0..27));
+        assert!((0..100).all(|index| (0.0..1.0).contains(&halton_value(index, 5, 7))));
+    }
+}
@@ -0,0 +1,179 @@
+//! Module containing gradient noise functions, used for procedural textures.
+//!
+//! The noise is Ken Perlin's improved noise, which is deterministic, so the
+//! same point always gives the same value.
+
+use crate::math::Vector3;
+
+/// Ken Perlin's reference permutation of the numbers 0 to 255.
+const PERMUTATION: [u8; 256] = [
+    151, 160, 137, 91, 90, 15, 131, 13, 201, 95, 96, 53, 194, 233, 7, 225, 140, 36, 103, 30, 69,
+    142, 8, 99, 37, 240, 21, 10, 23, 190, 6, 148, 247, 120, 234, 75, 0, 26, 197, 62, 94, 252, 219,
+    203, 117, 35, 11, 32, 57, 177, 33, 88, 237, 149, 56, 87, 174, 20, 125, 136, 171, 168, 68, 175,
+    74, 165, 71, 134, 139, 48, 27, 166, 77, 146, 158, 231, 83, 111, 229, 122, 60, 211, 133, 230,
+    220, 105, 92, 41, 55, 46, 245, 40, 244, 102, 143, 54, 65, 25, 63, 161, 1, 216, 80, 73, 209, 76,
+    132, 187, 208, 89, 18, 169, 200, 196, 135, 130, 116, 188, 159, 86, 164, 100, 109, 198, 173,
+    186, 3, 64, 52, 217, 226, 250, 124, 123, 5, 202, 38, 147, 118, 126, 255, 82, 85, 212, 207, 206,
+    59, 227, 47, 16, 58, 17, 182, 189, 28, 42, 223, 183, 170, 213, 119, 248, 152, 2, 44, 154, 163,
+    70, 221, 153, 101, 155, 167, 43, 172, 9, 129, 22, 39, 253, 19, 98, 108, 110, 79, 113, 224, 232,
+    178, 185, 112, 104, 218, 246, 97, 228, 251, 34, 242, 193, 238, 210, 144, 12, 191, 179, 162,
+    241, 81, 51, 145, 235, 249, 14, 239, 107, 49, 192, 214, 31, 181, 199, 106, 157, 184, 84, 204,
+    176, 115, 121, 50, 45, 127, 4, 150, 254, 138, 236, 205, 93, 222, 114, 67, 29, 24, 72, 243, 141,
+    128, 195, 78, 66, 215, 61, 156, 180,
+];
+
+/// Look up the permutation table, wrapping around after 256 entries.
+fn hash(i: usize) -> usize {
+    PERMUTATION[i & 255] as usize
+}
+
+/// The quintic smoothstep used for interpolating between lattice points.
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(t: f64, a: f64, b: f64) -> f64 {
+    a + t * (b - a)
+}
+
+/// Dot product between the offset (`x`, `y`, `z`) and one of 12 gradient
+/// directions, selected by `hash`.
+fn gradient(hash: usize, x: f64, y: f64, z: f64) -> f64 {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = if h < 4 {
+        y
+    } else if h == 12 || h == 14 {
+        x
+    } else {
+        z
+    };
+
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+/// Gradient noise at `point`. The result lies roughly between -1 and 1, and is
+/// zero at every integer lattice point.
+pub fn perlin(point: Vector3) -> f64 {
+    let (floor_x, floor_y, floor_z) = (point.x.floor(), point.y.floor(), point.z.floor());
+
+    // Lattice cell containing the point, wrapped to the size of the table.
+    let xi = (floor_x as i64 & 255) as usize;
+    let yi = (floor_y as i64 & 255) as usize;
+    let zi = (floor_z as i64 & 255) as usize;
+
+    // Position of the point inside the cell.
+    let (x, y, z) = (point.x - floor_x, point.y - floor_y, point.z - floor_z);
+    let (u, v, w) = (fade(x), fade(y), fade(z));
+
+    let a = hash(xi) + yi;
+    let aa = hash(a) + zi;
+    let ab = hash(a + 1) + zi;
+    let b = hash(xi + 1) + yi;
+    let ba = hash(b) + zi;
+    let bb = hash(b + 1) + zi;
+
+    lerp(
+        w,
+        lerp(
+            v,
+            lerp(
+                u,
+                gradient(hash(aa), x, y, z),
+                gradient(hash(ba), x - 1.0, y, z),
+            ),
+            lerp(
+                u,
+                gradient(hash(ab), x, y - 1.0, z),
+                gradient(hash(bb), x - 1.0, y - 1.0, z),
+            ),
+        ),
+        lerp(
+            v,
+            lerp(
+                u,
+                gradient(hash(aa + 1), x, y, z - 1.0),
+                gradient(hash(ba + 1), x - 1.0, y, z - 1.0),
+            ),
+            lerp(
+                u,
+                gradient(hash(ab + 1), x, y - 1.0, z - 1.0),
+                gradient(hash(bb + 1), x - 1.0, y - 1.0, z - 1.0),
+            ),
+        ),
+    )
+}
+
+/// Fractional Brownian motion: a sum of `octaves` layers of noise, where each
+/// layer has twice the frequency and half the amplitude of the previous one.
+pub fn fbm(point: Vector3, octaves: u32) -> f64 {
+    let mut sum = 0.0;
+    let mut frequency = 1.0;
+    let mut amplitude = 1.0;
+
+    for _ in 0..octaves {
+        sum += amplitude * perlin(frequency * point);
+        frequency *= 2.0;
+        amplitude *= 0.5;
+    }
+
+    sum
+}
+
+/// Like `fbm`, but summing the absolute value of each layer, which gives
+/// billowy patterns with sharp creases. The result is non-negative.
+pub fn turbulence(point: Vector3, octaves: u32) -> f64 {
+    let mut sum = 0.0;
+    let mut frequency = 1.0;
+    let mut amplitude = 1.0;
+
+    for _ in 0..octaves {
+        sum += amplitude * perlin(frequency * point).abs();
+        frequency *= 2.0;
+        amplitude *= 0.5;
+    }
+
+    sum
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use proptest::test_runner::RngSeed;
+
+    fn point(range: f64) -> impl Strategy<Value = Vector3> {
+        (-range..range, -range..range, -range..range).prop_map(Vector3::from)
+    }
+
+    #[test]
+    fn perlin_is_zero_at_lattice_points() {
+        for x in -3..3 {
+            for y in -3..3 {
+                for z in -3..3 {
+                    let lattice_point = Vector3::from((x as f64, y as f64, z as f64));
+                    assert_eq!(perlin(lattice_point), 0.0);
+                }
+            }
+        }
+    }
+
+    proptest! {
+        // Use a fixed seed, so that every run tests the same cases.
+        #![proptest_config(ProptestConfig {
+            rng_seed: RngSeed::Fixed(0),
+            ..ProptestConfig::default()
+        })]
+
+        #[test]
+        fn perlin_stays_within_one(p in point(300.0)) {
+            let value = perlin(p);
+            prop_assert!((-1.0..=1.0).contains(&value));
+        }
+
+        #[test]
+        fn turbulence_is_non_negative(p in point(300.0), octaves in 0..8u32) {
+            prop_assert!(turbulence(p, octaves) >= 0.0);
+        }
+    }
+}
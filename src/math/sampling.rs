@@ -0,0 +1,344 @@
+//! Module containing random number generation and functions for sampling
+//! directions and points, used by the Monte Carlo parts of the renderer.
+
+use crate::math::blue_noise::BlueNoiseMask;
+use crate::math::Vector3;
+use crate::math::{low_discrepancy, stratified};
+use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+/// How the numbers in [0, 1) that the samples of a pixel are taken with are
+/// spread.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SamplePattern {
+    /// Independent random numbers.
+    #[default]
+    Random,
+    /// In each dimension sampled, such as the point on the lens or on a
+    /// light, the samples of a pixel are spread over a jittered grid, so that
+    /// they cover it evenly, and noise goes away faster than with random
+    /// numbers.
+    Stratified,
+    /// The Halton sequence, whose samples are spread evenly over every
+    /// dimension, shifted randomly for each pixel.
+    Halton,
+    /// The Sobol sequence, scrambled randomly for each pixel, whose samples
+    /// are spread evenly over each pair of dimensions, and which converges
+    /// the fastest at low sample counts.
+    Sobol,
+}
+
+/// A small and fast pseudo-random number generator (PCG32). The same seed
+/// always gives the same sequence, so renders are reproducible.
+#[derive(Clone)]
+pub struct Rng {
+    state: u64,
+    /// The offsets of the numbers in [0, 1), if set by `with_blue_noise`.
+    blue_noise: Option<BlueNoiseShift>,
+    /// The sample being taken, if set by `with_pattern`.
+    sample: Option<SampleSet>,
+}
+
+/// Which sample of a set of samples spread by a sample pattern is being
+/// taken, and how many numbers it has taken.
+#[derive(Clone)]
+struct SampleSet {
+    pattern: SamplePattern,
+    /// The seed of the set, which gives each dimension its own order of the
+    /// strata, shift or scrambling.
+    seed: u32,
+    index: u32,
+    count: u32,
+    dimension: u32,
+}
+
+/// Where a pixel is in a tiled blue-noise mask, and how many numbers have
+/// been offset by it.
+#[derive(Clone)]
+struct BlueNoiseShift {
+    mask: Arc<BlueNoiseMask>,
+    x: usize,
+    y: usize,
+    dimension: u32,
+}
+
+impl SampleSet {
+    /// The seed of the next dimension of the sample.
+    fn dimension_seed(&self) -> u32 {
+        self.seed ^ self.dimension.wrapping_mul(0x9e37_79b9)
+    }
+
+    /// The number of the next dimension of sample `index` of `count`.
+    fn value(&self, index: u32, count: u32) -> f64 {
+        let seed = self.dimension_seed();
+        match self.pattern {
+            SamplePattern::Random | SamplePattern::Stratified => {
+                stratified::stratified_value(index, count, seed)
+            }
+            SamplePattern::Halton => {
+                low_discrepancy::halton_value(index, self.dimension, self.seed)
+            }
+            SamplePattern::Sobol => low_discrepancy::sobol_value(index, seed),
+        }
+    }
+
+    /// The numbers of the next two dimensions of sample `index` of `count`.
+    fn point(&self, index: u32, count: u32) -> (f64, f64) {
+        let seed = self.dimension_seed();
+        match self.pattern {
+            SamplePattern::Random | SamplePattern::Stratified => {
+                stratified::stratified_point(index, count, seed)
+            }
+            SamplePattern::Halton => (
+                low_discrepancy::halton_value(index, self.dimension, self.seed),
+                low_discrepancy::halton_value(index, self.dimension + 1, self.seed),
+            ),
+            SamplePattern::Sobol => low_discrepancy::sobol_point(index, seed),
+        }
+    }
+}
+
+impl Rng {
+    const MULTIPLIER: u64 = 6_364_136_223_846_793_005;
+    const INCREMENT: u64 = 1_442_695_040_888_963_407;
+
+    pub fn new(seed: u64) -> Self {
+        let mut rng = Self {
+            state: 0,
+            blue_noise: None,
+            sample: None,
+        };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+        rng
+    }
+
+    /// Make a generator whose seed is derived from several values, e.g. the
+    /// coordinates of a pixel, so that nearby values give unrelated sequences.
+    pub fn from_values(values: &[u64]) -> Self {
+        Self::from_seeded_values(0, values)
+    }
+
+    /// Like `from_values`, but different values of `seed` give unrelated
+    /// sequences for the same values.
+    pub fn from_seeded_values(seed: u64, values: &[u64]) -> Self {
+        let seed = values.iter().fold(seed, |hash: u64, &value| {
+            // One round of SplitMix64 per value.
+            let mut z = hash.wrapping_add(value).wrapping_add(0x9e37_79b9_7f4a_7c15);
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^ (z >> 31)
+        });
+        Self::new(seed)
+    }
+
+    /// Offset the numbers in [0, 1) given by the generator by the values of
+    /// `mask` at pixel (`x`, `y`), wrapping around. Pixels that share a seed
+    /// and are near each other then get numbers that differ as much as
+    /// possible, so that the error of sampling is spread evenly.
+    pub fn with_blue_noise(mut self, mask: Arc<BlueNoiseMask>, x: usize, y: usize) -> Self {
+        self.blue_noise = Some(BlueNoiseShift {
+            mask,
+            x,
+            y,
+            dimension: 0,
+        });
+        self
+    }
+
+    /// Spread the numbers in [0, 1) of the samples marked by `start_sample`
+    /// by `pattern`. Until the first sample is started, the numbers are
+    /// those of a single sample.
+    pub fn with_pattern(mut self, pattern: SamplePattern) -> Self {
+        if pattern == SamplePattern::Random {
+            return self;
+        }
+        let seed = self.next_u32();
+        self.sample = Some(SampleSet {
+            pattern,
+            seed,
+            index: 0,
+            count: 1,
+            dimension: 0,
+        });
+        self
+    }
+
+    /// Start taking sample `index` of `count`, whose numbers are spread
+    /// along with those of the other samples of the set, if the generator has
+    /// a sample pattern. Starting sample 0 starts a new set, unrelated to the
+    /// ones before it.
+    pub fn start_sample(&mut self, index: u32, count: u32) {
+        let pattern = match &self.sample {
+            Some(sample) => sample.pattern,
+            None => return,
+        };
+        let seed = if index == 0 {
+            self.next_u32()
+        } else {
+            self.sample.as_ref().map_or(0, |sample| sample.seed)
+        };
+        self.sample = Some(SampleSet {
+            pattern,
+            seed,
+            index,
+            count,
+            dimension: 0,
+        });
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let old_state = self.state;
+        self.state = old_state
+            .wrapping_mul(Self::MULTIPLIER)
+            .wrapping_add(Self::INCREMENT);
+
+        let xor_shifted = (((old_state >> 18) ^ old_state) >> 27) as u32;
+        let rotation = (old_state >> 59) as u32;
+        xor_shifted.rotate_right(rotation)
+    }
+
+    /// A uniformly distributed number in [0, 1).
+    pub fn next_f64(&mut self) -> f64 {
+        let value = match &mut self.sample {
+            Some(sample) => {
+                let value = sample.value(sample.index, sample.count);
+                sample.dimension += 1;
+                value
+            }
+            None => self.next_random(),
+        };
+        self.shifted(value)
+    }
+
+    /// A pair of uniformly distributed numbers in [0, 1).
+    pub fn next_pair(&mut self) -> (f64, f64) {
+        self.next_pair_in(0, 1)
+    }
+
+    /// A pair of uniformly distributed numbers in [0, 1), for part `stratum`
+    /// of `strata` parts of the current sample, such as the samples of a
+    /// light at a point. With a sample pattern, the pairs of all parts of all
+    /// samples are spread over the unit square together, and the dimension
+    /// is only used up by the last part, so no other numbers are to be taken
+    /// between the parts.
+    pub fn next_pair_in(&mut self, stratum: u32, strata: u32) -> (f64, f64) {
+        let (x, y) = match &mut self.sample {
+            Some(sample) => {
+                let point = sample.point(sample.index * strata + stratum, sample.count * strata);
+                if stratum + 1 >= strata {
+                    sample.dimension += 2;
+                }
+                point
+            }
+            None => (self.next_random(), self.next_random()),
+        };
+        (self.shifted(x), self.shifted(y))
+    }
+
+    /// A random number in [0, 1), from the sequence of the generator.
+    fn next_random(&mut self) -> f64 {
+        // Use 53 random bits, which is the precision of an f64.
+        let bits = (u64::from(self.next_u32()) << 21) | u64::from(self.next_u32() >> 11);
+        bits as f64 / (1u64 << 53) as f64
+    }
+
+    /// Offset `value` by the blue-noise mask, if any.
+    fn shifted(&mut self, value: f64) -> f64 {
+        match &mut self.blue_noise {
+            None => value,
+            Some(shift) => {
+                let offset = shift.mask.value(shift.x, shift.y, shift.dimension);
+                shift.dimension = shift.dimension.wrapping_add(1);
+                (value + offset).fract()
+            }
+        }
+    }
+}
+
+/// Map a uniformly distributed point `u` in the unit square to a direction in
+/// the hemisphere around the z-axis, distributed proportionally to the cosine
+/// of the angle to the z-axis.
+pub fn cosine_hemisphere(u: (f64, f64)) -> Vector3 {
+    let radius = u.0.sqrt();
+    let (sin, cos) = (2.0 * PI * u.1).sin_cos();
+
+    Vector3::from((radius * cos, radius * sin, (1.0 - u.0).max(0.0).sqrt()))
+}
+
+/// Map a uniformly distributed point `u` in the unit square to a point
+/// uniformly distributed in the unit disk.
+pub fn uniform_disk(u: (f64, f64)) -> (f64, f64) {
+    let radius = u.0.sqrt();
+    let (sin, cos) = (2.0 * PI * u.1).sin_cos();
+
+    (radius * cos, radius * sin)
+}
+
+/// Map a uniformly distributed point `u` in the unit square to a point
+/// uniformly distributed in the unit disk, by mapping squares around the
+/// middle of the unit square to circles. Points that are close in the square
+/// stay close in the disk, so well spread samples stay well spread.
+pub fn concentric_disk(u: (f64, f64)) -> (f64, f64) {
+    let (x, y) = (2.0 * u.0 - 1.0, 2.0 * u.1 - 1.0);
+    if x == 0.0 && y == 0.0 {
+        return (0.0, 0.0);
+    }
+    let (radius, angle) = if x.abs() > y.abs() {
+        (x, 0.25 * PI * (y / x))
+    } else {
+        (y, 0.5 * PI - 0.25 * PI * (x / y))
+    };
+    let (sin, cos) = angle.sin_cos();
+
+    (radius * cos, radius * sin)
+}
+
+/// Transform a vector given relative to the z-axis, so that the z-axis is
+/// mapped to the unit vector `normal`.
+pub fn orient_along(local: Vector3, normal: Vector3) -> Vector3 {
+    let tangent = normal.perpendicular();
+    let bitangent = normal.cross(tangent);
+
+    local.x * tangent + local.y * bitangent + local.z * normal
+}
+
+/// Map a uniformly distributed point `u` in the unit square to a direction
+/// uniformly distributed in the cone around the z-axis, whose half-angle has
+/// cosine `cos_max`.
+pub fn uniform_cone(u: (f64, f64), cos_max: f64) -> Vector3 {
+    let cos_theta = 1.0 - u.0 * (1.0 - cos_max);
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let (sin, cos) = (2.0 * PI * u.1).sin_cos();
+
+    Vector3::from((sin_theta * cos, sin_theta * sin, cos_theta))
+}
+
+/// The probability density, over solid angle, of the directions sampled by
+/// `uniform_cone`.
+pub fn uniform_cone_pdf(cos_max: f64) -> f64 {
+    1.0 / (2.0 * PI * (1.0 - cos_max))
+}
+
+/// Find point `index` of `count` points spread evenly over the unit square, on
+/// a Fibonacci lattice, for sampling without randomness.
+pub fn lattice_point(index: u32, count: u32) -> (f64, f64) {
+    // The fractional part of the golden ratio.
+    const GOLDEN: f64 = 0.618_033_988_749_894_9;
+    let index = f64::from(index);
+    ((index + 0.5) / f64::from(count), (index * GOLDEN).fract())
+}
+
+/// Map a uniformly distributed point `u` in the unit square to a microfacet
+/// normal in the hemisphere around the z-axis, distributed proportionally to
+/// the GGX distribution with roughness `alpha_x` along the x-axis and
+/// `alpha_y` along the y-axis, times the cosine of the angle to the z-axis.
+pub fn ggx_normal(u: (f64, f64), alpha_x: f64, alpha_y: f64) -> Vector3 {
+    // Sample the slope of the microfacet for roughness 1, and stretch it.
+    let slope = (u.0 / (1.0 - u.0)).sqrt();
+    let (sin, cos) = (2.0 * PI * u.1).sin_cos();
+
+    Vector3::from((alpha_x * slope * cos, alpha_y * slope * sin, 1.0)).normalize()
+}
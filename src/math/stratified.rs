@@ -0,0 +1,132 @@
+//! Module containing stratified point sets, used for spreading the samples of
+//! a pixel evenly over each dimension that is sampled, such as the position
+//! in the pixel, the point on the lens and the point on a light.
+//!
+//! Points are generated by correlated multi-jittered sampling, after Andrew
+//! Kensler. The samples of a set fall in a jittered grid of strata, which are
+//! shuffled so that each row and column of the grid holds one sample, and any
+//! number of samples can be taken. Each point is found from its index and a
+//! seed by hashing alone, so that the samples of a set can be taken in any
+//! order, and different seeds give unrelated sets.
+
+/// Find sample `index` of `count` numbers in [0, 1), one in each of `count`
+/// equal intervals, in an order and at positions in the intervals given by
+/// `seed`.
+pub fn stratified_value(index: u32, count: u32, seed: u32) -> f64 {
+    let count = count.max(1);
+    let stratum = permute(index % count, count, seed.wrapping_mul(0x51633e2d));
+    let jitter = hash_to_unit(index, seed.wrapping_mul(0x68bc21eb));
+    (f64::from(stratum) + jitter) / f64::from(count)
+}
+
+/// Find sample `index` of `count` points in the unit square, spread over a
+/// jittered grid as by correlated multi-jittered sampling, in an order and at
+/// positions in the strata given by `seed`.
+pub fn stratified_point(index: u32, count: u32, seed: u32) -> (f64, f64) {
+    let count = count.max(1);
+    // A grid of about as many columns as rows, with at least `count` cells.
+    let columns = f64::from(count).sqrt() as u32;
+    let rows = count.div_ceil(columns);
+    let index = permute(index % count, count, seed.wrapping_mul(0x51633e2d));
+    let (column, row) = (index % columns, index / columns);
+    let shuffled_column = permute(column, columns, seed.wrapping_mul(0x68bc21eb));
+    let shuffled_row = permute(row, rows, seed.wrapping_mul(0x02e5be93));
+    let jitter_x = hash_to_unit(index, seed.wrapping_mul(0x967a889b));
+    let jitter_y = hash_to_unit(index, seed.wrapping_mul(0x368cc8b7));
+    let (columns, rows) = (f64::from(columns), f64::from(rows));
+    (
+        (f64::from(column) + (f64::from(shuffled_row) + jitter_x) / rows) / columns,
+        (f64::from(row) + (f64::from(shuffled_column) + jitter_y) / columns) / rows,
+    )
+}
+
+/// Map `index` in [0, `length`) to another number in [0, `length`), by a
+/// permutation given by `seed`.
+fn permute(index: u32, length: u32, seed: u32) -> u32 {
+    if length <= 1 {
+        return 0;
+    }
+    let mask = u32::MAX >> (length - 1).leading_zeros();
+    let mut index = index;
+    // Numbers are hashed within the next power of two until they are in
+    // range, which keeps the mapping one to one.
+    loop {
+        index ^= seed;
+        index = index.wrapping_mul(0xe170893d);
+        index ^= seed >> 16;
+        index ^= (index & mask) >> 4;
+        index ^= seed >> 8;
+        index = index.wrapping_mul(0x0929eb3f);
+        index ^= seed >> 23;
+        index ^= (index & mask) >> 1;
+        index = index.wrapping_mul(1 | seed >> 27);
+        index = index.wrapping_mul(0x6935fa69);
+        index ^= (index & mask) >> 11;
+        index = index.wrapping_mul(0x74dcb303);
+        index ^= (index & mask) >> 2;
+        index = index.wrapping_mul(0x9e501cc3);
+        index ^= (index & mask) >> 2;
+        index = index.wrapping_mul(0xc860a3df);
+        index &= mask;
+        index ^= index >> 5;
+        if index < length {
+            break;
+        }
+    }
+    (index.wrapping_add(seed)) % length
+}
+
+/// Hash `value` with `seed` to a number in [0, 1).
+fn hash_to_unit(value: u32, seed: u32) -> f64 {
+    let mut value = value ^ seed;
+    value ^= value >> 17;
+    value ^= value >> 10;
+    value = value.wrapping_mul(0xb36534e5);
+    value ^= value >> 12;
+    value ^= value >> 21;
+    value = value.wrapping_mul(0x93fc4795);
+    value ^= 0xdf6e307f;
+    value ^= value >> 17;
+    value = value.wrapping_mul(1 | seed >> 18);
+    f64::from(value) / 4_294_967_296.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn permutations_are_one_to_one() {
+        for length in [1, 2, 3, 7, 16, 100] {
+            let mut permuted: Vec<u32> = (0..length).map(|i| permute(i, length, 12345)).collect();
+            permuted.sort_unstable();
+            assert!(permuted.into_iter().eq(0..length));
+        }
+    }
+
+    #[test]
+    fn samples_fall_in_different_strata() {
+        for count in [1, 5, 16, 30] {
+            let mut strata: Vec<u32> = (0..count)
+                .map(|index| (stratified_value(index, count, 7) * f64::from(count)) as u32)
+                .collect();
+            strata.sort_unstable();
+            assert!(strata.into_iter().eq(0..count));
+
+            // Each point is in a row and a column of its own, on the finer
+            // grid within the strata.
+            let points: Vec<_> = (0..count)
+                .map(|index| stratified_point(index, count, 7))
+                .collect();
+            let columns = f64::from(count).sqrt() as u32;
+            let cells = f64::from(columns * count.div_ceil(columns));
+            for (a, &(x, y)) in points.iter().enumerate() {
+                assert!((0.0..1.0).contains(&x) && (0.0..1.0).contains(&y));
+                for &(other_x, other_y) in &points[a + 1..] {
+                    assert_ne!((x * cells) as u32, (other_x * cells) as u32);
+                    assert_ne!((y * cells) as u32, (other_y * cells) as u32);
+                }
+            }
+        }
+    }
+}
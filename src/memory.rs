@@ -0,0 +1,130 @@
+//! Module for reporting how much memory a scene uses.
+//!
+//! Only the large parts of a scene are counted: the vertices, triangles and
+//! BVHs of meshes, and the photon map. Spheres, planes and the procedural
+//! textures take a few bytes each.
+
+use std::fmt;
+
+/// The memory used by a surface, in bytes.
+#[derive(Clone, Copy, Default)]
+pub struct MemoryUsage {
+    /// Positions, normals and texture coordinates of vertices.
+    pub vertices: usize,
+    /// Vertex indices and tangents of triangles.
+    pub triangles: usize,
+    /// The bounding volume hierarchy.
+    pub bvh: usize,
+}
+
+impl MemoryUsage {
+    pub fn total(&self) -> usize {
+        self.vertices + self.triangles + self.bvh
+    }
+}
+
+/// The memory used by a scene, made by `Scene::memory_report`.
+pub struct MemoryReport {
+    /// The memory used by each surface that uses any, by its index in the order
+    /// the surfaces were added to the scene.
+    pub surfaces: Vec<(usize, MemoryUsage)>,
+    /// The memory used by the photon map, in bytes.
+    pub photon_map: usize,
+}
+
+impl MemoryReport {
+    /// The total memory used, in bytes.
+    pub fn total(&self) -> usize {
+        self.surfaces
+            .iter()
+            .map(|(_, usage)| usage.total())
+            .sum::<usize>()
+            + self.photon_map
+    }
+
+    /// Check the memory used against `budget` bytes. Returns a warning naming
+    /// the largest user of memory if the budget is exceeded.
+    pub fn check_budget(&self, budget: usize) -> Option<String> {
+        let total = self.total();
+        if total <= budget {
+            return None;
+        }
+
+        let largest_surface = self
+            .surfaces
+            .iter()
+            .max_by_key(|(_, usage)| usage.total())
+            .filter(|(_, usage)| usage.total() > self.photon_map);
+        let largest = match largest_surface {
+            Some((index, usage)) => format!("surface {} ({})", index, megabytes(usage.total())),
+            None => format!("the photon map ({})", megabytes(self.photon_map)),
+        };
+        Some(format!(
+            "The scene uses {}, which is more than the budget of {}. The largest part is {}.",
+            megabytes(total),
+            megabytes(budget),
+            largest
+        ))
+    }
+}
+
+impl fmt::Display for MemoryReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (index, usage) in &self.surfaces {
+            writeln!(
+                f,
+                "Surface {}: {} ({} vertices, {} triangles, {} BVH)",
+                index,
+                megabytes(usage.total()),
+                megabytes(usage.vertices),
+                megabytes(usage.triangles),
+                megabytes(usage.bvh)
+            )?;
+        }
+        if self.photon_map > 0 {
+            writeln!(f, "Photon map: {}", megabytes(self.photon_map))?;
+        }
+        write!(f, "Total: {}", megabytes(self.total()))
+    }
+}
+
+/// Format a number of bytes in megabytes.
+fn megabytes(bytes: usize) -> String {
+    format!("{:.1} MB", bytes as f64 / 1e6)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn budget_warning_names_the_largest_part() {
+        let report = MemoryReport {
+            surfaces: vec![
+                (
+                    0,
+                    MemoryUsage {
+                        vertices: 1_000_000,
+                        triangles: 2_000_000,
+                        bvh: 1_000_000,
+                    },
+                ),
+                (
+                    2,
+                    MemoryUsage {
+                        vertices: 5_000_000,
+                        triangles: 0,
+                        bvh: 0,
+                    },
+                ),
+            ],
+            photon_map: 3_000_000,
+        };
+        assert_eq!(report.total(), 12_000_000);
+        assert!(report.check_budget(12_000_000).is_none());
+
+        let warning = report.check_budget(10_000_000).unwrap();
+        assert!(warning.contains("surface 2 (5.0 MB)"));
+        assert!(report.to_string().ends_with("Total: 12.0 MB"));
+    }
+}
@@ -0,0 +1,73 @@
+//! Module for recording how a render was made.
+//!
+//! The metadata of a render is saved as a JSON file next to the image, with
+//! the same name and the extension `.json`, so that the render can be traced
+//! back to its settings and reproduced later.
+
+use crate::image::ColorSpace;
+use crate::scene::Integrator;
+use serde::Serialize;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+/// A description of a render, made by `Scene::metadata`.
+#[derive(Serialize)]
+pub struct RenderMetadata {
+    /// The version of rustbeam that made the render.
+    pub version: String,
+    pub width: usize,
+    pub height: usize,
+    pub integrator: Integrator,
+    pub color_space: ColorSpace,
+    /// The number of photons traced from each light for caustics, if any.
+    pub caustic_photons: Option<usize>,
+    pub camera: CameraMetadata,
+    /// The hash of the content of the scene, as a hexadecimal number, unless
+    /// the scene contains something that can't be hashed.
+    pub scene_hash: Option<String>,
+    pub timings: Timings,
+    /// When the metadata was made, in seconds since the Unix epoch.
+    pub created: u64,
+}
+
+/// The placement and field of view of the camera.
+#[derive(Serialize)]
+pub struct CameraMetadata {
+    /// The transformation from camera coordinates to world coordinates, as
+    /// rows of a 4 x 4 matrix, as given by `Camera::matrix`. In camera
+    /// coordinates, x points right, y up and z backwards, as in Blender and
+    /// glTF, and the camera is at the origin.
+    pub matrix: [[f64; 4]; 4],
+    /// The horizontal field of view, in degrees.
+    pub horizontal_fov: f64,
+    /// The width of the part of the scene seen by orthographic cameras.
+    /// `None` for perspective cameras.
+    pub view_width: Option<f64>,
+    /// The exposure value at ISO 100, for scenes lit in physical units.
+    pub exposure: Option<f64>,
+}
+
+/// How long the parts of the render took, in seconds.
+#[derive(Serialize)]
+pub struct Timings {
+    pub photon_map: Option<f64>,
+    pub render: f64,
+}
+
+impl RenderMetadata {
+    /// Convert the metadata to pretty-printed JSON.
+    pub fn to_json(&self) -> Result<String, Box<dyn Error>> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Save the metadata next to the image `image_filename`, with the
+    /// extension replaced by `.json`.
+    pub fn save_sidecar(&self, image_filename: &str) -> Result<(), Box<dyn Error>> {
+        fs::write(
+            Path::new(image_filename).with_extension("json"),
+            self.to_json()?,
+        )?;
+        Ok(())
+    }
+}
@@ -0,0 +1,244 @@
+//! Module for telling the user that a render is done.
+//!
+//! Long renders can end with a desktop notification, or with their metadata
+//! posted as JSON to a webhook, such as that of a chat service, so that nobody
+//! has to keep an eye on the terminal. Webhooks at `http` URLs are posted to
+//! directly, and those at `https` URLs through `curl`, which must be
+//! installed.
+
+use crate::metadata::RenderMetadata;
+use std::error::Error;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+/// How long to wait for a webhook to connect, receive and answer.
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A way of telling that a render is done.
+pub enum Notifier {
+    /// A desktop notification, through `notify-send` on Linux and
+    /// `osascript` on macOS.
+    Desktop,
+    /// A POST request with the metadata of the render as JSON, to an `http`
+    /// URL, or to an `https` URL through `curl`.
+    Webhook(String),
+}
+
+impl Notifier {
+    /// Tell that the render described by `metadata` is done.
+    pub fn notify(&self, metadata: &RenderMetadata) -> Result<(), Box<dyn Error>> {
+        match self {
+            Notifier::Desktop => notify_desktop(&format!(
+                "Rendered {} x {} pixels in {:.1} seconds",
+                metadata.width, metadata.height, metadata.timings.render
+            )),
+            Notifier::Webhook(url) if url.starts_with("https://") => {
+                post_json_with_curl(url, &metadata.to_json()?)
+            }
+            Notifier::Webhook(url) => post_json(url, &metadata.to_json()?),
+        }
+    }
+}
+
+fn notify_desktop(message: &str) -> Result<(), Box<dyn Error>> {
+    let status = if cfg!(target_os = "macos") {
+        Command::new("osascript")
+            .arg("-e")
+            .arg(format!(
+                "display notification {:?} with title \"rustbeam\"",
+                message
+            ))
+            .status()?
+    } else if cfg!(unix) {
+        Command::new("notify-send")
+            .args(["rustbeam", message])
+            .status()?
+    } else {
+        return Err("Desktop notifications are not supported on this platform".into());
+    };
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("Desktop notification failed with {}", status).into())
+    }
+}
+
+/// Split an `http` URL into its host, port and path. IPv6 addresses are
+/// given in brackets, as in `http://[::1]:8080/`, and returned without them.
+fn parse_http_url(url: &str) -> Result<(&str, u16, &str), Box<dyn Error>> {
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| format!("Webhook URL {} must start with http://", url))?;
+    let (authority, path) = match rest.find('/') {
+        Some(index) => rest.split_at(index),
+        None => (rest, "/"),
+    };
+    let (host, port) = match authority.strip_prefix('[') {
+        Some(bracketed) => {
+            let (host, rest) = bracketed
+                .split_once(']')
+                .ok_or_else(|| format!("Webhook URL {} has an unclosed bracket", url))?;
+            match rest.strip_prefix(':') {
+                Some(port) => (host, port.parse()?),
+                None if rest.is_empty() => (host, 80),
+                None => return Err(format!("Webhook URL {} has an invalid host", url).into()),
+            }
+        }
+        None => match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse()?),
+            None => (authority, 80),
+        },
+    };
+    if host.is_empty() {
+        return Err(format!("Webhook URL {} has no host", url).into());
+    }
+    Ok((host, port, path))
+}
+
+/// Connect to `port` at `host`, trying each of its addresses in turn, and
+/// giving up on each after `WEBHOOK_TIMEOUT`.
+fn connect(host: &str, port: u16) -> Result<TcpStream, Box<dyn Error>> {
+    let mut last_error = None;
+    for address in (host, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, WEBHOOK_TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(error) => last_error = Some(error),
+        }
+    }
+    Err(match last_error {
+        Some(error) => error.into(),
+        None => format!("Found no address for {}", host).into(),
+    })
+}
+
+/// Post `json` to the `http` URL `url`, and check that the server accepts it.
+fn post_json(url: &str, json: &str) -> Result<(), Box<dyn Error>> {
+    let (host, port, path) = parse_http_url(url)?;
+    let mut stream = connect(host, port)?;
+    stream.set_read_timeout(Some(WEBHOOK_TIMEOUT))?;
+    stream.set_write_timeout(Some(WEBHOOK_TIMEOUT))?;
+
+    let authority = if host.contains(':') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    };
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        path,
+        authority,
+        json.len(),
+        json
+    )?;
+    stream.flush()?;
+
+    // Only the status line of the response is needed.
+    let mut response = Vec::new();
+    let mut buffer = [0; 256];
+    while !response.contains(&b'\n') {
+        let read = stream.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        response.extend_from_slice(&buffer[..read]);
+    }
+    let response = String::from_utf8_lossy(&response);
+    let status_line = response.lines().next().unwrap_or_default();
+    match status_line.split_whitespace().nth(1) {
+        Some(status) if status.starts_with('2') => Ok(()),
+        _ => Err(format!("Webhook answered {:?}", status_line).into()),
+    }
+}
+
+/// Post `json` to `url`, such as an `https` URL, with `curl`, which fails
+/// unless the server accepts it.
+fn post_json_with_curl(url: &str, json: &str) -> Result<(), Box<dyn Error>> {
+    let mut curl = Command::new("curl")
+        .args(["--silent", "--show-error", "--fail", "--max-time"])
+        .arg(WEBHOOK_TIMEOUT.as_secs().to_string())
+        .args(["--header", "Content-Type: application/json"])
+        .args(["--data-binary", "@-", url])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .map_err(|error| format!("Could not run curl for the webhook: {}", error))?;
+    if let Some(mut stdin) = curl.stdin.take() {
+        stdin.write_all(json.as_bytes())?;
+    }
+
+    let status = curl.wait()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("Posting to the webhook with curl failed with {}", status).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::BufRead;
+    use std::io::BufReader;
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn webhooks_receive_json() {
+        assert_eq!(
+            parse_http_url("http://example.com:8080/hooks/render").unwrap(),
+            ("example.com", 8080, "/hooks/render")
+        );
+        assert_eq!(
+            parse_http_url("http://example.com").unwrap(),
+            ("example.com", 80, "/")
+        );
+        assert_eq!(
+            parse_http_url("http://[::1]:8080/done").unwrap(),
+            ("::1", 8080, "/done")
+        );
+        assert_eq!(
+            parse_http_url("http://[fe80::1]").unwrap(),
+            ("fe80::1", 80, "/")
+        );
+        assert!(parse_http_url("http://[::1/").is_err());
+        assert!(parse_http_url("https://example.com/").is_err());
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut request = String::new();
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if let Some(value) = line.strip_prefix("Content-Length: ") {
+                    length = value.trim().parse().unwrap();
+                }
+                request.push_str(&line);
+                if line == "\r\n" {
+                    break;
+                }
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            reader
+                .get_mut()
+                .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+                .unwrap();
+            (request, String::from_utf8(body).unwrap())
+        });
+
+        let url = format!("http://127.0.0.1:{}/done", port);
+        post_json(&url, "{\"width\": 64}").unwrap();
+        let (request, body) = server.join().unwrap();
+        assert!(request.starts_with("POST /done HTTP/1.1\r\n"));
+        assert_eq!(body, "{\"width\": 64}");
+    }
+}
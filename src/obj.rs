@@ -0,0 +1,297 @@
+//! Module for loading and saving triangle meshes as Wavefront OBJ files.
+//!
+//! Only geometry is read: vertex positions, texture coordinates and faces.
+//! Polygons with more than three vertices are split into triangles. Normals
+//! are ignored, since `Mesh` computes its own. The parser is meant to be safe
+//! to use on untrusted files, so malformed input gives an `ObjError` instead
+//! of a panic, and the size of the mesh is limited. Saved files also have the
+//! vertex normals, for other tools to shade the mesh smoothly.
+
+use crate::math::Vector3;
+use crate::surfaces::Mesh;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// The largest OBJ file, in bytes, that `load_obj` reads.
+pub const MAX_FILE_SIZE: u64 = 1 << 30;
+/// The largest number of vertex positions or texture coordinates in a file.
+pub const MAX_VERTICES: usize = 1 << 24;
+/// The largest number of triangles in a file, after splitting polygons.
+pub const MAX_TRIANGLES: usize = 1 << 25;
+
+/// An error found while parsing an OBJ file.
+#[derive(Debug)]
+pub struct ObjError {
+    /// The line the error was found on, counting from 1.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "OBJ error on line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for ObjError {}
+
+/// The vertex positions, texture coordinates and triangles of a mesh.
+type Geometry = (Vec<Vector3>, Vec<(f64, f64)>, Vec<[usize; 3]>);
+
+/// Load a mesh from the OBJ file `filename`.
+pub fn load_obj(filename: &str) -> Result<Mesh, Box<dyn Error>> {
+    let (positions, uvs, triangles) = parse_geometry(&read_obj(filename)?)?;
+    Ok(Mesh::with_texture_coordinates(positions, uvs, triangles))
+}
+
+/// Like `load_obj`, but the BVH of the mesh is cached in the directory
+/// `cache_dir`. See `Mesh::with_bvh_cache`.
+pub fn load_obj_with_bvh_cache(filename: &str, cache_dir: &Path) -> Result<Mesh, Box<dyn Error>> {
+    let (positions, uvs, triangles) = parse_geometry(&read_obj(filename)?)?;
+    Ok(Mesh::with_bvh_cache(positions, uvs, triangles, cache_dir))
+}
+
+/// Read the OBJ file `filename`, if it isn't too large.
+fn read_obj(filename: &str) -> Result<String, Box<dyn Error>> {
+    let file_size = fs::metadata(filename)?.len();
+    if file_size > MAX_FILE_SIZE {
+        return Err(format!("{} is larger than {} bytes", filename, MAX_FILE_SIZE).into());
+    }
+
+    Ok(fs::read_to_string(filename)?)
+}
+
+/// Parse the contents of an OBJ file into a mesh.
+pub fn parse_obj(source: &str) -> Result<Mesh, ObjError> {
+    let (positions, uvs, triangles) = parse_geometry(source)?;
+    Ok(Mesh::with_texture_coordinates(positions, uvs, triangles))
+}
+
+/// Parse the geometry in the contents of an OBJ file.
+fn parse_geometry(source: &str) -> Result<Geometry, ObjError> {
+    let mut obj_positions = Vec::new();
+    let mut obj_uvs = Vec::new();
+
+    // OBJ files index positions and texture coordinates separately, while a
+    // mesh has one set of texture coordinates per vertex. Each distinct pair of
+    // indices becomes a vertex of the mesh.
+    let mut vertex_indices = HashMap::new();
+    let mut positions = Vec::new();
+    let mut uvs = Vec::new();
+    let mut triangles = Vec::new();
+
+    for (line_index, line) in source.lines().enumerate() {
+        let line_number = line_index + 1;
+        let error = |message: String| ObjError {
+            line: line_number,
+            message,
+        };
+
+        let line = line.split('#').next().unwrap_or("");
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            None => continue,
+            Some(keyword) => keyword,
+        };
+
+        match keyword {
+            "v" => {
+                let coordinates = parse_numbers(tokens, 3, 4).map_err(error)?;
+                if obj_positions.len() >= MAX_VERTICES {
+                    return Err(error(format!("More than {} positions", MAX_VERTICES)));
+                }
+                obj_positions.push(Vector3::from((
+                    coordinates[0],
+                    coordinates[1],
+                    coordinates[2],
+                )));
+            }
+            "vt" => {
+                let coordinates = parse_numbers(tokens, 1, 3).map_err(error)?;
+                if obj_uvs.len() >= MAX_VERTICES {
+                    return Err(error(format!(
+                        "More than {} texture coordinates",
+                        MAX_VERTICES
+                    )));
+                }
+                obj_uvs.push((coordinates[0], coordinates.get(1).copied().unwrap_or(0.0)));
+            }
+            "f" => {
+                let mut face = Vec::new();
+                for token in tokens {
+                    let mut indices = token.split('/');
+                    let position = resolve_index(indices.next(), obj_positions.len())
+                        .map_err(error)?
+                        .ok_or_else(|| error(format!("Missing position index in {}", token)))?;
+                    let uv = resolve_index(indices.next(), obj_uvs.len()).map_err(error)?;
+
+                    let vertex = *vertex_indices.entry((position, uv)).or_insert_with(|| {
+                        positions.push(obj_positions[position]);
+                        uvs.push(uv.map_or((0.0, 0.0), |uv| obj_uvs[uv]));
+                        positions.len() - 1
+                    });
+                    face.push(vertex);
+                }
+
+                if face.len() < 3 {
+                    return Err(error("Face with fewer than 3 vertices".to_string()));
+                }
+                if triangles.len() + face.len() - 2 > MAX_TRIANGLES {
+                    return Err(error(format!("More than {} triangles", MAX_TRIANGLES)));
+                }
+                for i in 1..face.len() - 1 {
+                    triangles.push([face[0], face[i], face[i + 1]]);
+                }
+            }
+            // Normals, groups, materials and other statements are not needed
+            // for the geometry.
+            _ => (),
+        }
+    }
+
+    Ok((positions, uvs, triangles))
+}
+
+/// Save `mesh` as the OBJ file `filename`.
+pub fn save_obj(mesh: &Mesh, filename: &str) -> Result<(), Box<dyn Error>> {
+    let mut writer = BufWriter::new(File::create(filename)?);
+    write_obj(mesh, &mut writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Write `mesh` in the OBJ format to `writer`. Each vertex has the same index
+/// for its position, texture coordinates and normal.
+pub fn write_obj(mesh: &Mesh, writer: &mut impl Write) -> Result<(), Box<dyn Error>> {
+    for position in mesh.positions() {
+        writeln!(writer, "v {} {} {}", position.x, position.y, position.z)?;
+    }
+    for uv in mesh.uvs() {
+        writeln!(writer, "vt {} {}", uv.0, uv.1)?;
+    }
+    for normal in mesh.normals() {
+        writeln!(writer, "vn {} {} {}", normal.x, normal.y, normal.z)?;
+    }
+    for triangle in mesh.triangles() {
+        let [a, b, c] = triangle.map(|vertex| vertex + 1);
+        writeln!(writer, "f {a}/{a}/{a} {b}/{b}/{b} {c}/{c}/{c}")?;
+    }
+    Ok(())
+}
+
+/// Parse between `min_count` and `max_count` finite numbers.
+fn parse_numbers<'a>(
+    tokens: impl Iterator<Item = &'a str>,
+    min_count: usize,
+    max_count: usize,
+) -> Result<Vec<f64>, String> {
+    let mut numbers = Vec::new();
+    for token in tokens {
+        if numbers.len() == max_count {
+            return Err(format!("Expected at most {} numbers", max_count));
+        }
+        match token.parse::<f64>() {
+            Ok(number) if number.is_finite() => numbers.push(number),
+            _ => return Err(format!("Invalid number {}", token)),
+        }
+    }
+
+    if numbers.len() < min_count {
+        Err(format!("Expected at least {} numbers", min_count))
+    } else {
+        Ok(numbers)
+    }
+}
+
+/// Convert a 1-based, or negative relative, OBJ index into an index into a
+/// list of length `len`. An empty or missing index gives `None`.
+fn resolve_index(token: Option<&str>, len: usize) -> Result<Option<usize>, String> {
+    let token = match token {
+        None | Some("") => return Ok(None),
+        Some(token) => token,
+    };
+
+    let index: i64 = token
+        .parse()
+        .map_err(|_| format!("Invalid index {}", token))?;
+    let resolved = if index > 0 {
+        index - 1
+    } else {
+        len as i64 + index
+    };
+
+    if index == 0 || resolved < 0 || resolved >= len as i64 {
+        Err(format!("Index {} out of range", index))
+    } else {
+        Ok(Some(resolved as usize))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_quad() {
+        let source = "
+            # A unit square split into two triangles.
+            v 0 0 0
+            v 1 0 0
+            v 1 1 0
+            v 0 1 0
+            vt 0 0
+            vt 1 1
+            f 1/1 2/1 3/2 -1/2
+        ";
+        assert!(parse_obj(source).is_ok());
+    }
+
+    #[test]
+    fn malformed_files_give_errors() {
+        let malformed = [
+            "v 1 2",
+            "v 1 2 nan",
+            "v 1 2 3 4 5",
+            "v 0 0 0\nf 1 1",
+            "v 0 0 0\nf 0 1 1",
+            "v 0 0 0\nf 1 1 2",
+            "v 0 0 0\nf 1 1 -2",
+            "v 0 0 0\nf 1/1 1 1",
+            "v 0 0 0\nf 1 1 99999999999999999999",
+            "v 0 0 0\nf /1 1 1",
+        ];
+
+        for source in malformed.iter() {
+            assert!(parse_obj(source).is_err(), "{:?} was accepted", source);
+        }
+    }
+
+    #[test]
+    fn saved_mesh_is_parsed_back() {
+        let positions = vec![
+            Vector3::from((0.0, 0.0, 0.0)),
+            Vector3::from((1.0, 0.0, 0.0)),
+            Vector3::from((0.0, 1.0, 0.5)),
+            Vector3::from((1.0, 1.0, 0.25)),
+        ];
+        let uvs = vec![(0.0, 0.0), (1.0, 0.0), (0.0, 1.0), (1.0, 1.0)];
+        let mesh = Mesh::with_texture_coordinates(positions, uvs, vec![[0, 1, 2], [2, 1, 3]]);
+
+        let mut source = Vec::new();
+        write_obj(&mesh, &mut source).unwrap();
+        let parsed = parse_obj(&String::from_utf8(source).unwrap()).unwrap();
+        assert_eq!(parsed.positions().len(), mesh.positions().len());
+        for (parsed, position) in parsed.positions().iter().zip(mesh.positions()) {
+            assert_eq!(
+                (parsed.x, parsed.y, parsed.z),
+                (position.x, position.y, position.z)
+            );
+        }
+        assert_eq!(parsed.uvs(), mesh.uvs());
+        assert_eq!(parsed.triangles(), mesh.triangles());
+    }
+}
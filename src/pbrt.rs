@@ -0,0 +1,1113 @@
+//! Module for importing scenes in the PBRT v3 scene format, for rendering the
+//! published PBRT test scenes for comparison.
+//!
+//! Only a subset of the format is read:
+//!
+//! - The camera: `LookAt` and the transforms, and `perspective` and
+//!   `orthographic` cameras, with `fov`, `lensradius` and `focaldistance`.
+//! - The resolution of the film, and the number of samples of each pixel.
+//! - Transforms and the attribute and transform stacks, and `Include`.
+//! - `sphere` and `trianglemesh` shapes.
+//! - `matte`, `plastic`, `metal`, `mirror` and `glass` materials, with
+//!   constant colors, and named materials.
+//! - `point` and `distant` lights, and `diffuse` area lights on spheres.
+//!
+//! Everything else is skipped with a warning, as are textures and colors
+//! given as spectra. PBRT has a left-handed coordinate system, so the scene is
+//! mirrored if needed, to make the image the same as PBRT's. Malformed input
+//! gives a `PbrtError` instead of a panic.
+
+use crate::lights::{PointLight, SphereLight, Sun};
+use crate::materials::Material;
+use crate::math::Vector3;
+use crate::scene::{Camera, Integrator, Projection, Scene};
+use crate::surfaces::{Mesh, Sphere};
+use crate::textures::Constant;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// How deeply `Include` directives may be nested.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// An error found while parsing a PBRT file.
+#[derive(Debug)]
+pub struct PbrtError {
+    /// The line the error was found on, counting from 1.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for PbrtError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PBRT error on line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for PbrtError {}
+
+/// A scene imported from a PBRT file, with the size of the image that PBRT
+/// renders of it.
+pub struct PbrtScene {
+    pub scene: Scene,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// Load the PBRT file `filename`. Files that it includes are relative to its
+/// directory. Returns the scene together with warnings about anything in the
+/// file that was skipped.
+pub fn load_pbrt(filename: &str) -> Result<(PbrtScene, Vec<String>), Box<dyn Error>> {
+    let source = fs::read_to_string(filename)?;
+    let base_dir = Path::new(filename)
+        .parent()
+        .unwrap_or_else(|| Path::new(""));
+    parse_pbrt(&source, base_dir)
+}
+
+/// Parse the contents of a PBRT file. Files that it includes are relative to
+/// `base_dir`.
+pub fn parse_pbrt(
+    source: &str,
+    base_dir: &Path,
+) -> Result<(PbrtScene, Vec<String>), Box<dyn Error>> {
+    let tokens = tokenize(source, base_dir, 0)?;
+    let mut importer = Importer::new();
+    let mut tokens = tokens.into_iter().peekable();
+    while let Some(token) = tokens.next() {
+        let directive = match token.kind {
+            TokenKind::Word(word) => word,
+            _ => return Err(token.error("Expected a directive").into()),
+        };
+        // The arguments are the tokens up to the next directive.
+        let mut arguments = Vec::new();
+        while let Some(argument) = tokens.next_if(|token| !token.is_word()) {
+            arguments.push(argument);
+        }
+        importer.directive(&directive, token.line, &arguments)?;
+    }
+    Ok(importer.finish())
+}
+
+/// A token of a PBRT file, and the line it is on.
+#[derive(Clone, Debug)]
+struct Token {
+    kind: TokenKind,
+    line: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum TokenKind {
+    Word(String),
+    Str(String),
+    Number(f64),
+    Open,
+    Close,
+}
+
+impl Token {
+    fn is_word(&self) -> bool {
+        matches!(self.kind, TokenKind::Word(_))
+    }
+
+    fn error(&self, message: &str) -> PbrtError {
+        PbrtError {
+            line: self.line,
+            message: message.to_string(),
+        }
+    }
+}
+
+/// Split `source` into tokens, with the tokens of included files, relative to
+/// `base_dir`, in place of the `Include` directives. Included tokens have
+/// the line of the directive.
+fn tokenize(source: &str, base_dir: &Path, depth: usize) -> Result<Vec<Token>, PbrtError> {
+    let mut tokens = Vec::new();
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        let mut chars = text.chars().peekable();
+        while let Some(&c) = chars.peek() {
+            let kind = match c {
+                '#' => break,
+                c if c.is_whitespace() => {
+                    chars.next();
+                    continue;
+                }
+                '[' | ']' => {
+                    chars.next();
+                    if c == '[' {
+                        TokenKind::Open
+                    } else {
+                        TokenKind::Close
+                    }
+                }
+                '"' => {
+                    chars.next();
+                    let string: String = chars.by_ref().take_while(|&c| c != '"').collect();
+                    TokenKind::Str(string)
+                }
+                _ => {
+                    let mut word = String::new();
+                    while let Some(&c) = chars.peek() {
+                        if c.is_whitespace() || "[]\"#".contains(c) {
+                            break;
+                        }
+                        word.push(c);
+                        chars.next();
+                    }
+                    match word.parse() {
+                        Ok(number) => TokenKind::Number(number),
+                        Err(_) => TokenKind::Word(word),
+                    }
+                }
+            };
+            tokens.push(Token { kind, line });
+        }
+    }
+
+    // Splice in included files.
+    let mut spliced = Vec::with_capacity(tokens.len());
+    let mut tokens = tokens.into_iter().peekable();
+    while let Some(token) = tokens.next() {
+        if token.kind != TokenKind::Word("Include".to_string()) {
+            spliced.push(token);
+            continue;
+        }
+        let filename = match tokens.next() {
+            Some(Token {
+                kind: TokenKind::Str(filename),
+                ..
+            }) => filename,
+            _ => return Err(token.error("Include needs a file name")),
+        };
+        if depth >= MAX_INCLUDE_DEPTH {
+            return Err(token.error("Includes are nested too deeply"));
+        }
+        let path = base_dir.join(&filename);
+        let source = fs::read_to_string(&path)
+            .map_err(|error| token.error(&format!("Can't read {filename}: {error}")))?;
+        let directory = path.parent().map_or_else(PathBuf::new, Path::to_path_buf);
+        let included = tokenize(&source, &directory, depth + 1).map_err(|error| {
+            token.error(&format!(
+                "In {filename}, on line {}: {}",
+                error.line, error.message
+            ))
+        })?;
+        spliced.extend(included.into_iter().map(|included| Token {
+            line: token.line,
+            ..included
+        }));
+    }
+    Ok(spliced)
+}
+
+/// A value in the parameter list of a directive.
+#[derive(Clone, Debug)]
+enum Value {
+    Number(f64),
+    Str(String),
+}
+
+/// A parameter of a directive, such as `"rgb Kd" [0.5 0.5 0.5]`.
+struct Parameter {
+    kind: String,
+    name: String,
+    values: Vec<Value>,
+}
+
+/// The arguments of a directive: the values before the parameter list, and
+/// the parameters.
+struct Arguments {
+    values: Vec<Value>,
+    parameters: Vec<Parameter>,
+    line: usize,
+}
+
+impl Arguments {
+    fn parse(tokens: &[Token], line: usize) -> Result<Self, PbrtError> {
+        let error = |message: &str| PbrtError {
+            line,
+            message: message.to_string(),
+        };
+        // Read one value, or a list of values in brackets.
+        let read_values = |index: &mut usize| -> Result<Vec<Value>, PbrtError> {
+            let mut values = Vec::new();
+            let bracketed = tokens[*index].kind == TokenKind::Open;
+            if bracketed {
+                *index += 1;
+            }
+            while let Some(token) = tokens.get(*index) {
+                match &token.kind {
+                    TokenKind::Number(number) => values.push(Value::Number(*number)),
+                    TokenKind::Str(string) => values.push(Value::Str(string.clone())),
+                    TokenKind::Close if bracketed => {
+                        *index += 1;
+                        return Ok(values);
+                    }
+                    _ => return Err(token.error("Unexpected token in a parameter list")),
+                }
+                *index += 1;
+                if !bracketed {
+                    return Ok(values);
+                }
+            }
+            if bracketed {
+                Err(error("Unclosed bracket"))
+            } else {
+                Ok(values)
+            }
+        };
+
+        let mut values = Vec::new();
+        let mut index = 0;
+        // Values come before the parameters, which start with a string with
+        // a type and a name.
+        while index < tokens.len() {
+            let is_parameter = matches!(
+                &tokens[index].kind,
+                TokenKind::Str(string) if string.split_whitespace().count() == 2
+            );
+            if is_parameter {
+                break;
+            }
+            values.extend(read_values(&mut index)?);
+        }
+
+        let mut parameters = Vec::new();
+        while index < tokens.len() {
+            let declaration = match &tokens[index].kind {
+                TokenKind::Str(declaration) => declaration.clone(),
+                _ => return Err(tokens[index].error("Expected a parameter")),
+            };
+            let mut words = declaration.split_whitespace();
+            let (kind, name) = match (words.next(), words.next(), words.next()) {
+                (Some(kind), Some(name), None) => (kind.to_string(), name.to_string()),
+                _ => return Err(tokens[index].error("Expected a parameter type and name")),
+            };
+            index += 1;
+            if index >= tokens.len() {
+                return Err(error(&format!("The parameter {name} has no value")));
+            }
+            let values = read_values(&mut index)?;
+            parameters.push(Parameter { kind, name, values });
+        }
+
+        Ok(Self {
+            values,
+            parameters,
+            line,
+        })
+    }
+
+    fn error(&self, message: &str) -> PbrtError {
+        PbrtError {
+            line: self.line,
+            message: message.to_string(),
+        }
+    }
+
+    /// The numbers before the parameter list, of which there must be `count`.
+    fn numbers(&self, count: usize, directive: &str) -> Result<Vec<f64>, PbrtError> {
+        let numbers: Vec<f64> = self
+            .values
+            .iter()
+            .filter_map(|value| match value {
+                Value::Number(number) => Some(*number),
+                Value::Str(_) => None,
+            })
+            .collect();
+        if numbers.len() == count && numbers.len() == self.values.len() {
+            Ok(numbers)
+        } else {
+            Err(self.error(&format!("{directive} needs {count} numbers")))
+        }
+    }
+
+    /// The string before the parameter list, such as the type of a shape.
+    fn name(&self, directive: &str) -> Result<&str, PbrtError> {
+        match self.values.first() {
+            Some(Value::Str(name)) => Ok(name),
+            _ => Err(self.error(&format!("{directive} needs a name"))),
+        }
+    }
+
+    fn parameter(&self, name: &str) -> Option<&Parameter> {
+        self.parameters
+            .iter()
+            .find(|parameter| parameter.name == name)
+    }
+
+    fn numbers_of(&self, name: &str) -> Result<Option<Vec<f64>>, PbrtError> {
+        let parameter = match self.parameter(name) {
+            Some(parameter) => parameter,
+            None => return Ok(None),
+        };
+        parameter
+            .values
+            .iter()
+            .map(|value| match value {
+                Value::Number(number) => Ok(*number),
+                Value::Str(_) => Err(self.error(&format!("The parameter {name} must be numbers"))),
+            })
+            .collect::<Result<_, _>>()
+            .map(Some)
+    }
+
+    fn float(&self, name: &str, default: f64) -> Result<f64, PbrtError> {
+        match self.numbers_of(name)?.as_deref() {
+            None => Ok(default),
+            Some([value]) => Ok(*value),
+            Some(_) => Err(self.error(&format!("The parameter {name} must be one number"))),
+        }
+    }
+
+    fn point(&self, name: &str, default: Vector3) -> Result<Vector3, PbrtError> {
+        match self.numbers_of(name)?.as_deref() {
+            None => Ok(default),
+            Some([x, y, z]) => Ok(Vector3::from((*x, *y, *z))),
+            Some(_) => Err(self.error(&format!("The parameter {name} must be three numbers"))),
+        }
+    }
+
+    fn string(&self, name: &str) -> Option<&str> {
+        match self.parameter(name)?.values.first() {
+            Some(Value::Str(string)) => Some(string),
+            _ => None,
+        }
+    }
+
+    /// The color `name`, or `default` if it isn't given, or is given as a
+    /// spectrum or texture, which gives a warning.
+    fn color(
+        &self,
+        name: &str,
+        default: Vector3,
+        warnings: &mut Vec<String>,
+    ) -> Result<Vector3, PbrtError> {
+        let kind = match self.parameter(name) {
+            Some(parameter) => parameter.kind.as_str(),
+            None => return Ok(default),
+        };
+        match kind {
+            "rgb" | "color" => match self.numbers_of(name)?.as_deref() {
+                Some([r, g, b]) => Ok(Vector3::from((*r, *g, *b))),
+                _ => Err(self.error(&format!("The color {name} must be three numbers"))),
+            },
+            "float" => Ok(self.float(name, 0.0)? * Vector3::ones()),
+            _ => {
+                warnings.push(format!(
+                    "Line {}: {kind} {name} isn't supported, and was replaced by a constant color",
+                    self.line
+                ));
+                Ok(default)
+            }
+        }
+    }
+}
+
+/// A 4 x 4 matrix, as rows.
+type Matrix = [[f64; 4]; 4];
+
+const IDENTITY: Matrix = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+
+fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    let mut product = [[0.0; 4]; 4];
+    for (i, row) in product.iter_mut().enumerate() {
+        for (j, element) in row.iter_mut().enumerate() {
+            *element = (0..4).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    product
+}
+
+/// Invert `matrix` by Gauss-Jordan elimination, or return `None` if it is
+/// singular.
+fn invert(matrix: &Matrix) -> Option<Matrix> {
+    let mut a = *matrix;
+    let mut inverse = IDENTITY;
+    for column in 0..4 {
+        let pivot =
+            (column..4).max_by(|&i, &j| a[i][column].abs().total_cmp(&a[j][column].abs()))?;
+        if a[pivot][column].abs() < 1e-12 {
+            return None;
+        }
+        a.swap(column, pivot);
+        inverse.swap(column, pivot);
+        let scale = 1.0 / a[column][column];
+        for j in 0..4 {
+            a[column][j] *= scale;
+            inverse[column][j] *= scale;
+        }
+        for i in (0..4).filter(|&i| i != column) {
+            let factor = a[i][column];
+            for j in 0..4 {
+                a[i][j] -= factor * a[column][j];
+                inverse[i][j] -= factor * inverse[column][j];
+            }
+        }
+    }
+    Some(inverse)
+}
+
+fn transform_point(matrix: &Matrix, point: Vector3) -> Vector3 {
+    let row = |i: usize| {
+        matrix[i][0] * point.x + matrix[i][1] * point.y + matrix[i][2] * point.z + matrix[i][3]
+    };
+    let w = row(3);
+    (1.0 / w) * Vector3::from((row(0), row(1), row(2)))
+}
+
+fn transform_vector(matrix: &Matrix, vector: Vector3) -> Vector3 {
+    let row =
+        |i: usize| matrix[i][0] * vector.x + matrix[i][1] * vector.y + matrix[i][2] * vector.z;
+    Vector3::from((row(0), row(1), row(2)))
+}
+
+fn translation(d: Vector3) -> Matrix {
+    [
+        [1.0, 0.0, 0.0, d.x],
+        [0.0, 1.0, 0.0, d.y],
+        [0.0, 0.0, 1.0, d.z],
+        [0.0, 0.0, 0.0, 1.0],
+    ]
+}
+
+fn scaling(s: Vector3) -> Matrix {
+    [
+        [s.x, 0.0, 0.0, 0.0],
+        [0.0, s.y, 0.0, 0.0],
+        [0.0, 0.0, s.z, 0.0],
+        [0.0, 0.0, 0.0, 1.0],
+    ]
+}
+
+/// The rotation by `degrees` around `axis`, as in PBRT.
+fn rotation(degrees: f64, axis: Vector3) -> Matrix {
+    let a = axis.normalize();
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    [
+        [
+            a.x * a.x + (1.0 - a.x * a.x) * cos,
+            a.x * a.y * (1.0 - cos) - a.z * sin,
+            a.x * a.z * (1.0 - cos) + a.y * sin,
+            0.0,
+        ],
+        [
+            a.x * a.y * (1.0 - cos) + a.z * sin,
+            a.y * a.y + (1.0 - a.y * a.y) * cos,
+            a.y * a.z * (1.0 - cos) - a.x * sin,
+            0.0,
+        ],
+        [
+            a.x * a.z * (1.0 - cos) - a.y * sin,
+            a.y * a.z * (1.0 - cos) + a.x * sin,
+            a.z * a.z + (1.0 - a.z * a.z) * cos,
+            0.0,
+        ],
+        [0.0, 0.0, 0.0, 1.0],
+    ]
+}
+
+/// The camera-from-world matrix of a PBRT camera at `eye`, looking at
+/// `target`, with `up` up, in PBRT's left-handed camera space.
+fn look_at(eye: Vector3, target: Vector3, up: Vector3) -> Option<Matrix> {
+    let direction = (target - eye).normalize();
+    let right = up.normalize().cross(direction);
+    if right.norm2() <= 1e-12 {
+        return None;
+    }
+    let right = right.normalize();
+    let up = direction.cross(right);
+    invert(&[
+        [right.x, up.x, direction.x, eye.x],
+        [right.y, up.y, direction.y, eye.y],
+        [right.z, up.z, direction.z, eye.z],
+        [0.0, 0.0, 0.0, 1.0],
+    ])
+}
+
+/// A material, as given by a `Material` or `MakeNamedMaterial` directive.
+#[derive(Clone)]
+struct MaterialSpec {
+    albedo: Vector3,
+    roughness: f64,
+    metallic: f64,
+    transmission: f64,
+    ior: f64,
+}
+
+impl Default for MaterialSpec {
+    /// PBRT's default material: matte, with a reflectance of 0.5.
+    fn default() -> Self {
+        Self {
+            albedo: 0.5 * Vector3::ones(),
+            roughness: 0.5,
+            metallic: 0.0,
+            transmission: 0.0,
+            ior: 1.5,
+        }
+    }
+}
+
+impl MaterialSpec {
+    fn parse(
+        kind: &str,
+        arguments: &Arguments,
+        warnings: &mut Vec<String>,
+    ) -> Result<Self, PbrtError> {
+        let default = Self::default();
+        Ok(match kind {
+            "matte" => Self {
+                albedo: arguments.color("Kd", default.albedo, warnings)?,
+                ..default
+            },
+            "plastic" => Self {
+                albedo: arguments.color("Kd", 0.25 * Vector3::ones(), warnings)?,
+                roughness: arguments.float("roughness", 0.1)?,
+                ..default
+            },
+            // The reflectance of copper, PBRT's default metal.
+            "metal" => Self {
+                albedo: Vector3::from((0.95, 0.64, 0.54)),
+                roughness: arguments.float("roughness", 0.01)?,
+                metallic: 1.0,
+                ..default
+            },
+            "mirror" => Self {
+                albedo: arguments.color("Kr", 0.9 * Vector3::ones(), warnings)?,
+                roughness: 0.0,
+                metallic: 1.0,
+                ..default
+            },
+            "glass" => Self {
+                albedo: arguments.color("Kt", Vector3::ones(), warnings)?,
+                transmission: 1.0,
+                ior: arguments.float("eta", arguments.float("index", 1.5)?)?,
+                ..default
+            },
+            _ => {
+                warnings.push(format!(
+                    "Line {}: the material {kind} isn't supported, and was replaced by a matte one",
+                    arguments.line
+                ));
+                Self {
+                    albedo: arguments.color("Kd", default.albedo, warnings)?,
+                    ..default
+                }
+            }
+        })
+    }
+
+    fn build(&self) -> Material {
+        Material::new(Constant::new(self.albedo))
+            .with_roughness(self.roughness)
+            .with_metallic(self.metallic)
+            .with_transmission(self.transmission)
+            .with_ior(self.ior)
+    }
+}
+
+/// A camera, as given by a `Camera` directive.
+#[derive(Clone)]
+struct CameraSpec {
+    world_from_camera: Matrix,
+    kind: String,
+    /// The field of view of the shorter side of the image, in degrees.
+    fov: f64,
+    lens_radius: f64,
+    focus_distance: f64,
+}
+
+impl Default for CameraSpec {
+    /// The camera that PBRT uses if there is no `Camera` directive.
+    fn default() -> Self {
+        Self {
+            world_from_camera: IDENTITY,
+            kind: "perspective".to_string(),
+            fov: 90.0,
+            lens_radius: 0.0,
+            focus_distance: 1e6,
+        }
+    }
+}
+
+/// What `AttributeBegin` saves, and `AttributeEnd` restores.
+#[derive(Clone)]
+struct Attributes {
+    transform: Matrix,
+    material: MaterialSpec,
+    /// The radiance emitted by shapes, if they are area lights.
+    area_light: Option<Vector3>,
+}
+
+/// The state of an import.
+struct Importer {
+    scene: Scene,
+    warnings: Vec<String>,
+    attributes: Attributes,
+    /// Saved attributes, and whether they were saved by `TransformBegin`,
+    /// which only saves the transform.
+    stack: Vec<(Attributes, bool)>,
+    named_materials: HashMap<String, MaterialSpec>,
+    camera: Option<CameraSpec>,
+    /// Mirrors the scene, as PBRT's camera is left-handed.
+    mirror: Matrix,
+    width: usize,
+    height: usize,
+    samples_per_pixel: u32,
+    integrator: Option<(String, u32)>,
+}
+
+impl Importer {
+    fn new() -> Self {
+        Self {
+            scene: Scene::new(),
+            warnings: Vec::new(),
+            attributes: Attributes {
+                transform: IDENTITY,
+                material: MaterialSpec::default(),
+                area_light: None,
+            },
+            stack: Vec::new(),
+            named_materials: HashMap::new(),
+            camera: None,
+            mirror: IDENTITY,
+            width: 640,
+            height: 480,
+            samples_per_pixel: 16,
+            integrator: None,
+        }
+    }
+
+    fn warn(&mut self, line: usize, message: &str) {
+        self.warnings.push(format!("Line {line}: {message}"));
+    }
+
+    /// Apply `matrix` after the current transform.
+    fn concatenate(&mut self, matrix: &Matrix) {
+        self.attributes.transform = multiply(&self.attributes.transform, matrix);
+    }
+
+    fn directive(
+        &mut self,
+        directive: &str,
+        line: usize,
+        tokens: &[Token],
+    ) -> Result<(), PbrtError> {
+        let arguments = Arguments::parse(tokens, line)?;
+        match directive {
+            "Identity" => self.attributes.transform = IDENTITY,
+            "Translate" => {
+                let d = arguments.numbers(3, directive)?;
+                self.concatenate(&translation(Vector3::from((d[0], d[1], d[2]))));
+            }
+            "Scale" => {
+                let s = arguments.numbers(3, directive)?;
+                self.concatenate(&scaling(Vector3::from((s[0], s[1], s[2]))));
+            }
+            "Rotate" => {
+                let r = arguments.numbers(4, directive)?;
+                let axis = Vector3::from((r[1], r[2], r[3]));
+                if axis.norm2() == 0.0 {
+                    return Err(arguments.error("Rotate needs a non-zero axis"));
+                }
+                self.concatenate(&rotation(r[0], axis));
+            }
+            "LookAt" => {
+                let v = arguments.numbers(9, directive)?;
+                let matrix = look_at(
+                    Vector3::from((v[0], v[1], v[2])),
+                    Vector3::from((v[3], v[4], v[5])),
+                    Vector3::from((v[6], v[7], v[8])),
+                )
+                .ok_or_else(|| arguments.error("LookAt needs distinct directions"))?;
+                self.concatenate(&matrix);
+            }
+            "Transform" | "ConcatTransform" => {
+                // PBRT lists the elements column by column.
+                let m = arguments.numbers(16, directive)?;
+                let mut matrix = [[0.0; 4]; 4];
+                for (i, &element) in m.iter().enumerate() {
+                    matrix[i % 4][i / 4] = element;
+                }
+                if directive == "Transform" {
+                    self.attributes.transform = matrix;
+                } else {
+                    self.concatenate(&matrix);
+                }
+            }
+            "Camera" => self.camera(&arguments)?,
+            "Film" => {
+                self.width = arguments.float("xresolution", 640.0)? as usize;
+                self.height = arguments.float("yresolution", 480.0)? as usize;
+                if self.width == 0 || self.height == 0 {
+                    return Err(arguments.error("The film must have a resolution"));
+                }
+            }
+            "Sampler" => {
+                self.samples_per_pixel = arguments.float("pixelsamples", 16.0)?.max(1.0) as u32;
+            }
+            "Integrator" => {
+                let name = arguments.name(directive)?.to_string();
+                let max_depth = arguments.float("maxdepth", 5.0)?.max(0.0) as u32;
+                self.integrator = Some((name, max_depth));
+            }
+            "WorldBegin" => {
+                self.attributes.transform = IDENTITY;
+                self.place_camera(line);
+            }
+            "WorldEnd" => {}
+            "AttributeBegin" => self.stack.push((self.attributes.clone(), false)),
+            "TransformBegin" => self.stack.push((self.attributes.clone(), true)),
+            "AttributeEnd" | "TransformEnd" => {
+                let (attributes, transform_only) = self
+                    .stack
+                    .pop()
+                    .ok_or_else(|| arguments.error(&format!("{directive} without a begin")))?;
+                if transform_only {
+                    self.attributes.transform = attributes.transform;
+                } else {
+                    self.attributes = attributes;
+                }
+            }
+            "Material" => {
+                let kind = arguments.name(directive)?.to_string();
+                self.attributes.material =
+                    MaterialSpec::parse(&kind, &arguments, &mut self.warnings)?;
+            }
+            "MakeNamedMaterial" => {
+                let name = arguments.name(directive)?.to_string();
+                let kind = arguments.string("type").unwrap_or("matte").to_string();
+                let material = MaterialSpec::parse(&kind, &arguments, &mut self.warnings)?;
+                self.named_materials.insert(name, material);
+            }
+            "NamedMaterial" => {
+                let name = arguments.name(directive)?;
+                match self.named_materials.get(name) {
+                    Some(material) => self.attributes.material = material.clone(),
+                    None => {
+                        let message = format!("The material {name} isn't defined");
+                        return Err(arguments.error(&message));
+                    }
+                }
+            }
+            "AreaLightSource" => {
+                let name = arguments.name(directive)?.to_string();
+                if name != "diffuse" {
+                    self.warn(line, &format!("The area light {name} isn't supported"));
+                }
+                let radiance = arguments.color("L", Vector3::ones(), &mut self.warnings)?;
+                let scale = arguments.color("scale", Vector3::ones(), &mut self.warnings)?;
+                self.attributes.area_light = Some(radiance.elementwise_mul(scale));
+            }
+            "LightSource" => self.light(&arguments)?,
+            "Shape" => self.shape(&arguments)?,
+            _ => self.warn(
+                line,
+                &format!("{directive} isn't supported, and was skipped"),
+            ),
+        }
+        Ok(())
+    }
+
+    fn camera(&mut self, arguments: &Arguments) -> Result<(), PbrtError> {
+        let name = arguments.name("Camera")?.to_string();
+        if name != "perspective" && name != "orthographic" {
+            let message = format!("The camera {name} isn't supported, and is perspective instead");
+            self.warn(arguments.line, &message);
+        }
+        if arguments.parameter("screenwindow").is_some() {
+            self.warn(arguments.line, "screenwindow isn't supported");
+        }
+        let world_from_camera = invert(&self.attributes.transform)
+            .ok_or_else(|| arguments.error("The camera transform can't be inverted"))?;
+        self.camera = Some(CameraSpec {
+            world_from_camera,
+            kind: name,
+            fov: arguments.float("fov", 90.0)?,
+            lens_radius: arguments.float("lensradius", 0.0)?,
+            focus_distance: arguments.float("focaldistance", 1e6)?,
+        });
+        Ok(())
+    }
+
+    /// Place the camera of the scene where the PBRT camera is, mirroring the
+    /// scene if the camera is left-handed.
+    fn place_camera(&mut self, line: usize) {
+        let spec = self.camera.clone().unwrap_or_default();
+        let matrix = spec.world_from_camera;
+        let eye = transform_point(&matrix, Vector3::zero());
+        let direction = transform_vector(&matrix, Vector3::k());
+        let up = transform_vector(&matrix, Vector3::j());
+        let right = transform_vector(&matrix, Vector3::i());
+        if direction.norm2() == 0.0 || up.norm2() == 0.0 {
+            self.warn(line, "The camera transform is degenerate, and was ignored");
+            return;
+        }
+        let camera = Camera::look_at(eye, eye + direction, up);
+
+        // The image is to the right of the camera along PBRT's x-axis. If
+        // that is to the left of ours, the scene is mirrored across the plane
+        // through the camera and its up and forward directions.
+        if right.dot(camera.right()) < 0.0 {
+            let n = camera.right();
+            let mut mirror = IDENTITY;
+            for (i, row) in mirror.iter_mut().take(3).enumerate() {
+                for (j, element) in row.iter_mut().take(3).enumerate() {
+                    *element -= 2.0 * n.component(i) * n.component(j);
+                }
+                row[3] = 2.0 * eye.dot(n) * n.component(i);
+            }
+            self.mirror = mirror;
+        }
+
+        self.scene.set_camera(camera.position, camera.orientation);
+        let aspect_ratio = self.width as f64 / self.height as f64;
+        if spec.kind == "orthographic" {
+            // The shorter side of the image spans from -1 to 1.
+            let view_width = 2.0 * aspect_ratio.max(1.0);
+            let scale = right.norm();
+            self.scene.set_camera_projection(Projection::Orthographic {
+                view_width: view_width * scale,
+            });
+        } else if aspect_ratio >= 1.0 {
+            // The field of view is that of the shorter side.
+            self.scene.set_vertical_fov(spec.fov, aspect_ratio);
+        } else {
+            self.scene.set_horizontal_fov(spec.fov);
+        }
+        if spec.lens_radius > 0.0 {
+            self.scene
+                .set_depth_of_field(spec.lens_radius, spec.focus_distance);
+        }
+    }
+
+    /// The transform from the object space of shapes and lights to the scene.
+    fn object_to_scene(&self) -> Matrix {
+        multiply(&self.mirror, &self.attributes.transform)
+    }
+
+    fn light(&mut self, arguments: &Arguments) -> Result<(), PbrtError> {
+        let name = arguments.name("LightSource")?;
+        let matrix = self.object_to_scene();
+        let scale = arguments.color("scale", Vector3::ones(), &mut self.warnings)?;
+        match name {
+            "point" => {
+                let intensity = arguments.color("I", Vector3::ones(), &mut self.warnings)?;
+                let position = transform_point(&matrix, arguments.point("from", Vector3::zero())?);
+                self.scene.add_light(PointLight::new(
+                    position,
+                    intensity.elementwise_mul(scale),
+                    1.0,
+                ));
+            }
+            "distant" => {
+                let radiance = arguments.color("L", Vector3::ones(), &mut self.warnings)?;
+                let from = arguments.point("from", Vector3::zero())?;
+                let to = arguments.point("to", Vector3::k())?;
+                let direction = transform_vector(&matrix, to - from);
+                if direction.norm2() == 0.0 {
+                    return Err(arguments.error("A distant light needs a direction"));
+                }
+                self.scene.add_light(Sun::new(
+                    radiance.elementwise_mul(scale),
+                    direction.normalize(),
+                ));
+            }
+            _ => {
+                let message = format!("The light {name} isn't supported, and was skipped");
+                self.warn(arguments.line, &message);
+            }
+        }
+        Ok(())
+    }
+
+    fn shape(&mut self, arguments: &Arguments) -> Result<(), PbrtError> {
+        let name = arguments.name("Shape")?;
+        let matrix = self.object_to_scene();
+        match name {
+            "sphere" => {
+                let radius = arguments.float("radius", 1.0)?;
+                if arguments.parameter("zmin").is_some()
+                    || arguments.parameter("zmax").is_some()
+                    || arguments.parameter("phimax").is_some()
+                {
+                    self.warn(arguments.line, "Partial spheres are rendered whole");
+                }
+                let center = transform_point(&matrix, Vector3::zero());
+                let radius = radius * transform_vector(&matrix, Vector3::i()).norm();
+                if let Some(radiance) = self.attributes.area_light {
+                    self.scene
+                        .add_light(SphereLight::new(center, radius, radiance));
+                } else {
+                    self.scene.add_surface_with_material(
+                        Sphere::new(center, radius),
+                        self.attributes.material.build(),
+                    );
+                }
+            }
+            "trianglemesh" => {
+                let mesh = self.mesh(arguments, &matrix)?;
+                if self.attributes.area_light.is_some() {
+                    let message = "Area lights are only supported on spheres, so the mesh doesn't \
+                                   emit light";
+                    self.warn(arguments.line, message);
+                }
+                self.scene
+                    .add_surface_with_material(mesh, self.attributes.material.build());
+            }
+            _ => {
+                let message = format!("The shape {name} isn't supported, and was skipped");
+                self.warn(arguments.line, &message);
+            }
+        }
+        Ok(())
+    }
+
+    fn mesh(&self, arguments: &Arguments, matrix: &Matrix) -> Result<Mesh, PbrtError> {
+        let points = arguments
+            .numbers_of("P")?
+            .ok_or_else(|| arguments.error("A triangle mesh needs the points P"))?;
+        let indices = arguments
+            .numbers_of("indices")?
+            .ok_or_else(|| arguments.error("A triangle mesh needs indices"))?;
+        if points.len() % 3 != 0 || indices.len() % 3 != 0 {
+            return Err(arguments.error("A triangle mesh needs whole points and triangles"));
+        }
+        let positions: Vec<Vector3> = points
+            .chunks(3)
+            .map(|p| transform_point(matrix, Vector3::from((p[0], p[1], p[2]))))
+            .collect();
+        let triangles = indices
+            .chunks(3)
+            .map(|triangle| {
+                let mut vertices = [0; 3];
+                for (vertex, &index) in vertices.iter_mut().zip(triangle) {
+                    if !(index >= 0.0 && (index as usize) < positions.len() && index.fract() == 0.0)
+                    {
+                        return Err(arguments.error(&format!("Invalid vertex index {index}")));
+                    }
+                    *vertex = index as usize;
+                }
+                Ok(vertices)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let uvs = match arguments.numbers_of("uv")? {
+            Some(uvs) => Some(uvs),
+            None => arguments.numbers_of("st")?,
+        };
+        Ok(match uvs {
+            Some(uvs) if uvs.len() == 2 * positions.len() => {
+                let uvs = uvs.chunks(2).map(|uv| (uv[0], uv[1])).collect();
+                Mesh::with_texture_coordinates(positions, uvs, triangles)
+            }
+            _ => Mesh::new(positions, triangles),
+        })
+    }
+
+    fn finish(mut self) -> (PbrtScene, Vec<String>) {
+        if self.camera.is_none() {
+            self.warnings
+                .push("The file has no camera, so PBRT's default camera is used".to_string());
+        }
+        let (name, max_depth) = self
+            .integrator
+            .clone()
+            .unwrap_or_else(|| ("path".to_string(), 5));
+        let integrator = match name.as_str() {
+            "directlighting" => Integrator::DirectLighting,
+            _ => {
+                if name != "path" {
+                    self.warnings.push(format!(
+                        "The integrator {name} isn't supported, and was replaced by path tracing"
+                    ));
+                }
+                Integrator::PathTracing {
+                    max_depth,
+                    samples_per_pixel: self.samples_per_pixel,
+                }
+            }
+        };
+        self.scene.set_integrator(integrator);
+        (
+            PbrtScene {
+                scene: self.scene,
+                width: self.width,
+                height: self.height,
+            },
+            self.warnings,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::Image;
+
+    const SCENE: &str = r#"
+        LookAt 0 -5 1  0 0 1  0 0 1 # Looking along y.
+        Camera "perspective" "float fov" [40]
+        Film "image" "integer xresolution" [32] "integer yresolution" [16]
+        Sampler "halton" "integer pixelsamples" 4
+        Integrator "directlighting"
+        WorldBegin
+        LightSource "point" "rgb I" [10 10 10] "point from" [0 -3 3]
+        AttributeBegin
+            Material "matte" "rgb Kd" [0.8 0.2 0.2]
+            Translate 1.2 0 1
+            Shape "sphere" "float radius" 0.5
+        AttributeEnd
+        Shape "trianglemesh" "integer indices" [0 1 2 0 2 3]
+            "point P" [-4 -4 0  4 -4 0  4 4 0  -4 4 0]
+        Shape "curve" "point P" [0 0 0  1 0 0  1 1 0  0 1 0]
+        WorldEnd
+    "#;
+
+    #[test]
+    fn scenes_are_imported() {
+        let (imported, warnings) = parse_pbrt(SCENE, Path::new("")).unwrap();
+        assert_eq!((imported.width, imported.height), (32, 16));
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("curve"));
+
+        let camera = imported.scene.camera();
+        assert!((camera.position - Vector3::from((0.0, -5.0, 1.0))).norm() < 1e-12);
+        assert!((camera.vertical_fov(2.0) - 40.0).abs() < 1e-9);
+
+        // PBRT sees things on its x-axis to the left, so the sphere is on the
+        // left of the image.
+        let (width, height) = (imported.width, imported.height);
+        let mut image = Image::new(width, height);
+        image.update(imported.scene.spawn_render_threads(width, height).iter());
+        let srgba = image.get_srgba_vector();
+        let redness = |x: usize| {
+            let offset = 4 * (height / 2 * width + x);
+            i32::from(srgba[offset]) - i32::from(srgba[offset + 1])
+        };
+        assert!(redness(width / 3) > 30);
+        assert!(redness(2 * width / 3) < 10);
+    }
+
+    #[test]
+    fn malformed_files_give_errors() {
+        for source in [
+            "Translate 1 2",
+            "Shape \"sphere\" \"float radius\" [1",
+            "Shape \"trianglemesh\" \"integer indices\" [0 1 5] \"point P\" [0 0 0 1 0 0 0 1 0]",
+            "AttributeEnd",
+            "NamedMaterial \"missing\"",
+            "\"sphere\"",
+        ] {
+            assert!(parse_pbrt(source, Path::new("")).is_err(), "{}", source);
+        }
+    }
+}
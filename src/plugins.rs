@@ -0,0 +1,142 @@
+//! Module for extending scene files with user-defined surfaces, textures and
+//! lights.
+//!
+//! Types implementing `Surface`, `Texture` or `Light` outside of this crate
+//! can be used in scene files by registering a constructor for them under a
+//! name in a `Registry`. A scene file refers to them as a `Plugin` with that
+//! name, together with parameters that are deserialized into the argument of
+//! the constructor:
+//!
+//! ```ron
+//! (shape: Plugin(name: "torus", parameters: (major_radius: 2.0, minor_radius: 0.5)))
+//! ```
+//!
+//! Materials are built from textures, so custom materials are made by
+//! registering the textures they consist of.
+//!
+//! Surfaces and textures can also be loaded at runtime from shared libraries,
+//! using `Registry::load_library`, which is unsafe, as it runs code from the
+//! library that Rust can't check.
+
+mod dynamic;
+
+pub use dynamic::{HitV1, PluginV1, SurfaceTypeV1, TextureTypeV1};
+
+use crate::lights::Light;
+use crate::surfaces::Surface;
+use crate::textures::Texture;
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::error::Error;
+
+/// A function making an object from the parameters given in a scene file.
+pub type Constructor<T> = Box<dyn Fn(ron::Value) -> Result<T, Box<dyn Error>> + Send + Sync>;
+
+/// A collection of named constructors for user-defined surfaces, textures and
+/// lights.
+#[derive(Default)]
+pub struct Registry {
+    surfaces: HashMap<String, Constructor<Box<dyn Surface + Send + Sync>>>,
+    textures: HashMap<String, Constructor<Box<dyn Texture + Send + Sync>>>,
+    lights: HashMap<String, Constructor<Box<dyn Light + Send + Sync>>>,
+}
+
+/// Wrap `constructor` so that it takes its parameters as a RON value.
+fn from_value<P, T, U, F>(constructor: F, into_boxed: fn(T) -> U) -> Constructor<U>
+where
+    P: DeserializeOwned + 'static,
+    T: 'static,
+    U: 'static,
+    F: Fn(P) -> Result<T, Box<dyn Error>> + Send + Sync + 'static,
+{
+    Box::new(move |parameters: ron::Value| {
+        let parameters = parameters.into_rust()?;
+        Ok(into_boxed(constructor(parameters)?))
+    })
+}
+
+impl Registry {
+    /// Make an empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a constructor for surfaces named `name`. Registering another
+    /// constructor with the same name replaces the first one.
+    pub fn register_surface<P, S, F>(&mut self, name: &str, constructor: F)
+    where
+        P: DeserializeOwned + 'static,
+        S: Surface + Send + Sync + 'static,
+        F: Fn(P) -> Result<S, Box<dyn Error>> + Send + Sync + 'static,
+    {
+        self.surfaces.insert(
+            name.to_string(),
+            from_value(constructor, |surface| Box::new(surface) as Box<_>),
+        );
+    }
+
+    /// Register a constructor for textures named `name`.
+    pub fn register_texture<P, T, F>(&mut self, name: &str, constructor: F)
+    where
+        P: DeserializeOwned + 'static,
+        T: Texture + Send + Sync + 'static,
+        F: Fn(P) -> Result<T, Box<dyn Error>> + Send + Sync + 'static,
+    {
+        self.textures.insert(
+            name.to_string(),
+            from_value(constructor, |texture| Box::new(texture) as Box<_>),
+        );
+    }
+
+    /// Register a constructor for lights named `name`.
+    pub fn register_light<P, L, F>(&mut self, name: &str, constructor: F)
+    where
+        P: DeserializeOwned + 'static,
+        L: Light + Send + Sync + 'static,
+        F: Fn(P) -> Result<L, Box<dyn Error>> + Send + Sync + 'static,
+    {
+        self.lights.insert(
+            name.to_string(),
+            from_value(constructor, |light| Box::new(light) as Box<_>),
+        );
+    }
+
+    /// Make the surface named `name` from `parameters`.
+    pub fn make_surface(
+        &self,
+        name: &str,
+        parameters: ron::Value,
+    ) -> Result<Box<dyn Surface + Send + Sync>, Box<dyn Error>> {
+        let constructor = self
+            .surfaces
+            .get(name)
+            .ok_or_else(|| format!("Unknown surface plugin {}", name))?;
+        constructor(parameters)
+    }
+
+    /// Make the texture named `name` from `parameters`.
+    pub fn make_texture(
+        &self,
+        name: &str,
+        parameters: ron::Value,
+    ) -> Result<Box<dyn Texture + Send + Sync>, Box<dyn Error>> {
+        let constructor = self
+            .textures
+            .get(name)
+            .ok_or_else(|| format!("Unknown texture plugin {}", name))?;
+        constructor(parameters)
+    }
+
+    /// Make the light named `name` from `parameters`.
+    pub fn make_light(
+        &self,
+        name: &str,
+        parameters: ron::Value,
+    ) -> Result<Box<dyn Light + Send + Sync>, Box<dyn Error>> {
+        let constructor = self
+            .lights
+            .get(name)
+            .ok_or_else(|| format!("Unknown light plugin {}", name))?;
+        constructor(parameters)
+    }
+}
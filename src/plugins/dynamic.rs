@@ -0,0 +1,362 @@
+//! Module for loading plugins from shared libraries at runtime.
+//!
+//! A plugin library exports the C function
+//!
+//! ```c
+//! const struct PluginV1 *rustbeam_plugin_v1(void);
+//! ```
+//!
+//! which returns a description of the surface and texture types in the
+//! library. Only types with a C layout cross the library boundary, so plugins
+//! can be written in any language, and don't need to be built with the same
+//! compiler as rustbeam. Parameters from scene files are passed to plugins as
+//! NUL-terminated RON text. The functions of a plugin are called from several
+//! render threads at once, so they must be thread safe.
+//!
+//! The ABI is versioned by the name of the exported function. Incompatible
+//! changes to it will be made as a new function, `rustbeam_plugin_v2`, so that
+//! old plugins keep working.
+
+use super::Registry;
+use crate::math::{Ray, Vector3};
+use crate::surfaces::{Intersection, Surface};
+use crate::textures::Texture;
+use std::error::Error;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int, c_void};
+use std::sync::Arc;
+
+/// The description of a plugin library, returned by `rustbeam_plugin_v1`.
+#[repr(C)]
+pub struct PluginV1 {
+    pub surfaces: *const SurfaceTypeV1,
+    pub num_surfaces: usize,
+    pub textures: *const TextureTypeV1,
+    pub num_textures: usize,
+}
+
+/// A type of surface provided by a plugin.
+#[repr(C)]
+pub struct SurfaceTypeV1 {
+    /// The NUL-terminated name that scene files refer to the surface by.
+    pub name: *const c_char,
+    /// Make a surface from NUL-terminated RON parameters. Returns null if the
+    /// parameters are invalid.
+    pub create: unsafe extern "C" fn(parameters: *const c_char) -> *mut c_void,
+    /// Free a surface made by `create`.
+    pub destroy: unsafe extern "C" fn(surface: *mut c_void),
+    /// Find the first intersection between a surface and the ray starting at
+    /// `origin` (3 numbers) with unit `direction` (3 numbers). Returns 1 and
+    /// fills in `hit` if there is an intersection, and 0 otherwise.
+    pub intersect: unsafe extern "C" fn(
+        surface: *const c_void,
+        origin: *const f64,
+        direction: *const f64,
+        hit: *mut HitV1,
+    ) -> c_int,
+}
+
+/// An intersection found by a surface plugin. See `Intersection`.
+#[repr(C)]
+pub struct HitV1 {
+    pub distance: f64,
+    pub normal: [f64; 3],
+    pub tangent: [f64; 3],
+    pub uv: [f64; 2],
+}
+
+/// A type of texture provided by a plugin.
+#[repr(C)]
+pub struct TextureTypeV1 {
+    /// The NUL-terminated name that scene files refer to the texture by.
+    pub name: *const c_char,
+    /// Make a texture from NUL-terminated RON parameters. Returns null if the
+    /// parameters are invalid.
+    pub create: unsafe extern "C" fn(parameters: *const c_char) -> *mut c_void,
+    /// Free a texture made by `create`.
+    pub destroy: unsafe extern "C" fn(texture: *mut c_void),
+    /// Write the color, in linear RGB, of a texture at `point` (3 numbers),
+    /// with texture coordinates `uv` (2 numbers), into `color` (3 numbers).
+    pub color: unsafe extern "C" fn(
+        texture: *const c_void,
+        point: *const f64,
+        uv: *const f64,
+        color: *mut f64,
+    ),
+}
+
+/// A loaded shared library. The library is unloaded when dropped, so every
+/// object made by the library holds on to it.
+struct Library {
+    handle: *mut c_void,
+}
+
+// The handle is only used for unloading the library.
+unsafe impl Send for Library {}
+unsafe impl Sync for Library {}
+
+impl Library {
+    #[cfg(unix)]
+    fn open(path: &str) -> Result<Self, Box<dyn Error>> {
+        let c_path = CString::new(path)?;
+        let handle = unsafe { libc::dlopen(c_path.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL) };
+        if handle.is_null() {
+            Err(format!("Could not load plugin library {}: {}", path, dl_error()).into())
+        } else {
+            Ok(Self { handle })
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn open(path: &str) -> Result<Self, Box<dyn Error>> {
+        Err(format!(
+            "Could not load plugin library {}: not supported on this platform",
+            path
+        )
+        .into())
+    }
+
+    #[cfg(unix)]
+    fn symbol(&self, name: &str) -> Result<*mut c_void, Box<dyn Error>> {
+        let c_name = CString::new(name)?;
+        let symbol = unsafe { libc::dlsym(self.handle, c_name.as_ptr()) };
+        if symbol.is_null() {
+            Err(format!("Plugin library has no symbol {}: {}", name, dl_error()).into())
+        } else {
+            Ok(symbol)
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn symbol(&self, name: &str) -> Result<*mut c_void, Box<dyn Error>> {
+        Err(format!("Plugin library has no symbol {}", name).into())
+    }
+}
+
+impl Drop for Library {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        unsafe {
+            libc::dlclose(self.handle);
+        }
+    }
+}
+
+/// Find the message of the last error from the dynamic linker.
+#[cfg(unix)]
+fn dl_error() -> String {
+    let message = unsafe { libc::dlerror() };
+    if message.is_null() {
+        "unknown error".to_string()
+    } else {
+        unsafe { CStr::from_ptr(message) }
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+/// Convert the parameters of a plugin to the text passed to `create`.
+fn parameters_to_c_string(parameters: &ron::Value) -> Result<CString, Box<dyn Error>> {
+    Ok(CString::new(ron::to_string(parameters)?)?)
+}
+
+/// A surface made by a plugin library.
+struct DynamicSurface {
+    surface: *mut c_void,
+    destroy: unsafe extern "C" fn(*mut c_void),
+    intersect: unsafe extern "C" fn(*const c_void, *const f64, *const f64, *mut HitV1) -> c_int,
+    _library: Arc<Library>,
+}
+
+// Plugins are required to be thread safe.
+unsafe impl Send for DynamicSurface {}
+unsafe impl Sync for DynamicSurface {}
+
+impl Surface for DynamicSurface {
+    fn closest_intersection(&self, ray: &Ray) -> Option<Intersection> {
+        let origin = [ray.origin.x, ray.origin.y, ray.origin.z];
+        let direction = [ray.direction.x, ray.direction.y, ray.direction.z];
+        let mut hit = HitV1 {
+            distance: 0.0,
+            normal: [0.0; 3],
+            tangent: [0.0; 3],
+            uv: [0.0; 2],
+        };
+
+        let found = unsafe {
+            (self.intersect)(self.surface, origin.as_ptr(), direction.as_ptr(), &mut hit)
+        };
+        if found == 0 || !hit.distance.is_finite() {
+            return None;
+        }
+
+        let normal = Vector3::from((hit.normal[0], hit.normal[1], hit.normal[2])).normalize();
+        let tangent = Vector3::from((hit.tangent[0], hit.tangent[1], hit.tangent[2]));
+        // Don't trust the plugin to give a tangent perpendicular to the normal.
+        let tangent = tangent - tangent.dot(normal) * normal;
+        let tangent = if tangent.norm2() > 0.0 {
+            tangent.normalize()
+        } else {
+            normal.perpendicular()
+        };
+
+        Some(Intersection {
+            distance: hit.distance,
+            normal,
+            tangent,
+            uv: (hit.uv[0], hit.uv[1]),
+        })
+    }
+}
+
+impl Drop for DynamicSurface {
+    fn drop(&mut self) {
+        unsafe { (self.destroy)(self.surface) }
+    }
+}
+
+/// A texture made by a plugin library.
+struct DynamicTexture {
+    texture: *mut c_void,
+    destroy: unsafe extern "C" fn(*mut c_void),
+    color: unsafe extern "C" fn(*const c_void, *const f64, *const f64, *mut f64),
+    _library: Arc<Library>,
+}
+
+// Plugins are required to be thread safe.
+unsafe impl Send for DynamicTexture {}
+unsafe impl Sync for DynamicTexture {}
+
+impl Texture for DynamicTexture {
+    fn color(&self, point: Vector3, uv: (f64, f64)) -> Vector3 {
+        let point = [point.x, point.y, point.z];
+        let uv = [uv.0, uv.1];
+        let mut color = [0.0; 3];
+        unsafe {
+            (self.color)(
+                self.texture,
+                point.as_ptr(),
+                uv.as_ptr(),
+                color.as_mut_ptr(),
+            )
+        };
+        Vector3::from((color[0], color[1], color[2]))
+    }
+}
+
+impl Drop for DynamicTexture {
+    fn drop(&mut self) {
+        unsafe { (self.destroy)(self.texture) }
+    }
+}
+
+impl Registry {
+    /// Load the plugin library at `path`, and register the surfaces and
+    /// textures in it. Only supported on Unix-like systems.
+    ///
+    /// # Safety
+    ///
+    /// Loading the library runs its initialization code, and the plugin it
+    /// describes is called without any checks. The library must export
+    /// `rustbeam_plugin_v1` with the signature and behavior described in the
+    /// module documentation, and its functions must be thread safe.
+    pub unsafe fn load_library(&mut self, path: &str) -> Result<(), Box<dyn Error>> {
+        let library = Arc::new(Library::open(path)?);
+        let entry_point = library.symbol("rustbeam_plugin_v1")?;
+        let entry_point: unsafe extern "C" fn() -> *const PluginV1 =
+            unsafe { std::mem::transmute(entry_point) };
+
+        let plugin = unsafe { entry_point().as_ref() }
+            .ok_or_else(|| format!("Plugin library {} returned no plugin", path))?;
+
+        let surface_types = if plugin.num_surfaces == 0 {
+            &[][..]
+        } else {
+            unsafe { std::slice::from_raw_parts(plugin.surfaces, plugin.num_surfaces) }
+        };
+        for surface_type in surface_types {
+            let name = unsafe { CStr::from_ptr(surface_type.name) }.to_str()?;
+            let (create, destroy, intersect) = (
+                surface_type.create,
+                surface_type.destroy,
+                surface_type.intersect,
+            );
+            let library = library.clone();
+            let error_name = name.to_string();
+
+            self.surfaces.insert(
+                name.to_string(),
+                Box::new(move |parameters: ron::Value| {
+                    let parameters = parameters_to_c_string(&parameters)?;
+                    let surface = unsafe { create(parameters.as_ptr()) };
+                    if surface.is_null() {
+                        return Err(format!("Invalid parameters for {}", error_name).into());
+                    }
+                    Ok(Box::new(DynamicSurface {
+                        surface,
+                        destroy,
+                        intersect,
+                        _library: library.clone(),
+                    }) as Box<_>)
+                }),
+            );
+        }
+
+        let texture_types = if plugin.num_textures == 0 {
+            &[][..]
+        } else {
+            unsafe { std::slice::from_raw_parts(plugin.textures, plugin.num_textures) }
+        };
+        for texture_type in texture_types {
+            let name = unsafe { CStr::from_ptr(texture_type.name) }.to_str()?;
+            let (create, destroy, color) = (
+                texture_type.create,
+                texture_type.destroy,
+                texture_type.color,
+            );
+            let library = library.clone();
+            let error_name = name.to_string();
+
+            self.textures.insert(
+                name.to_string(),
+                Box::new(move |parameters: ron::Value| {
+                    let parameters = parameters_to_c_string(&parameters)?;
+                    let texture = unsafe { create(parameters.as_ptr()) };
+                    if texture.is_null() {
+                        return Err(format!("Invalid parameters for {}", error_name).into());
+                    }
+                    Ok(Box::new(DynamicTexture {
+                        texture,
+                        destroy,
+                        color,
+                        _library: library.clone(),
+                    }) as Box<_>)
+                }),
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_libraries_are_errors() {
+        let mut registry = Registry::new();
+        let result = unsafe { registry.load_library("test-data/no-such-plugin.so") };
+        assert!(result.is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn libraries_without_a_plugin_are_errors() {
+        // The C library is always loaded, and is no plugin.
+        let mut registry = Registry::new();
+        let result = unsafe { registry.load_library("libc.so.6") };
+        let message = result.err().unwrap().to_string();
+        assert!(message.contains("rustbeam_plugin_v1"), "{}", message);
+    }
+}
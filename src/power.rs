@@ -0,0 +1,78 @@
+//! Module for making long renders get along with the rest of the system.
+//!
+//! A render can keep the system from going to sleep while it runs, and its
+//! threads can run at a lower priority than other programs, so that the
+//! machine stays usable. Both are best effort: on systems that offer no way to
+//! do it, rendering goes on as usual.
+
+use std::error::Error;
+use std::process::{Child, Command, Stdio};
+
+/// Keeps the system from sleeping for as long as it is alive, by running a
+/// helper program of the system that holds an inhibitor: `caffeinate` on
+/// macOS, and `systemd-inhibit` on Linux.
+pub struct KeepAwake {
+    helper: Child,
+}
+
+impl KeepAwake {
+    /// Start keeping the system awake. `reason` is shown by systems that list
+    /// what is keeping them awake.
+    pub fn start(reason: &str) -> Result<Self, Box<dyn Error>> {
+        let mut command = if cfg!(target_os = "macos") {
+            // Exits by itself if this process dies without dropping the guard.
+            let mut command = Command::new("caffeinate");
+            command.args(["-i", "-w", &std::process::id().to_string()]);
+            command
+        } else if cfg!(target_os = "linux") {
+            let mut command = Command::new("systemd-inhibit");
+            command.args([
+                "--what=idle:sleep",
+                "--who=rustbeam",
+                &format!("--why={}", reason),
+                "sleep",
+                "infinity",
+            ]);
+            command
+        } else {
+            return Err("Keeping the system awake is not supported on this platform".into());
+        };
+
+        let helper = command
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+        Ok(Self { helper })
+    }
+}
+
+impl Drop for KeepAwake {
+    fn drop(&mut self) {
+        // The helper may already have exited, e.g. if it found no inhibitor to
+        // hold, which is fine.
+        let _ = self.helper.kill();
+        let _ = self.helper.wait();
+    }
+}
+
+/// Lower the priority of the calling thread, so that other programs get to
+/// run first. On Linux, only the calling thread is affected, while on other
+/// Unix systems, the whole process is.
+#[cfg(unix)]
+pub fn lower_thread_priority() -> Result<(), Box<dyn Error>> {
+    // The nice value of background jobs, from -20 for the highest priority to
+    // 19 for the lowest.
+    const NICE: i32 = 10;
+
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, NICE) } == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error().into())
+    }
+}
+
+#[cfg(not(unix))]
+pub fn lower_thread_priority() -> Result<(), Box<dyn Error>> {
+    Err("Lowering the priority of threads is not supported on this platform".into())
+}
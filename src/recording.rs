@@ -0,0 +1,257 @@
+//! Module for recording how scenes are built and rendered, as scripts that
+//! can be replayed.
+//!
+//! A `Recording` is a list of commands, which build a scene from the same
+//! descriptions of surfaces, materials and lights as scene files, place the
+//! camera, and render. Since every command is data, a recording saved as RON
+//! reproduces a session exactly, e.g. when attached to a bug report, and can
+//! be edited and replayed, or previewed in a window. A recording looks like
+//! this:
+//!
+//! ```ron
+//! (
+//!     commands: [
+//!         AddSurface((shape: Sphere(center: (0.0, 2.0, 0.0), radius: 0.5))),
+//!         AddLight(Sun(color: (1.0, 1.0, 1.0), direction: (1.0, 1.0, -1.0))),
+//!         LookAt(eye: (0.0, -3.0, 1.0), target: (0.0, 2.0, 0.0), up: (0.0, 0.0, 1.0)),
+//!         Render(width: 640, height: 360, output: "sphere.png"),
+//!     ],
+//! )
+//! ```
+
+use crate::plugins::Registry;
+use crate::scene::{Camera, Scene};
+use crate::scene_file::{
+    IntegratorDescription, LightDescription, MaterialDescription, SceneDescription,
+    SurfaceDescription, VectorDescription,
+};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+
+/// A step in building or rendering a scene.
+#[derive(Clone, Serialize, Deserialize)]
+pub enum Command {
+    /// Start over from the scene described, as read from a scene file.
+    /// Relative paths in it are relative to `directory`, which is relative to
+    /// the working directory when replaying.
+    SetScene {
+        description: Box<SceneDescription>,
+        directory: String,
+    },
+    AddSurface(SurfaceDescription),
+    AddLight(LightDescription),
+    /// Add a material that surfaces can refer to by name, or replace the one
+    /// with that name.
+    SetMaterial {
+        name: String,
+        material: MaterialDescription,
+    },
+    SetIntegrator(IntegratorDescription),
+    /// Place the camera at `eye`, looking at `target`, as with
+    /// `Camera::look_at`.
+    LookAt {
+        eye: VectorDescription,
+        target: VectorDescription,
+        up: VectorDescription,
+    },
+    /// Set the horizontal field of view of the camera, in degrees.
+    SetHorizontalFov(f64),
+    /// Make the camera orthographic, seeing a part of the scene this wide.
+    SetOrthographic(f64),
+    /// Give the camera a lens, as with `Camera::with_depth_of_field`.
+    SetDepthOfField {
+        aperture_radius: f64,
+        focus_distance: f64,
+    },
+    /// Render the scene built so far, and save the image as `output`.
+    Render {
+        width: usize,
+        height: usize,
+        output: String,
+    },
+}
+
+/// The scene being built while replaying a recording.
+struct Session {
+    description: SceneDescription,
+    directory: PathBuf,
+    camera: Camera,
+}
+
+impl Session {
+    fn new() -> Self {
+        Self {
+            description: SceneDescription::default(),
+            directory: PathBuf::new(),
+            camera: Camera::default(),
+        }
+    }
+
+    /// Carry out `command`, unless it is a render.
+    fn apply(&mut self, command: &Command) {
+        match command {
+            Command::SetScene {
+                description,
+                directory,
+            } => {
+                self.description = description.as_ref().clone();
+                self.directory = PathBuf::from(directory);
+            }
+            Command::AddSurface(surface) => self.description.surfaces.push(surface.clone()),
+            Command::AddLight(light) => self.description.lights.push(light.clone()),
+            Command::SetMaterial { name, material } => {
+                self.description
+                    .materials
+                    .insert(name.clone(), material.clone());
+            }
+            Command::SetIntegrator(integrator) => self.description.integrator = integrator.clone(),
+            Command::LookAt { eye, target, up } => {
+                let camera = Camera::look_at(*eye, *target, *up);
+                self.camera.position = camera.position;
+                self.camera.orientation = camera.orientation;
+            }
+            Command::SetHorizontalFov(degrees) => {
+                self.camera = self.camera.with_horizontal_fov(*degrees);
+            }
+            Command::SetOrthographic(view_width) => {
+                self.camera = self.camera.with_orthographic(*view_width);
+            }
+            Command::SetDepthOfField {
+                aperture_radius,
+                focus_distance,
+            } => {
+                self.camera = self
+                    .camera
+                    .with_depth_of_field(*aperture_radius, *focus_distance);
+            }
+            Command::Render { .. } => {}
+        }
+    }
+
+    /// Build the scene, with plugins made using `registry`.
+    fn build(&self, registry: &Registry) -> Result<Scene, Box<dyn Error>> {
+        let mut scene = self.description.build(&self.directory, registry)?;
+        scene.set_camera(self.camera.position, self.camera.orientation);
+        scene.set_camera_screen(self.camera.screen_width, self.camera.distance_to_screen);
+        scene.set_camera_projection(self.camera.projection);
+        scene.set_depth_of_field(self.camera.aperture_radius, self.camera.focus_distance);
+        Ok(scene)
+    }
+}
+
+/// A list of commands for building and rendering scenes.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Recording {
+    pub commands: Vec<Command>,
+}
+
+impl Recording {
+    /// Make an empty recording.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `command` to the end of the recording.
+    pub fn record(&mut self, command: Command) {
+        self.commands.push(command);
+    }
+
+    /// Read a recording saved as the RON file `filename`.
+    pub fn load(filename: &str) -> Result<Self, Box<dyn Error>> {
+        Ok(ron::from_str(&fs::read_to_string(filename)?)?)
+    }
+
+    /// Save the recording as the RON file `filename`.
+    pub fn save(&self, filename: &str) -> Result<(), Box<dyn Error>> {
+        let source = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+        fs::write(filename, source)?;
+        Ok(())
+    }
+
+    /// Carry out the commands in order, calling `render` with the scene built
+    /// so far and the size and output of each render. Plugins are made using
+    /// `registry`.
+    pub fn replay(
+        &self,
+        registry: &Registry,
+        mut render: impl FnMut(Scene, usize, usize, &str) -> Result<(), Box<dyn Error>>,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut session = Session::new();
+        for command in &self.commands {
+            match command {
+                Command::Render {
+                    width,
+                    height,
+                    output,
+                } => render(session.build(registry)?, *width, *height, output)?,
+                _ => session.apply(command),
+            }
+        }
+        Ok(())
+    }
+
+    /// Build the scene as it is after all the commands, for previewing.
+    pub fn scene(&self, registry: &Registry) -> Result<Scene, Box<dyn Error>> {
+        let mut session = Session::new();
+        for command in &self.commands {
+            session.apply(command);
+        }
+        session.build(registry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::Image;
+    use crate::math::Vector3;
+
+    const RECORDING: &str = r#"(
+        commands: [
+            AddSurface((shape: Sphere(center: (0.0, 2.0, 0.0), radius: 0.5))),
+            AddLight(Sun(color: (1.0, 1.0, 1.0), direction: (1.0, 1.0, -1.0))),
+            Render(width: 32, height: 18, output: "before.png"),
+            LookAt(eye: (0.0, -3.0, 1.0), target: (0.0, 2.0, 0.0), up: (0.0, 0.0, 1.0)),
+            SetHorizontalFov(30.0),
+            Render(width: 16, height: 9, output: "after.png"),
+        ],
+    )"#;
+
+    #[test]
+    fn replay_renders_in_order() {
+        let recording: Recording = ron::from_str(RECORDING).unwrap();
+        let mut renders = Vec::new();
+        recording
+            .replay(&Registry::new(), |scene, width, height, output| {
+                renders.push((scene.camera().position, width, height, output.to_string()));
+                Ok(())
+            })
+            .unwrap();
+
+        assert_eq!(renders.len(), 2);
+        assert_eq!(renders[0].3, "before.png");
+        assert_eq!(renders[0].0.norm(), 0.0);
+        assert_eq!((renders[1].1, renders[1].2), (16, 9));
+        assert!((renders[1].0 - Vector3::from((0.0, -3.0, 1.0))).norm() < 1e-12);
+    }
+
+    #[test]
+    fn recordings_survive_saving() {
+        let recording: Recording = ron::from_str(RECORDING).unwrap();
+        let source = ron::to_string(&recording).unwrap();
+        let reloaded: Recording = ron::from_str(&source).unwrap();
+        assert_eq!(reloaded.commands.len(), recording.commands.len());
+
+        // The sphere is in the middle of the preview of the last scene.
+        let (width, height) = (16, 9);
+        let scene = reloaded.scene(&Registry::new()).unwrap();
+        assert!((scene.camera().horizontal_fov() - 30.0).abs() < 1e-9);
+        let mut image = Image::new(width, height);
+        image.update(scene.spawn_render_threads(width, height).iter());
+        let srgba = image.get_srgba_vector();
+        assert!(srgba[4 * (height / 2 * width + width / 2)] > 0);
+        assert_eq!(srgba[0], 0);
+    }
+}
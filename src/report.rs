@@ -0,0 +1,361 @@
+//! Module for HTML reports comparing two renders.
+//!
+//! A report is a single HTML file with the images embedded, so that it can be
+//! shared as it is. It shows the two renders on top of each other, with a
+//! slider that wipes from one to the other, a table of how much they differ,
+//! and their metadata side by side, if it was saved next to them. This is
+//! useful when checking how a change to an integrator or a material affects
+//! the image.
+
+use serde_json::Value;
+use std::error::Error;
+use std::fmt::Write;
+use std::fs::{self, File};
+use std::path::Path;
+
+/// A rendered image, loaded for comparison.
+pub struct Render {
+    /// The name shown in the report.
+    pub label: String,
+    width: usize,
+    height: usize,
+    /// The contents of the PNG file, for embedding in the report.
+    png: Vec<u8>,
+    /// The decoded pixels, in SRGBA, row by row from the top.
+    srgba: Vec<u8>,
+    /// The metadata saved next to the image, if any.
+    metadata: Option<Value>,
+}
+
+impl Render {
+    /// Load the PNG image `path`, together with the metadata saved next to it
+    /// with `RenderMetadata::save_sidecar`, if it exists.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let png = fs::read(path)?;
+        let (width, height, srgba) = decode_png(path)?;
+        let metadata = match fs::read_to_string(path.with_extension("json")) {
+            Ok(json) => Some(serde_json::from_str(&json)?),
+            Err(_) => None,
+        };
+        Ok(Self {
+            label: path.display().to_string(),
+            width,
+            height,
+            png,
+            srgba,
+            metadata,
+        })
+    }
+}
+
+/// Read a PNG image into 8-bit SRGBA pixels, with its width and height.
+pub(crate) fn decode_png(path: &Path) -> Result<(usize, usize, Vec<u8>), Box<dyn Error>> {
+    let decoder = png::Decoder::new(File::open(path)?);
+    let (info, mut reader) = decoder.read_info()?;
+    let mut buffer = vec![0; info.buffer_size()];
+    reader.next_frame(&mut buffer)?;
+
+    let channels = match info.color_type {
+        png::ColorType::Grayscale => 1,
+        png::ColorType::GrayscaleAlpha => 2,
+        png::ColorType::RGB => 3,
+        png::ColorType::RGBA => 4,
+        png::ColorType::Indexed => return Err("Unexpected palette in PNG image".into()),
+    };
+    let (width, height) = (info.width as usize, info.height as usize);
+
+    let mut srgba = Vec::with_capacity(4 * width * height);
+    for row in buffer.chunks(info.line_size) {
+        for pixel in row[..channels * width].chunks(channels) {
+            match channels {
+                1 => srgba.extend_from_slice(&[pixel[0], pixel[0], pixel[0], 255]),
+                2 => srgba.extend_from_slice(&[pixel[0], pixel[0], pixel[0], pixel[1]]),
+                3 => srgba.extend_from_slice(&[pixel[0], pixel[1], pixel[2], 255]),
+                _ => srgba.extend_from_slice(pixel),
+            }
+        }
+    }
+    Ok((width, height, srgba))
+}
+
+/// How much two images of the same size differ, over the color channels of
+/// all pixels, in 8-bit SRGB values.
+#[derive(Debug)]
+pub struct Difference {
+    pub mean_absolute: f64,
+    pub root_mean_square: f64,
+    /// The peak signal-to-noise ratio, in decibels. Infinite for identical
+    /// images.
+    pub psnr: f64,
+    /// The largest difference in any channel.
+    pub max: u8,
+    /// The number of pixels where any channel differs.
+    pub changed_pixels: usize,
+}
+
+impl Difference {
+    /// Compare the color channels of two renders. Returns an error if their
+    /// sizes differ.
+    pub fn between(before: &Render, after: &Render) -> Result<Self, Box<dyn Error>> {
+        if (before.width, before.height) != (after.width, after.height) {
+            return Err(format!(
+                "Can't compare a {} x {} image with a {} x {} image",
+                before.width, before.height, after.width, after.height
+            )
+            .into());
+        }
+
+        let (mut sum, mut sum2, mut max, mut changed_pixels) = (0.0, 0.0, 0, 0);
+        for (a, b) in before.srgba.chunks(4).zip(after.srgba.chunks(4)) {
+            let mut changed = false;
+            for channel in 0..3 {
+                let difference = a[channel].abs_diff(b[channel]);
+                sum += f64::from(difference);
+                sum2 += f64::from(difference).powi(2);
+                max = max.max(difference);
+                changed |= difference > 0;
+            }
+            changed_pixels += usize::from(changed);
+        }
+
+        let count = (3 * before.width * before.height).max(1) as f64;
+        let root_mean_square = (sum2 / count).sqrt();
+        Ok(Self {
+            mean_absolute: sum / count,
+            root_mean_square,
+            psnr: 20.0 * (255.0 / root_mean_square).log10(),
+            max,
+            changed_pixels,
+        })
+    }
+}
+
+/// Make a report comparing `before` with `after`, as a self-contained HTML
+/// page. Returns an error if the images have different sizes.
+pub fn comparison_report(before: &Render, after: &Render) -> Result<String, Box<dyn Error>> {
+    let difference = Difference::between(before, after)?;
+
+    let mut html = String::new();
+    writeln!(html, "<!DOCTYPE html>")?;
+    writeln!(html, "<html><head><meta charset=\"utf-8\">")?;
+    writeln!(html, "<title>rustbeam comparison</title>")?;
+    writeln!(html, "<style>{}</style></head><body>", STYLE)?;
+    writeln!(
+        html,
+        "<h1>{} vs. {}</h1>",
+        escape(&before.label),
+        escape(&after.label)
+    )?;
+
+    // The after image is below, and the before image above it is clipped to
+    // the left of the slider.
+    writeln!(
+        html,
+        "<div class=\"compare\" style=\"width: {}px; height: {}px\">",
+        before.width, before.height
+    )?;
+    writeln!(html, "<img src=\"{}\">", data_url(&after.png))?;
+    writeln!(
+        html,
+        "<div id=\"before\"><img src=\"{}\"></div></div>",
+        data_url(&before.png)
+    )?;
+    writeln!(
+        html,
+        "<input type=\"range\" min=\"0\" max=\"100\" value=\"50\" style=\"width: {}px\" \
+         oninput=\"document.getElementById('before').style.width = this.value + '%'\">",
+        before.width
+    )?;
+    writeln!(
+        html,
+        "<p>Left: {}. Right: {}.</p>",
+        escape(&before.label),
+        escape(&after.label)
+    )?;
+
+    writeln!(html, "<h2>Difference</h2><table>")?;
+    let pixels = before.width * before.height;
+    let rows = [
+        (
+            "Mean absolute difference",
+            format!("{:.3}", difference.mean_absolute),
+        ),
+        (
+            "Root mean square difference",
+            format!("{:.3}", difference.root_mean_square),
+        ),
+        ("PSNR", format!("{:.2} dB", difference.psnr)),
+        ("Largest difference", difference.max.to_string()),
+        (
+            "Changed pixels",
+            format!(
+                "{} of {} ({:.2} %)",
+                difference.changed_pixels,
+                pixels,
+                100.0 * difference.changed_pixels as f64 / pixels.max(1) as f64
+            ),
+        ),
+    ];
+    for (name, value) in rows.iter() {
+        writeln!(html, "<tr><th>{}</th><td>{}</td></tr>", name, value)?;
+    }
+    writeln!(html, "</table>")?;
+
+    if before.metadata.is_some() || after.metadata.is_some() {
+        writeln!(html, "<h2>Metadata</h2><table>")?;
+        writeln!(html, "<tr><th></th><th>Before</th><th>After</th></tr>")?;
+        let mut before_fields = Vec::new();
+        let mut after_fields = Vec::new();
+        if let Some(metadata) = &before.metadata {
+            flatten("", metadata, &mut before_fields);
+        }
+        if let Some(metadata) = &after.metadata {
+            flatten("", metadata, &mut after_fields);
+        }
+        let mut names: Vec<&String> = before_fields
+            .iter()
+            .chain(after_fields.iter())
+            .map(|(name, _)| name)
+            .collect();
+        names.sort();
+        names.dedup();
+
+        let find = |fields: &[(String, String)], name: &str| {
+            fields
+                .iter()
+                .find(|(field, _)| field == name)
+                .map_or(String::new(), |(_, value)| value.clone())
+        };
+        for name in names {
+            let (before_value, after_value) =
+                (find(&before_fields, name), find(&after_fields, name));
+            let class = if before_value == after_value {
+                ""
+            } else {
+                " class=\"changed\""
+            };
+            writeln!(
+                html,
+                "<tr{}><th>{}</th><td>{}</td><td>{}</td></tr>",
+                class,
+                escape(name),
+                escape(&before_value),
+                escape(&after_value)
+            )?;
+        }
+        writeln!(html, "</table>")?;
+    }
+
+    writeln!(html, "</body></html>")?;
+    Ok(html)
+}
+
+const STYLE: &str = "body { font-family: sans-serif; background: #222; color: #eee; } \
+    .compare { position: relative; } \
+    .compare img { position: absolute; top: 0; left: 0; image-rendering: pixelated; } \
+    #before { position: absolute; top: 0; left: 0; width: 50%; height: 100%; overflow: hidden; \
+    border-right: 1px solid #fff; } \
+    table { border-collapse: collapse; } \
+    th, td { text-align: left; padding: 2px 12px; } \
+    tr.changed { color: #fc6; }";
+
+/// List the fields of a JSON value, with the names of nested fields joined by
+/// dots.
+fn flatten(name: &str, value: &Value, fields: &mut Vec<(String, String)>) {
+    match value {
+        Value::Object(object) => {
+            for (key, value) in object {
+                let name = if name.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", name, key)
+                };
+                flatten(&name, value, fields);
+            }
+        }
+        Value::String(string) => fields.push((name.to_string(), string.clone())),
+        _ => fields.push((name.to_string(), value.to_string())),
+    }
+}
+
+/// Escape text for HTML.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Make a data URL of a PNG image, for embedding it.
+fn data_url(png: &[u8]) -> String {
+    format!("data:image/png;base64,{}", base64(png))
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, &byte)| {
+            bits | u32::from(byte) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(char::from(ALPHABET[(bits >> (18 - 6 * i) & 63) as usize]));
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::Image;
+
+    #[test]
+    fn reports_show_differences_and_metadata() {
+        assert_eq!(base64(b"Man"), "TWFu");
+        assert_eq!(base64(b"Ma"), "TWE=");
+        assert_eq!(base64(b"M"), "TQ==");
+
+        let directory = std::env::temp_dir();
+        let save = |name: &str, gray: f64| {
+            let mut image = Image::new(4, 2);
+            image.set_pixel(0, 0, (gray, gray, gray));
+            let path = directory.join(format!("rustbeam-{}-{}.png", name, std::process::id()));
+            image.save_png(path.to_str().unwrap()).unwrap();
+            path
+        };
+        let before_path = save("before", 0.0);
+        let after_path = save("after", 1.0);
+        fs::write(
+            after_path.with_extension("json"),
+            "{\"timings\": {\"render\": 2.5}}",
+        )
+        .unwrap();
+
+        let before = Render::load(&before_path).unwrap();
+        let after = Render::load(&after_path).unwrap();
+        for path in [&before_path, &after_path] {
+            fs::remove_file(path).unwrap();
+        }
+        fs::remove_file(after_path.with_extension("json")).unwrap();
+
+        // One pixel went from black to white.
+        let difference = Difference::between(&before, &after).unwrap();
+        assert_eq!(difference.max, 255);
+        assert_eq!(difference.changed_pixels, 1);
+        assert!((difference.mean_absolute - 255.0 / 8.0).abs() < 1e-9);
+        assert!(Difference::between(&before, &before)
+            .unwrap()
+            .psnr
+            .is_infinite());
+
+        let html = comparison_report(&before, &after).unwrap();
+        assert_eq!(html.matches("data:image/png;base64,").count(), 2);
+        assert!(html.contains("<th>timings.render</th><td></td><td>2.5</td>"));
+    }
+}
@@ -2,29 +2,499 @@
 //!
 //! This module performs the actual rendering.
 
-use crate::image::Pixel;
-use crate::lights::Sun;
+mod algorithms;
+mod camera_path;
+mod changes;
+mod cost_map;
+mod description;
+mod graph;
+mod irradiance_cache;
+mod light_groups;
+mod light_tree;
+mod names;
+mod photons;
+mod render_handle;
+mod shading;
+
+pub use algorithms::{AmbientOcclusion, DebugView, RenderAlgorithm, SurfaceHit};
+pub use camera_path::{CameraKeyframe, CameraPath};
+pub use changes::SceneChanges;
+pub use cost_map::{CostMap, PixelCost};
+pub use graph::NodeId;
+pub use light_groups::LightGroupImages;
+pub use render_handle::RenderHandle;
+pub use shading::{Shader, ShadingContext};
+
+use crate::flare::{FlareSource, LensFlare};
+use crate::gltf::Gltf;
+use crate::hashing::ContentHasher;
+use crate::image::{ColorSpace, Image, Pixel};
+use crate::lights::Light;
+use crate::materials::{Bsdf, Material, MaterialLibrary};
+use crate::math::blue_noise::BlueNoiseMask;
+use crate::math::sampling::{self, Rng, SamplePattern};
 use crate::math::{Ray, UnitQuaternion, Vector3};
-use crate::surfaces::Surface;
+use crate::memory::MemoryReport;
+use crate::metadata::{CameraMetadata, RenderMetadata, Timings};
+use crate::power::{self, KeepAwake};
+use crate::scene_file;
+use crate::sensor::Sensor;
+use crate::stats::{self, Counter};
+use crate::surfaces::{Intersection, Mesh, Surface, Transform};
+use crate::textures::Texture;
+use irradiance_cache::IrradianceCache;
+use light_groups::LightGroups;
+use light_tree::LightTree;
+use names::Labels;
+use photons::PhotonMap;
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashMap};
 use std::error::Error;
+use std::ops::Range;
 use std::{
-    f64::{EPSILON, INFINITY},
+    f64::{consts::PI, EPSILON, INFINITY},
     sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
         mpsc,
-        mpsc::{Receiver, Sender},
-        Arc,
+        mpsc::Sender,
+        Arc, Mutex,
     },
     thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
+/// The largest number of perfectly specular bounces followed when rendering
+/// with `Integrator::DirectLighting`.
+const MAX_SPECULAR_DEPTH: u32 = 8;
+/// The largest number of transparent surfaces that a shadow ray passes through.
+const MAX_SHADOW_SURFACES: u32 = 16;
+/// Surfaces hit at distances this close, relative to the distance, coincide,
+/// and the one with the higher priority is hit.
+const COINCIDENCE_TOLERANCE: f64 = 1e-7;
+/// How many rays are traced between checks of the time spent on a pixel.
+const RAYS_PER_TIME_CHECK: u64 = 64;
+/// The number of tiles across and down the image that are rendered to
+/// calibrate the render schedule, and their width and height in pixels.
+const PROBE_TILES: usize = 4;
+const PROBE_TILE_SIZE: usize = 8;
+/// The least time worth starting another render thread for.
+const MIN_THREAD_TIME: Duration = Duration::from_millis(20);
+/// The range of tile sizes chosen by calibration, in pixels.
+const MIN_TILE_SIZE: usize = 8;
+const MAX_TILE_SIZE: usize = 128;
+
+/// Limits on the work done for a single pixel, so that a pixel where rays get
+/// trapped, e.g. between coincident surfaces, can't hang a render thread. When
+/// a limit is reached, tracing of the pixel stops, and it is given the color
+/// `PixelLimits::COLOR`, so that it can be found in the image.
+#[derive(Clone, Copy, Default)]
+pub struct PixelLimits {
+    /// The largest number of rays traced for a pixel.
+    pub max_rays: Option<u64>,
+    /// The longest time spent on a pixel.
+    pub max_time: Option<Duration>,
+}
+
+impl PixelLimits {
+    /// The color of pixels that reached a limit, a bright magenta.
+    pub const COLOR: (f64, f64, f64) = (1.0, 0.0, 1.0);
+
+    fn is_unlimited(&self) -> bool {
+        self.max_rays.is_none() && self.max_time.is_none()
+    }
+}
+
+/// When to stop taking samples of a pixel, so that a render can be asked to
+/// stop when it is clean enough, or after a while, rather than after a guessed
+/// number of samples. A pixel is sampled until one of the conditions is met,
+/// or until it has the number of samples given by the integrator, whichever
+/// comes first. Only path tracing and render algorithms may take several
+/// samples of each pixel, so the conditions only apply to them, except in
+/// progressive renders, where clean pixels are left out of later passes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct HaltConditions {
+    /// Stop sampling a pixel when the standard error of its luminance is below
+    /// this fraction of the luminance. Pixels darker than
+    /// `HaltConditions::DARK_LUMINANCE` are held to the error allowed at that
+    /// luminance, so that they don't need many samples for their small
+    /// errors. At least `HaltConditions::MIN_SAMPLES` samples are taken, for
+    /// estimating the error.
+    pub noise_threshold: Option<f64>,
+    /// Stop sampling when this long has passed since the render started with
+    /// `Scene::spawn_render_threads`. The pixels left are rendered with a
+    /// single sample each.
+    pub max_time: Option<Duration>,
+}
+
+impl HaltConditions {
+    /// The number of samples of a pixel before its noise is estimated.
+    pub const MIN_SAMPLES: u32 = 16;
+    /// The luminance below which the noise of pixels is compared to the
+    /// luminance itself.
+    pub const DARK_LUMINANCE: f64 = 0.05;
+}
+
+/// The exposure of the camera, for scenes lit by lights in physical units,
+/// given by `lights::Brightness`. It scales the luminance of the scene, in
+/// cd/m², to the values of the pixels, where 1 is white, before they are
+/// clamped.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Exposure {
+    /// The exposure value at ISO 100. A sunny day is about 15, a bright
+    /// office about 7, and a dim living room about 4.
+    pub ev100: f64,
+}
+
+impl Exposure {
+    /// Find the exposure of a camera with the aperture `f_number`, the
+    /// shutter open for `shutter_time` seconds, and the sensitivity `iso`.
+    pub fn from_camera(f_number: f64, shutter_time: f64, iso: f64) -> Self {
+        Self {
+            ev100: (f_number * f_number / shutter_time).log2() - (iso / 100.0).log2(),
+        }
+    }
+
+    /// Find the factor from luminance, in cd/m², to pixel values. The
+    /// brightest luminance that isn't clipped is 1.2 * 2^EV100, as for the
+    /// saturation-based sensitivity of cameras.
+    pub fn scale(&self) -> f64 {
+        1.0 / (1.2 * 2_f64.powf(self.ev100))
+    }
+}
+
+/// How far a ray must go before it can hit a surface, so that rays leaving a
+/// surface don't hit it again at the point they leave from, through rounding
+/// errors. Too small a bias gives shadow acne, dark specks on lit surfaces,
+/// and too large a bias lets light leak through thin or touching surfaces.
+/// Rounding errors grow with the coordinates, so the bias grows with the
+/// distance of the ray origin from the origin of the scene. Meshes don't need
+/// a bias, as they ignore rays hitting the triangles they leave from.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct RayBias {
+    /// The bias near the origin of the scene.
+    pub absolute: f64,
+    /// The bias as a fraction of the largest coordinate of the ray origin,
+    /// used where it is larger than `absolute`.
+    pub relative: f64,
+}
+
+impl Default for RayBias {
+    /// A bias of the square root of machine epsilon near the origin, and of
+    /// a few thousand times machine epsilon relative to the coordinates, which
+    /// covers their rounding errors even at grazing angles.
+    fn default() -> Self {
+        Self {
+            absolute: EPSILON.sqrt(),
+            relative: 4096.0 * EPSILON,
+        }
+    }
+}
+
+impl RayBias {
+    /// Find the bias of rays from `origin`.
+    fn distance(&self, origin: Vector3) -> f64 {
+        let largest = origin.x.abs().max(origin.y.abs()).max(origin.z.abs());
+        self.absolute.max(self.relative * largest)
+    }
+}
+
+/// How each pixel is sampled, and how the random numbers used for sampling it
+/// are chosen.
+#[derive(Clone, Default)]
+pub struct SamplerSettings {
+    /// The number of rays traced through points spread over each pixel,
+    /// whose colors are averaged, which smooths the staircases along the
+    /// edges of surfaces. The points are jittered by random amounts, so that
+    /// they don't line up between pixels. Each ray is sampled as the
+    /// integrator samples a pixel. 0 and 1 both trace one ray through the
+    /// middle of the pixel.
+    pub pixel_samples: u32,
+    /// How the numbers that the samples of each pixel are taken with are
+    /// spread, over the points in the pixel, on the lens and on lights, and
+    /// the other dimensions sampled by the integrator.
+    pub pattern: SamplePattern,
+    /// Offset the random numbers of each pixel by a blue-noise mask tiled over
+    /// the image, so that the noise of renders with few samples is fine and
+    /// evenly spread, without clumps. Pixels in the same tile of the mask
+    /// share their random numbers before they are offset.
+    pub blue_noise: bool,
+    /// The mask used with `blue_noise`, or `BlueNoiseMask::default_mask` if
+    /// `None`.
+    pub blue_noise_mask: Option<Arc<BlueNoiseMask>>,
+    /// Mixed into the random numbers of every pixel, so that renders with
+    /// different seeds have different noise, while renders with the same seed
+    /// are the same.
+    pub seed: u64,
+    /// Spread the rays of each pass of progressive renders over the pixels by
+    /// the noise of their samples so far, so that noisy pixels, like those in
+    /// soft shadows and glass, get more rays per pass than clean ones. A pixel
+    /// gets up to `SamplerSettings::MAX_PASS_RAYS` rays, and each pass traces
+    /// about one ray per pixel. Until a pixel has
+    /// `HaltConditions::MIN_SAMPLES` samples, it gets one ray per pass.
+    pub adaptive_passes: bool,
+}
+
+impl SamplerSettings {
+    /// The most rays that a pixel gets in a pass with `adaptive_passes`.
+    pub const MAX_PASS_RAYS: u32 = 4;
+}
+
+/// The luminance of the samples of a pixel so far, for estimating its noise:
+/// the number of samples, the running mean of their luminance, and the sum of
+/// squared differences from the mean, by Welford's method.
+#[derive(Clone, Copy, Default)]
+struct SampleStats {
+    samples: u32,
+    mean: f64,
+    squares: f64,
+}
+
+impl SampleStats {
+    fn add(&mut self, rgb: Vector3) {
+        self.samples += 1;
+        let value = luminance(rgb);
+        let delta = value - self.mean;
+        self.mean += delta / f64::from(self.samples);
+        self.squares += delta * (value - self.mean);
+    }
+
+    /// The standard error of the mean luminance, as a fraction of the
+    /// luminance, where pixels darker than `HaltConditions::DARK_LUMINANCE`
+    /// are compared to that luminance. `None` until there are
+    /// `HaltConditions::MIN_SAMPLES` samples to estimate it from.
+    fn relative_error(&self) -> Option<f64> {
+        if self.samples < HaltConditions::MIN_SAMPLES {
+            return None;
+        }
+        let samples = f64::from(self.samples);
+        let standard_error = (self.squares / (samples - 1.0) / samples).sqrt();
+        Some(standard_error / self.mean.max(HaltConditions::DARK_LUMINANCE))
+    }
+}
+
+/// The noise of the pixels of a progressive render, for leaving clean pixels
+/// out of passes and spreading the rays of passes by noise.
+struct PassNoise {
+    pixels: Vec<SampleStats>,
+    /// The pass that `mean_error` was last found in.
+    pass: u32,
+    /// The mean relative error of the pixels with enough samples to estimate
+    /// it, at the start of `pass`, or 0 if there are none.
+    mean_error: f64,
+}
+
+impl PassNoise {
+    fn new(num_pixels: usize) -> Self {
+        Self {
+            pixels: vec![SampleStats::default(); num_pixels],
+            pass: 0,
+            mean_error: 0.0,
+        }
+    }
+
+    /// Find the mean relative error of the pixels, once per pass.
+    fn start_pass(&mut self, pass: u32) {
+        if pass <= self.pass {
+            return;
+        }
+        self.pass = pass;
+        let (sum, count) = self
+            .pixels
+            .iter()
+            .filter_map(SampleStats::relative_error)
+            .fold((0.0, 0_usize), |(sum, count), error| {
+                (sum + error, count + 1)
+            });
+        self.mean_error = if count > 0 { sum / count as f64 } else { 0.0 };
+    }
+
+    /// The number of rays to trace through a pixel with the samples of
+    /// `stats` in a pass with adaptive sampling: about one on average, more
+    /// for pixels noisier than the mean, and none for the cleanest ones.
+    fn rays(&self, stats: &SampleStats) -> u32 {
+        match stats.relative_error() {
+            Some(error) if self.mean_error > 0.0 => {
+                let rays = (error / self.mean_error).round();
+                rays.min(f64::from(SamplerSettings::MAX_PASS_RAYS)) as u32
+            }
+            _ => 1,
+        }
+    }
+}
+
+/// The work done so far on the pixel that a thread is rendering.
+#[derive(Clone, Copy)]
+struct PixelWork {
+    rays: u64,
+    start: Instant,
+    exceeded: bool,
+}
+
+thread_local! {
+    /// Kept per thread rather than passed along with every ray, since each
+    /// render thread works on one pixel at a time.
+    static PIXEL_WORK: Cell<PixelWork> = Cell::new(PixelWork {
+        rays: 0,
+        start: Instant::now(),
+        exceeded: false,
+    });
+}
+
+/// How the camera projects the scene onto the image.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum Projection {
+    /// Rays spread out from the position of the camera through the screen, so
+    /// that things further away look smaller.
+    #[default]
+    Perspective,
+    /// Rays go along the direction of the camera, from a plane through its
+    /// position, so that things look the same size at any distance, as in
+    /// technical drawings and isometric art. `view_width` is the width of the
+    /// part of the scene seen, across the direction of the camera.
+    Orthographic { view_width: f64 },
+    /// Rays spread out from the position of the camera in all directions up
+    /// to half of `fov` degrees from its direction, which can be 180 degrees
+    /// or more, with `fov` across the width of the image. Pixels beyond the
+    /// widest angle that `mapping` can show are black and transparent.
+    Fisheye { mapping: FisheyeMapping, fov: f64 },
+}
+
+/// The settings of a real camera, in the units of photography, for matching
+/// renders with photographs. The screen and lens of the camera, and the
+/// exposure of the scene, are found from them, with the units of the scene
+/// taken to be meters. The lens is a thin lens, and the field of view is that
+/// of the lens focused at infinity.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PhysicalCamera {
+    /// The focal length of the lens, in millimeters.
+    pub focal_length: f64,
+    /// The width of the sensor, in millimeters. 36 for full frame cameras.
+    pub sensor_width: f64,
+    /// The focal length divided by the diameter of the aperture.
+    pub f_number: f64,
+    /// How far in front of the camera things are in focus, in meters.
+    pub focus_distance: f64,
+    /// The sensitivity of the sensor.
+    pub iso: f64,
+    /// How long the shutter is open, in seconds.
+    pub shutter_time: f64,
+}
+
+impl Default for PhysicalCamera {
+    /// A full frame camera with a 50 mm lens at f/8, focused at 10 meters,
+    /// at ISO 100 with a shutter time of 1/125 seconds.
+    fn default() -> Self {
+        Self {
+            focal_length: 50.0,
+            sensor_width: 36.0,
+            f_number: 8.0,
+            focus_distance: 10.0,
+            iso: 100.0,
+            shutter_time: 1.0 / 125.0,
+        }
+    }
+}
+
+impl PhysicalCamera {
+    /// Find the horizontal field of view, in degrees.
+    pub fn horizontal_fov(&self) -> f64 {
+        2.0 * (0.5 * self.sensor_width / self.focal_length)
+            .atan()
+            .to_degrees()
+    }
+
+    /// Find the radius of the aperture, in meters.
+    pub fn aperture_radius(&self) -> f64 {
+        0.5 * self.focal_length * 1e-3 / self.f_number
+    }
+
+    /// Find the exposure from the aperture, shutter time and sensitivity.
+    pub fn exposure(&self) -> Exposure {
+        Exposure::from_camera(self.f_number, self.shutter_time, self.iso)
+    }
+}
+
+/// How fisheye cameras map the angle from the direction of the camera to the
+/// distance from the middle of the image.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum FisheyeMapping {
+    /// The distance is proportional to the angle, so that angles are kept
+    /// along lines through the middle. Shows angles up to 180 degrees.
+    Equidistant,
+    /// The distance is proportional to the sine of half the angle, so that
+    /// areas in the image are proportional to solid angles. Shows all
+    /// angles.
+    Equisolid,
+}
+
+impl FisheyeMapping {
+    /// The distance from the middle of the image of what is `angle` radians
+    /// from the direction of the camera, in units that depend on the mapping.
+    fn radius(self, angle: f64) -> f64 {
+        match self {
+            FisheyeMapping::Equidistant => angle,
+            FisheyeMapping::Equisolid => 2.0 * (0.5 * angle).sin(),
+        }
+    }
+
+    /// The angle from the direction of the camera of what is at `radius`
+    /// from the middle of the image, the inverse of `radius`. Returns `None`
+    /// beyond the largest radius of the mapping.
+    fn angle(self, radius: f64) -> Option<f64> {
+        match self {
+            FisheyeMapping::Equidistant => (radius <= PI).then_some(radius),
+            FisheyeMapping::Equisolid => (radius <= 2.0).then(|| 2.0 * (0.5 * radius).asin()),
+        }
+    }
+}
+
 /// The camera determines from which direction the scene is rendered. The
 /// default camera is located at the origin, looking along the y-axis, with up
-/// along the z-axis.
-struct Camera {
-    position: Vector3,
-    orientation: UnitQuaternion,
-    screen_width: f64,
-    distance_to_screen: f64,
+/// along the z-axis, and has a perspective projection. Settings left out when
+/// deserializing a camera are those of the default camera.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Camera {
+    pub position: Vector3,
+    /// The rotation from the default orientation of the camera.
+    pub orientation: UnitQuaternion,
+    /// The width of the screen that the image is projected onto, in front of
+    /// the camera. Together with `distance_to_screen`, it sets the field of
+    /// view of perspective cameras.
+    pub screen_width: f64,
+    pub distance_to_screen: f64,
+    pub projection: Projection,
+    /// The radius of the lens. Things away from `focus_distance` are blurred
+    /// the more, the larger the lens is. 0 for a pinhole camera, which has
+    /// everything in focus.
+    pub aperture_radius: f64,
+    /// How far in front of the camera things are in focus.
+    pub focus_distance: f64,
+    /// The number of samples of the lens taken of each pixel, when the
+    /// integrator takes only one sample of each. Other integrators sample
+    /// the lens once per sample.
+    pub lens_samples: u32,
+    /// How far the image is shifted to the right and up across the direction
+    /// of the camera, as fractions of its width, for perspective and
+    /// orthographic cameras. Shifting up instead of turning the camera
+    /// upwards keeps vertical lines parallel.
+    pub shift: (f64, f64),
+    /// How many degrees the plane in focus is turned around the right
+    /// direction of the camera, with the top away from the camera for
+    /// positive angles, for perspective and orthographic cameras with a lens.
+    /// A plane in focus along the ground gives the look of a miniature.
+    pub tilt: f64,
+    /// The radial distortion of the lens: the coefficients of the square and
+    /// the fourth power of the distance from the middle of the image, as a
+    /// fraction of half its width, by which the lens moves what is seen
+    /// outwards. Positive coefficients give barrel distortion, bulging the
+    /// image, and negative ones pincushion distortion.
+    pub distortion: (f64, f64),
+    /// How much larger the image is in red and smaller in blue than in green,
+    /// as a fraction, for the colored fringes towards the edges of the images
+    /// of real lenses. Each pixel is rendered once for each channel.
+    pub chromatic_aberration: f64,
 }
 
 impl Default for Camera {
@@ -34,36 +504,601 @@ impl Default for Camera {
             orientation: UnitQuaternion::id(),
             screen_width: 0.64,
             distance_to_screen: 0.5,
+            projection: Projection::Perspective,
+            aperture_radius: 0.0,
+            focus_distance: 1.0,
+            lens_samples: 16,
+            shift: (0.0, 0.0),
+            tilt: 0.0,
+            distortion: (0.0, 0.0),
+            chromatic_aberration: 0.0,
         }
     }
 }
 
 impl Camera {
+    /// Make a camera at `eye`, looking at `target`, turned so that `up` points
+    /// as far up in the image as it can. If `up` is along the direction of
+    /// view, any direction across it is used instead. The screen is the
+    /// default one.
+    pub fn look_at<T: Into<Vector3>, U: Into<Vector3>, V: Into<Vector3>>(
+        eye: T,
+        target: U,
+        up: V,
+    ) -> Self {
+        let position = eye.into();
+        let direction = (target.into() - position).normalize();
+        let right = direction.cross(up.into());
+        let right = if right.norm2() > 1e-12 {
+            right.normalize()
+        } else {
+            direction.perpendicular()
+        };
+        let up = right.cross(direction);
+
+        Self {
+            position,
+            orientation: UnitQuaternion::from_basis(right, direction, up),
+            ..Self::default()
+        }
+    }
+
+    /// Make a camera placed by the world-from-camera `matrix`, given as rows
+    /// of a 4 x 4 matrix, as cameras are placed in Blender and glTF, where
+    /// the camera looks along its negative z-axis, with y up. Scaling in the
+    /// matrix is ignored, and the screen is the default one.
+    pub fn from_matrix(matrix: [[f64; 4]; 4]) -> Self {
+        let column = |j: usize| Vector3::from((matrix[0][j], matrix[1][j], matrix[2][j]));
+        let direction = (-column(2)).normalize();
+        let right = direction.cross(column(1)).normalize();
+        let up = right.cross(direction);
+
+        Self {
+            position: column(3),
+            orientation: UnitQuaternion::from_basis(right, direction, up),
+            ..Self::default()
+        }
+    }
+
+    /// The world-from-camera matrix of the camera, as rows of a 4 x 4
+    /// matrix, with the camera looking along its negative z-axis, as for
+    /// `from_matrix`.
+    pub fn matrix(&self) -> [[f64; 4]; 4] {
+        let (right, up, backward) = (self.right(), self.up(), -self.direction());
+        let position = self.position;
+        [
+            [right.x, up.x, backward.x, position.x],
+            [right.y, up.y, backward.y, position.y],
+            [right.z, up.z, backward.z, position.z],
+            [0.0, 0.0, 0.0, 1.0],
+        ]
+    }
+
     /// Find the unit vector that points up when viewed through the camera.
-    fn up(&self) -> Vector3 {
+    pub fn up(&self) -> Vector3 {
         let ref_up = Vector3::k();
         ref_up.rotate(self.orientation)
     }
 
     /// Find the unit vector that points through the middle of the camera.
-    fn direction(&self) -> Vector3 {
+    pub fn direction(&self) -> Vector3 {
         let ref_dir = Vector3::j();
         ref_dir.rotate(self.orientation)
     }
 
     /// Find the unit vector that points right when viewed through the camera.
-    fn right(&self) -> Vector3 {
+    pub fn right(&self) -> Vector3 {
         self.direction().cross(self.up())
     }
+
+    /// Make the camera orthographic, seeing a part of the scene `view_width`
+    /// wide.
+    pub fn with_orthographic(mut self, view_width: f64) -> Self {
+        self.projection = Projection::Orthographic { view_width };
+        self
+    }
+
+    /// Make the camera a fisheye camera, mapping angles to the image by
+    /// `mapping`, and seeing `fov` degrees across the width of the image.
+    pub fn with_fisheye(mut self, mapping: FisheyeMapping, fov: f64) -> Self {
+        self.projection = Projection::Fisheye { mapping, fov };
+        self
+    }
+
+    /// Give the camera a lens with radius `aperture_radius`, focused at
+    /// `focus_distance` in front of it, for depth of field.
+    pub fn with_depth_of_field(mut self, aperture_radius: f64, focus_distance: f64) -> Self {
+        self.aperture_radius = aperture_radius;
+        self.focus_distance = focus_distance;
+        self
+    }
+
+    /// Set the screen and the lens from the settings of `physical`, with the
+    /// sensor as the screen, at the focal length from the camera.
+    pub fn with_physical(self, physical: &PhysicalCamera) -> Self {
+        Self {
+            screen_width: physical.sensor_width * 1e-3,
+            distance_to_screen: physical.focal_length * 1e-3,
+            ..self
+        }
+        .with_depth_of_field(physical.aperture_radius(), physical.focus_distance)
+    }
+
+    /// Shift the image `right` and `up`, as fractions of its width.
+    pub fn with_lens_shift(mut self, right: f64, up: f64) -> Self {
+        self.shift = (right, up);
+        self
+    }
+
+    /// Tilt the plane in focus by `degrees`.
+    pub fn with_tilt(mut self, degrees: f64) -> Self {
+        self.tilt = degrees;
+        self
+    }
+
+    /// Give the lens radial distortion with the coefficients `k1` and `k2`.
+    pub fn with_distortion(mut self, k1: f64, k2: f64) -> Self {
+        self.distortion = (k1, k2);
+        self
+    }
+
+    /// Make the image `amount` larger in red and smaller in blue.
+    pub fn with_chromatic_aberration(mut self, amount: f64) -> Self {
+        self.chromatic_aberration = amount;
+        self
+    }
+
+    /// Does the lens bend the image, by distortion or chromatic aberration?
+    fn distorts(&self) -> bool {
+        self.distortion != (0.0, 0.0) || self.chromatic_aberration != 0.0
+    }
+
+    /// Does the camera have a lens, blurring what is out of focus?
+    pub fn has_lens(&self) -> bool {
+        self.aperture_radius > 0.0
+    }
+
+    /// Widen or narrow the screen to a horizontal field of view of `degrees`.
+    pub fn with_horizontal_fov(mut self, degrees: f64) -> Self {
+        self.screen_width = 2.0 * self.distance_to_screen * (0.5 * degrees.to_radians()).tan();
+        self
+    }
+
+    /// Widen or narrow the screen to a vertical field of view of `degrees`, in
+    /// images with width divided by height equal to `aspect_ratio`.
+    pub fn with_vertical_fov(self, degrees: f64, aspect_ratio: f64) -> Self {
+        let vertical = (0.5 * degrees.to_radians()).tan();
+        let horizontal = 2.0 * (aspect_ratio * vertical).atan().to_degrees();
+        self.with_horizontal_fov(horizontal)
+    }
+
+    /// Find the horizontal field of view of the camera, in degrees, from its
+    /// screen, or for fisheye cameras, as given.
+    pub fn horizontal_fov(&self) -> f64 {
+        if let Projection::Fisheye { fov, .. } = self.projection {
+            return fov;
+        }
+        2.0 * (0.5 * self.screen_width / self.distance_to_screen)
+            .atan()
+            .to_degrees()
+    }
+
+    /// Find the vertical field of view of the camera, in degrees, in images
+    /// with width divided by height equal to `aspect_ratio`.
+    pub fn vertical_fov(&self, aspect_ratio: f64) -> f64 {
+        if let Projection::Fisheye { mapping, fov } = self.projection {
+            let radius = mapping.radius(0.5 * fov.to_radians()) / aspect_ratio;
+            return 2.0 * mapping.angle(radius).unwrap_or(PI).to_degrees();
+        }
+        let screen_height = self.screen_width / aspect_ratio;
+        2.0 * (0.5 * screen_height / self.distance_to_screen)
+            .atan()
+            .to_degrees()
+    }
+
+    /// The width of the pixels of an image `width` pixels wide, on the screen
+    /// of perspective cameras, in the scene for orthographic ones, and in the
+    /// units of the mapping for fisheye ones.
+    fn pixel_size(&self, width: usize) -> f64 {
+        match self.projection {
+            Projection::Perspective => self.screen_width / width as f64,
+            Projection::Orthographic { view_width } => view_width / width as f64,
+            Projection::Fisheye { mapping, fov } => {
+                mapping.radius(0.5 * fov.to_radians()) / (0.5 * width as f64)
+            }
+        }
+    }
+
+    /// Find the direction that a fisheye camera with `mapping` sees at
+    /// `right` pixels to the right of and `up` pixels above the middle of an
+    /// image `width` pixels wide, or `None` if it sees nothing there.
+    fn fisheye_direction(
+        &self,
+        mapping: FisheyeMapping,
+        width: usize,
+        right: f64,
+        up: f64,
+    ) -> Option<Vector3> {
+        let radius = right.hypot(up);
+        let angle = mapping.angle(radius * self.pixel_size(width))?;
+        let across = if radius > 0.0 {
+            (right * self.right() + up * self.up()) * (1.0 / radius)
+        } else {
+            Vector3::zero()
+        };
+        let (sin, cos) = angle.sin_cos();
+        Some(cos * self.direction() + sin * across)
+    }
+
+    /// Find the ray from the camera through the middle of the pixel at
+    /// (`pixel_x`, `pixel_y`) of an image of size `width` x `height`, together
+    /// with the rays through the next pixels to the right and below, for
+    /// finding how large the pixel is on textures. Returns `None` if the
+    /// camera sees nothing through the pixel.
+    fn pixel_rays(
+        &self,
+        width: usize,
+        height: usize,
+        pixel_x: usize,
+        pixel_y: usize,
+    ) -> Option<(Ray, [Ray; 2])> {
+        self.channel_rays(width, height, pixel_x, pixel_y, 1.0, (0.0, 0.0))
+    }
+
+    /// Like `pixel_rays`, but for a color channel whose image is
+    /// `1 / magnification` times as large as the green one, by chromatic
+    /// aberration, and through the point `offset` pixels from the middle of
+    /// the pixel.
+    fn channel_rays(
+        &self,
+        width: usize,
+        height: usize,
+        pixel_x: usize,
+        pixel_y: usize,
+        magnification: f64,
+        offset: (f64, f64),
+    ) -> Option<(Ray, [Ray; 2])> {
+        // The lens moves what is seen through the pixel outwards by the
+        // distortion.
+        let right = pixel_x as f64 + offset.0 - 0.5 * (width - 1) as f64;
+        let up = pixel_y as f64 + offset.1 - 0.5 * (height - 1) as f64;
+        let radius2 = (right * right + up * up) / (0.25 * (width * width) as f64);
+        let (k1, k2) = self.distortion;
+        let scale = magnification * (1.0 + k1 * radius2 + k2 * radius2 * radius2);
+
+        if let Projection::Fisheye { mapping, .. } = self.projection {
+            let right = right * scale;
+            let up = -up * scale;
+            let direction = self.fisheye_direction(mapping, width, right, up)?;
+            // Next to the widest angle, the neighbors may see nothing.
+            let neighbor = |right, up| {
+                let neighbor = self.fisheye_direction(mapping, width, right, up);
+                Ray::new(self.position, neighbor.unwrap_or(direction))
+            };
+            return Some((
+                Ray::new(self.position, direction),
+                [neighbor(right + scale, up), neighbor(right, up - scale)],
+            ));
+        }
+
+        let pixel_size = self.pixel_size(width);
+        let delta_y = -up * scale * pixel_size * self.up();
+        let delta_x = right * scale * pixel_size * self.right();
+        let (right, down) = (
+            pixel_size * scale * self.right(),
+            -pixel_size * scale * self.up(),
+        );
+        let shift =
+            (pixel_size * width as f64) * (self.shift.0 * self.right() + self.shift.1 * self.up());
+
+        match self.projection {
+            Projection::Perspective => {
+                let center_of_screen = self.direction() * self.distance_to_screen + shift;
+                let direction = center_of_screen + delta_x + delta_y;
+                Some((
+                    Ray::new(self.position, direction),
+                    [
+                        Ray::new(self.position, direction + right),
+                        Ray::new(self.position, direction + down),
+                    ],
+                ))
+            }
+            _ => {
+                let origin = self.position + shift + delta_x + delta_y;
+                let direction = self.direction();
+                Some((
+                    Ray::new(origin, direction),
+                    [
+                        Ray::new(origin + right, direction),
+                        Ray::new(origin + down, direction),
+                    ],
+                ))
+            }
+        }
+    }
+
+    /// Move the start of `ray`, found by `pixel_rays`, to a point on the lens
+    /// picked with `rng`, and turn it towards where it is in focus: where it
+    /// crosses the plane in focus, or for fisheye cameras, the sphere in
+    /// focus. Returns the ray as it is, without using `rng`, if the camera has
+    /// no lens, or if the ray never crosses the plane in focus. Each call
+    /// counts as a primary ray in the render statistics.
+    fn lens_ray(&self, ray: &Ray, rng: &mut Rng) -> Ray {
+        stats::count(Counter::PrimaryRays);
+        if !self.has_lens() {
+            return ray.clone();
+        }
+        let distance = match self.projection {
+            Projection::Fisheye { .. } => self.focus_distance,
+            _ if self.tilt == 0.0 => self.focus_distance / ray.direction.dot(self.direction()),
+            _ => {
+                let (sin, cos) = self.tilt.to_radians().sin_cos();
+                let normal = cos * self.direction() - sin * self.up();
+                let center = self.position + self.focus_distance * self.direction();
+                (center - ray.origin).dot(normal) / ray.direction.dot(normal)
+            }
+        };
+        if !(distance > 0.0 && distance.is_finite()) {
+            return ray.clone();
+        }
+        let focus = ray.origin + ray.direction * distance;
+        let (x, y) = sampling::concentric_disk(rng.next_pair());
+        let origin = ray.origin + self.aperture_radius * (x * self.right() + y * self.up());
+        Ray::new(origin, focus - origin)
+    }
+
+    /// Find where in an image of size `width` x `height` the point at `offset`
+    /// from the camera is seen, in pixels, the inverse of `pixel_rays`. For
+    /// perspective cameras, `offset` may also be the direction to something
+    /// infinitely far away, and for fisheye cameras, it is only a direction.
+    /// Returns `None` for what isn't in front of the camera, except for
+    /// fisheye cameras, which map every direction somewhere. Distortion and
+    /// chromatic aberration are left out.
+    fn project(&self, offset: Vector3, width: usize, height: usize) -> Option<(f64, f64)> {
+        let forward = offset.dot(self.direction());
+        if let Projection::Fisheye { mapping, .. } = self.projection {
+            let (right, up) = (offset.dot(self.right()), offset.dot(self.up()));
+            let across = right.hypot(up);
+            let angle = across.atan2(forward);
+            if offset.norm2() == 0.0 {
+                return None;
+            }
+            let scale = if across > 0.0 {
+                mapping.radius(angle) / (self.pixel_size(width) * across)
+            } else {
+                0.0
+            };
+            return Some((
+                right * scale + 0.5 * (width - 1) as f64,
+                -up * scale + 0.5 * (height - 1) as f64,
+            ));
+        }
+        if forward <= 0.0 {
+            return None;
+        }
+        let pixel_size = self.pixel_size(width);
+        let scale = match self.projection {
+            Projection::Perspective => self.distance_to_screen / (forward * pixel_size),
+            _ => 1.0 / pixel_size,
+        };
+        let shift = (self.shift.0 * width as f64, self.shift.1 * width as f64);
+        Some((
+            offset.dot(self.right()) * scale + 0.5 * (width - 1) as f64 - shift.0,
+            -offset.dot(self.up()) * scale + 0.5 * (height - 1) as f64 + shift.1,
+        ))
+    }
+}
+
+/// The algorithm used for computing the color of each pixel.
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+pub enum Integrator {
+    /// Only light arriving directly from the light sources, or via perfectly
+    /// specular reflection and refraction, is taken into account. Fast and
+    /// noise free, but without any other indirect light.
+    #[default]
+    DirectLighting,
+    /// Monte Carlo path tracing. Light bounces diffusely between surfaces up to
+    /// `max_depth` times, giving indirect light and color bleeding. Each pixel
+    /// is the average of `samples_per_pixel` paths.
+    PathTracing {
+        max_depth: u32,
+        samples_per_pixel: u32,
+    },
+    /// Like `DirectLighting`, plus diffuse interreflection from an irradiance
+    /// cache. At sparse points, the light arriving from other surfaces is
+    /// found by tracing `samples` paths of up to `max_depth` bounces, and it is
+    /// interpolated between them. Much faster than path tracing for mostly
+    /// diffuse scenes, and free of noise, but glossy reflections only show
+    /// direct light. A lower `max_error` gives denser points and fewer
+    /// artifacts; 0.2 is a good start.
+    IrradianceCaching {
+        max_depth: u32,
+        samples: u32,
+        max_error: f64,
+    },
+}
+
+/// How rendering of an image is split between threads. Threads take square
+/// tiles of pixels one at a time, until all are rendered. Small tiles share
+/// the work more evenly when some parts of the image are much slower to render
+/// than others, and large tiles cost less to hand out.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RenderSchedule {
+    pub num_threads: usize,
+    /// The width and height of the tiles, in pixels.
+    pub tile_size: usize,
+}
+
+/// A rectangle of pixels of an image, for rendering only that part of it.
+/// Pixels are counted from the top left corner of the image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CropWindow {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl CropWindow {
+    /// The columns and rows of the pixels of the window that are in an image
+    /// of size `width` x `height`.
+    pub fn ranges(&self, width: usize, height: usize) -> (Range<usize>, Range<usize>) {
+        let clip = |start: usize, length: usize, end: usize| {
+            start.min(end)..start.saturating_add(length).min(end)
+        };
+        (
+            clip(self.x, self.width, width),
+            clip(self.y, self.height, height),
+        )
+    }
+}
+
+/// How many threads to render with.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ThreadSettings {
+    /// The number of render threads, at least one. The default, `None`, is
+    /// to use all logical CPUs but one, so that the rest of the system stays
+    /// responsive, and fewer if the image is too quick to render to be worth
+    /// more.
+    pub num_threads: Option<usize>,
+}
+
+impl ThreadSettings {
+    /// The most threads to render with, at least one.
+    pub fn max_threads(&self) -> usize {
+        self.num_threads
+            .unwrap_or_else(|| num_cpus::get().saturating_sub(1))
+            .max(1)
+    }
+}
+
+/// The settings of a render, given together to `Scene::spawn_render` instead
+/// of with a setter each. Start from `Scene::render_settings` to keep the
+/// settings of the scene that aren't changed.
+#[derive(Clone, Copy)]
+pub struct RenderSettings {
+    /// The width of the image, in pixels.
+    pub width: usize,
+    /// The height of the image, in pixels.
+    pub height: usize,
+    /// The integrator, which also gives the number of samples per pixel and
+    /// the most bounces of light, as set by `Scene::set_integrator`.
+    pub integrator: Integrator,
+    /// Mixed into the random numbers of every pixel, as
+    /// `SamplerSettings::seed`.
+    pub seed: u64,
+    /// How many threads to render with, as set by `Scene::set_threads`.
+    pub threads: ThreadSettings,
+    /// The width and height of the tiles that the threads take, in pixels. If
+    /// `None`, the schedule of the scene is used, or else it is calibrated.
+    /// With a tile size, the number of threads isn't calibrated either.
+    pub tile_size: Option<usize>,
+    /// The part of the image to render, as set by `Scene::set_crop_window`.
+    pub crop: Option<CropWindow>,
+    /// A view of the geometry of the scene to render instead of its colors,
+    /// as the render algorithm. The render algorithm of the scene, if any, is
+    /// kept if `None`.
+    pub debug_view: Option<DebugView>,
+}
+
+/// A handle to a surface in a scene, returned when the surface is added. It
+/// stays valid until the surface is removed, while the indices of surfaces
+/// change as surfaces before them are removed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SurfaceId(u64);
+
+/// A handle to a light in a scene, returned when the light is added, like a
+/// `SurfaceId`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct LightId(u64);
+
+/// A surface in the scene, together with the material it is made of.
+struct Object {
+    id: SurfaceId,
+    /// The name and tags of the surface, as set by `set_surface_name` and
+    /// `tag_surface`.
+    labels: Labels,
+    /// The node of the scene graph that places the surface, and the surface
+    /// in the space of the node, if it was added with `add_surface_to_node`.
+    node: Option<(NodeId, Arc<dyn Surface + Send + Sync>)>,
+    surface: Box<dyn Surface + Send + Sync>,
+    /// Shared with other objects if it is a named material.
+    material: Arc<Material>,
+    /// Decides which of two coincident surfaces is hit, as set by
+    /// `set_surface_priority`.
+    priority: i32,
+    /// Whether the surface is cut out of the image, as set by
+    /// `set_surface_holdout`.
+    holdout: bool,
+    /// The transforms that placed the surface in the previous frame and place
+    /// it now, as set by `set_surface_motion`.
+    motion: Option<(Transform, Transform)>,
 }
 
 /// A `Scene` contains the camera, light sources, and surfaces that are to be
 /// rendered.
 #[derive(Default)]
 pub struct Scene {
-    surfaces: Vec<Box<dyn Surface + Send + Sync>>,
+    objects: Vec<Object>,
+    /// Materials that surfaces can refer to by name.
+    named_materials: HashMap<String, Arc<Material>>,
     camera: Camera,
-    lights: Vec<Sun>,
+    exposure: Option<Exposure>,
+    /// The camera in the previous frame, for motion vectors, if it moved.
+    previous_camera: Option<Camera>,
+    lights: Vec<Box<dyn Light + Send + Sync>>,
+    /// The handles of `lights`, and their names and tags, in the same order.
+    light_labels: Vec<(LightId, Labels)>,
+    /// The nodes of the scene graph, indexed by `NodeId`.
+    nodes: Vec<graph::Node>,
+    /// The number of surfaces and lights added so far, from which their
+    /// handles are made.
+    num_added: u64,
+    /// The color space that colors of lights and textures are given in.
+    color_space: ColorSpace,
+    integrator: Integrator,
+    /// Used instead of the integrator, if set.
+    algorithm: Option<Box<dyn RenderAlgorithm + Send + Sync>>,
+    /// Caustics from glass and mirrors, if enabled.
+    caustics: Option<PhotonMap>,
+    /// The lights picked from at each point instead of sampling all of them,
+    /// if built.
+    light_tree: Option<LightTree>,
+    /// Filled in while rendering with `Integrator::IrradianceCaching`.
+    irradiance_cache: IrradianceCache,
+    pixel_limits: PixelLimits,
+    ray_bias: RayBias,
+    sampler: SamplerSettings,
+    halt: HaltConditions,
+    /// When to stop sampling, from `halt.max_time`, set when rendering starts.
+    deadline: Option<Instant>,
+    /// Set by the `RenderHandle` of a render to stop it.
+    cancelled: Arc<AtomicBool>,
+    /// Found by `calibrate` when rendering if not set.
+    schedule: Option<RenderSchedule>,
+    /// How many threads `calibrate` may use.
+    threads: ThreadSettings,
+    /// Whether to keep the system from sleeping while rendering.
+    keep_awake: bool,
+    /// Whether to render at a lower priority than other programs.
+    low_priority: bool,
+    /// Whether renders are to be denoised.
+    denoise: bool,
+    /// The camera sensor that raw images of renders are made with, if any.
+    sensor: Option<Sensor>,
+    /// The lens flares added to renders, if any.
+    lens_flare: Option<LensFlare>,
+    /// The groups of lights rendered to images of their own, if any.
+    light_groups: Option<LightGroups>,
+    /// The cost of each pixel of renders, if it is recorded.
+    cost_map: Option<CostMap>,
+    /// The index in `lights` of the environment map, if there is one.
+    environment: Option<usize>,
+    /// The rectangles of pixels rendered, if not the whole image, as set by
+    /// `spawn_incremental_render`.
+    render_region: Option<Vec<(Range<usize>, Range<usize>)>>,
+    /// The part of the image rendered, if not all of it.
+    crop: Option<CropWindow>,
 }
 
 impl Scene {
@@ -72,137 +1107,2296 @@ impl Scene {
         Self::default()
     }
 
-    pub fn add_surface(&mut self, surface: impl Surface + Send + Sync + 'static) {
-        self.surfaces.push(Box::new(surface));
+    /// Load the scene described by the scene file `filename`, in RON, or in
+    /// JSON if its name ends in `.json`, as in `scene_file`. Anything in the
+    /// file that isn't recognized is ignored, without the warnings that
+    /// `scene_file::load_scene` gives.
+    pub fn from_file(filename: &str) -> Result<Self, Box<dyn Error>> {
+        Ok(scene_file::load_scene(filename)?.0)
     }
 
-    pub fn add_light(&mut self, light: Sun) {
-        self.lights.push(light);
+    /// Set the color space that the colors of lights and textures added to the
+    /// scene after this call are given in. They are converted to linear RGB
+    /// when added. The default is linear RGB.
+    pub fn set_color_space(&mut self, color_space: ColorSpace) {
+        self.color_space = color_space;
     }
 
-    /// Render the scene to an image of size `width` x `height`. Only a
-    /// part of the image is actually rendered, based on `thread_id` and
-    /// `num_threads`. The function should be called in `num_threads` separate
-    /// threads, where all the `sender`s send to the same receiver. The `sender`
-    /// sends rendered pixels together with x-y-coordinates through a channel.
-    pub fn render(
-        &self,
-        width: usize,
-        height: usize,
-        sender: Sender<(usize, usize, Pixel)>,
-        thread_id: usize,
-        num_threads: usize,
-    ) -> Result<(), Box<dyn Error>> {
-        let pixel_size = self.camera.screen_width / width as f64;
+    /// Choose the algorithm used for rendering. The default is
+    /// `Integrator::DirectLighting`. Panics if `Integrator::IrradianceCaching`
+    /// has no samples, or a `max_error` that isn't positive and finite.
+    pub fn set_integrator(&mut self, integrator: Integrator) {
+        if let Integrator::IrradianceCaching {
+            samples, max_error, ..
+        } = integrator
+        {
+            assert!(
+                samples > 0 && max_error > 0.0 && max_error.is_finite(),
+                "Irradiance caching needs samples and a positive, finite maximum error"
+            );
+        }
+        self.integrator = integrator;
+        self.irradiance_cache.clear();
+    }
 
-        let center_of_screen = self.camera.direction() * self.camera.distance_to_screen;
+    /// Render the scene with `algorithm` instead of the integrator set by
+    /// `set_integrator`. Scenes rendered with other algorithms than the
+    /// integrators can't be hashed.
+    pub fn set_render_algorithm(
+        &mut self,
+        algorithm: impl RenderAlgorithm + Send + Sync + 'static,
+    ) {
+        self.algorithm = Some(Box::new(algorithm));
+    }
 
-        for pixel_y in 0..height {
-            if (pixel_y + thread_id) % num_threads != 0 {
-                // Skip the line.
-                continue;
-            }
+    /// Limit the work done for each pixel. The default is no limits.
+    pub fn set_pixel_limits(&mut self, limits: PixelLimits) {
+        self.pixel_limits = limits;
+    }
 
-            let delta_y =
-                -(pixel_y as f64 - 0.5 * (height - 1) as f64) * pixel_size * self.camera.up();
+    /// Set how far rays must go before they can hit a surface. The default is
+    /// `RayBias::default()`.
+    pub fn set_ray_bias(&mut self, bias: RayBias) {
+        self.ray_bias = bias;
+    }
 
-            for pixel_x in 0..width {
-                let delta_x =
-                    (pixel_x as f64 - 0.5 * (width - 1) as f64) * pixel_size * self.camera.right();
+    /// Choose how the random numbers of each pixel are chosen. The default is
+    /// independent random numbers for each pixel.
+    pub fn set_sampler(&mut self, sampler: SamplerSettings) {
+        self.sampler = sampler;
+    }
 
-                let direction = center_of_screen + delta_x + delta_y;
+    /// How each pixel is sampled, as set by `set_sampler`.
+    pub fn sampler(&self) -> &SamplerSettings {
+        &self.sampler
+    }
 
-                let ray = Ray::new(self.camera.position, direction);
-
-                let mut rgb = Vector3::zero();
-                match self.trace(ray) {
-                    None => (),
-                    Some((intersection, normal)) => {
-                        for light in self.lights.iter() {
-                            let dir_to_light = -light.direction;
-                            let shadow_ray = Ray::new(intersection, dir_to_light);
-                            match self.trace(shadow_ray) {
-                                Some(_) => (),
-                                None => {
-                                    // The light illuminates the intersection point.
-                                    rgb += normal.dot(dir_to_light).max(0.0) * light.color;
-                                }
-                            }
-                        }
-                    }
-                }
-                sender.send((pixel_x, pixel_y, rgb.into()))?;
-            }
+    /// The settings of rendering the scene at size `width` x `height`, as
+    /// set on the scene, for changing some and passing to `spawn_render`.
+    pub fn render_settings(&self, width: usize, height: usize) -> RenderSettings {
+        RenderSettings {
+            width,
+            height,
+            integrator: self.integrator,
+            seed: self.sampler.seed,
+            threads: self
+                .schedule
+                .map_or(self.threads, |schedule| ThreadSettings {
+                    num_threads: Some(schedule.num_threads),
+                }),
+            tile_size: self.schedule.map(|schedule| schedule.tile_size),
+            crop: self.crop,
+            debug_view: None,
         }
+    }
 
-        Ok(())
+    /// Set when to stop sampling pixels. The default is to always take the
+    /// number of samples given by the integrator.
+    pub fn set_halt_conditions(&mut self, halt: HaltConditions) {
+        self.halt = halt;
     }
 
-    /// Spawn multiple threads for rendering the scene. The number of threads
-    /// spawned is one less than the number of CPU cores. Each thread renders a
-    /// subset of the pixels of the image. When a pixel is finished, it is sent
-    /// through a channel. The receiving end of the channel is returned from
-    /// this function.
-    pub fn spawn_render_threads(
-        self,
-        window_width: usize,
-        window_height: usize,
-    ) -> Receiver<(usize, usize, Pixel)> {
-        let (sender, receiver) = mpsc::channel();
-        let num_threads = num_cpus::get() - 1;
-        let scene_arc = Arc::new(self);
-        for thread_id in 1..num_threads {
-            let scene_clone = scene_arc.clone();
-            let sender_clone = sender.clone();
+    /// Set how rendering is split between threads. The default, `None`, is to
+    /// choose with `calibrate` for each render.
+    pub fn set_render_schedule(&mut self, schedule: Option<RenderSchedule>) {
+        self.schedule = schedule;
+    }
 
-            thread::spawn(move || {
-                scene_clone
-                    .render(
-                        window_width,
-                        window_height,
-                        sender_clone,
-                        thread_id,
-                        num_threads,
-                    )
-                    .unwrap();
-            });
-        }
+    /// Set how many threads to render with, for when the schedule is chosen
+    /// by `calibrate`. The tile size is still calibrated.
+    pub fn set_threads(&mut self, threads: ThreadSettings) {
+        self.threads = threads;
+    }
 
-        thread::spawn(move || {
-            scene_arc
-                .render(window_width, window_height, sender, 0, num_threads)
-                .unwrap();
-        });
+    /// Only render the pixels in `crop`, e.g. for working on a detail of the
+    /// scene, or for splitting a frame between machines. The other pixels of
+    /// the image are neither rendered nor sent. The default, `None`, is to
+    /// render the whole image.
+    pub fn set_crop_window(&mut self, crop: Option<CropWindow>) {
+        self.crop = crop;
+    }
 
-        receiver
+    /// Keep the system from going to sleep while the scene is rendered with
+    /// `spawn_render_threads`, for long renders. The default is not to.
+    pub fn set_keep_awake(&mut self, keep_awake: bool) {
+        self.keep_awake = keep_awake;
     }
 
-    /// Trace a ray until it intersects a surface in the scene. If nothing is
-    /// hit, then `None` is returned. Else, a tuple is returned, where the first
-    /// element is the intersection, and the second is the normal vector.
-    fn trace(&self, ray: Ray) -> Option<(Vector3, Vector3)> {
-        let mut closest_intersection = INFINITY;
-        let mut result = None;
+    /// Run the threads spawned by `spawn_render_threads` at a lower priority
+    /// than other programs, so that the machine stays usable during long
+    /// renders. The default is normal priority.
+    pub fn set_low_priority(&mut self, low_priority: bool) {
+        self.low_priority = low_priority;
+    }
 
-        for surface in self.surfaces.iter() {
-            let closest_intersection_of_surface = surface.closest_intersection(&ray);
+    /// Ask for renders of the scene to be denoised, guided by
+    /// `render_albedo` and `render_normals`, as done by
+    /// `crate::denoise::denoise`. The scene only records the setting, which
+    /// the program rendering it acts on. The default is not to denoise.
+    pub fn set_denoise(&mut self, denoise: bool) {
+        self.denoise = denoise;
+    }
 
-            match closest_intersection_of_surface {
-                None => continue,
-                Some((distance, normal)) => {
-                    if distance <= EPSILON.sqrt() {
-                        // TODO: Is square root of machine epsilon a good choice?
-                        // Don't intersect the same point that the ray is leaving from.
-                        continue;
-                    }
-                    // Ray intersects the surface.
-                    if distance < closest_intersection {
-                        closest_intersection = distance;
-                        result = Some((ray.origin + closest_intersection * ray.direction, normal));
-                    }
-                }
-            }
-        }
-        result
+    /// Are renders of the scene to be denoised, as set by `set_denoise`?
+    pub fn denoise(&self) -> bool {
+        self.denoise
+    }
+
+    /// Ask for a raw image of renders of the scene, as captured by `sensor`,
+    /// in addition to the image itself. The scene only records the sensor,
+    /// which the program rendering it uses. The default is no raw image.
+    pub fn set_sensor(&mut self, sensor: Option<Sensor>) {
+        self.sensor = sensor;
+    }
+
+    /// The sensor that raw images of renders are made with, as set by
+    /// `set_sensor`.
+    pub fn sensor(&self) -> Option<&Sensor> {
+        self.sensor.as_ref()
+    }
+
+    /// Ask for lens flares around bright lights in view to be added to renders
+    /// of the scene, at the places found by `flare_sources`. The scene only
+    /// records the setting, which the program rendering it acts on. The
+    /// default is no lens flares.
+    pub fn set_lens_flare(&mut self, lens_flare: Option<LensFlare>) {
+        self.lens_flare = lens_flare;
+    }
+
+    /// The lens flares added to renders, as set by `set_lens_flare`.
+    pub fn lens_flare(&self) -> Option<&LensFlare> {
+        self.lens_flare.as_ref()
+    }
+
+    /// Render an image of the light from each group of lights along with each
+    /// render by `spawn_render_threads`, in the same pass. The keys of
+    /// `groups` name the groups, and the values list the lights in them.
+    /// Lights in no group get a group of their own, and removed lights are
+    /// left out. The images are found from `light_group_images`. The default,
+    /// `None`, is no images.
+    pub fn set_light_groups(&mut self, groups: Option<BTreeMap<String, Vec<LightId>>>) {
+        self.light_groups = groups.map(LightGroups::new);
+    }
+
+    /// The images of the light groups set by `set_light_groups`, filled in
+    /// while the scene renders.
+    pub fn light_group_images(&self) -> Option<LightGroupImages> {
+        self.light_groups.as_ref().map(LightGroups::images)
+    }
+
+    /// Record the cost of each pixel of renders, measured in `cost`, or stop
+    /// recording it if `None`.
+    pub fn set_cost_map(&mut self, cost: Option<PixelCost>) {
+        self.cost_map = cost.map(CostMap::new);
     }
+
+    /// The costs of the pixels recorded as set by `set_cost_map`, filled in
+    /// while the scene renders.
+    pub fn cost_map(&self) -> Option<CostMap> {
+        self.cost_map.clone()
+    }
+
+    /// Find where lights are seen in a render of size `width` x `height`, and
+    /// how bright they are at the camera, for lens flares. Lights outside the
+    /// view or behind opaque surfaces are left out, and those behind
+    /// transparent surfaces are dimmed. Orthographic cameras have no lens, and
+    /// so no lens flares.
+    pub fn flare_sources(&self, width: usize, height: usize) -> Vec<FlareSource> {
+        if let Projection::Orthographic { .. } = self.camera.projection {
+            return Vec::new();
+        }
+        let scale = self.exposure.map_or(1.0, |exposure| exposure.scale());
+        self.lights
+            .iter()
+            .filter_map(|light| {
+                let glare = light.glare(self.camera.position)?;
+                let (x, y) = self.camera.project(glare.direction, width, height)?;
+                if !(0.0..width as f64).contains(&x) || !(0.0..height as f64).contains(&y) {
+                    return None;
+                }
+                self.start_pixel();
+                let transmittance =
+                    self.transmittance(self.camera.position, glare.direction, glare.distance, true);
+                let color = scale * glare.color.elementwise_mul(transmittance);
+                (color.norm2() > 0.0).then_some(FlareSource { x, y, color })
+            })
+            .collect()
+    }
+
+    /// Add a white surface to the scene.
+    pub fn add_surface(&mut self, surface: impl Surface + Send + Sync + 'static) -> SurfaceId {
+        self.add_surface_with_material(surface, Material::default())
+    }
+
+    /// Add a surface to the scene, colored by `texture`.
+    pub fn add_textured_surface(
+        &mut self,
+        surface: impl Surface + Send + Sync + 'static,
+        texture: impl Texture + Send + Sync + 'static,
+    ) -> SurfaceId {
+        self.add_surface_with_material(surface, Material::new(texture))
+    }
+
+    /// Add a surface made of `material` to the scene.
+    pub fn add_surface_with_material(
+        &mut self,
+        surface: impl Surface + Send + Sync + 'static,
+        material: Material,
+    ) -> SurfaceId {
+        let material = Arc::new(material.convert_albedo(self.color_space));
+        self.push_object(Box::new(surface), material)
+    }
+
+    fn push_object(
+        &mut self,
+        surface: Box<dyn Surface + Send + Sync>,
+        material: Arc<Material>,
+    ) -> SurfaceId {
+        let id = SurfaceId(self.num_added);
+        self.num_added += 1;
+        self.objects.push(Object {
+            id,
+            labels: Labels::default(),
+            node: None,
+            surface,
+            material,
+            priority: 0,
+            holdout: false,
+            motion: None,
+        });
+        id
+    }
+
+    /// Find the index of the surface `id` in the order the surfaces were
+    /// added, or `None` if it has been removed.
+    pub fn surface_index(&self, id: SurfaceId) -> Option<usize> {
+        self.objects.iter().position(|object| object.id == id)
+    }
+
+    /// The handles of the surfaces in the scene, in the order they were
+    /// added.
+    pub fn surface_ids(&self) -> Vec<SurfaceId> {
+        self.objects.iter().map(|object| object.id).collect()
+    }
+
+    /// Get the surface `id` to change it, or to replace it with another
+    /// surface, keeping its material and handle. Returns `None` if it has
+    /// been removed. Photon maps aren't updated, so build them again after
+    /// moving specular surfaces. A surface in a node of the scene graph is
+    /// taken out of the node, and stays where it is when the node moves.
+    pub fn get_mut_surface(
+        &mut self,
+        id: SurfaceId,
+    ) -> Option<&mut Box<dyn Surface + Send + Sync>> {
+        let index = self.surface_index(id)?;
+        let object = &mut self.objects[index];
+        object.node = None;
+        Some(&mut object.surface)
+    }
+
+    /// Remove the surface `id` from the scene and return it, or `None` if it
+    /// has already been removed. The surfaces added after it move down one
+    /// index.
+    pub fn remove_surface(&mut self, id: SurfaceId) -> Option<Box<dyn Surface + Send + Sync>> {
+        let index = self.surface_index(id)?;
+        self.irradiance_cache.clear();
+        Some(self.objects.remove(index).surface)
+    }
+
+    /// Register `material` under `name`, so that surfaces can be added with
+    /// `add_surface_with_named_material`. If a material is already registered
+    /// under the name, it is replaced, also on the surfaces made of it.
+    pub fn set_material(&mut self, name: &str, material: Material) {
+        self.irradiance_cache.clear();
+        let material = Arc::new(material.convert_albedo(self.color_space));
+        if let Some(old) = self
+            .named_materials
+            .insert(name.to_string(), Arc::clone(&material))
+        {
+            for object in &mut self.objects {
+                if Arc::ptr_eq(&object.material, &old) {
+                    object.material = Arc::clone(&material);
+                }
+            }
+        }
+    }
+
+    /// Register all the materials in `library`, as with `set_material`.
+    /// Swapping one library for another with the same names changes the
+    /// palette of the whole scene.
+    pub fn set_materials(&mut self, library: MaterialLibrary) {
+        for (name, material) in library {
+            self.set_material(&name, material);
+        }
+    }
+
+    /// Add a surface made of the material registered under `name`. Returns an
+    /// error if there is no such material.
+    pub fn add_surface_with_named_material(
+        &mut self,
+        surface: impl Surface + Send + Sync + 'static,
+        name: &str,
+    ) -> Result<SurfaceId, Box<dyn Error>> {
+        let material = self
+            .named_materials
+            .get(name)
+            .ok_or_else(|| format!("Unknown material {}", name))?;
+        Ok(self.push_object(Box::new(surface), Arc::clone(material)))
+    }
+
+    /// Set the priority of the surface `id`. Where two surfaces coincide, like
+    /// a decal on a floor, rays hit the one with the higher priority, instead
+    /// of either one depending on rounding errors. The default priority is 0.
+    /// Does nothing if the surface has been removed.
+    pub fn set_surface_priority(&mut self, id: SurfaceId, priority: i32) {
+        if let Some(index) = self.surface_index(id) {
+            self.objects[index].priority = priority;
+        }
+    }
+
+    /// Make the surface `id` a holdout, or back to a regular surface. Seen by
+    /// the camera, a holdout is fully transparent, and hides whatever is
+    /// behind it, leaving a hole in the image where live footage in front of
+    /// the rendered objects is to be composited. Otherwise, e.g. in shadows
+    /// and reflections, it is a regular surface. Does nothing if the surface
+    /// has been removed.
+    pub fn set_surface_holdout(&mut self, id: SurfaceId, holdout: bool) {
+        if let Some(index) = self.surface_index(id) {
+            self.objects[index].holdout = holdout;
+        }
+    }
+
+    /// Tell that the surface `id` moved since the previous frame, from where
+    /// it was placed by `previous` to where it is placed by `current`, for
+    /// `render_motion_vectors`. The surface itself must already be placed by
+    /// `current`. Does nothing if the surface has been removed.
+    pub fn set_surface_motion(&mut self, id: SurfaceId, previous: Transform, current: Transform) {
+        if let Some(index) = self.surface_index(id) {
+            self.objects[index].motion = Some((previous, current));
+        }
+    }
+
+    /// Place the camera at `position`, turned by `orientation` from looking
+    /// along the y-axis with up along the z-axis. `Camera::look_at` finds the
+    /// orientation for looking at a point.
+    pub fn set_camera<T: Into<Vector3>>(&mut self, position: T, orientation: UnitQuaternion) {
+        self.camera.position = position.into();
+        self.camera.orientation = orientation;
+    }
+
+    /// Set the size of the screen that the camera projects the image onto,
+    /// `distance_to_screen` in front of it and `screen_width` wide, which
+    /// sets the field of view. The default is a screen 0.64 wide at 0.5,
+    /// for a horizontal field of view of about 65 degrees. Setting the field
+    /// of view with `set_horizontal_fov` or `set_vertical_fov` is simpler.
+    pub fn set_camera_screen(&mut self, screen_width: f64, distance_to_screen: f64) {
+        self.camera.screen_width = screen_width;
+        self.camera.distance_to_screen = distance_to_screen;
+    }
+
+    /// Set the horizontal field of view of the camera to `degrees`, by
+    /// widening or narrowing its screen. The default is about 65 degrees.
+    pub fn set_horizontal_fov(&mut self, degrees: f64) {
+        self.camera = self.camera.with_horizontal_fov(degrees);
+    }
+
+    /// Set the vertical field of view of the camera to `degrees`, in images
+    /// with width divided by height equal to `aspect_ratio`, by widening or
+    /// narrowing its screen.
+    pub fn set_vertical_fov(&mut self, degrees: f64, aspect_ratio: f64) {
+        self.camera = self.camera.with_vertical_fov(degrees, aspect_ratio);
+    }
+
+    /// Choose how the camera projects the scene onto the image. The default is
+    /// `Projection::Perspective`. The screen and field of view only apply to
+    /// perspective cameras.
+    pub fn set_camera_projection(&mut self, projection: Projection) {
+        self.camera.projection = projection;
+    }
+
+    /// Give the camera a lens with radius `aperture_radius`, focused at
+    /// `focus_distance` in front of it, so that what is out of focus is
+    /// blurred. The default is a pinhole camera, with radius 0, which has
+    /// everything in focus. Integrators that take one sample of each pixel
+    /// take the lens samples of the camera instead, 16 by default.
+    pub fn set_depth_of_field(&mut self, aperture_radius: f64, focus_distance: f64) {
+        self.camera = self
+            .camera
+            .with_depth_of_field(aperture_radius, focus_distance);
+    }
+
+    /// Shift the image `right` and `up` across the direction of the camera, as
+    /// fractions of its width, for perspective and orthographic cameras. The
+    /// default is no shift. Looking straight ahead and shifting up, rather
+    /// than turning the camera upwards, keeps the verticals of buildings
+    /// parallel.
+    pub fn set_lens_shift(&mut self, right: f64, up: f64) {
+        self.camera = self.camera.with_lens_shift(right, up);
+    }
+
+    /// Tilt the plane in focus of the lens by `degrees` around the right
+    /// direction of the camera, with the top away from the camera for
+    /// positive angles. The default is 0, for a plane across the direction of
+    /// the camera. Only cameras with a lens, as set by `set_depth_of_field`,
+    /// have a plane in focus.
+    pub fn set_lens_tilt(&mut self, degrees: f64) {
+        self.camera = self.camera.with_tilt(degrees);
+    }
+
+    /// Give the lens radial distortion, moving what is seen at the distance
+    /// r from the middle of the image, as a fraction of half its width, out
+    /// by the factor 1 + `k1` r^2 + `k2` r^4. Positive coefficients give
+    /// barrel distortion, and negative ones pincushion distortion. The
+    /// default is no distortion.
+    pub fn set_lens_distortion(&mut self, k1: f64, k2: f64) {
+        self.camera = self.camera.with_distortion(k1, k2);
+    }
+
+    /// Make the image `amount` larger in red and smaller in blue than in
+    /// green, as a fraction, for colored fringes towards the edges of the
+    /// image. Each pixel is rendered once for each channel. The default is
+    /// 0, for no chromatic aberration.
+    pub fn set_chromatic_aberration(&mut self, amount: f64) {
+        self.camera = self.camera.with_chromatic_aberration(amount);
+    }
+
+    /// Set the number of samples of the lens taken of each pixel by
+    /// integrators that take one sample of each.
+    pub fn set_lens_samples(&mut self, samples: u32) {
+        self.camera.lens_samples = samples;
+    }
+
+    /// The camera of the scene, as set by `set_camera`, `set_camera_screen`,
+    /// `set_camera_projection`, `set_depth_of_field` and the field of view
+    /// setters.
+    pub fn camera(&self) -> &Camera {
+        &self.camera
+    }
+
+    /// Set the field of view, depth of field and exposure of the camera from
+    /// the settings of a real camera, for scenes lit by lights in physical
+    /// units and measured in meters.
+    pub fn set_physical_camera(&mut self, physical: &PhysicalCamera) {
+        self.camera = self.camera.with_physical(physical);
+        self.exposure = Some(physical.exposure());
+    }
+
+    /// Set the exposure of the camera, for scenes lit by lights in physical
+    /// units. The default, `None`, leaves the colors of the scene as they are,
+    /// as for lights given by colors from 0 to 1.
+    pub fn set_exposure(&mut self, exposure: Option<Exposure>) {
+        self.exposure = exposure;
+    }
+
+    /// Tell where the camera was in the previous frame, as for `set_camera`,
+    /// for `render_motion_vectors`. By default, the camera hasn't moved.
+    pub fn set_previous_camera<T: Into<Vector3>>(
+        &mut self,
+        position: T,
+        orientation: UnitQuaternion,
+    ) {
+        self.previous_camera = Some(Camera {
+            position: position.into(),
+            orientation,
+            ..self.camera
+        });
+    }
+
+    /// Add a light source to the scene.
+    pub fn add_light(&mut self, light: impl Light + Send + Sync + 'static) -> LightId {
+        self.add_boxed_light(Box::new(light))
+    }
+
+    /// Like `add_light`, but for a light that is already boxed.
+    pub fn add_boxed_light(&mut self, mut light: Box<dyn Light + Send + Sync>) -> LightId {
+        light.convert_color(self.color_space);
+        self.push_light(light)
+    }
+
+    fn push_light(&mut self, light: Box<dyn Light + Send + Sync>) -> LightId {
+        let id = LightId(self.num_added);
+        self.num_added += 1;
+        self.lights.push(light);
+        self.light_labels.push((id, Labels::default()));
+        id
+    }
+
+    /// Find the index of the light `id` in the order the lights were added,
+    /// environments included, or `None` if it has been removed.
+    pub fn light_index(&self, id: LightId) -> Option<usize> {
+        self.light_labels.iter().position(|(other, _)| *other == id)
+    }
+
+    /// Get the light `id` to change it, or to replace it with another light,
+    /// keeping its handle. Returns `None` if it has been removed. A light put
+    /// in its place isn't converted from the color space of the scene, and the
+    /// light tree and photon maps aren't updated, so build them again after
+    /// moving lights.
+    pub fn get_mut_light(&mut self, id: LightId) -> Option<&mut Box<dyn Light + Send + Sync>> {
+        let index = self.light_index(id)?;
+        Some(&mut self.lights[index])
+    }
+
+    /// Remove the light `id` from the scene and return it, or `None` if it has
+    /// already been removed. The lights added after it move down one index.
+    /// The light tree, if built, is built again without it.
+    pub fn remove_light(&mut self, id: LightId) -> Option<Box<dyn Light + Send + Sync>> {
+        let index = self.light_index(id)?;
+        self.light_labels.remove(index);
+        let light = self.lights.remove(index);
+        self.environment = match self.environment {
+            Some(environment) if environment == index => None,
+            Some(environment) if environment > index => Some(environment - 1),
+            environment => environment,
+        };
+        if let Some(light_tree) = &self.light_tree {
+            self.light_tree = LightTree::build(&self.lights, light_tree.samples);
+        }
+        Some(light)
+    }
+
+    /// Surround the scene with `environment`, such as an `EnvironmentMap` or a
+    /// `PhysicalSky`, which replaces any environment set before. It is seen
+    /// where rays hit nothing, and lights the scene like other lights. Its
+    /// radiance is linear, whatever the color space of the scene. Returns the
+    /// handle of the environment, which stays the same when it is replaced.
+    pub fn set_environment(&mut self, environment: impl Light + Send + Sync + 'static) -> LightId {
+        match self.environment {
+            Some(index) => {
+                self.lights[index] = Box::new(environment);
+                self.light_labels[index].0
+            }
+            None => {
+                self.environment = Some(self.lights.len());
+                self.push_light(Box::new(environment))
+            }
+        }
+    }
+
+    /// Trace `num_photons` photons from each light through the glass and
+    /// mirrors in the scene, so that the caustics they focus onto other
+    /// surfaces are rendered. Call this after adding all surfaces and lights.
+    /// Only lights that implement `Light::emit`, and specular surfaces that
+    /// implement `Surface::bounding_sphere`, give caustics.
+    pub fn build_photon_map(&mut self, num_photons: usize) {
+        self.caustics = Some(PhotonMap::build(self, num_photons));
+    }
+
+    /// Pick `samples` lights at each point from a tree of the lights with a
+    /// position, instead of sampling every light, for scenes with many
+    /// lights. Lights are picked more often the more light they seem to send
+    /// to the point. Call this after adding all lights. Lights without a
+    /// position, like the sun and environments, are still sampled at every
+    /// point, and so are all lights when rendering with
+    /// `Integrator::DirectLighting`, which doesn't sample randomly.
+    pub fn build_light_tree(&mut self, samples: u32) {
+        self.light_tree = LightTree::build(&self.lights, samples);
+    }
+
+    /// Find a hash of everything in the scene that affects how it renders:
+    /// the surfaces, materials, lights, camera and settings. The hash stays the
+    /// same across runs and platforms, so data saved from a render of the
+    /// scene can be checked against it. Returns `None` if the scene contains
+    /// something that can't be hashed, such as a surface made by a plugin.
+    pub fn content_hash(&self) -> Option<u64> {
+        let mut hasher = ContentHasher::new();
+
+        hasher.write_u64(self.objects.len() as u64);
+        for object in &self.objects {
+            object.surface.hash_content(&mut hasher);
+            object.material.hash_content(&mut hasher);
+            hasher.write_u64(u64::from(object.priority as u32));
+            hasher.write_u64(u64::from(object.holdout));
+        }
+        hasher.write_u64(self.lights.len() as u64);
+        for light in &self.lights {
+            light.hash_content(&mut hasher);
+        }
+        self.hash_settings(&mut hasher);
+
+        hasher.finish()
+    }
+
+    /// Write the camera and the render settings to `hasher`, for
+    /// `content_hash`.
+    fn hash_settings(&self, hasher: &mut ContentHasher) {
+        hasher.write_vector(self.camera.position);
+        hasher.write_vector(self.camera.direction());
+        hasher.write_vector(self.camera.up());
+        hasher.write_f64(self.camera.screen_width);
+        hasher.write_f64(self.camera.distance_to_screen);
+        match self.camera.projection {
+            Projection::Perspective => {}
+            Projection::Orthographic { view_width } => {
+                hasher.write_str("Orthographic");
+                hasher.write_f64(view_width);
+            }
+            Projection::Fisheye { mapping, fov } => {
+                hasher.write_str("Fisheye");
+                hasher.write_str(&format!("{mapping:?}"));
+                hasher.write_f64(fov);
+            }
+        }
+        if self.camera.has_lens() {
+            hasher.write_str("Lens");
+            hasher.write_f64(self.camera.aperture_radius);
+            hasher.write_f64(self.camera.focus_distance);
+            hasher.write_u64(u64::from(self.camera.lens_samples));
+            if self.camera.tilt != 0.0 {
+                hasher.write_str("Tilt");
+                hasher.write_f64(self.camera.tilt);
+            }
+        }
+        if self.camera.shift != (0.0, 0.0) {
+            hasher.write_str("Shift");
+            hasher.write_f64(self.camera.shift.0);
+            hasher.write_f64(self.camera.shift.1);
+        }
+        if self.camera.distorts() {
+            hasher.write_str("Distortion");
+            hasher.write_f64(self.camera.distortion.0);
+            hasher.write_f64(self.camera.distortion.1);
+            hasher.write_f64(self.camera.chromatic_aberration);
+        }
+
+        hasher.write_serialized(&self.integrator);
+        if self.algorithm.is_some() {
+            hasher.write_unknown();
+        }
+        if let Some(exposure) = self.exposure {
+            hasher.write_f64(exposure.ev100);
+        }
+        if self.halt != HaltConditions::default() {
+            hasher.write_serialized(&self.halt);
+        }
+        if self.ray_bias != RayBias::default() {
+            hasher.write_serialized(&self.ray_bias);
+        }
+        if self.sampler.pixel_samples > 1 {
+            hasher.write_str("PixelSamples");
+            hasher.write_u64(u64::from(self.sampler.pixel_samples));
+        }
+        if self.sampler.pattern != SamplePattern::Random {
+            hasher.write_serialized(&self.sampler.pattern);
+        }
+        if self.sampler.seed != 0 {
+            hasher.write_str("Seed");
+            hasher.write_u64(self.sampler.seed);
+        }
+        if self.sampler.blue_noise {
+            hasher.write_str("BlueNoise");
+            match &self.sampler.blue_noise_mask {
+                Some(mask) => mask.hash_content(hasher),
+                None => BlueNoiseMask::default_mask().hash_content(hasher),
+            }
+        }
+        hasher.write_u64(
+            self.caustics
+                .as_ref()
+                .map_or(0, |caustics| caustics.num_photons) as u64,
+        );
+        if let Some(light_tree) = &self.light_tree {
+            hasher.write_str("LightTree");
+            hasher.write_u64(u64::from(light_tree.samples));
+        }
+    }
+
+    /// Find how much memory the scene uses.
+    pub fn memory_report(&self) -> MemoryReport {
+        MemoryReport {
+            surfaces: self
+                .objects
+                .iter()
+                .map(|object| object.surface.memory_usage())
+                .enumerate()
+                .filter(|(_, usage)| usage.total() > 0)
+                .collect(),
+            photon_map: self.caustics.as_ref().map_or(0, PhotonMap::memory_usage),
+        }
+    }
+
+    /// Tessellate the surfaces of the scene into one mesh, within `tolerance`
+    /// meters of the surfaces, as by `Surface::tessellate`. Returns the mesh and
+    /// the number of surfaces that were left out since they can't be
+    /// tessellated, such as planes.
+    pub fn tessellate(&self, tolerance: f64) -> (Mesh, usize) {
+        let meshes: Vec<Mesh> = self
+            .objects
+            .iter()
+            .filter_map(|object| object.surface.tessellate(tolerance))
+            .collect();
+        let skipped = self.objects.len() - meshes.len();
+        (Mesh::merge(&meshes), skipped)
+    }
+
+    /// Make a glTF document of the surfaces, tessellated as by `tessellate`,
+    /// their materials and the camera, for an image with `aspect_ratio` width
+    /// over height. Returns the document and the number of surfaces that were
+    /// left out since they can't be tessellated.
+    pub fn to_gltf(
+        &self,
+        tolerance: f64,
+        aspect_ratio: f64,
+    ) -> Result<(Gltf, usize), Box<dyn Error>> {
+        let mut gltf = Gltf::new();
+        let mut skipped = 0;
+        for object in &self.objects {
+            let (mesh, transform) = match object.surface.tessellate_with_transform(tolerance) {
+                Some(tessellation) => tessellation,
+                None => {
+                    skipped += 1;
+                    continue;
+                }
+            };
+            let name = self
+                .named_materials
+                .iter()
+                .find(|(_, material)| Arc::ptr_eq(material, &object.material))
+                .map(|(name, _)| name.as_str());
+            gltf.add_mesh(&mesh, &transform, &object.material, name)?;
+        }
+
+        // The glTF camera looks along its negative z-axis.
+        let camera = &self.camera;
+        let matrix = camera.matrix();
+        match camera.projection {
+            // glTF has no fisheye cameras, so they are exported as perspective
+            // cameras, as wide as those can be.
+            Projection::Perspective | Projection::Fisheye { .. } => {
+                let vertical_fov = camera.vertical_fov(aspect_ratio).min(179.0).to_radians();
+                gltf.add_camera(matrix, vertical_fov, aspect_ratio);
+            }
+            Projection::Orthographic { view_width } => {
+                gltf.add_orthographic_camera(matrix, view_width, view_width / aspect_ratio);
+            }
+        }
+
+        Ok((gltf, skipped))
+    }
+
+    /// Describe a render of the scene at `width` x `height` pixels, which took
+    /// `render_time`, for saving along with the image.
+    pub fn metadata(&self, width: usize, height: usize, render_time: Duration) -> RenderMetadata {
+        let camera = CameraMetadata {
+            matrix: self.camera.matrix(),
+            horizontal_fov: self.camera.horizontal_fov(),
+            view_width: match self.camera.projection {
+                Projection::Orthographic { view_width } => Some(view_width),
+                _ => None,
+            },
+            exposure: self.exposure.map(|exposure| exposure.ev100),
+        };
+
+        RenderMetadata {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            width,
+            height,
+            integrator: self.integrator,
+            color_space: self.color_space,
+            caustic_photons: self.caustics.as_ref().map(|caustics| caustics.num_photons),
+            camera,
+            scene_hash: self.content_hash().map(|hash| format!("{:016x}", hash)),
+            timings: Timings {
+                photon_map: self
+                    .caustics
+                    .as_ref()
+                    .map(|caustics| caustics.build_time.as_secs_f64()),
+                render: render_time.as_secs_f64(),
+            },
+            created: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_secs()),
+        }
+    }
+
+    /// Find how far, in pixels, what is seen in each pixel of an image of size
+    /// `width` x `height` has moved across the image since the previous
+    /// frame, as given by `set_previous_camera` and `set_surface_motion`. The
+    /// red channel is the motion to the right and the green channel the
+    /// motion down, from where it was seen to the middle of the pixel. The
+    /// blue channel is 0. The motion of the background only depends on how
+    /// the camera turned. Motion vectors are found for the surface seen
+    /// through the middle of the pixel, so they aren't blended along edges.
+    /// Anything that was behind the camera has no motion.
+    pub fn render_motion_vectors(&self, width: usize, height: usize) -> Image {
+        let previous_camera = self.previous_camera.as_ref().unwrap_or(&self.camera);
+        self.render_first_hits(width, height, |ray, hit, (pixel_x, pixel_y)| {
+            let previous_direction = match hit {
+                Some((point, _, object)) => {
+                    let previous_point = match &object.motion {
+                        Some((previous, current)) => {
+                            previous.point_to_world(current.point_to_object(point))
+                        }
+                        None => point,
+                    };
+                    previous_point - previous_camera.position
+                }
+                // The background is infinitely far away, so it only moves
+                // when a perspective or fisheye camera turns.
+                None => match previous_camera.projection {
+                    Projection::Orthographic { .. } => {
+                        ray.origin + ray.direction - previous_camera.position
+                    }
+                    _ => ray.direction,
+                },
+            };
+            match previous_camera.project(previous_direction, width, height) {
+                Some((x, y)) => Vector3::from((pixel_x as f64 - x, pixel_y as f64 - y, 0.0)),
+                None => Vector3::zero(),
+            }
+        })
+    }
+
+    /// Render the albedo of the surfaces seen through the middle of each
+    /// pixel, for guiding a denoiser. Glass and mirrors have a white albedo,
+    /// and the background is black.
+    pub fn render_albedo(&self, width: usize, height: usize) -> Image {
+        self.render_first_hits(width, height, |_, hit, _| match hit {
+            Some((_, _, object)) if object.material.is_specular() => Vector3::from((1.0, 1.0, 1.0)),
+            Some((point, intersection, object)) => object.material.albedo(point, intersection.uv),
+            None => Vector3::zero(),
+        })
+    }
+
+    /// Render the unit shading normals, in world coordinates, of the surfaces
+    /// seen through the middle of each pixel, for guiding a denoiser. The
+    /// background has zero normals.
+    pub fn render_normals(&self, width: usize, height: usize) -> Image {
+        self.render_first_hits(width, height, |_, hit, _| match hit {
+            Some((point, intersection, object)) => object.material.shading_normal(
+                point,
+                intersection.uv,
+                intersection.normal,
+                intersection.tangent,
+            ),
+            None => Vector3::zero(),
+        })
+    }
+
+    /// Render an image of size `width` x `height` with the color
+    /// `color(ray, hit, (x, y))` at each pixel, where `ray` goes through the
+    /// middle of pixel (x, y), and `hit` is the first surface it hits.
+    fn render_first_hits(
+        &self,
+        width: usize,
+        height: usize,
+        color: impl Fn(&Ray, Option<(Vector3, Intersection, &Object)>, (usize, usize)) -> Vector3,
+    ) -> Image {
+        let mut image = Image::new(width, height);
+        for pixel_y in 0..height {
+            for pixel_x in 0..width {
+                let ray = match self.camera.pixel_rays(width, height, pixel_x, pixel_y) {
+                    Some((ray, _)) => ray,
+                    None => continue,
+                };
+                self.start_pixel();
+                let hit = self.trace(ray.clone());
+                let color = color(&ray, hit, (pixel_x, pixel_y));
+                image.set_pixel(pixel_x, pixel_y, color);
+            }
+        }
+        image
+    }
+
+    /// Render tiles of `tile_size` x `tile_size` pixels of an image of size
+    /// `width` x `height`, taking the index of the next tile to render from
+    /// `next_tile`, which is shared by all the threads rendering the image,
+    /// until all tiles are taken. Tiles are numbered row by row from the top,
+    /// and only cover the crop window, if it is set. Since each thread takes a
+    /// new tile when it is done with one, the work is shared evenly even when
+    /// some parts of the image are much slower to render than others. Each
+    /// row of a tile is sent through `sender` when it is rendered, as runs of
+    /// pixels next to each other, each with the x-y coordinates of its first
+    /// pixel. Rendering stops early when the render is cancelled, or when
+    /// nobody is waiting for the pixels any more.
+    pub fn render_tiles(
+        &self,
+        width: usize,
+        height: usize,
+        tile_size: usize,
+        next_tile: &AtomicUsize,
+        sender: Sender<(usize, usize, Vec<Pixel>)>,
+    ) {
+        let (xs, ys) = self.crop_ranges(width, height);
+        let num_tiles = num_tiles(&xs, &ys, tile_size);
+        loop {
+            let tile = next_tile.fetch_add(1, Ordering::Relaxed);
+            if tile >= num_tiles {
+                return;
+            }
+
+            let (tile_xs, tile_ys) = tile_ranges(tile, &xs, &ys, tile_size);
+            if let Some(region) = &self.render_region {
+                let overlaps =
+                    |a: &Range<usize>, b: &Range<usize>| a.start < b.end && b.start < a.end;
+                if !region
+                    .iter()
+                    .any(|(xs, ys)| overlaps(xs, &tile_xs) && overlaps(ys, &tile_ys))
+                {
+                    continue;
+                }
+            }
+            for pixel_y in tile_ys {
+                let mut row = Vec::with_capacity(tile_xs.len());
+                for pixel_x in tile_xs.clone() {
+                    if !self.in_render_region(pixel_x, pixel_y) {
+                        continue;
+                    }
+                    if let Some(cost_map) = &self.cost_map {
+                        cost_map.start_pixel();
+                    }
+                    let pixel = self.render_pixel_at(width, height, pixel_x, pixel_y, None);
+                    // The pixel may be unfinished.
+                    if self.is_cancelled() {
+                        return;
+                    }
+                    if let Some(light_groups) = &self.light_groups {
+                        light_groups.finish_pixel(pixel_x, pixel_y);
+                    }
+                    if let Some(cost_map) = &self.cost_map {
+                        cost_map.finish_pixel(pixel_x, pixel_y);
+                    }
+                    row.push((pixel_x, pixel_y, pixel));
+                }
+                for run in pixel_runs(row) {
+                    // The render was cancelled, or dropped, after the last
+                    // pixel was finished.
+                    if sender.send(run).is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Choose how to split rendering of an image of size `width` x `height`
+    /// between threads, by rendering a few small tiles spread over the image
+    /// and timing them. Threads are only added for as long as each gets enough
+    /// work to be worth starting, and the more the time per tile varies, the
+    /// smaller the tiles, so that the slow parts of the image are shared. An
+    /// explicit number of threads in the thread settings is used as it is.
+    /// Only the crop window is timed, if it is set.
+    pub fn calibrate(&self, width: usize, height: usize) -> RenderSchedule {
+        let max_threads = self.threads.max_threads();
+        let (crop_xs, crop_ys) = self.crop_ranges(width, height);
+
+        // The time per pixel in each of the probe tiles.
+        let mut costs = Vec::with_capacity(PROBE_TILES * PROBE_TILES);
+        for probe_y in 0..PROBE_TILES {
+            for probe_x in 0..PROBE_TILES {
+                let x0 = crop_xs.start + (2 * probe_x + 1) * crop_xs.len() / (2 * PROBE_TILES);
+                let y0 = crop_ys.start + (2 * probe_y + 1) * crop_ys.len() / (2 * PROBE_TILES);
+                let xs = x0..crop_xs.end.min(x0 + PROBE_TILE_SIZE);
+                let ys = y0..crop_ys.end.min(y0 + PROBE_TILE_SIZE);
+                let num_pixels = xs.len() * ys.len();
+                if num_pixels == 0 {
+                    continue;
+                }
+
+                let start = Instant::now();
+                for pixel_y in ys {
+                    for pixel_x in xs.clone() {
+                        self.render_pixel_at(width, height, pixel_x, pixel_y, None);
+                    }
+                }
+                costs.push(start.elapsed().as_secs_f64() / num_pixels as f64);
+            }
+        }
+        if costs.is_empty() {
+            return RenderSchedule {
+                num_threads: self.threads.num_threads.map_or(1, |_| max_threads),
+                tile_size: MAX_TILE_SIZE,
+            };
+        }
+
+        let mean = costs.iter().sum::<f64>() / costs.len() as f64;
+        let variance =
+            costs.iter().map(|cost| (cost - mean).powi(2)).sum::<f64>() / costs.len() as f64;
+        // The coefficient of variation of the time per pixel.
+        let heterogeneity = if mean > 0.0 {
+            variance.sqrt() / mean
+        } else {
+            0.0
+        };
+
+        let num_pixels = (crop_xs.len() * crop_ys.len()) as f64;
+        let total_time = mean * num_pixels;
+        let num_threads = match self.threads.num_threads {
+            Some(_) => max_threads,
+            None => {
+                ((total_time / MIN_THREAD_TIME.as_secs_f64()).ceil() as usize).clamp(1, max_threads)
+            }
+        };
+
+        // A few tiles per thread balance a uniform image, and more are needed
+        // the more uneven it is.
+        let tiles_per_thread = 4.0 * (1.0 + 4.0 * heterogeneity);
+        let tile_area = num_pixels / (num_threads as f64 * tiles_per_thread);
+        let tile_size = (tile_area.sqrt().round() as usize).clamp(MIN_TILE_SIZE, MAX_TILE_SIZE);
+
+        RenderSchedule {
+            num_threads,
+            tile_size,
+        }
+    }
+
+    /// Render the pixel at (`pixel_x`, `pixel_y`) of an image of size `width`
+    /// x `height`, or a single sample of it in `pass` of a progressive render,
+    /// given as the pass and the number of the sample of the pixel in it.
+    fn render_pixel_at(
+        &self,
+        width: usize,
+        height: usize,
+        pixel_x: usize,
+        pixel_y: usize,
+        pass: Option<(u32, u32)>,
+    ) -> Pixel {
+        let aberration = self.camera.chromatic_aberration;
+        let channel = |magnification| {
+            self.render_channel_at(width, height, pixel_x, pixel_y, magnification, pass)
+        };
+        if aberration == 0.0 {
+            return channel(1.0);
+        }
+        // Each channel is rendered with the same random numbers, so that the
+        // noise doesn't color the image. Green is rendered last, so that it is
+        // the light that light groups are split from.
+        let red = channel(1.0 - aberration);
+        let blue = channel(1.0 + aberration);
+        let green = channel(1.0);
+        Pixel::from_channels(red, green, blue)
+    }
+
+    /// Render the pixel at (`pixel_x`, `pixel_y`) as seen by a color channel
+    /// whose image is `1 / magnification` times as large as the green one.
+    /// Samples of progressive renders are taken through random points of the
+    /// pixel, which smooths edges as they add up.
+    fn render_channel_at(
+        &self,
+        width: usize,
+        height: usize,
+        pixel_x: usize,
+        pixel_y: usize,
+        magnification: f64,
+        pass: Option<(u32, u32)>,
+    ) -> Pixel {
+        let mut rng = self.pixel_rng(pixel_x, pixel_y, pass);
+        let offsets = match pass {
+            Some(_) => {
+                let (x, y) = rng.next_pair();
+                vec![(x - 0.5, y - 0.5)]
+            }
+            None => self.pixel_offsets(&mut rng),
+        };
+        self.start_pixel();
+        // The light of the groups is averaged over the rays like the pixel.
+        let mut group_light: Option<Vec<Vector3>> = None;
+        let mut pixel = Pixel::default();
+        for (index, &offset) in offsets.iter().enumerate() {
+            let rays =
+                self.camera
+                    .channel_rays(width, height, pixel_x, pixel_y, magnification, offset);
+            let sample = match rays {
+                Some((ray, differentials)) => {
+                    self.render_pixel(ray, &differentials, &mut rng, pass.is_some())
+                }
+                None => {
+                    if let Some(light_groups) = &self.light_groups {
+                        light_groups.start_pixel();
+                    }
+                    Pixel::with_alpha(Vector3::zero(), 0.0)
+                }
+            };
+            let weight = 1.0 / (index + 1) as f64;
+            pixel = if index == 0 {
+                sample
+            } else {
+                pixel.blend(sample, weight)
+            };
+            if let (Some(light_groups), true) = (&self.light_groups, offsets.len() > 1) {
+                let light = light_groups.pixel_light();
+                group_light = Some(match group_light {
+                    Some(mean) => (mean.into_iter().zip(light))
+                        .map(|(mean, light)| mean + weight * (light - mean))
+                        .collect(),
+                    None => light,
+                });
+            }
+        }
+        if let (Some(light_groups), Some(light)) = (&self.light_groups, group_light) {
+            light_groups.set_pixel_light(light);
+        }
+        if self.pixel_exceeded() {
+            if let Some(light_groups) = &self.light_groups {
+                light_groups.start_pixel();
+            }
+            Vector3::from(PixelLimits::COLOR).into()
+        } else if let Some(exposure) = self.exposure {
+            if let Some(light_groups) = &self.light_groups {
+                light_groups.scale(exposure.scale());
+            }
+            pixel.scaled(exposure.scale())
+        } else {
+            pixel
+        }
+    }
+
+    /// The offsets from the middle of a pixel, in pixels, of the rays traced
+    /// through it, as set by `SamplerSettings::pixel_samples`. The points are
+    /// spread evenly over the pixel, and shifted together by a random amount,
+    /// wrapping around its edges, or spread by the sample pattern.
+    fn pixel_offsets(&self, rng: &mut Rng) -> Vec<(f64, f64)> {
+        let count = self.sampler.pixel_samples;
+        if count <= 1 {
+            return vec![(0.0, 0.0)];
+        }
+        if self.sampler.pattern != SamplePattern::Random {
+            return (0..count)
+                .map(|index| {
+                    rng.start_sample(index, count);
+                    let (x, y) = rng.next_pair();
+                    (x - 0.5, y - 0.5)
+                })
+                .collect();
+        }
+        let shift = rng.next_pair();
+        (0..count)
+            .map(|index| {
+                let (x, y) = sampling::lattice_point(index, count);
+                ((x + shift.0).fract() - 0.5, (y + shift.1).fract() - 0.5)
+            })
+            .collect()
+    }
+
+    /// Make the random number generator of the pixel at (`pixel_x`,
+    /// `pixel_y`), as chosen by the sampler settings, with other random
+    /// numbers in each `pass` of a progressive render, and for each sample of
+    /// the pixel in the pass.
+    fn pixel_rng(&self, pixel_x: usize, pixel_y: usize, pass: Option<(u32, u32)>) -> Rng {
+        let seed = |x: usize, y: usize| match pass {
+            Some((pass, 0)) => {
+                Rng::from_seeded_values(self.sampler.seed, &[x as u64, y as u64, u64::from(pass)])
+            }
+            Some((pass, sample)) => Rng::from_seeded_values(
+                self.sampler.seed,
+                &[x as u64, y as u64, u64::from(pass), u64::from(sample)],
+            ),
+            None => Rng::from_seeded_values(self.sampler.seed, &[x as u64, y as u64]),
+        };
+        let seed = |x, y| seed(x, y).with_pattern(self.sampler.pattern);
+        if !self.sampler.blue_noise {
+            return seed(pixel_x, pixel_y);
+        }
+
+        let mask = match &self.sampler.blue_noise_mask {
+            Some(mask) => Arc::clone(mask),
+            None => BlueNoiseMask::default_mask(),
+        };
+        let tile = (pixel_x / mask.size(), pixel_y / mask.size());
+        seed(tile.0, tile.1).with_blue_noise(mask, pixel_x, pixel_y)
+    }
+
+    /// Find the color of the pixel that the camera `ray` passes through.
+    /// `differentials` are the rays through the neighboring pixels. Only one
+    /// sample is taken if `single_sample` is set, for progressive renders.
+    fn render_pixel(
+        &self,
+        ray: Ray,
+        differentials: &[Ray; 2],
+        rng: &mut Rng,
+        single_sample: bool,
+    ) -> Pixel {
+        let samples = |count: u32| if single_sample { 1 } else { count };
+        if let Some(light_groups) = &self.light_groups {
+            light_groups.start_pixel();
+        }
+        if let Some(pixel) = self.composite(&ray, rng) {
+            // Light from the surfaces around shadow catchers isn't split.
+            if let Some(light_groups) = &self.light_groups {
+                light_groups.start_pixel();
+            }
+            return pixel;
+        }
+
+        // Each sample sees the scene through its own point on the lens, if
+        // the camera has one.
+        let camera = &self.camera;
+        let rgb = match (&self.algorithm, self.integrator) {
+            (Some(algorithm), _) => {
+                // Other algorithms' light can't be split by light group.
+                self.weighted_light(Vector3::zero(), || {
+                    self.average_samples(samples(algorithm.samples_per_pixel()), rng, |rng| {
+                        algorithm.radiance(self, &camera.lens_ray(&ray, rng), rng)
+                    })
+                })
+            }
+            (None, Integrator::DirectLighting | Integrator::IrradianceCaching { .. })
+                if camera.has_lens() =>
+            {
+                self.average_samples(samples(camera.lens_samples), rng, |rng| {
+                    self.trace_direct(camera.lens_ray(&ray, rng), 0, rng, Some(differentials))
+                })
+            }
+            (None, Integrator::DirectLighting | Integrator::IrradianceCaching { .. }) => {
+                stats::count(Counter::PrimaryRays);
+                self.trace_direct(ray, 0, rng, Some(differentials))
+            }
+            (
+                None,
+                Integrator::PathTracing {
+                    max_depth,
+                    samples_per_pixel,
+                },
+            ) => self.average_samples(samples(samples_per_pixel), rng, |rng| {
+                let ray = camera.lens_ray(&ray, rng);
+                self.trace_path(ray, max_depth, rng, false, Some(differentials))
+            }),
+        };
+        rgb.into()
+    }
+
+    /// Find the average of up to `samples_per_pixel` samples of a pixel,
+    /// found by `sample`, stopping early by the halt conditions.
+    fn average_samples(
+        &self,
+        samples_per_pixel: u32,
+        rng: &mut Rng,
+        mut sample: impl FnMut(&mut Rng) -> Vector3,
+    ) -> Vector3 {
+        let mut sum = Vector3::zero();
+        let mut stats = SampleStats::default();
+        while stats.samples < samples_per_pixel {
+            rng.start_sample(stats.samples, samples_per_pixel);
+            let rgb = sample(rng);
+            sum += rgb;
+            stats.add(rgb);
+            if self.halts(&stats) {
+                break;
+            }
+        }
+        let samples = stats.samples;
+        if let Some(light_groups) = &self.light_groups {
+            light_groups.scale(1.0 / f64::from(samples.max(1)));
+        }
+        sum * (1.0 / f64::from(samples.max(1)))
+    }
+
+    /// Is sampling of a pixel to stop by the halt conditions, after the
+    /// samples of `stats`, or because the render is cancelled?
+    fn halts(&self, stats: &SampleStats) -> bool {
+        self.deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+            || self.is_clean(stats)
+            || self.is_cancelled()
+    }
+
+    /// Has the render been cancelled by its `RenderHandle`?
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Is the pixel at (`x`, `y`) in the region rendered by an incremental
+    /// render, if it is one?
+    fn in_render_region(&self, x: usize, y: usize) -> bool {
+        self.render_region.as_ref().is_none_or(|region| {
+            region
+                .iter()
+                .any(|(xs, ys)| xs.contains(&x) && ys.contains(&y))
+        })
+    }
+
+    /// The columns and rows of the pixels to render of an image of size
+    /// `width` x `height`: those in the crop window, or else all of them.
+    fn crop_ranges(&self, width: usize, height: usize) -> (Range<usize>, Range<usize>) {
+        match self.crop {
+            Some(crop) => crop.ranges(width, height),
+            None => (0..width, 0..height),
+        }
+    }
+
+    /// Is the noise of a pixel with the samples of `stats` below the noise
+    /// threshold of the halt conditions?
+    fn is_clean(&self, stats: &SampleStats) -> bool {
+        match (self.halt.noise_threshold, stats.relative_error()) {
+            (Some(threshold), Some(error)) => error <= threshold,
+            _ => false,
+        }
+    }
+
+    /// Find the pixel seen along the camera `ray` in a scene with shadow
+    /// catchers or holdouts, which is rendered for compositing. Holdouts are
+    /// transparent. With shadow catchers, the background is transparent, and
+    /// so are shadow catchers, except for the shadows they receive, which are
+    /// black and as opaque as the fraction of light they block, and the mirror
+    /// reflections they receive. Returns `None` for other surfaces, which are
+    /// rendered as usual, and in scenes without shadow catchers or holdouts.
+    fn composite(&self, ray: &Ray, rng: &mut Rng) -> Option<Pixel> {
+        let catches_shadows = self
+            .objects
+            .iter()
+            .any(|object| object.material.is_shadow_catcher());
+        if !catches_shadows && !self.objects.iter().any(|object| object.holdout) {
+            return None;
+        }
+        let transparent = Pixel::with_alpha(Vector3::zero(), 0.0);
+        let (point, hit, object) = match self.trace(ray.clone()) {
+            None if catches_shadows => return Some(transparent),
+            Some((_, _, object)) if object.holdout => return Some(transparent),
+            Some(closest) if closest.2.material.is_shadow_catcher() => closest,
+            _ => return None,
+        };
+
+        let bsdf = object.material.bsdf(point, hit.uv, hit.normal, hit.tangent);
+        let outgoing = -ray.direction;
+        let (samples, max_depth) = match self.integrator {
+            Integrator::PathTracing {
+                max_depth,
+                samples_per_pixel,
+            } => (samples_per_pixel.max(1), Some(max_depth)),
+            _ => (1, None),
+        };
+
+        // The light reflected with and without shadows, and the light from
+        // other surfaces reflected in the shadow catcher.
+        let (mut lit, mut unshadowed) = (0.0, 0.0);
+        let mut reflected = Vector3::zero();
+        for sample in 0..samples {
+            rng.start_sample(sample, samples);
+            for light in self.lights.iter() {
+                let light_samples = light.samples().max(1);
+                for light_sample in 0..light_samples {
+                    let u = light_sample_point(
+                        max_depth.map(|_| &mut *rng),
+                        light_sample,
+                        light_samples,
+                    );
+                    let illumination = light.illuminate(point, u);
+                    let through_transparent = self.caustics.is_none()
+                        && (illumination.pdf.is_infinite() || !self.paths_find_caustics());
+                    let transmittance = self.transmittance(
+                        point,
+                        illumination.direction,
+                        illumination.distance,
+                        through_transparent,
+                    );
+
+                    let light = (bsdf.normal().dot(illumination.direction).max(0.0)
+                        * illumination.color)
+                        .elementwise_mul(bsdf.reflectance(outgoing, illumination.direction));
+                    unshadowed += luminance(light);
+                    lit += luminance(light.elementwise_mul(transmittance));
+                }
+            }
+
+            for (direction, weight) in bsdf.specular_directions(outgoing) {
+                let ray = Ray::new(point, direction);
+                let rgb = match max_depth {
+                    Some(max_depth) => {
+                        self.trace_path(ray, max_depth.saturating_sub(1), rng, false, None)
+                    }
+                    None => self.trace_direct(ray, 1, rng, None),
+                };
+                reflected += weight.elementwise_mul(rgb);
+            }
+        }
+        let reflected = reflected * (1.0 / f64::from(samples));
+
+        let shadow = if unshadowed > 0.0 {
+            (1.0 - lit / unshadowed).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let reflection_alpha = reflected
+            .x
+            .max(reflected.y)
+            .max(reflected.z)
+            .clamp(0.0, 1.0);
+        let alpha = 1.0 - (1.0 - shadow) * (1.0 - reflection_alpha);
+        let rgb = if alpha > 0.0 {
+            reflected * (1.0 / alpha)
+        } else {
+            Vector3::zero()
+        };
+        Some(Pixel::with_alpha(rgb, alpha))
+    }
+
+    /// Spawn multiple threads for rendering the scene, as many as the render
+    /// schedule says, which is found with `calibrate` if it isn't set. The
+    /// threads render tiles of the image, and when a pixel is finished, it is
+    /// sent through a channel, which is received from through the returned
+    /// handle. The handle also cancels the render. Keeping the system awake
+    /// and lowering the priority of the threads, if set, are done where the
+    /// system supports it.
+    pub fn spawn_render_threads(
+        mut self,
+        window_width: usize,
+        window_height: usize,
+    ) -> RenderHandle {
+        self.deadline = self.halt.max_time.map(|max_time| Instant::now() + max_time);
+        let light_ids: Vec<LightId> = self.light_labels.iter().map(|&(id, _)| id).collect();
+        if let Some(light_groups) = &mut self.light_groups {
+            light_groups.start(&light_ids, window_width, window_height);
+        }
+        if let Some(cost_map) = &self.cost_map {
+            cost_map.start(window_width, window_height);
+        }
+        let (sender, receiver) = mpsc::channel();
+        let schedule = self
+            .schedule
+            .unwrap_or_else(|| self.calibrate(window_width, window_height));
+        // Records cached by the calibration or by earlier renders may be of
+        // the scene before it changed.
+        self.irradiance_cache.clear();
+        // Held by every render thread, so that it is dropped when the last one
+        // is done.
+        let keep_awake = Arc::new(
+            self.keep_awake
+                .then(|| KeepAwake::start("Rendering an image").ok())
+                .flatten(),
+        );
+        let cancelled = self.cancelled.clone();
+        let scene_arc = Arc::new(self);
+        let next_tile = Arc::new(AtomicUsize::new(0));
+        let mut threads = Vec::new();
+        for _ in 0..schedule.num_threads.max(1) {
+            let scene_clone = scene_arc.clone();
+            let next_tile = next_tile.clone();
+            let keep_awake = keep_awake.clone();
+            let sender_clone = sender.clone();
+
+            threads.push(thread::spawn(move || {
+                let _keep_awake = keep_awake;
+                let start = Instant::now();
+                if scene_clone.low_priority {
+                    // Rendering at normal priority is fine if it can't be
+                    // lowered.
+                    let _ = power::lower_thread_priority();
+                }
+                scene_clone.render_tiles(
+                    window_width,
+                    window_height,
+                    schedule.tile_size.max(1),
+                    &next_tile,
+                    sender_clone,
+                );
+                stats::take_thread_stats(start.elapsed())
+            }));
+        }
+
+        RenderHandle::new(receiver, cancelled, threads)
+    }
+
+    /// Like `spawn_render_threads`, but with `settings` used instead of the
+    /// settings of the scene that they cover.
+    pub fn spawn_render(mut self, settings: &RenderSettings) -> RenderHandle {
+        self.integrator = settings.integrator;
+        self.sampler.seed = settings.seed;
+        self.threads = settings.threads;
+        self.crop = settings.crop;
+        if let Some(debug_view) = settings.debug_view {
+            self.set_render_algorithm(debug_view);
+        }
+        if let Some(tile_size) = settings.tile_size {
+            self.schedule = Some(RenderSchedule {
+                num_threads: settings.threads.max_threads(),
+                tile_size,
+            });
+        }
+        self.spawn_render_threads(settings.width, settings.height)
+    }
+
+    /// Like `spawn_render_threads`, but the image is rendered in passes, each
+    /// with one sample of every pixel, so that a preview refines over time
+    /// instead of showing nothing until each pixel is done. The samples are
+    /// taken through random points of the pixels, which also smooths edges,
+    /// and are meant to be averaged with `Image::accumulate`. With a noise
+    /// threshold in the halt conditions, pixels are sampled adaptively: those
+    /// whose noise is below it are left out of later passes, so that the
+    /// samples go where the noise is. Rendering stops after `passes` passes,
+    /// or when every pixel is clean, or when `HaltConditions::max_time` has
+    /// passed after the first pass, or else when the render is cancelled.
+    /// Light groups and cost maps aren't rendered.
+    pub fn spawn_progressive_render(
+        mut self,
+        window_width: usize,
+        window_height: usize,
+        passes: Option<u32>,
+    ) -> RenderHandle {
+        self.deadline = self.halt.max_time.map(|max_time| Instant::now() + max_time);
+        self.light_groups = None;
+        self.cost_map = None;
+        let (sender, receiver) = mpsc::channel();
+        let schedule = self
+            .schedule
+            .unwrap_or_else(|| self.calibrate(window_width, window_height));
+        self.irradiance_cache.clear();
+        let keep_awake = Arc::new(
+            self.keep_awake
+                .then(|| KeepAwake::start("Rendering an image").ok())
+                .flatten(),
+        );
+        let tile_size = schedule.tile_size.max(1);
+        let (xs, ys) = self.crop_ranges(window_width, window_height);
+        let num_tiles = num_tiles(&xs, &ys, tile_size);
+        let num_pixels = xs.len() * ys.len();
+        // The noise of each pixel, when sampling adaptively, and the number of
+        // pixels that are clean.
+        let adaptive = self.halt.noise_threshold.is_some() || self.sampler.adaptive_passes;
+        let noise =
+            Arc::new(adaptive.then(|| Mutex::new(PassNoise::new(window_width * window_height))));
+        let clean = Arc::new(AtomicUsize::new(0));
+        let cancelled = self.cancelled.clone();
+        let scene_arc = Arc::new(self);
+        // The tiles of all passes are numbered one pass after another.
+        let next_tile = Arc::new(AtomicUsize::new(0));
+        let mut threads = Vec::new();
+        for _ in 0..schedule.num_threads.max(1) {
+            let scene = scene_arc.clone();
+            let next_tile = next_tile.clone();
+            let keep_awake = keep_awake.clone();
+            let sender = sender.clone();
+            let noise = noise.clone();
+            let clean = clean.clone();
+            let (xs, ys) = (xs.clone(), ys.clone());
+
+            threads.push(thread::spawn(move || {
+                let _keep_awake = keep_awake;
+                let start = Instant::now();
+                if scene.low_priority {
+                    let _ = power::lower_thread_priority();
+                }
+                loop {
+                    let tile = next_tile.fetch_add(1, Ordering::Relaxed);
+                    let pass = (tile / num_tiles.max(1)) as u32;
+                    let out_of_time = scene
+                        .deadline
+                        .is_some_and(|deadline| Instant::now() >= deadline);
+                    // The first pass is always finished, for a whole image.
+                    if num_tiles == 0
+                        || passes.is_some_and(|passes| pass >= passes)
+                        || (pass > 0 && out_of_time)
+                        || clean.load(Ordering::Relaxed) == num_pixels
+                        || scene.is_cancelled()
+                    {
+                        break;
+                    }
+
+                    let (tile_xs, tile_ys) = tile_ranges(tile % num_tiles, &xs, &ys, tile_size);
+                    let pixels: Vec<_> = tile_ys
+                        .flat_map(|y| tile_xs.clone().map(move |x| (x, y)))
+                        .collect();
+                    let sent = scene.render_pass_pixels(
+                        (window_width, window_height),
+                        &pixels,
+                        pass,
+                        noise.as_ref().as_ref().map(|noise| (noise, &*clean)),
+                        &sender,
+                    );
+                    // Nobody is waiting for more samples.
+                    if !sent {
+                        break;
+                    }
+                }
+                stats::take_thread_stats(start.elapsed())
+            }));
+        }
+
+        RenderHandle::new(receiver, cancelled, threads)
+    }
+
+    /// Render a sample of each of `pixels` in `pass` of a progressive render
+    /// of an image of size `size`, and send them by `sender`. With the noise
+    /// of the pixels, and the number of them that are clean, pixels that are
+    /// clean are left out, and the noise of the others is updated. With
+    /// `SamplerSettings::adaptive_passes`, noisy pixels get several samples
+    /// and the cleanest none, by their noise. Returns false if nobody is
+    /// waiting for the samples.
+    fn render_pass_pixels(
+        &self,
+        size: (usize, usize),
+        pixels: &[(usize, usize)],
+        pass: u32,
+        noise: Option<(&Mutex<PassNoise>, &AtomicUsize)>,
+        sender: &Sender<(usize, usize, Vec<Pixel>)>,
+    ) -> bool {
+        let (width, height) = size;
+        let rays: Vec<_> = match noise {
+            Some((noise, _)) => {
+                let mut noise = noise.lock().unwrap();
+                if self.sampler.adaptive_passes {
+                    noise.start_pass(pass);
+                }
+                pixels
+                    .iter()
+                    .map(|&(x, y)| {
+                        let stats = &noise.pixels[y * width + x];
+                        let rays = if self.is_clean(stats) {
+                            0
+                        } else if self.sampler.adaptive_passes {
+                            noise.rays(stats)
+                        } else {
+                            1
+                        };
+                        (x, y, rays)
+                    })
+                    .collect()
+            }
+            None => pixels.iter().map(|&(x, y)| (x, y, 1)).collect(),
+        };
+
+        let mut samples = Vec::with_capacity(rays.len());
+        for (x, y, rays) in rays {
+            for ray in 0..rays {
+                let pixel = self.render_pixel_at(width, height, x, y, Some((pass, ray)));
+                if self.is_cancelled() {
+                    return false;
+                }
+                samples.push((x, y, pixel));
+            }
+        }
+        for run in pixel_runs(samples.iter().copied()) {
+            if sender.send(run).is_err() {
+                return false;
+            }
+        }
+
+        if let Some((noise, clean)) = noise {
+            let mut noise = noise.lock().unwrap();
+            for (x, y, pixel) in samples {
+                // Other threads may sample a pixel in later passes before
+                // it is found to be clean, so it may become noisy again.
+                let stats = &mut noise.pixels[y * width + x];
+                let was_clean = self.is_clean(stats);
+                stats.add(pixel.rgb());
+                match (was_clean, self.is_clean(stats)) {
+                    (false, true) => clean.fetch_add(1, Ordering::Relaxed),
+                    (true, false) => clean.fetch_sub(1, Ordering::Relaxed),
+                    _ => 0,
+                };
+            }
+        }
+        true
+    }
+
+    /// Find the color seen along a ray, taking only direct light into account,
+    /// except for following perfectly specular reflection and refraction, and
+    /// the irradiance cache if it is used. `depth` is the number of perfectly
+    /// specular bounces so far. `differentials` are given for camera rays, as
+    /// for `footprint`.
+    fn trace_direct(
+        &self,
+        ray: Ray,
+        depth: u32,
+        rng: &mut Rng,
+        differentials: Option<&[Ray; 2]>,
+    ) -> Vector3 {
+        let closest = self.trace(ray.clone());
+        let surface_distance = closest
+            .as_ref()
+            .map_or(INFINITY, |(_, hit, _)| hit.distance);
+        let emitted = self.emitted_light(&ray, surface_distance, None);
+
+        match closest {
+            None => emitted,
+            Some((intersection, hit, object)) => {
+                let remaining_depth = MAX_SPECULAR_DEPTH.saturating_sub(depth);
+                if let Some(rgb) = self.shade(
+                    &object.material,
+                    &ray,
+                    intersection,
+                    &hit,
+                    remaining_depth,
+                    rng,
+                ) {
+                    return rgb + emitted;
+                }
+                let bsdf = object.material.filtered_bsdf(
+                    intersection,
+                    hit.uv,
+                    self.footprint(object, hit.uv, differentials),
+                    hit.normal,
+                    hit.tangent,
+                );
+                let outgoing = -ray.direction;
+                let mut rgb = self.direct_light(intersection, &bsdf, outgoing, None);
+                if let (Some(caustics), false) = (&self.caustics, bsdf.is_specular()) {
+                    rgb += caustics.radiance(intersection, &bsdf, outgoing);
+                }
+                if let Integrator::IrradianceCaching {
+                    max_depth,
+                    samples,
+                    max_error,
+                } = self.integrator
+                {
+                    let diffuse_albedo = bsdf.diffuse_albedo();
+                    if diffuse_albedo.norm2() > 0.0 {
+                        // Cached light can't be split by light group.
+                        let irradiance = self.weighted_light(Vector3::zero(), || {
+                            self.indirect_irradiance(
+                                intersection,
+                                bsdf.normal(),
+                                max_depth,
+                                samples,
+                                max_error,
+                                rng,
+                            )
+                        });
+                        rgb += diffuse_albedo.elementwise_mul(irradiance);
+                    }
+                }
+                if depth < MAX_SPECULAR_DEPTH {
+                    for (direction, weight) in bsdf.specular_directions(outgoing) {
+                        let ray = Ray::new(intersection, direction);
+                        let reflected = self.weighted_light(weight, || {
+                            self.trace_direct(ray, depth + 1, rng, None)
+                        });
+                        rgb += weight.elementwise_mul(reflected);
+                    }
+                }
+                rgb + emitted
+            }
+        }
+    }
+
+    /// Find the color seen along a ray by following a random path of up to
+    /// `max_depth` bounces. At each bounce, direct light from the light sources
+    /// is added, and the next direction is sampled from the BSDF of the
+    /// surface. Light from lights with an extent is found both by sampling the
+    /// lights and by paths hitting them, and the two are combined using
+    /// multiple importance sampling with the balance heuristic, which keeps the
+    /// noise low both for small lights and for glossy surfaces. With a photon
+    /// map, caustics are found from it instead of by paths hitting lights.
+    ///
+    /// If `after_diffuse_bounce`, the ray leaves a diffuse surface whose
+    /// direct light and caustics are already accounted for, so light from
+    /// lights hit directly by the ray is left out. `differentials` are given
+    /// for camera rays, as for `footprint`.
+    fn trace_path(
+        &self,
+        ray: Ray,
+        max_depth: u32,
+        rng: &mut Rng,
+        after_diffuse_bounce: bool,
+        differentials: Option<&[Ray; 2]>,
+    ) -> Vector3 {
+        let closest = self.trace(ray.clone());
+        self.trace_path_from(
+            ray,
+            closest,
+            max_depth,
+            rng,
+            after_diffuse_bounce,
+            differentials,
+        )
+    }
+
+    /// Like `trace_path`, where `closest` is what `ray` hits, already traced.
+    fn trace_path_from<'a>(
+        &'a self,
+        mut ray: Ray,
+        closest: Option<(Vector3, Intersection, &'a Object)>,
+        max_depth: u32,
+        rng: &mut Rng,
+        after_diffuse_bounce: bool,
+        differentials: Option<&[Ray; 2]>,
+    ) -> Vector3 {
+        let mut first_closest = Some(closest);
+        let mut rgb = Vector3::zero();
+        // The fraction of light reflected towards the camera along the path so
+        // far.
+        let mut throughput = Vector3::ones();
+        // The probability density of the direction of the ray, if it was
+        // sampled from a BSDF.
+        let mut bsdf_pdf = None;
+        // Has the path only bounced perfectly specularly since its last
+        // non-specular bounce? Light reaching that bounce this way is a
+        // caustic.
+        let mut in_caustic = false;
+        let mut last_bounce_specular = !after_diffuse_bounce;
+
+        for depth in 0..=max_depth {
+            let closest = match first_closest.take() {
+                Some(closest) => closest,
+                None => self.trace(ray.clone()),
+            };
+            let surface_distance = closest
+                .as_ref()
+                .map_or(INFINITY, |(_, hit, _)| hit.distance);
+            if !(in_caustic && self.caustics.is_some() || after_diffuse_bounce && depth == 0) {
+                let emitted = self.weighted_light(throughput, || {
+                    self.emitted_light(&ray, surface_distance, bsdf_pdf)
+                });
+                rgb += throughput.elementwise_mul(emitted);
+            }
+
+            let (intersection, hit, object) = match closest {
+                Some(closest) if depth < max_depth => closest,
+                _ => break,
+            };
+            // A custom shader finds all the light leaving the point, and
+            // traces any further bounces itself.
+            if let Some(shaded) = self.shade(
+                &object.material,
+                &ray,
+                intersection,
+                &hit,
+                max_depth - depth,
+                rng,
+            ) {
+                rgb += throughput.elementwise_mul(shaded);
+                break;
+            }
+            let differentials = if depth == 0 { differentials } else { None };
+            let bsdf = object.material.filtered_bsdf(
+                intersection,
+                hit.uv,
+                self.footprint(object, hit.uv, differentials),
+                hit.normal,
+                hit.tangent,
+            );
+            let outgoing = -ray.direction;
+
+            let direct = self.weighted_light(throughput, || {
+                self.direct_light(intersection, &bsdf, outgoing, Some(&mut *rng))
+            });
+            rgb += throughput.elementwise_mul(direct);
+            if let (Some(caustics), false) = (&self.caustics, bsdf.is_specular()) {
+                rgb += throughput.elementwise_mul(caustics.radiance(intersection, &bsdf, outgoing));
+            }
+
+            match bsdf.sample(outgoing, rng) {
+                None => break,
+                Some(sample) => {
+                    let specular = sample.pdf.is_infinite();
+                    in_caustic = specular && (in_caustic || !last_bounce_specular);
+                    last_bounce_specular = specular;
+                    throughput = throughput.elementwise_mul(sample.weight);
+                    bsdf_pdf = Some(sample.pdf);
+                    ray = Ray::new(intersection, sample.direction);
+                }
+            }
+        }
+
+        rgb
+    }
+
+    /// Find the irradiance at `point`, with unit `normal`, from light arriving
+    /// indirectly via other surfaces. It is interpolated from the irradiance
+    /// cache if possible. Otherwise, it is found by tracing paths and added to
+    /// the cache. The other arguments are the settings of
+    /// `Integrator::IrradianceCaching`. The irradiance is given as the color
+    /// reflected by a white, diffuse surface.
+    fn indirect_irradiance(
+        &self,
+        point: Vector3,
+        normal: Vector3,
+        max_depth: u32,
+        samples: u32,
+        max_error: f64,
+        rng: &mut Rng,
+    ) -> Vector3 {
+        if let Some(irradiance) = self.irradiance_cache.interpolate(point, normal, max_error) {
+            return irradiance;
+        }
+
+        let mut irradiance = Vector3::zero();
+        let mut inverse_distances = 0.0;
+
+        // Cosine weighted sampling, where the cosine cancels against the
+        // probability density.
+        for _ in 0..samples {
+            let direction =
+                sampling::orient_along(sampling::cosine_hemisphere(rng.next_pair()), normal);
+            let ray = Ray::new(point, direction);
+            let closest = self.trace(ray.clone());
+            if let Some((_, hit, _)) = &closest {
+                inverse_distances += 1.0 / hit.distance;
+            }
+            irradiance += self.trace_path_from(ray, closest, max_depth, rng, true, None);
+        }
+        let irradiance = irradiance * (1.0 / f64::from(samples.max(1)));
+
+        let distance = if inverse_distances > 0.0 {
+            f64::from(samples) / inverse_distances
+        } else {
+            INFINITY
+        };
+        self.irradiance_cache
+            .insert(point, normal, distance, irradiance, max_error);
+
+        irradiance
+    }
+
+    /// Find the width, in texture coordinates, of the pixel seen where a camera
+    /// ray hits `object` at texture coordinates `uv`. It is found from where
+    /// `differentials`, the rays through the neighboring pixels, hit the
+    /// surface of the object. Other rays have no differentials, and give 0, as
+    /// do objects whose textures don't use the footprint.
+    fn footprint(&self, object: &Object, uv: (f64, f64), differentials: Option<&[Ray; 2]>) -> f64 {
+        match differentials {
+            Some(differentials) if object.material.uses_footprint() => differentials
+                .iter()
+                .filter_map(|ray| object.surface.closest_intersection(ray))
+                .map(|hit| (hit.uv.0 - uv.0).hypot(hit.uv.1 - uv.1))
+                .fold(0.0, f64::max),
+            _ => 0.0,
+        }
+    }
+
+    /// Find the light reflected towards `outgoing` from `intersection`, coming
+    /// directly from the light sources, each sampled as many times as it asks
+    /// for. Lights with an extent are sampled using `rng`, and weighted for
+    /// multiple importance sampling with the `bsdf`. Without `rng`, the middle
+    /// of each light is used, unweighted, or points spread evenly over it if it
+    /// takes several samples. With `rng` and a light tree, the lights in the
+    /// tree are picked from it instead of all being sampled.
+    pub fn direct_light(
+        &self,
+        intersection: Vector3,
+        bsdf: &Bsdf,
+        outgoing: Vector3,
+        mut rng: Option<&mut Rng>,
+    ) -> Vector3 {
+        let weighted = rng.is_some();
+        let light_tree = self.light_tree.as_ref().filter(|_| weighted);
+
+        // Find the light reflected for the sample of `light` at `u`, where the
+        // light is sampled `samples` times at each point, on average.
+        let reflected_light = |index: usize, u: (f64, f64), samples: f64| {
+            let light = &self.lights[index];
+            let illumination = light.illuminate(intersection, u);
+
+            // Light through transparent surfaces is a caustic. Let it through
+            // the shadow, colored but unrefracted, unless it is found from the
+            // photon map or by paths hitting the light.
+            let through_transparent = self.caustics.is_none()
+                && (illumination.pdf.is_infinite() || !self.paths_find_caustics());
+            let transmittance = self.transmittance(
+                intersection,
+                illumination.direction,
+                illumination.distance,
+                through_transparent,
+            );
+            if transmittance.norm2() == 0.0 {
+                return Vector3::zero();
+            }
+
+            // The light illuminates the intersection point.
+            let reflectance = bsdf.reflectance(outgoing, illumination.direction);
+            let reflected = (bsdf.normal().dot(illumination.direction).max(0.0)
+                * illumination.color)
+                .elementwise_mul(reflectance)
+                .elementwise_mul(transmittance)
+                * (1.0 / samples);
+
+            let reflected = if weighted && illumination.pdf.is_finite() {
+                // All the samples of the light together compete with the one
+                // sample of the BSDF.
+                let light_pdf = samples * illumination.pdf;
+                let bsdf_pdf = bsdf.pdf(outgoing, illumination.direction);
+                reflected * (light_pdf / (light_pdf + bsdf_pdf))
+            } else {
+                reflected
+            };
+            if let Some(light_groups) = &self.light_groups {
+                light_groups.add(index, reflected);
+            }
+            reflected
+        };
+
+        let mut rgb = Vector3::zero();
+        for (index, light) in self.lights.iter().enumerate() {
+            if light_tree.is_some_and(|tree| tree.contains(index)) {
+                continue;
+            }
+            let samples = light.samples().max(1);
+            for sample in 0..samples {
+                let u = light_sample_point(rng.as_deref_mut(), sample, samples);
+                rgb += reflected_light(index, u, f64::from(samples));
+            }
+        }
+
+        // Only a few of the lights in the tree are sampled, each weighted by
+        // the probability of picking it.
+        if let (Some(tree), Some(rng)) = (light_tree, rng) {
+            for _ in 0..tree.samples {
+                let (index, probability) = tree.sample(intersection, rng.next_f64());
+                let u = rng.next_pair();
+                rgb += reflected_light(index, u, f64::from(tree.samples) * probability);
+            }
+        }
+
+        rgb
+    }
+
+    /// Run `find_light`, whose light reaches the camera multiplied by
+    /// `factor`, so that the light groups get the right share of it.
+    fn weighted_light<T>(&self, factor: Vector3, find_light: impl FnOnce() -> T) -> T {
+        match &self.light_groups {
+            Some(light_groups) => light_groups.weighted(factor, find_light),
+            None => find_light(),
+        }
+    }
+
+    /// Find the fraction of light reaching `point` from `direction`, from a
+    /// light `distance` away. Opaque surfaces in between block the light,
+    /// while transparent surfaces let some of it through, if
+    /// `through_transparent`.
+    pub fn transmittance(
+        &self,
+        point: Vector3,
+        direction: Vector3,
+        distance: f64,
+        through_transparent: bool,
+    ) -> Vector3 {
+        stats::count(Counter::ShadowRays);
+        let mut transmittance = Vector3::ones();
+        let mut ray = Ray::new(point, direction);
+        let mut remaining_distance = distance;
+
+        for _ in 0..MAX_SHADOW_SURFACES {
+            let (intersection, hit, object) = match self.trace(ray) {
+                Some(closest) if closest.1.distance < remaining_distance => closest,
+                _ => return transmittance,
+            };
+            if !(through_transparent && object.material.is_transparent()) {
+                return Vector3::zero();
+            }
+
+            let bsdf = object
+                .material
+                .bsdf(intersection, hit.uv, hit.normal, hit.tangent);
+            transmittance = transmittance.elementwise_mul(bsdf.shadow_transmittance(direction));
+            remaining_distance -= hit.distance;
+            ray = Ray::new(intersection, direction);
+        }
+
+        Vector3::zero()
+    }
+
+    /// Start counting the work done for a new pixel in this thread.
+    fn start_pixel(&self) {
+        if !self.pixel_limits.is_unlimited() {
+            PIXEL_WORK.with(|work| {
+                work.set(PixelWork {
+                    rays: 0,
+                    start: Instant::now(),
+                    exceeded: false,
+                })
+            });
+        }
+    }
+
+    /// Count a ray traced for the current pixel. Returns false if the pixel
+    /// has reached a limit, in which case no more rays should be traced.
+    fn count_ray(&self) -> bool {
+        if let Some(cost_map) = &self.cost_map {
+            cost_map.count_ray();
+        }
+        let limits = &self.pixel_limits;
+        if limits.is_unlimited() {
+            return true;
+        }
+
+        PIXEL_WORK.with(|work| {
+            let mut pixel = work.get();
+            pixel.rays += 1;
+            let too_many_rays = limits
+                .max_rays
+                .is_some_and(|max_rays| pixel.rays > max_rays);
+            let too_long = pixel.rays % RAYS_PER_TIME_CHECK == 0
+                && limits
+                    .max_time
+                    .is_some_and(|max_time| pixel.start.elapsed() > max_time);
+            pixel.exceeded |= too_many_rays || too_long;
+            work.set(pixel);
+            !pixel.exceeded
+        })
+    }
+
+    /// Did the current pixel reach a limit?
+    fn pixel_exceeded(&self) -> bool {
+        !self.pixel_limits.is_unlimited() && PIXEL_WORK.with(|work| work.get().exceeded)
+    }
+
+    /// Does the integrator trace paths, which find light from lights with an
+    /// extent through transparent surfaces?
+    fn paths_find_caustics(&self) -> bool {
+        matches!(
+            self.integrator,
+            Integrator::PathTracing { .. } | Integrator::IrradianceCaching { .. }
+        )
+    }
+
+    /// Find the light emitted towards the origin of `ray` by lights that the
+    /// ray hits before reaching `surface_distance`, which is infinite if the
+    /// ray hits no surface, and lights infinitely far away, like environment
+    /// maps, are only seen then. If the ray was sampled
+    /// from a BSDF with probability density `bsdf_pdf`, the light is weighted
+    /// for multiple importance sampling with the light sampling in
+    /// `direct_light`. Perfectly specular samples, with an infinite density,
+    /// can't be found by light sampling, so all their light is kept.
+    pub fn emitted_light(
+        &self,
+        ray: &Ray,
+        surface_distance: f64,
+        bsdf_pdf: Option<f64>,
+    ) -> Vector3 {
+        let mut rgb = Vector3::zero();
+
+        for (index, light) in self.lights.iter().enumerate() {
+            if let Some(hit) = light.hit(ray) {
+                if hit.distance < surface_distance || surface_distance == INFINITY {
+                    // Weighted against all the samples of the light in
+                    // `direct_light`, which are picked from the light tree
+                    // when the ray was sampled.
+                    let weight = bsdf_pdf
+                        .filter(|pdf| pdf.is_finite())
+                        .map_or(1.0, |bsdf_pdf| {
+                            let samples = match &self.light_tree {
+                                Some(tree) if tree.contains(index) => {
+                                    f64::from(tree.samples) * tree.probability(ray.origin, index)
+                                }
+                                _ => f64::from(light.samples().max(1)),
+                            };
+                            let light_pdf = samples * hit.pdf;
+                            bsdf_pdf / (bsdf_pdf + light_pdf)
+                        });
+                    if let Some(light_groups) = &self.light_groups {
+                        light_groups.add(index, weight * hit.radiance);
+                    }
+                    rgb += weight * hit.radiance;
+                }
+            }
+        }
+
+        rgb
+    }
+
+    /// Trace a ray until it intersects a surface in the scene. If nothing is
+    /// hit, then `None` is returned. Else, a tuple is returned, where the first
+    /// element is the intersection point, the second describes the surface at
+    /// the intersection, and the third is the object that was hit.
+    fn trace(&self, ray: Ray) -> Option<(Vector3, Intersection, &Object)> {
+        // A ray gone bad through NaNs in shading must not spread them to the
+        // surfaces.
+        if !ray.is_finite() || !self.count_ray() {
+            return None;
+        }
+
+        let mut closest_intersection = INFINITY;
+        let mut result: Option<(Vector3, Intersection, &Object)> = None;
+
+        let bias = self.ray_bias.distance(ray.origin);
+        for object in self.objects.iter() {
+            let closest_intersection_of_surface = match object.surface.closest_intersection(&ray) {
+                // The ray hit the surface it is leaving from, at the point it
+                // leaves from. Look again from past the bias, so that a hit
+                // further along the same surface isn't missed.
+                Some(hit) if hit.distance <= bias && !object.surface.ignores_leaving_rays() => {
+                    let past_bias = Ray {
+                        origin: ray.origin + bias * ray.direction,
+                        direction: ray.direction,
+                    };
+                    object
+                        .surface
+                        .closest_intersection(&past_bias)
+                        .filter(|hit| hit.distance > 0.0)
+                        .map(|hit| Intersection {
+                            distance: bias + hit.distance,
+                            ..hit
+                        })
+                }
+                hit => hit,
+            };
+
+            match closest_intersection_of_surface {
+                None => continue,
+                Some(hit) => {
+                    let distance = hit.distance;
+                    // Ray intersects the surface. Surfaces that coincide within
+                    // rounding errors, which grow with the distance, are
+                    // ordered by priority.
+                    let closer = match &result {
+                        Some((_, _, closest_object))
+                            if closest_object.priority != object.priority
+                                && (distance - closest_intersection).abs()
+                                    <= COINCIDENCE_TOLERANCE * distance.max(1.0) =>
+                        {
+                            object.priority > closest_object.priority
+                        }
+                        _ => distance < closest_intersection,
+                    };
+                    if closer {
+                        closest_intersection = distance;
+                        result = Some((
+                            ray.origin + closest_intersection * ray.direction,
+                            hit,
+                            object,
+                        ));
+                    }
+                }
+            }
+        }
+        result
+    }
+}
+
+/// Find the point in the unit square that sample `index` of `count` of a light
+/// is taken at, from `rng` if given, spread along with the other samples of
+/// the light by its sample pattern if it has one. Without it, a single sample
+/// is taken in the middle of the light, at (0, 0), and more are spread evenly
+/// over it.
+fn light_sample_point(rng: Option<&mut Rng>, index: u32, count: u32) -> (f64, f64) {
+    match rng {
+        Some(rng) => rng.next_pair_in(index, count),
+        None if count == 1 => (0.0, 0.0),
+        None => sampling::lattice_point(index, count),
+    }
+}
+
+/// Gather `pixels`, each with its x-y coordinates, into runs of pixels next to
+/// each other along a row, each with the x-y coordinates of its first pixel,
+/// for sending them together.
+fn pixel_runs(
+    pixels: impl IntoIterator<Item = (usize, usize, Pixel)>,
+) -> Vec<(usize, usize, Vec<Pixel>)> {
+    let mut runs: Vec<(usize, usize, Vec<Pixel>)> = Vec::new();
+    for (x, y, pixel) in pixels {
+        match runs.last_mut() {
+            Some((run_x, run_y, run)) if *run_y == y && *run_x + run.len() == x => run.push(pixel),
+            _ => runs.push((x, y, vec![pixel])),
+        }
+    }
+    runs
+}
+
+/// The number of tiles of `tile_size` x `tile_size` pixels that cover the
+/// pixels in columns `xs` and rows `ys`.
+fn num_tiles(xs: &Range<usize>, ys: &Range<usize>, tile_size: usize) -> usize {
+    xs.len().div_ceil(tile_size) * ys.len().div_ceil(tile_size)
+}
+
+/// The columns and rows of the pixels of tile `tile` of the pixels in columns
+/// `xs` and rows `ys`, where tiles of `tile_size` x `tile_size` pixels are
+/// numbered row by row from the top left. Tiles at the right and bottom edges
+/// may be smaller.
+fn tile_ranges(
+    tile: usize,
+    xs: &Range<usize>,
+    ys: &Range<usize>,
+    tile_size: usize,
+) -> (Range<usize>, Range<usize>) {
+    let tiles_per_row = xs.len().div_ceil(tile_size);
+    let (tile_x, tile_y) = (tile % tiles_per_row, tile / tiles_per_row);
+    let (x0, y0) = (xs.start + tile_x * tile_size, ys.start + tile_y * tile_size);
+    (
+        x0..xs.end.min(x0 + tile_size),
+        y0..ys.end.min(y0 + tile_size),
+    )
+}
+
+/// Find the luminance of a color in linear RGB.
+fn luminance(rgb: Vector3) -> f64 {
+    0.2126 * rgb.x + 0.7152 * rgb.y + 0.0722 * rgb.z
 }
@@ -0,0 +1,170 @@
+//! Module containing render algorithms, for rendering scenes with algorithms
+//! other than the built-in integrators.
+//!
+//! A `RenderAlgorithm` finds the color seen along each camera ray, from the
+//! scene, which it can query with `Scene::intersect`, `Scene::direct_light`,
+//! `Scene::emitted_light` and `Scene::transmittance`. The pixels of the image
+//! are the average of its samples, taken until the halt conditions of the
+//! scene are met, and the sampler, exposure, pixel limits and compositing of
+//! the scene apply as for the built-in integrators, which are render
+//! algorithms themselves. Light found by other algorithms isn't split by light
+//! group.
+
+use super::{Integrator, Scene, SurfaceId};
+use crate::materials::Material;
+use crate::math::sampling::{self, Rng};
+use crate::math::{Ray, Vector3};
+use crate::surfaces::Intersection;
+
+/// An algorithm for finding the color of each pixel.
+pub trait RenderAlgorithm {
+    /// Find the color seen along the camera `ray` in `scene`, as one sample
+    /// of a pixel, with random numbers from `rng`.
+    fn radiance(&self, scene: &Scene, ray: &Ray, rng: &mut Rng) -> Vector3;
+
+    /// The number of samples taken of each pixel, unless the halt conditions
+    /// of the scene stop sampling early.
+    fn samples_per_pixel(&self) -> u32 {
+        1
+    }
+}
+
+impl RenderAlgorithm for Integrator {
+    fn radiance(&self, scene: &Scene, ray: &Ray, rng: &mut Rng) -> Vector3 {
+        match *self {
+            Integrator::PathTracing { max_depth, .. } => {
+                scene.trace_path(ray.clone(), max_depth, rng, false, None)
+            }
+            _ => scene.trace_direct(ray.clone(), 0, rng, None),
+        }
+    }
+
+    fn samples_per_pixel(&self) -> u32 {
+        match *self {
+            Integrator::PathTracing {
+                samples_per_pixel, ..
+            } => samples_per_pixel,
+            _ => 1,
+        }
+    }
+}
+
+/// Ambient occlusion: the fraction of the hemisphere above the surface seen
+/// by the camera that isn't blocked by other surfaces within `distance`,
+/// weighted by the cosine of the angle to the normal, shown in gray. Rays
+/// that hit nothing are white. Each sample of a pixel traces one ray from the
+/// surface.
+#[derive(Clone, Copy, Debug)]
+pub struct AmbientOcclusion {
+    pub samples: u32,
+    pub distance: f64,
+}
+
+impl RenderAlgorithm for AmbientOcclusion {
+    fn radiance(&self, scene: &Scene, ray: &Ray, rng: &mut Rng) -> Vector3 {
+        let hit = match scene.intersect(ray) {
+            Some(hit) => hit,
+            None => return Vector3::ones(),
+        };
+        let normal = hit.material.shading_normal(
+            hit.point,
+            hit.intersection.uv,
+            hit.intersection.normal,
+            hit.intersection.tangent,
+        );
+        // The side of the surface that the camera sees.
+        let normal = if normal.dot(ray.direction) > 0.0 {
+            -normal
+        } else {
+            normal
+        };
+
+        let direction =
+            sampling::orient_along(sampling::cosine_hemisphere(rng.next_pair()), normal);
+        match scene.intersect(&Ray::new(hit.point, direction)) {
+            Some(blocker) if blocker.intersection.distance < self.distance => Vector3::zero(),
+            _ => Vector3::ones(),
+        }
+    }
+
+    fn samples_per_pixel(&self) -> u32 {
+        self.samples
+    }
+}
+
+/// Views of the geometry of a scene, shown instead of lit colors, for finding
+/// out what is wrong with surfaces, normals and texture coordinates. Each
+/// sample of a pixel traces one ray, and rays that hit nothing are black,
+/// except in `Depth`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DebugView {
+    /// The unit shading normals of the surfaces seen, in world coordinates,
+    /// with each component mapped from [-1, 1] to [0, 1].
+    Normals,
+    /// The distance along the ray to the surfaces seen, in gray, from black at
+    /// the camera to white at `max_distance` and beyond. Rays that hit nothing
+    /// are white.
+    Depth { max_distance: f64 },
+    /// The texture coordinates of the surfaces seen, u in red and v in green,
+    /// wrapped around to [0, 1).
+    Uv,
+    /// A color for each surface, the same in every render of the scene.
+    SurfaceIds,
+}
+
+impl RenderAlgorithm for DebugView {
+    fn radiance(&self, scene: &Scene, ray: &Ray, _rng: &mut Rng) -> Vector3 {
+        let hit = match (scene.intersect(ray), self) {
+            (Some(hit), _) => hit,
+            (None, DebugView::Depth { .. }) => return Vector3::ones(),
+            (None, _) => return Vector3::zero(),
+        };
+        match *self {
+            DebugView::Normals => {
+                let normal = hit.material.shading_normal(
+                    hit.point,
+                    hit.intersection.uv,
+                    hit.intersection.normal,
+                    hit.intersection.tangent,
+                );
+                0.5 * (normal + Vector3::ones())
+            }
+            DebugView::Depth { max_distance } => {
+                let gray = (hit.intersection.distance / max_distance).min(1.0);
+                Vector3::from((gray, gray, gray))
+            }
+            DebugView::Uv => {
+                let (u, v) = hit.intersection.uv;
+                Vector3::from((u.rem_euclid(1.0), v.rem_euclid(1.0), 0.0))
+            }
+            DebugView::SurfaceIds => {
+                let mut rng = Rng::from_values(&[hit.surface.0]);
+                Vector3::from((rng.next_f64(), rng.next_f64(), rng.next_f64()))
+            }
+        }
+    }
+}
+
+/// Where a ray hits a surface of a scene.
+pub struct SurfaceHit<'a> {
+    /// The point hit.
+    pub point: Vector3,
+    pub intersection: Intersection,
+    /// The material of the surface hit.
+    pub material: &'a Material,
+    /// The handle of the surface hit.
+    pub surface: SurfaceId,
+}
+
+impl Scene {
+    /// Find where `ray` first hits a surface of the scene, if it does.
+    pub fn intersect(&self, ray: &Ray) -> Option<SurfaceHit<'_>> {
+        self.trace(ray.clone())
+            .map(|(point, intersection, object)| SurfaceHit {
+                point,
+                intersection,
+                material: &object.material,
+                surface: object.id,
+            })
+    }
+}
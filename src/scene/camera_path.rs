@@ -0,0 +1,230 @@
+//! Module for animating the camera along a path through keyframes.
+//!
+//! A `CameraPath` places the camera at given frames, and the camera moves
+//! between them along a Catmull-Rom spline, which passes through every
+//! keyframe without stopping at it. The camera is turned smoothly in the same
+//! way, by blending the orientations of the keyframes with the weights that
+//! blend the positions. Keyframes may be any number of frames apart, and the
+//! camera stays at the first keyframe before it, and at the last one after
+//! it. Together with an `Animation`, or any other way of rendering a sequence
+//! of frames, this makes fly-throughs.
+
+use super::{Camera, Scene};
+use crate::math::{UnitQuaternion, Vector3};
+
+/// Where the camera is in a frame of a `CameraPath`.
+#[derive(Clone, Copy)]
+pub struct CameraKeyframe {
+    pub frame: f64,
+    pub position: Vector3,
+    /// The rotation from the default orientation of the camera, as for
+    /// `Scene::set_camera`.
+    pub orientation: UnitQuaternion,
+}
+
+/// A path that the camera follows from frame to frame.
+#[derive(Clone, Default)]
+pub struct CameraPath {
+    /// The keyframes, ordered by frame, with no two in the same frame.
+    keyframes: Vec<CameraKeyframe>,
+}
+
+impl CameraPath {
+    /// Make a path without keyframes, which doesn't move the camera.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a keyframe placing the camera at `position`, turned by
+    /// `orientation`, in `frame`, in place of any keyframe already in that
+    /// frame. Frames may be fractional.
+    pub fn with_keyframe<T: Into<Vector3>>(
+        mut self,
+        frame: f64,
+        position: T,
+        orientation: UnitQuaternion,
+    ) -> Self {
+        let keyframe = CameraKeyframe {
+            frame,
+            position: position.into(),
+            orientation,
+        };
+        match self
+            .keyframes
+            .binary_search_by(|other| other.frame.total_cmp(&frame))
+        {
+            Ok(index) => self.keyframes[index] = keyframe,
+            Err(index) => self.keyframes.insert(index, keyframe),
+        }
+        self
+    }
+
+    /// Add a keyframe placing the camera at `eye`, looking at `target`, as
+    /// with `Camera::look_at`.
+    pub fn with_look_at<T: Into<Vector3>, U: Into<Vector3>, V: Into<Vector3>>(
+        self,
+        frame: f64,
+        eye: T,
+        target: U,
+        up: V,
+    ) -> Self {
+        let camera = Camera::look_at(eye, target, up);
+        self.with_keyframe(frame, camera.position, camera.orientation)
+    }
+
+    /// The keyframes of the path, ordered by frame.
+    pub fn keyframes(&self) -> &[CameraKeyframe] {
+        &self.keyframes
+    }
+
+    /// Find where the camera is in `frame`, and its orientation, or `None` if
+    /// the path has no keyframes.
+    pub fn place_at(&self, frame: f64) -> Option<(Vector3, UnitQuaternion)> {
+        let first = self.keyframes.first()?;
+        let last = self.keyframes.last()?;
+        if frame <= first.frame {
+            return Some((first.position, first.orientation));
+        }
+        if frame >= last.frame {
+            return Some((last.position, last.orientation));
+        }
+
+        // The keyframes before and after the frame, and their neighbors,
+        // which are the keyframes themselves at the ends of the path.
+        let next = self
+            .keyframes
+            .iter()
+            .position(|keyframe| keyframe.frame > frame)?;
+        let keys = [
+            &self.keyframes[next.saturating_sub(2)],
+            &self.keyframes[next - 1],
+            &self.keyframes[next],
+            &self.keyframes[(next + 1).min(self.keyframes.len() - 1)],
+        ];
+
+        // Cubic Hermite interpolation, with the tangents of a Catmull-Rom
+        // spline scaled for uneven spacing, written as weights of the keys.
+        let span = keys[2].frame - keys[1].frame;
+        let t = (frame - keys[1].frame) / span;
+        let (t2, t3) = (t * t, t * t * t);
+        let h00 = 2.0 * t3 - 3.0 * t2 + 1.0;
+        let h10 = t3 - 2.0 * t2 + t;
+        let h01 = -2.0 * t3 + 3.0 * t2;
+        let h11 = t3 - t2;
+        let a = span / (keys[2].frame - keys[0].frame);
+        let b = span / (keys[3].frame - keys[1].frame);
+        let weights = [-h10 * a, h00 - h11 * b, h01 + h10 * a, h11 * b];
+
+        let mut position = Vector3::zero();
+        for (key, &weight) in keys.iter().zip(&weights) {
+            position += weight * key.position;
+        }
+        let rotations: Vec<_> = keys
+            .iter()
+            .zip(&weights)
+            .map(|(key, &weight)| (key.orientation, weight))
+            .collect();
+        Some((position, UnitQuaternion::blend(&rotations)))
+    }
+
+    /// Find the camera in `frame`, which is `camera` moved along the path.
+    /// The camera isn't moved if the path has no keyframes.
+    pub fn camera_at(&self, camera: &Camera, frame: f64) -> Camera {
+        let mut camera = *camera;
+        if let Some((position, orientation)) = self.place_at(frame) {
+            camera.position = position;
+            camera.orientation = orientation;
+        }
+        camera
+    }
+}
+
+impl Scene {
+    /// Place the camera where `path` has it in `frame`, and tell where it was
+    /// in the frame before, for `render_motion_vectors`. The camera isn't
+    /// moved if the path has no keyframes.
+    pub fn set_camera_path_frame(&mut self, path: &CameraPath, frame: u32) {
+        let frame = f64::from(frame);
+        if let Some((position, orientation)) = path.place_at(frame - 1.0) {
+            self.set_previous_camera(position, orientation);
+        }
+        if let Some((position, orientation)) = path.place_at(frame) {
+            self.set_camera(position, orientation);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOLERANCE: f64 = 1e-9;
+
+    fn path() -> CameraPath {
+        CameraPath::new()
+            .with_look_at(10.0, (0.0, -4.0, 0.0), (0.0, 0.0, 0.0), (0.0, 0.0, 1.0))
+            .with_look_at(0.0, (-4.0, 0.0, 0.0), (0.0, 0.0, 0.0), (0.0, 0.0, 1.0))
+            .with_look_at(30.0, (4.0, 0.0, 0.0), (0.0, 0.0, 0.0), (0.0, 0.0, 1.0))
+    }
+
+    #[test]
+    fn paths_pass_through_their_keyframes() {
+        let path = path();
+        let frames: Vec<f64> = path.keyframes().iter().map(|key| key.frame).collect();
+        assert_eq!(frames, [0.0, 10.0, 30.0]);
+
+        for keyframe in path.keyframes() {
+            let camera = path.camera_at(&Camera::default(), keyframe.frame);
+            assert!((camera.position - keyframe.position).norm() < TOLERANCE);
+            assert!(camera.direction().dot(-keyframe.position.normalize()) > 1.0 - TOLERANCE);
+        }
+
+        // The camera stays at the ends outside of the path.
+        let before = path.camera_at(&Camera::default(), -5.0);
+        assert!((before.position - Vector3::from((-4.0, 0.0, 0.0))).norm() < TOLERANCE);
+        let after = path.camera_at(&Camera::default(), 100.0);
+        assert!((after.position - Vector3::from((4.0, 0.0, 0.0))).norm() < TOLERANCE);
+    }
+
+    #[test]
+    fn cameras_move_and_turn_smoothly_between_keyframes() {
+        let path = path();
+        let camera_at = |frame| path.camera_at(&Camera::default(), frame);
+
+        // Halfway between the first two keyframes, the camera is between
+        // them, bulging outwards along the curve through the third, and turned
+        // about halfway from looking along x to looking along y.
+        let camera = camera_at(5.0);
+        let midpoint = Vector3::from((-2.0, -2.0, 0.0));
+        assert!(camera.position.norm() > midpoint.norm());
+        assert!(camera.position.dot(midpoint) > 0.0);
+        assert!(camera.position.z.abs() < TOLERANCE);
+        let halfway = Vector3::from((1.0, 1.0, 0.0)).normalize();
+        assert!(camera.direction().dot(halfway) > 0.95);
+        assert!((camera.up() - Vector3::k()).norm() < TOLERANCE);
+
+        // No jumps or sudden changes of speed from frame to frame.
+        let steps: Vec<f64> = (0..30)
+            .map(|frame| {
+                let frame = f64::from(frame);
+                (camera_at(frame + 1.0).position - camera_at(frame).position).norm()
+            })
+            .collect();
+        for pair in steps.windows(2) {
+            assert!(pair[1] < 1.0);
+            assert!((pair[1] - pair[0]).abs() < 0.1);
+        }
+    }
+
+    #[test]
+    fn scenes_know_where_the_camera_was_in_the_frame_before() {
+        let mut scene = Scene::new();
+        scene.set_camera_path_frame(&path(), 5);
+        let camera = path().camera_at(&Camera::default(), 5.0);
+        assert!((scene.camera().position - camera.position).norm() < TOLERANCE);
+
+        let previous = scene.previous_camera.unwrap();
+        let expected = path().camera_at(&Camera::default(), 4.0);
+        assert!((previous.position - expected.position).norm() < TOLERANCE);
+    }
+}
@@ -0,0 +1,249 @@
+//! Module for finding what changed between two versions of a scene, and
+//! re-rendering only the parts of the image that the changes can be seen in.
+//!
+//! `Scene::diff` compares the content hashes of the surfaces, materials and
+//! lights of two scenes, matched by their handles, and of the camera and
+//! settings, so content that can't
+//! be hashed, such as surfaces made by plugins and materials with shaders,
+//! always counts as changed. `Scene::changed_region` projects the bounding
+//! spheres of the changed surfaces, where they were and where they are, to
+//! rectangles of pixels, which are all that `Scene::spawn_incremental_render`
+//! re-traces. Shadows, reflections and indirect light that the changes cast
+//! outside those rectangles aren't updated, so incremental renders are for
+//! quick feedback while editing, not for final images.
+
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use std::ops::Range;
+
+use super::{LightId, Object, Projection, RenderHandle, Scene, SurfaceId};
+use crate::hashing::ContentHasher;
+use crate::lights::Light;
+use crate::math::Vector3;
+
+/// What changed in a scene compared with an earlier version of it, as found by
+/// `Scene::diff`.
+#[derive(Clone, Debug, Default)]
+pub struct SceneChanges {
+    /// The surfaces that were added, removed, moved or reshaped, or had their
+    /// priority, holdout or motion changed.
+    pub surfaces: Vec<SurfaceId>,
+    /// The surfaces whose material changed.
+    pub materials: Vec<SurfaceId>,
+    /// The lights that were added, removed or changed.
+    pub lights: Vec<LightId>,
+    /// Whether the camera or any of the render settings changed.
+    pub settings: bool,
+    /// The bounding spheres of the changed surfaces, before and after the
+    /// changes, or `None` for surfaces that can be seen anywhere in the image.
+    bounds: Vec<Option<(Vector3, f64)>>,
+}
+
+impl SceneChanges {
+    /// Whether nothing changed.
+    pub fn is_empty(&self) -> bool {
+        self.surfaces.is_empty()
+            && self.materials.is_empty()
+            && self.lights.is_empty()
+            && !self.settings
+    }
+}
+
+/// Whether content with hash `new` differs from content with hash `old`,
+/// where `None` means that there is no such content, and `Some(None)` that it
+/// couldn't be hashed.
+fn differs(new: Option<Option<u64>>, old: Option<Option<u64>>) -> bool {
+    match (new, old) {
+        (Some(Some(new)), Some(Some(old))) => new != old,
+        _ => true,
+    }
+}
+
+/// Pair up the handles `new` of a scene with the handles `old` of an earlier
+/// version of it: each handle in `new`, with its index there and in `old`, if
+/// it is there, followed by the handles only in `old`, with their indices.
+fn match_handles<I: Copy + Eq + Hash>(
+    new: &[I],
+    old: &[I],
+) -> Vec<(I, Option<usize>, Option<usize>)> {
+    let old_indices: HashMap<I, usize> = old
+        .iter()
+        .enumerate()
+        .map(|(index, &id)| (id, index))
+        .collect();
+    let new_ids: HashSet<I> = new.iter().copied().collect();
+    let mut matched: Vec<_> = new
+        .iter()
+        .enumerate()
+        .map(|(index, &id)| (id, Some(index), old_indices.get(&id).copied()))
+        .collect();
+    matched.extend(
+        old.iter()
+            .enumerate()
+            .filter(|(_, id)| !new_ids.contains(id))
+            .map(|(index, &id)| (id, None, Some(index))),
+    );
+    matched
+}
+
+fn surface_hash(object: &Object) -> Option<u64> {
+    let mut hasher = ContentHasher::new();
+    object.surface.hash_content(&mut hasher);
+    hasher.write_u64(u64::from(object.priority as u32));
+    hasher.write_u64(u64::from(object.holdout));
+    if let Some((previous, current)) = &object.motion {
+        previous.hash_content(&mut hasher);
+        current.hash_content(&mut hasher);
+    }
+    hasher.finish()
+}
+
+fn material_hash(object: &Object) -> Option<u64> {
+    let mut hasher = ContentHasher::new();
+    object.material.hash_content(&mut hasher);
+    hasher.finish()
+}
+
+fn light_hash(light: &(dyn Light + Send + Sync)) -> Option<u64> {
+    let mut hasher = ContentHasher::new();
+    light.hash_content(&mut hasher);
+    hasher.finish()
+}
+
+/// The bounding sphere of the surface of `object`, or `None` if it has none,
+/// or moves while the shutter is open, and so can be seen outside of it.
+fn bounds(object: &Object) -> Option<(Vector3, f64)> {
+    match object.motion {
+        Some(_) => None,
+        None => object.surface.bounding_sphere(),
+    }
+}
+
+impl Scene {
+    /// Find what changed in the scene since it was `old`. Surfaces and lights
+    /// are matched by their handles, which are the same in scenes built the
+    /// same way, and aren't shifted by removing surfaces and lights.
+    pub fn diff(&self, old: &Scene) -> SceneChanges {
+        let mut changes = SceneChanges::default();
+
+        for (id, new_index, old_index) in match_handles(&self.surface_ids(), &old.surface_ids()) {
+            let new_object = new_index.map(|index| &self.objects[index]);
+            let old_object = old_index.map(|index| &old.objects[index]);
+            let moved = differs(new_object.map(surface_hash), old_object.map(surface_hash));
+            let recolored = differs(new_object.map(material_hash), old_object.map(material_hash));
+            if moved {
+                changes.surfaces.push(id);
+                changes.bounds.extend(old_object.map(bounds));
+            }
+            if recolored && new_object.is_some() && old_object.is_some() {
+                changes.materials.push(id);
+            }
+            if moved || recolored {
+                changes.bounds.extend(new_object.map(bounds));
+            }
+        }
+
+        let light_ids = |scene: &Scene| -> Vec<LightId> {
+            scene.light_labels.iter().map(|&(id, _)| id).collect()
+        };
+        for (id, new_index, old_index) in match_handles(&light_ids(self), &light_ids(old)) {
+            if differs(
+                new_index.map(|index| light_hash(self.lights[index].as_ref())),
+                old_index.map(|index| light_hash(old.lights[index].as_ref())),
+            ) {
+                changes.lights.push(id);
+            }
+        }
+
+        let settings_hash = |scene: &Scene| {
+            let mut hasher = ContentHasher::new();
+            scene.hash_settings(&mut hasher);
+            hasher.finish()
+        };
+        changes.settings = differs(Some(settings_hash(self)), Some(settings_hash(old)));
+
+        changes
+    }
+
+    /// Like `spawn_render_threads`, but only the pixels of the image that
+    /// `changes`, found with `diff` against the scene rendered before, can be
+    /// seen in are rendered and sent, for replacing the pixels of the earlier
+    /// image. They are the pixels of `changed_region`, or else all of them.
+    pub fn spawn_incremental_render(
+        mut self,
+        changes: &SceneChanges,
+        window_width: usize,
+        window_height: usize,
+    ) -> RenderHandle {
+        self.render_region = self.changed_region(changes, window_width, window_height);
+        self.spawn_render_threads(window_width, window_height)
+    }
+
+    /// The rectangles of pixels that `changes` can be seen in, in an image of
+    /// size `width` x `height`, for invalidating them before they are
+    /// rendered again, or `None` if they can be seen anywhere. That is when
+    /// lights, the camera or the settings changed, when the camera has a
+    /// lens, distorts or is a fisheye camera, or when a changed surface has
+    /// no bounds or is partly behind the camera. The rectangles may overlap.
+    pub fn changed_region(
+        &self,
+        changes: &SceneChanges,
+        width: usize,
+        height: usize,
+    ) -> Option<Vec<(Range<usize>, Range<usize>)>> {
+        // Lenses blur the changes beyond their bounds, and fisheye cameras
+        // and distortion bend the edges of the bounds, so that they can't be
+        // projected by their corners.
+        let fisheye = matches!(self.camera.projection, Projection::Fisheye { .. });
+        let camera = &self.camera;
+        if !changes.lights.is_empty()
+            || changes.settings
+            || camera.has_lens()
+            || camera.distorts()
+            || fisheye
+        {
+            return None;
+        }
+
+        let mut region = Vec::with_capacity(changes.bounds.len());
+        for &bounds in &changes.bounds {
+            let (center, radius) = bounds?;
+            if !center.norm().is_finite() || !radius.is_finite() {
+                return None;
+            }
+            // The projection of the cube around the sphere covers the
+            // projection of the sphere.
+            let (mut min_x, mut min_y) = (f64::INFINITY, f64::INFINITY);
+            let (mut max_x, mut max_y) = (f64::NEG_INFINITY, f64::NEG_INFINITY);
+            for corner in 0..8 {
+                let offset = Vector3::from((
+                    if corner & 1 == 0 { -radius } else { radius },
+                    if corner & 2 == 0 { -radius } else { radius },
+                    if corner & 4 == 0 { -radius } else { radius },
+                ));
+                let direction = center + offset - self.camera.position;
+                let (x, y) = self.camera.project(direction, width, height)?;
+                min_x = min_x.min(x);
+                min_y = min_y.min(y);
+                max_x = max_x.max(x);
+                max_y = max_y.max(y);
+            }
+            // A pixel of margin, for samples away from the pixel centers.
+            let pixels = |min: f64, max: f64, size: usize| {
+                let start = (min.floor() - 1.0).max(0.0);
+                let end = (max.ceil() + 2.0).min(size as f64);
+                if start < end {
+                    start as usize..end as usize
+                } else {
+                    0..0
+                }
+            };
+            let xs = pixels(min_x, max_x, width);
+            let ys = pixels(min_y, max_y, height);
+            if !xs.is_empty() && !ys.is_empty() {
+                region.push((xs, ys));
+            }
+        }
+        Some(region)
+    }
+}
@@ -0,0 +1,145 @@
+//! Module containing cost maps, which record how much work each pixel of a
+//! render took.
+//!
+//! The cost of a pixel is either the time spent rendering it, or the number of
+//! rays traced for it, found by the render thread rendering it while it does.
+//! Shown as a heatmap, it points out the surfaces and parts of the image that
+//! most of the time of a render goes to, such as glass seen through glass or
+//! detailed meshes.
+
+use crate::image::Image;
+use crate::math::Vector3;
+use std::cell::Cell;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// The colors of a heatmap, from the cheapest pixels to the most costly,
+/// blended between evenly.
+const HEAT_COLORS: [(f64, f64, f64); 5] = [
+    (0.0, 0.0, 0.0),
+    (0.0, 0.0, 1.0),
+    (1.0, 0.0, 0.0),
+    (1.0, 1.0, 0.0),
+    (1.0, 1.0, 1.0),
+];
+
+thread_local! {
+    /// When this thread started on the pixel it is rendering, and the number
+    /// of rays traced for it so far.
+    static PIXEL_COST: Cell<(Instant, u64)> = Cell::new((Instant::now(), 0));
+}
+
+/// The cost of each pixel, row by row from the top.
+#[derive(Default)]
+struct Costs {
+    width: usize,
+    height: usize,
+    values: Vec<f64>,
+}
+
+/// What the cost of a pixel is measured in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PixelCost {
+    /// The time spent rendering the pixel, in seconds.
+    Time,
+    /// The number of rays traced for the pixel.
+    Rays,
+}
+
+/// The cost of each pixel of a render, shared with the threads that fill it
+/// in.
+#[derive(Clone)]
+pub struct CostMap {
+    cost: PixelCost,
+    costs: Arc<Mutex<Costs>>,
+}
+
+impl CostMap {
+    pub(super) fn new(cost: PixelCost) -> Self {
+        Self {
+            cost,
+            costs: Arc::default(),
+        }
+    }
+
+    /// What the costs are measured in.
+    pub fn cost(&self) -> PixelCost {
+        self.cost
+    }
+
+    /// The costs of the pixels, as a gray image with the cost of each pixel in
+    /// every channel, in seconds or rays. Pixels that weren't rendered cost
+    /// nothing.
+    pub fn costs(&self) -> Image {
+        self.image(|cost| cost * Vector3::ones())
+    }
+
+    /// The costs of the pixels in false color, from black for pixels that
+    /// cost nothing, through blue, red and yellow, to white for the most
+    /// costly pixel.
+    pub fn heatmap(&self) -> Image {
+        let max = {
+            let costs = self.costs.lock().unwrap();
+            costs.values.iter().copied().fold(0.0, f64::max)
+        };
+        self.image(|cost| heat_color(if max > 0.0 { cost / max } else { 0.0 }))
+    }
+
+    /// Make an image with the color given by `color` for the cost of each
+    /// pixel.
+    fn image(&self, color: impl Fn(f64) -> Vector3) -> Image {
+        let costs = self.costs.lock().unwrap();
+        let mut image = Image::new(costs.width, costs.height);
+        for (index, &cost) in costs.values.iter().enumerate() {
+            image.set_pixel(index % costs.width, index / costs.width, color(cost));
+        }
+        image
+    }
+
+    /// Clear the costs, for a render of size `width` x `height`.
+    pub(super) fn start(&self, width: usize, height: usize) {
+        *self.costs.lock().unwrap() = Costs {
+            width,
+            height,
+            values: vec![0.0; width * height],
+        };
+    }
+
+    /// Start measuring the cost of a new pixel, in this thread.
+    pub(super) fn start_pixel(&self) {
+        PIXEL_COST.with(|cost| cost.set((Instant::now(), 0)));
+    }
+
+    /// Count a ray traced for the pixel.
+    pub(super) fn count_ray(&self) {
+        PIXEL_COST.with(|cost| {
+            let (start, rays) = cost.get();
+            cost.set((start, rays + 1));
+        });
+    }
+
+    /// Write the cost of the pixel to pixel (`x`, `y`) of the costs.
+    pub(super) fn finish_pixel(&self, x: usize, y: usize) {
+        let (start, rays) = PIXEL_COST.with(Cell::get);
+        let cost = match self.cost {
+            PixelCost::Time => start.elapsed().as_secs_f64(),
+            PixelCost::Rays => rays as f64,
+        };
+        let mut costs = self.costs.lock().unwrap();
+        let width = costs.width;
+        costs.values[y * width + x] = cost;
+    }
+}
+
+/// The color of `heat`, from 0 for the cheapest pixels to 1 for the most
+/// costly.
+fn heat_color(heat: f64) -> Vector3 {
+    let position = heat.clamp(0.0, 1.0) * (HEAT_COLORS.len() - 1) as f64;
+    let index = (position as usize).min(HEAT_COLORS.len() - 2);
+    let fraction = position - index as f64;
+    let (from, to) = (
+        Vector3::from(HEAT_COLORS[index]),
+        Vector3::from(HEAT_COLORS[index + 1]),
+    );
+    from + fraction * (to - from)
+}
@@ -0,0 +1,258 @@
+//! Module for describing scenes built in code as in scene files, so that they
+//! can be saved with `SceneDescription::save` and loaded again.
+//!
+//! Only what scene files can describe is described: the built-in surfaces,
+//! lights and textures, the camera and the render settings. Surfaces, lights
+//! and textures made by plugins, surfaces placed by nodes of the scene graph,
+//! environments, custom shaders and custom render algorithms can't be
+//! described, and neither can the camera settings that scene files leave out,
+//! such as fisheye projections and lens distortion.
+
+use super::{Camera, Integrator, Projection, Scene};
+use crate::image::ColorSpace;
+use crate::scene_file::{
+    BayerPatternDescription, CameraDescription, ColorDescription, ColorSpaceDescription,
+    IntegratorDescription, LensFlareDescription, LightDescription, MaterialDescription,
+    SamplerDescription, SceneDescription, SensorDescription, SurfaceDescription,
+};
+use crate::sensor::BayerPattern;
+use std::error::Error;
+use std::sync::Arc;
+
+impl Scene {
+    /// Describe the scene as in scene files, for saving scenes built in code
+    /// with `SceneDescription::save`. Colors are described in the color space
+    /// set by `set_color_space`, which should be the one they were given in.
+    /// Returns an error if the scene contains something that scene files
+    /// can't describe, such as a surface made by a plugin.
+    pub fn to_description(&self) -> Result<SceneDescription, Box<dyn Error>> {
+        if self.algorithm.is_some() {
+            return Err("Custom render algorithms can't be described in a scene file".into());
+        }
+        if self.sampler.blue_noise_mask.is_some() {
+            return Err("Blue-noise masks can't be described in a scene file".into());
+        }
+
+        let mut description = SceneDescription {
+            color_space: match self.color_space {
+                ColorSpace::Linear => ColorSpaceDescription::Linear,
+                ColorSpace::Srgb => ColorSpaceDescription::Srgb,
+            },
+            integrator: match self.integrator {
+                Integrator::DirectLighting => IntegratorDescription::DirectLighting,
+                Integrator::PathTracing {
+                    max_depth,
+                    samples_per_pixel,
+                } => IntegratorDescription::PathTracing {
+                    max_depth,
+                    samples_per_pixel,
+                },
+                Integrator::IrradianceCaching {
+                    max_depth,
+                    samples,
+                    max_error,
+                } => IntegratorDescription::IrradianceCaching {
+                    max_depth,
+                    samples,
+                    max_error,
+                },
+            },
+            sampler: SamplerDescription {
+                pattern: self.sampler.pattern,
+                pixel_samples: self.sampler.pixel_samples,
+                blue_noise: self.sampler.blue_noise,
+                blue_noise_mask: None,
+                seed: self.sampler.seed,
+                adaptive_passes: self.sampler.adaptive_passes,
+            },
+            exposure: self.exposure.map(|exposure| exposure.ev100),
+            camera: Some(describe_camera(&self.camera)?),
+            caustic_photons: self
+                .caustics
+                .as_ref()
+                .map_or(0, |caustics| caustics.num_photons),
+            light_tree_samples: self
+                .light_tree
+                .as_ref()
+                .map_or(0, |light_tree| light_tree.samples),
+            ray_bias: Some(self.ray_bias.absolute),
+            relative_ray_bias: Some(self.ray_bias.relative),
+            max_rays_per_pixel: self.pixel_limits.max_rays,
+            max_seconds_per_pixel: self.pixel_limits.max_time.map(|time| time.as_secs_f64()),
+            noise_threshold: self.halt.noise_threshold,
+            max_render_seconds: self.halt.max_time.map(|time| time.as_secs_f64()),
+            keep_awake: self.keep_awake,
+            low_priority: self.low_priority,
+            denoise: self.denoise,
+            sensor: self.sensor.as_ref().map(|sensor| SensorDescription {
+                pattern: match sensor.pattern {
+                    BayerPattern::Rggb => BayerPatternDescription::Rggb,
+                    BayerPattern::Bggr => BayerPatternDescription::Bggr,
+                    BayerPattern::Grbg => BayerPatternDescription::Grbg,
+                    BayerPattern::Gbrg => BayerPatternDescription::Gbrg,
+                },
+                full_well: Some(sensor.full_well),
+                read_noise: Some(sensor.read_noise),
+                bit_depth: Some(sensor.bit_depth),
+                black_level: Some(sensor.black_level),
+                seed: sensor.seed,
+            }),
+            lens_flare: self.lens_flare.as_ref().map(|flare| LensFlareDescription {
+                threshold: Some(flare.threshold),
+                intensity: Some(flare.intensity),
+                streaks: Some(flare.streaks),
+                streak_length: Some(flare.streak_length),
+                ghosts: Some(flare.ghosts),
+            }),
+            ..SceneDescription::default()
+        };
+
+        for (name, material) in &self.named_materials {
+            let material = material
+                .describe()
+                .ok_or_else(|| format!("Material {} can't be described in a scene file", name))?;
+            description.materials.insert(name.clone(), material);
+        }
+
+        for (index, object) in self.objects.iter().enumerate() {
+            let shape = object
+                .surface
+                .describe()
+                .ok_or_else(|| format!("Surface {} can't be described in a scene file", index))?;
+            let material_name = self
+                .named_materials
+                .iter()
+                .find(|(_, material)| Arc::ptr_eq(material, &object.material))
+                .map(|(name, _)| name.clone());
+            let material = match material_name {
+                Some(_) => MaterialDescription::default(),
+                None => object.material.describe().ok_or_else(|| {
+                    format!(
+                        "The material of surface {} can't be described in a scene file",
+                        index
+                    )
+                })?,
+            };
+            description.surfaces.push(SurfaceDescription {
+                shape,
+                material,
+                material_name,
+                priority: object.priority,
+                holdout: object.holdout,
+                name: object.labels.name().map(str::to_string),
+                tags: object.labels.tags().to_vec(),
+            });
+        }
+
+        for (index, light) in self.lights.iter().enumerate() {
+            let light = light
+                .describe()
+                .ok_or_else(|| format!("Light {} can't be described in a scene file", index))?;
+            description
+                .lights
+                .push(light_in_color_space(light, self.color_space));
+        }
+        for (index, (_, labels)) in self.light_labels.iter().enumerate() {
+            if let Some(name) = labels.name() {
+                description.light_names.insert(name.to_string(), index);
+            }
+        }
+        description.light_groups = self.light_groups.as_ref().map(|light_groups| {
+            light_groups
+                .named()
+                .iter()
+                .map(|(name, ids)| {
+                    let indices = ids.iter().filter_map(|&id| self.light_index(id)).collect();
+                    (name.clone(), indices)
+                })
+                .collect()
+        });
+
+        Ok(description)
+    }
+}
+
+/// Convert the colors of `light`, which lights keep in linear RGB once they
+/// are added, back to `color_space`, where scene files give them.
+fn light_in_color_space(light: LightDescription, color_space: ColorSpace) -> LightDescription {
+    let convert = |color: ColorDescription| color_space.from_linear(color.into()).into();
+    match light {
+        LightDescription::Sun {
+            color,
+            direction,
+            illuminance,
+            angular_radius,
+            samples,
+        } => LightDescription::Sun {
+            color: convert(color),
+            direction,
+            illuminance,
+            angular_radius,
+            samples,
+        },
+        LightDescription::SphereLight {
+            center,
+            radius,
+            radiance,
+            brightness,
+        } => LightDescription::SphereLight {
+            center,
+            radius,
+            radiance: convert(radiance),
+            brightness,
+        },
+        LightDescription::PointLight {
+            position,
+            color,
+            intensity,
+            brightness,
+        } => LightDescription::PointLight {
+            position,
+            color: convert(color),
+            intensity,
+            brightness,
+        },
+        LightDescription::AreaLight {
+            width,
+            height,
+            radiance,
+            transform,
+            samples,
+            brightness,
+        } => LightDescription::AreaLight {
+            width,
+            height,
+            radiance: convert(radiance),
+            transform,
+            samples,
+            brightness,
+        },
+        plugin @ LightDescription::Plugin { .. } => plugin,
+    }
+}
+
+/// Describe `camera` as in scene files, looking one unit ahead, or return an
+/// error if it has settings that scene files can't describe.
+fn describe_camera(camera: &Camera) -> Result<CameraDescription, Box<dyn Error>> {
+    if matches!(camera.projection, Projection::Fisheye { .. })
+        || camera.shift != (0.0, 0.0)
+        || camera.tilt != 0.0
+        || camera.distorts()
+        || camera.lens_samples != Camera::default().lens_samples
+    {
+        return Err("The camera has settings that can't be described in a scene file".into());
+    }
+
+    Ok(CameraDescription {
+        eye: camera.position.into(),
+        target: (camera.position + camera.direction()).into(),
+        up: camera.up().into(),
+        horizontal_fov: Some(camera.horizontal_fov()),
+        view_width: match camera.projection {
+            Projection::Orthographic { view_width } => Some(view_width),
+            _ => None,
+        },
+        aperture_radius: camera.aperture_radius,
+        focus_distance: camera.has_lens().then_some(camera.focus_distance),
+    })
+}
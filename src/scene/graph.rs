@@ -0,0 +1,230 @@
+//! Module for the scene graph, a hierarchy of nodes that place surfaces in
+//! the scene, for setups where parts move together, like a lamp standing on a
+//! table, or the joints of an arm.
+//!
+//! Each node has a transform relative to its parent, or to the scene for
+//! nodes without a parent, and the surfaces added to a node are defined in the
+//! space of the node. The transforms of a surface are composed from the node
+//! up to the root when the surface is added and whenever the transform of a
+//! node is set, so the surfaces are always in place for rendering. Only the
+//! surfaces below the nodes that moved are placed again, and meshes keep their
+//! BVHs, which are built in the space of the mesh, so moving parts of a scene
+//! for each frame of an animation is cheap. Since a
+//! `Transform` can't express every composition of scaling and rotation, the
+//! transforms are kept as a chain, each applied after the one below it.
+//! Lights don't belong to nodes, but `node_point_to_world` finds where to put
+//! them.
+
+use super::{Scene, SurfaceId};
+use crate::materials::Material;
+use crate::math::Vector3;
+use crate::surfaces::{Surface, Transform, Transformed};
+use std::sync::Arc;
+
+/// A handle to a node of the scene graph, returned by `Scene::add_node`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct NodeId(usize);
+
+/// A node of the scene graph.
+pub(super) struct Node {
+    parent: Option<NodeId>,
+    /// The transform from the space of the node to that of its parent.
+    transform: Transform,
+}
+
+impl Scene {
+    /// Add a node to the scene graph, placed by `transform` relative to
+    /// `parent`, or to the scene if `parent` is `None`.
+    pub fn add_node(&mut self, parent: Option<NodeId>, transform: Transform) -> NodeId {
+        if let Some(NodeId(parent)) = parent {
+            assert!(parent < self.nodes.len(), "Unknown parent node");
+        }
+        self.nodes.push(Node { parent, transform });
+        NodeId(self.nodes.len() - 1)
+    }
+
+    /// Set the transform of `node` relative to its parent, which moves the
+    /// surfaces of the node and of all the nodes below it.
+    pub fn set_node_transform(&mut self, node: NodeId, transform: Transform) {
+        self.update_transforms(&[(node, transform)]);
+    }
+
+    /// Set the transforms of several nodes relative to their parents at once,
+    /// such as for a frame of an animation, which moves the surfaces of the
+    /// nodes and of all the nodes below them. The surfaces are placed again
+    /// once for all the nodes.
+    pub fn update_transforms(&mut self, transforms: &[(NodeId, Transform)]) {
+        let mut moved = vec![false; self.nodes.len()];
+        for &(NodeId(index), transform) in transforms {
+            self.nodes[index].transform = transform;
+            moved[index] = true;
+        }
+        // Parents are added before their children.
+        for index in 0..self.nodes.len() {
+            if let Some(NodeId(parent)) = self.nodes[index].parent {
+                moved[index] |= moved[parent];
+            }
+        }
+        self.place_node_surfaces(&moved);
+        self.irradiance_cache.clear();
+    }
+
+    /// The transform of `node` relative to its parent.
+    pub fn node_transform(&self, node: NodeId) -> Transform {
+        self.nodes[node.0].transform
+    }
+
+    /// Add a surface made of `material`, defined in the space of `node`, which
+    /// places it in the scene.
+    pub fn add_surface_to_node(
+        &mut self,
+        node: NodeId,
+        surface: impl Surface + Send + Sync + 'static,
+        material: Material,
+    ) -> SurfaceId {
+        let surface: Arc<dyn Surface + Send + Sync> = Arc::new(surface);
+        let material = Arc::new(material.convert_albedo(self.color_space));
+        let id = self.push_object(self.place(node, &surface), material);
+        if let Some(object) = self.objects.last_mut() {
+            object.node = Some((node, surface));
+        }
+        id
+    }
+
+    /// Transform `point` from the space of `node` to the scene, such as for
+    /// placing a light where a node has a lamp.
+    pub fn node_point_to_world(&self, node: NodeId, point: Vector3) -> Vector3 {
+        self.world_transforms(node)
+            .iter()
+            .fold(point, |point, transform| transform.point_to_world(point))
+    }
+
+    /// The transforms that place the surfaces of `node` in the scene, from
+    /// that of the node to that of the root above it.
+    fn world_transforms(&self, node: NodeId) -> Vec<Transform> {
+        let mut transforms = Vec::new();
+        let mut node = Some(node);
+        while let Some(NodeId(index)) = node {
+            transforms.push(self.nodes[index].transform);
+            node = self.nodes[index].parent;
+        }
+        transforms
+    }
+
+    /// Place `surface` in the scene by the transforms of `node`.
+    fn place(
+        &self,
+        node: NodeId,
+        surface: &Arc<dyn Surface + Send + Sync>,
+    ) -> Box<dyn Surface + Send + Sync> {
+        self.world_transforms(node)
+            .into_iter()
+            .fold(Box::new(Arc::clone(surface)), |placed, transform| {
+                Box::new(Transformed::new(placed, transform))
+            })
+    }
+
+    /// Place the surfaces of the nodes that `moved` holds for, by index, where
+    /// their nodes are now.
+    fn place_node_surfaces(&mut self, moved: &[bool]) {
+        let placed: Vec<_> = self
+            .objects
+            .iter()
+            .map(|object| {
+                let (node, surface) = object.node.as_ref().filter(|(node, _)| moved[node.0])?;
+                Some(self.place(*node, surface))
+            })
+            .collect();
+        for (object, placed) in self.objects.iter_mut().zip(placed) {
+            if let Some(placed) = placed {
+                object.surface = placed;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::{Ray, UnitQuaternion};
+    use crate::surfaces::Sphere;
+    use std::f64::consts::PI;
+
+    const TOLERANCE: f64 = 1e-9;
+
+    fn center(scene: &Scene, id: SurfaceId) -> Vector3 {
+        let index = scene.surface_index(id).unwrap();
+        scene.objects[index].surface.bounding_sphere().unwrap().0
+    }
+
+    #[test]
+    fn children_move_with_their_parents() {
+        let mut scene = Scene::new();
+        let table = scene.add_node(
+            None,
+            Transform::new(Vector3::ones(), UnitQuaternion::id(), (2.0, 0.0, 0.0)),
+        );
+        let lamp = scene.add_node(
+            Some(table),
+            Transform::new((0.5, 0.5, 0.5), UnitQuaternion::id(), (1.0, 0.0, 1.0)),
+        );
+        let top = scene.add_surface_to_node(
+            table,
+            Sphere::new((0.0, 0.0, 0.0), 1.0),
+            Material::default(),
+        );
+        let bulb =
+            scene.add_surface_to_node(lamp, Sphere::new((0.0, 0.0, 1.0), 0.2), Material::default());
+        assert!((center(&scene, top) - Vector3::from((2.0, 0.0, 0.0))).norm() < TOLERANCE);
+        assert!((center(&scene, bulb) - Vector3::from((3.0, 0.0, 1.5))).norm() < TOLERANCE);
+
+        // Turning the table a quarter turn around z carries the lamp along.
+        let turned = UnitQuaternion::from_axis_angle((0.0, 0.0, 1.0), PI / 2.0);
+        scene.set_node_transform(
+            table,
+            Transform::new(Vector3::ones(), turned, (2.0, 0.0, 0.0)),
+        );
+        let expected = Vector3::from((2.0, 1.0, 1.5));
+        assert!((center(&scene, bulb) - expected).norm() < TOLERANCE);
+        let light_position = scene.node_point_to_world(lamp, Vector3::from((0.0, 0.0, 1.0)));
+        assert!((light_position - expected).norm() < TOLERANCE);
+
+        // The bulb is hit where it now is, with its scaled radius.
+        let ray = Ray::new(Vector3::from((2.0, 1.0, 5.0)), -Vector3::k());
+        let index = scene.surface_index(bulb).unwrap();
+        let hit = scene.objects[index]
+            .surface
+            .closest_intersection(&ray)
+            .unwrap();
+        assert!((hit.distance - 3.4).abs() < TOLERANCE);
+    }
+
+    #[test]
+    fn transforms_are_updated_together() {
+        let mut scene = Scene::new();
+        let arm = scene.add_node(None, Transform::identity());
+        let hand = scene.add_node(Some(arm), Transform::identity());
+        let other = scene.add_node(None, Transform::identity());
+        let finger =
+            scene.add_surface_to_node(hand, Sphere::new((1.0, 0.0, 0.0), 0.1), Material::default());
+        let ball = scene.add_surface_to_node(
+            other,
+            Sphere::new((0.0, 0.0, 0.0), 1.0),
+            Material::default(),
+        );
+        let ball_index = scene.surface_index(ball).unwrap();
+        let ball_surface = |scene: &Scene| {
+            let surface: *const _ = scene.objects[ball_index].surface.as_ref();
+            surface.cast::<()>()
+        };
+        let unmoved = ball_surface(&scene);
+
+        let moved = |x: f64| Transform::new(Vector3::ones(), UnitQuaternion::id(), (x, 0.0, 0.0));
+        scene.update_transforms(&[(arm, moved(2.0)), (hand, moved(3.0))]);
+        let expected = Vector3::from((6.0, 0.0, 0.0));
+        assert!((center(&scene, finger) - expected).norm() < TOLERANCE);
+        assert!(center(&scene, ball).norm() < TOLERANCE);
+        // The surfaces of nodes that didn't move aren't placed again.
+        assert!(ball_surface(&scene) == unmoved);
+    }
+}
@@ -0,0 +1,206 @@
+//! Module containing the irradiance cache, which speeds up rendering of diffuse
+//! interreflection.
+//!
+//! Indirect light on diffuse surfaces changes slowly across the surfaces, so
+//! it is only computed, by path tracing many directions, at sparse points.
+//! These records are cached and interpolated for the points in between. Each
+//! record is valid within a distance proportional to the harmonic mean
+//! distance to the surfaces around it, so records are dense in corners and
+//! sparse in open areas, following Ward et al., "A Ray Tracing Solution for
+//! Diffuse Interreflection" (1988).
+//!
+//! The cache is shared by the render threads, which add records as they need
+//! them.
+
+use crate::math::Vector3;
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+/// The smallest and largest harmonic mean distance used for the records, in
+/// meters. Without limits, records are very dense in corners, and valid
+/// everywhere in open scenes.
+const MIN_RECORD_DISTANCE: f64 = 0.05;
+const MAX_RECORD_DISTANCE: f64 = 10.0;
+
+/// The irradiance at a point, computed by sampling the hemisphere around it.
+struct Record {
+    position: Vector3,
+    normal: Vector3,
+    /// The harmonic mean distance to the surfaces seen from the point.
+    distance: f64,
+    irradiance: Vector3,
+}
+
+/// A cache of irradiance records. To find the records near a point quickly,
+/// they are sorted into grids of cubical cells, one grid for each power of two
+/// cell size. Each record is put in the grid with the smallest cells that are
+/// at least as large as the distance it is valid within, in every cell the
+/// record is valid in, which are at most eight.
+#[derive(Default)]
+pub(super) struct IrradianceCache {
+    grids: RwLock<Grids>,
+}
+
+#[derive(Default)]
+struct Grids {
+    records: Vec<Record>,
+    /// Indices into `records`, by grid level (the base 2 logarithm of the
+    /// cell size) and cell coordinates.
+    cells: HashMap<(i32, [i64; 3]), Vec<usize>>,
+    /// The levels that have records.
+    levels: Option<(i32, i32)>,
+}
+
+impl IrradianceCache {
+    /// Remove all the records, which are out of date when the scene changes.
+    pub(super) fn clear(&self) {
+        *self.grids.write().unwrap() = Grids::default();
+    }
+
+    /// Interpolate the irradiance at `point`, where the unit normal of the
+    /// surface is `normal`, from the records that are valid there. `max_error`
+    /// determines how far records are valid. Returns `None` if there are no
+    /// valid records.
+    pub(super) fn interpolate(
+        &self,
+        point: Vector3,
+        normal: Vector3,
+        max_error: f64,
+    ) -> Option<Vector3> {
+        let grids = self.grids.read().unwrap();
+        let (min_level, max_level) = grids.levels?;
+
+        let mut irradiance = Vector3::zero();
+        let mut total_weight = 0.0;
+        for level in min_level..=max_level {
+            let indices = match grids.cells.get(&(level, cell(point, level))) {
+                Some(indices) => indices,
+                None => continue,
+            };
+
+            for &index in indices {
+                let record = &grids.records[index];
+                let offset = point - record.position;
+
+                // Skip records in front of the point, which see other surfaces.
+                if offset.dot(normal + record.normal) < -0.01 * record.distance {
+                    continue;
+                }
+
+                // The error estimate of Ward et al. Subtracting its limit from
+                // the weight makes the interpolation continuous at the edge of
+                // where records are valid.
+                let error = offset.norm() / record.distance
+                    + (1.0 - normal.dot(record.normal)).max(0.0).sqrt();
+                let weight = 1.0 / error.max(1e-9) - 1.0 / max_error;
+                if weight > 0.0 {
+                    irradiance += weight * record.irradiance;
+                    total_weight += weight;
+                }
+            }
+        }
+
+        if total_weight > 0.0 {
+            Some(irradiance * (1.0 / total_weight))
+        } else {
+            None
+        }
+    }
+
+    /// Add a record of the `irradiance` at `point`, with unit `normal`, where
+    /// the harmonic mean distance to the surrounding surfaces is `distance`.
+    /// `max_error` must be the same as when interpolating.
+    pub(super) fn insert(
+        &self,
+        point: Vector3,
+        normal: Vector3,
+        distance: f64,
+        irradiance: Vector3,
+        max_error: f64,
+    ) {
+        let distance = distance.clamp(MIN_RECORD_DISTANCE, MAX_RECORD_DISTANCE);
+        // Beyond this distance, the error estimate exceeds the maximum error.
+        let valid_distance = max_error * distance;
+        let level = valid_distance.log2().ceil() as i32;
+
+        let mut grids = self.grids.write().unwrap();
+        let index = grids.records.len();
+        grids.records.push(Record {
+            position: point,
+            normal,
+            distance,
+            irradiance,
+        });
+
+        // The cells are at least as large as the distance the record is valid
+        // within, so it overlaps at most two cells along each axis.
+        let lower = cell(point - valid_distance * Vector3::ones(), level);
+        let upper = cell(point + valid_distance * Vector3::ones(), level);
+        for x in lower[0]..=upper[0] {
+            for y in lower[1]..=upper[1] {
+                for z in lower[2]..=upper[2] {
+                    grids
+                        .cells
+                        .entry((level, [x, y, z]))
+                        .or_default()
+                        .push(index);
+                }
+            }
+        }
+
+        grids.levels = Some(match grids.levels {
+            None => (level, level),
+            Some((min_level, max_level)) => (min_level.min(level), max_level.max(level)),
+        });
+    }
+}
+
+/// Find the coordinates of the cell containing `point`, in the grid at
+/// `level`.
+fn cell(point: Vector3, level: i32) -> [i64; 3] {
+    let size = 2f64.powi(level);
+    [
+        (point.x / size).floor() as i64,
+        (point.y / size).floor() as i64,
+        (point.z / size).floor() as i64,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_are_only_used_near_where_they_are_valid() {
+        let cache = IrradianceCache::default();
+        assert!(cache
+            .interpolate(Vector3::zero(), Vector3::k(), 0.2)
+            .is_none());
+
+        cache.insert(Vector3::zero(), Vector3::k(), 1.0, Vector3::ones(), 0.2);
+        cache.insert(
+            Vector3::from((0.1, 0.0, 0.0)),
+            Vector3::k(),
+            1.0,
+            Vector3::zero(),
+            0.2,
+        );
+
+        // Halfway between the records, they are weighted equally.
+        let halfway = cache.interpolate(Vector3::from((0.05, 0.0, 0.0)), Vector3::k(), 0.2);
+        assert!((halfway.unwrap() - 0.5 * Vector3::ones()).norm() < 1e-9);
+
+        // Too far away, or on a surface facing another way.
+        assert!(cache
+            .interpolate(Vector3::from((-0.3, 0.0, 0.0)), Vector3::k(), 0.2)
+            .is_none());
+        assert!(cache
+            .interpolate(Vector3::zero(), Vector3::i(), 0.2)
+            .is_none());
+
+        cache.clear();
+        assert!(cache
+            .interpolate(Vector3::from((0.05, 0.0, 0.0)), Vector3::k(), 0.2)
+            .is_none());
+    }
+}
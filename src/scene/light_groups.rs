@@ -0,0 +1,179 @@
+//! Module containing light groups, which split a render by the lights that
+//! light it.
+//!
+//! The images of the light groups are rendered in the same pass as the render
+//! itself. Whenever light from a light source is found along a path from the
+//! camera, it is added to the group of the light, weighted by the fraction of
+//! it that reaches the camera along the path. The images of all the groups add
+//! up to the render, so that the lights can be rebalanced when compositing
+//! without rendering again. Indirect light from the irradiance cache,
+//! caustics from the photon map and light from custom shaders aren't split by
+//! light, and are left out of the groups, and pixels of shadow catchers and
+//! holdouts are black in them.
+
+use super::LightId;
+use crate::image::Image;
+use crate::math::Vector3;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+thread_local! {
+    /// The light of each group in the pixel being rendered by this thread.
+    /// Kept per thread, like the work done for the pixel, rather than passed
+    /// along with every ray.
+    static PIXEL_LIGHT: RefCell<PixelLight> = RefCell::new(PixelLight {
+        weight: Vector3::ones(),
+        groups: Vec::new(),
+    });
+}
+
+struct PixelLight {
+    /// The fraction of the light found now that reaches the camera.
+    weight: Vector3,
+    /// The light of each group so far.
+    groups: Vec<Vector3>,
+}
+
+/// The images of the light groups of a render, shared with the threads that
+/// fill them in.
+#[derive(Clone, Default)]
+pub struct LightGroupImages {
+    images: Arc<Mutex<Vec<(String, Image)>>>,
+}
+
+impl LightGroupImages {
+    /// Take the images, each with the name of its group, when rendering is
+    /// done. Nothing is left to take afterwards.
+    pub fn take(&self) -> Vec<(String, Image)> {
+        std::mem::take(&mut *self.images.lock().unwrap())
+    }
+}
+
+/// The groups that the lights of a scene are split into, and their images.
+pub(super) struct LightGroups {
+    /// The lights in each named group.
+    named: BTreeMap<String, Vec<LightId>>,
+    /// The index of the group of each light, found when rendering starts.
+    group_of_light: Vec<usize>,
+    num_groups: usize,
+    images: LightGroupImages,
+}
+
+impl LightGroups {
+    pub(super) fn new(named: BTreeMap<String, Vec<LightId>>) -> Self {
+        Self {
+            named,
+            group_of_light: Vec::new(),
+            num_groups: 0,
+            images: LightGroupImages::default(),
+        }
+    }
+
+    /// The lights in each named group, as they were set.
+    pub(super) fn named(&self) -> &BTreeMap<String, Vec<LightId>> {
+        &self.named
+    }
+
+    pub(super) fn images(&self) -> LightGroupImages {
+        self.images.clone()
+    }
+
+    /// Put each of `lights`, the handles of the lights of the scene in order,
+    /// in its group, and make black images of size `width` x `height` for the
+    /// groups. Lights in none of the named groups get a group of their own,
+    /// named `light<index>`. Removed lights are left out of their groups.
+    pub(super) fn start(&mut self, lights: &[LightId], width: usize, height: usize) {
+        let mut names: Vec<String> = self.named.keys().cloned().collect();
+        let mut group_of_light = vec![None; lights.len()];
+        for (group, members) in self.named.values().enumerate() {
+            for member in members {
+                if let Some(index) = lights.iter().position(|light| light == member) {
+                    group_of_light[index] = Some(group);
+                }
+            }
+        }
+        self.group_of_light = group_of_light
+            .into_iter()
+            .enumerate()
+            .map(|(light, group)| {
+                group.unwrap_or_else(|| {
+                    names.push(format!("light{light}"));
+                    names.len() - 1
+                })
+            })
+            .collect();
+        self.num_groups = names.len();
+
+        *self.images.images.lock().unwrap() = names
+            .into_iter()
+            .map(|name| (name, Image::new(width, height)))
+            .collect();
+    }
+
+    /// Start finding the light of the groups in a new pixel, in this thread.
+    /// Calling it again clears the light found so far.
+    pub(super) fn start_pixel(&self) {
+        PIXEL_LIGHT.with(|pixel| {
+            let mut pixel = pixel.borrow_mut();
+            pixel.weight = Vector3::ones();
+            pixel.groups = vec![Vector3::zero(); self.num_groups];
+        });
+    }
+
+    /// Run `find_light`, where the light found reaches the camera multiplied
+    /// by `factor`, on top of the weight of the path so far.
+    pub(super) fn weighted<T>(&self, factor: Vector3, find_light: impl FnOnce() -> T) -> T {
+        let outer = PIXEL_LIGHT.with(|pixel| {
+            let mut pixel = pixel.borrow_mut();
+            let outer = pixel.weight;
+            pixel.weight = outer.elementwise_mul(factor);
+            outer
+        });
+        let result = find_light();
+        PIXEL_LIGHT.with(|pixel| pixel.borrow_mut().weight = outer);
+        result
+    }
+
+    /// Add `rgb` from the light with index `light` to the pixel.
+    pub(super) fn add(&self, light: usize, rgb: Vector3) {
+        PIXEL_LIGHT.with(|pixel| {
+            let mut pixel = pixel.borrow_mut();
+            let weighted = pixel.weight.elementwise_mul(rgb);
+            if let Some(group) = pixel.groups.get_mut(self.group_of_light[light]) {
+                *group += weighted;
+            }
+        });
+    }
+
+    /// Scale the light found in the pixel by `factor`, as when averaging
+    /// samples.
+    pub(super) fn scale(&self, factor: f64) {
+        PIXEL_LIGHT.with(|pixel| {
+            for group in &mut pixel.borrow_mut().groups {
+                *group = factor * *group;
+            }
+        });
+    }
+
+    /// The light of each group found in the pixel so far.
+    pub(super) fn pixel_light(&self) -> Vec<Vector3> {
+        PIXEL_LIGHT.with(|pixel| pixel.borrow().groups.clone())
+    }
+
+    /// Replace the light of each group found in the pixel by `groups`, as
+    /// when averaging the rays through a pixel.
+    pub(super) fn set_pixel_light(&self, groups: Vec<Vector3>) {
+        PIXEL_LIGHT.with(|pixel| pixel.borrow_mut().groups = groups);
+    }
+
+    /// Write the light found in the pixel to pixel (`x`, `y`) of the images.
+    pub(super) fn finish_pixel(&self, x: usize, y: usize) {
+        let mut images = self.images.images.lock().unwrap();
+        PIXEL_LIGHT.with(|pixel| {
+            for ((_, image), &rgb) in images.iter_mut().zip(&pixel.borrow().groups) {
+                image.set_pixel(x, y, rgb);
+            }
+        });
+    }
+}
@@ -0,0 +1,232 @@
+//! Module containing the light tree, for scenes with many lights.
+//!
+//! Sampling every light at every shaded point gets slow when a scene has
+//! hundreds of lights. Instead, the lights with a position are put in a binary
+//! tree of bounding boxes, and a few of them are picked at each point by
+//! walking down the tree, choosing between the two children of each node by
+//! an estimate of how much light they send to the point. Lights that are dim
+//! or far away are rarely picked, and their light is divided by the
+//! probability of picking them, which keeps the render unbiased.
+
+use crate::lights::{Light, LightBounds};
+use crate::math::Vector3;
+
+enum Children {
+    /// A leaf holds the light with this index in the lights of the scene.
+    Leaf(usize),
+    /// Indices of the two children in the nodes of the tree.
+    Inner(usize, usize),
+}
+
+struct Node {
+    /// The box around the lights below the node, and their total power.
+    bounds: LightBounds,
+    parent: Option<usize>,
+    children: Children,
+}
+
+impl Node {
+    /// Estimate how much light the lights below the node send to `point`.
+    /// Points inside the box are treated as being at its edge.
+    fn importance(&self, point: Vector3) -> f64 {
+        let center = 0.5 * (self.bounds.min + self.bounds.max);
+        let radius2 = 0.25 * (self.bounds.max - self.bounds.min).norm2();
+        let distance2 = (center - point).norm2().max(radius2).max(1e-12);
+        self.bounds.power / distance2
+    }
+}
+
+/// A binary tree of the lights with a position, which picks a light for a
+/// point with a probability that follows how much light it sends there.
+pub(super) struct LightTree {
+    nodes: Vec<Node>,
+    root: usize,
+    /// The leaf of each light of the scene, by the index of the light, or
+    /// `None` for lights not in the tree.
+    leaves: Vec<Option<usize>>,
+    /// The number of lights picked from the tree at each point.
+    pub(super) samples: u32,
+}
+
+impl LightTree {
+    /// Put the `lights` that have bounds in a tree, which `samples` lights
+    /// are picked from at each point. Returns `None` if no light has bounds.
+    pub(super) fn build(lights: &[Box<dyn Light + Send + Sync>], samples: u32) -> Option<Self> {
+        let mut bounded: Vec<(usize, LightBounds)> = lights
+            .iter()
+            .enumerate()
+            .filter_map(|(index, light)| Some((index, light.bounds()?)))
+            .collect();
+        if bounded.is_empty() {
+            return None;
+        }
+
+        let mut tree = Self {
+            nodes: Vec::with_capacity(2 * bounded.len() - 1),
+            root: 0,
+            leaves: vec![None; lights.len()],
+            samples: samples.max(1),
+        };
+        tree.root = tree.build_node(&mut bounded);
+        Some(tree)
+    }
+
+    /// Add a node for `lights`, of which there is at least one, and the nodes
+    /// below it, and return its index.
+    fn build_node(&mut self, lights: &mut [(usize, LightBounds)]) -> usize {
+        if let [(light, bounds)] = *lights {
+            self.leaves[light] = Some(self.nodes.len());
+            self.nodes.push(Node {
+                bounds,
+                parent: None,
+                children: Children::Leaf(light),
+            });
+            return self.nodes.len() - 1;
+        }
+
+        // Split the lights in half along the axis where their centers are
+        // spread out the most.
+        let center = |bounds: &LightBounds| 0.5 * (bounds.min + bounds.max);
+        let first_center = center(&lights[0].1);
+        let (lower, upper) = lights.iter().fold(
+            (first_center, first_center),
+            |(lower, upper), (_, bounds)| (lower.min(center(bounds)), upper.max(center(bounds))),
+        );
+        let spread = upper - lower;
+        let coordinate = |bounds: &LightBounds| {
+            let center = center(bounds);
+            if spread.x >= spread.y && spread.x >= spread.z {
+                center.x
+            } else if spread.y >= spread.z {
+                center.y
+            } else {
+                center.z
+            }
+        };
+        lights.sort_by(|(_, a), (_, b)| coordinate(a).total_cmp(&coordinate(b)));
+        let (first_lights, second_lights) = lights.split_at_mut(lights.len() / 2);
+
+        let first = self.build_node(first_lights);
+        let second = self.build_node(second_lights);
+        let (a, b) = (&self.nodes[first].bounds, &self.nodes[second].bounds);
+        let bounds = LightBounds {
+            min: a.min.min(b.min),
+            max: a.max.max(b.max),
+            power: a.power + b.power,
+        };
+        let index = self.nodes.len();
+        self.nodes.push(Node {
+            bounds,
+            parent: None,
+            children: Children::Inner(first, second),
+        });
+        self.nodes[first].parent = Some(index);
+        self.nodes[second].parent = Some(index);
+        index
+    }
+
+    /// Is the light with index `light` in the scene picked from the tree?
+    pub(super) fn contains(&self, light: usize) -> bool {
+        self.leaves.get(light).is_some_and(Option::is_some)
+    }
+
+    /// Pick a light for `point` with the uniformly distributed number `u` in
+    /// [0, 1). Returns the index of the light and the probability of picking
+    /// it.
+    pub(super) fn sample(&self, point: Vector3, mut u: f64) -> (usize, f64) {
+        let mut node = self.root;
+        let mut probability = 1.0;
+        loop {
+            match self.nodes[node].children {
+                Children::Leaf(light) => return (light, probability),
+                Children::Inner(first, second) => {
+                    // `u` is stretched to [0, 1) again for the next choice.
+                    let p = self.first_child_probability(first, second, point);
+                    if u < p {
+                        u /= p;
+                        probability *= p;
+                        node = first;
+                    } else {
+                        u = ((u - p) / (1.0 - p)).min(1.0 - f64::EPSILON);
+                        probability *= 1.0 - p;
+                        node = second;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Find the probability that `sample` picks the light with index `light`
+    /// for `point`. It is 0 for lights not in the tree.
+    pub(super) fn probability(&self, point: Vector3, light: usize) -> f64 {
+        let mut node = match self.leaves.get(light) {
+            Some(Some(leaf)) => *leaf,
+            _ => return 0.0,
+        };
+        let mut probability = 1.0;
+        while let Some(parent) = self.nodes[node].parent {
+            if let Children::Inner(first, second) = self.nodes[parent].children {
+                let p = self.first_child_probability(first, second, point);
+                probability *= if node == first { p } else { 1.0 - p };
+            }
+            node = parent;
+        }
+        probability
+    }
+
+    /// Find the probability of choosing the node `first` over `second` when
+    /// picking a light for `point`. Nodes that seem equally unimportant are
+    /// equally likely.
+    fn first_child_probability(&self, first: usize, second: usize, point: Vector3) -> f64 {
+        let first = self.nodes[first].importance(point);
+        let second = self.nodes[second].importance(point);
+        let total = first + second;
+        if total > 0.0 && total.is_finite() {
+            first / total
+        } else {
+            0.5
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lights::{PointLight, Sun};
+    use crate::math::sampling::Rng;
+
+    #[test]
+    fn nearby_lights_are_picked_more_often() {
+        let mut lights: Vec<Box<dyn Light + Send + Sync>> =
+            vec![Box::new(Sun::new((1.0, 1.0, 1.0), (0.0, 0.0, -1.0)))];
+        for x in 0..100 {
+            let position = (f64::from(x), 0.0, 1.0);
+            lights.push(Box::new(PointLight::new(position, (1.0, 1.0, 1.0), 1.0)));
+        }
+        let tree = LightTree::build(&lights, 4).unwrap();
+        assert!(!tree.contains(0));
+        assert!(tree.contains(100));
+
+        // The probabilities of all the lights add up to 1, and match how
+        // often they are picked.
+        let point = Vector3::from((10.0, 0.0, 0.0));
+        let total: f64 = (0..lights.len())
+            .map(|light| tree.probability(point, light))
+            .sum();
+        assert!((total - 1.0).abs() < 1e-12);
+
+        let mut rng = Rng::new(1);
+        let picks = 10_000;
+        let mut near = 0;
+        for _ in 0..picks {
+            let (light, probability) = tree.sample(point, rng.next_f64());
+            assert!((probability - tree.probability(point, light)).abs() < 1e-12);
+            if light == 11 {
+                near += 1;
+            }
+        }
+        let expected = tree.probability(point, 11);
+        assert!(expected > 10.0 * tree.probability(point, 91));
+        assert!((f64::from(near) / f64::from(picks) - expected).abs() < 0.02);
+    }
+}
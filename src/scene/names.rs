@@ -0,0 +1,157 @@
+//! Module for naming and tagging the surfaces and lights of a scene, so that
+//! they can be found again by name, e.g. by scene files, editors and debugging
+//! tools, without keeping their handles around.
+//!
+//! A name is meant to pick out one surface or light, while a tag marks any
+//! number of them, such as all the walls of a room. Names aren't required to
+//! be unique, and looking one up finds the first surface or light with it, in
+//! the order they were added. Names and tags go away with the surface or light
+//! when it is removed, and don't affect how the scene renders.
+
+use super::{LightId, Scene, SurfaceId};
+
+/// The name and tags of a surface or light.
+#[derive(Clone, Debug, Default)]
+pub(super) struct Labels {
+    name: Option<String>,
+    tags: Vec<String>,
+}
+
+impl Labels {
+    fn add_tag(&mut self, tag: &str) {
+        if !self.has_tag(tag) {
+            self.tags.push(tag.to_string());
+        }
+    }
+
+    fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|other| other == tag)
+    }
+
+    pub(super) fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub(super) fn tags(&self) -> &[String] {
+        &self.tags
+    }
+}
+
+impl Scene {
+    /// Name the surface `id`, in place of any name it had. Does nothing if
+    /// the surface has been removed.
+    pub fn set_surface_name(&mut self, id: SurfaceId, name: &str) {
+        if let Some(index) = self.surface_index(id) {
+            self.objects[index].labels.name = Some(name.to_string());
+        }
+    }
+
+    /// The name of the surface `id`, if it has one.
+    pub fn surface_name(&self, id: SurfaceId) -> Option<&str> {
+        let index = self.surface_index(id)?;
+        self.objects[index].labels.name.as_deref()
+    }
+
+    /// Find the first surface named `name`.
+    pub fn find_surface(&self, name: &str) -> Option<SurfaceId> {
+        self.objects
+            .iter()
+            .find(|object| object.labels.name.as_deref() == Some(name))
+            .map(|object| object.id)
+    }
+
+    /// Tag the surface `id` with `tag`, along with any tags it has. Does
+    /// nothing if the surface has been removed.
+    pub fn tag_surface(&mut self, id: SurfaceId, tag: &str) {
+        if let Some(index) = self.surface_index(id) {
+            self.objects[index].labels.add_tag(tag);
+        }
+    }
+
+    /// Find the surfaces tagged with `tag`, in the order they were added.
+    pub fn surfaces_tagged(&self, tag: &str) -> Vec<SurfaceId> {
+        self.objects
+            .iter()
+            .filter(|object| object.labels.has_tag(tag))
+            .map(|object| object.id)
+            .collect()
+    }
+
+    /// Name the light `id`, in place of any name it had. Does nothing if the
+    /// light has been removed.
+    pub fn set_light_name(&mut self, id: LightId, name: &str) {
+        if let Some(index) = self.light_index(id) {
+            self.light_labels[index].1.name = Some(name.to_string());
+        }
+    }
+
+    /// The name of the light `id`, if it has one.
+    pub fn light_name(&self, id: LightId) -> Option<&str> {
+        let index = self.light_index(id)?;
+        self.light_labels[index].1.name.as_deref()
+    }
+
+    /// Find the first light named `name`.
+    pub fn find_light(&self, name: &str) -> Option<LightId> {
+        self.light_labels
+            .iter()
+            .find(|(_, labels)| labels.name.as_deref() == Some(name))
+            .map(|&(id, _)| id)
+    }
+
+    /// Tag the light `id` with `tag`, along with any tags it has. Does nothing
+    /// if the light has been removed.
+    pub fn tag_light(&mut self, id: LightId, tag: &str) {
+        if let Some(index) = self.light_index(id) {
+            self.light_labels[index].1.add_tag(tag);
+        }
+    }
+
+    /// Find the lights tagged with `tag`, in the order they were added.
+    pub fn lights_tagged(&self, tag: &str) -> Vec<LightId> {
+        self.light_labels
+            .iter()
+            .filter(|(_, labels)| labels.has_tag(tag))
+            .map(|&(id, _)| id)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lights::PointLight;
+    use crate::surfaces::{Plane, Sphere};
+
+    #[test]
+    fn surfaces_and_lights_are_found_by_name_and_tag() {
+        let mut scene = Scene::new();
+        let floor = scene.add_surface(Plane::new((0.0, 0.0, 1.0), 0.0));
+        let ball = scene.add_surface(Sphere::new((0.0, 2.0, 1.0), 1.0));
+        let wall = scene.add_surface(Plane::new((1.0, 0.0, 0.0), -5.0));
+        scene.set_surface_name(floor, "floor");
+        scene.set_surface_name(ball, "ball");
+        scene.tag_surface(floor, "room");
+        scene.tag_surface(wall, "room");
+        scene.tag_surface(wall, "room");
+
+        assert_eq!(scene.find_surface("floor"), Some(floor));
+        assert_eq!(scene.surface_name(ball), Some("ball"));
+        assert_eq!(scene.surface_name(wall), None);
+        assert_eq!(scene.surfaces_tagged("room"), [floor, wall]);
+
+        let lamp = scene.add_light(PointLight::new((0.0, 0.0, 3.0), (1.0, 1.0, 1.0), 1.0));
+        scene.set_light_name(lamp, "lamp");
+        scene.tag_light(lamp, "room");
+        assert_eq!(scene.find_light("lamp"), Some(lamp));
+        assert_eq!(scene.lights_tagged("room"), [lamp]);
+
+        // Names and tags go away with what they name.
+        scene.remove_surface(floor);
+        scene.remove_light(lamp);
+        assert_eq!(scene.find_surface("floor"), None);
+        assert_eq!(scene.surfaces_tagged("room"), [wall]);
+        assert_eq!(scene.find_light("lamp"), None);
+        assert!(scene.lights_tagged("room").is_empty());
+    }
+}
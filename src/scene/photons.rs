@@ -0,0 +1,168 @@
+//! Module containing the photon map, which renders caustics.
+//!
+//! Caustics are light focused onto a surface by glass and mirrors. Paths
+//! traced from the camera only find them by hitting a light right after a
+//! perfectly specular bounce, which is unlikely for small lights and
+//! impossible for the sun. Instead, photons are traced from the lights through
+//! the specular surfaces before rendering, and stored where they land on other
+//! surfaces. The density of photons around a point gives the caustic light
+//! there.
+
+use super::Scene;
+use crate::materials::Bsdf;
+use crate::math::kd_tree::KdTree;
+use crate::math::sampling::Rng;
+use crate::math::{Ray, Vector3};
+use std::f64::consts::PI;
+use std::time::{Duration, Instant};
+
+/// The number of photons that the caustic light at a point is estimated from.
+const NEAREST_PHOTONS: usize = 50;
+/// The largest number of times a photon bounces off specular surfaces.
+const MAX_BOUNCES: u32 = 8;
+
+/// A photon stored where it landed.
+struct Photon {
+    /// Unit vector pointing back along the path of the photon.
+    incoming: Vector3,
+    power: Vector3,
+}
+
+/// The photons that have reached non-specular surfaces via specular ones.
+pub(super) struct PhotonMap {
+    photons: KdTree<Photon>,
+    /// The largest distance from a point that photons are gathered from.
+    max_radius: f64,
+    /// The number of photons traced from each light.
+    pub(super) num_photons: usize,
+    /// How long it took to trace the photons.
+    pub(super) build_time: Duration,
+}
+
+impl PhotonMap {
+    /// Trace `num_photons` photons from each light in `scene` towards the
+    /// surfaces with perfectly specular materials.
+    pub(super) fn build(scene: &Scene, num_photons: usize) -> Self {
+        let start = Instant::now();
+        let mut photons = Vec::new();
+        let mut max_radius = 0.0;
+
+        // Aim for one sphere enclosing all the specular surfaces. Aiming for
+        // each of them separately would count the light towards overlapping
+        // ones twice.
+        let target = scene
+            .objects
+            .iter()
+            .filter(|object| object.material.is_specular())
+            .filter_map(|object| object.surface.bounding_sphere())
+            .map(|(center, radius)| {
+                let radius = radius * Vector3::ones();
+                (center - radius, center + radius)
+            })
+            .reduce(|(lower, upper), (other_lower, other_upper)| {
+                (lower.min(other_lower), upper.max(other_upper))
+            })
+            .map(|(lower, upper)| (0.5 * (lower + upper), 0.5 * (upper - lower).norm()));
+
+        if let (Some((center, radius)), true) = (target, num_photons > 0) {
+            // Photons spread evenly over the cross section of the sphere would
+            // have about this many photons within a quarter of this distance.
+            // Caustics are denser, so their details are sharper, while light
+            // spread out by curved surfaces is blurred to hide the noise.
+            max_radius = 4.0 * radius * (NEAREST_PHOTONS as f64 / num_photons as f64).sqrt();
+
+            for (index, light) in scene.lights.iter().enumerate() {
+                let mut rng = Rng::new(index as u64);
+                for _ in 0..num_photons {
+                    if let Some(emission) = light.emit(center, radius, rng.next_pair()) {
+                        let power = emission.power * (1.0 / num_photons as f64);
+                        trace_photon(scene, emission.ray, power, &mut rng, &mut photons);
+                    }
+                }
+            }
+        }
+
+        Self {
+            photons: KdTree::new(photons),
+            max_radius,
+            num_photons,
+            build_time: start.elapsed(),
+        }
+    }
+
+    /// Find the memory used by the photons, in bytes.
+    pub(super) fn memory_usage(&self) -> usize {
+        self.photons.memory_usage()
+    }
+
+    /// Estimate the caustic light reflected towards `outgoing` from `point`,
+    /// where the surface has `bsdf`.
+    pub(super) fn radiance(&self, point: Vector3, bsdf: &Bsdf, outgoing: Vector3) -> Vector3 {
+        let nearest = self
+            .photons
+            .nearest(point, NEAREST_PHOTONS, self.max_radius);
+        if nearest.is_empty() {
+            return Vector3::zero();
+        }
+
+        // The photons are spread over a disk reaching out to the farthest one,
+        // unless fewer than wanted were found.
+        let radius2 = if nearest.len() == NEAREST_PHOTONS {
+            nearest[nearest.len() - 1].distance2
+        } else {
+            self.max_radius * self.max_radius
+        };
+
+        let mut power = Vector3::zero();
+        for neighbor in nearest {
+            let photon = neighbor.item;
+            if bsdf.normal().dot(photon.incoming) > 0.0 {
+                power += bsdf
+                    .reflectance(outgoing, photon.incoming)
+                    .elementwise_mul(photon.power);
+            }
+        }
+
+        power * (1.0 / (PI * PI * radius2))
+    }
+}
+
+/// Follow a photon with `power` along `ray`, as long as it bounces off
+/// perfectly specular surfaces, and store it in `photons` where it lands on
+/// another surface after at least one such bounce. Light arriving directly
+/// from the lights is not stored, since it is found by sampling the lights.
+fn trace_photon(
+    scene: &Scene,
+    mut ray: Ray,
+    mut power: Vector3,
+    rng: &mut Rng,
+    photons: &mut Vec<(Vector3, Photon)>,
+) {
+    for bounce in 0..=MAX_BOUNCES {
+        let (point, hit, object) = match scene.trace(ray.clone()) {
+            Some(closest) => closest,
+            None => return,
+        };
+        let bsdf = object.material.bsdf(point, hit.uv, hit.normal, hit.tangent);
+        let outgoing = -ray.direction;
+
+        if bounce > 0 && !bsdf.is_specular() {
+            photons.push((
+                point,
+                Photon {
+                    incoming: outgoing,
+                    power,
+                },
+            ));
+        }
+
+        // A photon taking a non-specular bounce no longer makes a caustic.
+        match bsdf.sample(outgoing, rng) {
+            Some(sample) if sample.pdf.is_infinite() && bounce < MAX_BOUNCES => {
+                power = power.elementwise_mul(sample.weight);
+                ray = Ray::new(point, sample.direction);
+            }
+            _ => return,
+        }
+    }
+}
@@ -0,0 +1,98 @@
+//! Module for controlling renders running in other threads.
+//!
+//! `Scene::spawn_render_threads` and the other ways of spawning a render return
+//! a `RenderHandle`, which holds the receiving end of the channel the pixels
+//! are sent through, together with the render threads. Pixels are sent in
+//! runs along the rows of the image, rather than one by one, since a message
+//! per pixel is slow for large images. Cancelling the render
+//! sets a flag shared with the threads, which they check between pixels and
+//! between the samples of a pixel, so that they stop soon after, without
+//! sending the pixels they were working on. Each thread returns the
+//! statistics of its part of the render, which are added up when joining.
+
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+use crate::image::Pixel;
+use crate::stats::RenderStats;
+
+/// A render running in other threads. Dropping the handle cancels the render.
+pub struct RenderHandle {
+    receiver: Receiver<(usize, usize, Vec<Pixel>)>,
+    cancelled: Arc<AtomicBool>,
+    threads: Vec<JoinHandle<RenderStats>>,
+}
+
+impl RenderHandle {
+    pub(super) fn new(
+        receiver: Receiver<(usize, usize, Vec<Pixel>)>,
+        cancelled: Arc<AtomicBool>,
+        threads: Vec<JoinHandle<RenderStats>>,
+    ) -> Self {
+        Self {
+            receiver,
+            cancelled,
+            threads,
+        }
+    }
+
+    /// The channel that rendered pixels are sent through, as runs of pixels
+    /// next to each other along a row, each with the x-y coordinates of its
+    /// first pixel. It is closed when all the threads are done.
+    pub fn receiver(&self) -> &Receiver<(usize, usize, Vec<Pixel>)> {
+        &self.receiver
+    }
+
+    /// Wait for runs of rendered pixels, until the render is done or
+    /// cancelled.
+    pub fn iter(&self) -> mpsc::Iter<'_, (usize, usize, Vec<Pixel>)> {
+        self.receiver.iter()
+    }
+
+    /// Like `iter`, but each pixel on its own, with its x-y coordinates.
+    pub fn pixels(&self) -> impl Iterator<Item = (usize, usize, Pixel)> + '_ {
+        self.iter().flat_map(|(x, y, run)| {
+            run.into_iter()
+                .enumerate()
+                .map(move |(index, pixel)| (x + index, y, pixel))
+        })
+    }
+
+    /// Ask the render threads to stop. They stop soon after, and pixels that
+    /// were not finished are not sent.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Has the render been cancelled?
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Wait for the render threads to stop, which is when the render is done
+    /// unless it is cancelled first, and return the statistics of the
+    /// render. Returns `Err` if a thread panicked.
+    pub fn join(mut self) -> Result<RenderStats, Box<dyn Error>> {
+        let mut stats = RenderStats::default();
+        let mut panicked = false;
+        for thread in self.threads.drain(..) {
+            match thread.join() {
+                Ok(thread_stats) => stats.add(&thread_stats),
+                Err(_) => panicked = true,
+            }
+        }
+        if panicked {
+            return Err("A render thread panicked".into());
+        }
+        Ok(stats)
+    }
+}
+
+impl Drop for RenderHandle {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
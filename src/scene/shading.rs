@@ -0,0 +1,133 @@
+//! Module containing custom shaders, for prototyping shading models.
+//!
+//! A material given a shader with `Material::with_shader` is shaded by a
+//! closure instead of by its BSDF, where rays from the camera reach it. The
+//! closure gets a `ShadingContext` with the point that was hit, can ask it for
+//! the light arriving from the lights of the scene, and can trace rays further
+//! into the scene, and returns the color leaving the point towards the ray.
+//! Elsewhere, the rest of the material is still used: for shadows cast through
+//! it, for photons and for shadow catchers. Light from shaders isn't split by
+//! light group.
+
+use super::{light_sample_point, Scene, MAX_SPECULAR_DEPTH};
+use crate::lights::Illumination;
+use crate::materials::Material;
+use crate::math::sampling::Rng;
+use crate::math::{Ray, Vector3};
+use crate::scene::Integrator;
+use crate::surfaces::Intersection;
+
+/// A custom shader, finding the color leaving a point from a `ShadingContext`.
+pub type Shader = dyn Fn(&mut ShadingContext) -> Vector3 + Send + Sync;
+
+/// Where a ray hits a surface with a custom shader, and how to find the light
+/// arriving there.
+pub struct ShadingContext<'a> {
+    scene: &'a Scene,
+    rng: &'a mut Rng,
+    /// The number of bounces that rays traced from here may still take.
+    remaining_depth: u32,
+    /// The point hit.
+    pub point: Vector3,
+    /// The unit normal of the surface at the point, pointing out of the
+    /// surface, with the normal map of the material applied.
+    pub normal: Vector3,
+    /// A unit tangent of the surface at the point.
+    pub tangent: Vector3,
+    /// The texture coordinates of the point.
+    pub uv: (f64, f64),
+    /// Unit vector pointing from the point back along the ray that hit it.
+    pub outgoing: Vector3,
+    /// The albedo of the material at the point.
+    pub albedo: Vector3,
+}
+
+impl ShadingContext<'_> {
+    /// Find the light arriving at the point from the lights of the scene, each
+    /// sampled as many times as it asks for. The color of each sample is
+    /// divided by the number of samples of its light, and dimmed by the
+    /// surfaces between the light and the point, so that a white, diffuse
+    /// surface reflects the sum of the colors, each times the cosine of the
+    /// angle between the normal and the direction of the light. Lights with
+    /// an extent are sampled at random points when path tracing, and at fixed
+    /// points otherwise.
+    pub fn lights(&mut self) -> Vec<Illumination> {
+        let random = matches!(self.scene.integrator, Integrator::PathTracing { .. });
+        let mut illuminations = Vec::new();
+        for light in &self.scene.lights {
+            let light_samples = light.samples().max(1);
+            for light_sample in 0..light_samples {
+                let rng = if random { Some(&mut *self.rng) } else { None };
+                let u = light_sample_point(rng, light_sample, light_samples);
+                let mut illumination = light.illuminate(self.point, u);
+                let through_transparent = self.scene.caustics.is_none()
+                    && (illumination.pdf.is_infinite() || !self.scene.paths_find_caustics());
+                let transmittance = self.scene.transmittance(
+                    self.point,
+                    illumination.direction,
+                    illumination.distance,
+                    through_transparent,
+                );
+                illumination.color = (1.0 / f64::from(light_samples))
+                    * illumination.color.elementwise_mul(transmittance);
+                illuminations.push(illumination);
+            }
+        }
+        illuminations
+    }
+
+    /// Find the color seen from the point along `direction`, as the scene is
+    /// rendered, for reflection and refraction. Returns black when rays have
+    /// bounced as many times as the integrator allows, or if `direction` is
+    /// zero or not finite.
+    pub fn trace(&mut self, direction: Vector3) -> Vector3 {
+        let ray = match Ray::try_new(self.point, direction) {
+            Ok(ray) if self.remaining_depth > 0 => ray,
+            _ => return Vector3::zero(),
+        };
+        let remaining_depth = self.remaining_depth - 1;
+        match self.scene.integrator {
+            Integrator::PathTracing { .. } => {
+                self.scene
+                    .trace_path(ray, remaining_depth, self.rng, false, None)
+            }
+            _ => self.scene.trace_direct(
+                ray,
+                MAX_SPECULAR_DEPTH - remaining_depth.min(MAX_SPECULAR_DEPTH),
+                self.rng,
+                None,
+            ),
+        }
+    }
+}
+
+impl Scene {
+    /// Find the color leaving `point`, hit by `ray`, towards the ray, with the
+    /// shader of `material`. Returns `None` if the material has no shader.
+    /// Rays traced by the shader may bounce `remaining_depth` more times.
+    pub(super) fn shade(
+        &self,
+        material: &Material,
+        ray: &Ray,
+        point: Vector3,
+        hit: &Intersection,
+        remaining_depth: u32,
+        rng: &mut Rng,
+    ) -> Option<Vector3> {
+        let shader = material.shader()?;
+        let mut context = ShadingContext {
+            scene: self,
+            rng,
+            remaining_depth,
+            point,
+            normal: material.shading_normal(point, hit.uv, hit.normal, hit.tangent),
+            tangent: hit.tangent,
+            uv: hit.uv,
+            outgoing: -ray.direction,
+            albedo: material.albedo(point, hit.uv),
+        };
+        // What the shader does with the light is unknown, so it can't be
+        // split by light group.
+        Some(self.weighted_light(Vector3::zero(), || shader(&mut context)))
+    }
+}
@@ -0,0 +1,1817 @@
+//! Module for reading and upgrading scene description files.
+//!
+//! Scene files are written in RON, or in JSON if their names end in `.json`,
+//! and describe the surfaces, materials and lights of a scene, the camera and
+//! the render settings as data. The format is versioned: each file records the
+//! version of the format it was written for, and files written for older
+//! versions, or without a version, which are for version 1, are migrated to
+//! the current version when read, a version at a time. Fields that aren't
+//! recognized, e.g. because the file was written by a newer version of
+//! rustbeam, are ignored with a warning, so that scene libraries keep working
+//! across upgrades.
+//!
+//! A minimal scene file looks like this:
+//!
+//! ```ron
+//! (
+//!     version: 1,
+//!     materials: {
+//!         "gray": (albedo: Constant((0.8, 0.8, 0.8))),
+//!     },
+//!     surfaces: [
+//!         (shape: Sphere(center: (0.0, 2.0, 0.0), radius: 0.5)),
+//!         (
+//!             shape: Plane(normal: (0.0, 0.0, 1.0), distance: -0.5),
+//!             material_name: Some("gray"),
+//!         ),
+//!     ],
+//!     lights: [Sun(color: (1.0, 1.0, 1.0), direction: (1.0, 1.0, -1.0))],
+//! )
+//! ```
+
+use crate::flare::LensFlare;
+use crate::image::ColorSpace;
+use crate::lights::{
+    AreaLight, Brightness, EnvironmentMap, Light, PhysicalSky, PointLight, SphereLight, Sun,
+};
+use crate::materials::Material;
+use crate::math::blue_noise::BlueNoiseMask;
+use crate::math::sampling::SamplePattern;
+use crate::math::{UnitQuaternion, Vector3};
+use crate::obj;
+use crate::plugins::Registry;
+use crate::scene::{
+    Camera, CameraPath, Exposure, HaltConditions, Integrator, PhysicalCamera, PixelLimits,
+    Projection, RayBias, SamplerSettings, Scene,
+};
+use crate::sensor::{BayerPattern, Sensor};
+use crate::surfaces::{Mesh, Plane, Rect, Sphere, Surface, Transform};
+use crate::textures::{
+    Checker, CheckerMapping, Constant, ImageTexture, Marble, Texture, TextureCache, Turbulence,
+    Wood,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The version of the scene file format written by this version of rustbeam.
+pub const CURRENT_VERSION: u32 = 1;
+
+/// The version of scene files without a version, which were written before the
+/// format was versioned.
+const FIRST_VERSION: u32 = 1;
+
+/// A migration of a scene description from one version of the format to the
+/// next. Files of older versions are read into the fields of the current
+/// version, and the migration converts what changed meaning in between.
+type Migration = fn(&mut SceneDescription, &mut Vec<String>);
+
+/// The migrations from each version of the format to the next, starting with
+/// the one from the first version to the second.
+const MIGRATIONS: [Migration; (CURRENT_VERSION - FIRST_VERSION) as usize] = [];
+
+/// An RGB color.
+pub type ColorDescription = (f64, f64, f64);
+/// A position or direction in 3D space.
+pub type VectorDescription = (f64, f64, f64);
+
+/// The contents of a scene file. The default is an empty scene for the current
+/// version of the format.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SceneDescription {
+    /// The version of the scene file format. Files without a version are for
+    /// the first version.
+    #[serde(default = "first_version")]
+    pub version: u32,
+    /// The color space that colors in the file are given in.
+    #[serde(default)]
+    pub color_space: ColorSpaceDescription,
+    #[serde(default)]
+    pub integrator: IntegratorDescription,
+    /// How the random numbers of each pixel are chosen.
+    #[serde(default)]
+    pub sampler: SamplerDescription,
+    /// The exposure value at ISO 100 of the camera, for scenes lit by lights
+    /// with brightnesses in physical units. No exposure if `None`.
+    #[serde(default)]
+    pub exposure: Option<f64>,
+    /// Where the camera is, and how it sees the scene. The default camera if
+    /// `None`.
+    #[serde(default)]
+    pub camera: Option<CameraDescription>,
+    /// The settings of a real camera, which set the field of view, the depth
+    /// of field and the exposure, in place of `exposure`, with the scene
+    /// measured in meters.
+    #[serde(default)]
+    pub physical_camera: Option<PhysicalCameraDescription>,
+    /// Keyframes of a path that the camera follows in animations, as for
+    /// `CameraPath`. The camera is placed where the path has it in frame 0
+    /// when the scene isn't animated.
+    #[serde(default)]
+    pub camera_path: Vec<CameraKeyframeDescription>,
+    /// Materials that surfaces can refer to by name.
+    #[serde(default)]
+    pub materials: BTreeMap<String, MaterialDescription>,
+    #[serde(default)]
+    pub surfaces: Vec<SurfaceDescription>,
+    #[serde(default)]
+    pub lights: Vec<LightDescription>,
+    /// An environment map surrounding the scene, which is seen where rays hit
+    /// nothing, and lights the scene.
+    #[serde(default)]
+    pub environment: Option<EnvironmentDescription>,
+    /// A procedural sky with the sun, instead of an environment map.
+    #[serde(default)]
+    pub sky: Option<SkyDescription>,
+    /// The number of photons traced from each light for rendering caustics,
+    /// or 0 for no caustics.
+    #[serde(default)]
+    pub caustic_photons: usize,
+    /// The number of lights picked at each point from a tree of the lights,
+    /// for scenes with many lights, or 0 for sampling every light.
+    #[serde(default)]
+    pub light_tree_samples: u32,
+    /// A directory where the BVHs of large meshes are cached between renders,
+    /// relative to the directory of the scene file. No caching if `None`.
+    #[serde(default)]
+    pub bvh_cache: Option<String>,
+    /// The memory, in megabytes, that the scene is expected to fit in. Loading
+    /// a larger scene gives a warning.
+    #[serde(default)]
+    pub memory_budget: Option<f64>,
+    /// How far rays must go before they can hit a surface, near the origin of
+    /// the scene. Raise it for shadow acne, and lower it for light leaking
+    /// through thin surfaces.
+    #[serde(default)]
+    pub ray_bias: Option<f64>,
+    /// How far rays must go before they can hit a surface, as a fraction of
+    /// the largest coordinate of the ray origin, for large scenes.
+    #[serde(default)]
+    pub relative_ray_bias: Option<f64>,
+    /// The largest number of rays traced for a pixel. Pixels that need more
+    /// are given a bright magenta color.
+    #[serde(default)]
+    pub max_rays_per_pixel: Option<u64>,
+    /// The longest time, in seconds, spent on a pixel. Pixels that take longer
+    /// are given a bright magenta color.
+    #[serde(default)]
+    pub max_seconds_per_pixel: Option<f64>,
+    /// Stop path tracing a pixel when the standard error of its luminance is
+    /// below this fraction of the luminance, before taking all its samples.
+    #[serde(default)]
+    pub noise_threshold: Option<f64>,
+    /// Stop taking more than one sample of each pixel after this many seconds
+    /// of rendering.
+    #[serde(default)]
+    pub max_render_seconds: Option<f64>,
+    /// Keep the system from going to sleep while rendering.
+    #[serde(default)]
+    pub keep_awake: bool,
+    /// Render at a lower priority than other programs, so that the machine
+    /// stays usable.
+    #[serde(default)]
+    pub low_priority: bool,
+    /// Denoise the render with Open Image Denoise.
+    #[serde(default)]
+    pub denoise: bool,
+    /// Also save a raw image of the render, as captured by a simulated camera
+    /// sensor. No raw image if `None`.
+    #[serde(default)]
+    pub sensor: Option<SensorDescription>,
+    /// Add lens flares around bright lights in view. No lens flares if
+    /// `None`.
+    #[serde(default)]
+    pub lens_flare: Option<LensFlareDescription>,
+    /// Also render an image of the light from each group of lights, named by
+    /// the keys, and listing indices into `lights`, where the environment or
+    /// sky comes last. Lights in no group get an image of their own. No
+    /// images if `None`.
+    #[serde(default)]
+    pub light_groups: Option<BTreeMap<String, Vec<usize>>>,
+    /// Names to find lights by, as with `Scene::find_light`, for indices
+    /// into `lights`, where the environment or sky comes last.
+    #[serde(default)]
+    pub light_names: BTreeMap<String, usize>,
+}
+
+impl Default for SceneDescription {
+    fn default() -> Self {
+        Self {
+            version: CURRENT_VERSION,
+            color_space: ColorSpaceDescription::Linear,
+            integrator: IntegratorDescription::DirectLighting,
+            sampler: SamplerDescription::default(),
+            exposure: None,
+            camera: None,
+            physical_camera: None,
+            camera_path: Vec::new(),
+            materials: BTreeMap::new(),
+            surfaces: Vec::new(),
+            lights: Vec::new(),
+            environment: None,
+            sky: None,
+            caustic_photons: 0,
+            light_tree_samples: 0,
+            bvh_cache: None,
+            memory_budget: None,
+            ray_bias: None,
+            relative_ray_bias: None,
+            max_rays_per_pixel: None,
+            max_seconds_per_pixel: None,
+            noise_threshold: None,
+            max_render_seconds: None,
+            keep_awake: false,
+            low_priority: false,
+            denoise: false,
+            sensor: None,
+            lens_flare: None,
+            light_groups: None,
+            light_names: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub enum ColorSpaceDescription {
+    #[default]
+    Linear,
+    Srgb,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub enum IntegratorDescription {
+    #[default]
+    DirectLighting,
+    PathTracing {
+        max_depth: u32,
+        samples_per_pixel: u32,
+    },
+    IrradianceCaching {
+        max_depth: u32,
+        samples: u32,
+        max_error: f64,
+    },
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct SamplerDescription {
+    /// How the samples of each pixel are spread.
+    #[serde(default)]
+    pub pattern: SamplePattern,
+    /// The number of rays traced through each pixel, for antialiasing. One
+    /// ray through the middle of each pixel if 0 or 1.
+    #[serde(default)]
+    pub pixel_samples: u32,
+    /// Offset the random numbers of each pixel by a blue-noise mask, for
+    /// evenly spread noise.
+    #[serde(default)]
+    pub blue_noise: bool,
+    /// A square, gray PNG image to use as the blue-noise mask. A relative path
+    /// is relative to the directory of the scene file. A generated mask is
+    /// used if `None`.
+    #[serde(default)]
+    pub blue_noise_mask: Option<String>,
+    /// Mixed into the random numbers of every pixel, for renders with other
+    /// noise.
+    #[serde(default)]
+    pub seed: u64,
+    /// Spread the rays of each pass of progressive renders by the noise of
+    /// the pixels, as for `SamplerSettings::adaptive_passes`.
+    #[serde(default)]
+    pub adaptive_passes: bool,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub enum BayerPatternDescription {
+    #[default]
+    Rggb,
+    Bggr,
+    Grbg,
+    Gbrg,
+}
+
+/// A camera sensor as for `Sensor`, where missing fields take the values of
+/// `Sensor::default`.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SensorDescription {
+    #[serde(default)]
+    pub pattern: BayerPatternDescription,
+    /// The number of electrons that fills a pixel.
+    #[serde(default)]
+    pub full_well: Option<f64>,
+    /// The standard deviation of the read noise, in electrons.
+    #[serde(default)]
+    pub read_noise: Option<f64>,
+    #[serde(default)]
+    pub bit_depth: Option<u8>,
+    #[serde(default)]
+    pub black_level: Option<u16>,
+    #[serde(default)]
+    pub seed: u64,
+}
+
+impl SensorDescription {
+    fn build(&self) -> Result<Sensor, Box<dyn Error>> {
+        let default = Sensor::default();
+        let sensor = Sensor {
+            pattern: match self.pattern {
+                BayerPatternDescription::Rggb => BayerPattern::Rggb,
+                BayerPatternDescription::Bggr => BayerPattern::Bggr,
+                BayerPatternDescription::Grbg => BayerPattern::Grbg,
+                BayerPatternDescription::Gbrg => BayerPattern::Gbrg,
+            },
+            full_well: self.full_well.unwrap_or(default.full_well),
+            read_noise: self.read_noise.unwrap_or(default.read_noise),
+            bit_depth: self.bit_depth.unwrap_or(default.bit_depth),
+            black_level: self.black_level.unwrap_or(default.black_level),
+            seed: self.seed,
+        };
+        if !(1..=16).contains(&sensor.bit_depth) {
+            return Err("Sensor bit depth must be from 1 to 16".into());
+        }
+        if !(sensor.full_well > 0.0 && sensor.read_noise >= 0.0) {
+            return Err("Sensor full well must be positive, and read noise not negative".into());
+        }
+        Ok(sensor)
+    }
+}
+
+/// Lens flares as for `LensFlare`, where missing fields take the values of
+/// `LensFlare::default`.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LensFlareDescription {
+    /// The luminance of a light, as it lights a white surface facing it at
+    /// the camera, below which it doesn't flare.
+    #[serde(default)]
+    pub threshold: Option<f64>,
+    #[serde(default)]
+    pub intensity: Option<f64>,
+    /// The number of streaks of the starburst.
+    #[serde(default)]
+    pub streaks: Option<u32>,
+    /// The length of the streaks, as a fraction of the width of the image.
+    #[serde(default)]
+    pub streak_length: Option<f64>,
+    #[serde(default)]
+    pub ghosts: Option<u32>,
+}
+
+impl LensFlareDescription {
+    fn build(&self) -> LensFlare {
+        let default = LensFlare::default();
+        LensFlare {
+            threshold: self.threshold.unwrap_or(default.threshold),
+            intensity: self.intensity.unwrap_or(default.intensity),
+            streaks: self.streaks.unwrap_or(default.streaks),
+            streak_length: self.streak_length.unwrap_or(default.streak_length),
+            ghosts: self.ghosts.unwrap_or(default.ghosts),
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SurfaceDescription {
+    pub shape: ShapeDescription,
+    #[serde(default)]
+    pub material: MaterialDescription,
+    /// The name of a material in `materials` to use instead of `material`.
+    #[serde(default)]
+    pub material_name: Option<String>,
+    /// Where surfaces coincide, the one with the highest priority is seen.
+    #[serde(default)]
+    pub priority: i32,
+    /// Holdouts are cut out of the image, for compositing.
+    #[serde(default)]
+    pub holdout: bool,
+    /// A name to find the surface by, as with `Scene::find_surface`.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Tags to find the surface by, as with `Scene::surfaces_tagged`.
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub enum ShapeDescription {
+    Sphere {
+        center: VectorDescription,
+        radius: f64,
+    },
+    Plane {
+        normal: VectorDescription,
+        distance: f64,
+        #[serde(default)]
+        transform: Option<TransformDescription>,
+    },
+    /// A rectangle, made by `Rect::new` and then transformed.
+    Rect {
+        width: f64,
+        height: f64,
+        #[serde(default)]
+        transform: Option<TransformDescription>,
+    },
+    /// A mesh loaded from an OBJ file. A relative path is relative to the
+    /// directory of the scene file.
+    Obj { path: String },
+    /// A mesh given by its vertices, with texture coordinates (0, 0) if `uvs`
+    /// is left out, and its triangles, as three indices into the vertices.
+    Mesh {
+        positions: Vec<VectorDescription>,
+        #[serde(default)]
+        uvs: Vec<(f64, f64)>,
+        triangles: Vec<[usize; 3]>,
+    },
+    /// A surface registered in the `Registry` under `name`.
+    Plugin {
+        name: String,
+        #[serde(default = "no_parameters")]
+        parameters: ron::Value,
+    },
+}
+
+/// A rotation around the origin, followed by a translation.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TransformDescription {
+    #[serde(default = "default_rotation_axis")]
+    pub rotation_axis: VectorDescription,
+    /// The angle of the rotation, in degrees.
+    #[serde(default)]
+    pub rotation_angle: f64,
+    #[serde(default)]
+    pub translation: VectorDescription,
+}
+
+fn default_rotation_axis() -> VectorDescription {
+    (0.0, 0.0, 1.0)
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct MaterialDescription {
+    pub albedo: TextureDescription,
+    #[serde(default)]
+    pub normal_map: Option<TextureDescription>,
+    #[serde(default = "default_roughness")]
+    pub roughness: f64,
+    #[serde(default)]
+    pub anisotropy: f64,
+    #[serde(default)]
+    pub metallic: f64,
+    #[serde(default)]
+    pub transmission: f64,
+    #[serde(default = "default_ior")]
+    pub ior: f64,
+    #[serde(default)]
+    pub shadow_catcher: bool,
+}
+
+impl Default for MaterialDescription {
+    fn default() -> Self {
+        Self {
+            albedo: TextureDescription::Constant((1.0, 1.0, 1.0)),
+            normal_map: None,
+            roughness: default_roughness(),
+            anisotropy: 0.0,
+            metallic: 0.0,
+            transmission: 0.0,
+            ior: default_ior(),
+            shadow_catcher: false,
+        }
+    }
+}
+
+fn default_roughness() -> f64 {
+    0.5
+}
+
+fn default_ior() -> f64 {
+    1.5
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub enum TextureDescription {
+    Constant(ColorDescription),
+    /// A PNG image. A relative path is relative to the directory of the scene
+    /// file.
+    Image {
+        path: String,
+    },
+    Checker {
+        even: Box<TextureDescription>,
+        odd: Box<TextureDescription>,
+        scale: f64,
+        /// Lay out the squares in texture coordinates instead of 3D space.
+        #[serde(default)]
+        uv: bool,
+    },
+    Marble {
+        base: Box<TextureDescription>,
+        vein: Box<TextureDescription>,
+        scale: f64,
+        octaves: u32,
+    },
+    Wood {
+        light: Box<TextureDescription>,
+        dark: Box<TextureDescription>,
+        scale: f64,
+        octaves: u32,
+    },
+    Turbulence {
+        low: Box<TextureDescription>,
+        high: Box<TextureDescription>,
+        scale: f64,
+        octaves: u32,
+    },
+    /// A texture registered in the `Registry` under `name`.
+    Plugin {
+        name: String,
+        #[serde(default = "no_parameters")]
+        parameters: ron::Value,
+    },
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub enum LightDescription {
+    Sun {
+        color: ColorDescription,
+        direction: VectorDescription,
+        /// The illuminance on a surface facing the sun, in lux, replacing the
+        /// brightness of `color`.
+        #[serde(default)]
+        illuminance: Option<f64>,
+        /// The angular radius of the disc of the sun, in degrees, for soft
+        /// shadows. The real sun has an angular radius of about 0.27 degrees.
+        #[serde(default)]
+        angular_radius: f64,
+        /// The number of samples of the sun at each point, if it has an
+        /// angular radius.
+        #[serde(default = "default_area_light_samples")]
+        samples: u32,
+    },
+    SphereLight {
+        center: VectorDescription,
+        radius: f64,
+        radiance: ColorDescription,
+        /// The brightness in physical units, replacing that of `radiance`.
+        #[serde(default)]
+        brightness: Option<BrightnessDescription>,
+    },
+    PointLight {
+        position: VectorDescription,
+        color: ColorDescription,
+        #[serde(default = "default_intensity")]
+        intensity: f64,
+        /// The brightness in physical units, replacing `intensity` and the
+        /// brightness of `color`.
+        #[serde(default)]
+        brightness: Option<BrightnessDescription>,
+    },
+    /// A rectangle light, made by `AreaLight::new` and then transformed.
+    AreaLight {
+        width: f64,
+        height: f64,
+        radiance: ColorDescription,
+        #[serde(default)]
+        transform: Option<TransformDescription>,
+        /// The number of samples of the light at each point.
+        #[serde(default = "default_area_light_samples")]
+        samples: u32,
+        /// The brightness in physical units, replacing that of `radiance`.
+        #[serde(default)]
+        brightness: Option<BrightnessDescription>,
+    },
+    /// A light registered in the `Registry` under `name`.
+    Plugin {
+        name: String,
+        #[serde(default = "no_parameters")]
+        parameters: ron::Value,
+    },
+}
+
+/// The brightness of a light in physical units, as for `Brightness`.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub enum BrightnessDescription {
+    Lumens(f64),
+    Candela(f64),
+    Watts { watts: f64, efficacy: f64 },
+}
+
+impl BrightnessDescription {
+    fn build(self) -> Brightness {
+        match self {
+            BrightnessDescription::Lumens(lumens) => Brightness::Lumens(lumens),
+            BrightnessDescription::Candela(candela) => Brightness::Candela(candela),
+            BrightnessDescription::Watts { watts, efficacy } => {
+                Brightness::Watts { watts, efficacy }
+            }
+        }
+    }
+}
+
+fn default_area_light_samples() -> u32 {
+    16
+}
+
+/// An environment map, made by `EnvironmentMap::load`.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct EnvironmentDescription {
+    /// An equirectangular Radiance HDR image. A relative path is relative to
+    /// the directory of the scene file.
+    pub path: String,
+    #[serde(default = "default_intensity")]
+    pub intensity: f64,
+    /// The angle to turn the map around the z-axis, in degrees.
+    #[serde(default)]
+    pub rotation_angle: f64,
+    /// The number of samples of the map at each point.
+    #[serde(default = "default_area_light_samples")]
+    pub samples: u32,
+}
+
+/// The camera, at `eye`, looking at `target`, as with `Camera::look_at`.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CameraDescription {
+    pub eye: VectorDescription,
+    pub target: VectorDescription,
+    /// The direction that is up in the image. The z-axis by default.
+    #[serde(default = "default_up")]
+    pub up: VectorDescription,
+    /// In degrees. About 65 degrees if `None`.
+    #[serde(default)]
+    pub horizontal_fov: Option<f64>,
+    /// The width of the part of the scene seen by an orthographic camera, or
+    /// `None` for a perspective camera.
+    #[serde(default)]
+    pub view_width: Option<f64>,
+    /// The radius of the lens, for depth of field, or 0 for no lens.
+    #[serde(default)]
+    pub aperture_radius: f64,
+    /// How far in front of the camera things are in focus. The distance to
+    /// `target` if `None`.
+    #[serde(default)]
+    pub focus_distance: Option<f64>,
+}
+
+/// The settings of a real camera, as for `PhysicalCamera`. Settings that are
+/// left out are those of a full frame camera with a 50 mm lens at f/8,
+/// focused at 10 meters, at ISO 100 with a shutter time of 1/125 seconds.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PhysicalCameraDescription {
+    /// In millimeters.
+    pub focal_length: f64,
+    /// In millimeters.
+    pub sensor_width: f64,
+    pub f_number: f64,
+    /// In meters.
+    pub focus_distance: f64,
+    pub iso: f64,
+    /// In seconds.
+    pub shutter_time: f64,
+}
+
+impl Default for PhysicalCameraDescription {
+    fn default() -> Self {
+        let camera = PhysicalCamera::default();
+        Self {
+            focal_length: camera.focal_length,
+            sensor_width: camera.sensor_width,
+            f_number: camera.f_number,
+            focus_distance: camera.focus_distance,
+            iso: camera.iso,
+            shutter_time: camera.shutter_time,
+        }
+    }
+}
+
+/// Where the camera is in `frame` of an animation: at `eye`, looking at
+/// `target`, as with `Camera::look_at`.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CameraKeyframeDescription {
+    pub frame: f64,
+    pub eye: VectorDescription,
+    pub target: VectorDescription,
+    /// The direction that is up in the image. The z-axis by default.
+    #[serde(default = "default_up")]
+    pub up: VectorDescription,
+}
+
+fn default_up() -> VectorDescription {
+    (0.0, 0.0, 1.0)
+}
+
+/// A procedural sky, made by `PhysicalSky::new`.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SkyDescription {
+    /// The angle of the sun above the horizon, in degrees.
+    pub sun_elevation: f64,
+    /// The angle of the sun from the y-axis towards the x-axis, in degrees.
+    #[serde(default)]
+    pub sun_azimuth: f64,
+    #[serde(default = "default_turbidity")]
+    pub turbidity: f64,
+    #[serde(default = "default_intensity")]
+    pub intensity: f64,
+    /// The number of samples of the sky at each point.
+    #[serde(default = "default_area_light_samples")]
+    pub samples: u32,
+}
+
+fn default_intensity() -> f64 {
+    1.0
+}
+
+fn default_turbidity() -> f64 {
+    3.0
+}
+
+fn no_parameters() -> ron::Value {
+    ron::Value::Unit
+}
+
+fn first_version() -> u32 {
+    FIRST_VERSION
+}
+
+/// Only the version of a scene file, which is read before the rest of the file
+/// to find out how to read it.
+#[derive(Deserialize)]
+struct VersionDescription {
+    /// 0 if the file has no version.
+    #[serde(default)]
+    version: u32,
+}
+
+/// The format that a scene file is written in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SceneFormat {
+    Ron,
+    Json,
+}
+
+impl SceneFormat {
+    /// Find the format of the scene file `filename` from its extension. Files
+    /// whose names don't end in `.json` are read as RON.
+    pub fn from_filename(filename: &str) -> Self {
+        let extension = Path::new(filename).extension();
+        if extension.is_some_and(|extension| extension.eq_ignore_ascii_case("json")) {
+            SceneFormat::Json
+        } else {
+            SceneFormat::Ron
+        }
+    }
+}
+
+/// Parse the contents of a scene file, migrating it to the current version of
+/// the format if needed. Returns the description together with warnings about
+/// anything in the file that was ignored.
+pub fn parse_scene_description(
+    source: &str,
+) -> Result<(SceneDescription, Vec<String>), Box<dyn Error>> {
+    parse_scene_description_as(source, SceneFormat::Ron)
+}
+
+/// Like `parse_scene_description`, but for a scene file in `format`.
+pub fn parse_scene_description_as(
+    source: &str,
+    format: SceneFormat,
+) -> Result<(SceneDescription, Vec<String>), Box<dyn Error>> {
+    let mut warnings = Vec::new();
+
+    let version = match format {
+        SceneFormat::Ron => ron::from_str::<VersionDescription>(source)?,
+        SceneFormat::Json => serde_json::from_str::<VersionDescription>(source)?,
+    };
+    let version = match version.version {
+        0 => {
+            warnings.push(format!(
+                "The scene file has no version. Assuming version {}.",
+                FIRST_VERSION
+            ));
+            FIRST_VERSION
+        }
+        version => version,
+    };
+    if version > CURRENT_VERSION {
+        warnings.push(format!(
+            "The scene file is for version {} of the format, but only versions up to {} are \
+             known. Unknown fields are ignored.",
+            version, CURRENT_VERSION
+        ));
+    }
+
+    let mut ignore = |path: serde_ignored::Path| {
+        warnings.push(format!("Unknown field {} was ignored", path));
+    };
+    let mut description: SceneDescription = match format {
+        SceneFormat::Ron => {
+            let mut deserializer = ron::Deserializer::from_str(source)?;
+            let description = serde_ignored::deserialize(&mut deserializer, &mut ignore)?;
+            deserializer.end()?;
+            description
+        }
+        SceneFormat::Json => {
+            let mut deserializer = serde_json::Deserializer::from_str(source);
+            let description = serde_ignored::deserialize(&mut deserializer, &mut ignore)?;
+            deserializer.end()?;
+            description
+        }
+    };
+    migrate(&mut description, version, &MIGRATIONS, &mut warnings);
+
+    Ok((description, warnings))
+}
+
+/// Bring `description`, read from a file of `version`, up to the version after
+/// the last of `migrations`, which migrate from each version to the next,
+/// starting with the first version, by running those from `version` on in
+/// order. Descriptions of newer versions are only marked as the latest.
+fn migrate(
+    description: &mut SceneDescription,
+    version: u32,
+    migrations: &[Migration],
+    warnings: &mut Vec<String>,
+) {
+    let skipped = version.saturating_sub(FIRST_VERSION) as usize;
+    for migration in migrations.iter().skip(skipped) {
+        migration(description, warnings);
+    }
+    description.version = FIRST_VERSION + migrations.len() as u32;
+}
+
+/// Load the scene file `filename`, in the format given by its extension.
+/// Returns the scene together with warnings about anything in the file that
+/// was ignored.
+pub fn load_scene(filename: &str) -> Result<(Scene, Vec<String>), Box<dyn Error>> {
+    load_scene_with_plugins(filename, &Registry::new())
+}
+
+/// Like `load_scene`, but plugins in the file are made using `registry`.
+pub fn load_scene_with_plugins(
+    filename: &str,
+    registry: &Registry,
+) -> Result<(Scene, Vec<String>), Box<dyn Error>> {
+    let source = fs::read_to_string(filename)?;
+    let (description, mut warnings) =
+        parse_scene_description_as(&source, SceneFormat::from_filename(filename))?;
+    let base_dir = Path::new(filename)
+        .parent()
+        .unwrap_or_else(|| Path::new(""));
+
+    let scene = description.build(base_dir, registry)?;
+    if let Some(budget) = description.memory_budget {
+        warnings.extend(scene.memory_report().check_budget((budget * 1e6) as usize));
+    }
+    Ok((scene, warnings))
+}
+
+/// Rewrite the contents of a scene file in the current version of the format.
+/// Unknown fields are dropped. Returns the new contents together with
+/// warnings about anything that was dropped.
+pub fn upgrade_scene(source: &str) -> Result<(String, Vec<String>), Box<dyn Error>> {
+    let (mut description, warnings) = parse_scene_description(source)?;
+    description.version = CURRENT_VERSION;
+
+    let upgraded = ron::ser::to_string_pretty(&description, ron::ser::PrettyConfig::default())?;
+    Ok((upgraded, warnings))
+}
+
+impl SceneDescription {
+    /// Save the description as the scene file `filename`, in the format given
+    /// by its extension.
+    pub fn save(&self, filename: &str) -> Result<(), Box<dyn Error>> {
+        let source = match SceneFormat::from_filename(filename) {
+            SceneFormat::Ron => {
+                ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?
+            }
+            SceneFormat::Json => serde_json::to_string_pretty(self)?,
+        };
+        fs::write(filename, source)?;
+        Ok(())
+    }
+
+    /// Find the path that the camera follows in animations.
+    pub fn camera_path(&self) -> CameraPath {
+        self.camera_path
+            .iter()
+            .fold(CameraPath::new(), |path, keyframe| {
+                path.with_look_at(keyframe.frame, keyframe.eye, keyframe.target, keyframe.up)
+            })
+    }
+
+    /// Build the scene described. Relative paths to other files are relative
+    /// to `base_dir`, and plugins are made using `registry`.
+    pub fn build(&self, base_dir: &Path, registry: &Registry) -> Result<Scene, Box<dyn Error>> {
+        let mut scene = Scene::new();
+        let bvh_cache = self.bvh_cache.as_ref().map(|path| base_dir.join(path));
+        let textures = TextureCache::new();
+
+        scene.set_color_space(match self.color_space {
+            ColorSpaceDescription::Linear => ColorSpace::Linear,
+            ColorSpaceDescription::Srgb => ColorSpace::Srgb,
+        });
+        if let IntegratorDescription::IrradianceCaching {
+            samples, max_error, ..
+        } = self.integrator
+        {
+            if samples == 0 || max_error <= 0.0 || !max_error.is_finite() {
+                return Err(
+                    "Irradiance caching needs samples and a positive, finite max_error".into(),
+                );
+            }
+        }
+        scene.set_integrator(match self.integrator {
+            IntegratorDescription::DirectLighting => Integrator::DirectLighting,
+            IntegratorDescription::PathTracing {
+                max_depth,
+                samples_per_pixel,
+            } => Integrator::PathTracing {
+                max_depth,
+                samples_per_pixel,
+            },
+            IntegratorDescription::IrradianceCaching {
+                max_depth,
+                samples,
+                max_error,
+            } => Integrator::IrradianceCaching {
+                max_depth,
+                samples,
+                max_error,
+            },
+        });
+
+        scene.set_exposure(self.exposure.map(|ev100| Exposure { ev100 }));
+        if let Some(camera) = &self.camera {
+            let (eye, target) = (Vector3::from(camera.eye), Vector3::from(camera.target));
+            if (target - eye).norm2() == 0.0 {
+                return Err("The camera must look at something other than where it is".into());
+            }
+            let placed = Camera::look_at(eye, target, camera.up);
+            scene.set_camera(placed.position, placed.orientation);
+            if let Some(degrees) = camera.horizontal_fov {
+                scene.set_horizontal_fov(degrees);
+            }
+            if let Some(view_width) = camera.view_width {
+                scene.set_camera_projection(Projection::Orthographic { view_width });
+            }
+            if camera.aperture_radius > 0.0 {
+                let focus_distance = camera
+                    .focus_distance
+                    .unwrap_or_else(|| (target - eye).norm());
+                scene.set_depth_of_field(camera.aperture_radius, focus_distance);
+            }
+        }
+        if let Some(camera) = &self.physical_camera {
+            scene.set_physical_camera(&PhysicalCamera {
+                focal_length: camera.focal_length,
+                sensor_width: camera.sensor_width,
+                f_number: camera.f_number,
+                focus_distance: camera.focus_distance,
+                iso: camera.iso,
+                shutter_time: camera.shutter_time,
+            });
+        }
+        if let Some((position, orientation)) = self.camera_path().place_at(0.0) {
+            scene.set_camera(position, orientation);
+        }
+        scene.set_sampler(SamplerSettings {
+            pixel_samples: self.sampler.pixel_samples,
+            pattern: self.sampler.pattern,
+            blue_noise: self.sampler.blue_noise,
+            blue_noise_mask: match &self.sampler.blue_noise_mask {
+                Some(path) => Some(Arc::new(BlueNoiseMask::load(&base_dir.join(path))?)),
+                None => None,
+            },
+            seed: self.sampler.seed,
+            adaptive_passes: self.sampler.adaptive_passes,
+        });
+        scene.set_pixel_limits(PixelLimits {
+            max_rays: self.max_rays_per_pixel,
+            max_time: self
+                .max_seconds_per_pixel
+                .map(Duration::try_from_secs_f64)
+                .transpose()?,
+        });
+        let default_bias = RayBias::default();
+        scene.set_ray_bias(RayBias {
+            absolute: self.ray_bias.unwrap_or(default_bias.absolute),
+            relative: self.relative_ray_bias.unwrap_or(default_bias.relative),
+        });
+        scene.set_halt_conditions(HaltConditions {
+            noise_threshold: self.noise_threshold,
+            max_time: self
+                .max_render_seconds
+                .map(Duration::try_from_secs_f64)
+                .transpose()?,
+        });
+        scene.set_keep_awake(self.keep_awake);
+        scene.set_low_priority(self.low_priority);
+        scene.set_denoise(self.denoise);
+        scene.set_lens_flare(self.lens_flare.as_ref().map(LensFlareDescription::build));
+        scene.set_sensor(
+            self.sensor
+                .as_ref()
+                .map(SensorDescription::build)
+                .transpose()?,
+        );
+
+        for (name, material) in self.materials.iter() {
+            scene.set_material(name, material.build(base_dir, &textures, registry)?);
+        }
+
+        for surface in self.surfaces.iter() {
+            let shape = surface
+                .shape
+                .build(base_dir, bvh_cache.as_deref(), registry)?;
+            let id = match &surface.material_name {
+                None => scene.add_surface_with_material(
+                    shape,
+                    surface.material.build(base_dir, &textures, registry)?,
+                ),
+                Some(name) => scene.add_surface_with_named_material(shape, name)?,
+            };
+            scene.set_surface_priority(id, surface.priority);
+            scene.set_surface_holdout(id, surface.holdout);
+            if let Some(name) = &surface.name {
+                scene.set_surface_name(id, name);
+            }
+            for tag in &surface.tags {
+                scene.tag_surface(id, tag);
+            }
+        }
+
+        let mut light_ids = Vec::new();
+        for light in self.lights.iter() {
+            light_ids.push(scene.add_boxed_light(light.build(registry)?));
+        }
+        if let Some(environment) = &self.environment {
+            let rotation = UnitQuaternion::from_axis_angle(
+                (0.0, 0.0, 1.0),
+                environment.rotation_angle.to_radians(),
+            );
+            light_ids.push(
+                scene.set_environment(
+                    EnvironmentMap::load(&base_dir.join(&environment.path))?
+                        .with_intensity(environment.intensity)
+                        .with_rotation(rotation)
+                        .with_samples(environment.samples),
+                ),
+            );
+        }
+        if let Some(sky) = &self.sky {
+            if self.environment.is_some() {
+                return Err("A scene can't have both an environment map and a sky".into());
+            }
+            light_ids.push(
+                scene.set_environment(
+                    PhysicalSky::new(
+                        sky.sun_elevation.to_radians(),
+                        sky.sun_azimuth.to_radians(),
+                        sky.turbidity,
+                    )
+                    .with_intensity(sky.intensity)
+                    .with_samples(sky.samples),
+                ),
+            );
+        }
+
+        for (name, &index) in &self.light_names {
+            let id = light_ids.get(index).ok_or_else(|| {
+                format!("The light name {name} refers to a light that doesn't exist")
+            })?;
+            scene.set_light_name(*id, name);
+        }
+        if let Some(light_groups) = &self.light_groups {
+            let mut groups = BTreeMap::new();
+            for (name, lights) in light_groups {
+                let ids = lights
+                    .iter()
+                    .map(|&light| light_ids.get(light).copied())
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or("Light group refers to a light that doesn't exist")?;
+                groups.insert(name.clone(), ids);
+            }
+            scene.set_light_groups(Some(groups));
+        }
+
+        if self.caustic_photons > 0 {
+            scene.build_photon_map(self.caustic_photons);
+        }
+        if self.light_tree_samples > 0 {
+            scene.build_light_tree(self.light_tree_samples);
+        }
+
+        Ok(scene)
+    }
+}
+
+impl ShapeDescription {
+    /// Find the transform that places the shape in the scene, for finding how
+    /// it moved between frames of an animation. A sphere is a unit sphere
+    /// scaled by its radius and moved to its center. Shapes without a
+    /// transform are placed by the identity.
+    pub fn placement(&self) -> Result<Transform, Box<dyn Error>> {
+        Ok(match self {
+            ShapeDescription::Sphere { center, radius } => {
+                let scale = if *radius > 0.0 { *radius } else { 1.0 };
+                Transform::new((scale, scale, scale), UnitQuaternion::id(), *center)
+            }
+            ShapeDescription::Plane {
+                transform: Some(transform),
+                ..
+            }
+            | ShapeDescription::Rect {
+                transform: Some(transform),
+                ..
+            } => {
+                let (rotation, translation) = transform.build()?;
+                Transform::new((1.0, 1.0, 1.0), rotation, translation)
+            }
+            _ => Transform::identity(),
+        })
+    }
+
+    fn build(
+        &self,
+        base_dir: &Path,
+        bvh_cache: Option<&Path>,
+        registry: &Registry,
+    ) -> Result<Box<dyn Surface + Send + Sync>, Box<dyn Error>> {
+        Ok(match self {
+            ShapeDescription::Sphere { center, radius } => Box::new(Sphere::new(*center, *radius)),
+            ShapeDescription::Plane {
+                normal,
+                distance,
+                transform,
+            } => {
+                check_direction("Plane normal", *normal)?;
+                let plane = Plane::new(*normal, *distance);
+                Box::new(match transform {
+                    None => plane,
+                    Some(transform) => {
+                        let (rotation, translation) = transform.build()?;
+                        plane.with_transform(rotation, translation)
+                    }
+                })
+            }
+            ShapeDescription::Rect {
+                width,
+                height,
+                transform,
+            } => {
+                let rect = Rect::new(*width, *height);
+                Box::new(match transform {
+                    None => rect,
+                    Some(transform) => {
+                        let (rotation, translation) = transform.build()?;
+                        rect.with_transform(rotation, translation)
+                    }
+                })
+            }
+            ShapeDescription::Obj { path } => {
+                let path = base_dir.join(path);
+                let filename = path.to_str().ok_or("Invalid OBJ file path")?;
+                Box::new(match bvh_cache {
+                    None => obj::load_obj(filename)?,
+                    Some(cache_dir) => obj::load_obj_with_bvh_cache(filename, cache_dir)?,
+                })
+            }
+            ShapeDescription::Mesh {
+                positions,
+                uvs,
+                triangles,
+            } => {
+                let positions: Vec<Vector3> = positions.iter().map(|&p| p.into()).collect();
+                let uvs = if uvs.is_empty() {
+                    vec![(0.0, 0.0); positions.len()]
+                } else {
+                    uvs.clone()
+                };
+                Box::new(Mesh::try_with_texture_coordinates(
+                    positions,
+                    uvs,
+                    triangles.clone(),
+                )?)
+            }
+            ShapeDescription::Plugin { name, parameters } => {
+                registry.make_surface(name, parameters.clone())?
+            }
+        })
+    }
+}
+
+impl TransformDescription {
+    /// Describe the rotation `rotation` around the origin, followed by the
+    /// translation `translation`.
+    pub fn new<T: Into<Vector3>>(rotation: UnitQuaternion, translation: T) -> Self {
+        let (axis, angle) = rotation.axis_angle();
+        Self {
+            rotation_axis: axis.into(),
+            rotation_angle: angle.to_degrees(),
+            translation: translation.into().into(),
+        }
+    }
+
+    fn build(&self) -> Result<(UnitQuaternion, VectorDescription), Box<dyn Error>> {
+        check_direction("Rotation axis", self.rotation_axis)?;
+        let rotation =
+            UnitQuaternion::from_axis_angle(self.rotation_axis, self.rotation_angle.to_radians());
+        Ok((rotation, self.translation))
+    }
+}
+
+impl MaterialDescription {
+    fn build(
+        &self,
+        base_dir: &Path,
+        textures: &TextureCache,
+        registry: &Registry,
+    ) -> Result<Material, Box<dyn Error>> {
+        let material = Material::new(self.albedo.build(base_dir, textures, registry)?)
+            .with_roughness(self.roughness)
+            .with_anisotropy(self.anisotropy)
+            .with_metallic(self.metallic)
+            .with_transmission(self.transmission)
+            .with_ior(self.ior)
+            .with_shadow_catcher(self.shadow_catcher);
+        Ok(match &self.normal_map {
+            None => material,
+            Some(normal_map) => {
+                material.with_normal_map(normal_map.build(base_dir, textures, registry)?)
+            }
+        })
+    }
+}
+
+impl TextureDescription {
+    fn build(
+        &self,
+        base_dir: &Path,
+        textures: &TextureCache,
+        registry: &Registry,
+    ) -> Result<Box<dyn Texture + Send + Sync>, Box<dyn Error>> {
+        Ok(match self {
+            TextureDescription::Constant(color) => Box::new(Constant::new(*color)),
+            TextureDescription::Image { path } => {
+                Box::new(ImageTexture::load(&base_dir.join(path), textures)?)
+            }
+            TextureDescription::Checker {
+                even,
+                odd,
+                scale,
+                uv,
+            } => {
+                let mapping = if *uv {
+                    CheckerMapping::Uv
+                } else {
+                    CheckerMapping::Solid
+                };
+                Box::new(Checker::new(
+                    even.build(base_dir, textures, registry)?,
+                    odd.build(base_dir, textures, registry)?,
+                    *scale,
+                    mapping,
+                ))
+            }
+            TextureDescription::Marble {
+                base,
+                vein,
+                scale,
+                octaves,
+            } => Box::new(Marble::new(
+                base.build(base_dir, textures, registry)?,
+                vein.build(base_dir, textures, registry)?,
+                *scale,
+                *octaves,
+            )),
+            TextureDescription::Wood {
+                light,
+                dark,
+                scale,
+                octaves,
+            } => Box::new(Wood::new(
+                light.build(base_dir, textures, registry)?,
+                dark.build(base_dir, textures, registry)?,
+                *scale,
+                *octaves,
+            )),
+            TextureDescription::Turbulence {
+                low,
+                high,
+                scale,
+                octaves,
+            } => Box::new(Turbulence::new(
+                low.build(base_dir, textures, registry)?,
+                high.build(base_dir, textures, registry)?,
+                *scale,
+                *octaves,
+            )),
+            TextureDescription::Plugin { name, parameters } => {
+                registry.make_texture(name, parameters.clone())?
+            }
+        })
+    }
+}
+
+impl LightDescription {
+    fn build(&self, registry: &Registry) -> Result<Box<dyn Light + Send + Sync>, Box<dyn Error>> {
+        Ok(match self {
+            LightDescription::Sun {
+                color,
+                direction,
+                illuminance,
+                angular_radius,
+                samples,
+            } => {
+                check_direction("Sun direction", *direction)?;
+                let mut sun = Sun::new(*color, *direction)
+                    .with_angular_radius(angular_radius.to_radians())
+                    .with_samples(*samples);
+                if let Some(illuminance) = illuminance {
+                    sun = sun.with_illuminance(*illuminance);
+                }
+                Box::new(sun)
+            }
+            LightDescription::SphereLight {
+                center,
+                radius,
+                radiance,
+                brightness,
+            } => {
+                let light = SphereLight::new(*center, *radius, *radiance);
+                Box::new(match brightness {
+                    Some(brightness) => light.with_brightness(brightness.build()),
+                    None => light,
+                })
+            }
+            LightDescription::PointLight {
+                position,
+                color,
+                intensity,
+                brightness,
+            } => {
+                let light = PointLight::new(*position, *color, *intensity);
+                Box::new(match brightness {
+                    Some(brightness) => light.with_brightness(brightness.build()),
+                    None => light,
+                })
+            }
+            LightDescription::AreaLight {
+                width,
+                height,
+                radiance,
+                transform,
+                samples,
+                brightness,
+            } => {
+                let mut light = AreaLight::new(*width, *height, *radiance).with_samples(*samples);
+                if let Some(brightness) = brightness {
+                    light = light.with_brightness(brightness.build());
+                }
+                Box::new(match transform {
+                    None => light,
+                    Some(transform) => {
+                        let (rotation, translation) = transform.build()?;
+                        light.with_transform(rotation, translation)
+                    }
+                })
+            }
+            LightDescription::Plugin { name, parameters } => {
+                registry.make_light(name, parameters.clone())?
+            }
+        })
+    }
+}
+
+/// Check that the direction called `name` is finite and non-zero, since it is
+/// normalized.
+fn check_direction(name: &str, direction: VectorDescription) -> Result<(), Box<dyn Error>> {
+    let (x, y, z) = direction;
+    if !(x.is_finite() && y.is_finite() && z.is_finite()) || (x, y, z) == (0.0, 0.0, 0.0) {
+        return Err(format!("{} must be finite and non-zero", name).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::Image;
+    use crate::materials::MaterialLibrary;
+
+    const SCENE: &str = "(
+        version: 1,
+        surfaces: [
+            (shape: Sphere(center: (0.0, 2.0, 0.0), radius: 0.5)),
+            (
+                shape: Plane(normal: (0.0, 0.0, 1.0), distance: -0.5),
+                material: (albedo: Checker(
+                    even: Constant((0.9, 0.9, 0.9)),
+                    odd: Constant((0.1, 0.1, 0.1)),
+                    scale: 0.3,
+                )),
+            ),
+        ],
+        lights: [Sun(color: (1.0, 1.0, 1.0), direction: (1.0, 1.0, -1.0))],
+    )";
+
+    #[test]
+    fn parse_scene() {
+        let (description, warnings) = parse_scene_description(SCENE).unwrap();
+        assert!(warnings.is_empty());
+        assert_eq!(description.surfaces.len(), 2);
+        assert!(description.build(Path::new(""), &Registry::new()).is_ok());
+    }
+
+    #[test]
+    fn unknown_fields_give_warnings() {
+        let source = SCENE.replace("radius: 0.5", "radius: 0.5, shininess: 3.0");
+        let source = source.replace("version: 1", "version: 2");
+        let (description, warnings) = parse_scene_description(&source).unwrap();
+        assert_eq!(warnings.len(), 2);
+        assert_eq!(description.version, CURRENT_VERSION);
+    }
+
+    #[test]
+    fn irradiance_caching_needs_samples_and_a_positive_error() {
+        let build = |integrator: &str| {
+            let source = SCENE.replace("version: 1,", &format!("version: 1, {}", integrator));
+            let (description, _) = parse_scene_description(&source).unwrap();
+            description
+                .build(Path::new(""), &Registry::new())
+                .map(|_| ())
+        };
+        assert!(
+            build("integrator: IrradianceCaching(max_depth: 2, samples: 8, max_error: 0.2),")
+                .is_ok()
+        );
+        assert!(
+            build("integrator: IrradianceCaching(max_depth: 2, samples: 0, max_error: 0.2),")
+                .is_err()
+        );
+        assert!(
+            build("integrator: IrradianceCaching(max_depth: 2, samples: 8, max_error: 0.0),")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn unversioned_scenes_are_the_first_version() {
+        let source = SCENE.replace("version: 1,", "");
+        let (description, warnings) = parse_scene_description(&source).unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("version 1"));
+        assert_eq!(description.version, CURRENT_VERSION);
+        assert_eq!(description.surfaces.len(), 2);
+        assert!(description.build(Path::new(""), &Registry::new()).is_ok());
+    }
+
+    #[test]
+    fn migrations_run_in_order_from_the_version_of_the_file() {
+        let migrations: [Migration; 3] = [
+            |_, warnings| warnings.push("1 to 2".to_string()),
+            |_, warnings| warnings.push("2 to 3".to_string()),
+            |_, warnings| warnings.push("3 to 4".to_string()),
+        ];
+        let mut description = SceneDescription::default();
+        let mut warnings = Vec::new();
+        migrate(&mut description, 2, &migrations, &mut warnings);
+        assert_eq!(warnings, ["2 to 3", "3 to 4"]);
+        assert_eq!(description.version, 4);
+
+        let mut warnings = Vec::new();
+        migrate(&mut description, 1, &migrations, &mut warnings);
+        assert_eq!(warnings, ["1 to 2", "2 to 3", "3 to 4"]);
+        let mut warnings = Vec::new();
+        migrate(&mut description, 5, &migrations, &mut warnings);
+        assert!(warnings.is_empty());
+        assert_eq!(description.version, 4);
+    }
+
+    #[test]
+    fn upgraded_scene_can_be_parsed() {
+        let source = SCENE.replace("version: 1,", "");
+        let (upgraded, warnings) = upgrade_scene(&source).unwrap();
+        assert_eq!(warnings.len(), 1);
+
+        let (description, warnings) = parse_scene_description(&upgraded).unwrap();
+        assert!(warnings.is_empty());
+        assert_eq!(description.version, CURRENT_VERSION);
+        assert_eq!(description.lights.len(), 1);
+    }
+
+    #[test]
+    fn scenes_are_read_from_json_files() {
+        let source = r#"{
+            "version": 1,
+            "camera": {"eye": [0.0, -3.0, 1.0], "target": [0.0, 0.0, 1.0], "horizontal_fov": 40.0},
+            "surfaces": [{"shape": {"Sphere": {"center": [0.0, 2.0, 0.0], "radius": 0.5}}}],
+            "lights": [{"Sun": {"color": [1.0, 1.0, 1.0], "direction": [1.0, 1.0, -1.0]}}],
+            "shininess": 3.0
+        }"#;
+        let filename = std::env::temp_dir().join(format!("rustbeam-{}.json", std::process::id()));
+        fs::write(&filename, source).unwrap();
+        let filename = filename.to_str().unwrap();
+        assert_eq!(SceneFormat::from_filename(filename), SceneFormat::Json);
+
+        let (_, warnings) = load_scene(filename).unwrap();
+        let scene = Scene::from_file(filename).unwrap();
+        fs::remove_file(filename).unwrap();
+        assert_eq!(warnings.len(), 1);
+        let camera = scene.camera();
+        assert!((camera.position - Vector3::from((0.0, -3.0, 1.0))).norm() < 1e-12);
+        assert!((camera.horizontal_fov() - 40.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn saved_scenes_can_be_loaded() {
+        let source = SCENE.replace(
+            "version: 1,",
+            "version: 1, camera: Some((eye: (0.0, -4.0, 0.0), target: (0.0, 0.0, 0.0))),",
+        );
+        let (description, _) = parse_scene_description(&source).unwrap();
+        for extension in ["ron", "json"] {
+            let filename = std::env::temp_dir()
+                .join(format!("rustbeam-saved-{}.{extension}", std::process::id()));
+            let filename = filename.to_str().unwrap();
+            description.save(filename).unwrap();
+            let (scene, warnings) = load_scene(filename).unwrap();
+            fs::remove_file(filename).unwrap();
+            assert!(warnings.is_empty());
+            assert!((scene.camera().position - Vector3::from((0.0, -4.0, 0.0))).norm() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn cameras_can_be_described() {
+        let source = SCENE.replace(
+            "version: 1,",
+            "version: 1, camera: Some((
+                eye: (0.0, -4.0, 0.0),
+                target: (0.0, 0.0, 0.0),
+                view_width: Some(3.0),
+                aperture_radius: 0.1,
+            )),",
+        );
+        let (description, warnings) = parse_scene_description(&source).unwrap();
+        assert!(warnings.is_empty());
+        let scene = description.build(Path::new(""), &Registry::new()).unwrap();
+
+        let camera = scene.camera();
+        assert!((camera.direction() - Vector3::j()).norm() < 1e-12);
+        assert_eq!(
+            camera.projection,
+            Projection::Orthographic { view_width: 3.0 }
+        );
+        assert_eq!(camera.focus_distance, 4.0);
+
+        let source = source.replace("target: (0.0, 0.0, 0.0)", "target: (0.0, -4.0, 0.0)");
+        let (description, _) = parse_scene_description(&source).unwrap();
+        assert!(description.build(Path::new(""), &Registry::new()).is_err());
+    }
+
+    #[test]
+    fn physical_cameras_fill_in_left_out_settings() {
+        let source = SCENE.replace(
+            "version: 1,",
+            "version: 1, physical_camera: Some((focal_length: 24.0, f_number: 4.0)),",
+        );
+        let (description, warnings) = parse_scene_description(&source).unwrap();
+        assert!(warnings.is_empty());
+        let scene = description.build(Path::new(""), &Registry::new()).unwrap();
+
+        let camera = scene.camera();
+        assert!((camera.horizontal_fov() - 73.74).abs() < 0.01);
+        assert!((camera.aperture_radius - 0.003).abs() < 1e-12);
+        assert_eq!(camera.focus_distance, 10.0);
+    }
+
+    #[test]
+    fn camera_paths_place_the_camera_in_the_first_frame() {
+        let source = SCENE.replace(
+            "version: 1,",
+            "version: 1, camera_path: [
+                (frame: 0.0, eye: (0.0, -3.0, 1.0), target: (0.0, 0.0, 1.0)),
+                (frame: 24.0, eye: (3.0, 0.0, 1.0), target: (0.0, 0.0, 1.0)),
+            ],",
+        );
+        let (description, warnings) = parse_scene_description(&source).unwrap();
+        assert!(warnings.is_empty());
+        assert_eq!(description.camera_path().keyframes().len(), 2);
+        let scene = description.build(Path::new(""), &Registry::new()).unwrap();
+
+        let camera = scene.camera();
+        assert!((camera.position - Vector3::from((0.0, -3.0, 1.0))).norm() < 1e-12);
+        assert!((camera.direction() - Vector3::j()).norm() < 1e-12);
+    }
+
+    #[test]
+    fn content_hash_depends_on_content_only() {
+        let hash = |source: &str| {
+            let (description, _) = parse_scene_description(source).unwrap();
+            let scene = description.build(Path::new(""), &Registry::new()).unwrap();
+            scene.content_hash().unwrap()
+        };
+
+        let reformatted = SCENE.replace("radius: 0.5", "radius:0.50");
+        assert_eq!(hash(SCENE), hash(&reformatted));
+        let moved = SCENE.replace("radius: 0.5", "radius: 0.6");
+        assert_ne!(hash(SCENE), hash(&moved));
+    }
+
+    #[test]
+    fn plugins_are_made_from_the_registry() {
+        #[derive(serde::Deserialize)]
+        struct BallParameters {
+            radius: f64,
+        }
+
+        let mut registry = Registry::new();
+        registry.register_surface("ball", |parameters: BallParameters| {
+            Ok(Sphere::new((0.0, 3.0, 0.0), parameters.radius))
+        });
+
+        let source = SCENE.replace(
+            "Sphere(center: (0.0, 2.0, 0.0), radius: 0.5)",
+            "Plugin(name: \"ball\", parameters: (radius: 0.5))",
+        );
+        let (description, warnings) = parse_scene_description(&source).unwrap();
+        assert!(warnings.is_empty());
+        assert!(description.build(Path::new(""), &registry).is_ok());
+        assert!(description.build(Path::new(""), &Registry::new()).is_err());
+    }
+
+    #[test]
+    fn transformed_shapes_are_built() {
+        let source = SCENE.replace(
+            "Sphere(center: (0.0, 2.0, 0.0), radius: 0.5)",
+            "Rect(width: 2.0, height: 1.0, transform: Some((rotation_angle: 90.0)))",
+        );
+        let (description, warnings) = parse_scene_description(&source).unwrap();
+        assert!(warnings.is_empty());
+        assert!(description.build(Path::new(""), &Registry::new()).is_ok());
+
+        let zero_axis = source.replace(
+            "rotation_angle",
+            "rotation_axis: (0.0, 0.0, 0.0), rotation_angle",
+        );
+        let (description, _) = parse_scene_description(&zero_axis).unwrap();
+        assert!(description.build(Path::new(""), &Registry::new()).is_err());
+    }
+
+    #[test]
+    fn surfaces_share_named_materials() {
+        let source = SCENE
+            .replace(
+                "surfaces: [",
+                "materials: {\"red\": (albedo: Constant((0.8, 0.1, 0.1)))}, surfaces: [",
+            )
+            .replace("radius: 0.5)", "radius: 0.5), material_name: Some(\"red\")");
+        let (description, warnings) = parse_scene_description(&source).unwrap();
+        assert!(warnings.is_empty());
+        let mut scene = description.build(Path::new(""), &Registry::new()).unwrap();
+        let hash = scene.content_hash();
+
+        // Replacing the material changes the surfaces made of it.
+        let mut library = MaterialLibrary::new();
+        library.insert("red", Material::new(Constant::new((0.9, 0.1, 0.1))));
+        scene.set_materials(library);
+        assert_ne!(scene.content_hash(), hash);
+        scene.set_material("red", Material::new(Constant::new((0.8, 0.1, 0.1))));
+        assert_eq!(scene.content_hash(), hash);
+
+        let unknown = source.replace("Some(\"red\")", "Some(\"blue\")");
+        let (description, _) = parse_scene_description(&unknown).unwrap();
+        assert!(description.build(Path::new(""), &Registry::new()).is_err());
+    }
+
+    #[test]
+    fn surfaces_and_lights_are_named() {
+        let source = SCENE
+            .replace(
+                "radius: 0.5)",
+                "radius: 0.5), name: Some(\"ball\"), tags: [\"props\"]",
+            )
+            .replace("lights: [", "light_names: {\"sun\": 0}, lights: [");
+        let (description, warnings) = parse_scene_description(&source).unwrap();
+        assert!(warnings.is_empty());
+        let scene = description.build(Path::new(""), &Registry::new()).unwrap();
+        let ball = scene.find_surface("ball").unwrap();
+        assert_eq!(scene.surface_index(ball), Some(0));
+        assert_eq!(scene.surfaces_tagged("props"), [ball]);
+        let sun = scene.find_light("sun").unwrap();
+        assert_eq!(scene.light_index(sun), Some(0));
+
+        let missing = source.replace("\"sun\": 0", "\"sun\": 1");
+        let (description, _) = parse_scene_description(&missing).unwrap();
+        assert!(description.build(Path::new(""), &Registry::new()).is_err());
+    }
+
+    #[test]
+    fn scenes_built_in_code_are_described() {
+        let source = SCENE
+            .replace(
+                "version: 1,",
+                "version: 1,
+                camera: Some((eye: (1.0, -4.0, 1.0), target: (0.0, 0.0, 0.5))),
+                materials: {\"stone\": (albedo: Marble(
+                    base: Constant((0.9, 0.9, 0.8)),
+                    vein: Constant((0.2, 0.2, 0.3)),
+                    scale: 2.0,
+                    octaves: 4,
+                ))},",
+            )
+            .replace(
+                "radius: 0.5)",
+                "radius: 0.5), material_name: Some(\"stone\"), name: Some(\"ball\")",
+            )
+            .replace(
+                "surfaces: [",
+                "surfaces: [
+                    (shape: Rect(width: 2.0, height: 1.0, transform: Some((
+                        rotation_axis: (1.0, 0.0, 0.0),
+                        rotation_angle: 30.0,
+                        translation: (0.0, 1.0, 0.0),
+                    )))),
+                    (shape: Mesh(
+                        positions: [(0.0, 0.0, 0.0), (1.0, 0.0, 0.0), (0.0, 1.0, 0.0)],
+                        triangles: [(0, 1, 2)],
+                    )),",
+            )
+            .replace(
+                "lights: [",
+                "light_names: {\"lamp\": 1}, lights: [
+                    PointLight(position: (0.0, 0.0, 2.0), color: (1.0, 0.8, 0.6)),
+                    SphereLight(center: (1.0, 1.0, 2.0), radius: 0.2, radiance: (2.0, 2.0, 2.0)),
+                    AreaLight(width: 1.0, height: 1.0, radiance: (3.0, 3.0, 3.0), samples: 4),",
+            );
+        let (description, warnings) = parse_scene_description(&source).unwrap();
+        assert!(warnings.is_empty());
+        let scene = description.build(Path::new(""), &Registry::new()).unwrap();
+
+        let described = scene.to_description().unwrap();
+        assert_eq!(described.version, CURRENT_VERSION);
+        let source = ron::to_string(&described).unwrap();
+        let (description, warnings) = parse_scene_description(&source).unwrap();
+        assert!(warnings.is_empty());
+        let rebuilt = description.build(Path::new(""), &Registry::new()).unwrap();
+        // Rotations are rebuilt from axes and angles, so compare what the
+        // camera sees rather than the exact numbers.
+        let close = |a: Image, b: Image| {
+            let (a, b) = (a.rgb_f32(), b.rgb_f32());
+            a.len() == b.len() && a.iter().zip(&b).all(|(a, b)| (a - b).abs() < 1e-4)
+        };
+        assert!(close(
+            rebuilt.render_albedo(32, 18),
+            scene.render_albedo(32, 18)
+        ));
+        assert!(close(
+            rebuilt.render_normals(32, 18),
+            scene.render_normals(32, 18)
+        ));
+        let redescribed = rebuilt.to_description().unwrap();
+        assert_eq!(redescribed.lights.len(), described.lights.len());
+        assert_eq!(
+            rebuilt
+                .find_surface("ball")
+                .map(|id| rebuilt.surface_index(id)),
+            Some(Some(2))
+        );
+        assert_eq!(
+            rebuilt.find_light("lamp").map(|id| rebuilt.light_index(id)),
+            Some(Some(1))
+        );
+    }
+
+    #[test]
+    fn plugins_are_not_described() {
+        struct Ball(Sphere);
+
+        impl Surface for Ball {
+            fn closest_intersection(
+                &self,
+                ray: &crate::math::Ray,
+            ) -> Option<crate::surfaces::Intersection> {
+                self.0.closest_intersection(ray)
+            }
+        }
+
+        let mut scene = Scene::new();
+        scene.add_surface(Sphere::new((0.0, 2.0, 0.0), 0.5));
+        assert!(scene.to_description().is_ok());
+        scene.add_surface(Ball(Sphere::new((0.0, 3.0, 0.0), 0.5)));
+        assert!(scene.to_description().is_err());
+    }
+
+    #[test]
+    fn default_descriptions_are_the_current_version() {
+        let description = SceneDescription::default();
+        assert_eq!(description.version, CURRENT_VERSION);
+        let source = ron::to_string(&description).unwrap();
+        let (_, warnings) = parse_scene_description(&source).unwrap();
+        assert!(warnings.is_empty());
+    }
+}
@@ -0,0 +1,180 @@
+//! Module for animating scenes with Rhai scripts.
+//!
+//! An animation is a scene file together with a script, which changes the
+//! scene for each frame. The script defines a function `frame`, which is
+//! called with the frame number and the description of the scene, as read
+//! from the scene file, and returns the description of the scene in that
+//! frame:
+//!
+//! ```rhai
+//! fn frame(n, scene) {
+//!     // Move the first sphere upwards and make it shinier.
+//!     scene.surfaces[0].shape.Sphere.center[2] = 0.05 * n;
+//!     scene.surfaces[0].material.metallic = 0.02 * n;
+//!     scene
+//! }
+//! ```
+//!
+//! The description has the same structure as the scene file. Enum variants,
+//! like the kind of shape above, are maps with the name of the variant as the
+//! only key, and tuples are arrays. Numbers that are decimal in the scene
+//! file must be set to decimal numbers, e.g. `1.0` instead of `1`.
+//!
+//! Only available with the `scripting` feature.
+
+use crate::plugins::Registry;
+use crate::scene::Scene;
+use crate::scene_file::{parse_scene_description, SceneDescription};
+use rhai::{Dynamic, Engine, Scope, AST, INT};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A scene that changes from frame to frame, as determined by a script.
+pub struct Animation {
+    engine: Engine,
+    script: AST,
+    /// The scene as described by the scene file, before the script is run.
+    description: SceneDescription,
+    /// The directory that relative paths in the scene file are relative to.
+    base_dir: PathBuf,
+}
+
+impl Animation {
+    /// Make an animation of the scene `description`, changed by the Rhai
+    /// `script`. Relative paths in the description are relative to
+    /// `base_dir`.
+    pub fn new(
+        description: SceneDescription,
+        script: &str,
+        base_dir: &Path,
+    ) -> Result<Self, Box<dyn Error>> {
+        let engine = Engine::new();
+        let script = engine.compile(script)?;
+
+        Ok(Self {
+            engine,
+            script,
+            description,
+            base_dir: base_dir.to_path_buf(),
+        })
+    }
+
+    /// Load an animation from the scene file `scene_filename` and the script
+    /// file `script_filename`. Returns the animation together with warnings
+    /// about anything in the scene file that was ignored.
+    pub fn load(
+        scene_filename: &str,
+        script_filename: &str,
+    ) -> Result<(Self, Vec<String>), Box<dyn Error>> {
+        let (description, warnings) =
+            parse_scene_description(&fs::read_to_string(scene_filename)?)?;
+        let script = fs::read_to_string(script_filename)?;
+        let base_dir = Path::new(scene_filename)
+            .parent()
+            .unwrap_or_else(|| Path::new(""));
+
+        Ok((Self::new(description, &script, base_dir)?, warnings))
+    }
+
+    /// Run the script to find the description of the scene in `frame`.
+    pub fn description_at(&self, frame: u32) -> Result<SceneDescription, Box<dyn Error>> {
+        let description = rhai::serde::to_dynamic(&self.description)?;
+        let description: Dynamic = self.engine.call_fn(
+            &mut Scope::new(),
+            &self.script,
+            "frame",
+            (INT::from(frame), description),
+        )?;
+
+        Ok(rhai::serde::from_dynamic(&description)?)
+    }
+
+    /// Build the scene in `frame`, with the camera where the camera path of
+    /// the scene has it. Plugins in the scene are made using `registry`.
+    pub fn scene_at(&self, frame: u32, registry: &Registry) -> Result<Scene, Box<dyn Error>> {
+        let description = self.description_at(frame)?;
+        let mut scene = description.build(&self.base_dir, registry)?;
+        scene.set_camera_path_frame(&description.camera_path(), frame);
+        Ok(scene)
+    }
+
+    /// Like `scene_at`, but each surface is also told how it moved since the
+    /// previous frame, as found from `ShapeDescription::placement`, and the
+    /// camera where it was on the camera path, for
+    /// `Scene::render_motion_vectors`. Nothing moves in the first frame, and
+    /// surfaces don't move if the script changes the number of surfaces.
+    pub fn scene_with_motion_at(
+        &self,
+        frame: u32,
+        registry: &Registry,
+    ) -> Result<Scene, Box<dyn Error>> {
+        let description = self.description_at(frame)?;
+        let mut scene = description.build(&self.base_dir, registry)?;
+        scene.set_camera_path_frame(&description.camera_path(), frame);
+        if frame == 0 {
+            return Ok(scene);
+        }
+
+        let previous = self.description_at(frame - 1)?;
+        if previous.surfaces.len() == description.surfaces.len() {
+            for ((previous, current), id) in previous
+                .surfaces
+                .iter()
+                .zip(&description.surfaces)
+                .zip(scene.surface_ids())
+            {
+                scene.set_surface_motion(
+                    id,
+                    previous.shape.placement()?,
+                    current.shape.placement()?,
+                );
+            }
+        }
+        Ok(scene)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene_file::ShapeDescription;
+
+    const SCENE: &str = "(
+        version: 1,
+        surfaces: [
+            (shape: Sphere(center: (0.0, 2.0, 0.0), radius: 0.5)),
+            (shape: Plane(normal: (0.0, 0.0, 1.0), distance: -0.5)),
+        ],
+        lights: [Sun(color: (1.0, 1.0, 1.0), direction: (1.0, 1.0, -1.0))],
+    )";
+
+    const SCRIPT: &str = "
+        fn frame(n, scene) {
+            scene.surfaces[0].shape.Sphere.center[2] = 0.05 * n;
+            scene
+        }
+    ";
+
+    #[test]
+    fn scripts_change_the_scene_from_frame_to_frame() {
+        let (description, warnings) = parse_scene_description(SCENE).unwrap();
+        assert!(warnings.is_empty());
+        let animation = Animation::new(description, SCRIPT, Path::new("")).unwrap();
+
+        let center = |frame| match animation.description_at(frame).unwrap().surfaces[0].shape {
+            ShapeDescription::Sphere { center, .. } => center,
+            _ => panic!("The first surface is not a sphere"),
+        };
+        assert_eq!(center(0), (0.0, 2.0, 0.0));
+        assert_eq!(center(1), (0.0, 2.0, 0.05));
+
+        let registry = Registry::new();
+        let first = animation.scene_at(0, &registry).unwrap();
+        let second = animation.scene_at(1, &registry).unwrap();
+        let changes = second.diff(&first);
+        assert_eq!(changes.surfaces, vec![second.surface_ids()[0]]);
+        assert!(changes.materials.is_empty());
+        assert!(changes.lights.is_empty());
+    }
+}
@@ -0,0 +1,239 @@
+//! Module simulating the sensor of a digital camera, for raw images.
+//!
+//! A camera sensor sees a single color at each pixel, through a Bayer mosaic
+//! of red, green and blue filters, and counts the electrons freed by the light
+//! there. The count is noisy: light arrives as photons, which gives shot noise
+//! that grows with the square root of the signal, and reading the count out
+//! adds read noise of a fixed size. The count is then turned into a number of
+//! a fixed bit depth, above a black level.
+//!
+//! Simulating this for renders gives raw images with a known ground truth,
+//! for research on demosaicing and image signal processing.
+
+use crate::image::Image;
+use crate::math::sampling::Rng;
+use std::error::Error;
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io::BufWriter;
+
+/// The order of the color filters in the 2 x 2 squares of a Bayer mosaic,
+/// reading the top row and then the bottom row.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum BayerPattern {
+    #[default]
+    Rggb,
+    Bggr,
+    Grbg,
+    Gbrg,
+}
+
+impl BayerPattern {
+    /// Find the color channel (0, 1 or 2 for red, green or blue) seen by
+    /// pixel (`x`, `y`).
+    pub fn channel(self, x: usize, y: usize) -> usize {
+        let order = match self {
+            BayerPattern::Rggb => [0, 1, 1, 2],
+            BayerPattern::Bggr => [2, 1, 1, 0],
+            BayerPattern::Grbg => [1, 0, 2, 1],
+            BayerPattern::Gbrg => [1, 2, 0, 1],
+        };
+        order[2 * (y % 2) + x % 2]
+    }
+}
+
+/// A simulated camera sensor, turning linear renders into raw images.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sensor {
+    pub pattern: BayerPattern,
+    /// The number of electrons that fills a pixel, which a pixel value of 1
+    /// gives. Fewer electrons give more shot noise relative to the signal.
+    pub full_well: f64,
+    /// The standard deviation of the read noise, in electrons.
+    pub read_noise: f64,
+    /// The number of bits of the raw values, from 1 to 16.
+    pub bit_depth: u8,
+    /// The raw value of a pixel without light. Noise can take values below it.
+    pub black_level: u16,
+    /// Chooses the noise, so that captures can be repeated.
+    pub seed: u64,
+}
+
+impl Default for Sensor {
+    /// A 12-bit sensor with an RGGB mosaic, like that of many cameras.
+    fn default() -> Self {
+        Self {
+            pattern: BayerPattern::Rggb,
+            full_well: 10_000.0,
+            read_noise: 3.0,
+            bit_depth: 12,
+            black_level: 256,
+            seed: 0,
+        }
+    }
+}
+
+impl Sensor {
+    /// Capture the linear, unclamped render `image` with the sensor. Values
+    /// above 1 are clipped, like light beyond a full well.
+    pub fn capture(&self, image: &Image) -> RawImage {
+        let (width, height) = image.get_size();
+        let bit_depth = self.bit_depth.clamp(1, 16);
+        let max_value = ((1_u32 << bit_depth) - 1) as f64;
+        let black_level = f64::from(self.black_level).min(max_value);
+        // The raw values per electron, with a full well at the largest value.
+        let gain = (max_value - black_level) / self.full_well.max(1.0);
+
+        let rgb = image.rgb_f32();
+        let mut rng = Rng::new(self.seed);
+        let values = (0..width * height)
+            .map(|offset| {
+                let channel = self.pattern.channel(offset % width, offset / width);
+                let signal = f64::from(rgb[3 * offset + channel]).clamp(0.0, 1.0);
+                let electrons =
+                    poisson(&mut rng, signal * self.full_well) + self.read_noise * normal(&mut rng);
+                (black_level + gain * electrons)
+                    .round()
+                    .clamp(0.0, max_value) as u16
+            })
+            .collect();
+
+        RawImage {
+            width,
+            height,
+            bit_depth,
+            pattern: self.pattern,
+            values,
+        }
+    }
+}
+
+/// The output of a `Sensor`: one value per pixel, for the color of the filter
+/// over it.
+pub struct RawImage {
+    width: usize,
+    height: usize,
+    bit_depth: u8,
+    pattern: BayerPattern,
+    values: Vec<u16>,
+}
+
+impl RawImage {
+    pub fn get_size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    pub fn bit_depth(&self) -> u8 {
+        self.bit_depth
+    }
+
+    pub fn pattern(&self) -> BayerPattern {
+        self.pattern
+    }
+
+    /// Get the raw value of pixel (`x`, `y`).
+    pub fn value(&self, x: usize, y: usize) -> u16 {
+        self.values[y * self.width + x]
+    }
+
+    /// Save the raw values as a 16-bit grayscale PNG file, unscaled, so that
+    /// a 12-bit sensor only uses values up to 4095.
+    pub fn save_png(&self, filename: &str) -> Result<(), Box<dyn Error>> {
+        let mut png_encoder = png::Encoder::new(
+            BufWriter::new(File::create(filename)?),
+            self.width as u32,
+            self.height as u32,
+        );
+        png_encoder.set_color(png::ColorType::Grayscale);
+        png_encoder.set_depth(png::BitDepth::Sixteen);
+        let mut png_writer = png_encoder.write_header()?;
+        // PNG stores 16-bit values big-endian.
+        let data: Vec<u8> = self
+            .values
+            .iter()
+            .flat_map(|value| value.to_be_bytes())
+            .collect();
+        png_writer.write_image_data(&data)?;
+
+        Ok(())
+    }
+}
+
+/// Draw a number from a normal distribution with mean 0 and standard
+/// deviation 1, with the Box-Muller transform.
+fn normal(rng: &mut Rng) -> f64 {
+    let (u, v) = rng.next_pair();
+    (-2.0 * (1.0 - u).ln()).sqrt() * (2.0 * PI * v).cos()
+}
+
+/// Draw a number from a Poisson distribution with mean `mean`. Large means
+/// are approximated by a normal distribution.
+fn poisson(rng: &mut Rng, mean: f64) -> f64 {
+    if mean > 30.0 {
+        return (mean + mean.sqrt() * normal(rng)).max(0.0);
+    }
+    // Count uniform numbers until their product drops below e^-mean.
+    let limit = (-mean).exp();
+    let mut count = 0.0;
+    let mut product = rng.next_f64();
+    while product > limit {
+        count += 1.0;
+        product *= rng.next_f64();
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capture_follows_the_mosaic_and_noise_model() {
+        let (width, height) = (64, 64);
+        let mut image = Image::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                image.set_pixel(x, y, (0.6, 0.3, 2.0));
+            }
+        }
+
+        // Without read noise and with a huge full well, the values are exact.
+        let clean = Sensor {
+            full_well: 1e12,
+            read_noise: 0.0,
+            ..Sensor::default()
+        }
+        .capture(&image);
+        assert_eq!(clean.get_size(), (width, height));
+        assert_eq!(clean.pattern().channel(1, 0), 1);
+        assert_eq!(clean.value(0, 0), 256 + 2303);
+        assert_eq!(clean.value(1, 0), 256 + 1152);
+        assert_eq!(clean.value(0, 1), 256 + 1152);
+        assert_eq!(clean.value(1, 1), 4095);
+
+        // Shot noise on a green signal of 120 electrons has a standard
+        // deviation of about 11 electrons.
+        let noisy = Sensor {
+            full_well: 400.0,
+            read_noise: 0.0,
+            black_level: 0,
+            bit_depth: 16,
+            ..Sensor::default()
+        }
+        .capture(&image);
+        let gain = 65535.0 / 400.0;
+        let greens: Vec<f64> = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .filter(|&(x, y)| noisy.pattern().channel(x, y) == 1)
+            .map(|(x, y)| f64::from(noisy.value(x, y)) / gain)
+            .collect();
+        let mean = greens.iter().sum::<f64>() / greens.len() as f64;
+        let variance = greens
+            .iter()
+            .map(|value| (value - mean).powi(2))
+            .sum::<f64>()
+            / greens.len() as f64;
+        assert!((mean - 120.0).abs() < 1.0, "{}", mean);
+        assert!((variance - 120.0).abs() < 20.0, "{}", variance);
+    }
+}
@@ -0,0 +1,107 @@
+//! Module for counting the work done while rendering, for performance work.
+//!
+//! Rays and intersection tests are counted only when rustbeam is built with
+//! the `stats` feature, since the counters are updated in the innermost loops
+//! of rendering. Each thread counts in counters of its own, and the render
+//! threads add them to the statistics of their render when they are done,
+//! which `RenderHandle::join` returns. The time of each render thread is
+//! measured either way.
+
+use serde::Serialize;
+use std::fmt;
+use std::time::Duration;
+
+#[cfg(feature = "stats")]
+use std::cell::Cell;
+
+/// Whether rustbeam was built with the `stats` feature, so that rays and
+/// intersection tests are counted.
+pub const AVAILABLE: bool = cfg!(feature = "stats");
+
+/// Statistics of a render.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct RenderStats {
+    /// The number of rays traced from the camera.
+    pub primary_rays: u64,
+    /// The number of rays traced towards lights, to find whether they are
+    /// blocked.
+    pub shadow_rays: u64,
+    /// The number of nodes of the BVHs of meshes that rays were tested
+    /// against.
+    pub bvh_node_visits: u64,
+    /// The number of triangles that rays were tested against.
+    pub triangle_tests: u64,
+    /// The time spent by each render thread, in seconds, in the order they
+    /// finished.
+    pub thread_times: Vec<f64>,
+}
+
+impl RenderStats {
+    /// Add the statistics of `other`, such as those of another thread.
+    pub fn add(&mut self, other: &RenderStats) {
+        self.primary_rays += other.primary_rays;
+        self.shadow_rays += other.shadow_rays;
+        self.bvh_node_visits += other.bvh_node_visits;
+        self.triangle_tests += other.triangle_tests;
+        self.thread_times.extend_from_slice(&other.thread_times);
+    }
+}
+
+impl fmt::Display for RenderStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Primary rays: {}", self.primary_rays)?;
+        writeln!(f, "Shadow rays: {}", self.shadow_rays)?;
+        writeln!(f, "BVH node visits: {}", self.bvh_node_visits)?;
+        writeln!(f, "Triangle tests: {}", self.triangle_tests)?;
+        write!(f, "Thread times:")?;
+        for time in &self.thread_times {
+            write!(f, " {:.2} s", time)?;
+        }
+        Ok(())
+    }
+}
+
+/// What is counted.
+#[derive(Clone, Copy)]
+pub(crate) enum Counter {
+    PrimaryRays,
+    ShadowRays,
+    BvhNodeVisits,
+    TriangleTests,
+}
+
+#[cfg(feature = "stats")]
+thread_local! {
+    /// The counts of this thread, by `Counter`.
+    static COUNTS: Cell<[u64; 4]> = const { Cell::new([0; 4]) };
+}
+
+/// Count one more of `counter` on this thread, if counting.
+#[inline]
+pub(crate) fn count(counter: Counter) {
+    #[cfg(feature = "stats")]
+    COUNTS.with(|counts| {
+        let mut values = counts.get();
+        values[counter as usize] += 1;
+        counts.set(values);
+    });
+    #[cfg(not(feature = "stats"))]
+    let _ = counter;
+}
+
+/// Take the counts of this thread so far, which start over from zero, as the
+/// statistics of a render thread that took `time`.
+pub(crate) fn take_thread_stats(time: Duration) -> RenderStats {
+    #[cfg(feature = "stats")]
+    let [primary_rays, shadow_rays, bvh_node_visits, triangle_tests] =
+        COUNTS.with(|counts| counts.replace([0; 4]));
+    #[cfg(not(feature = "stats"))]
+    let [primary_rays, shadow_rays, bvh_node_visits, triangle_tests] = [0; 4];
+    RenderStats {
+        primary_rays,
+        shadow_rays,
+        bvh_node_visits,
+        triangle_tests,
+        thread_times: vec![time.as_secs_f64()],
+    }
+}
@@ -0,0 +1,85 @@
+//! Module for saving triangle meshes as binary STL files, as read by most
+//! slicers for 3D printing.
+//!
+//! STL has no shared vertices or texture coordinates: each triangle is stored
+//! as its normal and the positions of its three corners, in counterclockwise
+//! order seen from outside, as 32-bit floats. There is no unit, but slicers
+//! mostly assume millimeters, so the positions can be scaled on saving.
+
+use crate::surfaces::Mesh;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+/// Save `mesh` as the binary STL file `filename`, with its positions
+/// multiplied by `scale`, such as 1000 to give millimeters for a scene in
+/// meters.
+pub fn save_stl(mesh: &Mesh, filename: &str, scale: f64) -> Result<(), Box<dyn Error>> {
+    let mut writer = BufWriter::new(File::create(filename)?);
+    write_stl(mesh, &mut writer, scale)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Write `mesh` in the binary STL format to `writer`, with its positions
+/// multiplied by `scale`.
+pub fn write_stl(mesh: &Mesh, writer: &mut impl Write, scale: f64) -> Result<(), Box<dyn Error>> {
+    let triangle_count =
+        u32::try_from(mesh.triangles().len()).map_err(|_| "Too many triangles for an STL file")?;
+
+    // An 80-byte header, which must not start with "solid", since that marks
+    // the text format.
+    let mut header = [b' '; 80];
+    header[..8].copy_from_slice(b"rustbeam");
+    writer.write_all(&header)?;
+    writer.write_all(&triangle_count.to_le_bytes())?;
+
+    for triangle in mesh.triangles() {
+        let [a, b, c] = triangle.map(|vertex| scale * mesh.positions()[vertex]);
+        let normal = (b - a).cross(c - a);
+        let normal = if normal.norm2() > 0.0 {
+            normal.normalize()
+        } else {
+            normal
+        };
+        for vector in [normal, a, b, c] {
+            for coordinate in [vector.x, vector.y, vector.z] {
+                writer.write_all(&(coordinate as f32).to_le_bytes())?;
+            }
+        }
+        // The attribute byte count, which is unused.
+        writer.write_all(&[0, 0])?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::Vector3;
+    use std::convert::TryInto;
+
+    #[test]
+    fn triangles_are_written_with_normals() {
+        let positions = vec![
+            Vector3::from((0.0, 0.0, 0.0)),
+            Vector3::from((1.0, 0.0, 0.0)),
+            Vector3::from((0.0, 1.0, 0.0)),
+        ];
+        let mesh = Mesh::new(positions, vec![[0, 1, 2]]);
+
+        let mut bytes = Vec::new();
+        write_stl(&mesh, &mut bytes, 1000.0).unwrap();
+        assert_eq!(bytes.len(), 80 + 4 + 50);
+        assert_eq!(bytes[80..84], 1_u32.to_le_bytes());
+
+        let float = |index: usize| {
+            let offset = 84 + 4 * index;
+            f32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+        };
+        // The normal is up, and the second corner is 1000 along x.
+        assert_eq!([float(0), float(1), float(2)], [0.0, 0.0, 1.0]);
+        assert_eq!([float(6), float(7), float(8)], [1000.0, 0.0, 0.0]);
+    }
+}
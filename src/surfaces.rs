@@ -1,8 +1,22 @@
 //! Module containing the different surfaces that can be rendered.
 
-use crate::math::{Interval, Ray, Vector3};
-use std::f64::{INFINITY, NEG_INFINITY};
+mod mesh;
+mod tessellation;
+mod transform;
 
+pub use mesh::Mesh;
+pub use transform::{Transform, Transformed};
+
+use crate::hashing::ContentHasher;
+use crate::math::{Ray, UnitQuaternion, Vector3};
+use crate::memory::MemoryUsage;
+use crate::scene_file::{ShapeDescription, TransformDescription};
+use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
+use std::f64::{EPSILON, INFINITY, NEG_INFINITY};
+use std::sync::Arc;
+
+#[derive(Clone, Copy)]
 struct BoundingBox {
     /// The first corner is the corner that has the lowest coordinate values,
     /// and the second, the highest coordinate values.
@@ -17,88 +31,428 @@ impl BoundingBox {
         }
     }
 
-    /// Does the ray intersect the bounding box?
-    fn intersects(&self, ray: &Ray) -> bool {
-        // We intersect the ray and the 3 cardinal direction slabs generated
-        // from the bounding box.
-        let mut t_interval = if ray.direction.x != 0.0 {
-            // Ray intersects the x-direction slab.
-            let t0 = (self.corners.0.x - ray.origin.x) / ray.direction.x;
-            let t1 = (self.corners.1.x - ray.origin.x) / ray.direction.x;
+    /// Compute the minimal bounding box containing all the `points`, of which
+    /// there must be at least one.
+    fn from_points(mut points: impl Iterator<Item = Vector3>) -> Self {
+        let first_point = points.next().expect("No points to bound");
+        let (lower, upper) = points.fold((first_point, first_point), |(lower, upper), point| {
+            (lower.min(point), upper.max(point))
+        });
+        Self::new(lower, upper)
+    }
 
-            Interval::new(t0, t1)
-        } else {
-            Interval::new(NEG_INFINITY, INFINITY)
-        };
+    /// Compute the minimal bounding box containing both bounding boxes.
+    fn union(&self, other: &BoundingBox) -> Self {
+        Self::new(
+            self.corners.0.min(other.corners.0),
+            self.corners.1.max(other.corners.1),
+        )
+    }
 
-        if ray.direction.y != 0.0 {
-            // Ray intersects the y-direction slab.
-            let t0 = (self.corners.0.y - ray.origin.y) / ray.direction.y;
-            let t1 = (self.corners.1.y - ray.origin.y) / ray.direction.y;
+    fn center(&self) -> Vector3 {
+        0.5 * (self.corners.0 + self.corners.1)
+    }
 
-            match t_interval.intersection(Interval::new(t0, t1)) {
-                None => return false,
-                Some(interval) => t_interval = interval,
-            }
-        }
+    /// Compute the smallest sphere containing the bounding box, as its center
+    /// and radius.
+    fn bounding_sphere(&self) -> (Vector3, f64) {
+        (
+            self.center(),
+            0.5 * (self.corners.1 - self.corners.0).norm(),
+        )
+    }
 
-        if ray.direction.z != 0.0 {
-            // Ray intersects the z-direction slab.
-            let t0 = (self.corners.0.z - ray.origin.z) / ray.direction.z;
-            let t1 = (self.corners.1.z - ray.origin.z) / ray.direction.z;
+    /// The axis (0, 1 or 2 for x, y or z) along which the box is longest.
+    fn longest_axis(&self) -> usize {
+        let diagonal = self.corners.1 - self.corners.0;
+        if diagonal.x >= diagonal.y && diagonal.x >= diagonal.z {
+            0
+        } else if diagonal.y >= diagonal.z {
+            1
+        } else {
+            2
+        }
+    }
 
-            match t_interval.intersection(Interval::new(t0, t1)) {
-                None => return false,
-                Some(interval) => t_interval = interval,
+    /// Does the ray intersect the bounding box?
+    fn intersects(&self, ray: &Ray) -> bool {
+        // We intersect the ray and the 3 cardinal direction slabs generated
+        // from the bounding box. A ray parallel to a slab has an infinite
+        // inverse direction, which puts the slab at infinite distances of the
+        // same sign if the origin is outside it, and of opposite signs if it
+        // is inside.
+        let mut near = NEG_INFINITY;
+        let mut far = INFINITY;
+        for axis in 0..3 {
+            let inverse_direction = 1.0 / ray.direction.component(axis);
+            let t0 =
+                (self.corners.0.component(axis) - ray.origin.component(axis)) * inverse_direction;
+            let t1 =
+                (self.corners.1.component(axis) - ray.origin.component(axis)) * inverse_direction;
+            if t0.is_nan() || t1.is_nan() {
+                // The ray is parallel to the slab and starts on its boundary,
+                // so the slab doesn't limit it.
+                continue;
             }
+
+            near = near.max(t0.min(t1));
+            far = far.min(t0.max(t1));
         }
 
-        let endpoints = t_interval.get_endpoints();
-        endpoints.0 >= 0.0 || endpoints.1 >= 0.0
+        near <= far && far >= 0.0
     }
 }
 
+/// Information about where a ray intersects a surface.
+pub struct Intersection {
+    /// The length along the ray to the intersection.
+    pub distance: f64,
+    /// The unit normal of the surface at the intersection.
+    pub normal: Vector3,
+    /// A unit tangent of the surface at the intersection. Together with the
+    /// normal, it determines the tangent space used for normal mapping.
+    pub tangent: Vector3,
+    /// The texture coordinates of the intersection.
+    pub uv: (f64, f64),
+}
+
 /// A `Surface` can intersect a `Ray`.
 pub trait Surface {
-    /// Find the length along a ray to the first intersection between the ray
-    /// and the surface (if any). Also returns the normal of the surface in the
-    /// intersection.
-    fn closest_intersection(&self, ray: &Ray) -> Option<(f64, Vector3)>;
+    /// Find the first intersection between the ray and the surface (if any).
+    fn closest_intersection(&self, ray: &Ray) -> Option<Intersection>;
+
+    /// Find a sphere enclosing the surface, as its center and radius. Unbounded
+    /// surfaces return `None`, which is the default.
+    fn bounding_sphere(&self) -> Option<(Vector3, f64)> {
+        None
+    }
+
+    /// Write the shape and placement of the surface to `hasher`. The default
+    /// writes that the surface can't be hashed.
+    fn hash_content(&self, hasher: &mut ContentHasher) {
+        hasher.write_unknown();
+    }
+
+    /// Find the memory used by the surface. Only surfaces with many vertices
+    /// need to report it, so the default is none.
+    fn memory_usage(&self) -> MemoryUsage {
+        MemoryUsage::default()
+    }
+
+    /// Approximate the surface by a triangle mesh, such as for exporting it to
+    /// other tools. Curved surfaces get more triangles the smaller `tolerance`
+    /// is, which is the largest distance in meters between the mesh and the
+    /// surface. Surfaces that can't be tessellated, such as unbounded ones,
+    /// return `None`, which is the default.
+    fn tessellate(&self, _tolerance: f64) -> Option<Mesh> {
+        None
+    }
+
+    /// Like `tessellate`, but the mesh may be in object space, placed in the
+    /// scene by the returned transform, so that exporters can keep the
+    /// transform separate. The default tessellates the surface in the scene,
+    /// with the identity transform.
+    fn tessellate_with_transform(&self, tolerance: f64) -> Option<(Mesh, Transform)> {
+        self.tessellate(tolerance)
+            .map(|mesh| (mesh, Transform::identity()))
+    }
+
+    /// Whether the surface itself ignores rays hitting it at the point they
+    /// leave it from, so that the scene must not ignore its hits closer than
+    /// the ray bias. The default is false.
+    fn ignores_leaving_rays(&self) -> bool {
+        false
+    }
+
+    /// Describe the surface as in scene files, for saving scenes built in
+    /// code. The default returns `None`, for surfaces that scene files can't
+    /// describe, such as those made by plugins.
+    fn describe(&self) -> Option<ShapeDescription> {
+        None
+    }
+}
+
+impl<T: Surface + ?Sized> Surface for Box<T> {
+    fn closest_intersection(&self, ray: &Ray) -> Option<Intersection> {
+        self.as_ref().closest_intersection(ray)
+    }
+
+    fn bounding_sphere(&self) -> Option<(Vector3, f64)> {
+        self.as_ref().bounding_sphere()
+    }
+
+    fn hash_content(&self, hasher: &mut ContentHasher) {
+        self.as_ref().hash_content(hasher);
+    }
+
+    fn memory_usage(&self) -> MemoryUsage {
+        self.as_ref().memory_usage()
+    }
+
+    fn tessellate(&self, tolerance: f64) -> Option<Mesh> {
+        self.as_ref().tessellate(tolerance)
+    }
+
+    fn tessellate_with_transform(&self, tolerance: f64) -> Option<(Mesh, Transform)> {
+        self.as_ref().tessellate_with_transform(tolerance)
+    }
+
+    fn ignores_leaving_rays(&self) -> bool {
+        self.as_ref().ignores_leaving_rays()
+    }
+
+    fn describe(&self) -> Option<ShapeDescription> {
+        self.as_ref().describe()
+    }
+}
+
+/// A shared surface, such as one placed in the scene by a node of the scene
+/// graph, is the surface itself.
+impl<T: Surface + ?Sized> Surface for Arc<T> {
+    fn closest_intersection(&self, ray: &Ray) -> Option<Intersection> {
+        self.as_ref().closest_intersection(ray)
+    }
+
+    fn bounding_sphere(&self) -> Option<(Vector3, f64)> {
+        self.as_ref().bounding_sphere()
+    }
+
+    fn hash_content(&self, hasher: &mut ContentHasher) {
+        self.as_ref().hash_content(hasher);
+    }
+
+    fn memory_usage(&self) -> MemoryUsage {
+        self.as_ref().memory_usage()
+    }
+
+    fn tessellate(&self, tolerance: f64) -> Option<Mesh> {
+        self.as_ref().tessellate(tolerance)
+    }
+
+    fn tessellate_with_transform(&self, tolerance: f64) -> Option<(Mesh, Transform)> {
+        self.as_ref().tessellate_with_transform(tolerance)
+    }
+
+    fn ignores_leaving_rays(&self) -> bool {
+        self.as_ref().ignores_leaving_rays()
+    }
+
+    fn describe(&self) -> Option<ShapeDescription> {
+        self.as_ref().describe()
+    }
 }
 
+/// An infinite plane. Texture coordinates are distances in meters along the
+/// tangent and bitangent of the plane, measured from the origin of the plane.
+/// The plane made by `new` has its origin at the point closest to the origin of
+/// the scene.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Plane {
+    /// The point on the plane where the texture coordinates are (0, 0).
+    origin: Vector3,
     normal_vec: Vector3,
-    distance_from_origin: f64,
+    /// A fixed unit vector in the plane, pointing in the direction of
+    /// increasing u.
+    tangent: Vector3,
 }
 
 impl Plane {
     pub fn new<T: Into<Vector3>>(normal_vec: T, distance_from_origin: f64) -> Self {
+        let normal_vec = normal_vec.into().normalize();
         Self {
-            normal_vec: normal_vec.into().normalize(),
-            distance_from_origin,
+            origin: distance_from_origin * normal_vec,
+            normal_vec,
+            tangent: normal_vec.perpendicular(),
         }
     }
-}
 
-impl Surface for Plane {
-    fn closest_intersection(&self, ray: &Ray) -> Option<(f64, Vector3)> {
+    /// Rotate the plane, together with its texture coordinates, by `rotation`
+    /// around the origin of the scene, and then move it by `translation`.
+    pub fn with_transform<T: Into<Vector3>>(
+        mut self,
+        rotation: UnitQuaternion,
+        translation: T,
+    ) -> Self {
+        self.origin = self.origin.rotate(rotation) + translation.into();
+        self.normal_vec = self.normal_vec.rotate(rotation).normalize();
+        self.tangent = self.tangent.rotate(rotation).normalize();
+        self
+    }
+
+    fn bitangent(&self) -> Vector3 {
+        self.normal_vec.cross(self.tangent)
+    }
+
+    /// Find where `ray` intersects the plane, as the distance along the ray
+    /// and the texture coordinates there.
+    fn intersect(&self, ray: &Ray) -> Option<(f64, (f64, f64))> {
         let ray_direction_dot_normal = ray.direction.dot(self.normal_vec);
         if ray_direction_dot_normal == 0.0 {
+            return None;
+        }
+
+        // Ray intersects plane.
+        let distance_to_intersection =
+            (self.origin - ray.origin).dot(self.normal_vec) / ray_direction_dot_normal;
+        if distance_to_intersection > 0.0 {
+            let point = ray.origin + distance_to_intersection * ray.direction - self.origin;
+            Some((
+                distance_to_intersection,
+                (point.dot(self.tangent), point.dot(self.bitangent())),
+            ))
+        } else {
             None
+        }
+    }
+
+    fn intersection(&self, distance: f64, uv: (f64, f64)) -> Intersection {
+        Intersection {
+            distance,
+            normal: self.normal_vec,
+            tangent: self.tangent,
+            uv,
+        }
+    }
+
+    fn hash_frame(&self, hasher: &mut ContentHasher) {
+        hasher.write_vector(self.origin);
+        hasher.write_vector(self.normal_vec);
+        hasher.write_vector(self.tangent);
+    }
+}
+
+impl Surface for Plane {
+    fn closest_intersection(&self, ray: &Ray) -> Option<Intersection> {
+        self.intersect(ray)
+            .map(|(distance, uv)| self.intersection(distance, uv))
+    }
+
+    fn hash_content(&self, hasher: &mut ContentHasher) {
+        hasher.write_str("Plane");
+        self.hash_frame(hasher);
+    }
+
+    fn describe(&self) -> Option<ShapeDescription> {
+        // The plane through the origin of the scene facing up along the z-axis
+        // has its tangent along the y-axis, which the rotation turns into the
+        // tangent of this plane.
+        let rotation = UnitQuaternion::from_basis(
+            self.tangent.cross(self.normal_vec),
+            self.tangent,
+            self.normal_vec,
+        );
+        Some(ShapeDescription::Plane {
+            normal: (0.0, 0.0, 1.0),
+            distance: 0.0,
+            transform: Some(TransformDescription::new(rotation, self.origin)),
+        })
+    }
+}
+
+/// A rectangle. Texture coordinates are distances in meters along its sides,
+/// as for `Plane`, from (0, 0) in one corner to (width, height) in the
+/// opposite one.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Rect {
+    /// The plane of the rectangle, with its origin in a corner and its tangent
+    /// along the width.
+    plane: Plane,
+    width: f64,
+    height: f64,
+}
+
+impl Rect {
+    /// Make a rectangle centered at the origin of the scene, facing up along
+    /// the z-axis, with its width along the x-axis and its height along the
+    /// y-axis. Use `with_transform` to place it elsewhere.
+    pub fn new(width: f64, height: f64) -> Self {
+        Self {
+            plane: Plane {
+                origin: Vector3::from((-0.5 * width, -0.5 * height, 0.0)),
+                normal_vec: Vector3::k(),
+                tangent: Vector3::i(),
+            },
+            width,
+            height,
+        }
+    }
+
+    /// Rotate the rectangle by `rotation` around the origin of the scene, and
+    /// then move it by `translation`.
+    pub fn with_transform<T: Into<Vector3>>(
+        mut self,
+        rotation: UnitQuaternion,
+        translation: T,
+    ) -> Self {
+        self.plane = self.plane.with_transform(rotation, translation);
+        self
+    }
+}
+
+impl Surface for Rect {
+    fn closest_intersection(&self, ray: &Ray) -> Option<Intersection> {
+        let (distance, (u, v)) = self.plane.intersect(ray)?;
+        if (0.0..=self.width).contains(&u) && (0.0..=self.height).contains(&v) {
+            Some(self.plane.intersection(distance, (u, v)))
         } else {
-            // Ray intersects plane.
-            let distance_to_intersection = (self.distance_from_origin
-                - ray.origin.dot(self.normal_vec))
-                / ray_direction_dot_normal;
-            if distance_to_intersection > 0.0 {
-                Some((distance_to_intersection, self.normal_vec))
-            } else {
-                None
-            }
+            None
         }
     }
+
+    fn bounding_sphere(&self) -> Option<(Vector3, f64)> {
+        let center = self.plane.origin
+            + 0.5 * self.width * self.plane.tangent
+            + 0.5 * self.height * self.plane.bitangent();
+        Some((center, 0.5 * self.width.hypot(self.height)))
+    }
+
+    fn hash_content(&self, hasher: &mut ContentHasher) {
+        hasher.write_str("Rect");
+        self.plane.hash_frame(hasher);
+        hasher.write_f64(self.width);
+        hasher.write_f64(self.height);
+    }
+
+    fn describe(&self) -> Option<ShapeDescription> {
+        let rotation = UnitQuaternion::from_basis(
+            self.plane.tangent,
+            self.plane.bitangent(),
+            self.plane.normal_vec,
+        );
+        let corner = Vector3::from((-0.5 * self.width, -0.5 * self.height, 0.0));
+        Some(ShapeDescription::Rect {
+            width: self.width,
+            height: self.height,
+            transform: Some(TransformDescription::new(
+                rotation,
+                self.plane.origin - corner.rotate(rotation),
+            )),
+        })
+    }
+
+    fn tessellate(&self, _tolerance: f64) -> Option<Mesh> {
+        let (tangent, bitangent) = (self.plane.tangent, self.plane.bitangent());
+        let uvs = vec![
+            (0.0, 0.0),
+            (self.width, 0.0),
+            (self.width, self.height),
+            (0.0, self.height),
+        ];
+        let positions = uvs
+            .iter()
+            .map(|&(u, v)| self.plane.origin + u * tangent + v * bitangent)
+            .collect();
+        Some(Mesh::with_texture_coordinates(
+            positions,
+            uvs,
+            vec![[0, 1, 2], [0, 2, 3]],
+        ))
+    }
 }
 
+/// A sphere. Texture coordinates are given by a spherical mapping around the
+/// z-axis: u is the longitude and v the latitude, both scaled to [0, 1].
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Sphere {
     pub center_pos: Vector3,
     /// In meters.
@@ -122,7 +476,7 @@ impl Sphere {
 }
 
 impl Surface for Sphere {
-    fn closest_intersection(&self, ray: &Ray) -> Option<(f64, Vector3)> {
+    fn closest_intersection(&self, ray: &Ray) -> Option<Intersection> {
         if self.bounding_box().intersects(ray) {
             let origin_to_center = self.center_pos - ray.origin;
             let origin_to_center_dot_dir = origin_to_center.dot(ray.direction);
@@ -134,7 +488,10 @@ impl Surface for Sphere {
             } else {
                 // Ray intersects sphere.
                 let mut distance_to_intersection = origin_to_center_dot_dir - discriminant.sqrt();
-                if distance_to_intersection <= 0.0 {
+                // A ray leaving the surface, such as one refracted into the
+                // sphere, finds the near intersection at about zero distance.
+                // The scene skips it, so take the far one instead.
+                if distance_to_intersection <= EPSILON.sqrt() {
                     distance_to_intersection = origin_to_center_dot_dir + discriminant.sqrt();
                     if distance_to_intersection <= 0.0 {
                         return None;
@@ -142,11 +499,298 @@ impl Surface for Sphere {
                 }
                 let normal =
                     (ray.direction * distance_to_intersection - origin_to_center).normalize();
-                Some((distance_to_intersection, normal))
+                // The tangent points east, along the circles of latitude around
+                // the z-axis. At the poles, any perpendicular vector will do.
+                let tangent = Vector3::k().cross(normal);
+                let tangent = if tangent.norm2() > 0.0 {
+                    tangent.normalize()
+                } else {
+                    normal.perpendicular()
+                };
+                // Longitude around the z-axis gives u, and latitude gives v,
+                // from 0 at the south pole to 1 at the north pole.
+                let u = 0.5 + normal.y.atan2(normal.x) / (2.0 * PI);
+                let v = 0.5 + normal.z.clamp(-1.0, 1.0).asin() / PI;
+                Some(Intersection {
+                    distance: distance_to_intersection,
+                    normal,
+                    tangent,
+                    uv: (u, v),
+                })
             }
         } else {
             // Ray doesn't intersect bounding box.
             None
         }
     }
+
+    fn bounding_sphere(&self) -> Option<(Vector3, f64)> {
+        Some((self.center_pos, self.radius))
+    }
+
+    fn hash_content(&self, hasher: &mut ContentHasher) {
+        hasher.write_str("Sphere");
+        hasher.write_vector(self.center_pos);
+        hasher.write_f64(self.radius);
+    }
+
+    fn describe(&self) -> Option<ShapeDescription> {
+        Some(ShapeDescription::Sphere {
+            center: self.center_pos.into(),
+            radius: self.radius,
+        })
+    }
+
+    fn tessellate(&self, tolerance: f64) -> Option<Mesh> {
+        Some(tessellation::sphere(
+            self.center_pos,
+            self.radius,
+            tolerance,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use proptest::test_runner::RngSeed;
+
+    /// Tolerance for comparing lengths in scenes of size around 10.
+    const TOLERANCE: f64 = 1e-9;
+
+    fn vector(range: f64) -> impl Strategy<Value = Vector3> {
+        (-range..range, -range..range, -range..range).prop_map(Vector3::from)
+    }
+
+    fn direction() -> impl Strategy<Value = Vector3> {
+        vector(1.0).prop_filter("direction must be non-zero", |v| v.norm2() > 1e-6)
+    }
+
+    /// Check the invariants that every intersection must satisfy.
+    fn check_intersection(ray: &Ray, hit: &Intersection) -> Result<(), TestCaseError> {
+        prop_assert!(hit.distance.is_finite() && hit.distance > 0.0);
+        prop_assert!((hit.normal.norm2() - 1.0).abs() < TOLERANCE);
+        prop_assert!((hit.tangent.norm2() - 1.0).abs() < TOLERANCE);
+        prop_assert!(hit.normal.dot(hit.tangent).abs() < TOLERANCE);
+        prop_assert!(ray.direction.norm2() > 0.0);
+        Ok(())
+    }
+
+    #[test]
+    fn axis_parallel_rays_intersect_bounding_box_only_within_slabs() {
+        let bounding_box = BoundingBox::new((0.0, 0.0, 0.0), (1.0, 1.0, 1.0));
+        let along_x = |origin: (f64, f64, f64), sign: f64| {
+            Ray::new(
+                Vector3::from(origin),
+                Vector3::from((sign, sign * 0.0, -0.0)),
+            )
+        };
+
+        assert!(bounding_box.intersects(&along_x((-1.0, 0.5, 0.5), 1.0)));
+        assert!(bounding_box.intersects(&along_x((2.0, 0.5, 0.5), -1.0)));
+        assert!(!bounding_box.intersects(&along_x((2.0, 0.5, 0.5), 1.0)));
+        // Outside the y- or z-slab, on either side.
+        assert!(!bounding_box.intersects(&along_x((-1.0, 1.5, 0.5), 1.0)));
+        assert!(!bounding_box.intersects(&along_x((-1.0, 0.5, -0.5), 1.0)));
+        assert!(!bounding_box.intersects(&along_x((2.0, -0.5, 0.5), -1.0)));
+        // On the boundary of the y- and z-slabs.
+        assert!(bounding_box.intersects(&along_x((-1.0, 1.0, 0.0), 1.0)));
+        assert!(bounding_box.intersects(&along_x((2.0, 0.0, 1.0), -1.0)));
+    }
+
+    #[test]
+    fn transformed_rect_is_hit_only_inside() {
+        // A 2 x 1 wall facing the negative y-axis, 3 meters away.
+        let rotation = UnitQuaternion::from_axis_angle((1.0, 0.0, 0.0), 0.5 * PI);
+        let rect = Rect::new(2.0, 1.0).with_transform(rotation, (0.0, 3.0, 0.0));
+        let towards = |x: f64, z: f64| Ray::new(Vector3::zero(), Vector3::from((x, 3.0, z)));
+
+        let hit = rect.closest_intersection(&towards(0.5, 0.25)).unwrap();
+        assert!((hit.distance - (0.25 + 9.0 + 0.0625_f64).sqrt()).abs() < TOLERANCE);
+        assert!((hit.normal - Vector3::from((0.0, -1.0, 0.0))).norm() < TOLERANCE);
+        assert!((hit.uv.0 - 1.5).abs() < TOLERANCE && (hit.uv.1 - 0.75).abs() < TOLERANCE);
+        assert!(rect.closest_intersection(&towards(1.1, 0.0)).is_none());
+        assert!(rect.closest_intersection(&towards(0.0, -0.6)).is_none());
+    }
+
+    #[test]
+    fn surfaces_survive_serialization() {
+        fn round_trip<S: Surface + Serialize + for<'de> Deserialize<'de>>(surface: &S) {
+            let source = ron::to_string(surface).unwrap();
+            let found: S = ron::from_str(&source).unwrap();
+            let mut hashers = (ContentHasher::new(), ContentHasher::new());
+            surface.hash_content(&mut hashers.0);
+            found.hash_content(&mut hashers.1);
+            assert_eq!(hashers.0.finish(), hashers.1.finish());
+        }
+
+        let rotation = UnitQuaternion::from_axis_angle((1.0, 2.0, 3.0), 0.7);
+        round_trip(&Sphere::new((0.1, 2.0, -0.3), 0.5));
+        round_trip(&Plane::new((0.0, 1.0, 1.0), 2.0).with_transform(rotation, (1.0, 0.0, 0.0)));
+        round_trip(&Rect::new(2.0, 1.0).with_transform(rotation, (0.0, 3.0, 0.0)));
+        let positions = vec![
+            Vector3::zero(),
+            Vector3::i(),
+            Vector3::j(),
+            Vector3::from((0.3, 0.3, 1.0)),
+        ];
+        round_trip(&Mesh::new(positions, vec![[0, 2, 1], [0, 1, 3], [1, 2, 3]]));
+
+        // The normals, tangents and BVH are computed again, from vertices that
+        // must exist.
+        let source = "(positions: [(x: 0.0, y: 0.0, z: 0.0)], uvs: [(0.0, 0.0)], \
+                      triangles: [(0, 0, 1)])";
+        assert!(ron::from_str::<Mesh>(source).is_err());
+    }
+
+    proptest! {
+        // Use a fixed seed, so that every run tests the same cases.
+        #![proptest_config(ProptestConfig {
+            rng_seed: RngSeed::Fixed(0),
+            ..ProptestConfig::default()
+        })]
+
+        #[test]
+        fn sphere_intersection_lies_on_sphere(
+            center in vector(10.0),
+            radius in 0.1..5.0,
+            origin in vector(10.0),
+            direction in direction(),
+        ) {
+            let sphere = Sphere::new(center, radius);
+            let ray = Ray::new(origin, direction);
+
+            if let Some(hit) = sphere.closest_intersection(&ray) {
+                check_intersection(&ray, &hit)?;
+
+                let point = ray.origin + hit.distance * ray.direction;
+                let center_to_point = point - center;
+                prop_assert!((center_to_point.norm2().sqrt() - radius).abs() < TOLERANCE);
+                prop_assert!((center_to_point * (1.0 / radius) - hit.normal).norm2() < TOLERANCE);
+                prop_assert!((0.0..=1.0).contains(&hit.uv.0) && (0.0..=1.0).contains(&hit.uv.1));
+            }
+        }
+
+        #[test]
+        fn ray_from_inside_sphere_hits_it(
+            center in vector(10.0),
+            radius in 0.1..5.0,
+            offset in vector(0.5),
+            direction in direction(),
+        ) {
+            let sphere = Sphere::new(center, radius);
+            let ray = Ray::new(center + radius * offset, direction);
+
+            let hit = sphere.closest_intersection(&ray);
+            prop_assert!(hit.is_some());
+            check_intersection(&ray, &hit.unwrap())?;
+        }
+
+        #[test]
+        fn sphere_intersection_implies_bounding_box_intersection(
+            center in vector(10.0),
+            radius in 0.1..5.0,
+            origin in vector(10.0),
+            direction in direction(),
+        ) {
+            let sphere = Sphere::new(center, radius);
+            let ray = Ray::new(origin, direction);
+
+            if sphere.closest_intersection(&ray).is_some() {
+                prop_assert!(sphere.bounding_box().intersects(&ray));
+            }
+        }
+
+        #[test]
+        fn bounding_box_intersection_matches_points_along_ray(
+            corners in (vector(5.0), vector(5.0)),
+            origin in vector(10.0),
+            direction in direction(),
+            flattened_axes in (any::<bool>(), any::<bool>()),
+        ) {
+            // Make the ray parallel to one or two of the slabs, if chosen.
+            let mut direction = direction;
+            if flattened_axes.0 {
+                direction.y = 0.0;
+            }
+            if flattened_axes.1 {
+                direction.z = 0.0;
+            }
+            prop_assume!(direction.norm2() > 1e-6);
+            let bounding_box = BoundingBox::new(corners.0.min(corners.1), corners.0.max(corners.1));
+            let ray = Ray::new(origin, direction);
+
+            // Points along the ray inside a slightly smaller box must be
+            // found, and the ray must pass near a slightly larger box if it
+            // intersects.
+            let shrunk = BoundingBox::new(
+                bounding_box.corners.0 + 1e-3 * Vector3::ones(),
+                bounding_box.corners.1 - 1e-3 * Vector3::ones(),
+            );
+            let grown = BoundingBox::new(
+                bounding_box.corners.0 - 1e-3 * Vector3::ones(),
+                bounding_box.corners.1 + 1e-3 * Vector3::ones(),
+            );
+            let contains = |bounding_box: &BoundingBox, point: Vector3| {
+                (0..3).all(|axis| {
+                    let coordinate = point.component(axis);
+                    bounding_box.corners.0.component(axis) <= coordinate
+                        && coordinate <= bounding_box.corners.1.component(axis)
+                })
+            };
+            let points = (0..=40_000).map(|i| ray.origin + (i as f64 * 1e-3) * ray.direction);
+            let mut points_in_shrunk = points.clone().filter(|&point| contains(&shrunk, point));
+            if points_in_shrunk.next().is_some() {
+                prop_assert!(bounding_box.intersects(&ray));
+            }
+            if bounding_box.intersects(&ray) {
+                let mut points_in_grown = points.filter(|&point| contains(&grown, point));
+                prop_assert!(points_in_grown.next().is_some());
+            }
+        }
+
+        #[test]
+        fn ray_towards_triangle_hits_mesh_there(
+            vertices in (vector(10.0), vector(10.0), vector(10.0)),
+            origin in vector(10.0),
+            barycentric in (0.01..0.98, 0.01..0.98),
+        ) {
+            let (p0, p1, p2) = vertices;
+            prop_assume!((p1 - p0).cross(p2 - p0).norm2() > 1e-2);
+            let (u, v) = barycentric;
+            prop_assume!(u + v < 0.99);
+
+            let mesh = Mesh::new(vec![p0, p1, p2], vec![[0, 1, 2]]);
+            let target = p0 + u * (p1 - p0) + v * (p2 - p0);
+            prop_assume!((target - origin).norm2() > 1e-2);
+            let ray = Ray::new(origin, target - origin);
+
+            let hit = mesh.closest_intersection(&ray);
+            prop_assert!(hit.is_some());
+            let hit = hit.unwrap();
+            check_intersection(&ray, &hit)?;
+            prop_assert!((hit.distance - (target - origin).norm()).abs() < 1e-6);
+        }
+
+        #[test]
+        fn plane_intersection_lies_on_plane(
+            normal in direction(),
+            distance_from_origin in -10.0..10.0,
+            origin in vector(10.0),
+            direction in direction(),
+        ) {
+            let plane = Plane::new(normal, distance_from_origin);
+            let ray = Ray::new(origin, direction);
+
+            if let Some(hit) = plane.closest_intersection(&ray) {
+                check_intersection(&ray, &hit)?;
+
+                let point = ray.origin + hit.distance * ray.direction;
+                let normal = normal.normalize();
+                prop_assert!((point.dot(normal) - distance_from_origin).abs() < TOLERANCE);
+                prop_assert!((hit.normal - normal).norm2() < TOLERANCE);
+            }
+        }
+    }
 }
@@ -0,0 +1,535 @@
+//! Module containing triangle meshes.
+
+mod bvh_cache;
+
+use super::{BoundingBox, Intersection, Surface};
+use crate::hashing::ContentHasher;
+use crate::math::{Ray, Vector3};
+use crate::memory::MemoryUsage;
+use crate::scene_file::ShapeDescription;
+use crate::stats::{self, Counter};
+use crate::textures::Texture;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::mem;
+use std::path::Path;
+
+/// The largest number of triangles that is stored in a leaf of the bounding
+/// volume hierarchy.
+const MAX_TRIANGLES_PER_LEAF: usize = 4;
+
+/// How far from the plane of a triangle a ray may start and still be taken to
+/// leave from the triangle, as a fraction of the largest coordinate of the ray
+/// origin and the triangle. This covers the rounding errors of hit points,
+/// also when they are moved into the space of a transformed mesh.
+const LEAVING_TOLERANCE: f64 = 4096.0 * f64::EPSILON;
+
+/// A node in a bounding volume hierarchy (BVH) over the triangles of a mesh.
+enum BvhNode {
+    Leaf {
+        bounding_box: BoundingBox,
+        /// Indices into the triangles of the mesh.
+        triangles: Vec<usize>,
+    },
+    Branch {
+        bounding_box: BoundingBox,
+        children: Box<(BvhNode, BvhNode)>,
+    },
+}
+
+impl BvhNode {
+    /// Build a BVH over the triangles with indices `triangles`, given the
+    /// bounding boxes of all the triangles of the mesh.
+    fn build(mut triangles: Vec<usize>, triangle_boxes: &[BoundingBox]) -> Self {
+        let bounding_box = triangles
+            .iter()
+            .map(|&triangle| triangle_boxes[triangle])
+            .fold(triangle_boxes[triangles[0]], |union, triangle_box| {
+                union.union(&triangle_box)
+            });
+
+        if triangles.len() <= MAX_TRIANGLES_PER_LEAF {
+            return BvhNode::Leaf {
+                bounding_box,
+                triangles,
+            };
+        }
+
+        // Split the triangles in two equally large halves along the axis where
+        // the centers of the triangles are most spread out.
+        let centers_box = BoundingBox::from_points(
+            triangles
+                .iter()
+                .map(|&triangle| triangle_boxes[triangle].center()),
+        );
+        let axis = centers_box.longest_axis();
+        triangles.sort_by(|&first, &second| {
+            let first_center = triangle_boxes[first].center().component(axis);
+            let second_center = triangle_boxes[second].center().component(axis);
+            first_center
+                .partial_cmp(&second_center)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        let second_half = triangles.split_off(triangles.len() / 2);
+
+        BvhNode::Branch {
+            bounding_box,
+            children: Box::new((
+                BvhNode::build(triangles, triangle_boxes),
+                BvhNode::build(second_half, triangle_boxes),
+            )),
+        }
+    }
+
+    /// Find the memory used by this node and its descendants, in bytes.
+    fn memory_usage(&self) -> usize {
+        mem::size_of::<Self>()
+            + match self {
+                BvhNode::Leaf { triangles, .. } => triangles.len() * mem::size_of::<usize>(),
+                BvhNode::Branch { children, .. } => {
+                    children.0.memory_usage() + children.1.memory_usage()
+                }
+            }
+    }
+
+    fn bounding_box(&self) -> &BoundingBox {
+        match self {
+            BvhNode::Leaf { bounding_box, .. } | BvhNode::Branch { bounding_box, .. } => {
+                bounding_box
+            }
+        }
+    }
+
+    /// Intersect the ray with the triangles in this node, replacing `closest`
+    /// if an intersection closer than it is found.
+    fn closest_intersection(&self, mesh: &Mesh, ray: &Ray, closest: &mut Option<Intersection>) {
+        stats::count(Counter::BvhNodeVisits);
+        match self {
+            BvhNode::Leaf {
+                bounding_box,
+                triangles,
+            } => {
+                if bounding_box.intersects(ray) {
+                    for &triangle in triangles {
+                        if let Some(hit) = mesh.intersect_triangle(triangle, ray) {
+                            if closest.as_ref().is_none_or(|c| hit.distance < c.distance) {
+                                *closest = Some(hit);
+                            }
+                        }
+                    }
+                }
+            }
+            BvhNode::Branch {
+                bounding_box,
+                children,
+            } => {
+                if bounding_box.intersects(ray) {
+                    children.0.closest_intersection(mesh, ray, closest);
+                    children.1.closest_intersection(mesh, ray, closest);
+                }
+            }
+        }
+    }
+}
+
+/// A surface made of triangles, which may share vertices. The mesh is shaded
+/// smoothly, by interpolating vertex normals across each triangle. It is
+/// serialized as its vertices and triangles.
+#[derive(Serialize, Deserialize)]
+#[serde(try_from = "MeshData")]
+pub struct Mesh {
+    positions: Vec<Vector3>,
+    /// Unit normal of each vertex.
+    #[serde(skip_serializing)]
+    normals: Vec<Vector3>,
+    /// Texture coordinates of each vertex.
+    uvs: Vec<(f64, f64)>,
+    /// Each triangle is three indices into the vertices, in counterclockwise
+    /// order when seen from the front.
+    triangles: Vec<[usize; 3]>,
+    /// Tangent of each triangle, pointing in the direction of increasing u.
+    #[serde(skip_serializing)]
+    tangents: Vec<Vector3>,
+    /// `None` if the mesh has no triangles.
+    #[serde(skip_serializing)]
+    bvh: Option<BvhNode>,
+}
+
+/// The fields of a serialized `Mesh`, from which the rest is computed.
+#[derive(Deserialize)]
+struct MeshData {
+    positions: Vec<Vector3>,
+    uvs: Vec<(f64, f64)>,
+    triangles: Vec<[usize; 3]>,
+}
+
+impl TryFrom<MeshData> for Mesh {
+    type Error = String;
+
+    fn try_from(data: MeshData) -> Result<Self, String> {
+        Self::try_with_texture_coordinates(data.positions, data.uvs, data.triangles)
+    }
+}
+
+impl Mesh {
+    /// Make a mesh from vertex `positions` and `triangles`. Vertex normals are
+    /// computed from the triangles, and all texture coordinates are (0, 0).
+    pub fn new(positions: Vec<Vector3>, triangles: Vec<[usize; 3]>) -> Self {
+        let uvs = vec![(0.0, 0.0); positions.len()];
+        Self::with_texture_coordinates(positions, uvs, triangles)
+    }
+
+    /// Make a mesh from vertex `positions`, texture coordinates `uvs` for each
+    /// vertex, and `triangles`.
+    pub fn with_texture_coordinates(
+        positions: Vec<Vector3>,
+        uvs: Vec<(f64, f64)>,
+        triangles: Vec<[usize; 3]>,
+    ) -> Self {
+        let mut mesh = Self::without_bvh(positions, uvs, triangles);
+        mesh.bvh = mesh.build_bvh();
+        mesh
+    }
+
+    /// Like `with_texture_coordinates`, but returns an error instead of
+    /// panicking if there isn't one texture coordinate for each vertex, or a
+    /// triangle refers to a vertex that doesn't exist, for meshes read from
+    /// files.
+    pub(crate) fn try_with_texture_coordinates(
+        positions: Vec<Vector3>,
+        uvs: Vec<(f64, f64)>,
+        triangles: Vec<[usize; 3]>,
+    ) -> Result<Self, String> {
+        if uvs.len() != positions.len() {
+            return Err("A mesh must have one texture coordinate for each vertex".to_string());
+        }
+        if triangles
+            .iter()
+            .flatten()
+            .any(|&vertex| vertex >= positions.len())
+        {
+            return Err("A mesh triangle refers to a vertex that doesn't exist".to_string());
+        }
+        Ok(Self::with_texture_coordinates(positions, uvs, triangles))
+    }
+
+    /// Like `with_texture_coordinates`, but the BVH of a large mesh is read
+    /// from the directory `cache_dir`, if it has been built for the same mesh
+    /// before. Otherwise it is built and saved there, if possible. Building the
+    /// BVH takes long for meshes with millions of triangles.
+    pub fn with_bvh_cache(
+        positions: Vec<Vector3>,
+        uvs: Vec<(f64, f64)>,
+        triangles: Vec<[usize; 3]>,
+        cache_dir: &Path,
+    ) -> Self {
+        let mut mesh = Self::without_bvh(positions, uvs, triangles);
+        mesh.bvh = if mesh.triangles.len() < bvh_cache::MIN_TRIANGLES {
+            mesh.build_bvh()
+        } else {
+            bvh_cache::load_or_build(&mesh, cache_dir)
+        };
+        mesh
+    }
+
+    /// Combine `meshes` into one mesh.
+    pub fn merge(meshes: &[Mesh]) -> Self {
+        let mut positions = Vec::new();
+        let mut uvs = Vec::new();
+        let mut triangles = Vec::new();
+        for mesh in meshes {
+            let offset = positions.len();
+            positions.extend_from_slice(&mesh.positions);
+            uvs.extend_from_slice(&mesh.uvs);
+            triangles.extend(
+                mesh.triangles
+                    .iter()
+                    .map(|triangle| triangle.map(|vertex| vertex + offset)),
+            );
+        }
+        Self::with_texture_coordinates(positions, uvs, triangles)
+    }
+
+    /// Make a mesh without a BVH, which must be added before the mesh is used.
+    fn without_bvh(
+        positions: Vec<Vector3>,
+        uvs: Vec<(f64, f64)>,
+        triangles: Vec<[usize; 3]>,
+    ) -> Self {
+        assert_eq!(positions.len(), uvs.len());
+        assert!(triangles
+            .iter()
+            .flatten()
+            .all(|&vertex| vertex < positions.len()));
+
+        // The normal of a vertex is the average of the normals of the triangles
+        // sharing the vertex, weighted by the areas of the triangles.
+        let mut normals = vec![Vector3::zero(); positions.len()];
+        for triangle in triangles.iter() {
+            let [p0, p1, p2] = triangle.map(|vertex| positions[vertex]);
+            let area_normal = (p1 - p0).cross(p2 - p0);
+            for &vertex in triangle {
+                normals[vertex] += area_normal;
+            }
+        }
+        let normals = normals.into_iter().map(Vector3::normalize).collect();
+
+        let tangents = triangles
+            .iter()
+            .map(|triangle| {
+                let [p0, p1, p2] = triangle.map(|vertex| positions[vertex]);
+                let [uv0, uv1, uv2] = triangle.map(|vertex| uvs[vertex]);
+                let (edge1, edge2) = (p1 - p0, p2 - p0);
+                let (du1, dv1) = (uv1.0 - uv0.0, uv1.1 - uv0.1);
+                let (du2, dv2) = (uv2.0 - uv0.0, uv2.1 - uv0.1);
+
+                let determinant = du1 * dv2 - du2 * dv1;
+                if determinant.abs() > 1e-12 {
+                    ((dv2 * edge1 - dv1 * edge2) * (1.0 / determinant)).normalize()
+                } else {
+                    // Degenerate texture coordinates. Use an edge instead.
+                    edge1.normalize()
+                }
+            })
+            .collect();
+
+        Self {
+            positions,
+            normals,
+            uvs,
+            triangles,
+            tangents,
+            bvh: None,
+        }
+    }
+
+    /// The positions of the vertices.
+    pub fn positions(&self) -> &[Vector3] {
+        &self.positions
+    }
+
+    /// The unit normals of the vertices.
+    pub fn normals(&self) -> &[Vector3] {
+        &self.normals
+    }
+
+    /// The texture coordinates of the vertices.
+    pub fn uvs(&self) -> &[(f64, f64)] {
+        &self.uvs
+    }
+
+    /// The triangles, as indices into the vertices, in counterclockwise order
+    /// when seen from the front.
+    pub fn triangles(&self) -> &[[usize; 3]] {
+        &self.triangles
+    }
+
+    /// Build a BVH over the triangles, or `None` if there are none.
+    fn build_bvh(&self) -> Option<BvhNode> {
+        if self.triangles.is_empty() {
+            return None;
+        }
+
+        let triangle_boxes: Vec<_> = self
+            .triangles
+            .iter()
+            .map(|triangle| {
+                BoundingBox::from_points(triangle.iter().map(|&vertex| self.positions[vertex]))
+            })
+            .collect();
+        Some(BvhNode::build(
+            (0..self.triangles.len()).collect(),
+            &triangle_boxes,
+        ))
+    }
+
+    /// Make a displaced copy of the mesh. The mesh is first subdivided
+    /// `levels` times, splitting each triangle into four, and then each vertex
+    /// is moved along its normal by `scale` times the value of the `height`
+    /// texture, which is the average of its color channels.
+    pub fn displace(&self, height: &dyn Texture, scale: f64, levels: u32) -> Self {
+        let mut positions = self.positions.clone();
+        let mut normals = self.normals.clone();
+        let mut uvs = self.uvs.clone();
+        let mut triangles = self.triangles.clone();
+
+        for _ in 0..levels {
+            let mut midpoints = HashMap::new();
+            let mut midpoint = |a: usize, b: usize| {
+                *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                    positions.push(0.5 * (positions[a] + positions[b]));
+                    normals.push((normals[a] + normals[b]).normalize());
+                    uvs.push((0.5 * (uvs[a].0 + uvs[b].0), 0.5 * (uvs[a].1 + uvs[b].1)));
+                    positions.len() - 1
+                })
+            };
+
+            triangles = triangles
+                .iter()
+                .flat_map(|&[a, b, c]| {
+                    let (ab, bc, ca) = (midpoint(a, b), midpoint(b, c), midpoint(c, a));
+                    vec![[a, ab, ca], [ab, b, bc], [ca, bc, c], [ab, bc, ca]]
+                })
+                .collect();
+        }
+
+        for ((position, normal), uv) in positions.iter_mut().zip(normals.iter()).zip(uvs.iter()) {
+            let color = height.color(*position, *uv);
+            let height = (color.x + color.y + color.z) / 3.0;
+            *position += scale * height * *normal;
+        }
+
+        Self::with_texture_coordinates(positions, uvs, triangles)
+    }
+
+    /// Intersect the ray with the triangle with index `triangle`, using the
+    /// Möller-Trumbore algorithm.
+    fn intersect_triangle(&self, triangle: usize, ray: &Ray) -> Option<Intersection> {
+        stats::count(Counter::TriangleTests);
+        let [i0, i1, i2] = self.triangles[triangle];
+        let [p0, p1, p2] = [self.positions[i0], self.positions[i1], self.positions[i2]];
+
+        let (edge1, edge2) = (p1 - p0, p2 - p0);
+        let p_vec = ray.direction.cross(edge2);
+        let determinant = edge1.dot(p_vec);
+        if determinant.abs() < 1e-12 {
+            // The ray is parallel to the triangle.
+            return None;
+        }
+        let recip_determinant = 1.0 / determinant;
+
+        // Barycentric coordinates of the intersection.
+        let t_vec = ray.origin - p0;
+        let u = t_vec.dot(p_vec) * recip_determinant;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q_vec = t_vec.cross(edge1);
+        let v = ray.direction.dot(q_vec) * recip_determinant;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+
+        let distance = edge2.dot(q_vec) * recip_determinant;
+        if distance <= 0.0 {
+            return None;
+        }
+
+        // A ray leaving from the triangle hits it where it starts, through
+        // rounding errors. Such hits are found by how far from the plane of the
+        // triangle the ray starts, rather than by how far along the ray they
+        // are, so that a ray leaving a closed mesh near an edge still hits the
+        // triangle on the other side of the edge, however close it is.
+        let largest = [ray.origin, p0, p1, p2]
+            .iter()
+            .map(|point| point.x.abs().max(point.y.abs()).max(point.z.abs()))
+            .fold(0.0, f64::max);
+        let face_normal = edge1.cross(edge2);
+        if t_vec.dot(face_normal).abs() <= LEAVING_TOLERANCE * largest * face_normal.norm() {
+            return None;
+        }
+
+        let w = 1.0 - u - v;
+        let normal = w * self.normals[i0] + u * self.normals[i1] + v * self.normals[i2];
+        let normal = if normal.norm2() > 0.0 {
+            normal.normalize()
+        } else {
+            edge1.cross(edge2).normalize()
+        };
+
+        // Make the tangent of the triangle perpendicular to the interpolated
+        // normal.
+        let tangent = self.tangents[triangle];
+        let tangent = tangent - tangent.dot(normal) * normal;
+        let tangent = if tangent.norm2() > 0.0 {
+            tangent.normalize()
+        } else {
+            normal.perpendicular()
+        };
+
+        let (uv0, uv1, uv2) = (self.uvs[i0], self.uvs[i1], self.uvs[i2]);
+        let uv = (
+            w * uv0.0 + u * uv1.0 + v * uv2.0,
+            w * uv0.1 + u * uv1.1 + v * uv2.1,
+        );
+
+        Some(Intersection {
+            distance,
+            normal,
+            tangent,
+            uv,
+        })
+    }
+}
+
+impl Surface for Mesh {
+    fn closest_intersection(&self, ray: &Ray) -> Option<Intersection> {
+        let mut closest = None;
+        if let Some(bvh) = &self.bvh {
+            bvh.closest_intersection(self, ray, &mut closest);
+        }
+        closest
+    }
+
+    fn bounding_sphere(&self) -> Option<(Vector3, f64)> {
+        self.bvh
+            .as_ref()
+            .map(|bvh| bvh.bounding_box().bounding_sphere())
+    }
+
+    /// A mesh only ignores rays hitting the triangles they leave from, and
+    /// keeps the hits on the other triangles, which closed meshes must not
+    /// lose where a ray leaves them again within the ray bias.
+    fn ignores_leaving_rays(&self) -> bool {
+        true
+    }
+
+    fn describe(&self) -> Option<ShapeDescription> {
+        Some(ShapeDescription::Mesh {
+            positions: self
+                .positions
+                .iter()
+                .map(|&position| position.into())
+                .collect(),
+            uvs: self.uvs.clone(),
+            triangles: self.triangles.clone(),
+        })
+    }
+
+    fn hash_content(&self, hasher: &mut ContentHasher) {
+        // The normals, tangents and BVH are computed from the rest.
+        hasher.write_str("Mesh");
+        hasher.write_u64(self.positions.len() as u64);
+        for (position, uv) in self.positions.iter().zip(&self.uvs) {
+            hasher.write_vector(*position);
+            hasher.write_f64(uv.0);
+            hasher.write_f64(uv.1);
+        }
+        hasher.write_u64(self.triangles.len() as u64);
+        for triangle in &self.triangles {
+            for &vertex in triangle {
+                hasher.write_u64(vertex as u64);
+            }
+        }
+    }
+
+    fn memory_usage(&self) -> MemoryUsage {
+        MemoryUsage {
+            vertices: self.positions.len() * mem::size_of::<Vector3>()
+                + self.normals.len() * mem::size_of::<Vector3>()
+                + self.uvs.len() * mem::size_of::<(f64, f64)>(),
+            triangles: self.triangles.len() * mem::size_of::<[usize; 3]>()
+                + self.tangents.len() * mem::size_of::<Vector3>(),
+            bvh: self.bvh.as_ref().map_or(0, BvhNode::memory_usage),
+        }
+    }
+
+    fn tessellate(&self, _tolerance: f64) -> Option<Mesh> {
+        Some(Mesh::with_texture_coordinates(
+            self.positions.clone(),
+            self.uvs.clone(),
+            self.triangles.clone(),
+        ))
+    }
+}
@@ -0,0 +1,250 @@
+//! Module for caching the BVHs of large meshes on disk.
+//!
+//! Each BVH is saved in its own file, named by the hash of the mesh it was
+//! built for, so a changed mesh never finds the BVH of the old one. The files
+//! are in a simple binary format, checked when read: a file that is damaged or
+//! was written by another version of the format is ignored, and the BVH is
+//! built again.
+
+use super::{BvhNode, Mesh};
+use crate::hashing::ContentHasher;
+use crate::math::Vector3;
+use crate::surfaces::{BoundingBox, Surface};
+use std::convert::TryInto;
+use std::fs;
+use std::path::Path;
+
+/// Meshes with fewer triangles than this build their BVHs faster than they are
+/// read from disk.
+pub(super) const MIN_TRIANGLES: usize = 10_000;
+
+/// Identifies a BVH file.
+const MAGIC: &[u8; 4] = b"RBVH";
+/// Must be increased when the format, or how BVHs are built, changes.
+const FORMAT_VERSION: u32 = 1;
+/// BVHs are balanced, so even the largest meshes give far shallower trees.
+const MAX_DEPTH: u32 = 64;
+
+const LEAF: u8 = 0;
+const BRANCH: u8 = 1;
+
+/// Read the BVH of `mesh` from `cache_dir`, or build it and try to save it
+/// there. Failing to save it only means that it is built again next time.
+pub(super) fn load_or_build(mesh: &Mesh, cache_dir: &Path) -> Option<BvhNode> {
+    let mut hasher = ContentHasher::new();
+    hasher.write_u64(u64::from(FORMAT_VERSION));
+    mesh.hash_content(&mut hasher);
+    let hash = hasher.finish()?;
+    let path = cache_dir.join(format!("{:016x}.bvh", hash));
+
+    if let Some(bvh) = fs::read(&path)
+        .ok()
+        .and_then(|bytes| read(&bytes, mesh.triangles.len()))
+    {
+        return Some(bvh);
+    }
+
+    let bvh = mesh.build_bvh()?;
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    bytes.extend_from_slice(&(mesh.triangles.len() as u64).to_le_bytes());
+    write_node(&bvh, &mut bytes);
+
+    // Write to a temporary file first, so that other renders never read a
+    // partly written file.
+    let temporary_path = path.with_extension(format!("{}.tmp", std::process::id()));
+    let saved = fs::create_dir_all(cache_dir)
+        .and_then(|_| fs::write(&temporary_path, &bytes))
+        .and_then(|_| fs::rename(&temporary_path, &path));
+    if saved.is_err() {
+        let _ = fs::remove_file(&temporary_path);
+    }
+
+    Some(bvh)
+}
+
+/// Read a BVH over `num_triangles` triangles from the contents of a file.
+/// Returns `None` if the contents are not a valid BVH.
+fn read(bytes: &[u8], num_triangles: usize) -> Option<BvhNode> {
+    let mut reader = Reader { bytes };
+    if reader.take(4)? != MAGIC
+        || reader.u32()? != FORMAT_VERSION
+        || reader.u64()? != num_triangles as u64
+    {
+        return None;
+    }
+
+    let bvh = read_node(&mut reader, num_triangles, 0)?;
+    if reader.bytes.is_empty() {
+        Some(bvh)
+    } else {
+        None
+    }
+}
+
+fn read_node(reader: &mut Reader, num_triangles: usize, depth: u32) -> Option<BvhNode> {
+    if depth > MAX_DEPTH {
+        return None;
+    }
+
+    let tag = reader.take(1)?[0];
+    let bounding_box = BoundingBox::new(reader.vector()?, reader.vector()?);
+    match tag {
+        LEAF => {
+            let count = reader.u32()? as usize;
+            let triangles = (0..count)
+                .map(|_| reader.u32().map(|index| index as usize))
+                .collect::<Option<Vec<_>>>()?;
+            if triangles.iter().any(|&triangle| triangle >= num_triangles) {
+                return None;
+            }
+            Some(BvhNode::Leaf {
+                bounding_box,
+                triangles,
+            })
+        }
+        BRANCH => {
+            let first = read_node(reader, num_triangles, depth + 1)?;
+            let second = read_node(reader, num_triangles, depth + 1)?;
+            Some(BvhNode::Branch {
+                bounding_box,
+                children: Box::new((first, second)),
+            })
+        }
+        _ => None,
+    }
+}
+
+fn write_node(node: &BvhNode, bytes: &mut Vec<u8>) {
+    let write_box = |bytes: &mut Vec<u8>, bounding_box: &BoundingBox| {
+        let (lower, upper) = bounding_box.corners;
+        for value in [lower.x, lower.y, lower.z, upper.x, upper.y, upper.z] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+    };
+
+    match node {
+        BvhNode::Leaf {
+            bounding_box,
+            triangles,
+        } => {
+            bytes.push(LEAF);
+            write_box(bytes, bounding_box);
+            bytes.extend_from_slice(&(triangles.len() as u32).to_le_bytes());
+            for &triangle in triangles {
+                bytes.extend_from_slice(&(triangle as u32).to_le_bytes());
+            }
+        }
+        BvhNode::Branch {
+            bounding_box,
+            children,
+        } => {
+            bytes.push(BRANCH);
+            write_box(bytes, bounding_box);
+            write_node(&children.0, bytes);
+            write_node(&children.1, bytes);
+        }
+    }
+}
+
+/// Reads little-endian numbers from the start of `bytes`, returning `None` if
+/// there are too few bytes left.
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Option<&'a [u8]> {
+        if self.bytes.len() < count {
+            return None;
+        }
+        let (taken, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+        Some(taken)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    fn vector(&mut self) -> Option<Vector3> {
+        let mut coordinates = [0.0; 3];
+        for coordinate in coordinates.iter_mut() {
+            *coordinate = f64::from_le_bytes(self.take(8)?.try_into().ok()?);
+        }
+        Some(Vector3::from((
+            coordinates[0],
+            coordinates[1],
+            coordinates[2],
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::Ray;
+
+    #[test]
+    fn cached_bvh_gives_the_same_intersections() {
+        // A grid of triangles, large enough to be cached.
+        let size = 80;
+        let mut positions = Vec::new();
+        for y in 0..=size {
+            for x in 0..=size {
+                let (x, y) = (x as f64 / size as f64, y as f64 / size as f64);
+                positions.push(Vector3::from((x, y, 0.1 * (10.0 * x * y).sin())));
+            }
+        }
+        let vertex = |x: usize, y: usize| y * (size + 1) + x;
+        let mut triangles = Vec::new();
+        for y in 0..size {
+            for x in 0..size {
+                triangles.push([vertex(x, y), vertex(x + 1, y), vertex(x + 1, y + 1)]);
+                triangles.push([vertex(x, y), vertex(x + 1, y + 1), vertex(x, y + 1)]);
+            }
+        }
+        assert!(triangles.len() >= MIN_TRIANGLES);
+        let uvs = vec![(0.0, 0.0); positions.len()];
+
+        let cache_dir = std::env::temp_dir().join(format!("rustbeam-bvh-{}", std::process::id()));
+        let built = Mesh::with_bvh_cache(
+            positions.clone(),
+            uvs.clone(),
+            triangles.clone(),
+            &cache_dir,
+        );
+        let files: Vec<_> = fs::read_dir(&cache_dir).unwrap().collect();
+        assert_eq!(files.len(), 1);
+        let path = files[0].as_ref().unwrap().path();
+
+        let loaded = Mesh::with_bvh_cache(positions, uvs, triangles, &cache_dir);
+        for i in 0..100 {
+            let t = i as f64 / 100.0;
+            let ray = Ray::new(
+                Vector3::from((t, 1.0 - t, 1.0)),
+                Vector3::from((0.1, 0.2, -1.0)),
+            );
+            let (first, second) = (
+                built.closest_intersection(&ray),
+                loaded.closest_intersection(&ray),
+            );
+            assert_eq!(
+                first.map(|hit| hit.distance),
+                second.map(|hit| hit.distance)
+            );
+        }
+
+        // A damaged file is ignored.
+        let bytes = fs::read(&path).unwrap();
+        assert!(read(&bytes, built.triangles.len()).is_some());
+        assert!(read(&bytes[..bytes.len() - 1], built.triangles.len()).is_none());
+        assert!(read(&bytes, built.triangles.len() - 1).is_none());
+
+        fs::remove_dir_all(&cache_dir).unwrap();
+    }
+}
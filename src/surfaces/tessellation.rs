@@ -0,0 +1,275 @@
+//! Module for approximating surfaces by triangle meshes, for export.
+//!
+//! Curved analytic surfaces are tessellated adaptively: a circle of radius r
+//! split into segments of angle θ deviates at most r (1 - cos(θ / 2)) from
+//! its chords, so the number of segments is chosen to keep that below the
+//! tolerance. Implicit surfaces, given by a function that is negative inside,
+//! are polygonized by marching cubes, where each cube is split into six
+//! tetrahedra along its diagonal. Neighboring cubes then split their shared
+//! faces the same way, so the mesh has no cracks and no ambiguous cases.
+
+use super::Mesh;
+use crate::math::Vector3;
+use std::collections::HashMap;
+use std::f64::consts::PI;
+
+/// The fewest segments that a circle is split into.
+const MIN_SEGMENTS: usize = 3;
+/// The most segments that a circle is split into, however small the
+/// tolerance.
+const MAX_SEGMENTS: usize = 4096;
+
+/// The corners of the six tetrahedra that each cube is split into. Corner `c`
+/// of a cube is offset by `c & 1` along x, `(c >> 1) & 1` along y and
+/// `(c >> 2) & 1` along z.
+const TETRAHEDRA: [[usize; 4]; 6] = [
+    [0, 1, 3, 7],
+    [0, 3, 2, 7],
+    [0, 2, 6, 7],
+    [0, 6, 4, 7],
+    [0, 4, 5, 7],
+    [0, 5, 1, 7],
+];
+
+/// Find how many segments a full circle of `radius` must be split into for
+/// the chords to be within `tolerance` of it.
+pub(super) fn circle_segments(radius: f64, tolerance: f64) -> usize {
+    let ratio = (tolerance / radius).min(1.0);
+    let segment_angle = 2.0 * (1.0 - ratio).acos();
+    if segment_angle > 0.0 {
+        ((2.0 * PI / segment_angle).ceil() as usize).clamp(MIN_SEGMENTS, MAX_SEGMENTS)
+    } else {
+        MAX_SEGMENTS
+    }
+}
+
+/// Tessellate a sphere into a grid of longitude and latitude, with the same
+/// texture coordinates as `Sphere`. The vertices on the seam at u = 0 and
+/// u = 1 are repeated, so that the texture coordinates don't wrap around.
+pub(super) fn sphere(center: Vector3, radius: f64, tolerance: f64) -> Mesh {
+    // The middle of a patch of the grid is further from its corners than the
+    // middle of a side is, by about a factor of the square root of 2, which
+    // doubles the distance to the sphere.
+    let longitudes = circle_segments(radius, 0.5 * tolerance).max(4);
+    let latitudes = longitudes / 2;
+
+    let mut positions = Vec::with_capacity((longitudes + 1) * (latitudes + 1));
+    let mut uvs = Vec::with_capacity(positions.capacity());
+    for j in 0..=latitudes {
+        let v = j as f64 / latitudes as f64;
+        let latitude = PI * (v - 0.5);
+        for i in 0..=longitudes {
+            let u = i as f64 / longitudes as f64;
+            let longitude = 2.0 * PI * (u - 0.5);
+            let direction = Vector3::from((
+                latitude.cos() * longitude.cos(),
+                latitude.cos() * longitude.sin(),
+                latitude.sin(),
+            ));
+            positions.push(center + radius * direction);
+            uvs.push((u, v));
+        }
+    }
+
+    // Going east and then north is counterclockwise seen from outside. At the
+    // poles, one of the two triangles of each quad has no area.
+    let vertex = |i: usize, j: usize| j * (longitudes + 1) + i;
+    let mut triangles = Vec::new();
+    for j in 0..latitudes {
+        for i in 0..longitudes {
+            let (a, b) = (vertex(i, j), vertex(i + 1, j));
+            let (c, d) = (vertex(i + 1, j + 1), vertex(i, j + 1));
+            if j > 0 {
+                triangles.push([a, b, c]);
+            }
+            if j + 1 < latitudes {
+                triangles.push([a, c, d]);
+            }
+        }
+    }
+
+    Mesh::with_texture_coordinates(positions, uvs, triangles)
+}
+
+impl Mesh {
+    /// Make a mesh of the implicit surface where `function` is zero, which is
+    /// negative inside and positive outside, such as a signed distance
+    /// function. The surface is found within the box with corners `lower` and
+    /// `upper`, sampled in cubes of side at most `cell_size`. The mesh is
+    /// closed where the surface doesn't leave the box, and its triangles face
+    /// outwards. All texture coordinates are (0, 0).
+    pub fn from_implicit(
+        function: impl Fn(Vector3) -> f64,
+        lower: Vector3,
+        upper: Vector3,
+        cell_size: f64,
+    ) -> Self {
+        let extent = upper - lower;
+        let cells =
+            [0, 1, 2].map(|axis| ((extent.component(axis) / cell_size).ceil() as usize).max(1));
+        let step = Vector3::from((
+            extent.x / cells[0] as f64,
+            extent.y / cells[1] as f64,
+            extent.z / cells[2] as f64,
+        ));
+
+        // The function is sampled once at each corner of the grid.
+        let grid_index =
+            |x: usize, y: usize, z: usize| (z * (cells[1] + 1) + y) * (cells[0] + 1) + x;
+        let mut points = Vec::with_capacity((cells[0] + 1) * (cells[1] + 1) * (cells[2] + 1));
+        for z in 0..=cells[2] {
+            for y in 0..=cells[1] {
+                for x in 0..=cells[0] {
+                    let offset = Vector3::from((x as f64, y as f64, z as f64));
+                    points.push(lower + offset.elementwise_mul(step));
+                }
+            }
+        }
+        let values: Vec<f64> = points.iter().map(|&point| function(point)).collect();
+
+        // Each grid edge crossing the surface gets one vertex, shared by all
+        // the triangles around the edge. Edges ending on the surface share
+        // the vertex at their end instead, where the triangles between them
+        // collapse.
+        let mut edge_vertices = HashMap::new();
+        let mut positions = Vec::new();
+        let mut edge_vertex = |inside: usize, outside: usize| {
+            let key = if values[outside] == 0.0 {
+                (outside, outside)
+            } else {
+                (inside, outside)
+            };
+            *edge_vertices.entry(key).or_insert_with(|| {
+                let t = values[inside] / (values[inside] - values[outside]);
+                positions.push(points[inside] + t * (points[outside] - points[inside]));
+                positions.len() - 1
+            })
+        };
+
+        let mut triangles = Vec::new();
+        for z in 0..cells[2] {
+            for y in 0..cells[1] {
+                for x in 0..cells[0] {
+                    let corner =
+                        |c: usize| grid_index(x + (c & 1), y + ((c >> 1) & 1), z + (c >> 2));
+                    for tetrahedron in TETRAHEDRA.iter() {
+                        let corners = tetrahedron.map(corner);
+                        let (inside, outside): (Vec<usize>, Vec<usize>) =
+                            corners.iter().partition(|&&corner| values[corner] < 0.0);
+
+                        let polygon = match (inside.len(), outside.len()) {
+                            (1, 3) => outside.iter().map(|&o| edge_vertex(inside[0], o)).collect(),
+                            (3, 1) => inside.iter().map(|&i| edge_vertex(i, outside[0])).collect(),
+                            (2, 2) => vec![
+                                edge_vertex(inside[0], outside[0]),
+                                edge_vertex(inside[0], outside[1]),
+                                edge_vertex(inside[1], outside[1]),
+                                edge_vertex(inside[1], outside[0]),
+                            ],
+                            _ => continue,
+                        };
+
+                        // The triangles are turned to face away from the
+                        // inside corners once all vertices are placed.
+                        let centroid = |corners: &[usize]| {
+                            corners
+                                .iter()
+                                .fold(Vector3::zero(), |sum, &c| sum + points[c])
+                                * (1.0 / corners.len() as f64)
+                        };
+                        let outwards = centroid(&outside) - centroid(&inside);
+                        for i in 1..polygon.len() - 1 {
+                            let [a, b, c] = [polygon[0], polygon[i], polygon[i + 1]];
+                            if a != b && b != c && c != a {
+                                triangles.push(([a, b, c], outwards));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        let triangles = triangles
+            .into_iter()
+            .map(|([a, b, c], outwards)| {
+                let normal = (positions[b] - positions[a]).cross(positions[c] - positions[a]);
+                if normal.dot(outwards) >= 0.0 {
+                    [a, b, c]
+                } else {
+                    [a, c, b]
+                }
+            })
+            .collect();
+        Mesh::new(positions, triangles)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::surfaces::{Sphere, Surface};
+
+    /// Check that `mesh` is closed, with every edge shared by two triangles
+    /// going in opposite directions, and that its triangles face away from
+    /// `center`.
+    fn check_closed_around(mesh: &Mesh, center: Vector3) {
+        let mut edges = HashMap::new();
+        for &[a, b, c] in mesh.triangles() {
+            for edge in [(a, b), (b, c), (c, a)] {
+                *edges.entry(edge).or_insert(0) += 1;
+            }
+
+            let [pa, pb, pc] = [a, b, c].map(|vertex| mesh.positions()[vertex]);
+            let centroid = (pa + pb + pc) * (1.0 / 3.0);
+            assert!((pb - pa).cross(pc - pa).dot(centroid - center) > 0.0);
+        }
+        for (&(a, b), &count) in &edges {
+            assert_eq!(count, 1);
+            assert_eq!(edges.get(&(b, a)), Some(&1));
+        }
+    }
+
+    #[test]
+    fn sphere_is_tessellated_within_tolerance() {
+        let center = Vector3::from((1.0, 2.0, 3.0));
+        for &(radius, tolerance) in &[(1.0, 0.1), (1.0, 1e-3), (10.0, 1e-3)] {
+            let mesh = Sphere::new(center, radius).tessellate(tolerance).unwrap();
+
+            for &[a, b, c] in mesh.triangles() {
+                let [pa, pb, pc] = [a, b, c].map(|vertex| mesh.positions()[vertex]);
+                for point in [pa, pb, pc] {
+                    assert!(((point - center).norm() - radius).abs() < 1e-9);
+                }
+                let centroid = (pa + pb + pc) * (1.0 / 3.0);
+                assert!(radius - (centroid - center).norm() <= tolerance);
+                assert!((pb - pa).cross(pc - pa).dot(centroid - center) > 0.0);
+            }
+        }
+
+        // A finer tolerance gives more triangles.
+        let sphere = Sphere::new(center, 1.0);
+        assert!(
+            sphere.tessellate(1e-3).unwrap().triangles().len()
+                > sphere.tessellate(1e-2).unwrap().triangles().len()
+        );
+    }
+
+    #[test]
+    fn implicit_sphere_is_closed() {
+        let center = Vector3::from((0.1, 0.2, 0.3));
+        let function = |point: Vector3| (point - center).norm() - 1.0;
+        let cell_size = 0.1;
+        let mesh = Mesh::from_implicit(
+            function,
+            Vector3::from((-1.5, -1.5, -1.5)),
+            Vector3::from((1.5, 1.5, 1.5)),
+            cell_size,
+        );
+
+        assert!(!mesh.triangles().is_empty());
+        for &position in mesh.positions() {
+            assert!(function(position).abs() < cell_size * cell_size);
+        }
+        check_closed_around(&mesh, center);
+    }
+}
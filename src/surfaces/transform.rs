@@ -0,0 +1,301 @@
+//! Module containing surfaces that are scaled, rotated and moved.
+//!
+//! A transformed surface is intersected in object space, where it is defined,
+//! by transforming the ray with the inverse transform. Distances along the
+//! transformed ray are rescaled to distances in the scene, and normals are
+//! transformed by the inverse transpose of the transform, so that they stay
+//! perpendicular to the surface under non-uniform scaling.
+
+use super::{Intersection, Mesh, Surface};
+use crate::hashing::ContentHasher;
+use crate::math::{Ray, UnitQuaternion, Vector3};
+use crate::memory::MemoryUsage;
+use serde::{Deserialize, Serialize};
+
+/// A transform from the object space of a surface to the scene: scaling along
+/// the axes, followed by a rotation around the origin, and then a translation.
+/// Custom surfaces can use its methods to place themselves in the scene in
+/// the same way as `Transformed` does.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct Transform {
+    scale: Vector3,
+    rotation: UnitQuaternion,
+    translation: Vector3,
+}
+
+impl Transform {
+    /// Make a transform that scales by the components of `scale`, which must
+    /// be non-zero, then rotates by `rotation`, and then moves by
+    /// `translation`.
+    pub fn new<S: Into<Vector3>, T: Into<Vector3>>(
+        scale: S,
+        rotation: UnitQuaternion,
+        translation: T,
+    ) -> Self {
+        Self {
+            scale: scale.into(),
+            rotation,
+            translation: translation.into(),
+        }
+    }
+
+    /// The transform that leaves everything in place.
+    pub fn identity() -> Self {
+        Self::new(Vector3::ones(), UnitQuaternion::id(), Vector3::zero())
+    }
+
+    /// The 4 x 4 matrix of the transform, which transforms points in object
+    /// space, as columns with a fourth component of 1, to the scene.
+    pub fn matrix(&self) -> [[f64; 4]; 4] {
+        let [x, y, z] = [Vector3::i(), Vector3::j(), Vector3::k()].map(|v| self.vector_to_world(v));
+        let t = self.translation;
+        [
+            [x.x, y.x, z.x, t.x],
+            [x.y, y.y, z.y, t.y],
+            [x.z, y.z, z.z, t.z],
+            [0.0, 0.0, 0.0, 1.0],
+        ]
+    }
+
+    /// Transform a point from object space to the scene.
+    pub fn point_to_world(&self, point: Vector3) -> Vector3 {
+        self.vector_to_world(point) + self.translation
+    }
+
+    /// Transform a point from the scene to object space.
+    pub fn point_to_object(&self, point: Vector3) -> Vector3 {
+        self.vector_to_object(point - self.translation)
+    }
+
+    /// Transform a vector, such as a tangent or the difference between two
+    /// points, from object space to the scene. The length may change.
+    pub fn vector_to_world(&self, vector: Vector3) -> Vector3 {
+        vector.elementwise_mul(self.scale).rotate(self.rotation)
+    }
+
+    /// Transform a vector from the scene to object space. The length may
+    /// change.
+    pub fn vector_to_object(&self, vector: Vector3) -> Vector3 {
+        vector
+            .rotate(self.rotation.invert())
+            .elementwise_mul(self.inverse_scale())
+    }
+
+    /// Transform a normal from object space to the scene, by the inverse
+    /// transpose of the transform, and normalize it.
+    pub fn normal_to_world(&self, normal: Vector3) -> Vector3 {
+        normal
+            .elementwise_mul(self.inverse_scale())
+            .rotate(self.rotation)
+            .normalize()
+    }
+
+    /// Transform a ray from the scene to object space. Returns the ray,
+    /// together with the factor that converts distances along it to distances
+    /// along `ray`, which differ under scaling since the direction of the ray
+    /// is normalized.
+    pub fn ray_to_object(&self, ray: &Ray) -> (Ray, f64) {
+        let direction = self.vector_to_object(ray.direction);
+        let length = direction.norm();
+        (
+            Ray::new(self.point_to_object(ray.origin), direction),
+            1.0 / length,
+        )
+    }
+
+    /// Transform an intersection found along a ray made by `ray_to_object`
+    /// to the scene, given the factor returned with the ray.
+    pub fn intersection_to_world(&self, hit: Intersection, distance_factor: f64) -> Intersection {
+        let normal = self.normal_to_world(hit.normal);
+        // The transformed tangent is in the surface, but only perpendicular to
+        // the normal under uniform scaling.
+        let tangent = self.vector_to_world(hit.tangent);
+        let tangent = tangent - tangent.dot(normal) * normal;
+        let tangent = if tangent.norm2() > 0.0 {
+            tangent.normalize()
+        } else {
+            normal.perpendicular()
+        };
+        Intersection {
+            distance: hit.distance * distance_factor,
+            normal,
+            tangent,
+            uv: hit.uv,
+        }
+    }
+
+    pub fn hash_content(&self, hasher: &mut ContentHasher) {
+        hasher.write_vector(self.scale);
+        hasher.write_vector(Vector3::i().rotate(self.rotation));
+        hasher.write_vector(Vector3::j().rotate(self.rotation));
+        hasher.write_vector(self.translation);
+    }
+
+    fn inverse_scale(&self) -> Vector3 {
+        Vector3::from((1.0 / self.scale.x, 1.0 / self.scale.y, 1.0 / self.scale.z))
+    }
+
+    /// The largest factor that the transform scales lengths by.
+    fn max_scale(&self) -> f64 {
+        self.scale
+            .x
+            .abs()
+            .max(self.scale.y.abs())
+            .max(self.scale.z.abs())
+    }
+}
+
+/// A surface placed in the scene by a `Transform`, such as a sphere scaled
+/// into an ellipsoid. Texture coordinates are those of the surface.
+pub struct Transformed<S> {
+    surface: S,
+    transform: Transform,
+}
+
+impl<S: Surface> Transformed<S> {
+    pub fn new(surface: S, transform: Transform) -> Self {
+        Self { surface, transform }
+    }
+}
+
+impl<S: Surface> Surface for Transformed<S> {
+    fn closest_intersection(&self, ray: &Ray) -> Option<Intersection> {
+        let (object_ray, distance_factor) = self.transform.ray_to_object(ray);
+        let hit = self.surface.closest_intersection(&object_ray)?;
+        Some(self.transform.intersection_to_world(hit, distance_factor))
+    }
+
+    fn bounding_sphere(&self) -> Option<(Vector3, f64)> {
+        let (center, radius) = self.surface.bounding_sphere()?;
+        Some((
+            self.transform.point_to_world(center),
+            radius * self.transform.max_scale(),
+        ))
+    }
+
+    fn hash_content(&self, hasher: &mut ContentHasher) {
+        hasher.write_str("Transformed");
+        self.transform.hash_content(hasher);
+        self.surface.hash_content(hasher);
+    }
+
+    fn memory_usage(&self) -> MemoryUsage {
+        self.surface.memory_usage()
+    }
+
+    fn ignores_leaving_rays(&self) -> bool {
+        self.surface.ignores_leaving_rays()
+    }
+
+    fn tessellate(&self, tolerance: f64) -> Option<Mesh> {
+        let mesh = self
+            .surface
+            .tessellate(tolerance / self.transform.max_scale())?;
+        let positions = mesh
+            .positions()
+            .iter()
+            .map(|&position| self.transform.point_to_world(position))
+            .collect();
+        // Mirroring turns the triangles inside out, so they are turned back.
+        let scale = self.transform.scale;
+        let mirrored = scale.x * scale.y * scale.z < 0.0;
+        let triangles = mesh
+            .triangles()
+            .iter()
+            .map(|&[a, b, c]| if mirrored { [a, c, b] } else { [a, b, c] })
+            .collect();
+        Some(Mesh::with_texture_coordinates(
+            positions,
+            mesh.uvs().to_vec(),
+            triangles,
+        ))
+    }
+
+    fn tessellate_with_transform(&self, tolerance: f64) -> Option<(Mesh, Transform)> {
+        let mesh = self
+            .surface
+            .tessellate(tolerance / self.transform.max_scale())?;
+        Some((mesh, self.transform))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::surfaces::Sphere;
+    use proptest::prelude::*;
+    use proptest::test_runner::RngSeed;
+    use std::f64::consts::PI;
+
+    const TOLERANCE: f64 = 1e-9;
+
+    fn vector(range: f64) -> impl Strategy<Value = Vector3> {
+        (-range..range, -range..range, -range..range).prop_map(Vector3::from)
+    }
+
+    proptest! {
+        // Use a fixed seed, so that every run tests the same cases.
+        #![proptest_config(ProptestConfig {
+            rng_seed: RngSeed::Fixed(0),
+            ..ProptestConfig::default()
+        })]
+
+        #[test]
+        fn scaled_sphere_intersection_lies_on_ellipsoid(
+            semi_axes in (0.2..3.0, 0.2..3.0, 0.2..3.0),
+            angle in 0.0..2.0 * PI,
+            center in vector(5.0),
+            origin in vector(10.0),
+            direction in vector(1.0).prop_filter("non-zero", |v| v.norm2() > 1e-6),
+        ) {
+            let rotation = UnitQuaternion::from_axis_angle((1.0, 2.0, 3.0), angle);
+            let transform = Transform::new(semi_axes, rotation, center);
+            let ellipsoid = Transformed::new(Sphere::new((0.0, 0.0, 0.0), 1.0), transform);
+            let ray = Ray::new(origin, direction);
+
+            if let Some(hit) = ellipsoid.closest_intersection(&ray) {
+                prop_assert!(hit.distance > 0.0);
+                prop_assert!((hit.normal.norm2() - 1.0).abs() < TOLERANCE);
+                prop_assert!((hit.tangent.norm2() - 1.0).abs() < TOLERANCE);
+                prop_assert!(hit.normal.dot(hit.tangent).abs() < TOLERANCE);
+
+                // The point is on the ellipsoid, where the normal is along
+                // the gradient of its implicit equation.
+                let point = ray.origin + hit.distance * ray.direction;
+                let local = (point - center).rotate(rotation.invert());
+                let (a, b, c) = semi_axes;
+                let ellipsoid_value = (local.x / a).powi(2) + (local.y / b).powi(2) + (local.z / c).powi(2);
+                prop_assert!((ellipsoid_value - 1.0).abs() < 1e-6);
+                let gradient = Vector3::from((local.x / (a * a), local.y / (b * b), local.z / (c * c)))
+                    .rotate(rotation)
+                    .normalize();
+                prop_assert!((gradient - hit.normal).norm() < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn transform_round_trips() {
+        let rotation = UnitQuaternion::from_axis_angle((0.0, 0.0, 1.0), 0.5 * PI);
+        let transform = Transform::new((2.0, 1.0, 0.5), rotation, (1.0, 0.0, 0.0));
+
+        let point = Vector3::from((1.0, 1.0, 1.0));
+        let world = transform.point_to_world(point);
+        assert!((world - Vector3::from((0.0, 2.0, 0.5))).norm() < TOLERANCE);
+        assert!((transform.point_to_object(world) - point).norm() < TOLERANCE);
+
+        // A plane through (1, 0, 0) and (0, 1, 0) in object space has normal
+        // (1, 1, 0), but the transformed normal is not the transformed vector.
+        let normal = transform.normal_to_world(Vector3::from((1.0, 1.0, 0.0)));
+        let edge = transform.vector_to_world(Vector3::from((1.0, -1.0, 0.0)));
+        assert!(normal.dot(edge).abs() < TOLERANCE);
+        assert!(
+            (transform
+                .vector_to_world(Vector3::from((1.0, 1.0, 0.0)))
+                .normalize()
+                - normal)
+                .norm()
+                > 0.1
+        );
+    }
+}
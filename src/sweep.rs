@@ -0,0 +1,122 @@
+//! Module for rendering parameter sweeps.
+//!
+//! A sweep renders a scene several times, varying one or two parameters, such
+//! as the roughness and metallicity of a material or the angle and intensity
+//! of a light, and assembles the images in a labeled contact sheet. This makes
+//! it easy to compare the looks side by side.
+
+use crate::image::Image;
+use crate::scene::Scene;
+
+/// The space around labels and between cells, in pixels.
+const PADDING: usize = 4;
+
+/// A parameter that is varied in a sweep, together with the values it takes.
+pub struct Parameter {
+    /// The name shown in the labels.
+    pub name: String,
+    pub values: Vec<f64>,
+}
+
+impl Parameter {
+    pub fn new(name: &str, values: Vec<f64>) -> Self {
+        Self {
+            name: name.to_string(),
+            values,
+        }
+    }
+
+    /// Make a parameter taking `count` evenly spaced values from `first` to
+    /// `last`.
+    pub fn linear(name: &str, first: f64, last: f64, count: usize) -> Self {
+        let values = (0..count)
+            .map(|i| {
+                if count == 1 {
+                    first
+                } else {
+                    first + (last - first) * i as f64 / (count - 1) as f64
+                }
+            })
+            .collect();
+        Self::new(name, values)
+    }
+
+    /// The label of the parameter with value `value`.
+    fn label(&self, value: f64) -> String {
+        // Three decimals are enough to tell the images apart.
+        let value = (value * 1e3).round() / 1e3;
+        format!("{} = {}", self.name, value)
+    }
+}
+
+/// A sweep over one parameter, varying along the columns of the contact sheet,
+/// or two parameters, the second varying along the rows.
+pub struct Sweep {
+    columns: Parameter,
+    rows: Option<Parameter>,
+    cell_width: usize,
+    cell_height: usize,
+}
+
+impl Sweep {
+    /// Make a sweep over `columns`, rendering images of 320 x 240 pixels.
+    pub fn new(columns: Parameter) -> Self {
+        Self {
+            columns,
+            rows: None,
+            cell_width: 320,
+            cell_height: 240,
+        }
+    }
+
+    /// Vary a second parameter along the rows of the contact sheet.
+    pub fn with_rows(mut self, rows: Parameter) -> Self {
+        self.rows = Some(rows);
+        self
+    }
+
+    /// Set the size of each rendered image.
+    pub fn with_cell_size(mut self, width: usize, height: usize) -> Self {
+        self.cell_width = width;
+        self.cell_height = height;
+        self
+    }
+
+    /// Render the contact sheet. `make_scene` is called once per image with
+    /// the value of the column parameter and, if there are rows, the value of
+    /// the row parameter, and makes the scene to render. Each image is labeled
+    /// with the values it was made with.
+    pub fn render(&self, make_scene: impl Fn(&[f64]) -> Scene) -> Image {
+        let row_values = match &self.rows {
+            None => vec![None],
+            Some(rows) => rows.values.iter().map(|&value| Some(value)).collect(),
+        };
+
+        let mut images = Vec::new();
+        let mut labels = Vec::new();
+        for row_value in row_values.iter() {
+            for &column_value in self.columns.values.iter() {
+                let mut values = vec![column_value];
+                let mut label = self.columns.label(column_value);
+                if let (Some(rows), Some(row_value)) = (&self.rows, row_value) {
+                    values.push(*row_value);
+                    label = format!("{}\n{}", label, rows.label(*row_value));
+                }
+
+                let scene = make_scene(&values);
+                let mut image = Image::new(self.cell_width, self.cell_height);
+                image.update(
+                    scene
+                        .spawn_render_threads(self.cell_width, self.cell_height)
+                        .iter(),
+                );
+                image.clamp();
+
+                images.push(image);
+                labels.push(label);
+            }
+        }
+
+        Image::grid(&images, self.columns.values.len(), PADDING, &labels)
+    }
+}
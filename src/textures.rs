@@ -0,0 +1,507 @@
+//! Module containing textures, which give surfaces their color.
+
+mod image_texture;
+
+pub use image_texture::{ImageTexture, MipMap, TextureCache};
+
+use crate::hashing::ContentHasher;
+use crate::image::ColorSpace;
+use crate::math::{noise, Vector3};
+use crate::scene_file::TextureDescription;
+use std::f64::consts::PI;
+
+/// A `Texture` maps points on a surface to colors.
+pub trait Texture {
+    /// Find the color of the texture, in linear RGB, at `point`. `uv` are the
+    /// texture coordinates of the point on the surface.
+    fn color(&self, point: Vector3, uv: (f64, f64)) -> Vector3;
+
+    /// Like `color`, but averaged over an area around the point of width
+    /// `footprint` in texture coordinates, which avoids aliasing where the
+    /// texture is seen from afar. The default ignores the footprint.
+    fn filtered_color(&self, point: Vector3, uv: (f64, f64), _footprint: f64) -> Vector3 {
+        self.color(point, uv)
+    }
+
+    /// Does `filtered_color` use the footprint? Finding the footprint takes
+    /// extra intersections, so it is only done for textures that use it. The
+    /// default is false.
+    fn uses_footprint(&self) -> bool {
+        false
+    }
+
+    /// Write the kind and parameters of the texture to `hasher`. The default
+    /// writes that the texture can't be hashed.
+    fn hash_content(&self, hasher: &mut ContentHasher) {
+        hasher.write_unknown();
+    }
+
+    /// Describe the texture as in scene files, for saving scenes built in
+    /// code. The default returns `None`, for textures that scene files can't
+    /// describe, such as those made by plugins.
+    fn describe(&self) -> Option<TextureDescription> {
+        None
+    }
+}
+
+impl<T: Texture + ?Sized> Texture for Box<T> {
+    fn color(&self, point: Vector3, uv: (f64, f64)) -> Vector3 {
+        self.as_ref().color(point, uv)
+    }
+
+    fn filtered_color(&self, point: Vector3, uv: (f64, f64), footprint: f64) -> Vector3 {
+        self.as_ref().filtered_color(point, uv, footprint)
+    }
+
+    fn uses_footprint(&self) -> bool {
+        self.as_ref().uses_footprint()
+    }
+
+    fn hash_content(&self, hasher: &mut ContentHasher) {
+        self.as_ref().hash_content(hasher);
+    }
+
+    fn describe(&self) -> Option<TextureDescription> {
+        self.as_ref().describe()
+    }
+}
+
+/// A texture with the same color everywhere.
+pub struct Constant {
+    /// In linear RGB.
+    pub color: Vector3,
+}
+
+impl Constant {
+    pub fn new<T: Into<Vector3>>(color: T) -> Self {
+        Self {
+            color: color.into(),
+        }
+    }
+}
+
+impl Texture for Constant {
+    fn color(&self, _point: Vector3, _uv: (f64, f64)) -> Vector3 {
+        self.color
+    }
+
+    fn hash_content(&self, hasher: &mut ContentHasher) {
+        hasher.write_str("Constant");
+        hasher.write_vector(self.color);
+    }
+
+    fn describe(&self) -> Option<TextureDescription> {
+        Some(TextureDescription::Constant(self.color.into()))
+    }
+}
+
+/// A texture whose colors are given in some other color space than linear RGB.
+/// The colors are converted to linear RGB when looked up.
+pub struct Converted {
+    texture: Box<dyn Texture + Send + Sync>,
+    color_space: ColorSpace,
+}
+
+impl Converted {
+    /// Interpret the colors of `texture` as being in `color_space`.
+    pub fn new(texture: impl Texture + Send + Sync + 'static, color_space: ColorSpace) -> Self {
+        Self::from_boxed(Box::new(texture), color_space)
+    }
+
+    /// Like `new`, but for a texture that is already boxed.
+    pub fn from_boxed(texture: Box<dyn Texture + Send + Sync>, color_space: ColorSpace) -> Self {
+        Self {
+            texture,
+            color_space,
+        }
+    }
+}
+
+impl Texture for Converted {
+    fn color(&self, point: Vector3, uv: (f64, f64)) -> Vector3 {
+        self.color_space.to_linear(self.texture.color(point, uv))
+    }
+
+    fn filtered_color(&self, point: Vector3, uv: (f64, f64), footprint: f64) -> Vector3 {
+        self.color_space
+            .to_linear(self.texture.filtered_color(point, uv, footprint))
+    }
+
+    fn uses_footprint(&self) -> bool {
+        self.texture.uses_footprint()
+    }
+
+    fn hash_content(&self, hasher: &mut ContentHasher) {
+        hasher.write_str("Converted");
+        hasher.write_serialized(&self.color_space);
+        self.texture.hash_content(hasher);
+    }
+
+    /// The colors of the description are those of the texture, in its color
+    /// space.
+    fn describe(&self) -> Option<TextureDescription> {
+        self.texture.describe()
+    }
+}
+
+/// Determines which coordinates a `Checker` texture is laid out in.
+#[derive(Clone, Copy)]
+pub enum CheckerMapping {
+    /// Squares in the 2D texture coordinates of the surface.
+    Uv,
+    /// Cubes in 3D space. The surface shows a cross-section of the cubes, so no
+    /// texture coordinates are needed.
+    Solid,
+}
+
+/// A checkerboard pattern alternating between two sub-textures.
+pub struct Checker {
+    even: Box<dyn Texture + Send + Sync>,
+    odd: Box<dyn Texture + Send + Sync>,
+    /// The side length of each square (or cube).
+    scale: f64,
+    mapping: CheckerMapping,
+}
+
+impl Checker {
+    /// Make a checkerboard of `even` and `odd` squares with side length
+    /// `scale`, laid out according to `mapping`.
+    pub fn new(
+        even: impl Texture + Send + Sync + 'static,
+        odd: impl Texture + Send + Sync + 'static,
+        scale: f64,
+        mapping: CheckerMapping,
+    ) -> Self {
+        Self {
+            even: Box::new(even),
+            odd: Box::new(odd),
+            scale,
+            mapping,
+        }
+    }
+
+    /// Make a checkerboard alternating between two colors.
+    pub fn from_colors<T: Into<Vector3>, U: Into<Vector3>>(
+        even_color: T,
+        odd_color: U,
+        scale: f64,
+        mapping: CheckerMapping,
+    ) -> Self {
+        Self::new(
+            Constant::new(even_color),
+            Constant::new(odd_color),
+            scale,
+            mapping,
+        )
+    }
+}
+
+impl Texture for Checker {
+    fn color(&self, point: Vector3, uv: (f64, f64)) -> Vector3 {
+        let recip_scale = 1.0 / self.scale;
+        let cell_sum = match self.mapping {
+            CheckerMapping::Uv => (uv.0 * recip_scale).floor() + (uv.1 * recip_scale).floor(),
+            CheckerMapping::Solid => {
+                (point.x * recip_scale).floor()
+                    + (point.y * recip_scale).floor()
+                    + (point.z * recip_scale).floor()
+            }
+        };
+
+        if cell_sum.rem_euclid(2.0) == 0.0 {
+            self.even.color(point, uv)
+        } else {
+            self.odd.color(point, uv)
+        }
+    }
+
+    fn hash_content(&self, hasher: &mut ContentHasher) {
+        hasher.write_str(match self.mapping {
+            CheckerMapping::Uv => "Checker(Uv)",
+            CheckerMapping::Solid => "Checker(Solid)",
+        });
+        self.even.hash_content(hasher);
+        self.odd.hash_content(hasher);
+        hasher.write_f64(self.scale);
+    }
+
+    fn describe(&self) -> Option<TextureDescription> {
+        let (even, odd) = describe_pair(self.even.as_ref(), self.odd.as_ref())?;
+        Some(TextureDescription::Checker {
+            even,
+            odd,
+            scale: self.scale,
+            uv: matches!(self.mapping, CheckerMapping::Uv),
+        })
+    }
+}
+
+/// Blend linearly between the colors of two textures. `t` = 0 gives the color
+/// of `first`, and `t` = 1 the color of `second`.
+fn mix(
+    first: &dyn Texture,
+    second: &dyn Texture,
+    t: f64,
+    point: Vector3,
+    uv: (f64, f64),
+) -> Vector3 {
+    (1.0 - t) * first.color(point, uv) + t * second.color(point, uv)
+}
+
+/// Describe two sub-textures, or return `None` if either can't be described.
+fn describe_pair(
+    first: &dyn Texture,
+    second: &dyn Texture,
+) -> Option<(Box<TextureDescription>, Box<TextureDescription>)> {
+    Some((Box::new(first.describe()?), Box::new(second.describe()?)))
+}
+
+/// Write a texture blending two sub-textures by noise to `hasher`.
+fn hash_noise_texture(
+    hasher: &mut ContentHasher,
+    kind: &str,
+    textures: [&dyn Texture; 2],
+    scale: f64,
+    octaves: u32,
+) {
+    hasher.write_str(kind);
+    for texture in textures {
+        texture.hash_content(hasher);
+    }
+    hasher.write_f64(scale);
+    hasher.write_u64(u64::from(octaves));
+}
+
+/// Cloudy pattern blending two sub-textures using turbulent noise.
+pub struct Turbulence {
+    low: Box<dyn Texture + Send + Sync>,
+    high: Box<dyn Texture + Send + Sync>,
+    /// The approximate size of the largest features of the noise.
+    scale: f64,
+    octaves: u32,
+}
+
+impl Turbulence {
+    /// Make a turbulence texture where `low` shows where there is little noise,
+    /// and `high` where there is much. More `octaves` give finer detail.
+    pub fn new(
+        low: impl Texture + Send + Sync + 'static,
+        high: impl Texture + Send + Sync + 'static,
+        scale: f64,
+        octaves: u32,
+    ) -> Self {
+        Self {
+            low: Box::new(low),
+            high: Box::new(high),
+            scale,
+            octaves,
+        }
+    }
+}
+
+impl Texture for Turbulence {
+    fn color(&self, point: Vector3, uv: (f64, f64)) -> Vector3 {
+        let t = noise::turbulence(point * (1.0 / self.scale), self.octaves).min(1.0);
+        mix(self.low.as_ref(), self.high.as_ref(), t, point, uv)
+    }
+
+    fn hash_content(&self, hasher: &mut ContentHasher) {
+        hash_noise_texture(
+            hasher,
+            "Turbulence",
+            [self.low.as_ref(), self.high.as_ref()],
+            self.scale,
+            self.octaves,
+        );
+    }
+
+    fn describe(&self) -> Option<TextureDescription> {
+        let (low, high) = describe_pair(self.low.as_ref(), self.high.as_ref())?;
+        Some(TextureDescription::Turbulence {
+            low,
+            high,
+            scale: self.scale,
+            octaves: self.octaves,
+        })
+    }
+}
+
+/// Marble with veins running perpendicular to the x-axis, distorted by
+/// turbulent noise.
+pub struct Marble {
+    base: Box<dyn Texture + Send + Sync>,
+    vein: Box<dyn Texture + Send + Sync>,
+    /// The distance between veins.
+    scale: f64,
+    octaves: u32,
+}
+
+impl Marble {
+    pub fn new(
+        base: impl Texture + Send + Sync + 'static,
+        vein: impl Texture + Send + Sync + 'static,
+        scale: f64,
+        octaves: u32,
+    ) -> Self {
+        Self {
+            base: Box::new(base),
+            vein: Box::new(vein),
+            scale,
+            octaves,
+        }
+    }
+}
+
+impl Texture for Marble {
+    fn color(&self, point: Vector3, uv: (f64, f64)) -> Vector3 {
+        let scaled_point = point * (1.0 / self.scale);
+        let phase = PI * (scaled_point.x + 4.0 * noise::turbulence(scaled_point, self.octaves));
+        // Sharpen the veins by raising the sine wave to a power.
+        let t = (1.0 - phase.sin().abs()).powi(4);
+        mix(self.base.as_ref(), self.vein.as_ref(), t, point, uv)
+    }
+
+    fn hash_content(&self, hasher: &mut ContentHasher) {
+        hash_noise_texture(
+            hasher,
+            "Marble",
+            [self.base.as_ref(), self.vein.as_ref()],
+            self.scale,
+            self.octaves,
+        );
+    }
+
+    fn describe(&self) -> Option<TextureDescription> {
+        let (base, vein) = describe_pair(self.base.as_ref(), self.vein.as_ref())?;
+        Some(TextureDescription::Marble {
+            base,
+            vein,
+            scale: self.scale,
+            octaves: self.octaves,
+        })
+    }
+}
+
+/// Wood with growth rings centered on the z-axis, distorted by fractal noise.
+pub struct Wood {
+    light: Box<dyn Texture + Send + Sync>,
+    dark: Box<dyn Texture + Send + Sync>,
+    /// The distance between growth rings.
+    scale: f64,
+    octaves: u32,
+}
+
+impl Wood {
+    pub fn new(
+        light: impl Texture + Send + Sync + 'static,
+        dark: impl Texture + Send + Sync + 'static,
+        scale: f64,
+        octaves: u32,
+    ) -> Self {
+        Self {
+            light: Box::new(light),
+            dark: Box::new(dark),
+            scale,
+            octaves,
+        }
+    }
+}
+
+impl Texture for Wood {
+    fn color(&self, point: Vector3, uv: (f64, f64)) -> Vector3 {
+        let scaled_point = point * (1.0 / self.scale);
+        let distance_to_axis = (scaled_point.x.powi(2) + scaled_point.y.powi(2)).sqrt();
+        let rings = distance_to_axis + 0.5 * noise::fbm(scaled_point, self.octaves);
+        let t = rings - rings.floor();
+        mix(self.light.as_ref(), self.dark.as_ref(), t, point, uv)
+    }
+
+    fn hash_content(&self, hasher: &mut ContentHasher) {
+        hash_noise_texture(
+            hasher,
+            "Wood",
+            [self.light.as_ref(), self.dark.as_ref()],
+            self.scale,
+            self.octaves,
+        );
+    }
+
+    fn describe(&self) -> Option<TextureDescription> {
+        let (light, dark) = describe_pair(self.light.as_ref(), self.dark.as_ref())?;
+        Some(TextureDescription::Wood {
+            light,
+            dark,
+            scale: self.scale,
+            octaves: self.octaves,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checker_cells_alternate() {
+        let scale = 0.5;
+        let center = |cell: i32| (cell as f64 + 0.5) * scale;
+        let is_even = |color: Vector3| color.x == 1.0;
+
+        let uv = Checker::from_colors((1.0, 1.0, 1.0), (0.0, 0.0, 0.0), scale, CheckerMapping::Uv);
+        let solid = Checker::from_colors(
+            (1.0, 1.0, 1.0),
+            (0.0, 0.0, 0.0),
+            scale,
+            CheckerMapping::Solid,
+        );
+        for i in -3..3 {
+            for j in -3..3 {
+                let color = |i, j| uv.color(Vector3::zero(), (center(i), center(j)));
+                assert_eq!(is_even(color(i, j)), (i + j) % 2 == 0);
+                assert_ne!(is_even(color(i, j)), is_even(color(i + 1, j)));
+                assert_ne!(is_even(color(i, j)), is_even(color(i, j + 1)));
+
+                for k in -3..3 {
+                    let color = |i, j, k| {
+                        let point = Vector3::from((center(i), center(j), center(k)));
+                        solid.color(point, (0.0, 0.0))
+                    };
+                    assert_eq!(is_even(color(i, j, k)), (i + j + k) % 2 == 0);
+                    assert_ne!(is_even(color(i, j, k)), is_even(color(i + 1, j, k)));
+                    assert_ne!(is_even(color(i, j, k)), is_even(color(i, j + 1, k)));
+                    assert_ne!(is_even(color(i, j, k)), is_even(color(i, j, k + 1)));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn marble_is_deterministic_and_varies() {
+        let marble = || {
+            Marble::new(
+                Constant::new((0.9, 0.9, 0.9)),
+                Constant::new((0.1, 0.1, 0.1)),
+                0.5,
+                4,
+            )
+        };
+        let (first, second) = (marble(), marble());
+        let colors: Vec<_> = (0..50)
+            .map(|i| Vector3::from((0.13 * i as f64, 0.07 * i as f64, 0.3)))
+            .map(|point| {
+                (
+                    first.color(point, (0.0, 0.0)),
+                    second.color(point, (0.0, 0.0)),
+                )
+            })
+            .collect();
+
+        assert!(colors.iter().all(|(a, b)| (*a - *b).norm() == 0.0));
+        let (min, max) = colors
+            .iter()
+            .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), (a, _)| {
+                (min.min(a.x), max.max(a.x))
+            });
+        assert!(max - min > 0.3);
+    }
+}
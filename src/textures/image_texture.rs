@@ -0,0 +1,325 @@
+//! Module for textures made from images.
+//!
+//! Images are loaded through a `TextureCache`, so that an image used by many
+//! textures is only loaded once. Each image is prefiltered into a mipmap: a
+//! pyramid of ever smaller versions of the image, each half the size of the
+//! one before. A color looked up over a large footprint is read from a small
+//! version, instead of from a few texels of the full image, which avoids
+//! aliasing. The smaller versions are made tile by tile when first needed, so
+//! that parts of large images that are never seen from afar cost no memory.
+
+use super::Texture;
+use crate::hashing::ContentHasher;
+use crate::math::Vector3;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// The width and height of the tiles that the smaller versions of an image
+/// are made in, in texels.
+const TILE_SIZE: usize = 32;
+
+/// Loads images for textures, and keeps them for other textures using the
+/// same image.
+#[derive(Default)]
+pub struct TextureCache {
+    images: Mutex<HashMap<PathBuf, Arc<MipMap>>>,
+}
+
+impl TextureCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load the PNG image `path`, unless it is already loaded.
+    pub fn load(&self, path: &Path) -> Result<Arc<MipMap>, Box<dyn Error>> {
+        let mut images = self.images.lock().map_err(|_| "Texture cache poisoned")?;
+        if let Some(image) = images.get(path) {
+            return Ok(Arc::clone(image));
+        }
+
+        let image = Arc::new(read_png(path)?);
+        images.insert(path.to_path_buf(), Arc::clone(&image));
+        Ok(image)
+    }
+}
+
+/// Read a PNG image. Only the color channels are kept.
+fn read_png(path: &Path) -> Result<MipMap, Box<dyn Error>> {
+    // Palettes and less than 8 bits per channel are expanded, and 16 bits per
+    // channel are reduced to 8, by default.
+    let decoder = png::Decoder::new(File::open(path)?);
+    let (info, mut reader) = decoder.read_info()?;
+    let mut buffer = vec![0; info.buffer_size()];
+    reader.next_frame(&mut buffer)?;
+
+    let channels = match info.color_type {
+        png::ColorType::Grayscale => 1,
+        png::ColorType::GrayscaleAlpha => 2,
+        png::ColorType::RGB => 3,
+        png::ColorType::RGBA => 4,
+        png::ColorType::Indexed => return Err("Unexpected palette in PNG image".into()),
+    };
+    let (width, height) = (info.width as usize, info.height as usize);
+
+    let mut rgb = Vec::with_capacity(3 * width * height);
+    for row in buffer.chunks(info.line_size) {
+        for pixel in row[..channels * width].chunks(channels) {
+            if channels < 3 {
+                rgb.extend_from_slice(&[pixel[0]; 3]);
+            } else {
+                rgb.extend_from_slice(&pixel[..3]);
+            }
+        }
+    }
+
+    MipMap::new(width, height, rgb)
+}
+
+/// An image, together with smaller versions of it for looking up colors over
+/// larger footprints.
+pub struct MipMap {
+    width: usize,
+    height: usize,
+    /// The red, green and blue values of the full image, row by row from the
+    /// top.
+    rgb: Vec<u8>,
+    /// The smaller versions of the image, each half the size of the one
+    /// before, rounded up, down to a single texel.
+    levels: Vec<Level>,
+}
+
+/// A smaller version of an image, made in tiles when first needed.
+struct Level {
+    width: usize,
+    height: usize,
+    /// The number of tiles in each row.
+    tiles_per_row: usize,
+    tiles: Vec<OnceLock<Vec<[f32; 3]>>>,
+}
+
+impl MipMap {
+    /// Make a mipmap of an image of size `width` x `height`, given by the red,
+    /// green and blue values of its texels, row by row from the top.
+    pub fn new(width: usize, height: usize, rgb: Vec<u8>) -> Result<Self, Box<dyn Error>> {
+        if width == 0 || height == 0 || rgb.len() != 3 * width * height {
+            return Err("Invalid image size".into());
+        }
+
+        let mut levels = Vec::new();
+        let (mut level_width, mut level_height) = (width, height);
+        while level_width > 1 || level_height > 1 {
+            level_width = level_width.div_ceil(2);
+            level_height = level_height.div_ceil(2);
+            let tiles_per_row = level_width.div_ceil(TILE_SIZE);
+            let tiles_per_column = level_height.div_ceil(TILE_SIZE);
+            levels.push(Level {
+                width: level_width,
+                height: level_height,
+                tiles_per_row,
+                tiles: (0..tiles_per_row * tiles_per_column)
+                    .map(|_| OnceLock::new())
+                    .collect(),
+            });
+        }
+
+        Ok(Self {
+            width,
+            height,
+            rgb,
+            levels,
+        })
+    }
+
+    /// Look up the color at texture coordinates `uv`, averaged over an area of
+    /// width `footprint`, also in texture coordinates. The image covers
+    /// texture coordinates from 0 to 1, with v pointing up, and repeats outside
+    /// them. Colors are interpolated bilinearly within each version of the
+    /// image, and linearly between the two versions closest to the footprint.
+    pub fn lookup(&self, uv: (f64, f64), footprint: f64) -> Vector3 {
+        let level = (footprint * self.width.max(self.height) as f64).log2();
+        if level.is_nan() || level <= 0.0 {
+            return self.bilinear(0, uv);
+        }
+
+        let level = level.min(self.levels.len() as f64);
+        let finer = level.floor();
+        let t = level - finer;
+        let finer = finer as usize;
+        if t == 0.0 {
+            self.bilinear(finer, uv)
+        } else {
+            (1.0 - t) * self.bilinear(finer, uv) + t * self.bilinear(finer + 1, uv)
+        }
+    }
+
+    /// Write the size and texels of the image to `hasher`.
+    pub fn hash_content(&self, hasher: &mut ContentHasher) {
+        hasher.write_u64(self.width as u64);
+        hasher.write_u64(self.height as u64);
+        hasher.write_bytes(&self.rgb);
+    }
+
+    fn size(&self, level: usize) -> (usize, usize) {
+        match level {
+            0 => (self.width, self.height),
+            _ => (self.levels[level - 1].width, self.levels[level - 1].height),
+        }
+    }
+
+    fn bilinear(&self, level: usize, uv: (f64, f64)) -> Vector3 {
+        let (width, height) = self.size(level);
+        // Texel centers are at half-integer coordinates.
+        let x = uv.0 * width as f64 - 0.5;
+        let y = (1.0 - uv.1) * height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+
+        let wrap =
+            |coordinate: f64, size: usize| coordinate.rem_euclid(size as f64) as usize % size;
+        let (x0, x1) = (wrap(x0, width), wrap(x0 + 1.0, width));
+        let (y0, y1) = (wrap(y0, height), wrap(y0 + 1.0, height));
+
+        let top = (1.0 - tx) * self.texel(level, x0, y0) + tx * self.texel(level, x1, y0);
+        let bottom = (1.0 - tx) * self.texel(level, x0, y1) + tx * self.texel(level, x1, y1);
+        (1.0 - ty) * top + ty * bottom
+    }
+
+    fn texel(&self, level: usize, x: usize, y: usize) -> Vector3 {
+        if level == 0 {
+            let index = 3 * (y * self.width + x);
+            let rgb = &self.rgb[index..index + 3];
+            return Vector3::from((
+                f64::from(rgb[0]) / 255.0,
+                f64::from(rgb[1]) / 255.0,
+                f64::from(rgb[2]) / 255.0,
+            ));
+        }
+
+        let tiles = &self.levels[level - 1];
+        let (tile_x, tile_y) = (x / TILE_SIZE, y / TILE_SIZE);
+        let tile = tiles.tiles[tile_y * tiles.tiles_per_row + tile_x]
+            .get_or_init(|| self.make_tile(level, tile_x, tile_y));
+        let [r, g, b] = tile[(y % TILE_SIZE) * TILE_SIZE + x % TILE_SIZE];
+        Vector3::from((f64::from(r), f64::from(g), f64::from(b)))
+    }
+
+    /// Make a tile of `level` by averaging blocks of 2 x 2 texels of the level
+    /// before it.
+    fn make_tile(&self, level: usize, tile_x: usize, tile_y: usize) -> Vec<[f32; 3]> {
+        let (width, height) = self.size(level);
+        let (finer_width, finer_height) = self.size(level - 1);
+        let mut tile = vec![[0.0; 3]; TILE_SIZE * TILE_SIZE];
+
+        for y in 0..TILE_SIZE.min(height - tile_y * TILE_SIZE) {
+            for x in 0..TILE_SIZE.min(width - tile_x * TILE_SIZE) {
+                let (finer_x, finer_y) =
+                    (2 * (tile_x * TILE_SIZE + x), 2 * (tile_y * TILE_SIZE + y));
+                let mut sum = Vector3::zero();
+                for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                    // An odd size leaves the last texels without a neighbor.
+                    let sample_x = (finer_x + dx).min(finer_width - 1);
+                    let sample_y = (finer_y + dy).min(finer_height - 1);
+                    sum += self.texel(level - 1, sample_x, sample_y);
+                }
+                let average = 0.25 * sum;
+                tile[y * TILE_SIZE + x] = [average.x as f32, average.y as f32, average.z as f32];
+            }
+        }
+
+        tile
+    }
+}
+
+/// A texture given by an image. The colors of the image are used as they
+/// are, so they are in the color space of the scene, like other colors.
+pub struct ImageTexture {
+    image: Arc<MipMap>,
+}
+
+impl ImageTexture {
+    pub fn new(image: Arc<MipMap>) -> Self {
+        Self { image }
+    }
+
+    /// Make a texture of the PNG image `path`, loaded through `cache`.
+    pub fn load(path: &Path, cache: &TextureCache) -> Result<Self, Box<dyn Error>> {
+        Ok(Self::new(cache.load(path)?))
+    }
+}
+
+impl Texture for ImageTexture {
+    fn color(&self, _point: Vector3, uv: (f64, f64)) -> Vector3 {
+        self.image.lookup(uv, 0.0)
+    }
+
+    fn filtered_color(&self, _point: Vector3, uv: (f64, f64), footprint: f64) -> Vector3 {
+        self.image.lookup(uv, footprint)
+    }
+
+    fn uses_footprint(&self) -> bool {
+        true
+    }
+
+    fn hash_content(&self, hasher: &mut ContentHasher) {
+        hasher.write_str("Image");
+        self.image.hash_content(hasher);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn large_footprints_average_the_image() {
+        // Stripes of black and white, one texel wide.
+        let (width, height) = (100, 60);
+        let rgb = (0..width * height)
+            .flat_map(|index| [if index % 2 == 0 { 255 } else { 0 }; 3])
+            .collect();
+        let image = MipMap::new(width, height, rgb).unwrap();
+
+        // At texel centers, the stripes are found.
+        let center = |x: usize| ((x as f64 + 0.5) / width as f64, 0.5);
+        assert!((image.lookup(center(10), 0.0).x - 1.0).abs() < 1e-9);
+        assert!(image.lookup(center(11), 0.0).x.abs() < 1e-9);
+
+        // From afar, they blend to gray, at any footprint in between levels.
+        for &footprint in &[0.1, 0.3, 1.0, 10.0] {
+            for x in 0..width {
+                let color = image.lookup(center(x), footprint);
+                assert!((color.x - 0.5).abs() < 0.02);
+                assert!((color.x - color.z).abs() < 1e-6);
+            }
+        }
+
+        assert!(MipMap::new(2, 2, vec![0; 11]).is_err());
+    }
+
+    #[test]
+    fn images_are_loaded_once() {
+        let path =
+            std::env::temp_dir().join(format!("rustbeam-texture-{}.png", std::process::id()));
+        let mut encoder = png::Encoder::new(File::create(&path).unwrap(), 2, 1);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder
+            .write_header()
+            .unwrap()
+            .write_image_data(&[0, 255])
+            .unwrap();
+
+        let cache = TextureCache::new();
+        let first = cache.load(&path).unwrap();
+        assert!(Arc::ptr_eq(&first, &cache.load(&path).unwrap()));
+        std::fs::remove_file(&path).unwrap();
+
+        let texture = ImageTexture::new(first);
+        let white = texture.color(Vector3::zero(), (0.75, 0.5));
+        assert!((white - Vector3::ones()).norm() < 1e-9);
+        assert!(cache.load(Path::new("no-such-texture.png")).is_err());
+    }
+}
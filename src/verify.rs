@@ -0,0 +1,190 @@
+//! Module for checking that a build renders like the reference build.
+//!
+//! Renders differ slightly between platforms, compilers and CPUs, e.g. where
+//! fused multiply-adds or the math library round differently, so checking
+//! them against reference images bit for bit fails on perfectly good builds.
+//! Instead, the canonical scenes are rendered and compared with their
+//! reference images perceptually, in CIELAB, where a difference of about 2.3
+//! is just noticeable.
+
+use crate::image::{self, Image};
+use crate::lights::Sun;
+use crate::report;
+use crate::scene::Scene;
+use crate::surfaces::{Plane, Sphere};
+use std::error::Error;
+use std::fmt::Write;
+use std::path::Path;
+
+/// The directory with the reference images in the source tree.
+pub const REFERENCE_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/test-data");
+/// The size of the canonical renders.
+const WIDTH: usize = 1280;
+const HEIGHT: usize = 720;
+/// The difference in CIELAB that is just noticeable.
+const JUST_NOTICEABLE: f64 = 2.3;
+/// The largest fraction of pixels that may differ noticeably, since a few
+/// pixels on edges may flip between surfaces.
+const MAX_NOTICEABLE_FRACTION: f64 = 1e-3;
+/// The largest mean difference in CIELAB over all pixels.
+const MAX_MEAN_DIFFERENCE: f64 = 0.1;
+
+/// A scene with a reference image, `test_render_<name>_ref.png`.
+pub struct CanonicalScene {
+    pub name: &'static str,
+    build: fn() -> Scene,
+}
+
+/// The scenes that every build should render like the reference build.
+pub fn canonical_scenes() -> Vec<CanonicalScene> {
+    vec![
+        CanonicalScene {
+            name: "sphere",
+            build: || lit_by_three_suns(true, false),
+        },
+        CanonicalScene {
+            name: "plane",
+            build: || lit_by_three_suns(false, true),
+        },
+        CanonicalScene {
+            name: "sphere_and_plane",
+            build: || lit_by_three_suns(true, true),
+        },
+    ]
+}
+
+/// A sphere and a floor, either or both, lit by a red, a green and a blue sun.
+fn lit_by_three_suns(sphere: bool, plane: bool) -> Scene {
+    let mut scene = Scene::new();
+
+    if sphere {
+        scene.add_surface(Sphere::new((0.0, 2.0, 0.0), 0.5));
+    }
+    if plane {
+        scene.add_surface(Plane::new((0.0, 0.0, 1.0), -0.5));
+    }
+
+    scene.add_light(Sun::new((1.0, 0.0, 0.0), (1.0, 1.0, -1.0)));
+    scene.add_light(Sun::new((0.0, 1.0, 0.0), (-1.0, 1.0, -1.0)));
+    scene.add_light(Sun::new((0.0, 0.0, 1.0), (0.0, 1.0, 1.0)));
+
+    scene
+}
+
+/// How a render of a canonical scene differs from its reference image.
+pub struct Verification {
+    pub name: &'static str,
+    /// The mean and largest difference in CIELAB over all pixels.
+    pub mean_difference: f64,
+    pub max_difference: f64,
+    /// The number of pixels that differ noticeably, out of `pixels`.
+    pub noticeable_pixels: usize,
+    pub pixels: usize,
+}
+
+impl Verification {
+    /// Is the render close enough to the reference image?
+    pub fn passed(&self) -> bool {
+        self.mean_difference <= MAX_MEAN_DIFFERENCE
+            && self.noticeable_pixels as f64 <= MAX_NOTICEABLE_FRACTION * self.pixels as f64
+    }
+}
+
+/// Render `scene` and compare it with its reference image in
+/// `reference_dir`.
+pub fn verify(
+    scene: &CanonicalScene,
+    reference_dir: &Path,
+) -> Result<Verification, Box<dyn Error>> {
+    let reference_path = reference_dir.join(format!("test_render_{}_ref.png", scene.name));
+    let (width, height, reference) = report::decode_png(&reference_path)?;
+    if (width, height) != (WIDTH, HEIGHT) {
+        return Err(format!("{} is not {} x {}", reference_path.display(), WIDTH, HEIGHT).into());
+    }
+
+    let mut image = Image::new(WIDTH, HEIGHT);
+    image.update((scene.build)().spawn_render_threads(WIDTH, HEIGHT).iter());
+
+    let mut verification = Verification {
+        name: scene.name,
+        mean_difference: 0.0,
+        max_difference: 0.0,
+        noticeable_pixels: 0,
+        pixels: WIDTH * HEIGHT,
+    };
+    for (rendered, reference) in image.get_srgba_vector().chunks(4).zip(reference.chunks(4)) {
+        let difference = lab_distance(srgb_to_lab(rendered), srgb_to_lab(reference));
+        verification.mean_difference += difference;
+        verification.max_difference = verification.max_difference.max(difference);
+        verification.noticeable_pixels += usize::from(difference > JUST_NOTICEABLE);
+    }
+    verification.mean_difference /= verification.pixels as f64;
+    Ok(verification)
+}
+
+/// Make a report of verifications, as a table with a line for each scene.
+pub fn report(verifications: &[Verification]) -> String {
+    let mut report = format!(
+        "{:<20} {:>10} {:>10} {:>12}  result\n",
+        "scene", "mean ΔE", "max ΔE", "noticeable"
+    );
+    for verification in verifications {
+        // Writing to a string can't fail.
+        let _ = writeln!(
+            report,
+            "{:<20} {:>10.4} {:>10.4} {:>12}  {}",
+            verification.name,
+            verification.mean_difference,
+            verification.max_difference,
+            verification.noticeable_pixels,
+            if verification.passed() {
+                "pass"
+            } else {
+                "FAIL"
+            }
+        );
+    }
+    report
+}
+
+/// Convert an 8-bit SRGB color to CIELAB, with a D65 white point.
+fn srgb_to_lab(srgb: &[u8]) -> [f64; 3] {
+    let linear = |value: u8| image::srgb_to_linear(f64::from(value) / 255.0);
+    let (r, g, b) = (linear(srgb[0]), linear(srgb[1]), linear(srgb[2]));
+
+    // XYZ relative to the white point.
+    let x = (0.412_456_4 * r + 0.357_576_1 * g + 0.180_437_5 * b) / 0.950_47;
+    let y = 0.212_672_9 * r + 0.715_152_2 * g + 0.072_175 * b;
+    let z = (0.019_333_9 * r + 0.119_192 * g + 0.950_304_1 * b) / 1.088_83;
+
+    let f = |t: f64| {
+        if t > 216.0 / 24389.0 {
+            t.cbrt()
+        } else {
+            (24389.0 / 27.0 * t + 16.0) / 116.0
+        }
+    };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+fn lab_distance(a: [f64; 3], b: [f64; 3]) -> f64 {
+    ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lab_differences_are_perceptual() {
+        let white = srgb_to_lab(&[255, 255, 255]);
+        assert!((white[0] - 100.0).abs() < 1e-3 && white[1].abs() < 1e-2 && white[2].abs() < 1e-2);
+        assert!(srgb_to_lab(&[0, 0, 0])[0].abs() < 1e-9);
+
+        // A step of one in SRGB is not noticeable, while black to white is.
+        let step = lab_distance(srgb_to_lab(&[100, 100, 100]), srgb_to_lab(&[101, 100, 100]));
+        assert!(step < JUST_NOTICEABLE);
+        assert!(lab_distance(white, srgb_to_lab(&[0, 0, 0])) > 99.0);
+    }
+}